@@ -20,16 +20,20 @@ use arrow_array::cast::as_string_array;
 use arrow_array::RecordBatch;
 use arrow_schema::DataType;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use datafusion::arrow::compute::kernels::cast;
 use datafusion::arrow::datatypes::Schema;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
 
 pub mod parser;
 pub mod rule;
 pub mod target;
 
+use crate::metadata::STREAM_INFO;
 use crate::metrics::ALERTS_STATES;
 use crate::utils::arrow::get_field;
 use crate::utils::uid;
@@ -39,6 +43,10 @@ use crate::{storage, utils};
 pub use self::rule::Rule;
 use self::target::Target;
 
+// how often streams are checked for gaps in ingest, the smallest gap an ingest
+// gap rule can be set to
+pub const GAP_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
 #[derive(Default, Debug, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Alerts {
@@ -73,27 +81,38 @@ impl Alert {
             match state {
                 AlertState::Listening | AlertState::Firing => (),
                 alert_state @ (AlertState::SetToFiring | AlertState::Resolved) => {
-                    let context = self.get_context(
-                        stream_name.to_owned(),
-                        alert_state,
-                        &self.rule,
-                        events.slice(index, 1),
-                    );
-                    ALERTS_STATES
-                        .with_label_values(&[
-                            context.stream.as_str(),
-                            context.alert_info.alert_name.as_str(),
-                            context.alert_info.alert_state.to_string().as_str(),
-                        ])
-                        .inc();
-                    for target in &self.targets {
-                        target.call(context.clone());
-                    }
+                    self.notify(stream_name, alert_state, events.slice(index, 1))
                 }
             }
         }
     }
 
+    // Fire an ingest gap alert if the stream has received no events within its
+    // interval, resolving it is left to the next event
+    pub fn check_gap(&self, stream_name: &str, now: DateTime<Utc>) {
+        let Rule::IngestGap(rule) = &self.rule else {
+            return;
+        };
+        if rule.check(now) == AlertState::SetToFiring {
+            let no_event = RecordBatch::new_empty(Arc::new(Schema::empty()));
+            self.notify(stream_name, AlertState::SetToFiring, no_event)
+        }
+    }
+
+    fn notify(&self, stream_name: &str, alert_state: AlertState, event_row: RecordBatch) {
+        let context = self.get_context(stream_name.to_owned(), alert_state, &self.rule, event_row);
+        ALERTS_STATES
+            .with_label_values(&[
+                context.stream.as_str(),
+                context.alert_info.alert_name.as_str(),
+                context.alert_info.alert_state.to_string().as_str(),
+            ])
+            .inc();
+        for target in &self.targets {
+            target.call(context.clone());
+        }
+    }
+
     fn get_context(
         &self,
         stream_name: String,
@@ -127,6 +146,16 @@ impl Alert {
     }
 }
 
+// Check every stream for gaps in ingest
+pub fn init_gap_scheduler() {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(GAP_CHECK_INTERVAL).await;
+            STREAM_INFO.check_ingest_gaps(Utc::now());
+        }
+    });
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Message {
@@ -17,6 +17,7 @@
  */
 
 use arrow_array::{cast::as_string_array, RecordBatch};
+use chrono::{DateTime, Utc};
 use datafusion::arrow::datatypes::Schema;
 use itertools::Itertools;
 use serde::{
@@ -27,7 +28,11 @@ use std::{
     fmt,
     marker::PhantomData,
     str::FromStr,
-    sync::atomic::{AtomicU32, Ordering},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Mutex,
+    },
+    time::Duration,
 };
 
 use self::base::{
@@ -44,12 +49,14 @@ pub enum Rule {
     Column(ColumnRule),
     #[serde(deserialize_with = "string_or_struct", serialize_with = "to_string")]
     Composite(CompositeRule),
+    IngestGap(IngestGapRule),
 }
 
 impl Rule {
     pub fn resolves(&self, event: RecordBatch) -> Vec<AlertState> {
         match self {
             Rule::Column(rule) => rule.resolves(event),
+            Rule::IngestGap(rule) => rule.resolves(Utc::now()),
             Rule::Composite(rule) => rule
                 .resolves(event)
                 .iter()
@@ -68,6 +75,7 @@ impl Rule {
         match self {
             Rule::Column(rule) => rule.valid_for_schema(schema),
             Rule::Composite(rule) => rule.valid_for_schema(schema),
+            Rule::IngestGap(_) => true,
         }
     }
 
@@ -75,6 +83,10 @@ impl Rule {
         match self {
             Rule::Column(rule) => rule.trigger_reason(),
             Rule::Composite(rule) => format!("matched rule {}", rule),
+            Rule::IngestGap(rule) => format!(
+                "no events received for {}",
+                humantime::format_duration(rule.interval)
+            ),
         }
     }
 }
@@ -219,6 +231,59 @@ impl ConsecutiveStringRule {
     }
 }
 
+// Fires when a stream receives no events for longer than the interval, which
+// is how dead agents show up, and resolves with the first event after that.
+// Gaps are counted from the later of the last event and the time the rule was
+// set or the server started.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IngestGapRule {
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    #[serde(skip)]
+    state: GapState,
+}
+
+impl IngestGapRule {
+    // events arrived, a gap which was firing is resolved
+    fn resolves(&self, now: DateTime<Utc>) -> Vec<AlertState> {
+        *self.state.last_event.lock().unwrap() = now;
+        if self.state.firing.swap(false, Ordering::AcqRel) {
+            vec![AlertState::Resolved]
+        } else {
+            Vec::new()
+        }
+    }
+
+    pub fn check(&self, now: DateTime<Utc>) -> AlertState {
+        let last_event = *self.state.last_event.lock().unwrap();
+        let elapsed = (now - last_event).to_std().unwrap_or_default();
+        if elapsed <= self.interval {
+            return AlertState::Listening;
+        }
+        if self.state.firing.swap(true, Ordering::AcqRel) {
+            AlertState::Firing
+        } else {
+            AlertState::SetToFiring
+        }
+    }
+}
+
+#[derive(Debug)]
+struct GapState {
+    last_event: Mutex<DateTime<Utc>>,
+    firing: AtomicBool,
+}
+
+impl Default for GapState {
+    fn default() -> Self {
+        Self {
+            last_event: Mutex::new(Utc::now()),
+            firing: AtomicBool::new(false),
+        }
+    }
+}
+
 fn one() -> u32 {
     1
 }
@@ -432,9 +497,11 @@ where
 mod tests {
     use std::sync::atomic::AtomicU32;
 
+    use chrono::{Duration, Utc};
     use rstest::*;
+    use serde_json::json;
 
-    use super::{AlertState, ConsecutiveRepeatState};
+    use super::{AlertState, ConsecutiveRepeatState, Rule};
 
     #[fixture]
     pub fn rule(#[default(5)] repeats: u32, #[default(0)] repeated: u32) -> ConsecutiveRepeatState {
@@ -463,6 +530,32 @@ mod tests {
         assert_eq!(rule.update_and_fetch_state(), AlertState::Listening);
         assert_eq!(rule.update_and_fetch_state(), AlertState::SetToFiring);
     }
+
+    #[test]
+    fn ingest_gap_fires_once_and_clears_on_events() {
+        let rule: Rule =
+            serde_json::from_value(json!({"type": "ingestGap", "config": {"interval": "10m"}}))
+                .unwrap();
+        let Rule::IngestGap(rule) = rule else {
+            panic!("not an ingest gap rule")
+        };
+        let now = Utc::now();
+
+        assert_eq!(rule.check(now), AlertState::Listening);
+        assert_eq!(
+            rule.check(now + Duration::minutes(11)),
+            AlertState::SetToFiring
+        );
+        assert_eq!(rule.check(now + Duration::minutes(12)), AlertState::Firing);
+
+        let later = now + Duration::minutes(13);
+        assert_eq!(rule.resolves(later), vec![AlertState::Resolved]);
+        assert!(rule.resolves(later).is_empty());
+        assert_eq!(
+            rule.check(later + Duration::minutes(5)),
+            AlertState::Listening
+        );
+    }
 }
 
 pub mod base {
@@ -16,22 +16,34 @@
  *
  */
 
+use std::collections::HashSet;
 use std::sync::Arc;
 
-use chrono::{DateTime, NaiveDateTime, NaiveTime, Utc};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use relative_path::RelativePathBuf;
 
 use crate::{
     catalog::manifest::Manifest,
+    event::DEFAULT_TIMESTAMP_KEY,
+    localcache::LocalCacheManager,
+    metadata::STREAM_INFO,
+    option::CONFIG,
     query::PartialTimeFilter,
-    storage::{ObjectStorage, ObjectStorageError},
+    stats::Stats,
+    storage::{encryption, staging, ObjectStorage, ObjectStorageError},
 };
 
-use self::{column::Column, snapshot::ManifestItem};
+use self::{
+    column::{Column, TypedStatistics},
+    snapshot::ManifestItem,
+};
 
 pub mod column;
 pub mod manifest;
+pub mod schema_diff;
 pub mod snapshot;
+pub mod trim;
+pub mod usage;
 
 pub use manifest::create_from_parquet_file;
 
@@ -45,6 +57,7 @@ pub trait ManifestFile {
     fn file_size(&self) -> u64;
     fn num_rows(&self) -> u64;
     fn columns(&self) -> &[Column];
+    fn severity(&self) -> Option<&manifest::SeverityIndex>;
 }
 
 impl ManifestFile for manifest::File {
@@ -67,6 +80,10 @@ impl ManifestFile for manifest::File {
     fn columns(&self) -> &[Column] {
         self.columns.as_slice()
     }
+
+    fn severity(&self) -> Option<&manifest::SeverityIndex> {
+        self.severity.as_ref()
+    }
 }
 
 pub async fn update_snapshot(
@@ -74,11 +91,14 @@ pub async fn update_snapshot(
     stream_name: &str,
     change: manifest::File,
 ) -> Result<(), ObjectStorageError> {
-    fn get_file_bounds(file: &manifest::File) -> (DateTime<Utc>, DateTime<Utc>) {
+    fn get_file_bounds(
+        file: &manifest::File,
+        timestamp_key: &str,
+    ) -> (DateTime<Utc>, DateTime<Utc>) {
         match file
             .columns()
             .iter()
-            .find(|col| col.name == "p_timestamp")
+            .find(|col| col.name == timestamp_key)
             .unwrap()
             .stats
             .clone()
@@ -100,7 +120,10 @@ pub async fn update_snapshot(
     let mut meta = storage.get_snapshot(stream_name).await?;
     let manifests = &mut meta.manifest_list;
 
-    let (lower_bound, _) = get_file_bounds(&change);
+    let timestamp_key = STREAM_INFO
+        .timestamp_key(stream_name)
+        .unwrap_or_else(|_| DEFAULT_TIMESTAMP_KEY.to_string());
+    let (lower_bound, _) = get_file_bounds(&change, &timestamp_key);
     let pos = manifests.iter().position(|item| {
         item.time_lower_bound <= lower_bound && lower_bound < item.time_upper_bound
     });
@@ -154,6 +177,342 @@ pub async fn update_snapshot(
     Ok(())
 }
 
+/// Remove all data of a stream for a single date.
+/// Manifests of the date are dropped from the snapshot before any file is deleted,
+/// so that a query never plans on files which no longer exist.
+/// Returns the stats and number of files of the removed data or None if there is no data for this date.
+pub async fn remove_date_partition(
+    storage: Arc<dyn ObjectStorage + Send>,
+    stream_name: &str,
+    date: NaiveDate,
+) -> Result<Option<(Stats, u64)>, ObjectStorageError> {
+    let mut meta = storage.get_snapshot(stream_name).await?;
+    let (removed, retained): (Vec<_>, Vec<_>) = meta.manifest_list.into_iter().partition(|item| {
+        item.time_lower_bound.date_naive() == date && item.time_upper_bound.date_naive() == date
+    });
+    if removed.is_empty() {
+        return Ok(None);
+    }
+
+    let mut stats = Stats::default();
+    let mut files = 0;
+    for item in &removed {
+        let path = partition_path(stream_name, item.time_lower_bound, item.time_upper_bound);
+        if let Some(manifest) = storage.get_manifest(&path).await? {
+            files += manifest.files.len() as u64;
+            stats.files += manifest.files.len() as u64;
+            for file in manifest.files {
+                stats.events += file.num_rows;
+                stats.ingestion += file.ingestion_size;
+                stats.storage += file.file_size;
+            }
+        }
+    }
+
+    meta.manifest_list = retained;
+    storage.put_snapshot(stream_name, meta).await?;
+
+    let date = date.and_time(NaiveTime::MIN).and_utc();
+    storage
+        .delete_prefix(&partition_path(stream_name, date, date))
+        .await?;
+
+    Ok(Some((stats, files)))
+}
+
+/// Remove rows older than `cutoff` from the files of a stream on the date of `cutoff`.
+/// Files entirely older than the cutoff are dropped, files overlapping it are rewritten
+/// in place with only the newer rows. Manifest entries are updated before files are
+/// deleted, so that a query never plans on files which no longer exist.
+/// Returns the stats of the removed rows and the number of files dropped or rewritten.
+pub async fn trim_date_partition(
+    storage: Arc<dyn ObjectStorage + Send>,
+    stream_name: &str,
+    cutoff: DateTime<Utc>,
+) -> Result<Option<(Stats, u64)>, ObjectStorageError> {
+    let date = cutoff.date_naive();
+    let meta = storage.get_snapshot(stream_name).await?;
+    let Some(item) = meta.manifest_list.iter().find(|item| {
+        item.time_lower_bound.date_naive() == date && item.time_upper_bound.date_naive() == date
+    }) else {
+        return Ok(None);
+    };
+    let path = partition_path(stream_name, item.time_lower_bound, item.time_upper_bound);
+    let Some(mut manifest) = storage.get_manifest(&path).await? else {
+        return Ok(None);
+    };
+
+    let timestamp_key = STREAM_INFO
+        .timestamp_key(stream_name)
+        .unwrap_or_else(|_| DEFAULT_TIMESTAMP_KEY.to_string());
+    let cutoff = cutoff.timestamp_millis();
+    let tmp_path = CONFIG
+        .staging_dir()
+        .join(format!(".{stream_name}.retention.parquet"));
+
+    let mut stats = Stats::default();
+    let mut files = 0;
+    let mut removed = Vec::new();
+    let mut retained = Vec::with_capacity(manifest.files.len());
+    for file in std::mem::take(&mut manifest.files) {
+        let Some(TypedStatistics::Int(bounds)) = file
+            .columns
+            .iter()
+            .find(|column| column.name == timestamp_key)
+            .and_then(|column| column.stats.clone())
+        else {
+            retained.push(file);
+            continue;
+        };
+        if bounds.min >= cutoff {
+            retained.push(file);
+            continue;
+        }
+        let Some(relative) = trim::relative_path(stream_name, &file.file_path) else {
+            retained.push(file);
+            continue;
+        };
+
+        files += 1;
+        if bounds.max < cutoff {
+            stats.events += file.num_rows;
+            stats.ingestion += file.ingestion_size;
+            stats.storage += file.file_size;
+            stats.files += 1;
+            removed.push((relative, file.file_path));
+            continue;
+        }
+
+        // encrypted files are trimmed in plain and encrypted again with the active key
+        let keyring = if encryption::is_encrypted(relative.as_str()) {
+            let keyring = encryption::KEYRING.as_ref().ok_or_else(|| {
+                ObjectStorageError::UnhandledError(
+                    format!("no encryption keyfile to trim {relative}").into(),
+                )
+            })?;
+            Some(keyring)
+        } else {
+            None
+        };
+        let mut data = storage.get_object(&relative).await?;
+        if let Some(keyring) = keyring {
+            data = keyring
+                .decrypt(&data)
+                .map_err(|err| ObjectStorageError::UnhandledError(err.into()))?
+                .into();
+        }
+        let rows = trim::trim_parquet(
+            data,
+            &timestamp_key,
+            cutoff,
+            staging::stream_parquet_props(stream_name),
+            &tmp_path,
+        )
+        .map_err(|err| ObjectStorageError::UnhandledError(err.into()))?;
+        if rows == 0 {
+            stats.events += file.num_rows;
+            stats.ingestion += file.ingestion_size;
+            stats.storage += file.file_size;
+            stats.files += 1;
+            removed.push((relative, file.file_path));
+            continue;
+        }
+
+        let mut trimmed = create_from_parquet_file(file.file_path.clone(), &tmp_path)
+            .map_err(|err| ObjectStorageError::UnhandledError(err.into()))?;
+        match keyring {
+            Some(keyring) => {
+                let encrypted = keyring
+                    .encrypt_file(&tmp_path)
+                    .map_err(|err| ObjectStorageError::UnhandledError(err.into()))?;
+                let uploaded = storage.upload_file(relative.as_str(), &encrypted).await;
+                let _ = std::fs::remove_file(encrypted);
+                uploaded?;
+                trimmed.key_id = Some(keyring.active_key().to_string());
+            }
+            None => storage.upload_file(relative.as_str(), &tmp_path).await?,
+        }
+        stats.events += file.num_rows.saturating_sub(trimmed.num_rows);
+        stats.ingestion += file.ingestion_size.saturating_sub(trimmed.ingestion_size);
+        stats.storage += file.file_size.saturating_sub(trimmed.file_size);
+        evict_from_cache(stream_name, &file.file_path).await;
+        retained.push(trimmed);
+    }
+    let _ = std::fs::remove_file(&tmp_path);
+
+    if files == 0 {
+        return Ok(None);
+    }
+
+    manifest.files = retained;
+    storage.put_manifest(&path, manifest).await?;
+    for (relative, file_path) in removed {
+        evict_from_cache(stream_name, &file_path).await;
+        storage.delete_prefix(&relative).await?;
+    }
+
+    Ok(Some((stats, files)))
+}
+
+/// Remove the files of a stream on a date for which `remove` holds, given their path.
+/// Like `trim_date_partition` the manifest of the date is updated before the files
+/// are deleted. Returns the stats of the removed files and their number.
+pub async fn remove_date_files(
+    storage: Arc<dyn ObjectStorage + Send>,
+    stream_name: &str,
+    date: NaiveDate,
+    remove: impl Fn(&str) -> bool,
+) -> Result<Option<(Stats, u64)>, ObjectStorageError> {
+    let meta = storage.get_snapshot(stream_name).await?;
+    let Some(item) = meta.manifest_list.iter().find(|item| {
+        item.time_lower_bound.date_naive() == date && item.time_upper_bound.date_naive() == date
+    }) else {
+        return Ok(None);
+    };
+    let path = partition_path(stream_name, item.time_lower_bound, item.time_upper_bound);
+    let Some(mut manifest) = storage.get_manifest(&path).await? else {
+        return Ok(None);
+    };
+
+    let (removed, retained): (Vec<_>, Vec<_>) = std::mem::take(&mut manifest.files)
+        .into_iter()
+        .partition(|file| remove(&file.file_path));
+    if removed.is_empty() {
+        return Ok(None);
+    }
+
+    let mut stats = Stats::default();
+    for file in &removed {
+        stats.events += file.num_rows;
+        stats.ingestion += file.ingestion_size;
+        stats.storage += file.file_size;
+        stats.files += 1;
+    }
+    manifest.files = retained;
+    storage.put_manifest(&path, manifest).await?;
+    for file in &removed {
+        evict_from_cache(stream_name, &file.file_path).await;
+        if let Some(relative) = trim::relative_path(stream_name, &file.file_path) {
+            storage.delete_prefix(&relative).await?;
+        }
+    }
+
+    Ok(Some((stats, removed.len() as u64)))
+}
+
+// a rewritten or deleted file must not be served from the local cache anymore
+async fn evict_from_cache(stream_name: &str, file_path: &str) {
+    if let Some(cache_manager) = LocalCacheManager::global() {
+        if let Err(err) = cache_manager.remove(stream_name, file_path).await {
+            log::warn!("failed to evict {file_path} from the local cache: {err}");
+        }
+    }
+}
+
+/// Difference in schema of a stream between two dates, derived from
+/// the schema of every file written between them.
+pub async fn schema_diff(
+    storage: Arc<dyn ObjectStorage + Send>,
+    stream_name: &str,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<schema_diff::SchemaDiff, ObjectStorageError> {
+    let meta = storage.get_snapshot(stream_name).await?;
+    let mut items: Vec<_> = meta
+        .manifest_list
+        .into_iter()
+        .filter(|item| {
+            item.time_upper_bound.date_naive() >= from && item.time_lower_bound.date_naive() <= to
+        })
+        .collect();
+    items.sort_by_key(|item| item.time_lower_bound);
+
+    let mut days: Vec<(NaiveDate, schema_diff::DaySchema)> = Vec::new();
+    for item in items {
+        let path = partition_path(stream_name, item.time_lower_bound, item.time_upper_bound);
+        let Some(manifest) = storage.get_manifest(&path).await? else {
+            continue;
+        };
+        let mut schema = schema_diff::DaySchema::new();
+        for file in &manifest.files {
+            schema.extend(schema_diff::file_schema(file));
+        }
+        days.push((item.time_lower_bound.date_naive(), schema));
+    }
+
+    Ok(schema_diff::diff(&days))
+}
+
+/// Number of files listed in the manifests of a stream.
+pub async fn file_count(
+    storage: Arc<dyn ObjectStorage + Send>,
+    stream_name: &str,
+) -> Result<u64, ObjectStorageError> {
+    let meta = storage.get_snapshot(stream_name).await?;
+    let mut files = 0;
+    for item in meta.manifest_list {
+        let path = partition_path(stream_name, item.time_lower_bound, item.time_upper_bound);
+        if let Some(manifest) = storage.get_manifest(&path).await? {
+            files += manifest.files.len() as u64;
+        }
+    }
+    Ok(files)
+}
+
+/// Columns of the files of a stream holding data between `start` and `end`.
+/// Files without time statistics are assumed to be in range.
+pub async fn range_columns(
+    storage: Arc<dyn ObjectStorage + Send>,
+    stream_name: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<HashSet<String>, ObjectStorageError> {
+    let meta = storage.get_snapshot(stream_name).await?;
+    let timestamp_key = STREAM_INFO
+        .timestamp_key(stream_name)
+        .unwrap_or_else(|_| DEFAULT_TIMESTAMP_KEY.to_string());
+    let mut columns = HashSet::new();
+    for item in meta.manifest_list.into_iter().filter(|item| {
+        item.time_upper_bound.date_naive() >= start.date_naive()
+            && item.time_lower_bound.date_naive() <= end.date_naive()
+    }) {
+        let path = partition_path(stream_name, item.time_lower_bound, item.time_upper_bound);
+        let Some(manifest) = storage.get_manifest(&path).await? else {
+            continue;
+        };
+        for file in &manifest.files {
+            let bounds = file
+                .columns
+                .iter()
+                .find(|column| column.name == timestamp_key)
+                .and_then(|column| column.stats.as_ref());
+            if let Some(TypedStatistics::Int(bounds)) = bounds {
+                if bounds.max < start.timestamp_millis() || bounds.min >= end.timestamp_millis() {
+                    continue;
+                }
+            }
+            columns.extend(schema_diff::file_schema(file).into_keys());
+        }
+    }
+    Ok(columns)
+}
+
+/// Storage used by a stream per date and partition, from the files in its manifests.
+pub async fn usage_by_date(
+    storage: Arc<dyn ObjectStorage + Send>,
+    stream_name: &str,
+) -> Result<Vec<usage::DateUsage>, ObjectStorageError> {
+    let meta = storage.get_snapshot(stream_name).await?;
+    let mut files = Vec::new();
+    for item in meta.manifest_list {
+        let path = partition_path(stream_name, item.time_lower_bound, item.time_upper_bound);
+        if let Some(manifest) = storage.get_manifest(&path).await? {
+            files.extend(manifest.files);
+        }
+    }
+    Ok(usage::usage_by_date(&files))
+}
+
 /// Partition the path to which this manifest belongs.
 /// Useful when uploading the manifest file.
 fn partition_path(
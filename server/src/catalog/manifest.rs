@@ -16,12 +16,15 @@
  *
  */
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
+use arrow_schema::DataType;
 use itertools::Itertools;
-use parquet::{file::reader::FileReader, format::SortingColumn};
+use parquet::{arrow::parquet_to_arrow_schema, file::reader::FileReader, format::SortingColumn};
 
-use super::column::Column;
+use crate::event::severity::SEVERITY_NUMBER_KEY;
+
+use super::column::{Column, TypedStatistics};
 
 #[derive(
     Debug,
@@ -56,6 +59,43 @@ pub struct File {
     pub ingestion_size: u64,
     pub columns: Vec<Column>,
     pub sort_order_id: Vec<SortInfo>,
+    // column types as read from the parquet footer, not present for older files
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub schema: BTreeMap<String, DataType>,
+    // range of severity numbers in the file, absent when it has no severity_number column
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<SeverityIndex>,
+    // id of the key the file is encrypted with, absent for unencrypted files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+}
+
+/// Skip index over the OTLP `severity_number` column of a file.
+/// Lets files be pruned for severity filters which plain column statistics can
+/// not handle, such as `IN` lists, `BETWEEN` or several bands joined by `OR`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SeverityIndex {
+    pub min: i64,
+    pub max: i64,
+}
+
+impl SeverityIndex {
+    fn from_columns(columns: &[Column]) -> Option<Self> {
+        let column = columns
+            .iter()
+            .find(|column| column.name == SEVERITY_NUMBER_KEY)?;
+        match &column.stats {
+            Some(TypedStatistics::Int(stats)) => Some(SeverityIndex {
+                min: stats.min,
+                max: stats.max,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn overlaps(&self, min: i64, max: i64) -> bool {
+        min <= self.max && max >= self.min
+    }
 }
 
 /// A manifest file composed of multiple file entries.
@@ -109,8 +149,20 @@ pub fn create_from_parquet_file(
         .iter()
         .fold(0, |acc, x| acc + x.total_byte_size() as u64);
 
+    manifest_file.schema =
+        parquet_to_arrow_schema(file_meta.schema_descr(), file_meta.key_value_metadata())
+            .map(|schema| {
+                schema
+                    .fields()
+                    .iter()
+                    .map(|field| (field.name().clone(), field.data_type().clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
     let columns = column_statistics(row_groups);
     manifest_file.columns = columns.into_values().collect();
+    manifest_file.severity = SeverityIndex::from_columns(&manifest_file.columns);
     let mut sort_orders = sort_order(row_groups);
 
     if let Some(last_sort_order) = sort_orders.pop() {
@@ -0,0 +1,177 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use arrow_schema::DataType;
+use chrono::NaiveDate;
+
+use super::{column::TypedStatistics, manifest};
+
+pub type DaySchema = BTreeMap<String, DataType>;
+
+#[derive(Debug, Default, PartialEq, serde::Serialize)]
+pub struct SchemaDiff {
+    pub added: Vec<FieldChange>,
+    pub removed: Vec<FieldChange>,
+    pub changed: Vec<FieldChange>,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldChange {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_type: Option<String>,
+    // first day from which the field is in its final state
+    pub since: NaiveDate,
+}
+
+// Column types of a file. Files written before the footer schema was recorded
+// in the manifest fall back to the type of their column statistics.
+pub fn file_schema(file: &manifest::File) -> DaySchema {
+    if !file.schema.is_empty() {
+        return file.schema.clone();
+    }
+    file.columns
+        .iter()
+        .map(|column| {
+            let data_type = match column.stats {
+                Some(TypedStatistics::Bool(_)) => DataType::Boolean,
+                Some(TypedStatistics::Int(_)) => DataType::Int64,
+                Some(TypedStatistics::Float(_)) => DataType::Float64,
+                Some(TypedStatistics::String(_)) | None => DataType::Utf8,
+            };
+            (column.name.clone(), data_type)
+        })
+        .collect()
+}
+
+// Difference between the schema of the first and the last day.
+// `days` holds the union of file schemas of every day with data, in order.
+// The date of a change is the first day of the trailing run of days
+// in which the field has its final state, so it is exact up to a day.
+pub fn diff(days: &[(NaiveDate, DaySchema)]) -> SchemaDiff {
+    let mut diff = SchemaDiff::default();
+    let (Some((_, first)), Some((_, last))) = (days.first(), days.last()) else {
+        return diff;
+    };
+
+    for (name, data_type) in last {
+        match first.get(name) {
+            None => diff.added.push(FieldChange {
+                name: name.clone(),
+                from_type: None,
+                to_type: Some(data_type.to_string()),
+                since: since(days, |schema| schema.contains_key(name)),
+            }),
+            Some(old_type) if old_type != data_type => diff.changed.push(FieldChange {
+                name: name.clone(),
+                from_type: Some(old_type.to_string()),
+                to_type: Some(data_type.to_string()),
+                since: since(days, |schema| schema.get(name) == Some(data_type)),
+            }),
+            Some(_) => {}
+        }
+    }
+
+    for (name, data_type) in first {
+        if !last.contains_key(name) {
+            diff.removed.push(FieldChange {
+                name: name.clone(),
+                from_type: Some(data_type.to_string()),
+                to_type: None,
+                since: since(days, |schema| !schema.contains_key(name)),
+            })
+        }
+    }
+
+    diff
+}
+
+fn since(days: &[(NaiveDate, DaySchema)], holds: impl Fn(&DaySchema) -> bool) -> NaiveDate {
+    days.iter()
+        .rev()
+        .take_while(|(_, schema)| holds(schema))
+        .last()
+        .map(|(date, _)| *date)
+        .expect("holds for the last day")
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_schema::DataType;
+    use chrono::NaiveDate;
+
+    use super::{diff, DaySchema};
+
+    fn day(date: &str, fields: &[(&str, DataType)]) -> (NaiveDate, DaySchema) {
+        (
+            date.parse().unwrap(),
+            fields
+                .iter()
+                .map(|(name, data_type)| (name.to_string(), data_type.clone()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn added_removed_and_changed_fields() {
+        let days = [
+            day(
+                "2024-01-01",
+                &[("msg", DataType::Utf8), ("code", DataType::Int64)],
+            ),
+            day(
+                "2024-01-02",
+                &[("message", DataType::Utf8), ("code", DataType::Int64)],
+            ),
+            day(
+                "2024-01-03",
+                &[("message", DataType::Utf8), ("code", DataType::Utf8)],
+            ),
+        ];
+        let diff = diff(&days);
+
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].name, "message");
+        assert_eq!(diff.added[0].since.to_string(), "2024-01-02");
+        assert_eq!(diff.removed[0].name, "msg");
+        assert_eq!(diff.removed[0].since.to_string(), "2024-01-02");
+        assert_eq!(diff.changed[0].name, "code");
+        assert_eq!(diff.changed[0].from_type.as_deref(), Some("Int64"));
+        assert_eq!(diff.changed[0].to_type.as_deref(), Some("Utf8"));
+        assert_eq!(diff.changed[0].since.to_string(), "2024-01-03");
+    }
+
+    #[test]
+    fn reappearing_field_reports_last_change() {
+        let days = [
+            day("2024-01-01", &[]),
+            day("2024-01-02", &[("level", DataType::Utf8)]),
+            day("2024-01-03", &[]),
+            day("2024-01-04", &[("level", DataType::Utf8)]),
+        ];
+        let diff = diff(&days);
+
+        assert_eq!(diff.added[0].since.to_string(), "2024-01-04");
+        assert!(diff.removed.is_empty() && diff.changed.is_empty());
+    }
+}
@@ -0,0 +1,164 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{fs::File, path::Path};
+
+use arrow_array::{RecordBatch, Scalar, TimestampMillisecondArray};
+use arrow_schema::{DataType, TimeUnit};
+use bytes::Bytes;
+use datafusion::arrow::compute::{cast, filter_record_batch, kernels::cmp::gt_eq};
+use parquet::{
+    arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter},
+    file::properties::WriterProperties,
+};
+use relative_path::RelativePathBuf;
+
+/// Path of a file in a manifest relative to the root of the store,
+/// files of a stream are always stored under `{stream}/date=..`
+pub fn relative_path(stream: &str, file_path: &str) -> Option<RelativePathBuf> {
+    let start = file_path.rfind(&format!("{stream}/date="))?;
+    Some(RelativePathBuf::from(&file_path[start..]))
+}
+
+/// Rewrite a parquet file keeping only the rows at or after `cutoff` (epoch millis)
+/// in its timestamp column. Returns the number of rows written to `out`.
+pub fn trim_parquet(
+    data: Bytes,
+    timestamp_key: &str,
+    cutoff: i64,
+    props: WriterProperties,
+    out: &Path,
+) -> anyhow::Result<u64> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(data)?.build()?;
+    let cutoff = Scalar::new(TimestampMillisecondArray::from(vec![cutoff]));
+
+    let mut writer: Option<ArrowWriter<File>> = None;
+    let mut rows = 0;
+    for batch in reader {
+        let batch = batch?;
+        let batch = retain_from(&batch, timestamp_key, &cutoff)?;
+        let writer = match writer.as_mut() {
+            Some(writer) => writer,
+            None => writer.insert(ArrowWriter::try_new(
+                File::create(out)?,
+                batch.schema(),
+                Some(props.clone()),
+            )?),
+        };
+        rows += batch.num_rows() as u64;
+        writer.write(&batch)?;
+    }
+
+    if let Some(writer) = writer {
+        writer.close()?;
+    }
+    Ok(rows)
+}
+
+fn retain_from(
+    batch: &RecordBatch,
+    timestamp_key: &str,
+    cutoff: &Scalar<TimestampMillisecondArray>,
+) -> anyhow::Result<RecordBatch> {
+    let Some(column) = batch.column_by_name(timestamp_key) else {
+        anyhow::bail!("timestamp column {timestamp_key} is missing");
+    };
+    let column = cast(column, &DataType::Timestamp(TimeUnit::Millisecond, None))?;
+    let mask = gt_eq(&column, cutoff)?;
+    Ok(filter_record_batch(batch, &mask)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int64Array, RecordBatch, TimestampMillisecondArray};
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use bytes::Bytes;
+    use parquet::{
+        arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter},
+        file::properties::WriterProperties,
+    };
+
+    use super::{relative_path, trim_parquet};
+
+    #[test]
+    fn relative_path_of_file() {
+        assert_eq!(
+            relative_path("app", "data/app/date=2024-01-01/hour=00/a.parquet")
+                .unwrap()
+                .as_str(),
+            "app/date=2024-01-01/hour=00/a.parquet"
+        );
+        assert!(relative_path("app", "data/other/date=2024-01-01/a.parquet").is_none());
+    }
+
+    #[test]
+    fn trim_rows_before_cutoff() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("value", DataType::Int64, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(TimestampMillisecondArray::from(vec![300, 200, 100])),
+                Arc::new(Int64Array::from(vec![3, 2, 1])),
+            ],
+        )
+        .unwrap();
+        let mut data = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut data, schema, None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let dir = std::env::temp_dir().join(format!("trim-{}", ulid::Ulid::new()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let out = dir.join("out.parquet");
+        let rows = trim_parquet(
+            Bytes::from(data),
+            "p_timestamp",
+            200,
+            WriterProperties::default(),
+            &out,
+        )
+        .unwrap();
+        assert_eq!(rows, 2);
+
+        let file = std::fs::File::open(&out).unwrap();
+        let batches: Vec<_> = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            batches[0]
+                .column(1)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap(),
+            &Int64Array::from(vec![3, 2])
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
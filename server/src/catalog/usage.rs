@@ -0,0 +1,143 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+
+use crate::storage::partition;
+
+use super::manifest;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    pub files: u64,
+    pub storage_size: u64,
+}
+
+impl Usage {
+    fn add(&mut self, file: &manifest::File) {
+        self.files += 1;
+        self.storage_size += file.file_size;
+    }
+}
+
+// Storage used by the files of a stream for a date, split further by the
+// values of every partition column of the stream
+#[derive(Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DateUsage {
+    pub date: NaiveDate,
+    #[serde(flatten)]
+    pub usage: Usage,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub partitions: BTreeMap<String, BTreeMap<String, Usage>>,
+}
+
+// Usage by date, derived from the paths and sizes of files in the manifests
+pub fn usage_by_date<'a>(files: impl IntoIterator<Item = &'a manifest::File>) -> Vec<DateUsage> {
+    let mut dates: BTreeMap<NaiveDate, DateUsage> = BTreeMap::new();
+    for file in files {
+        let Some(date) = file_date(&file.file_path) else {
+            continue;
+        };
+        let entry = dates.entry(date).or_insert_with(|| DateUsage {
+            date,
+            usage: Usage::default(),
+            partitions: BTreeMap::new(),
+        });
+        entry.usage.add(file);
+        for (column, value) in partition::partition_dirs(&file.file_path) {
+            entry
+                .partitions
+                .entry(column.to_string())
+                .or_default()
+                .entry(value.to_string())
+                .or_default()
+                .add(file);
+        }
+    }
+    dates.into_values().collect()
+}
+
+fn file_date(file_path: &str) -> Option<NaiveDate> {
+    file_path.split('/').find_map(|segment| {
+        let date = segment.strip_prefix("date=")?;
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use crate::catalog::manifest::File;
+
+    use super::{usage_by_date, Usage};
+
+    fn file(path: &str, file_size: u64) -> File {
+        File {
+            file_path: path.to_string(),
+            file_size,
+            ..File::default()
+        }
+    }
+
+    #[test]
+    fn usage_grouped_by_date_and_partition() {
+        let files = [
+            file(
+                "s3://b/app/date=2024-01-02/hour=00/minute=00/host.data.parquet",
+                10,
+            ),
+            file(
+                "s3://b/app/date=2024-01-01/hour=00/minute=00/service_name=web/host.data.parquet",
+                20,
+            ),
+            file(
+                "s3://b/app/date=2024-01-01/hour=01/minute=00/service_name=web/host.data.parquet",
+                30,
+            ),
+            file(
+                "s3://b/app/date=2024-01-01/hour=01/minute=00/service_name=db/host.data.parquet",
+                5,
+            ),
+        ];
+        let usage = usage_by_date(&files);
+
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage[0].date, NaiveDate::from_ymd_opt(2024, 1, 1).unwrap());
+        assert_eq!(
+            usage[0].usage,
+            Usage {
+                files: 3,
+                storage_size: 55
+            }
+        );
+        assert_eq!(
+            usage[0].partitions["service_name"]["web"],
+            Usage {
+                files: 2,
+                storage_size: 50
+            }
+        );
+        assert!(usage[1].partitions.is_empty());
+        assert_eq!(usage[1].usage.storage_size, 10);
+    }
+}
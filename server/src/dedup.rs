@@ -0,0 +1,311 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+
+use crate::metrics::SUPPRESSED_REPEATS;
+use crate::sampling::Labels;
+
+// column holding the number of identical events a stored event stands for
+pub const REPEAT_COUNT_KEY: &str = "repeat_count";
+
+// how often repeats of windows which have ended are flushed
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+pub static REPEATS: Lazy<Repeats> = Lazy::new(Repeats::default);
+
+// Per stream suppression of repeated events, like syslog's "last message
+// repeated N times". The first of consecutive identical events (same fields
+// other than the timestamp and time fields of the stream, and same tags and
+// metadata) is ingested as is, the ones repeating it within
+// `window_secs` of it are only counted. The repeats are then ingested as a
+// single copy of the event once a different event arrives or the window ends,
+// so their p_timestamp is the time they are flushed.
+// Every ingested event has a `repeat_count`, 1 for events ingested as is and
+// the number of repeats for the copy standing for them, so that the number of
+// events sent is sum(repeat_count).
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Dedup {
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+}
+
+fn default_window_secs() -> u64 {
+    10
+}
+
+impl Dedup {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.window_secs == 0 {
+            return Err("windowSecs must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+// The last event ingested for a stream and the number of times it was repeated since
+#[derive(Debug)]
+struct Run {
+    hash: u64,
+    labels: Labels,
+    event: Value,
+    ends_at: DateTime<Utc>,
+    repeats: u64,
+}
+
+impl Run {
+    // copy of the event standing for its repeats
+    fn into_repeated(self) -> Option<(Labels, Value)> {
+        (self.repeats > 0).then(|| (self.labels, with_repeat_count(self.event, self.repeats)))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Repeats {
+    runs: Mutex<HashMap<String, Run>>,
+}
+
+impl Repeats {
+    /// Drop the events of an ingest body repeating the previous event of the
+    /// stream. Returns the events to ingest grouped by consecutive labels, which
+    /// includes the repeats of an earlier event ended by an event of this body.
+    /// Fields in `ignored`, such as time fields, may differ between repeats.
+    pub fn collapse(
+        &self,
+        stream_name: &str,
+        dedup: &Dedup,
+        labels: &Labels,
+        body: Value,
+        ignored: &[String],
+        now: DateTime<Utc>,
+    ) -> Vec<(Labels, Vec<Value>)> {
+        let events = match body {
+            Value::Array(events) => events,
+            event => vec![event],
+        };
+        let mut runs = self.runs.lock().unwrap();
+        let mut ingest: Vec<(Labels, Vec<Value>)> = Vec::new();
+        let mut push = |labels: Labels, event: Value| match ingest.last_mut() {
+            Some((last, events)) if *last == labels => events.push(event),
+            _ => ingest.push((labels, vec![event])),
+        };
+
+        let mut suppressed = 0;
+        for event in events {
+            let hash = hash(&event, ignored);
+            if let Some(run) = runs.get_mut(stream_name) {
+                if run.hash == hash && run.labels == *labels && now < run.ends_at {
+                    run.repeats += 1;
+                    suppressed += 1;
+                    continue;
+                }
+            }
+            let run = Run {
+                hash,
+                labels: labels.clone(),
+                event: event.clone(),
+                ends_at: now + chrono::Duration::seconds(dedup.window_secs as i64),
+                repeats: 0,
+            };
+            if let Some((labels, event)) = runs
+                .insert(stream_name.to_string(), run)
+                .and_then(Run::into_repeated)
+            {
+                push(labels, event);
+            }
+            push(labels.clone(), with_repeat_count(event, 1));
+        }
+
+        if suppressed > 0 {
+            SUPPRESSED_REPEATS
+                .with_label_values(&[stream_name])
+                .inc_by(suppressed);
+        }
+        ingest
+    }
+
+    /// Remove the runs whose window ended by now and return the repeats to ingest
+    pub fn take_ended(&self, now: DateTime<Utc>) -> Vec<(String, Labels, Value)> {
+        let mut runs = self.runs.lock().unwrap();
+        let ended: Vec<String> = runs
+            .iter()
+            .filter(|(_, run)| run.ends_at <= now)
+            .map(|(stream_name, _)| stream_name.clone())
+            .collect();
+        ended
+            .into_iter()
+            .filter_map(|stream_name| {
+                let (labels, event) = runs.remove(&stream_name)?.into_repeated()?;
+                Some((stream_name, labels, event))
+            })
+            .collect()
+    }
+}
+
+fn hash(event: &Value, ignored: &[String]) -> u64 {
+    let bytes = match event {
+        Value::Object(map) => serde_json::to_vec(
+            &map.iter()
+                .filter(|(key, _)| !ignored.contains(key))
+                .collect::<BTreeMap<_, _>>(),
+        ),
+        event => serde_json::to_vec(event),
+    };
+    xxhash_rust::xxh3::xxh3_64(&bytes.expect("event can be serialized"))
+}
+
+fn with_repeat_count(mut event: Value, count: u64) -> Value {
+    if let Value::Object(map) = &mut event {
+        map.insert(REPEAT_COUNT_KEY.to_string(), Value::from(count));
+    }
+    event
+}
+
+// Ingest the repeats of runs as their windows end
+pub fn init_repeat_scheduler() {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(FLUSH_INTERVAL).await;
+            ingest_repeats(REPEATS.take_ended(Utc::now())).await;
+        }
+    });
+}
+
+// Ingest the repeats of every run, ended or not, before the server stops
+pub async fn flush_repeats() {
+    ingest_repeats(REPEATS.take_ended(DateTime::<Utc>::MAX_UTC)).await;
+}
+
+async fn ingest_repeats(runs: Vec<(String, Labels, Value)>) {
+    for (stream_name, labels, event) in runs {
+        if let Err(err) = crate::handlers::http::push_repeats(&stream_name, labels, event).await {
+            log::warn!(
+                "failed to ingest repeated events of stream {}: {}",
+                stream_name,
+                err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+    use serde_json::json;
+
+    use super::{Dedup, Repeats};
+    use crate::sampling::Labels;
+
+    fn labels(tags: &str) -> Labels {
+        Labels {
+            tags: tags.to_string(),
+            metadata: String::new(),
+        }
+    }
+
+    #[test]
+    fn consecutive_repeats_are_collapsed() {
+        let repeats = Repeats::default();
+        let dedup = Dedup { window_secs: 10 };
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 10, 0, 0).unwrap();
+        let line = json!({"body": "retrying connection"});
+
+        let ingest = repeats.collapse(
+            "app",
+            &dedup,
+            &labels(""),
+            json!([line, line, line, {"body": "connected"}, line]),
+            &[],
+            now,
+        );
+
+        assert_eq!(ingest.len(), 1);
+        let events = &ingest[0].1;
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0]["repeat_count"], 1);
+        assert_eq!(events[1]["body"], "retrying connection");
+        assert_eq!(events[1]["repeat_count"], 2);
+        assert_eq!(events[2]["body"], "connected");
+        assert_eq!(events[3]["repeat_count"], 1);
+    }
+
+    #[test]
+    fn repeats_are_flushed_when_the_window_ends() {
+        let repeats = Repeats::default();
+        let dedup = Dedup { window_secs: 10 };
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 10, 0, 0).unwrap();
+        let line = json!({"body": "retrying connection"});
+
+        repeats.collapse("app", &dedup, &labels(""), json!([line, line]), &[], now);
+        // same event with other tags is not a repeat
+        let ingest = repeats.collapse("app", &dedup, &labels("a"), line.clone(), &[], now);
+        assert_eq!(ingest.len(), 2);
+        assert_eq!(ingest[0].1[0]["repeat_count"], 1);
+        assert_eq!(ingest[1].0, labels("a"));
+
+        repeats.collapse("app", &dedup, &labels("a"), line.clone(), &[], now);
+        assert!(repeats.take_ended(now).is_empty());
+        let ended = repeats.take_ended(now + Duration::seconds(10));
+        assert_eq!(ended.len(), 1);
+        assert_eq!(ended[0].2["repeat_count"], 1);
+
+        // a repeat after the window is ingested as is
+        let ingest = repeats.collapse(
+            "app",
+            &dedup,
+            &labels("a"),
+            line,
+            &[],
+            now + Duration::seconds(11),
+        );
+        assert_eq!(ingest[0].1[0]["repeat_count"], 1);
+    }
+
+    #[test]
+    fn time_fields_ignored_and_open_runs_flushed() {
+        let repeats = Repeats::default();
+        let dedup = Dedup { window_secs: 10 };
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 10, 0, 0).unwrap();
+        let ignored = ["time".to_string()];
+
+        let ingest = repeats.collapse(
+            "app",
+            &dedup,
+            &labels(""),
+            json!([
+                {"body": "retrying", "time": "10:00:00"},
+                {"body": "retrying", "time": "10:00:01"}
+            ]),
+            &ignored,
+            now,
+        );
+        assert_eq!(ingest[0].1.len(), 1);
+
+        // runs whose window has not ended are flushed too
+        let ended = repeats.take_ended(chrono::DateTime::<Utc>::MAX_UTC);
+        assert_eq!(ended.len(), 1);
+        assert_eq!(ended[0].2["repeat_count"], 1);
+    }
+}
@@ -17,18 +17,37 @@
 *
 */
 
+pub mod attribute_filter;
+pub mod attributes;
+pub mod body;
 pub mod format;
+pub mod ip_mask;
+pub mod keys;
+pub mod numbers;
+pub mod raw;
+pub mod receipts;
+pub mod routing;
+pub mod schema_lock;
+pub mod severity;
+pub mod shadow;
+pub mod skew;
+pub mod trace;
+pub mod type_conflict;
 mod writer;
 
 use arrow_array::RecordBatch;
 use arrow_schema::{Field, Fields, Schema};
 use itertools::Itertools;
 
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use crate::metadata;
+use crate::metrics::SCHEMA_VERSIONS;
+use crate::storage::{partition, staging};
 
 use self::error::EventError;
+use self::receipts::Receipt;
 pub use self::writer::STREAM_WRITERS;
 
 pub const DEFAULT_TIMESTAMP_KEY: &str = "p_timestamp";
@@ -42,6 +61,8 @@ pub struct Event {
     pub origin_format: &'static str,
     pub origin_size: u64,
     pub is_first_event: bool,
+    // replayed rows keep the time they were first ingested at
+    pub replayed: bool,
 }
 
 // Events holds the schema related to a each event for a single log stream
@@ -54,7 +75,7 @@ impl Event {
             commit_schema(&self.stream_name, self.rb.schema())?;
         }
 
-        Self::process_event(&self.stream_name, &key, self.rb.clone())?;
+        Self::process_event(&self.stream_name, &key, self.rb.clone(), self.replayed)?;
 
         metadata::STREAM_INFO.update_stats(
             &self.stream_name,
@@ -81,12 +102,63 @@ impl Event {
         stream_name: &str,
         schema_key: &str,
         rb: RecordBatch,
+        replayed: bool,
     ) -> Result<(), EventError> {
-        STREAM_WRITERS.append_to_local(stream_name, schema_key, rb)?;
+        let collecting = receipts::is_collecting() || trace::is_tracing();
+        match metadata::STREAM_INFO.partitioning(stream_name)? {
+            Some(partitioning) => {
+                let segments = partitioning.segments(stream_name, &rb);
+                let mut dirs = HashMap::new();
+                for (segment, rb) in partition::split_by(&segments, &rb) {
+                    let rows = rb.num_rows();
+                    let staged = STREAM_WRITERS.append_to_local(
+                        stream_name,
+                        schema_key,
+                        &segment,
+                        rb,
+                        replayed,
+                    )?;
+                    if collecting {
+                        let dir = staging::object_store_dir(&staged.file_path);
+                        trace_staged(stream_name, &dir, rows);
+                        dirs.insert(segment, (staged.timestamp, dir));
+                    }
+                }
+                // receipts follow the order of the rows
+                receipts::record(segments.iter().filter_map(|segment| {
+                    let (timestamp, dir) = dirs.get(segment)?;
+                    Some(Receipt::new(stream_name, *timestamp, dir.clone()))
+                }));
+            }
+            None => {
+                let rows = rb.num_rows();
+                let staged =
+                    STREAM_WRITERS.append_to_local(stream_name, schema_key, "", rb, replayed)?;
+                if collecting {
+                    let dir = staging::object_store_dir(&staged.file_path);
+                    trace_staged(stream_name, &dir, rows);
+                    receipts::record(
+                        (0..rows).map(|_| Receipt::new(stream_name, staged.timestamp, dir.clone())),
+                    );
+                }
+            }
+        }
         Ok(())
     }
 }
 
+fn trace_staged(stream_name: &str, dir: &str, rows: usize) {
+    trace::record(|trace| {
+        trace.records_staged += rows as u64;
+        *trace
+            .partitions
+            .entry(stream_name.to_string())
+            .or_default()
+            .entry(dir.to_string())
+            .or_default() += rows as u64;
+    });
+}
+
 pub fn get_schema_key(fields: &[Arc<Field>]) -> String {
     // Fields must be sorted
     let mut hasher = xxhash_rust::xxh3::Xxh3::new();
@@ -106,6 +178,10 @@ pub fn commit_schema(stream_name: &str, schema: Arc<Schema>) -> Result<(), Event
         .schema;
     let current_schema = Schema::new(map.values().cloned().collect::<Fields>());
     let schema = Schema::try_merge(vec![current_schema, schema.as_ref().clone()])?;
+    // merging only ever adds fields, so every change in field count is a new schema
+    if schema.fields.len() != map.len() {
+        SCHEMA_VERSIONS.with_label_values(&[stream_name]).inc();
+    }
     map.clear();
     map.extend(schema.fields.iter().map(|f| (f.name().clone(), f.clone())));
     Ok(())
@@ -131,3 +207,37 @@ pub mod error {
         ObjectStorage(#[from] ObjectStorageError),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_schema::{DataType, Field, Schema};
+
+    use super::commit_schema;
+    use crate::metadata::{LogStreamMetadata, STREAM_INFO};
+    use crate::metrics::SCHEMA_VERSIONS;
+
+    #[test]
+    fn new_fields_bump_schema_versions() {
+        let stream = "schema_versions_test";
+        STREAM_INFO
+            .write()
+            .unwrap()
+            .insert(stream.to_string(), LogStreamMetadata::default());
+        let schema = |names: &[&str]| {
+            Arc::new(Schema::new(
+                names
+                    .iter()
+                    .map(|name| Field::new(*name, DataType::Utf8, true))
+                    .collect::<Vec<_>>(),
+            ))
+        };
+
+        commit_schema(stream, schema(&["a"])).unwrap();
+        commit_schema(stream, schema(&["a"])).unwrap();
+        commit_schema(stream, schema(&["a", "b"])).unwrap();
+
+        assert_eq!(SCHEMA_VERSIONS.with_label_values(&[stream]).get(), 2);
+    }
+}
@@ -0,0 +1,133 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use serde_json::Value;
+
+// Per stream allow and deny lists of the attributes kept from events, applied
+// after nested fields are flattened so that noisy attributes never reach the
+// schema of the stream. With an allow list only the attributes it lists are
+// kept, attributes on the deny list are always dropped, even when allowed.
+// Entries ending with `*` match every attribute starting with the rest, such
+// as `k8s_labels_*`. The timestamp column of the stream is always kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AttributeFilter {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+impl AttributeFilter {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.allow.is_empty() && self.deny.is_empty() {
+            return Err("either an allow or a deny list is required".to_string());
+        }
+        if let Some(empty) = self
+            .allow
+            .iter()
+            .chain(&self.deny)
+            .find(|key| key.trim_end_matches('*').trim().is_empty())
+        {
+            return Err(format!("{empty:?} does not name an attribute"));
+        }
+        Ok(())
+    }
+
+    pub fn keeps(&self, key: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix('*') {
+            Some(prefix) => key.starts_with(prefix),
+            None => key == pattern,
+        };
+        if self.deny.iter().any(matches) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(matches)
+    }
+
+    /// Drop the attributes of flattened events which are not kept, returns the
+    /// number of attributes dropped
+    pub fn apply(&self, value: &mut Value, timestamp_key: &str) -> usize {
+        match value {
+            Value::Array(events) => events
+                .iter_mut()
+                .map(|event| self.apply(event, timestamp_key))
+                .sum(),
+            Value::Object(event) => {
+                let count = event.len();
+                event.retain(|key, _| key == timestamp_key || self.keeps(key));
+                count - event.len()
+            }
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::AttributeFilter;
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let filter: AttributeFilter = serde_json::from_value(json!({
+            "allow": ["level", "msg", "http_*"],
+            "deny": ["http_user_agent", "msg"]
+        }))
+        .unwrap();
+        assert!(filter.validate().is_ok());
+
+        let mut events = json!([
+            {
+                "time": "2024-05-01T10:00:00Z",
+                "level": "info",
+                "msg": "ok",
+                "http_status": 200,
+                "http_user_agent": "curl",
+                "trace_id": "abc"
+            },
+            {"level": "warn"}
+        ]);
+        assert_eq!(filter.apply(&mut events, "time"), 3);
+        assert_eq!(
+            events,
+            json!([
+                {"time": "2024-05-01T10:00:00Z", "level": "info", "http_status": 200},
+                {"level": "warn"}
+            ])
+        );
+    }
+
+    #[test]
+    fn deny_only_keeps_the_rest() {
+        let filter = AttributeFilter {
+            allow: Vec::new(),
+            deny: vec!["k8s_labels_*".to_string()],
+        };
+        let mut event = json!({"k8s_labels_app": "web", "k8s_pod": "web-1"});
+        assert_eq!(filter.apply(&mut event, "p_timestamp"), 1);
+        assert_eq!(event, json!({"k8s_pod": "web-1"}));
+
+        assert!(AttributeFilter::default().validate().is_err());
+        let filter = AttributeFilter {
+            allow: vec!["*".to_string()],
+            deny: Vec::new(),
+        };
+        assert!(filter.validate().is_err());
+    }
+}
@@ -0,0 +1,188 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::sync::Arc;
+
+use arrow_schema::{DataType, Field, Fields};
+use serde_json::{Map, Value};
+
+use super::severity::{SEVERITY_NUMBER_KEY, SEVERITY_TEXT_KEY};
+use super::{DEFAULT_METADATA_KEY, DEFAULT_TAGS_KEY, DEFAULT_TIMESTAMP_KEY};
+
+pub const ATTRIBUTES_KEY: &str = "attributes";
+
+// Per stream storage of the attributes of events in a single map column,
+// instead of a column per attribute. Meant for sources whose attributes
+// differ from event to event, where every new attribute would otherwise grow
+// the schema of the stream. Attributes listed in `columns`, time fields and
+// severity columns are still stored as their own columns. Values are stored as strings, other JSON values as
+// their JSON encoding, and are read with `map_get(attributes, 'key')`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttributeMap {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub columns: Vec<String>,
+}
+
+impl AttributeMap {
+    pub fn validate(&self) -> Result<(), String> {
+        let column = self.column();
+        if column.trim().is_empty() {
+            return Err("attribute column cannot be empty".to_string());
+        }
+        if [
+            DEFAULT_TIMESTAMP_KEY,
+            DEFAULT_TAGS_KEY,
+            DEFAULT_METADATA_KEY,
+        ]
+        .contains(&column)
+        {
+            return Err(format!(
+                "attribute column cannot be the reserved column {column}"
+            ));
+        }
+        if self.columns.iter().any(|name| name == column) {
+            return Err(format!(
+                "attribute column {column} cannot also be kept as a column"
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn column(&self) -> &str {
+        self.column.as_deref().unwrap_or(ATTRIBUTES_KEY)
+    }
+
+    /// Move the attributes of flattened events, other than the ones kept as
+    /// columns, into the attribute column. Null attributes are left out.
+    /// The timestamp, time fields and severity columns always stay columns.
+    pub fn apply(&self, value: &mut Value, timestamp_key: &str, time_fields: &[String]) {
+        match value {
+            Value::Array(events) => events
+                .iter_mut()
+                .for_each(|event| self.apply(event, timestamp_key, time_fields)),
+            Value::Object(event) => {
+                let keys: Vec<String> = event
+                    .keys()
+                    .filter(|key| {
+                        !self.columns.contains(key)
+                            && !time_fields.contains(key)
+                            && ![timestamp_key, SEVERITY_NUMBER_KEY, SEVERITY_TEXT_KEY]
+                                .contains(&key.as_str())
+                    })
+                    .cloned()
+                    .collect();
+                let mut attributes = Map::new();
+                for key in keys {
+                    let value = match event.remove(&key) {
+                        Some(Value::Null) | None => continue,
+                        Some(Value::String(value)) => value,
+                        Some(value) => value.to_string(),
+                    };
+                    attributes.insert(key, Value::String(value));
+                }
+                if !attributes.is_empty() {
+                    event.insert(self.column().to_string(), Value::Object(attributes));
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Type of the attribute column, string keys mapped to string values
+pub fn map_data_type() -> DataType {
+    let entries = Fields::from(vec![
+        Field::new("keys", DataType::Utf8, false),
+        Field::new("values", DataType::Utf8, true),
+    ]);
+    DataType::Map(
+        Arc::new(Field::new("entries", DataType::Struct(entries), false)),
+        false,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::AttributeMap;
+
+    #[test]
+    fn attributes_are_moved_into_map() {
+        let config = AttributeMap {
+            column: None,
+            columns: vec!["level".to_string()],
+        };
+        let mut events = json!([
+            {"level": "info", "user_id": 7, "path": "/", "tags": ["a"], "ref": null},
+            {"level": "warn"}
+        ]);
+        config.apply(&mut events, "p_timestamp", &[]);
+
+        assert_eq!(
+            events,
+            json!([
+                {
+                    "level": "info",
+                    "attributes": {"user_id": "7", "path": "/", "tags": "[\"a\"]"}
+                },
+                {"level": "warn"}
+            ])
+        );
+    }
+
+    #[test]
+    fn time_and_severity_stay_columns() {
+        let config = AttributeMap::default();
+        let mut event = json!({
+            "seen": "2024-01-01T00:00:00Z",
+            "severity_number": 9,
+            "severity_text": "INFO",
+            "user_id": 7
+        });
+        config.apply(&mut event, "p_timestamp", &["seen".to_string()]);
+
+        assert_eq!(
+            event,
+            json!({
+                "seen": "2024-01-01T00:00:00Z",
+                "severity_number": 9,
+                "severity_text": "INFO",
+                "attributes": {"user_id": "7"}
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_columns() {
+        let reserved = AttributeMap {
+            column: Some("p_tags".to_string()),
+            columns: Vec::new(),
+        };
+        assert!(reserved.validate().is_err());
+
+        let kept = AttributeMap {
+            column: None,
+            columns: vec!["attributes".to_string()],
+        };
+        assert!(kept.validate().is_err());
+    }
+}
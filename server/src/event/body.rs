@@ -0,0 +1,133 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use super::{DEFAULT_METADATA_KEY, DEFAULT_TAGS_KEY, DEFAULT_TIMESTAMP_KEY};
+
+pub const BODY_KEY: &str = "body";
+
+// Per stream configuration of the column holding the body of OTLP log records.
+// `column` renames the body column (it is `body` by default) and `force_string`
+// stores structured bodies as their JSON encoding, so that the column is
+// always a string no matter what the body of a record is.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BodyConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<String>,
+    #[serde(default)]
+    pub force_string: bool,
+}
+
+impl BodyConfig {
+    /// `timestamp_key` is the column the stream keeps the time of events in
+    pub fn validate(&self, timestamp_key: &str) -> Result<(), String> {
+        let reserved = [
+            DEFAULT_TIMESTAMP_KEY,
+            timestamp_key,
+            DEFAULT_TAGS_KEY,
+            DEFAULT_METADATA_KEY,
+        ];
+        match self.column.as_deref().map(str::trim) {
+            Some("") => Err("body column cannot be empty".to_string()),
+            Some(column) if reserved.contains(&column) => Err(format!(
+                "body column cannot be the reserved column {column}"
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Move the body of a flattened record to the configured column.
+    /// The body replaces any other field of the record with that name.
+    pub fn apply(&self, record: &mut BTreeMap<String, Value>) {
+        let Some(mut body) = record.remove(BODY_KEY) else {
+            return;
+        };
+        if self.force_string {
+            body = match body {
+                Value::Null | Value::String(_) => body,
+                body => Value::String(body.to_string()),
+            };
+        }
+        let column = self.column.as_deref().unwrap_or(BODY_KEY);
+        record.insert(column.to_string(), body);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::{json, Value};
+
+    use super::BodyConfig;
+
+    fn record(body: Value) -> BTreeMap<String, Value> {
+        BTreeMap::from([
+            ("body".to_string(), body),
+            ("severity_text".to_string(), json!("INFO")),
+        ])
+    }
+
+    #[test]
+    fn default_config_keeps_typed_body() {
+        let mut event = record(json!({"user": "a"}));
+        BodyConfig::default().apply(&mut event);
+        assert_eq!(event["body"], json!({"user": "a"}));
+    }
+
+    #[test]
+    fn renamed_and_forced_string_body() {
+        let config = BodyConfig {
+            column: Some("message".to_string()),
+            force_string: true,
+        };
+
+        let mut event = record(json!({"user": "a"}));
+        config.apply(&mut event);
+        assert!(!event.contains_key("body"));
+        assert_eq!(event["message"], json!(r#"{"user":"a"}"#));
+
+        let mut event = record(json!(42));
+        config.apply(&mut event);
+        assert_eq!(event["message"], json!("42"));
+
+        let mut event = record(json!("plain"));
+        config.apply(&mut event);
+        assert_eq!(event["message"], json!("plain"));
+    }
+
+    #[test]
+    fn reserved_column_is_invalid() {
+        for column in ["p_timestamp", "event_time", "p_tags", "p_metadata"] {
+            let config = BodyConfig {
+                column: Some(column.to_string()),
+                force_string: false,
+            };
+            assert!(config.validate("event_time").is_err());
+        }
+        let config = BodyConfig {
+            column: Some("message".to_string()),
+            force_string: false,
+        };
+        assert!(config.validate("event_time").is_ok());
+    }
+}
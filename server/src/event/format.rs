@@ -25,8 +25,9 @@ use arrow_schema::{DataType, Field, Schema, TimeUnit};
 
 use crate::utils::{self, arrow::get_field};
 
-use super::{DEFAULT_METADATA_KEY, DEFAULT_TAGS_KEY, DEFAULT_TIMESTAMP_KEY};
+use super::{DEFAULT_METADATA_KEY, DEFAULT_TAGS_KEY};
 
+pub mod arrow;
 pub mod json;
 
 type Tags = String;
@@ -46,6 +47,7 @@ pub trait EventFormat: Sized {
     fn into_recordbatch(
         self,
         schema: HashMap<String, Arc<Field>>,
+        timestamp_key: &str,
     ) -> Result<(RecordBatch, bool), AnyError> {
         let (data, mut schema, is_first, tags, metadata) = self.to_data(schema)?;
 
@@ -53,25 +55,22 @@ pub trait EventFormat: Sized {
             return Err(anyhow!("field {} is a reserved field", DEFAULT_TAGS_KEY));
         };
 
-        if get_field(&schema, DEFAULT_TAGS_KEY).is_some() {
+        if get_field(&schema, DEFAULT_METADATA_KEY).is_some() {
             return Err(anyhow!(
                 "field {} is a reserved field",
                 DEFAULT_METADATA_KEY
             ));
         };
 
-        if get_field(&schema, DEFAULT_TAGS_KEY).is_some() {
-            return Err(anyhow!(
-                "field {} is a reserved field",
-                DEFAULT_TIMESTAMP_KEY
-            ));
+        if get_field(&schema, timestamp_key).is_some() {
+            return Err(anyhow!("field {} is a reserved field", timestamp_key));
         };
 
-        // add the p_timestamp field to the event schema to the 0th index
+        // add the timestamp field to the event schema to the 0th index
         schema.insert(
             0,
             Arc::new(Field::new(
-                DEFAULT_TIMESTAMP_KEY,
+                timestamp_key,
                 DataType::Timestamp(TimeUnit::Millisecond, None),
                 true,
             )),
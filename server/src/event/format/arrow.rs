@@ -0,0 +1,188 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ *
+ */
+
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::anyhow;
+use arrow_array::{new_null_array, RecordBatch};
+use arrow_schema::{Field, Schema};
+use itertools::Itertools;
+
+use super::{EventFormat, Metadata, Tags};
+
+// Record batches sent by Arrow native producers. Their columns are stored as
+// they are, without flattening, so columns already in the stream need to have
+// the same type in the batch. Columns new to the stream are added to it.
+pub struct Event {
+    pub rb: RecordBatch,
+    pub tags: Tags,
+    pub metadata: Metadata,
+}
+
+impl EventFormat for Event {
+    type Data = RecordBatch;
+
+    fn to_data(
+        self,
+        schema: HashMap<String, Arc<Field>>,
+    ) -> Result<(Self::Data, Vec<Arc<Field>>, bool, Tags, Metadata), anyhow::Error> {
+        let batch_schema = self.rb.schema();
+        if let Some(name) = batch_schema
+            .fields()
+            .iter()
+            .map(|field| field.name())
+            .duplicates()
+            .next()
+        {
+            return Err(anyhow!("column {name} is in the batch more than once"));
+        }
+
+        let mut is_first = false;
+        let mut mismatched = Vec::new();
+        let fields = batch_schema
+            .fields()
+            .iter()
+            .map(|field| match schema.get(field.name()) {
+                Some(existing) => {
+                    if existing.data_type() != field.data_type() {
+                        mismatched.push(format!(
+                            "{} is {} in the batch and {} in the stream",
+                            field.name(),
+                            field.data_type(),
+                            existing.data_type()
+                        ));
+                    }
+                    existing.clone()
+                }
+                None => {
+                    is_first = true;
+                    // later events may leave the column out
+                    Arc::new(field.as_ref().clone().with_nullable(true))
+                }
+            })
+            .collect_vec();
+        if !mismatched.is_empty() {
+            return Err(anyhow!(
+                "Schema of the batch does not match the stream, column {}",
+                mismatched.join(", column ")
+            ));
+        }
+
+        Ok((self.rb, fields, is_first, self.tags, self.metadata))
+    }
+
+    // columns are taken by name, the columns which are not in the batch (the
+    // timestamp, tags and metadata) are filled in afterwards
+    fn decode(data: Self::Data, schema: Arc<Schema>) -> Result<RecordBatch, anyhow::Error> {
+        let columns = schema
+            .fields()
+            .iter()
+            .map(|field| match data.column_by_name(field.name()) {
+                Some(column) => column.clone(),
+                None => new_null_array(field.data_type(), data.num_rows()),
+            })
+            .collect_vec();
+        Ok(RecordBatch::try_new(schema, columns)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use arrow_array::{Int64Array, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+
+    use super::Event;
+    use crate::event::format::EventFormat;
+
+    fn event(fields: Vec<Field>, columns: Vec<arrow_array::ArrayRef>) -> Event {
+        Event {
+            rb: RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).unwrap(),
+            tags: "env=prod".to_string(),
+            metadata: String::new(),
+        }
+    }
+
+    #[test]
+    fn batches_keep_their_columns() {
+        let stream = HashMap::from([(
+            "host".to_string(),
+            Arc::new(Field::new("host", DataType::Utf8, true)),
+        )]);
+        let event = event(
+            vec![
+                Field::new("host", DataType::Utf8, false),
+                Field::new("code", DataType::Int64, false),
+            ],
+            vec![
+                Arc::new(StringArray::from(vec!["a", "b"])),
+                Arc::new(Int64Array::from(vec![200, 500])),
+            ],
+        );
+
+        let (rb, is_first) = event.into_recordbatch(stream, "p_timestamp").unwrap();
+        assert!(is_first);
+        let names: Vec<_> = rb
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect();
+        assert_eq!(
+            names,
+            ["p_timestamp", "host", "code", "p_tags", "p_metadata"]
+        );
+        assert!(rb.schema().field(2).is_nullable());
+        assert_eq!(
+            rb.column(2).as_ref(),
+            &Int64Array::from(vec![200, 500]) as &dyn arrow_array::Array
+        );
+        assert_eq!(
+            rb.column(3).as_ref(),
+            &StringArray::from(vec!["env=prod", "env=prod"]) as &dyn arrow_array::Array
+        );
+    }
+
+    #[test]
+    fn mismatched_types_are_rejected() {
+        let stream = HashMap::from([(
+            "code".to_string(),
+            Arc::new(Field::new("code", DataType::Int64, true)),
+        )]);
+        let event = event(
+            vec![Field::new("code", DataType::Utf8, true)],
+            vec![Arc::new(StringArray::from(vec!["200"]))],
+        );
+
+        let err = event
+            .into_recordbatch(stream, "p_timestamp")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("code is Utf8 in the batch and Int64 in the stream"));
+
+        let reserved = self::event(
+            vec![Field::new("p_metadata", DataType::Utf8, true)],
+            vec![Arc::new(StringArray::from(vec!["x"]))],
+        );
+        assert!(reserved
+            .into_recordbatch(HashMap::new(), "p_timestamp")
+            .is_err());
+    }
+}
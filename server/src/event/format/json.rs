@@ -22,19 +22,25 @@
 use anyhow::anyhow;
 use arrow_array::RecordBatch;
 use arrow_json::reader::{infer_json_schema_from_iterator, ReaderBuilder};
-use arrow_schema::{DataType, Field, Fields, Schema};
+use arrow_schema::{DataType, Field, Fields, Schema, TimeUnit};
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
 use datafusion::arrow::util::bit_util::round_upto_multiple_of_64;
 use itertools::Itertools;
 use serde_json::Value;
 use std::{collections::HashMap, sync::Arc};
 
 use super::{EventFormat, Metadata, Tags};
-use crate::utils::{arrow::get_field, json::flatten_json_body};
+use crate::event::attributes;
+use crate::utils::arrow::get_field;
 
 pub struct Event {
     pub data: Value,
     pub tags: Tags,
     pub metadata: Metadata,
+    // candidate fields promoted to timestamp columns when first seen
+    pub time_fields: Vec<String>,
+    // column holding the attributes of events as a map, see `AttributeMap`
+    pub attribute_column: Option<String>,
 }
 
 impl EventFormat for Event {
@@ -46,13 +52,15 @@ impl EventFormat for Event {
         self,
         schema: HashMap<String, Arc<Field>>,
     ) -> Result<(Self::Data, Vec<Arc<Field>>, bool, Tags, Metadata), anyhow::Error> {
-        let data = flatten_json_body(self.data)?;
+        // the body is flattened by the caller, flattening it again here
+        // would explode the attribute map into columns
+        let data = self.data;
         let stream_schema = schema;
 
         // incoming event may be a single json or a json array
         // but Data (type defined above) is a vector of json values
         // hence we need to convert the incoming event to a vector of json values
-        let value_arr = match data {
+        let mut value_arr = match data {
             Value::Array(arr) => arr,
             value @ Value::Object(_) => vec![value],
             _ => unreachable!("flatten would have failed beforehand"),
@@ -67,6 +75,14 @@ impl EventFormat for Event {
             Ok(schema) => schema,
             Err(_) => match infer_json_schema_from_iterator(value_arr.iter().map(Ok)) {
                 Ok(infer_schema) => {
+                    let infer_schema = promote_time_fields(
+                        infer_schema,
+                        &stream_schema,
+                        &self.time_fields,
+                        &value_arr,
+                    );
+                    let infer_schema =
+                        map_attribute_column(infer_schema, self.attribute_column.as_deref());
                     if let Err(err) = Schema::try_merge(vec![
                         Schema::new(stream_schema.values().cloned().collect::<Fields>()),
                         infer_schema.clone(),
@@ -90,6 +106,26 @@ impl EventFormat for Event {
             },
         };
 
+        for name in &self.time_fields {
+            let Some(field) = get_field(&schema, name) else {
+                continue;
+            };
+            if !matches!(field.data_type(), DataType::Timestamp(_, _)) {
+                continue;
+            }
+            for value in value_arr.iter_mut().filter_map(|value| value.get_mut(name)) {
+                if value.is_null() {
+                    continue;
+                }
+                let Some(time) = parse_time(value) else {
+                    return Err(anyhow!(
+                        "Could not process this event, value {value} of time field {name} is not a timestamp"
+                    ));
+                };
+                *value = Value::String(time.to_rfc3339_opts(SecondsFormat::Millis, true));
+            }
+        }
+
         if value_arr
             .iter()
             .any(|value| fields_mismatch(&schema, value))
@@ -119,6 +155,90 @@ impl EventFormat for Event {
     }
 }
 
+// Fields already typed as timestamps in the stream keep their type, inference
+// would see them as strings or numbers. New fields configured as time fields
+// become timestamps if every value of the event parses as one, if any value
+// does not they are left as inferred.
+fn promote_time_fields(
+    infer_schema: Schema,
+    stream_schema: &HashMap<String, Arc<Field>>,
+    time_fields: &[String],
+    values: &[Value],
+) -> Schema {
+    let fields = infer_schema
+        .fields
+        .iter()
+        .map(|field| match stream_schema.get(field.name()) {
+            Some(existing) if matches!(existing.data_type(), DataType::Timestamp(_, _)) => {
+                existing.clone()
+            }
+            None if time_fields.contains(field.name()) && parses_as_time(field.name(), values) => {
+                Arc::new(Field::new(
+                    field.name(),
+                    DataType::Timestamp(TimeUnit::Millisecond, None),
+                    true,
+                ))
+            }
+            _ => field.clone(),
+        })
+        .collect::<Fields>();
+    Schema::new_with_metadata(fields, infer_schema.metadata)
+}
+
+// Inference sees the attribute map of events as a struct of the attributes
+// in this event, the column is a map of all attributes instead
+fn map_attribute_column(infer_schema: Schema, column: Option<&str>) -> Schema {
+    let Some(column) = column else {
+        return infer_schema;
+    };
+    let fields = infer_schema
+        .fields
+        .iter()
+        .map(|field| match field.name() == column {
+            true => Arc::new(Field::new(column, attributes::map_data_type(), true)),
+            false => field.clone(),
+        })
+        .collect::<Fields>();
+    Schema::new_with_metadata(fields, infer_schema.metadata)
+}
+
+fn parses_as_time(name: &str, values: &[Value]) -> bool {
+    let mut values = values
+        .iter()
+        .filter_map(|value| value.get(name))
+        .filter(|value| !value.is_null())
+        .peekable();
+    values.peek().is_some() && values.all(|value| parse_time(value).is_some())
+}
+
+// Parse RFC3339 strings and epoch timestamps, either numbers or numeric strings.
+// The unit of an epoch is guessed from its magnitude, seconds up to 10^11
+// (year 5138), then milliseconds, microseconds and nanoseconds.
+fn parse_time(value: &Value) -> Option<DateTime<Utc>> {
+    let epoch = match value {
+        Value::String(value) => {
+            if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+                return Some(time.with_timezone(&Utc));
+            }
+            value.parse::<f64>().ok()?
+        }
+        Value::Number(value) => value.as_f64()?,
+        _ => return None,
+    };
+    if !epoch.is_finite() {
+        return None;
+    }
+
+    // timestamp columns are in milliseconds
+    let millis = match epoch.abs() {
+        abs if abs < 1e11 => epoch * 1e3,
+        abs if abs < 1e14 => epoch,
+        abs if abs < 1e17 => epoch / 1e3,
+        _ => epoch / 1e6,
+    };
+    Utc.timestamp_millis_opt(millis.round() as i64).single()
+}
+
 // Returns arrow schema with the fields that are present in the request body
 // This schema is an input to convert the request body to arrow record batch
 fn derive_arrow_schema(
@@ -213,6 +333,7 @@ fn valid_type(data_type: &DataType, value: &Value) -> bool {
             }
         }
         DataType::Timestamp(_, _) => value.is_string() || value.is_number(),
+        DataType::Map(_, _) => value.is_object(),
         _ => unreachable!(),
     }
 }
@@ -0,0 +1,161 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use serde_json::Value;
+
+// Per stream masking of IP addresses to their network before events are
+// stored, so that client addresses are not kept in full while requests can
+// still be grouped by network. With
+//
+//   {"columns": ["client_ip"], "ipv4Prefix": 24, "ipv6Prefix": 48}
+//
+// `203.0.113.77` is stored as `203.0.113.0` and `2001:db8:1:2::7` as
+// `2001:db8:1::`. Columns are matched after nested fields are flattened.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IpMask {
+    pub columns: Vec<String>,
+    #[serde(default = "default_ipv4_prefix")]
+    pub ipv4_prefix: u8,
+    #[serde(default = "default_ipv6_prefix")]
+    pub ipv6_prefix: u8,
+}
+
+fn default_ipv4_prefix() -> u8 {
+    24
+}
+
+fn default_ipv6_prefix() -> u8 {
+    48
+}
+
+impl IpMask {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.columns.is_empty() {
+            return Err("at least one column is required".to_string());
+        }
+        if self.ipv4_prefix > 32 {
+            return Err(format!(
+                "ipv4 prefix {} is not in range 0..=32",
+                self.ipv4_prefix
+            ));
+        }
+        if self.ipv6_prefix > 128 {
+            return Err(format!(
+                "ipv6 prefix {} is not in range 0..=128",
+                self.ipv6_prefix
+            ));
+        }
+        Ok(())
+    }
+
+    /// Mask the addresses in the columns of flattened events. Values which
+    /// are not addresses are left as they are.
+    /// Returns the number of such values.
+    pub fn apply(&self, value: &mut Value) -> usize {
+        match value {
+            Value::Array(events) => events.iter_mut().map(|event| self.apply(event)).sum(),
+            Value::Object(event) => {
+                let mut invalid = 0;
+                for column in &self.columns {
+                    match event.get_mut(column) {
+                        None | Some(Value::Null) => (),
+                        Some(Value::String(value)) => match value.trim().parse::<IpAddr>() {
+                            Ok(ip) => *value = self.mask(ip).to_string(),
+                            Err(_) => invalid += 1,
+                        },
+                        Some(_) => invalid += 1,
+                    }
+                }
+                invalid
+            }
+            _ => 0,
+        }
+    }
+
+    fn mask(&self, ip: IpAddr) -> IpAddr {
+        match ip {
+            IpAddr::V4(ip) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.ipv4_prefix))
+                    .unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+            }
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.ipv6_prefix))
+                    .unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::IpMask;
+
+    #[test]
+    fn addresses_are_masked_to_network() {
+        let mask: IpMask = serde_json::from_value(json!({"columns": ["client_ip"]})).unwrap();
+        let mut events = json!([
+            {"client_ip": "203.0.113.77"},
+            {"client_ip": "2001:db8:1:2::7"},
+            {"client_ip": "unknown"},
+            {"client_ip": 7},
+            {"client_ip": null},
+            {"path": "/"}
+        ]);
+
+        assert_eq!(mask.apply(&mut events), 2);
+        assert_eq!(
+            events,
+            json!([
+                {"client_ip": "203.0.113.0"},
+                {"client_ip": "2001:db8:1::"},
+                {"client_ip": "unknown"},
+                {"client_ip": 7},
+                {"client_ip": null},
+                {"path": "/"}
+            ])
+        );
+    }
+
+    #[test]
+    fn prefix_lengths() {
+        let mut mask = IpMask {
+            columns: vec!["ip".to_string()],
+            ipv4_prefix: 0,
+            ipv6_prefix: 128,
+        };
+        let mut event = json!({"ip": "198.51.100.9"});
+        mask.apply(&mut event);
+        assert_eq!(event, json!({"ip": "0.0.0.0"}));
+
+        let mut event = json!({"ip": "2001:db8::1"});
+        mask.apply(&mut event);
+        assert_eq!(event, json!({"ip": "2001:db8::1"}));
+
+        mask.ipv4_prefix = 33;
+        assert!(mask.validate().is_err());
+    }
+}
@@ -0,0 +1,259 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+
+use arrow_schema::{DataType, Field};
+use serde_json::{Map, Value};
+
+// Per stream sanitization of the keys of flattened events into identifiers,
+// so that columns can be used in SQL without quoting. Characters other than
+// ASCII letters, digits and `_` are replaced with `_` and keys starting with a
+// digit are prefixed with `_`, `http status` is stored as `http_status` and
+// `2xx` as `_2xx`.
+//
+// The column of every key which is not stored under its own name is recorded
+// in the key mapping of the stream. Keys are mapped once and keep their column
+// from then on. When a key maps to a column of another key, with `suffix` it
+// gets the first free column of `{column}_1`, `{column}_2` and so on, with
+// `string` both are stored in the column as strings.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeySanitization {
+    // keep letters and digits of other scripts than latin
+    #[serde(default)]
+    pub allow_unicode: bool,
+    #[serde(default)]
+    pub on_collision: Collision,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Collision {
+    #[default]
+    Suffix,
+    String,
+}
+
+// original key to the column it is stored in
+pub type KeyMapping = BTreeMap<String, String>;
+
+impl KeySanitization {
+    pub fn sanitize(&self, key: &str) -> String {
+        let mut column: String = key
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric()
+                    || c == '_'
+                    || (self.allow_unicode && c.is_alphanumeric())
+                {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        if column.chars().next().map_or(true, |c| c.is_numeric()) {
+            column.insert(0, '_');
+        }
+        column
+    }
+
+    /// Rename the keys of flattened events to their columns, mapping new keys
+    /// as they are seen. Returns true if the mapping changed.
+    pub fn apply(
+        &self,
+        value: &mut Value,
+        mapping: &mut KeyMapping,
+        schema: &HashMap<String, Arc<Field>>,
+    ) -> bool {
+        let mut events: Vec<&mut Map<String, Value>> = match value {
+            Value::Array(events) => events.iter_mut().filter_map(Value::as_object_mut).collect(),
+            Value::Object(event) => vec![event],
+            _ => return false,
+        };
+
+        // keys are mapped before any event is renamed so that every value of
+        // a shared column is a string
+        let keys: BTreeSet<String> = events
+            .iter()
+            .flat_map(|event| event.keys())
+            .filter(|key| !mapping.contains_key(*key))
+            .cloned()
+            .collect();
+        let mut owners = owners(mapping, schema);
+        let mut changed = false;
+        for key in keys {
+            changed |= self.map_key(key, mapping, &mut owners, schema);
+        }
+
+        let shared = shared(mapping);
+        for event in events.iter_mut() {
+            let mut renamed = Map::new();
+            for (key, value) in std::mem::take(*event) {
+                let column = mapping.get(&key).cloned().unwrap_or(key);
+                let value = match value {
+                    Value::String(_) | Value::Null => value,
+                    value if shared.contains(column.as_str()) => Value::String(value.to_string()),
+                    value => value,
+                };
+                // of keys sharing a column the first one in key order is kept
+                renamed.entry(column).or_insert(value);
+            }
+            **event = renamed;
+        }
+        changed
+    }
+
+    // Map a key seen for the first time, returns true if it is not stored under
+    // its own name
+    fn map_key(
+        &self,
+        key: String,
+        mapping: &mut KeyMapping,
+        owners: &mut HashMap<String, String>,
+        schema: &HashMap<String, Arc<Field>>,
+    ) -> bool {
+        let column = self.sanitize(&key);
+        let owner = match owners.get(&column) {
+            None => {
+                owners.insert(column.clone(), key.clone());
+                if column == key {
+                    return false;
+                }
+                mapping.insert(key, column);
+                return true;
+            }
+            Some(owner) if *owner == key => return false,
+            Some(owner) => owner.clone(),
+        };
+
+        // a column holding values of another type can not be shared
+        let string_column = schema
+            .get(&column)
+            .map_or(true, |field| field.data_type() == &DataType::Utf8);
+        if self.on_collision == Collision::String && string_column {
+            // the owner is recorded as well so the column is known to be shared
+            mapping.entry(owner).or_insert_with(|| column.clone());
+            mapping.insert(key, column);
+            return true;
+        }
+        let mut index = 1;
+        let suffixed = loop {
+            let suffixed = format!("{column}_{index}");
+            if !owners.contains_key(&suffixed) {
+                break suffixed;
+            }
+            index += 1;
+        };
+        owners.insert(suffixed.clone(), key.clone());
+        mapping.insert(key, suffixed);
+        true
+    }
+}
+
+// Key each column of a stream belongs to, columns which are not in the mapping
+// belong to the key of the same name
+fn owners(mapping: &KeyMapping, schema: &HashMap<String, Arc<Field>>) -> HashMap<String, String> {
+    let mut owners: HashMap<String, String> = schema
+        .keys()
+        .map(|column| (column.clone(), column.clone()))
+        .collect();
+    for (key, column) in mapping {
+        owners.insert(column.clone(), key.clone());
+    }
+    owners
+}
+
+// columns holding the values of more than one key
+fn shared(mapping: &KeyMapping) -> HashSet<&str> {
+    let mut seen = HashSet::new();
+    mapping
+        .values()
+        .filter(|column| !seen.insert(column.as_str()))
+        .map(String::as_str)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use arrow_schema::{DataType, Field};
+    use serde_json::json;
+
+    use super::{Collision, KeyMapping, KeySanitization};
+
+    #[test]
+    fn keys_become_identifiers() {
+        let config = KeySanitization::default();
+        assert_eq!(config.sanitize("http status"), "http_status");
+        assert_eq!(config.sanitize("2xx"), "_2xx");
+        assert_eq!(config.sanitize("température"), "temp_rature");
+        assert_eq!(config.sanitize(""), "_");
+        assert_eq!(config.sanitize("user_id"), "user_id");
+
+        let unicode = KeySanitization {
+            allow_unicode: true,
+            ..Default::default()
+        };
+        assert_eq!(unicode.sanitize("température"), "température");
+    }
+
+    #[test]
+    fn colliding_keys_are_suffixed() {
+        let config = KeySanitization::default();
+        let schema = HashMap::from([(
+            "a_b".to_string(),
+            Arc::new(Field::new("a_b", DataType::Int64, true)),
+        )]);
+        let mut mapping = KeyMapping::new();
+        let mut events = json!([{"a b": 1, "a-b": 2, "a_b": 3, "9": 4}]);
+
+        assert!(config.apply(&mut events, &mut mapping, &schema));
+        assert_eq!(events, json!([{"a_b_1": 1, "a_b_2": 2, "a_b": 3, "_9": 4}]));
+
+        // keys keep their column whatever keys they are sent with
+        let mut events = json!({"a-b": 5});
+        assert!(!config.apply(&mut events, &mut mapping, &schema));
+        assert_eq!(events, json!({"a_b_2": 5}));
+        assert_eq!(mapping.get("a b").map(String::as_str), Some("a_b_1"));
+        assert!(!mapping.contains_key("a_b"));
+    }
+
+    #[test]
+    fn colliding_keys_share_string_column() {
+        let config = KeySanitization {
+            on_collision: Collision::String,
+            ..Default::default()
+        };
+        let schema = HashMap::new();
+        let mut mapping = KeyMapping::new();
+        let mut events = json!([{"a b": 1}, {"a-b": true}]);
+
+        assert!(config.apply(&mut events, &mut mapping, &schema));
+        assert_eq!(events, json!([{"a_b": "1"}, {"a_b": "true"}]));
+        assert_eq!(mapping.get("a-b").map(String::as_str), Some("a_b"));
+
+        let mut events = json!({"a b": 2});
+        config.apply(&mut events, &mut mapping, &schema);
+        assert_eq!(events, json!({"a_b": "2"}));
+    }
+}
@@ -0,0 +1,178 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use arrow_schema::{DataType, Field};
+use serde_json::{Number, Value};
+
+// largest integer a float represents exactly, 2^53 - 1
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+// Per stream handling of numbers in events. Columns keep the type they were
+// created with, the mode only decides how numbers of new columns are stored
+// and converts numbers where the type of the column allows it without loss.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NumberMode {
+    /// integers are stored as Int64 and other numbers as Float64, integers
+    /// beyond the range of Int64 become floats and lose precision
+    #[default]
+    Infer,
+    /// integers are stored exactly, as Int64 or, for columns with integers
+    /// beyond its range such as unsigned 64-bit ids, as strings
+    Exact,
+    /// every number is stored as Float64
+    Float,
+}
+
+impl NumberMode {
+    pub fn is_infer(&self) -> bool {
+        *self == NumberMode::Infer
+    }
+
+    /// Convert the numbers of flattened events to the type they are stored as
+    pub fn apply(&self, value: &mut Value, schema: &HashMap<String, Arc<Field>>) {
+        let events: Vec<&mut serde_json::Map<String, Value>> = match value {
+            Value::Array(events) => events.iter_mut().filter_map(Value::as_object_mut).collect(),
+            Value::Object(event) => vec![event],
+            _ => return,
+        };
+
+        match self {
+            NumberMode::Infer => (),
+            NumberMode::Exact => {
+                // new columns with integers beyond Int64 are stored as strings
+                let beyond_int64: HashSet<String> = events
+                    .iter()
+                    .flat_map(|event| event.iter())
+                    .filter(|(key, value)| {
+                        !schema.contains_key(*key) && value.is_u64() && !value.is_i64()
+                    })
+                    .map(|(key, _)| key.clone())
+                    .collect();
+
+                for (key, value) in events.into_iter().flat_map(|event| event.iter_mut()) {
+                    let Value::Number(number) = value else {
+                        continue;
+                    };
+                    if number.is_f64() {
+                        continue;
+                    }
+                    match schema.get(key).map(|field| field.data_type()) {
+                        Some(DataType::Utf8) => *value = Value::String(number.to_string()),
+                        Some(DataType::Float16 | DataType::Float32 | DataType::Float64) => {
+                            if let Some(float) = exact_float(number) {
+                                *value = float;
+                            }
+                        }
+                        Some(_) => (),
+                        None if beyond_int64.contains(key) => {
+                            *value = Value::String(number.to_string())
+                        }
+                        None => (),
+                    }
+                }
+            }
+            NumberMode::Float => {
+                for (key, value) in events.into_iter().flat_map(|event| event.iter_mut()) {
+                    let Value::Number(number) = value else {
+                        continue;
+                    };
+                    if number.is_f64() {
+                        continue;
+                    }
+                    let integer_column = schema
+                        .get(key)
+                        .is_some_and(|field| field.data_type().is_integer());
+                    if !integer_column {
+                        if let Some(float) = number.as_f64().and_then(Number::from_f64) {
+                            *value = Value::Number(float);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Integer as a float if the float represents it exactly
+fn exact_float(number: &Number) -> Option<Value> {
+    let magnitude = match number.as_i64() {
+        Some(integer) => integer.unsigned_abs(),
+        None => number.as_u64()?,
+    };
+    if magnitude > MAX_SAFE_INTEGER {
+        return None;
+    }
+    number
+        .as_f64()
+        .and_then(Number::from_f64)
+        .map(Value::Number)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use arrow_schema::{DataType, Field};
+    use serde_json::json;
+
+    use super::NumberMode;
+
+    #[test]
+    fn exact_mode_keeps_big_integers() {
+        let schema = HashMap::from([
+            (
+                "ratio".to_string(),
+                Arc::new(Field::new("ratio", DataType::Float64, true)),
+            ),
+            (
+                "trace".to_string(),
+                Arc::new(Field::new("trace", DataType::Utf8, true)),
+            ),
+        ]);
+        let mut events = json!([
+            {"id": 18446744073709551615u64, "seq": 9007199254740993i64, "ratio": 1, "trace": 7},
+            {"id": 12, "seq": 1, "ratio": 9007199254740993i64}
+        ]);
+        NumberMode::Exact.apply(&mut events, &schema);
+
+        assert_eq!(
+            events,
+            json!([
+                {"id": "18446744073709551615", "seq": 9007199254740993i64, "ratio": 1.0, "trace": "7"},
+                {"id": "12", "seq": 1, "ratio": 9007199254740993i64}
+            ])
+        );
+    }
+
+    #[test]
+    fn float_mode_keeps_integer_columns() {
+        let schema = HashMap::from([(
+            "count".to_string(),
+            Arc::new(Field::new("count", DataType::Int64, true)),
+        )]);
+        let mut events = json!({"count": 3, "latency": 12});
+        NumberMode::Float.apply(&mut events, &schema);
+
+        assert_eq!(events, json!({"count": 3, "latency": 12.0}));
+    }
+}
@@ -0,0 +1,198 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde_json::value::RawValue;
+use serde_json::Value;
+
+pub const RAW_KEY: &str = "_raw";
+
+const DEFAULT_MAX_SIZE: usize = 16 * 1024;
+const MAX_SIZE_LIMIT: usize = 1024 * 1024;
+
+// Per stream storage of every event as it was sent, verbatim in the `_raw`
+// column next to its parsed columns, so that events can be parsed again when
+// the rules of the stream change. The payload is taken from the request body
+// before it is parsed: the text of every event of a JSON body, and the whole
+// body for events parsed from any other format, as text or base64 if it is
+// binary such as protobuf. Events routed or shadowed to other streams keep the
+// column, streams masking IP addresses can not keep payloads. Events larger
+// than the maximum size, or sent with a `_raw` field of their own, are stored
+// without their raw payload.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RawPayload {
+    // size in bytes of the largest raw payload stored
+    #[serde(default = "default_max_size")]
+    pub max_size: usize,
+}
+
+fn default_max_size() -> usize {
+    DEFAULT_MAX_SIZE
+}
+
+// Payload of the events parsed from one request body, as it was sent
+pub enum Original<'a> {
+    // text of every event of a JSON body, in order
+    Events(Vec<&'a RawValue>),
+    // body all events were parsed from
+    Body(String),
+}
+
+impl<'a> Original<'a> {
+    // Events of a JSON body, an array of events or a single one
+    pub fn json(body: &'a [u8]) -> Result<Self, serde_json::Error> {
+        let raw: &RawValue = serde_json::from_slice(body)?;
+        let events =
+            serde_json::from_str::<Vec<&RawValue>>(raw.get()).unwrap_or_else(|_| vec![raw]);
+        Ok(Original::Events(events))
+    }
+
+    // Body of any other format
+    pub fn body(body: &[u8]) -> Self {
+        match std::str::from_utf8(body) {
+            Ok(text) => Original::Body(text.to_string()),
+            Err(_) => Original::Body(STANDARD.encode(body)),
+        }
+    }
+
+    fn get(&self, index: usize) -> Option<&str> {
+        match self {
+            Original::Events(events) => events.get(index).map(|event| event.get()),
+            Original::Body(body) => Some(body),
+        }
+    }
+}
+
+impl RawPayload {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_size == 0 || self.max_size > MAX_SIZE_LIMIT {
+            return Err(format!(
+                "max size should be between 1 and {MAX_SIZE_LIMIT} bytes"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Add the raw payload to every event of a JSON body, returns the number
+    /// of events which were too large for it
+    pub fn apply(&self, value: &mut Value, original: &Original) -> usize {
+        let mut oversized = 0;
+        let events = match value {
+            Value::Array(events) => events.iter_mut().collect(),
+            event => vec![event],
+        };
+        for (index, event) in events.into_iter().enumerate() {
+            let Value::Object(event) = event else {
+                continue;
+            };
+            if event.contains_key(RAW_KEY) {
+                continue;
+            }
+            if let Some(raw) = self.payload(original.get(index), &mut oversized) {
+                event.insert(RAW_KEY.to_string(), raw);
+            }
+        }
+        oversized
+    }
+
+    /// Add the raw payload to every record parsed from a body, returns the
+    /// number of records which were too large for it
+    pub fn apply_records(
+        &self,
+        records: &mut [BTreeMap<String, Value>],
+        original: &Original,
+    ) -> usize {
+        let mut oversized = 0;
+        for (index, record) in records.iter_mut().enumerate() {
+            if record.contains_key(RAW_KEY) {
+                continue;
+            }
+            if let Some(raw) = self.payload(original.get(index), &mut oversized) {
+                record.insert(RAW_KEY.to_string(), raw);
+            }
+        }
+        oversized
+    }
+
+    fn payload(&self, raw: Option<&str>, oversized: &mut usize) -> Option<Value> {
+        let raw = raw?;
+        if raw.len() > self.max_size {
+            *oversized += 1;
+            return None;
+        }
+        Some(Value::String(raw.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::{json, Value};
+
+    use super::{Original, RawPayload};
+
+    #[test]
+    fn events_keep_their_payload() {
+        let raw = RawPayload { max_size: 40 };
+        let body = format!(
+            r#"[ {{"user": {{"id": 1}},  "msg": "ok"}}, {{"msg": "{}"}}, {{"_raw": "sent", "a": 1}} ]"#,
+            "x".repeat(64)
+        );
+        let original = Original::json(body.as_bytes()).unwrap();
+        let mut events: Value = serde_json::from_str(&body).unwrap();
+
+        assert_eq!(raw.apply(&mut events, &original), 1);
+        // kept as sent, not as parsed
+        assert_eq!(events[0]["_raw"], r#"{"user": {"id": 1},  "msg": "ok"}"#);
+        assert!(events[1].get("_raw").is_none());
+        // a column of the same name sent with the event is kept
+        assert_eq!(events[2]["_raw"], "sent");
+
+        let body = br#"{"msg": "single"}"#;
+        let mut event: Value = serde_json::from_slice(body).unwrap();
+        raw.apply(&mut event, &Original::json(body).unwrap());
+        assert_eq!(event["_raw"], r#"{"msg": "single"}"#);
+
+        assert!(RawPayload { max_size: 0 }.validate().is_err());
+        assert!(raw.validate().is_ok());
+    }
+
+    #[test]
+    fn records_keep_the_body_they_were_parsed_from() {
+        let raw = RawPayload { max_size: 64 };
+        let mut records = vec![
+            BTreeMap::from([("line".to_string(), json!("a"))]),
+            BTreeMap::from([("line".to_string(), json!("b"))]),
+        ];
+
+        assert_eq!(
+            raw.apply_records(&mut records, &Original::body(b"a\nb\n")),
+            0
+        );
+        assert_eq!(records[1]["_raw"], "a\nb\n");
+
+        // binary bodies such as protobuf are kept as base64
+        let mut records = vec![BTreeMap::new()];
+        raw.apply_records(&mut records, &Original::body(&[0x0a, 0xff, 0x00]));
+        assert_eq!(records[0]["_raw"], "Cv8A");
+    }
+}
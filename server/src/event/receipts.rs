@@ -0,0 +1,108 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::cell::RefCell;
+use std::future::Future;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+// Receipts of the records staged while an ingest request is handled, for
+// clients verifying where their records landed without querying them back.
+// Collection is scoped to the request so that nothing is recorded for events
+// staged outside of one, like sampled or deduplicated events flushed later.
+tokio::task_local! {
+    static RECEIPTS: RefCell<Option<Vec<Receipt>>>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Receipt {
+    pub stream: String,
+    // p_timestamp assigned to the record
+    pub timestamp: String,
+    // directories of the record in the object store relative to its stream,
+    // date=../hour=../minute=.. followed by any partition columns
+    pub partition: String,
+}
+
+impl Receipt {
+    pub fn new(stream: &str, timestamp: DateTime<Utc>, partition: String) -> Self {
+        Self {
+            stream: stream.to_string(),
+            timestamp: timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+            partition,
+        }
+    }
+}
+
+/// Run the future collecting the receipts of the records it stages
+pub async fn collect<F: Future>(future: F) -> (F::Output, Vec<Receipt>) {
+    RECEIPTS
+        .scope(RefCell::new(Some(Vec::new())), async {
+            let output = future.await;
+            let receipts = RECEIPTS.with(|receipts| receipts.take().unwrap_or_default());
+            (output, receipts)
+        })
+        .await
+}
+
+/// Run the future without collecting receipts for the records it stages,
+/// for copies of records such as those of shadow streams
+pub async fn ignore<F: Future>(future: F) -> F::Output {
+    if !is_collecting() {
+        return future.await;
+    }
+    RECEIPTS.scope(RefCell::new(None), future).await
+}
+
+pub fn is_collecting() -> bool {
+    RECEIPTS
+        .try_with(|receipts| receipts.borrow().is_some())
+        .unwrap_or(false)
+}
+
+pub fn record(receipts: impl IntoIterator<Item = Receipt>) {
+    let _ = RECEIPTS.try_with(|collected| {
+        if let Some(collected) = collected.borrow_mut().as_mut() {
+            collected.extend(receipts);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::{collect, ignore, is_collecting, record, Receipt};
+
+    #[actix_web::test]
+    async fn receipts_are_collected_in_scope() {
+        let time = Utc.with_ymd_and_hms(2024, 1, 1, 10, 5, 0).unwrap();
+        let receipt = Receipt::new("app", time, "date=2024-01-01/hour=10/minute=05".to_string());
+
+        record([receipt.clone()]);
+        assert!(!is_collecting());
+
+        let ((), receipts) = collect(async {
+            record([receipt.clone()]);
+            ignore(async { record([receipt.clone()]) }).await;
+        })
+        .await;
+        assert_eq!(receipts, vec![receipt]);
+        assert_eq!(receipts[0].timestamp, "2024-01-01T10:05:00.000Z");
+    }
+}
@@ -0,0 +1,211 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+
+use crate::validator;
+
+// Per stream routing of events to derived streams by the value of a field.
+// `stream` names the derived streams, `{field}` in it is replaced by the value
+// of the field, e.g. `logs{service.name}` routes an event of service `Checkout`
+// to stream `logscheckout`. Values are lowercased and stripped of anything but
+// letters and digits, as stream names only allow those. Derived streams are
+// created with the stream template `template` if they do not exist, at most
+// `max_streams` of them. Events without the field, or with a value for which no
+// more streams can be created, go to `default_stream`, or stay in the stream itself.
+// A derived stream records the value it was created for, a different value
+// naming the same stream, such as `cart-api` and `cartapi`, is rejected. Events
+// routed to streams not created by the routing need the caller to be allowed
+// to ingest into them. `since` is set by the server to the time routing of
+// live events started, a rebuild only routes events ingested before it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Routing {
+    pub field: String,
+    pub stream: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_stream: Option<String>,
+    #[serde(default = "default_max_streams")]
+    pub max_streams: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+}
+
+fn default_max_streams() -> usize {
+    100
+}
+
+// Stream an event is routed to along with the value of the field naming it,
+// lowercased as values differing in case share a stream
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Route {
+    pub stream: String,
+    pub value: String,
+}
+
+// Stream and value a derived stream was created for by routing
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RoutedFrom {
+    pub stream: String,
+    pub value: String,
+}
+
+impl Routing {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.field.is_empty() {
+            return Err("routing field can not be empty".to_string());
+        }
+        if self.max_streams == 0 {
+            return Err("routing should be allowed to create at least one stream".to_string());
+        }
+        if !self.stream.contains(&self.placeholder()) {
+            return Err(format!(
+                "stream name {} does not contain {}",
+                self.stream,
+                self.placeholder()
+            ));
+        }
+        validator::stream_name(&self.stream.replace(&self.placeholder(), "x"))
+            .map_err(|err| format!("stream name {} is invalid, {err}", self.stream))?;
+        if let Some(default_stream) = &self.default_stream {
+            validator::stream_name(default_stream).map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn placeholder(&self) -> String {
+        format!("{{{}}}", self.field)
+    }
+
+    /// Route of an event, None if the event has no usable value
+    pub fn route_of(&self, event: &Map<String, Value>) -> Option<Route> {
+        let value = match lookup(event, &self.field)? {
+            Value::String(value) => value.to_lowercase(),
+            Value::Number(value) => value.to_string(),
+            _ => return None,
+        };
+        let sanitized: String = value
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        if sanitized.is_empty() {
+            return None;
+        }
+        let stream = self.stream.replace(&self.placeholder(), &sanitized);
+        validator::stream_name(&stream)
+            .is_ok()
+            .then_some(Route { stream, value })
+    }
+
+    /// Split a JSON event or array of events by their route.
+    /// Events which are not routed are grouped under None.
+    pub fn split(&self, json: Value) -> BTreeMap<Option<Route>, Vec<Value>> {
+        let events = match json {
+            Value::Array(events) => events,
+            event => vec![event],
+        };
+        let mut routed: BTreeMap<Option<Route>, Vec<Value>> = BTreeMap::new();
+        for event in events {
+            let route = event.as_object().and_then(|event| self.route_of(event));
+            routed.entry(route).or_default().push(event);
+        }
+        routed
+    }
+}
+
+// value of a field either by its key or by its path through nested objects
+fn lookup<'a>(event: &'a Map<String, Value>, field: &str) -> Option<&'a Value> {
+    if let Some(value) = event.get(field) {
+        return Some(value);
+    }
+    let (head, rest) = field.split_once('.')?;
+    match event.get(head)? {
+        Value::Object(nested) => lookup(nested, rest),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{Route, Routing};
+
+    fn routing() -> Routing {
+        Routing {
+            field: "service.name".to_string(),
+            stream: "logs{service.name}".to_string(),
+            template: None,
+            default_stream: None,
+            max_streams: 10,
+            since: None,
+        }
+    }
+
+    fn route(stream: &str, value: &str) -> Option<Route> {
+        Some(Route {
+            stream: stream.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    #[test]
+    fn events_are_split_by_field() {
+        let routed = routing().split(json!([
+            {"service.name": "Checkout", "a": 1},
+            {"service": {"name": "cart-api"}, "a": 2},
+            {"service.name": "checkout", "a": 3},
+            {"service.name": "--", "a": 4},
+            {"a": 5},
+        ]));
+
+        assert_eq!(routed.len(), 3);
+        assert_eq!(
+            routed[&route("logscheckout", "checkout")],
+            vec![
+                json!({"service.name": "Checkout", "a": 1}),
+                json!({"service.name": "checkout", "a": 3})
+            ]
+        );
+        assert_eq!(routed[&route("logscartapi", "cart-api")].len(), 1);
+        assert_eq!(routed[&None].len(), 2);
+    }
+
+    #[test]
+    fn invalid_routing() {
+        assert!(routing().validate().is_ok());
+
+        let mut invalid = routing();
+        invalid.stream = "logs".to_string();
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = routing();
+        invalid.stream = "logs_{service.name}".to_string();
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = routing();
+        invalid.max_streams = 0;
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,167 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+
+// column holding the JSON encoded fields of records which are not columns of a locked schema
+pub const OVERFLOW_KEY: &str = "p_overflow";
+
+// What to do with fields of a record which are not columns of a locked schema
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnNewColumn {
+    // reject the request
+    #[default]
+    Reject,
+    // move the fields to the p_overflow column
+    Overflow,
+}
+
+// A locked stream keeps its current schema, events can not add columns to it.
+// Unlike the attributes cap, which only limits the number of columns of a
+// record, this catches any new field such as a misspelt attribute.
+// Null fields never become columns so they are dropped in either mode.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SchemaLock {
+    #[serde(default)]
+    pub on_new_column: OnNewColumn,
+    pub locked_by: String,
+    pub locked_at: DateTime<Utc>,
+}
+
+impl SchemaLock {
+    /// Apply the lock to a flattened event or array of events.
+    /// Returns the new columns if the events are rejected.
+    pub fn apply(
+        &self,
+        json: &mut Value,
+        is_known: &dyn Fn(&str) -> bool,
+    ) -> Result<(), Vec<String>> {
+        let mut rejected = Vec::new();
+        match json {
+            Value::Object(event) => self.apply_event(event, is_known, &mut rejected),
+            Value::Array(events) => events
+                .iter_mut()
+                .filter_map(Value::as_object_mut)
+                .for_each(|event| self.apply_event(event, is_known, &mut rejected)),
+            _ => (),
+        }
+        if rejected.is_empty() {
+            Ok(())
+        } else {
+            rejected.sort();
+            rejected.dedup();
+            Err(rejected)
+        }
+    }
+
+    fn apply_event(
+        &self,
+        event: &mut Map<String, Value>,
+        is_known: &dyn Fn(&str) -> bool,
+        rejected: &mut Vec<String>,
+    ) {
+        let new: Vec<String> = event
+            .keys()
+            .filter(|key| !is_known(key) && key.as_str() != OVERFLOW_KEY)
+            .cloned()
+            .collect();
+        let mut fields = Map::new();
+        for key in new {
+            match event.remove(&key) {
+                None | Some(Value::Null) => (),
+                Some(_) if self.on_new_column == OnNewColumn::Reject => rejected.push(key),
+                Some(value) => {
+                    fields.insert(key, value);
+                }
+            }
+        }
+        overflow(event, fields);
+    }
+}
+
+/// Move fields of a record to its overflow column, along with the fields
+/// already in it
+pub fn overflow(event: &mut Map<String, Value>, mut fields: Map<String, Value>) {
+    if fields.is_empty() {
+        return;
+    }
+    if let Some(Value::String(existing)) = event.get(OVERFLOW_KEY) {
+        if let Ok(Value::Object(existing)) = serde_json::from_str(existing) {
+            for (key, value) in existing {
+                fields.entry(key).or_insert(value);
+            }
+        }
+    }
+    event.insert(
+        OVERFLOW_KEY.to_string(),
+        Value::String(Value::Object(fields).to_string()),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use serde_json::json;
+
+    use super::{OnNewColumn, SchemaLock};
+
+    fn lock(on_new_column: OnNewColumn) -> SchemaLock {
+        SchemaLock {
+            on_new_column,
+            locked_by: "admin".to_string(),
+            locked_at: Utc::now(),
+        }
+    }
+
+    fn is_known(key: &str) -> bool {
+        ["host", "status"].contains(&key)
+    }
+
+    #[test]
+    fn new_columns_are_rejected() {
+        let mut json = json!([
+            {"host": "a", "stauts": 200},
+            {"host": "b", "level": "info", "trace": null},
+        ]);
+        let rejected = lock(OnNewColumn::Reject)
+            .apply(&mut json, &is_known)
+            .unwrap_err();
+        assert_eq!(rejected, vec!["level", "stauts"]);
+
+        let mut json = json!({"host": "a", "trace": null});
+        assert!(lock(OnNewColumn::Reject)
+            .apply(&mut json, &is_known)
+            .is_ok());
+        assert_eq!(json, json!({"host": "a"}));
+    }
+
+    #[test]
+    fn new_columns_overflow() {
+        let mut json = json!({"host": "a", "stauts": 200, "level": "info"});
+        lock(OnNewColumn::Overflow)
+            .apply(&mut json, &is_known)
+            .unwrap();
+        assert_eq!(
+            json,
+            json!({"host": "a", "p_overflow": r#"{"stauts":200,"level":"info"}"#})
+        );
+    }
+}
@@ -0,0 +1,688 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Value};
+
+pub const SEVERITY_NUMBER_KEY: &str = "severity_number";
+pub const SEVERITY_TEXT_KEY: &str = "severity_text";
+// name the OTLP `SeverityNumber` enum gives to numbers it does not know
+pub const INVALID_SEVERITY_TEXT: &str = "Invalid severity number";
+
+// Severity levels as defined by the OpenTelemetry logs data model
+// https://opentelemetry.io/docs/specs/otel/logs/data-model/#field-severitynumber
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[allow(dead_code)]
+#[repr(i32)]
+pub enum SeverityNumber {
+    #[default]
+    Unspecified = 0,
+    Trace = 1,
+    Trace2 = 2,
+    Trace3 = 3,
+    Trace4 = 4,
+    Debug = 5,
+    Debug2 = 6,
+    Debug3 = 7,
+    Debug4 = 8,
+    Info = 9,
+    Info2 = 10,
+    Info3 = 11,
+    Info4 = 12,
+    Warn = 13,
+    Warn2 = 14,
+    Warn3 = 15,
+    Warn4 = 16,
+    Error = 17,
+    Error2 = 18,
+    Error3 = 19,
+    Error4 = 20,
+    Fatal = 21,
+    Fatal2 = 22,
+    Fatal3 = 23,
+    Fatal4 = 24,
+}
+
+impl SeverityNumber {
+    /// Map a free form log level (as commonly written by logging libraries)
+    /// to its severity number. Unknown levels map to `Unspecified`.
+    pub fn from_level(level: &str) -> Self {
+        match level.trim().to_ascii_lowercase().as_str() {
+            "trace" | "finest" => SeverityNumber::Trace,
+            "debug" | "fine" | "finer" | "dbg" => SeverityNumber::Debug,
+            "info" | "information" | "informational" | "notice" => SeverityNumber::Info,
+            "warn" | "warning" => SeverityNumber::Warn,
+            "error" | "err" | "severe" => SeverityNumber::Error,
+            "fatal" | "critical" | "crit" | "alert" | "emerg" | "emergency" | "panic" => {
+                SeverityNumber::Fatal
+            }
+            _ => SeverityNumber::Unspecified,
+        }
+    }
+}
+
+// Coarse severity levels, each grouping the four severity numbers of a level
+// (TRACE is 1-4, DEBUG is 5-8 and so on up to FATAL which is 21-24)
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum SeverityBand {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl SeverityBand {
+    pub const ALL: [SeverityBand; 6] = [
+        SeverityBand::Trace,
+        SeverityBand::Debug,
+        SeverityBand::Info,
+        SeverityBand::Warn,
+        SeverityBand::Error,
+        SeverityBand::Fatal,
+    ];
+
+    /// Band of a severity number, `Unspecified` and numbers out of range have none.
+    pub fn of(severity_number: i64) -> Option<Self> {
+        if !(SeverityNumber::Trace as i64..=SeverityNumber::Fatal4 as i64)
+            .contains(&severity_number)
+        {
+            return None;
+        }
+        Some(Self::ALL[(severity_number as usize - 1) / 4])
+    }
+}
+
+/// Short name of a severity number as in the OpenTelemetry data model,
+/// e.g. `INFO` for 9 and `ERROR3` for 19. Out of range numbers have none.
+pub fn severity_name(severity_number: i64) -> Option<String> {
+    let band = SeverityBand::of(severity_number)?;
+    let name = format!("{band:?}").to_uppercase();
+    match (severity_number - 1) % 4 {
+        0 => Some(name),
+        step => Some(format!("{name}{}", step + 1)),
+    }
+}
+
+// Per stream handling of OTLP severity numbers outside of the range defined
+// by the data model, which would otherwise be stored as they are
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownSeverity {
+    /// unknown numbers are stored as `Unspecified` (0)
+    #[default]
+    Unspecified,
+    /// unknown numbers are clamped to the nearest severity, TRACE for
+    /// numbers below the range and FATAL4 for numbers above it
+    Clamp,
+}
+
+impl UnknownSeverity {
+    pub fn is_unspecified(&self) -> bool {
+        *self == UnknownSeverity::Unspecified
+    }
+
+    /// Handle the severity of a flattened OTLP log record. A severity text of
+    /// `Invalid severity number` is replaced by the name of the stored number,
+    /// or dropped if that is `Unspecified`.
+    /// Returns false if the severity number was unknown.
+    pub fn apply(&self, record: &mut BTreeMap<String, Value>) -> bool {
+        let range = SeverityNumber::Unspecified as i64..=SeverityNumber::Fatal4 as i64;
+        let number = record.get(SEVERITY_NUMBER_KEY).and_then(Value::as_i64);
+        let known = number.map_or(true, |number| range.contains(&number));
+        let number = match (number, known) {
+            (Some(number), false) => {
+                let number = match self {
+                    UnknownSeverity::Unspecified => SeverityNumber::Unspecified as i64,
+                    UnknownSeverity::Clamp => {
+                        number.clamp(SeverityNumber::Trace as i64, SeverityNumber::Fatal4 as i64)
+                    }
+                };
+                record.insert(SEVERITY_NUMBER_KEY.to_string(), Value::from(number));
+                number
+            }
+            (number, _) => number.unwrap_or_default(),
+        };
+
+        let invalid_text = record
+            .get(SEVERITY_TEXT_KEY)
+            .and_then(Value::as_str)
+            .is_some_and(|text| text.trim().eq_ignore_ascii_case(INVALID_SEVERITY_TEXT));
+        if invalid_text {
+            match severity_name(number) {
+                Some(name) => record.insert(SEVERITY_TEXT_KEY.to_string(), Value::String(name)),
+                None => record.remove(SEVERITY_TEXT_KEY),
+            };
+        }
+        known
+    }
+}
+
+// Per stream configuration to derive severity columns from plain JSON events
+// and OTLP records without a severity.
+// `field` is the event field holding the log level, either a top level key or
+// a path of keys separated by dots such as `meta.log.level`, array elements
+// are addressed by their index. `levels` maps level text to a severity number
+// on top of the well known levels understood by `SeverityNumber::from_level`.
+// Events without a level, such as web access logs, can take their severity
+// from their HTTP status code instead.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SeverityMapping {
+    #[serde(default)]
+    pub field: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub levels: BTreeMap<String, i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<StatusSeverity>,
+}
+
+// Severity of events from their HTTP status code. `field` is looked up like
+// the level field and `levels` maps status classes such as `5xx` or single
+// codes such as `404` to a severity number, single codes taking precedence.
+// Codes matching neither take the default severity.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct StatusSeverity {
+    pub field: String,
+    #[serde(default = "default_status_levels")]
+    pub levels: BTreeMap<String, i32>,
+    #[serde(default = "default_status_severity")]
+    pub default: i32,
+}
+
+fn default_status_levels() -> BTreeMap<String, i32> {
+    BTreeMap::from([
+        ("4xx".to_string(), SeverityNumber::Warn as i32),
+        ("5xx".to_string(), SeverityNumber::Error as i32),
+    ])
+}
+
+fn default_status_severity() -> i32 {
+    SeverityNumber::Info as i32
+}
+
+impl StatusSeverity {
+    // status field with the default levels
+    pub fn new(field: &str) -> Self {
+        Self {
+            field: field.to_string(),
+            levels: default_status_levels(),
+            default: default_status_severity(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.field.trim().is_empty() {
+            return Err("status field cannot be empty".to_string());
+        }
+        // severity text is the name of the number, so it can not be unspecified
+        let range = SeverityNumber::Trace as i32..=SeverityNumber::Fatal4 as i32;
+        let check = |status: &str, number: &i32| {
+            if range.contains(number) {
+                return Ok(());
+            }
+            Err(format!(
+                "severity number {number} for {status} is not in range {}..={}",
+                range.start(),
+                range.end()
+            ))
+        };
+        for (status, number) in &self.levels {
+            if !is_status_pattern(status) {
+                return Err(format!(
+                    "{status} is neither a status class such as 5xx nor a status code"
+                ));
+            }
+            check(&format!("status {status}"), number)?;
+        }
+        check("other statuses", &self.default)
+    }
+
+    /// Severity number of a status code, none if the value is not one
+    pub fn severity_number(&self, status: &Value) -> Option<i32> {
+        let code = match status {
+            Value::Number(code) => code.as_u64()?,
+            Value::String(code) => code.trim().parse().ok()?,
+            _ => return None,
+        };
+        if !(100..=599).contains(&code) {
+            return None;
+        }
+        let number = self
+            .levels
+            .get(&code.to_string())
+            .or_else(|| self.levels.get(&format!("{}xx", code / 100)))
+            .unwrap_or(&self.default);
+        Some(*number)
+    }
+}
+
+fn is_status_pattern(status: &str) -> bool {
+    match status.as_bytes() {
+        [b'1'..=b'5', b'x', b'x'] => true,
+        _ => status
+            .parse::<u16>()
+            .is_ok_and(|code| (100..=599).contains(&code)),
+    }
+}
+
+impl SeverityMapping {
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.status {
+            Some(status) => status.validate()?,
+            None if self.field.trim().is_empty() => {
+                return Err("level field cannot be empty".to_string())
+            }
+            None => {}
+        }
+        let range = SeverityNumber::Unspecified as i32..=SeverityNumber::Fatal4 as i32;
+        for (level, number) in &self.levels {
+            if !range.contains(number) {
+                return Err(format!(
+                    "severity number {number} for level {level} is not in range {}..={}",
+                    range.start(),
+                    range.end()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Mapping for events which always carry an HTTP status code, such as web
+    /// access logs. The status field takes the default levels unless the
+    /// mapping of the stream has a status of its own.
+    pub fn with_status(mapping: Option<Self>, field: &str) -> Self {
+        let mut mapping = mapping.unwrap_or(Self {
+            field: String::new(),
+            levels: BTreeMap::new(),
+            status: None,
+        });
+        mapping
+            .status
+            .get_or_insert_with(|| StatusSeverity::new(field));
+        mapping
+    }
+
+    pub fn severity_number(&self, level: &str) -> i32 {
+        let level = level.trim();
+        self.levels
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(level))
+            .map(|(_, number)| *number)
+            .unwrap_or_else(|| SeverityNumber::from_level(level) as i32)
+    }
+
+    // Severity number and text of an event from its level field, or from its
+    // status code if it has no level
+    fn severity<'a>(&self, get: impl Fn(&str) -> Option<&'a Value>) -> Option<(i32, String)> {
+        let level = match lookup(&self.field, &get) {
+            Some(Value::String(level)) => Some(level.clone()),
+            Some(Value::Number(level)) => Some(level.to_string()),
+            _ => None,
+        };
+        if let Some(level) = level {
+            return Some((self.severity_number(&level), level));
+        }
+        let status = self.status.as_ref()?;
+        let number = status.severity_number(lookup(&status.field, &get)?)?;
+        Some((number, severity_name(number as i64)?))
+    }
+
+    /// Add severity columns to the event based on its level field. Events
+    /// without a level are left as they are, their severity is unspecified.
+    /// Returns false if the level is unknown and was mapped to `Unspecified`.
+    pub fn apply(&self, event: &mut Map<String, Value>) -> bool {
+        let Some((number, text)) = self.severity(|key| event.get(key)) else {
+            return true;
+        };
+        event.insert(SEVERITY_NUMBER_KEY.to_string(), Value::from(number));
+        event.insert(SEVERITY_TEXT_KEY.to_string(), Value::String(text));
+        number != SeverityNumber::Unspecified as i32
+    }
+
+    /// Add severity columns to a flattened OTLP log record whose severity
+    /// number is unspecified, from a level in its body or attributes.
+    /// Returns false if the level is unknown and was mapped to `Unspecified`.
+    pub fn apply_record(&self, record: &mut BTreeMap<String, Value>) -> bool {
+        let specified = record
+            .get(SEVERITY_NUMBER_KEY)
+            .and_then(Value::as_i64)
+            .is_some_and(|number| number != SeverityNumber::Unspecified as i64);
+        if specified {
+            return true;
+        }
+        let Some((number, text)) = self.severity(|key| record.get(key)) else {
+            return true;
+        };
+        record.insert(SEVERITY_NUMBER_KEY.to_string(), Value::from(number));
+        record.insert(SEVERITY_TEXT_KEY.to_string(), Value::String(text));
+        number != SeverityNumber::Unspecified as i32
+    }
+}
+
+// Value of a field of an event. A top level key named like the field takes
+// precedence over the path, so fields such as `log.level` keep working.
+fn lookup<'a>(field: &str, get: &impl Fn(&str) -> Option<&'a Value>) -> Option<&'a Value> {
+    let field = field.strip_prefix("$.").unwrap_or(field);
+    if field.is_empty() {
+        return None;
+    }
+    get(field).or_else(|| {
+        let mut segments = field.split('.');
+        let root = get(segments.next()?)?;
+        segments.try_fold(root, |value, segment| match value {
+            Value::Object(map) => map.get(segment),
+            Value::Array(values) => values.get(segment.parse::<usize>().ok()?),
+            _ => None,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::{json, Value};
+
+    use super::{severity_name, SeverityBand, SeverityMapping, SeverityNumber, UnknownSeverity};
+
+    #[test]
+    fn severity_number_bands() {
+        assert_eq!(SeverityBand::of(0), None);
+        assert_eq!(SeverityBand::of(1), Some(SeverityBand::Trace));
+        assert_eq!(SeverityBand::of(8), Some(SeverityBand::Debug));
+        assert_eq!(SeverityBand::of(9), Some(SeverityBand::Info));
+        assert_eq!(SeverityBand::of(16), Some(SeverityBand::Warn));
+        assert_eq!(SeverityBand::of(17), Some(SeverityBand::Error));
+        assert_eq!(SeverityBand::of(24), Some(SeverityBand::Fatal));
+        assert_eq!(SeverityBand::of(25), None);
+    }
+
+    #[test]
+    fn level_text_maps_to_severity() {
+        assert_eq!(SeverityNumber::from_level("INFO"), SeverityNumber::Info);
+        assert_eq!(
+            SeverityNumber::from_level(" warning "),
+            SeverityNumber::Warn
+        );
+        assert_eq!(SeverityNumber::from_level("Err"), SeverityNumber::Error);
+        assert_eq!(SeverityNumber::from_level("crit"), SeverityNumber::Fatal);
+        assert_eq!(
+            SeverityNumber::from_level("verbose"),
+            SeverityNumber::Unspecified
+        );
+    }
+
+    #[test]
+    fn severity_number_values() {
+        assert_eq!(SeverityNumber::Info as i32, 9);
+        assert_eq!(SeverityNumber::Fatal4 as i32, 24);
+    }
+
+    #[test]
+    fn custom_levels_override_known_levels() {
+        let mapping = SeverityMapping {
+            field: "lvl".to_string(),
+            levels: BTreeMap::from([("W".to_string(), 13), ("30".to_string(), 9)]),
+            status: None,
+        };
+        assert!(mapping.validate().is_ok());
+
+        let mut event = json!({"lvl": "w", "msg": "disk"});
+        assert!(mapping.apply(event.as_object_mut().unwrap()));
+        assert_eq!(event["severity_number"], Value::from(13));
+        assert_eq!(event["severity_text"], "w");
+
+        let mut event = json!({"lvl": 30});
+        assert!(mapping.apply(event.as_object_mut().unwrap()));
+        assert_eq!(event["severity_number"], Value::from(9));
+
+        let mut event = json!({"lvl": "verbose"});
+        assert!(!mapping.apply(event.as_object_mut().unwrap()));
+        assert_eq!(event["severity_number"], Value::from(0));
+
+        let mut event = json!({"msg": "no level"});
+        assert!(mapping.apply(event.as_object_mut().unwrap()));
+        assert!(event.get("severity_number").is_none());
+    }
+
+    #[test]
+    fn nested_level_paths() {
+        let mapping = SeverityMapping {
+            field: "meta.log.level".to_string(),
+            levels: BTreeMap::new(),
+            status: None,
+        };
+
+        let mut event = json!({"meta": {"log": {"level": "warn"}}});
+        assert!(mapping.apply(event.as_object_mut().unwrap()));
+        assert_eq!(event["severity_number"], Value::from(13));
+        assert_eq!(event["severity_text"], "warn");
+
+        // a top level key with dots is taken as is
+        let mut event = json!({"meta.log.level": "error", "meta": {"log": {"level": "warn"}}});
+        assert!(mapping.apply(event.as_object_mut().unwrap()));
+        assert_eq!(event["severity_number"], Value::from(17));
+
+        let mut event = json!({"meta": {"log": {}}});
+        assert!(mapping.apply(event.as_object_mut().unwrap()));
+        assert!(event.get("severity_number").is_none());
+
+        let mut event = json!({"meta": "info"});
+        assert!(mapping.apply(event.as_object_mut().unwrap()));
+        assert!(event.get("severity_number").is_none());
+
+        let mapping = SeverityMapping {
+            field: "$.entries.1.level".to_string(),
+            levels: BTreeMap::new(),
+            status: None,
+        };
+        let mut event = json!({"entries": [{"level": "debug"}, {"level": "fatal"}]});
+        assert!(mapping.apply(event.as_object_mut().unwrap()));
+        assert_eq!(event["severity_number"], Value::from(21));
+    }
+
+    #[test]
+    fn otel_records_without_severity() {
+        let mapping = SeverityMapping {
+            field: "body.meta.log.level".to_string(),
+            levels: BTreeMap::new(),
+            status: None,
+        };
+        let record = |number: i64| {
+            BTreeMap::from([
+                ("severity_number".to_string(), Value::from(number)),
+                (
+                    "body".to_string(),
+                    json!({"meta": {"log": {"level": "info"}}}),
+                ),
+            ])
+        };
+
+        let mut unspecified = record(0);
+        assert!(mapping.apply_record(&mut unspecified));
+        assert_eq!(unspecified["severity_number"], Value::from(9));
+        assert_eq!(unspecified["severity_text"], "info");
+
+        let mut specified = record(17);
+        assert!(mapping.apply_record(&mut specified));
+        assert_eq!(specified["severity_number"], Value::from(17));
+        assert!(specified.get("severity_text").is_none());
+
+        let mut missing = BTreeMap::from([("body".to_string(), json!("plain text"))]);
+        assert!(mapping.apply_record(&mut missing));
+        assert!(missing.get("severity_number").is_none());
+    }
+
+    #[test]
+    fn severity_names() {
+        assert_eq!(severity_name(9).as_deref(), Some("INFO"));
+        assert_eq!(severity_name(19).as_deref(), Some("ERROR3"));
+        assert_eq!(severity_name(24).as_deref(), Some("FATAL4"));
+        assert_eq!(severity_name(0), None);
+    }
+
+    #[test]
+    fn unknown_severity_numbers() {
+        let record = |number: i64| {
+            BTreeMap::from([
+                ("severity_number".to_string(), Value::from(number)),
+                (
+                    "severity_text".to_string(),
+                    Value::from("Invalid severity number"),
+                ),
+            ])
+        };
+
+        let mut unspecified = record(99);
+        assert!(!UnknownSeverity::Unspecified.apply(&mut unspecified));
+        assert_eq!(unspecified["severity_number"], Value::from(0));
+        assert!(unspecified.get("severity_text").is_none());
+
+        let mut clamped = record(99);
+        assert!(!UnknownSeverity::Clamp.apply(&mut clamped));
+        assert_eq!(clamped["severity_number"], Value::from(24));
+        assert_eq!(clamped["severity_text"], "FATAL4");
+
+        let mut clamped = record(-3);
+        assert!(!UnknownSeverity::Clamp.apply(&mut clamped));
+        assert_eq!(clamped["severity_number"], Value::from(1));
+        assert_eq!(clamped["severity_text"], "TRACE");
+
+        let mut known = record(17);
+        known.insert("severity_text".to_string(), Value::from("ERROR"));
+        assert!(UnknownSeverity::Clamp.apply(&mut known));
+        assert_eq!(known["severity_number"], Value::from(17));
+        assert_eq!(known["severity_text"], "ERROR");
+    }
+
+    #[test]
+    fn status_codes_map_to_severity() {
+        let mapping: SeverityMapping =
+            serde_json::from_value(json!({"status": {"field": "http.status"}})).unwrap();
+        assert!(mapping.validate().is_ok());
+
+        let severity = |status: Value| {
+            let mut event = json!({"http": {"status": status}, "path": "/"});
+            assert!(mapping.apply(event.as_object_mut().unwrap()));
+            (
+                event.get("severity_number").cloned(),
+                event.get("severity_text").cloned(),
+            )
+        };
+        for (status, number, text) in [
+            (json!(100), 9, "INFO"),
+            (json!(200), 9, "INFO"),
+            (json!("304"), 9, "INFO"),
+            (json!(404), 13, "WARN"),
+            (json!(499), 13, "WARN"),
+            (json!(500), 17, "ERROR"),
+            (json!(" 503 "), 17, "ERROR"),
+        ] {
+            assert_eq!(
+                severity(status),
+                (Some(Value::from(number)), Some(Value::from(text)))
+            );
+        }
+        for status in [json!(99), json!(600), json!("ok"), json!(null)] {
+            assert_eq!(severity(status), (None, None));
+        }
+    }
+
+    #[test]
+    fn status_levels_and_explicit_levels() {
+        let mapping: SeverityMapping = serde_json::from_value(json!({
+            "field": "level",
+            "status": {
+                "field": "status",
+                "levels": {"5xx": 17, "404": 5, "429": 13},
+                "default": 5
+            }
+        }))
+        .unwrap();
+        assert!(mapping.validate().is_ok());
+
+        // a level sent with the event is used over the status code
+        let mut event = json!({"level": "fatal", "status": 200});
+        assert!(mapping.apply(event.as_object_mut().unwrap()));
+        assert_eq!(event["severity_number"], Value::from(21));
+        assert_eq!(event["severity_text"], "fatal");
+
+        // single codes take precedence, 4xx is no longer mapped
+        let mut event = json!({"status": 404});
+        assert!(mapping.apply(event.as_object_mut().unwrap()));
+        assert_eq!(event["severity_number"], Value::from(5));
+        let mut event = json!({"status": 401});
+        assert!(mapping.apply(event.as_object_mut().unwrap()));
+        assert_eq!(event["severity_text"], "DEBUG");
+
+        let mut record = BTreeMap::from([
+            ("severity_number".to_string(), Value::from(0)),
+            ("status".to_string(), Value::from(502)),
+        ]);
+        assert!(mapping.apply_record(&mut record));
+        assert_eq!(record["severity_text"], "ERROR");
+
+        let invalid = |status: Value| {
+            serde_json::from_value::<SeverityMapping>(json!({"status": status}))
+                .unwrap()
+                .validate()
+                .is_err()
+        };
+        assert!(invalid(json!({"field": ""})));
+        assert!(invalid(json!({"field": "status", "levels": {"6xx": 17}})));
+        assert!(invalid(json!({"field": "status", "levels": {"abc": 17}})));
+        assert!(invalid(json!({"field": "status", "levels": {"5xx": 0}})));
+        assert!(invalid(json!({"field": "status", "default": 25})));
+    }
+
+    #[test]
+    fn status_added_to_mapping() {
+        let record = |status: i64| BTreeMap::from([("status".to_string(), Value::from(status))]);
+
+        // default status levels without a mapping of the stream
+        let mapping = SeverityMapping::with_status(None, "status");
+        let mut error = record(503);
+        assert!(mapping.apply_record(&mut error));
+        assert_eq!(error["severity_text"], "ERROR");
+
+        // the status of the mapping of the stream is kept
+        let stream: SeverityMapping = serde_json::from_value(json!({
+            "status": {"field": "status", "levels": {"404": 5}}
+        }))
+        .unwrap();
+        let mapping = SeverityMapping::with_status(Some(stream), "status");
+        let mut not_found = record(404);
+        assert!(mapping.apply_record(&mut not_found));
+        assert_eq!(not_found["severity_number"], Value::from(5));
+    }
+
+    #[test]
+    fn mapping_out_of_range_is_err() {
+        let mapping = SeverityMapping {
+            field: "level".to_string(),
+            levels: BTreeMap::from([("x".to_string(), 25)]),
+            status: None,
+        };
+        assert!(mapping.validate().is_err());
+    }
+}
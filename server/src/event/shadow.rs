@@ -0,0 +1,176 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+
+use super::{DEFAULT_METADATA_KEY, DEFAULT_TAGS_KEY, DEFAULT_TIMESTAMP_KEY};
+
+// Per stream shadow ingestion used to try out field changes on live traffic.
+// Every event ingested into the stream is also ingested into the `stream`
+// configured here, with top level fields renamed and dropped as configured.
+// Failures to ingest into the shadow stream never fail the original request
+// and removing the config stops shadow ingestion without touching either stream.
+// `since` is set by the server to the time shadow ingestion of live events
+// started, a rebuild only ingests events ingested before it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Shadow {
+    pub stream: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rename: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub drop: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+}
+
+impl Shadow {
+    /// `timestamp_key` is the column the shadow stream keeps the time of events in
+    pub fn validate(&self, stream_name: &str, timestamp_key: &str) -> Result<(), String> {
+        if self.stream == stream_name {
+            return Err("shadow stream must be different from the stream itself".to_string());
+        }
+        for (from, to) in &self.rename {
+            if from.is_empty() || to.is_empty() {
+                return Err("renamed fields can not be empty".to_string());
+            }
+            let reserved = [
+                DEFAULT_TIMESTAMP_KEY,
+                timestamp_key,
+                DEFAULT_TAGS_KEY,
+                DEFAULT_METADATA_KEY,
+            ];
+            if reserved.contains(&to.as_str()) {
+                return Err(format!(
+                    "field {from} can not be renamed to the reserved column {to}"
+                ));
+            }
+        }
+        if let Some(field) = self
+            .drop
+            .iter()
+            .find(|field| self.rename.contains_key(*field))
+        {
+            return Err(format!("field {field} is both renamed and dropped"));
+        }
+        Ok(())
+    }
+
+    /// Apply the field changes to a JSON event or array of events.
+    /// Anything other than an object is left as is.
+    pub fn apply(&self, json: &mut Value) {
+        match json {
+            Value::Object(event) => self.apply_event(event),
+            Value::Array(events) => events
+                .iter_mut()
+                .filter_map(Value::as_object_mut)
+                .for_each(|event| self.apply_event(event)),
+            _ => (),
+        }
+    }
+
+    fn apply_event(&self, event: &mut Map<String, Value>) {
+        for field in &self.drop {
+            event.remove(field);
+        }
+        let renamed: Vec<(&String, Value)> = self
+            .rename
+            .iter()
+            .filter_map(|(from, to)| event.remove(from).map(|value| (to, value)))
+            .collect();
+        for (to, value) in renamed {
+            event.insert(to.clone(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::json;
+
+    use super::Shadow;
+
+    fn shadow() -> Shadow {
+        Shadow {
+            stream: "app_shadow".to_string(),
+            rename: BTreeMap::from([
+                ("msg".to_string(), "message".to_string()),
+                ("lvl".to_string(), "level".to_string()),
+            ]),
+            drop: vec!["debug".to_string()],
+            since: None,
+        }
+    }
+
+    #[test]
+    fn fields_are_renamed_and_dropped() {
+        let mut json = json!([
+            {"msg": "a", "lvl": "info", "debug": true, "host": "h1"},
+            {"msg": "b", "host": "h2"},
+            "not an event"
+        ]);
+        shadow().apply(&mut json);
+        assert_eq!(
+            json,
+            json!([
+                {"message": "a", "level": "info", "host": "h1"},
+                {"message": "b", "host": "h2"},
+                "not an event"
+            ])
+        );
+    }
+
+    #[test]
+    fn renames_are_swappable() {
+        let shadow = Shadow {
+            stream: "app_shadow".to_string(),
+            rename: BTreeMap::from([
+                ("a".to_string(), "b".to_string()),
+                ("b".to_string(), "a".to_string()),
+            ]),
+            drop: Vec::new(),
+            since: None,
+        };
+        let mut json = json!({"a": 1, "b": 2});
+        shadow.apply(&mut json);
+        assert_eq!(json, json!({"a": 2, "b": 1}));
+    }
+
+    #[test]
+    fn invalid_shadow() {
+        assert!(shadow().validate("app", "p_timestamp").is_ok());
+        assert!(shadow().validate("app_shadow", "p_timestamp").is_err());
+
+        let mut invalid = shadow();
+        invalid.drop.push("msg".to_string());
+        assert!(invalid.validate("app", "p_timestamp").is_err());
+
+        for reserved in ["p_timestamp", "event_time", "p_tags", "p_metadata"] {
+            let mut invalid = shadow();
+            invalid
+                .rename
+                .insert("ts".to_string(), reserved.to_string());
+            assert!(invalid.validate("app", "event_time").is_err());
+        }
+    }
+}
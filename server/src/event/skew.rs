@@ -0,0 +1,232 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+pub const TIME_KEY: &str = "time_unix_nano";
+// column keeping the time a record was sent with when it is corrected
+pub const ORIGINAL_TIME_KEY: &str = "original_time_unix_nano";
+
+const NANOS_PER_MILLI: i128 = 1_000_000;
+const DEFAULT_MAX_CORRECTION_MS: u64 = 24 * 60 * 60 * 1000;
+
+// Per stream correction of the times of OTLP log records sent by sources with
+// drifting clocks. Times are shifted by the offset of their source, or of the
+// stream for sources without one, and with a tolerance times still further
+// from the time the server received them than that are set to the receive
+// time. Corrections larger than the maximum are not applied.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClockSkew {
+    // added to the time of every record, in milliseconds
+    #[serde(default)]
+    pub offset_ms: i64,
+    // attribute naming the source of a record, such as host.name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_key: Option<String>,
+    // offsets of single sources by the value of the source attribute
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sources: BTreeMap<String, i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tolerance_ms: Option<u64>,
+    #[serde(default = "default_max_correction")]
+    pub max_correction_ms: u64,
+}
+
+fn default_max_correction() -> u64 {
+    DEFAULT_MAX_CORRECTION_MS
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Correction {
+    // the time was kept as it was
+    None,
+    // the time was moved by the given nanoseconds
+    Applied(i128),
+    // the time was kept as the correction is larger than the maximum
+    Skipped(i128),
+}
+
+impl ClockSkew {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_correction_ms == 0 {
+            return Err("max correction should be at least 1 ms".to_string());
+        }
+        if !self.sources.is_empty() && self.source_key.as_deref().map_or(true, str::is_empty) {
+            return Err("source key is required for offsets of sources".to_string());
+        }
+        let offsets = std::iter::once(("the stream", &self.offset_ms)).chain(
+            self.sources
+                .iter()
+                .map(|(name, offset)| (name.as_str(), offset)),
+        );
+        for (source, offset) in offsets {
+            if offset.unsigned_abs() > self.max_correction_ms {
+                return Err(format!(
+                    "offset {offset} ms of {source} is larger than the max correction of {} ms",
+                    self.max_correction_ms
+                ));
+            }
+        }
+        if self.tolerance_ms == Some(0) {
+            return Err("tolerance should be at least 1 ms".to_string());
+        }
+        Ok(())
+    }
+
+    /// Correct the time of a flattened OTLP log record received at the given
+    /// time in nanoseconds, keeping the time it was sent with in
+    /// `original_time_unix_nano`
+    pub fn apply(&self, record: &mut BTreeMap<String, Value>, received: i128) -> Correction {
+        let Some(time) = record.get(TIME_KEY).and_then(parse_time) else {
+            return Correction::None;
+        };
+        let offset = self
+            .source_key
+            .as_ref()
+            .and_then(|key| record.get(key))
+            .and_then(Value::as_str)
+            .and_then(|source| self.sources.get(source))
+            .unwrap_or(&self.offset_ms);
+
+        let mut corrected = time + *offset as i128 * NANOS_PER_MILLI;
+        if let Some(tolerance) = self.tolerance_ms {
+            if (received - corrected).abs() > tolerance as i128 * NANOS_PER_MILLI {
+                corrected = received;
+            }
+        }
+        let correction = corrected - time;
+        if correction == 0 {
+            return Correction::None;
+        }
+        if correction.abs() > self.max_correction_ms as i128 * NANOS_PER_MILLI {
+            return Correction::Skipped(correction);
+        }
+
+        if let Some(original) = record.insert(TIME_KEY.to_string(), corrected.to_string().into()) {
+            record.insert(ORIGINAL_TIME_KEY.to_string(), original);
+        }
+        Correction::Applied(correction)
+    }
+}
+
+fn parse_time(time: &Value) -> Option<i128> {
+    match time {
+        Value::String(time) => time.parse().ok(),
+        Value::Number(time) => time.as_u64().map(i128::from),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::{json, Value};
+
+    use super::{ClockSkew, Correction};
+
+    const SECOND: i128 = 1_000_000_000;
+
+    fn record(source: &str, time: i128) -> BTreeMap<String, Value> {
+        BTreeMap::from([
+            ("host".to_string(), json!(source)),
+            ("time_unix_nano".to_string(), json!(time.to_string())),
+        ])
+    }
+
+    fn skew() -> ClockSkew {
+        serde_json::from_value(json!({
+            "offsetMs": 2000,
+            "sourceKey": "host",
+            "sources": {"web-1": -5000},
+            "maxCorrectionMs": 60000
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn offsets_by_source() {
+        let skew = skew();
+        assert!(skew.validate().is_ok());
+        let received = 1000 * SECOND;
+
+        let mut web = record("web-1", 100 * SECOND);
+        assert_eq!(
+            skew.apply(&mut web, received),
+            Correction::Applied(-5 * SECOND)
+        );
+        assert_eq!(web["time_unix_nano"], json!((95 * SECOND).to_string()));
+        assert_eq!(
+            web["original_time_unix_nano"],
+            json!((100 * SECOND).to_string())
+        );
+
+        let mut db = record("db-1", 100 * SECOND);
+        assert_eq!(
+            skew.apply(&mut db, received),
+            Correction::Applied(2 * SECOND)
+        );
+        assert_eq!(db["time_unix_nano"], json!((102 * SECOND).to_string()));
+
+        let mut untimed = BTreeMap::from([("host".to_string(), json!("db-1"))]);
+        assert_eq!(skew.apply(&mut untimed, received), Correction::None);
+    }
+
+    #[test]
+    fn tolerance_of_receive_time() {
+        let skew = ClockSkew {
+            offset_ms: 0,
+            tolerance_ms: Some(10_000),
+            ..skew()
+        };
+        let received = 1000 * SECOND;
+
+        let mut close = record("db-1", 995 * SECOND);
+        assert_eq!(skew.apply(&mut close, received), Correction::None);
+        assert!(close.get("original_time_unix_nano").is_none());
+
+        let mut drifted = record("db-1", 970 * SECOND);
+        assert_eq!(
+            skew.apply(&mut drifted, received),
+            Correction::Applied(30 * SECOND)
+        );
+        assert_eq!(drifted["time_unix_nano"], json!(received.to_string()));
+
+        // corrections beyond the maximum are left to be looked into
+        let mut far = record("db-1", 100 * SECOND);
+        assert_eq!(
+            skew.apply(&mut far, received),
+            Correction::Skipped(900 * SECOND)
+        );
+        assert_eq!(far["time_unix_nano"], json!((100 * SECOND).to_string()));
+    }
+
+    #[test]
+    fn offsets_are_bounded() {
+        let mut skew = skew();
+        skew.offset_ms = 120_000;
+        assert!(skew.validate().is_err());
+
+        let mut skew = self::skew();
+        skew.source_key = None;
+        assert!(skew.validate().is_err());
+    }
+}
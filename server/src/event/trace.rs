@@ -0,0 +1,136 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+
+use serde_json::Value;
+
+// Trace of what the stages of the ingest pipeline did with the records of a
+// request, to debug sources whose events do not end up as expected. Like
+// receipts the trace is scoped to the request, stages record into it only
+// while a traced request is handled and do nothing otherwise.
+tokio::task_local! {
+    static TRACE: RefCell<Option<IngestTrace>>;
+}
+
+pub const UNMATCHED_LINE: &str = "unmatched line";
+pub const MALFORMED_CSV_ROW: &str = "malformed csv row";
+pub const QUOTA_EXCEEDED: &str = "quota exceeded";
+pub const COLLAPSED_REPEAT: &str = "collapsed into repeat";
+pub const HELD_FOR_SAMPLING: &str = "held for sampling";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IngestTrace {
+    // Content-Length of the request, absent for chunked requests
+    pub bytes_received: Option<u64>,
+    pub decompressed_bytes: u64,
+    // events the body was parsed into as per its log source
+    pub records_parsed: u64,
+    pub records_staged: u64,
+    // records of the request not staged by it, by reason
+    pub records_dropped: BTreeMap<&'static str, u64>,
+    // attributes beyond the maximum number of attributes of a record
+    pub attributes_dropped: u64,
+    // columns the request added to the schema of every stream
+    pub columns_added: BTreeMap<String, BTreeSet<String>>,
+    // records staged per stream and object store directories
+    pub partitions: BTreeMap<String, BTreeMap<String, u64>>,
+}
+
+impl IngestTrace {
+    pub fn new(bytes_received: Option<u64>, decompressed_bytes: u64) -> Self {
+        Self {
+            bytes_received,
+            decompressed_bytes,
+            ..Default::default()
+        }
+    }
+
+    pub fn drop_records(&mut self, reason: &'static str, count: usize) {
+        if count > 0 {
+            *self.records_dropped.entry(reason).or_default() += count as u64;
+        }
+    }
+}
+
+/// Run the future tracing the ingest stages it goes through
+pub async fn collect<F: Future>(trace: IngestTrace, future: F) -> (F::Output, IngestTrace) {
+    TRACE
+        .scope(RefCell::new(Some(trace)), async {
+            let output = future.await;
+            let trace = TRACE.with(|trace| trace.take().unwrap_or_default());
+            (output, trace)
+        })
+        .await
+}
+
+/// Run the future without tracing it, for copies of records such as those of
+/// shadow streams
+pub async fn ignore<F: Future>(future: F) -> F::Output {
+    if !is_tracing() {
+        return future.await;
+    }
+    TRACE.scope(RefCell::new(None), future).await
+}
+
+pub fn is_tracing() -> bool {
+    TRACE
+        .try_with(|trace| trace.borrow().is_some())
+        .unwrap_or(false)
+}
+
+/// Record into the trace of the request, if it is traced
+pub fn record(f: impl FnOnce(&mut IngestTrace)) {
+    let _ = TRACE.try_with(|trace| {
+        if let Some(trace) = trace.borrow_mut().as_mut() {
+            f(trace);
+        }
+    });
+}
+
+// number of events of a JSON body
+pub fn count(json: &Value) -> usize {
+    json.as_array().map_or(1, Vec::len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collect, ignore, record, IngestTrace, QUOTA_EXCEEDED};
+
+    #[actix_web::test]
+    async fn stages_record_into_traced_requests() {
+        record(|trace| trace.records_parsed += 1);
+
+        let ((), trace) = collect(IngestTrace::new(Some(10), 40), async {
+            record(|trace| trace.records_parsed += 3);
+            record(|trace| trace.drop_records(QUOTA_EXCEEDED, 1));
+            record(|trace| trace.drop_records(QUOTA_EXCEEDED, 0));
+            ignore(async { record(|trace| trace.records_parsed += 3) }).await;
+        })
+        .await;
+
+        assert_eq!(trace.bytes_received, Some(10));
+        assert_eq!(trace.decompressed_bytes, 40);
+        assert_eq!(trace.records_parsed, 3);
+        assert_eq!(trace.records_dropped.get(QUOTA_EXCEEDED), Some(&1));
+        assert_eq!(trace.records_dropped.len(), 1);
+    }
+}
@@ -0,0 +1,238 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use arrow_schema::{DataType, Field};
+use serde_json::{Map, Value};
+
+use super::schema_lock;
+
+// Per stream handling of attributes whose values have different types within
+// a request, such as a status sent as `200` by one event and as `"OK"` by
+// another. The type of an attribute is the type of its column, or the type of
+// its first value if it is not a column yet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TypeConflict {
+    /// every value of the attribute is stored as a string, values of existing
+    /// columns are left as they are and rejected if not of their type
+    #[default]
+    String,
+    /// the request is rejected
+    Reject,
+    /// the attribute keeps its type, values of other types are moved to the
+    /// `p_overflow` column
+    Split,
+}
+
+// JSON types told apart when looking for conflicts, integers and floats are
+// both numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Bool,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+impl Kind {
+    fn of(value: &Value) -> Option<Self> {
+        match value {
+            Value::Null => None,
+            Value::Bool(_) => Some(Kind::Bool),
+            Value::Number(_) => Some(Kind::Number),
+            Value::String(_) => Some(Kind::String),
+            Value::Array(_) => Some(Kind::Array),
+            Value::Object(_) => Some(Kind::Object),
+        }
+    }
+
+    // timestamp columns take strings as well as numbers and are left out
+    fn of_column(data_type: &DataType) -> Option<Self> {
+        match data_type {
+            DataType::Boolean => Some(Kind::Bool),
+            data_type if data_type.is_numeric() => Some(Kind::Number),
+            DataType::Utf8 | DataType::LargeUtf8 => Some(Kind::String),
+            DataType::List(_) | DataType::LargeList(_) => Some(Kind::Array),
+            DataType::Struct(_) | DataType::Map(_, _) => Some(Kind::Object),
+            _ => None,
+        }
+    }
+}
+
+impl TypeConflict {
+    pub fn is_string(&self) -> bool {
+        *self == TypeConflict::String
+    }
+
+    /// Resolve conflicting types of the attributes of flattened events.
+    /// Returns the conflicting attributes if the events are rejected.
+    pub fn apply(
+        &self,
+        value: &mut Value,
+        schema: &HashMap<String, Arc<Field>>,
+    ) -> Result<(), Vec<String>> {
+        let mut events: Vec<&mut Map<String, Value>> = match value {
+            Value::Array(events) => events.iter_mut().filter_map(Value::as_object_mut).collect(),
+            Value::Object(event) => vec![event],
+            _ => return Ok(()),
+        };
+
+        let mut kinds: HashMap<String, Option<Kind>> = HashMap::new();
+        let mut conflicts = BTreeSet::new();
+        for (key, value) in events.iter().flat_map(|event| event.iter()) {
+            let Some(kind) = Kind::of(value) else {
+                continue;
+            };
+            let expected = *kinds
+                .entry(key.clone())
+                .or_insert_with(|| match schema.get(key) {
+                    Some(field) => Kind::of_column(field.data_type()),
+                    None => Some(kind),
+                });
+            if expected.is_some_and(|expected| expected != kind) {
+                conflicts.insert(key.clone());
+            }
+        }
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+
+        match self {
+            TypeConflict::Reject => return Err(conflicts.into_iter().collect()),
+            TypeConflict::String => {
+                conflicts.retain(|key| !schema.contains_key(key));
+                for (key, value) in events.iter_mut().flat_map(|event| event.iter_mut()) {
+                    if conflicts.contains(key) && !(value.is_string() || value.is_null()) {
+                        *value = Value::String(value.to_string());
+                    }
+                }
+            }
+            TypeConflict::Split => {
+                for event in events {
+                    let moved: Vec<String> = event
+                        .iter()
+                        .filter(|(key, value)| {
+                            conflicts.contains(*key)
+                                && Kind::of(value).is_some_and(|kind| kinds[*key] != Some(kind))
+                        })
+                        .map(|(key, _)| key.clone())
+                        .collect();
+                    let overflow = moved
+                        .into_iter()
+                        .filter_map(|key| Some((key.clone(), event.remove(&key)?)))
+                        .collect();
+                    schema_lock::overflow(event, overflow);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use arrow_schema::{DataType, Field};
+    use serde_json::{json, Value};
+
+    use super::TypeConflict;
+
+    fn events() -> Value {
+        json!([
+            {"status": 200, "ok": true},
+            {"status": "OK", "ok": true},
+            {"status": null},
+            {"status": [1, 2], "ok": "yes"}
+        ])
+    }
+
+    #[test]
+    fn conflicts_widen_to_string() {
+        let mut events = events();
+        assert!(TypeConflict::String
+            .apply(&mut events, &HashMap::new())
+            .is_ok());
+        assert_eq!(
+            events,
+            json!([
+                {"status": "200", "ok": "true"},
+                {"status": "OK", "ok": "true"},
+                {"status": null},
+                {"status": "[1,2]", "ok": "yes"}
+            ])
+        );
+
+        // columns keep their type
+        let schema = HashMap::from([(
+            "status".to_string(),
+            Arc::new(Field::new("status", DataType::Utf8, true)),
+        )]);
+        let mut events = json!([{"status": 200}, {"status": "OK"}]);
+        assert!(TypeConflict::String.apply(&mut events, &schema).is_ok());
+        assert_eq!(events, json!([{"status": 200}, {"status": "OK"}]));
+    }
+
+    #[test]
+    fn conflicts_are_rejected() {
+        let mut events = events();
+        assert_eq!(
+            TypeConflict::Reject.apply(&mut events, &HashMap::new()),
+            Err(vec!["ok".to_string(), "status".to_string()])
+        );
+
+        let mut same = json!([{"status": 200}, {"status": 404.5}, {"status": null}]);
+        assert!(TypeConflict::Reject
+            .apply(&mut same, &HashMap::new())
+            .is_ok());
+    }
+
+    #[test]
+    fn conflicts_split_into_overflow() {
+        let mut events = events();
+        assert!(TypeConflict::Split
+            .apply(&mut events, &HashMap::new())
+            .is_ok());
+        assert_eq!(
+            events,
+            json!([
+                {"status": 200, "ok": true},
+                {"ok": true, "p_overflow": r#"{"status":"OK"}"#},
+                {"status": null},
+                {"p_overflow": r#"{"status":[1,2],"ok":"yes"}"#}
+            ])
+        );
+
+        // the type of the column wins over the first value
+        let schema = HashMap::from([(
+            "status".to_string(),
+            Arc::new(Field::new("status", DataType::Utf8, true)),
+        )]);
+        let mut events = json!([{"status": 200}, {"status": "OK"}]);
+        assert!(TypeConflict::Split.apply(&mut events, &schema).is_ok());
+        assert_eq!(
+            events,
+            json!([{"p_overflow": r#"{"status":200}"#}, {"status": "OK"}])
+        );
+    }
+}
@@ -22,43 +22,120 @@ mod mem_writer;
 
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex, RwLock},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
 };
 
+use crate::metrics::{OLDEST_STAGING_RECORD_AGE_SECONDS, STAGING_SPILLED_BYTES};
+use crate::option::CONFIG;
+use crate::storage::staging;
 use crate::utils;
 
 use self::{errors::StreamWriterError, file_writer::FileWriter, mem_writer::MemWriter};
 use arrow_array::{RecordBatch, TimestampMillisecondArray};
-use arrow_schema::Schema;
-use chrono::Utc;
+use arrow_schema::{ArrowError, Schema};
+use chrono::{DateTime, Utc};
 use derive_more::{Deref, DerefMut};
 use once_cell::sync::Lazy;
 
 pub static STREAM_WRITERS: Lazy<WriterTable> = Lazy::new(WriterTable::default);
 
+// memory held by the in memory copy of staged records across all streams
+static STAGING_MEMORY: AtomicUsize = AtomicUsize::new(0);
+
+// Where the rows of a record batch were staged
+#[derive(Debug)]
+pub struct Staged {
+    // p_timestamp given to the rows, the time of staging for replayed rows
+    pub timestamp: DateTime<Utc>,
+    pub file_path: PathBuf,
+}
+
 #[derive(Default)]
 pub struct Writer {
     pub mem: MemWriter<16384>,
     pub disk: FileWriter,
+    // time of the first push since the last flush
+    oldest_record: Option<DateTime<Utc>>,
+    // set once a record could not be kept in memory, staging queries of this
+    // stream then read the staged records from disk until the next flush
+    spilled: bool,
 }
 
 impl Writer {
+    fn release(self) -> FileWriter {
+        STAGING_MEMORY.fetch_sub(self.mem.size(), Ordering::Relaxed);
+        self.disk
+    }
+
     fn push(
         &mut self,
         stream_name: &str,
         schema_key: &str,
+        partition: &str,
         rb: RecordBatch,
-    ) -> Result<(), StreamWriterError> {
-        let rb = utils::arrow::replace_columns(
-            rb.schema(),
-            &rb,
-            &[0],
-            &[Arc::new(get_timestamp_array(rb.num_rows()))],
-        );
-
-        self.disk.push(stream_name, schema_key, &rb)?;
-        self.mem.push(schema_key, rb);
-        Ok(())
+        replayed: bool,
+    ) -> Result<Staged, StreamWriterError> {
+        let timestamp = Utc::now();
+        let rb = if replayed {
+            rb
+        } else {
+            utils::arrow::replace_columns(
+                rb.schema(),
+                &rb,
+                &[0],
+                &[Arc::new(get_timestamp_array(timestamp, rb.num_rows()))],
+            )
+        };
+
+        let file_path = self
+            .disk
+            .push(stream_name, schema_key, partition, &rb)?
+            .to_path_buf();
+
+        // records are already on disk, while uploads fail and staging grows they
+        // are only kept there above the memory limit and picked up by the next
+        // sync like any other staged record
+        let size = rb.get_array_memory_size();
+        if staging::uploads_failing()
+            && over_memory_limit(
+                STAGING_MEMORY.load(Ordering::Relaxed),
+                size,
+                CONFIG.parseable.staging_memory_limit,
+            )
+        {
+            if !self.spilled {
+                log::warn!("uploads are failing and staging memory limit reached, records of stream {stream_name} are kept on disk until the next sync");
+                self.spilled = true;
+            }
+            STAGING_SPILLED_BYTES
+                .with_label_values(&[stream_name])
+                .inc_by(size as u64);
+        } else {
+            STAGING_MEMORY.fetch_add(size, Ordering::Relaxed);
+            self.mem.push(schema_key, rb);
+        }
+
+        let oldest_record = *self.oldest_record.get_or_insert(timestamp);
+        OLDEST_STAGING_RECORD_AGE_SECONDS
+            .with_label_values(&[stream_name])
+            .set((timestamp - oldest_record).num_seconds());
+        Ok(Staged {
+            timestamp,
+            file_path,
+        })
+    }
+
+    // staged records for queries, the in memory copy lacks spilled records
+    fn recordbatches(&self, schema: &Arc<Schema>) -> Result<Vec<RecordBatch>, ArrowError> {
+        if self.spilled {
+            self.disk.recordbatches(schema)
+        } else {
+            Ok(self.mem.recordbatch_cloned(schema))
+        }
     }
 }
 
@@ -71,48 +148,85 @@ impl WriterTable {
         &self,
         stream_name: &str,
         schema_key: &str,
+        partition: &str,
         record: RecordBatch,
-    ) -> Result<(), StreamWriterError> {
+        replayed: bool,
+    ) -> Result<Staged, StreamWriterError> {
         let hashmap_guard = self.read().unwrap();
 
-        match hashmap_guard.get(stream_name) {
-            Some(stream_writer) => {
-                stream_writer
-                    .lock()
-                    .unwrap()
-                    .push(stream_name, schema_key, record)?;
-            }
+        let staged = match hashmap_guard.get(stream_name) {
+            Some(stream_writer) => stream_writer.lock().unwrap().push(
+                stream_name,
+                schema_key,
+                partition,
+                record,
+                replayed,
+            )?,
             None => {
                 drop(hashmap_guard);
                 let mut map = self.write().unwrap();
                 // check for race condition
                 // if map contains entry then just
                 if let Some(writer) = map.get(stream_name) {
-                    writer
-                        .lock()
-                        .unwrap()
-                        .push(stream_name, schema_key, record)?;
+                    writer.lock().unwrap().push(
+                        stream_name,
+                        schema_key,
+                        partition,
+                        record,
+                        replayed,
+                    )?
                 } else {
                     let mut writer = Writer::default();
-                    writer.push(stream_name, schema_key, record)?;
+                    let staged =
+                        writer.push(stream_name, schema_key, partition, record, replayed)?;
                     map.insert(stream_name.to_owned(), Mutex::new(writer));
+                    staged
                 }
             }
         };
-        Ok(())
+        Ok(staged)
     }
 
     pub fn delete_stream(&self, stream_name: &str) {
-        self.write().unwrap().remove(stream_name);
+        if let Some(writer) = self.write().unwrap().remove(stream_name) {
+            writer.into_inner().unwrap().release();
+        }
+        _ = OLDEST_STAGING_RECORD_AGE_SECONDS.remove_label_values(&[stream_name]);
     }
 
     pub fn unset_all(&self) {
         let mut table = self.write().unwrap();
         let map = std::mem::take(&mut *table);
         drop(table);
-        for writer in map.into_values() {
-            let writer = writer.into_inner().unwrap();
-            writer.disk.close_all();
+        for (stream_name, writer) in map {
+            writer.into_inner().unwrap().release().close_all();
+            OLDEST_STAGING_RECORD_AGE_SECONDS
+                .with_label_values(&[&stream_name])
+                .set(0);
+        }
+    }
+
+    // The age of the oldest staged record of a stream is only set as records are
+    // pushed, so it is refreshed periodically for streams which stopped receiving events
+    pub fn refresh_staging_age(&self, now: DateTime<Utc>) {
+        let table = self.read().unwrap();
+        for (stream_name, writer) in table.iter() {
+            if let Some(oldest_record) = writer.lock().unwrap().oldest_record {
+                OLDEST_STAGING_RECORD_AGE_SECONDS
+                    .with_label_values(&[stream_name])
+                    .set((now - oldest_record).num_seconds());
+            }
+        }
+    }
+
+    // close the writer of a single stream so that its staged data is picked up by the next sync
+    pub fn unset_stream(&self, stream_name: &str) {
+        let writer = self.write().unwrap().remove(stream_name);
+        if let Some(writer) = writer {
+            writer.into_inner().unwrap().release().close_all();
+            OLDEST_STAGING_RECORD_AGE_SECONDS
+                .with_label_values(&[stream_name])
+                .set(0);
         }
     }
 
@@ -120,23 +234,22 @@ impl WriterTable {
         &self,
         stream_name: &str,
         schema: &Arc<Schema>,
-    ) -> Option<Vec<RecordBatch>> {
-        let records = self
-            .0
-            .read()
-            .unwrap()
-            .get(stream_name)?
-            .lock()
-            .unwrap()
-            .mem
-            .recordbatch_cloned(schema);
-
-        Some(records)
+    ) -> Result<Option<Vec<RecordBatch>>, ArrowError> {
+        let table = self.0.read().unwrap();
+        let Some(writer) = table.get(stream_name) else {
+            return Ok(None);
+        };
+        let records = writer.lock().unwrap().recordbatches(schema)?;
+        Ok(Some(records))
     }
 }
 
-fn get_timestamp_array(size: usize) -> TimestampMillisecondArray {
-    TimestampMillisecondArray::from_value(Utc::now().timestamp_millis(), size)
+fn over_memory_limit(used: usize, size: usize, limit: Option<usize>) -> bool {
+    limit.is_some_and(|limit| used + size > limit)
+}
+
+fn get_timestamp_array(timestamp: DateTime<Utc>, size: usize) -> TimestampMillisecondArray {
+    TimestampMillisecondArray::from_value(timestamp.timestamp_millis(), size)
 }
 
 pub mod errors {
@@ -149,3 +262,86 @@ pub mod errors {
         Io(#[from] std::io::Error),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Array, Int64Array, RecordBatch};
+    use arrow_ipc::writer::StreamWriter;
+    use arrow_schema::{DataType, Field, Schema};
+
+    use chrono::{Duration, TimeZone, Utc};
+    use std::sync::Mutex;
+
+    use super::file_writer::{ArrowWriter, FileWriter};
+    use super::{over_memory_limit, Writer, WriterTable};
+    use crate::metrics::OLDEST_STAGING_RECORD_AGE_SECONDS;
+
+    #[test]
+    fn spilled_records_read_from_disk() {
+        let dir = std::env::temp_dir().join(format!("spill-{}", ulid::Ulid::new()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file_path = dir.join("staged.arrows");
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, true)]));
+        let rb = |values: Vec<i64>| {
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(Int64Array::from(values))]).unwrap()
+        };
+
+        let file = std::fs::File::create(&file_path).unwrap();
+        let mut writer = StreamWriter::try_new(file, &schema).unwrap();
+        writer.write(&rb(vec![1, 2])).unwrap();
+        writer.write(&rb(vec![3])).unwrap();
+        let mut disk = FileWriter::default();
+        disk.insert("key".to_string(), ArrowWriter { file_path, writer });
+
+        // the file is still open for writing, a query schema with more columns
+        let query_schema = Arc::new(Schema::new(vec![
+            Field::new("a", DataType::Int64, true),
+            Field::new("b", DataType::Utf8, true),
+        ]));
+        let records = disk.recordbatches(&query_schema).unwrap();
+        assert_eq!(records.iter().map(|rb| rb.num_rows()).sum::<usize>(), 3);
+        assert_eq!(records[0].num_columns(), 2);
+        assert!(records[0].column(1).is_null(0));
+
+        disk.close_all();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn spill_only_above_limit() {
+        assert!(!over_memory_limit(usize::MAX / 2, 10, None));
+        assert!(!over_memory_limit(90, 10, Some(100)));
+        assert!(over_memory_limit(91, 10, Some(100)));
+    }
+
+    #[test]
+    fn staging_age_refreshed_without_pushes() {
+        let first_push = Utc.with_ymd_and_hms(2024, 1, 1, 10, 0, 0).unwrap();
+        let table = WriterTable::default();
+        let writer = Writer {
+            oldest_record: Some(first_push),
+            ..Writer::default()
+        };
+        table
+            .write()
+            .unwrap()
+            .insert("agedstream".to_string(), Mutex::new(writer));
+        table
+            .write()
+            .unwrap()
+            .insert("flushedstream".to_string(), Mutex::new(Writer::default()));
+        let gauge = |stream| OLDEST_STAGING_RECORD_AGE_SECONDS.with_label_values(&[stream]);
+
+        table.refresh_staging_age(first_push + Duration::seconds(45));
+        assert_eq!(gauge("agedstream").get(), 45);
+        assert_eq!(gauge("flushedstream").get(), 0);
+
+        table.refresh_staging_age(first_push + Duration::seconds(90));
+        assert_eq!(gauge("agedstream").get(), 90);
+
+        table.unset_stream("agedstream");
+        assert_eq!(gauge("agedstream").get(), 0);
+    }
+}
@@ -19,14 +19,21 @@
 
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use arrow_array::RecordBatch;
+use arrow_ipc::reader::StreamReader;
 use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{ArrowError, Schema};
 use derive_more::{Deref, DerefMut};
 
+use crate::metadata::STREAM_INFO;
+use crate::storage::compression::StagingCompression;
 use crate::storage::staging::StorageDir;
 
+use crate::utils::arrow::adapt_batch;
+
 use super::errors::StreamWriterError;
 
 pub struct ArrowWriter {
@@ -38,14 +45,16 @@ pub struct ArrowWriter {
 pub struct FileWriter(HashMap<String, ArrowWriter>);
 
 impl FileWriter {
-    // append to a existing stream
+    // append to a existing stream, returns the path of the file written to
     pub fn push(
         &mut self,
         stream_name: &str,
         schema_key: &str,
+        partition: &str,
         record: &RecordBatch,
-    ) -> Result<(), StreamWriterError> {
-        match self.get_mut(schema_key) {
+    ) -> Result<&Path, StreamWriterError> {
+        let key = format!("{schema_key}.{partition}");
+        match self.get_mut(&key) {
             Some(writer) => {
                 writer
                     .writer
@@ -55,9 +64,10 @@ impl FileWriter {
             // entry is not present thus we create it
             None => {
                 // this requires mutable borrow of the map so we drop this read lock and wait for write lock
-                let (path, writer) = init_new_stream_writer_file(stream_name, schema_key, record)?;
+                let (path, writer) =
+                    init_new_stream_writer_file(stream_name, schema_key, partition, record)?;
                 self.insert(
-                    schema_key.to_owned(),
+                    key.clone(),
                     ArrowWriter {
                         file_path: path,
                         writer,
@@ -66,7 +76,20 @@ impl FileWriter {
             }
         };
 
-        Ok(())
+        Ok(&self[&key].file_path)
+    }
+
+    // read back the records written so far, every write is flushed to the
+    // file so the files hold every record pushed until now
+    pub fn recordbatches(&self, schema: &Arc<Schema>) -> Result<Vec<RecordBatch>, ArrowError> {
+        let mut records = Vec::new();
+        for writer in self.values() {
+            let reader = StreamReader::try_new(File::open(&writer.file_path)?, None)?;
+            for rb in reader {
+                records.push(adapt_batch(schema, &rb?));
+            }
+        }
+        Ok(records)
     }
 
     pub fn close_all(self) {
@@ -79,16 +102,21 @@ impl FileWriter {
 fn init_new_stream_writer_file(
     stream_name: &str,
     schema_key: &str,
+    partition: &str,
     record: &RecordBatch,
 ) -> Result<(PathBuf, StreamWriter<std::fs::File>), StreamWriterError> {
     let dir = StorageDir::new(stream_name);
-    let path = dir.path_by_current_time(schema_key);
+    let path = dir.path_by_current_time(schema_key, partition);
 
     std::fs::create_dir_all(dir.data_path)?;
 
     let file = OpenOptions::new().create(true).append(true).open(&path)?;
 
-    let mut stream_writer = StreamWriter::try_new(file, &record.schema())
+    // files keep the compression they were opened with
+    let compression = STREAM_INFO.staging_compression(stream_name).ok().flatten();
+    let options =
+        StagingCompression::write_options(compression).map_err(StreamWriterError::Writer)?;
+    let mut stream_writer = StreamWriter::try_new_with_options(file, &record.schema(), options)
         .expect("File and RecordBatch both are checked");
 
     stream_writer
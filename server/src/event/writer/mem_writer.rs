@@ -36,6 +36,8 @@ pub struct MemWriter<const N: usize> {
     schema_map: HashSet<String>,
     read_buffer: Vec<RecordBatch>,
     mutable_buffer: MutableBuffer<N>,
+    // memory held by pushed recordbatches
+    size: usize,
 }
 
 impl<const N: usize> Default for MemWriter<N> {
@@ -45,12 +47,14 @@ impl<const N: usize> Default for MemWriter<N> {
             schema_map: HashSet::default(),
             read_buffer: Vec::default(),
             mutable_buffer: MutableBuffer::default(),
+            size: 0,
         }
     }
 }
 
 impl<const N: usize> MemWriter<N> {
     pub fn push(&mut self, schema_key: &str, rb: RecordBatch) {
+        self.size += rb.get_array_memory_size();
         if !self.schema_map.contains(schema_key) {
             self.schema_map.insert(schema_key.to_owned());
             self.schema = Schema::try_merge([self.schema.clone(), (*rb.schema()).clone()]).unwrap();
@@ -62,6 +66,10 @@ impl<const N: usize> MemWriter<N> {
         }
     }
 
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
     pub fn recordbatch_cloned(&self, schema: &Arc<Schema>) -> Vec<RecordBatch> {
         let mut read_buffer = self.read_buffer.clone();
         if self.mutable_buffer.rows > 0 {
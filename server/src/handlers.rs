@@ -23,6 +23,18 @@ const PREFIX_TAGS: &str = "x-p-tag-";
 const PREFIX_META: &str = "x-p-meta-";
 const STREAM_NAME_HEADER_KEY: &str = "x-p-stream";
 const LOG_SOURCE_KEY: &str = "x-p-log-source";
+const TEMPLATE_HEADER_KEY: &str = "x-p-template";
+const W3C_FIELDS_KEY: &str = "x-p-w3c-fields";
+const INGEST_KEY_HEADER_KEY: &str = "x-p-ingest-key";
+const TIMESTAMP_COLUMN_KEY: &str = "x-p-timestamp-column";
+// set to true for ingest responses to list where every accepted record was staged
+const RECEIPTS_HEADER_KEY: &str = "x-p-receipts";
+// set to true for ingest responses to trace what the ingest stages did with the request
+const TRACE_HEADER_KEY: &str = "x-p-trace";
+const SELECT_HEADER_KEY: &str = "x-p-select";
+const QUERY_ID_HEADER_KEY: &str = "x-p-query-id";
+const QUERY_START_TIME_HEADER_KEY: &str = "x-p-start-time";
+const QUERY_END_TIME_HEADER_KEY: &str = "x-p-end-time";
 
 const AUTHORIZATION_KEY: &str = "authorization";
 const SEPARATOR: char = '^';
@@ -39,5 +51,33 @@ const LOG_SOURCE_KINESIS: &str = "kinesis";
 // specification as explained here https://opentelemetry.io/docs/specs/otel/logs/data-model/
 const LOG_SOURCE_OTEL: &str = "otel";
 
+// JSON lines of an OTLP log record under `log` along with the optional
+// `resource` and `scope` it was emitted by
+const LOG_SOURCE_OTEL_LINES: &str = "otel-lines";
+
+// plaintext log lines, parsed with the pattern configured for the stream
+const LOG_SOURCE_TEXT: &str = "text";
+
+// web server access logs in W3C Extended Log Format
+const LOG_SOURCE_W3C: &str = "w3c";
+
+// CSV with a header row
+const LOG_SOURCE_CSV: &str = "csv";
+
+// length delimited protobuf events sent by Vector's native sink
+const LOG_SOURCE_VECTOR: &str = "vector";
+
+// streams pushed to the Loki push API, protobuf or JSON
+const LOG_SOURCE_LOKI: &str = "loki";
+
+// samples sent by Prometheus remote write
+const LOG_SOURCE_PROMETHEUS: &str = "prometheus";
+
+// record batches in the Arrow IPC stream format
+const LOG_SOURCE_ARROW: &str = "arrow";
+
+// plain JSON, used when no known log source is set
+const LOG_SOURCE_JSON: &str = "json";
+
 // AWS Kinesis constants
 const KINESIS_COMMON_ATTRIBUTES_KEY: &str = "x-amz-firehose-common-attributes";
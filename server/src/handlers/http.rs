@@ -35,23 +35,37 @@ use rustls_pemfile::{certs, pkcs8_private_keys};
 use crate::option::CONFIG;
 use crate::rbac::role::Action;
 
-use self::middleware::{DisAllowRootUser, RouteExt};
+use self::middleware::{CompressionSavings, DisAllowRootUser, RouteExt};
 
 mod about;
+mod csv;
 mod health_check;
 mod ingest;
 mod kinesis;
 mod llm;
 mod logstream;
+mod loki;
+mod lookup;
 mod middleware;
 mod oidc;
+mod otel;
+mod prometheus;
 mod query;
 mod rbac;
+mod replay;
 mod role;
+mod tail;
+mod template;
+mod text;
+mod vector;
+mod w3c;
+
+pub use self::ingest::{
+    create_stream_if_not_exists, push_events, push_repeats, push_sample, push_to_derived, PostError,
+};
 
 include!(concat!(env!("OUT_DIR"), "/generated.rs"));
 
-const MAX_EVENT_PAYLOAD_SIZE: usize = 10485760;
 const API_BASE_PATH: &str = "/api";
 const API_VERSION: &str = "v1";
 
@@ -75,6 +89,7 @@ pub async fn run_http(
             .configure(|cfg| configure_routes(cfg, oidc_client.clone()))
             .wrap(actix_web::middleware::Logger::default())
             .wrap(actix_web::middleware::Compress::default())
+            .wrap(CompressionSavings)
             .wrap(cross_origin_config())
     };
 
@@ -143,18 +158,13 @@ pub fn configure_routes(
                         .authorize_for_stream(Action::CreateStream),
                 )
                 // POST "/logstream/{logstream}" ==> Post logs to given log stream
-                .route(
-                    web::post()
-                        .to(ingest::post_event)
-                        .authorize_for_stream(Action::Ingest),
-                )
+                .route(web::post().to(ingest::post_event).authorize_for_ingest())
                 // DELETE "/logstream/{logstream}" ==> Delete log stream
                 .route(
                     web::delete()
                         .to(logstream::delete)
                         .authorize_for_stream(Action::DeleteStream),
-                )
-                .app_data(web::PayloadConfig::default().limit(MAX_EVENT_PAYLOAD_SIZE)),
+                ),
         )
         .service(
             web::resource("/alert")
@@ -179,6 +189,30 @@ pub fn configure_routes(
                     .authorize_for_stream(Action::GetSchema),
             ),
         )
+        .service(
+            // GET "/logstream/{logstream}/schema/diff" ==> Get schema changes of given log stream between two dates
+            web::resource("/schema/diff").route(
+                web::get()
+                    .to(logstream::get_schema_diff)
+                    .authorize_for_stream(Action::GetSchema),
+            ),
+        )
+        .service(
+            // DELETE "/logstream/{logstream}/partition/{date}" ==> Delete data of given log stream for a date
+            web::resource("/partition/{date}").route(
+                web::delete()
+                    .to(logstream::delete_partition)
+                    .authorize_for_stream(Action::DeletePartition),
+            ),
+        )
+        .service(
+            // GET "/logstream/{logstream}/info" ==> Get metadata, config and stats for given log stream
+            web::resource("/info").route(
+                web::get()
+                    .to(logstream::get_stream_info)
+                    .authorize_for_stream(Action::GetStreamInfo),
+            ),
+        )
         .service(
             // GET "/logstream/{logstream}/stats" ==> Get stats for given log stream
             web::resource("/stats").route(
@@ -187,6 +221,29 @@ pub fn configure_routes(
                     .authorize_for_stream(Action::GetStats),
             ),
         )
+        .service(
+            // GET "/logstream/{logstream}/stats/partitions" ==> Get storage used per date and partition for given log stream
+            web::resource("/stats/partitions").route(
+                web::get()
+                    .to(logstream::get_partition_stats)
+                    .authorize_for_stream(Action::GetStats),
+            ),
+        )
+        .service(
+            web::resource("/bundle")
+                // PUT "/logstream/{logstream}/bundle" ==> Create log stream from the configuration bundle of another log stream
+                .route(
+                    web::put()
+                        .to(logstream::put_bundle)
+                        .authorize_for_stream(Action::CreateStream),
+                )
+                // GET "/logstream/{logstream}/bundle" ==> Export configuration of given logstream as a bundle
+                .route(
+                    web::get()
+                        .to(logstream::get_bundle)
+                        .authorize_for_stream(Action::GetStreamBundle),
+                ),
+        )
         .service(
             web::resource("/retention")
                 // PUT "/logstream/{logstream}/retention" ==> Set retention for given logstream
@@ -202,6 +259,22 @@ pub fn configure_routes(
                         .authorize_for_stream(Action::GetRetention),
                 ),
         )
+        .service(
+            // GET "/logstream/{logstream}/retention/history" ==> Get data deleted by retention for given logstream
+            web::resource("/retention/history").route(
+                web::get()
+                    .to(logstream::get_retention_history)
+                    .authorize_for_stream(Action::GetRetention),
+            ),
+        )
+        .service(
+            // GET "/logstream/{logstream}/tail/aggregate" ==> Stream windowed counts of live events for given logstream
+            web::resource("/tail/aggregate").route(
+                web::get()
+                    .to(tail::aggregate)
+                    .authorize_for_stream(Action::Query),
+            ),
+        )
         .service(
             web::resource("/cache")
                 // PUT "/logstream/{logstream}/cache" ==> Set retention for given logstream
@@ -216,6 +289,544 @@ pub fn configure_routes(
                         .to(logstream::get_cache_enabled)
                         .authorize_for_stream(Action::GetCacheEnabled),
                 ),
+        )
+        .service(
+            web::resource("/labels")
+                // PUT "/logstream/{logstream}/labels" ==> Set labels of given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_labels)
+                        .authorize_for_stream(Action::PutLabels),
+                )
+                // GET "/logstream/{logstream}/labels" ==> Get labels of given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_labels)
+                        .authorize_for_stream(Action::GetLabels),
+                ),
+        )
+        .service(
+            web::resource("/encryption")
+                // PUT "/logstream/{logstream}/encryption" ==> Enable or disable encryption of new parquet files of given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_encryption)
+                        .authorize_for_stream(Action::PutEncryption),
+                )
+                // GET "/logstream/{logstream}/encryption" ==> Get if parquet files of given logstream are encrypted
+                .route(
+                    web::get()
+                        .to(logstream::get_encryption)
+                        .authorize_for_stream(Action::GetEncryption),
+                ),
+        )
+        .service(
+            web::resource("/pattern")
+                // PUT "/logstream/{logstream}/pattern" ==> Set plaintext log pattern for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_log_pattern)
+                        .authorize_for_stream(Action::PutLogPattern),
+                )
+                // GET "/logstream/{logstream}/pattern" ==> Get plaintext log pattern for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_log_pattern)
+                        .authorize_for_stream(Action::GetLogPattern),
+                ),
+        )
+        .service(
+            web::resource("/timestamp")
+                // PUT "/logstream/{logstream}/timestamp" ==> Set timestamp column name for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_timestamp_key)
+                        .authorize_for_stream(Action::PutTimestampKey),
+                )
+                // GET "/logstream/{logstream}/timestamp" ==> Get timestamp column name for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_timestamp_key)
+                        .authorize_for_stream(Action::GetTimestampKey),
+                ),
+        )
+        .service(
+            web::resource("/timefields")
+                // PUT "/logstream/{logstream}/timefields" ==> Set fields promoted to timestamp columns for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_time_fields)
+                        .authorize_for_stream(Action::PutTimeFields),
+                )
+                // GET "/logstream/{logstream}/timefields" ==> Get fields promoted to timestamp columns for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_time_fields)
+                        .authorize_for_stream(Action::GetTimeFields),
+                ),
+        )
+        .service(
+            web::resource("/columnorder")
+                // PUT "/logstream/{logstream}/columnorder" ==> Set columns leading query results for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_column_order)
+                        .authorize_for_stream(Action::PutColumnOrder),
+                )
+                // GET "/logstream/{logstream}/columnorder" ==> Get columns leading query results for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_column_order)
+                        .authorize_for_stream(Action::GetColumnOrder),
+                ),
+        )
+        .service(
+            web::resource("/casts")
+                // PUT "/logstream/{logstream}/casts" ==> Set columns computed by casting columns of given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_casts)
+                        .authorize_for_stream(Action::PutCasts),
+                )
+                // GET "/logstream/{logstream}/casts" ==> Get columns computed by casting columns of given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_casts)
+                        .authorize_for_stream(Action::GetCasts),
+                ),
+        )
+        .service(
+            web::resource("/severity")
+                // PUT "/logstream/{logstream}/severity" ==> Set severity mapping of JSON events for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_severity_mapping)
+                        .authorize_for_stream(Action::PutSeverityMapping),
+                )
+                // GET "/logstream/{logstream}/severity" ==> Get severity mapping of JSON events for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_severity_mapping)
+                        .authorize_for_stream(Action::GetSeverityMapping),
+                ),
+        )
+        .service(
+            web::resource("/unknownseverity")
+                // PUT "/logstream/{logstream}/unknownseverity" ==> Set handling of unknown OTLP severity numbers for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_unknown_severity)
+                        .authorize_for_stream(Action::PutUnknownSeverity),
+                )
+                // GET "/logstream/{logstream}/unknownseverity" ==> Get handling of unknown OTLP severity numbers for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_unknown_severity)
+                        .authorize_for_stream(Action::GetUnknownSeverity),
+                ),
+        )
+        .service(
+            web::resource("/body")
+                // PUT "/logstream/{logstream}/body" ==> Set column name and type of OTLP log body for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_body_config)
+                        .authorize_for_stream(Action::PutBodyConfig),
+                )
+                // GET "/logstream/{logstream}/body" ==> Get column name and type of OTLP log body for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_body_config)
+                        .authorize_for_stream(Action::GetBodyConfig),
+                ),
+        )
+        .service(
+            web::resource("/shadow")
+                // PUT "/logstream/{logstream}/shadow" ==> Ingest events of given logstream into a shadow stream as well
+                .route(
+                    web::put()
+                        .to(logstream::put_shadow)
+                        .authorize_for_stream(Action::PutShadow),
+                )
+                // GET "/logstream/{logstream}/shadow" ==> Get shadow stream config of given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_shadow)
+                        .authorize_for_stream(Action::GetShadow),
+                )
+                // DELETE "/logstream/{logstream}/shadow" ==> Stop shadow ingestion for given logstream
+                .route(
+                    web::delete()
+                        .to(logstream::delete_shadow)
+                        .authorize_for_stream(Action::PutShadow),
+                ),
+        )
+        .service(
+            web::resource("/routing")
+                // PUT "/logstream/{logstream}/routing" ==> Set routing of events to derived streams for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_routing)
+                        .authorize_for_stream(Action::PutRouting),
+                )
+                // GET "/logstream/{logstream}/routing" ==> Get routing of events to derived streams for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_routing)
+                        .authorize_for_stream(Action::GetRouting),
+                ),
+        )
+        .service(
+            web::resource("/rebuild")
+                // PUT "/logstream/{logstream}/rebuild" ==> Start rebuilding the derived streams of given logstream over a time range
+                .route(
+                    web::put()
+                        .to(logstream::put_rebuild)
+                        .authorize_for_stream(Action::Rebuild),
+                )
+                // GET "/logstream/{logstream}/rebuild" ==> Get the status of the latest rebuild of given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_rebuild)
+                        .authorize_for_stream(Action::GetRebuild),
+                )
+                // DELETE "/logstream/{logstream}/rebuild" ==> Cancel the running rebuild of given logstream
+                .route(
+                    web::delete()
+                        .to(logstream::delete_rebuild)
+                        .authorize_for_stream(Action::Rebuild),
+                ),
+        )
+        .service(
+            web::resource("/rebuild/resume")
+                // POST "/logstream/{logstream}/rebuild/resume" ==> Resume the latest rebuild of given logstream
+                .route(
+                    web::post()
+                        .to(logstream::resume_rebuild)
+                        .authorize_for_stream(Action::Rebuild),
+                ),
+        )
+        .service(
+            web::resource("/schemalock")
+                // PUT "/logstream/{logstream}/schemalock" ==> Lock the schema of given logstream
+                .route(
+                    web::put()
+                        .to(logstream::lock_schema)
+                        .authorize_for_stream(Action::LockSchema),
+                )
+                // GET "/logstream/{logstream}/schemalock" ==> Get the schema lock of given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_schema_lock)
+                        .authorize_for_stream(Action::GetSchema),
+                )
+                // DELETE "/logstream/{logstream}/schemalock" ==> Unlock the schema of given logstream
+                .route(
+                    web::delete()
+                        .to(logstream::unlock_schema)
+                        .authorize_for_stream(Action::LockSchema),
+                ),
+        )
+        .service(
+            web::resource("/quota")
+                // PUT "/logstream/{logstream}/quota" ==> Set daily ingestion quota for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_quota)
+                        .authorize_for_stream(Action::PutQuota),
+                )
+                // GET "/logstream/{logstream}/quota" ==> Get daily ingestion quota and its usage for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_quota)
+                        .authorize_for_stream(Action::GetQuota),
+                ),
+        )
+        .service(
+            web::resource("/sampling")
+                // PUT "/logstream/{logstream}/sampling" ==> Set weighted sampling of ingested events for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_sampling)
+                        .authorize_for_stream(Action::PutSampling),
+                )
+                // GET "/logstream/{logstream}/sampling" ==> Get weighted sampling of ingested events for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_sampling)
+                        .authorize_for_stream(Action::GetSampling),
+                ),
+        )
+        .service(
+            web::resource("/dedup")
+                // PUT "/logstream/{logstream}/dedup" ==> Set suppression of repeated events for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_dedup)
+                        .authorize_for_stream(Action::PutDedup),
+                )
+                // GET "/logstream/{logstream}/dedup" ==> Get suppression of repeated events for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_dedup)
+                        .authorize_for_stream(Action::GetDedup),
+                ),
+        )
+        .service(
+            web::resource("/maxfilesize")
+                // PUT "/logstream/{logstream}/maxfilesize" ==> Set size in bytes at which parquet files of given logstream are rotated
+                .route(
+                    web::put()
+                        .to(logstream::put_max_file_size)
+                        .authorize_for_stream(Action::PutMaxFileSize),
+                )
+                // GET "/logstream/{logstream}/maxfilesize" ==> Get size in bytes at which parquet files of given logstream are rotated
+                .route(
+                    web::get()
+                        .to(logstream::get_max_file_size)
+                        .authorize_for_stream(Action::GetMaxFileSize),
+                ),
+        )
+        .service(
+            web::resource("/attributemap")
+                // PUT "/logstream/{logstream}/attributemap" ==> Set storage of attributes in a map column for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_attribute_map)
+                        .authorize_for_stream(Action::PutAttributeMap),
+                )
+                // GET "/logstream/{logstream}/attributemap" ==> Get storage of attributes in a map column for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_attribute_map)
+                        .authorize_for_stream(Action::GetAttributeMap),
+                ),
+        )
+        .service(
+            web::resource("/numbermode")
+                // PUT "/logstream/{logstream}/numbermode" ==> Set how numbers of events are stored for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_number_mode)
+                        .authorize_for_stream(Action::PutNumberMode),
+                )
+                // GET "/logstream/{logstream}/numbermode" ==> Get how numbers of events are stored for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_number_mode)
+                        .authorize_for_stream(Action::GetNumberMode),
+                ),
+        )
+        .service(
+            web::resource("/typeconflict")
+                // PUT "/logstream/{logstream}/typeconflict" ==> Set how attributes with values of different types are stored for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_type_conflict)
+                        .authorize_for_stream(Action::PutTypeConflict),
+                )
+                // GET "/logstream/{logstream}/typeconflict" ==> Get how attributes with values of different types are stored for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_type_conflict)
+                        .authorize_for_stream(Action::GetTypeConflict),
+                ),
+        )
+        .service(
+            web::resource("/attributefilter")
+                // PUT "/logstream/{logstream}/attributefilter" ==> Set the attributes kept from events for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_attribute_filter)
+                        .authorize_for_stream(Action::PutAttributeFilter),
+                )
+                // GET "/logstream/{logstream}/attributefilter" ==> Get the attributes kept from events for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_attribute_filter)
+                        .authorize_for_stream(Action::GetAttributeFilter),
+                ),
+        )
+        .service(
+            web::resource("/ipmask")
+                // PUT "/logstream/{logstream}/ipmask" ==> Set masking of IP addresses to their network for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_ip_mask)
+                        .authorize_for_stream(Action::PutIpMask),
+                )
+                // GET "/logstream/{logstream}/ipmask" ==> Get masking of IP addresses to their network for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_ip_mask)
+                        .authorize_for_stream(Action::GetIpMask),
+                ),
+        )
+        .service(
+            web::resource("/rawpayload")
+                // PUT "/logstream/{logstream}/rawpayload" ==> Set storage of the raw payload of events for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_raw_payload)
+                        .authorize_for_stream(Action::PutRawPayload),
+                )
+                // GET "/logstream/{logstream}/rawpayload" ==> Get storage of the raw payload of events for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_raw_payload)
+                        .authorize_for_stream(Action::GetRawPayload),
+                ),
+        )
+        .service(
+            web::resource("/defaultview")
+                // PUT "/logstream/{logstream}/defaultview" ==> Set the query run when opening given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_default_view)
+                        .authorize_for_stream(Action::PutDefaultView),
+                )
+                // GET "/logstream/{logstream}/defaultview" ==> Get the query run when opening given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_default_view)
+                        .authorize_for_stream(Action::GetDefaultView),
+                ),
+        )
+        .service(
+            web::resource("/clockskew")
+                // PUT "/logstream/{logstream}/clockskew" ==> Set correction of OTLP log record times for clock skew for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_clock_skew)
+                        .authorize_for_stream(Action::PutClockSkew),
+                )
+                // GET "/logstream/{logstream}/clockskew" ==> Get correction of OTLP log record times for clock skew for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_clock_skew)
+                        .authorize_for_stream(Action::GetClockSkew),
+                ),
+        )
+        .service(
+            web::resource("/keysanitization")
+                // PUT "/logstream/{logstream}/keysanitization" ==> Set sanitization of event keys into identifiers for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_key_sanitization)
+                        .authorize_for_stream(Action::PutKeySanitization),
+                )
+                // GET "/logstream/{logstream}/keysanitization" ==> Get sanitization of event keys into identifiers for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_key_sanitization)
+                        .authorize_for_stream(Action::GetKeySanitization),
+                ),
+        )
+        .service(
+            web::resource("/keymapping")
+                // GET "/logstream/{logstream}/keymapping" ==> Get columns of the sanitized event keys for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_key_mapping)
+                        .authorize_for_stream(Action::GetKeySanitization),
+                ),
+        )
+        .service(
+            web::resource("/compression")
+                // PUT "/logstream/{logstream}/compression" ==> Set parquet compression codec for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_compression)
+                        .authorize_for_stream(Action::PutCompression),
+                )
+                // GET "/logstream/{logstream}/compression" ==> Get parquet compression codec for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_compression)
+                        .authorize_for_stream(Action::GetCompression),
+                ),
+        )
+        .service(
+            web::resource("/stagingcompression")
+                // PUT "/logstream/{logstream}/stagingcompression" ==> Set compression codec of staging files for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_staging_compression)
+                        .authorize_for_stream(Action::PutStagingCompression),
+                )
+                // GET "/logstream/{logstream}/stagingcompression" ==> Get compression codec of staging files for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_staging_compression)
+                        .authorize_for_stream(Action::GetStagingCompression),
+                ),
+        )
+        .service(
+            web::resource("/partitioning")
+                // PUT "/logstream/{logstream}/partitioning" ==> Set partition columns for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_partitioning)
+                        .authorize_for_stream(Action::PutPartitioning),
+                )
+                // GET "/logstream/{logstream}/partitioning" ==> Get partition columns for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_partitioning)
+                        .authorize_for_stream(Action::GetPartitioning),
+                ),
+        )
+        .service(
+            web::resource("/ingestkey")
+                // POST "/logstream/{logstream}/ingestkey" ==> Create an ingest key for given logstream
+                .route(
+                    web::post()
+                        .to(logstream::create_ingest_key)
+                        .authorize_for_stream(Action::CreateIngestKey),
+                )
+                // GET "/logstream/{logstream}/ingestkey" ==> List ingest keys of given logstream
+                .route(
+                    web::get()
+                        .to(logstream::list_ingest_keys)
+                        .authorize_for_stream(Action::ListIngestKey),
+                ),
+        )
+        .service(
+            // DELETE "/logstream/{logstream}/ingestkey/{id}" ==> Delete an ingest key of given logstream
+            web::resource("/ingestkey/{id}").route(
+                web::delete()
+                    .to(logstream::delete_ingest_key)
+                    .authorize_for_stream(Action::DeleteIngestKey),
+            ),
+        )
+        .service(
+            // POST "/logstream/{logstream}/replay" ==> Open a resumable replay session for given logstream
+            web::resource("/replay").route(
+                web::post()
+                    .to(replay::create_session)
+                    .authorize_for_ingest(),
+            ),
+        )
+        .service(
+            // GET "/logstream/{logstream}/replay/{session}" ==> Get committed chunks of a replay session
+            web::resource("/replay/{session}").route(
+                web::get()
+                    .to(replay::get_session_status)
+                    .authorize_for_ingest(),
+            ),
+        )
+        .service(
+            // POST "/logstream/{logstream}/replay/{session}/commit" ==> Commit a replay session and flush staged data
+            web::resource("/replay/{session}/commit").route(
+                web::post()
+                    .to(replay::commit_session)
+                    .authorize_for_ingest(),
+            ),
+        )
+        .service(
+            // PUT "/logstream/{logstream}/replay/{session}/{chunk}" ==> Post a numbered NDJSON chunk of a replay session
+            web::resource("/replay/{session}/{chunk}")
+                .route(web::put().to(replay::put_chunk).authorize_for_ingest()),
         );
 
     // User API
@@ -285,6 +896,49 @@ pub fn configure_routes(
                 .route(web::get().to(role::get).authorize(Action::GetRole)),
         );
 
+    let template_api = web::scope("/template")
+        .service(
+            resource("")
+                // GET "/template" ==> List stream templates
+                .route(
+                    web::get()
+                        .to(template::list)
+                        .authorize(Action::ListTemplate),
+                ),
+        )
+        .service(
+            resource("/{name}")
+                // PUT "/template/{name}" ==> Create or update a stream template, with ?propagate=true
+                // the template is also applied to the streams created from it
+                .route(web::put().to(template::put).authorize(Action::PutTemplate))
+                // DELETE "/template/{name}" ==> Delete a stream template
+                .route(
+                    web::delete()
+                        .to(template::delete)
+                        .authorize(Action::DeleteTemplate),
+                )
+                // GET "/template/{name}" ==> Get a stream template
+                .route(web::get().to(template::get).authorize(Action::GetTemplate)),
+        );
+
+    let lookup_api = web::scope("/lookup")
+        .service(
+            resource("")
+                // GET "/lookup" ==> List lookup tables
+                .route(web::get().to(lookup::list).authorize(Action::ListLookup)),
+        )
+        .service(
+            resource("/{name}")
+                // PUT "/lookup/{name}" ==> Create a lookup table or replace its rows, from CSV or JSON
+                .route(web::put().to(lookup::put).authorize(Action::PutLookup))
+                // DELETE "/lookup/{name}" ==> Delete a lookup table
+                .route(
+                    web::delete()
+                        .to(lookup::delete)
+                        .authorize(Action::DeleteLookup),
+                ),
+        );
+
     let mut oauth_api = web::scope("/o")
         .service(resource("/login").route(web::get().to(oidc::login)))
         .service(resource("/logout").route(web::get().to(oidc::logout)))
@@ -295,24 +949,91 @@ pub fn configure_routes(
         oauth_api = oauth_api.app_data(web::Data::from(client))
     }
 
+    // Base path "{url}/api/v1"
+    let mut api = web::scope(&base_path());
+
+    if CONFIG.parseable.vector_ingest {
+        // POST "/ingest/vector" ==> Post events sent by Vector to given log stream based on header
+        api = api.service(
+            web::resource("/ingest/vector")
+                .route(web::post().to(ingest::ingest_vector).authorize_for_ingest()),
+        );
+    }
+
     // Deny request if username is same as the env variable P_USERNAME.
     cfg.service(
-        // Base path "{url}/api/v1"
-        web::scope(&base_path())
+        api
             // POST "/query" ==> Get results of the SQL query passed in request body
             .service(
                 web::resource("/query")
                     .route(web::post().to(query::query).authorize(Action::Query)),
             )
+            // GET "/query/profile" ==> Get the slowest and most expensive recent queries
+            .service(
+                web::resource("/query/profile").route(
+                    web::get()
+                        .to(query::get_top_queries)
+                        .authorize(Action::GetQueryProfile),
+                ),
+            )
+            // POST "/query/{id}/_cancel" ==> Cancel a running query by the id of its X-P-Query-Id header
+            .service(
+                web::resource("/query/{id}/_cancel")
+                    .route(web::post().to(query::cancel_query).authorize(Action::Query)),
+            )
+            // POST "/query/facets" ==> Get row counts by the values of a column, or by severity level
+            .service(
+                web::resource("/query/facets")
+                    .route(web::post().to(query::query_facets).authorize(Action::Query)),
+            )
+            // POST "/query/histogram" ==> Get row counts by buckets of the values of a numeric column
+            .service(
+                web::resource("/query/histogram").route(
+                    web::post()
+                        .to(query::query_histogram)
+                        .authorize(Action::Query),
+                ),
+            )
+            // POST "/query/latest" ==> Get the most recent row of every group of rows
+            .service(
+                web::resource("/query/latest")
+                    .route(web::post().to(query::query_latest).authorize(Action::Query)),
+            )
             // POST "/ingest" ==> Post logs to given log stream based on header
             .service(
                 web::resource("/ingest")
-                    .route(
-                        web::post()
-                            .to(ingest::ingest)
-                            .authorize_for_stream(Action::Ingest),
-                    )
-                    .app_data(web::PayloadConfig::default().limit(MAX_EVENT_PAYLOAD_SIZE)),
+                    .route(web::post().to(ingest::ingest).authorize_for_ingest()),
+            )
+            // POST "/ingest/otel/stream" ==> Post a stream of length delimited protobuf OTLP logs to given log stream based on header
+            .service(
+                web::resource("/ingest/otel/stream").route(
+                    web::post()
+                        .to(ingest::ingest_otel_stream)
+                        .authorize_for_ingest(),
+                ),
+            )
+            // POST "/ingest/arrow" ==> Post record batches in the Arrow IPC stream format to given log stream based on header
+            .service(
+                web::resource("/ingest/arrow")
+                    .route(web::post().to(ingest::ingest_arrow).authorize_for_ingest()),
+            )
+            // POST "/loki/api/v1/push" ==> Post streams pushed by Loki clients to given log stream based on header
+            .service(
+                web::resource("/loki/api/v1/push")
+                    .route(web::post().to(ingest::ingest_loki).authorize_for_ingest()),
+            )
+            // POST "/write" ==> Post samples sent by Prometheus remote write to given log stream based on header
+            .service(
+                web::resource("/write")
+                    .route(web::post().to(ingest::remote_write).authorize_for_ingest()),
+            )
+            // POST "/ingest/multipart" ==> Post every part of a multipart request to the log stream of that part
+            .service(
+                web::resource("/ingest/multipart").route(
+                    web::post()
+                        .to(ingest::ingest_multipart)
+                        .authorize(Action::Ingest),
+                ),
             )
             // GET "/liveness" ==> Liveness check as per https://kubernetes.io/docs/tasks/configure-pod-container/configure-liveness-readiness-startup-probes/#define-a-liveness-command
             .service(web::resource("/liveness").route(web::get().to(health_check::liveness)))
@@ -323,12 +1044,34 @@ pub fn configure_routes(
                 web::resource("/about")
                     .route(web::get().to(about::about).authorize(Action::GetAbout)),
             )
+            // GET "/schemas" ==> Get the schemas of all or the selected log streams with their fingerprints
+            .service(
+                web::resource("/schemas").route(
+                    web::get()
+                        .to(logstream::export_schemas)
+                        .authorize(Action::GetSchema),
+                ),
+            )
+            // GET "/stats/labels/{label}" ==> Get stats of log streams summed per value of a label
+            .service(
+                web::resource("/stats/labels/{label}").route(
+                    web::get()
+                        .to(logstream::get_stats_by_label)
+                        .authorize(Action::GetStats),
+                ),
+            )
             .service(
                 web::scope("/logstream")
                     .service(
                         // GET "/logstream" ==> Get list of all Log Streams on the server
                         web::resource("")
-                            .route(web::get().to(logstream::list).authorize(Action::ListStream)),
+                            .route(web::get().to(logstream::list).authorize(Action::ListStream))
+                            // DELETE "/logstream" ==> Delete the listed log streams or those matching a pattern
+                            .route(
+                                web::delete()
+                                    .to(logstream::delete_bulk)
+                                    .authorize(Action::DeleteStream),
+                            ),
                     )
                     .service(
                         // logstream API
@@ -338,7 +1081,9 @@ pub fn configure_routes(
             .service(user_api)
             .service(llm_query_api)
             .service(oauth_api)
-            .service(role_api),
+            .service(role_api)
+            .service(template_api)
+            .service(lookup_api),
     )
     // GET "/" ==> Serve the static frontend directory
     .service(ResourceFiles::new("/", generated).resolve_not_found_to_root());
@@ -0,0 +1,237 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde_json::{Number, Value};
+
+const TIMESTAMP_KEY: &str = "timestamp";
+
+// A field of a CSV row and whether it was quoted
+type CsvField = (String, bool);
+
+// Parse CSV (RFC 4180) with a header row into JSON records.
+// The header row names the columns, every following row becomes a record.
+// Unquoted values are typed the way JSON would be, integers, floats and
+// booleans become numbers and booleans, quoted values always stay strings
+// and empty values are left out of the record. The value of
+// `timestamp_column` is stored as an RFC 3339 `timestamp` when it can be
+// parsed as a date time. Rows with a different number of values than the
+// header or broken quoting are skipped.
+// Returns the records along with the number of skipped rows.
+pub fn flatten_csv(
+    body: &str,
+    timestamp_column: Option<&str>,
+) -> Result<(Vec<BTreeMap<String, Value>>, usize), String> {
+    let mut rows = parse_rows(body).into_iter();
+    let header = match rows.next() {
+        Some(Ok(header)) => header
+            .into_iter()
+            .map(|(name, _)| name.trim().to_string())
+            .collect::<Vec<_>>(),
+        Some(Err(_)) => return Err("malformed header row".to_string()),
+        None => return Ok((Vec::new(), 0)),
+    };
+    if header.iter().any(String::is_empty) {
+        return Err("header row has an empty column name".to_string());
+    }
+    if let Some(column) = timestamp_column {
+        if !header.iter().any(|name| name == column) {
+            return Err(format!(
+                "timestamp column {column} is not in the header row"
+            ));
+        }
+    }
+
+    let mut records = Vec::new();
+    let mut skipped = 0;
+    for row in rows {
+        let Some(row) = row.ok().filter(|row| row.len() == header.len()) else {
+            skipped += 1;
+            continue;
+        };
+
+        let mut record = BTreeMap::new();
+        for (name, (value, quoted)) in header.iter().zip(row) {
+            if value.is_empty() {
+                continue;
+            }
+            if Some(name.as_str()) == timestamp_column {
+                record.insert(TIMESTAMP_KEY.to_string(), timestamp(value));
+                continue;
+            }
+            let value = if quoted {
+                Value::String(value)
+            } else {
+                typed_value(value)
+            };
+            record.insert(name.clone(), value);
+        }
+        records.push(record);
+    }
+
+    Ok((records, skipped))
+}
+
+// Split the body into rows of fields, a quoted field can span lines and
+// contains a quote as `""`. Empty lines are ignored.
+fn parse_rows(body: &str) -> Vec<Result<Vec<CsvField>, ()>> {
+    let mut rows = Vec::new();
+    let mut row: Vec<CsvField> = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut malformed = false;
+    let mut chars = body.chars().peekable();
+
+    let mut end_row = |row: &mut Vec<CsvField>, malformed: &mut bool| {
+        if *malformed {
+            rows.push(Err(()));
+        } else if !(row.len() == 1 && row[0].0.is_empty() && !row[0].1) {
+            rows.push(Ok(std::mem::take(row)));
+        }
+        row.clear();
+        *malformed = false;
+    };
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                c => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() && !quoted => {
+                in_quotes = true;
+                quoted = true;
+            }
+            ',' => row.push((std::mem::take(&mut field), std::mem::take(&mut quoted))),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                row.push((std::mem::take(&mut field), std::mem::take(&mut quoted)));
+                end_row(&mut row, &mut malformed);
+            }
+            // anything but a delimiter after a closing quote, or a quote
+            // within an unquoted field
+            _ if quoted || c == '"' => malformed = true,
+            c => field.push(c),
+        }
+    }
+
+    // a quote left open runs until the end of the body
+    malformed |= in_quotes;
+    if !field.is_empty() || quoted || !row.is_empty() || malformed {
+        row.push((field, quoted));
+        end_row(&mut row, &mut malformed);
+    }
+
+    rows
+}
+
+fn typed_value(value: String) -> Value {
+    match value.as_str() {
+        "true" => return Value::Bool(true),
+        "false" => return Value::Bool(false),
+        _ => {}
+    }
+    // leading zeros are significant in identifiers such as zip codes
+    let digits = value.trim_start_matches('-');
+    if digits.len() > 1 && digits.starts_with('0') && !digits.starts_with("0.") {
+        return Value::String(value);
+    }
+    if let Ok(number) = value.parse::<i64>() {
+        return Value::from(number);
+    }
+    match value.parse::<f64>().ok().and_then(Number::from_f64) {
+        Some(number)
+            if value
+                .chars()
+                .all(|c| c.is_ascii_digit() || "+-.eE".contains(c)) =>
+        {
+            Value::Number(number)
+        }
+        _ => Value::String(value),
+    }
+}
+
+fn timestamp(value: String) -> Value {
+    let parsed = DateTime::parse_from_rfc3339(&value)
+        .map(|time| time.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(&value, "%Y-%m-%d %H:%M:%S%.f").map(|time| time.and_utc())
+        });
+    match parsed {
+        Ok(time) => Value::String(time.to_rfc3339()),
+        Err(_) => Value::String(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::flatten_csv;
+
+    #[test]
+    fn parse_rows_with_header() {
+        let body = "time,host,status,latency,ok,zip,message\r\n\
+            2024-01-11 09:08:33,a,200,1.5,true,02134,\"said \"\"hi\"\", left\"\r\n\
+            2024-01-11T09:08:34Z,b,500,,false,10001,\"multi\nline\"\n\
+            \n\
+            too,few\n\
+            2024-01-11 09:08:35,c,\"404\",2,true,1,\"broken\"quote\n";
+
+        let (records, skipped) = flatten_csv(body, Some("time")).unwrap();
+
+        assert_eq!(skipped, 2);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["timestamp"], "2024-01-11T09:08:33+00:00");
+        assert_eq!(records[0]["status"], json!(200));
+        assert_eq!(records[0]["latency"], json!(1.5));
+        assert_eq!(records[0]["ok"], Value::Bool(true));
+        assert_eq!(records[0]["zip"], "02134");
+        assert_eq!(records[0]["message"], "said \"hi\", left");
+        assert!(!records[0].contains_key("time"));
+        assert_eq!(records[1]["timestamp"], "2024-01-11T09:08:34+00:00");
+        assert!(!records[1].contains_key("latency"));
+        assert_eq!(records[1]["message"], "multi\nline");
+    }
+
+    #[test]
+    fn quoted_values_stay_strings() {
+        let (records, skipped) = flatten_csv("id,name\n\"1\",\"true\"\n2,x", None).unwrap();
+        assert_eq!(skipped, 0);
+        assert_eq!(records[0]["id"], "1");
+        assert_eq!(records[0]["name"], "true");
+        assert_eq!(records[1]["id"], json!(2));
+    }
+
+    #[test]
+    fn bad_header_is_err() {
+        assert!(flatten_csv("a,,b\n1,2,3", None).is_err());
+        assert!(flatten_csv("a,b\n1,2", Some("time")).is_err());
+        assert!(flatten_csv("\"a,b\n1,2", None).is_err());
+    }
+}
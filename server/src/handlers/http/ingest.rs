@@ -16,93 +16,1635 @@
  *
  */
 
-use actix_web::{http::header::ContentType, HttpRequest, HttpResponse};
-use arrow_schema::Field;
-use bytes::Bytes;
+use actix_multipart::Multipart;
+use actix_web::dev::{self, Decompress};
+use actix_web::http::header::{self, ContentType, HeaderMap};
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use arrow_array::cast::AsArray;
+use arrow_array::types::TimestampMillisecondType;
+use arrow_array::{RecordBatch, TimestampMillisecondArray};
+use arrow_ipc::reader::StreamReader;
+use arrow_schema::{DataType, Field, TimeUnit};
+use bytes::{Bytes, BytesMut};
+use chrono::Utc;
+use datafusion::arrow::compute::cast;
+use futures_util::TryStreamExt;
 use http::StatusCode;
+use itertools::Itertools;
 use serde_json::Value;
 use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Instant;
 
+use crate::dedup::REPEATS;
+use crate::event::attributes::AttributeMap;
 use crate::event::error::EventError;
 use crate::event::format::EventFormat;
-use crate::event::{self, format};
+use crate::event::numbers::NumberMode;
+use crate::event::raw::{Original, RawPayload};
+use crate::event::receipts::{self, Receipt};
+use crate::event::routing::{Route, RoutedFrom, Routing};
+use crate::event::schema_lock::SchemaLock;
+use crate::event::severity::SeverityMapping;
+use crate::event::shadow::Shadow;
+use crate::event::skew::Correction;
+use crate::event::trace::{self, IngestTrace};
+use crate::event::type_conflict::TypeConflict;
+use crate::event::{self, format, DEFAULT_TIMESTAMP_KEY};
 use crate::handlers::{
-    LOG_SOURCE_KEY, LOG_SOURCE_KINESIS, LOG_SOURCE_OTEL, PREFIX_META, PREFIX_TAGS, SEPARATOR,
-    STREAM_NAME_HEADER_KEY,
+    INGEST_KEY_HEADER_KEY, LOG_SOURCE_ARROW, LOG_SOURCE_CSV, LOG_SOURCE_JSON, LOG_SOURCE_KEY,
+    LOG_SOURCE_KINESIS, LOG_SOURCE_LOKI, LOG_SOURCE_OTEL, LOG_SOURCE_OTEL_LINES,
+    LOG_SOURCE_PROMETHEUS, LOG_SOURCE_TEXT, LOG_SOURCE_VECTOR, LOG_SOURCE_W3C, PREFIX_META,
+    PREFIX_TAGS, RECEIPTS_HEADER_KEY, SEPARATOR, STREAM_NAME_HEADER_KEY, TIMESTAMP_COLUMN_KEY,
+    TRACE_HEADER_KEY, W3C_FIELDS_KEY,
 };
-use crate::metadata::STREAM_INFO;
+use crate::metadata::{LogStreamMetadata, STREAM_INFO};
+use crate::metrics::{
+    CLOCK_SKEW_CORRECTIONS, DROPPED_ATTRIBUTES, FILTERED_ATTRIBUTES, INGEST_REQUESTS_TOTAL,
+    INGEST_REQUEST_DURATION_SECONDS, INVALID_IP_VALUES, MALFORMED_CSV_ROWS, MALFORMED_OTLP_FRAMES,
+    OVERSIZED_RAW_PAYLOADS, OVERSIZED_REQUESTS, SHADOW_INGEST_ERRORS, UNKNOWN_OTEL_TIMESTAMPS,
+    UNKNOWN_SEVERITY_LEVELS, UNKNOWN_SEVERITY_NUMBERS, UNMATCHED_LOG_LINES,
+};
+use crate::option::CONFIG;
+use crate::quota::{self, Overflow};
+use crate::rbac::role::Action;
+use crate::rbac::{self, ingest_key, Users};
+use crate::rebuild::{self, SOURCE_TIMESTAMP_KEY};
+use crate::sampling::{Labels, Sample, RESERVOIRS};
+use crate::utils;
+use crate::utils::actix::extract_session_key_from_req;
 use crate::utils::header_parsing::{collect_labelled_headers, ParseHeaderError};
+use crate::utils::json::{flatten_json_body, limit_attributes};
 
+use super::csv;
 use super::kinesis;
-use super::logstream::error::CreateStreamError;
+use super::logstream::create_stream_with_template;
+use super::logstream::error::{CreateStreamError, StreamError};
+use super::loki;
+use super::otel;
+use super::prometheus;
+use super::text;
+use super::vector;
+use super::w3c;
 
 // Handler for POST /api/v1/ingest
 // ingests events by extracting stream name from header
 // creates if stream does not exist
-pub async fn ingest(req: HttpRequest, body: Bytes) -> Result<HttpResponse, PostError> {
-    if let Some((_, stream_name)) = req
-        .headers()
-        .iter()
-        .find(|&(key, _)| key == STREAM_NAME_HEADER_KEY)
-    {
+pub async fn ingest(
+    req: HttpRequest,
+    EventBody(body): EventBody,
+) -> Result<HttpResponse, PostError> {
+    let stream_name = stream_name_from_header(&req).unwrap_or_default();
+    let source = log_source_label(req.headers());
+    observe_ingest(&stream_name, source, async {
+        if let Some((_, stream_name)) = req
+            .headers()
+            .iter()
+            .find(|&(key, _)| key == STREAM_NAME_HEADER_KEY)
+        {
+            let stream_name = stream_name.to_str().unwrap().to_owned();
+            create_stream_if_not_exists(&stream_name).await?;
+
+            push_and_respond(req, body, stream_name).await
+        } else {
+            Err(PostError::Header(ParseHeaderError::MissingStreamName))
+        }
+    })
+    .await
+}
+
+// Handler for POST /api/v1/ingest/vector
+// ingests length delimited protobuf events sent by Vector's native sink,
+// stream name is extracted from header and the stream is created if it does not exist
+pub async fn ingest_vector(
+    req: HttpRequest,
+    EventBody(body): EventBody,
+) -> Result<HttpResponse, PostError> {
+    let stream_name = stream_name_from_header(&req).unwrap_or_default();
+    observe_ingest(&stream_name, LOG_SOURCE_VECTOR, async {
+        let Some((_, stream_name)) = req
+            .headers()
+            .iter()
+            .find(|&(key, _)| key == STREAM_NAME_HEADER_KEY)
+        else {
+            return Err(PostError::Header(ParseHeaderError::MissingStreamName));
+        };
         let stream_name = stream_name.to_str().unwrap().to_owned();
         create_stream_if_not_exists(&stream_name).await?;
 
-        flatten_and_push_logs(req, body, stream_name).await?;
+        let mut records =
+            vector::flatten_vector_events(&body).map_err(|err| PostError::Invalid(err.into()))?;
+        keep_raw_records(&stream_name, &mut records, &body)?;
+        if !records.is_empty() {
+            let body: Bytes = serde_json::to_vec(&records)?.into();
+            push_logs(stream_name, req, body).await?;
+        }
         Ok(HttpResponse::Ok().finish())
+    })
+    .await
+}
+
+// Handler for POST /api/v1/ingest/otel/stream
+// ingests a stream of length delimited protobuf OTLP logs, `LogsData` or
+// `ExportLogsServiceRequest` messages, sent on one long running request by
+// collectors. Every message is ingested as soon as it has arrived. Messages
+// which fail to decode or are larger than the maximum request size are skipped
+// and counted, only a corrupt message length ends the request. Stream name is
+// extracted from header and the stream is created if it does not exist
+pub async fn ingest_otel_stream(
+    req: HttpRequest,
+    payload: web::Payload,
+) -> Result<HttpResponse, PostError> {
+    let stream_name = stream_name_from_header(&req).unwrap_or_default();
+    observe_ingest(&stream_name, LOG_SOURCE_OTEL, async {
+        let Some(stream_name) = stream_name_from_header(&req) else {
+            return Err(PostError::Header(ParseHeaderError::MissingStreamName));
+        };
+        create_stream_if_not_exists(&stream_name).await?;
+
+        let mut payload = Decompress::from_headers(payload.into_inner(), req.headers());
+        let mut frames = otel::FrameDecoder::new(CONFIG.parseable.max_request_size);
+        let (mut ingested, mut skipped) = (0, 0);
+        while let Some(chunk) = payload
+            .try_next()
+            .await
+            .map_err(|err| PostError::Invalid(anyhow::anyhow!(err.to_string())))?
+        {
+            frames.extend(&chunk);
+            while let Some(frame) = frames
+                .next_frame()
+                .map_err(|err| PostError::Invalid(err.into()))?
+            {
+                let message = match frame {
+                    otel::Frame::Message(message) => message,
+                    otel::Frame::Oversized(len) => {
+                        log::warn!("skipped OTLP message of {len} bytes for stream {stream_name}, larger than the maximum request size");
+                        skipped += 1;
+                        continue;
+                    }
+                };
+                let unknown_time = CONFIG.parseable.otel_unknown_time;
+                let (records, unknown) = match otel::flatten_otel_logs_protobuf(&message, unknown_time) {
+                    Ok(flattened) => flattened,
+                    Err(err) => {
+                        log::warn!("skipped malformed OTLP message for stream {stream_name}: {err}");
+                        skipped += 1;
+                        continue;
+                    }
+                };
+                let mut records = prepare_otel_records(&stream_name, records, unknown)?;
+                keep_raw_records(&stream_name, &mut records, &message)?;
+                if !records.is_empty() {
+                    let body: Bytes = serde_json::to_vec(&records)?.into();
+                    push_logs(stream_name.clone(), req.clone(), body).await?;
+                }
+                ingested += 1;
+            }
+        }
+        if frames.is_truncated() {
+            skipped += 1;
+        }
+        if skipped > 0 {
+            MALFORMED_OTLP_FRAMES
+                .with_label_values(&[&stream_name])
+                .inc_by(skipped);
+        }
+        Ok(HttpResponse::Ok().json(serde_json::json!({
+            "ingested": ingested,
+            "skipped": skipped,
+        })))
+    })
+    .await
+}
+
+// Handler for POST /api/v1/ingest/arrow
+// ingests record batches in the Arrow IPC stream format, stored without being
+// flattened or going through the rules of the stream for JSON events, streams
+// with such rules reject them. The quota of the stream applies to their rows.
+// Batches are checked against the schema of the stream before any is stored. Stream
+// name is extracted from header and the stream is created if it does not exist
+pub async fn ingest_arrow(
+    req: HttpRequest,
+    EventBody(body): EventBody,
+) -> Result<HttpResponse, PostError> {
+    let stream_name = stream_name_from_header(&req).unwrap_or_default();
+    observe_ingest(&stream_name, LOG_SOURCE_ARROW, async {
+        let Some(stream_name) = stream_name_from_header(&req) else {
+            return Err(PostError::Header(ParseHeaderError::MissingStreamName));
+        };
+        create_stream_if_not_exists(&stream_name).await?;
+
+        let labels = Labels {
+            tags: collect_labelled_headers(&req, PREFIX_TAGS, SEPARATOR)?,
+            metadata: collect_labelled_headers(&req, PREFIX_META, SEPARATOR)?,
+        };
+        let batches = StreamReader::try_new(body.as_ref(), None)
+            .and_then(|reader| reader.collect::<Result<Vec<_>, _>>())
+            .map_err(|err| {
+                PostError::Invalid(anyhow::anyhow!("invalid Arrow IPC stream: {err}"))
+            })?;
+        let events = arrow_events(&stream_name, &labels, batches)?;
+        let received: usize = events.iter().map(|(rb, _)| rb.num_rows()).sum();
+        let events = enforce_arrow_quota(&stream_name, events)?;
+
+        let rows: usize = events.iter().map(|(rb, _)| rb.num_rows()).sum();
+        for (rb, is_first_event) in events {
+            // the size of the request is shared by the batches as per their rows
+            let origin_size = (body.len() * rb.num_rows() / received) as u64;
+            event::Event {
+                rb,
+                stream_name: stream_name.clone(),
+                origin_format: LOG_SOURCE_ARROW,
+                origin_size,
+                is_first_event,
+                replayed: false,
+            }
+            .process()
+            .await?;
+        }
+        Ok(HttpResponse::Ok().json(serde_json::json!({ "ingested": rows })))
+    })
+    .await
+}
+
+// Quota of the stream applied to Arrow batches like `enforce_quota` does to
+// JSON events. Sampled batches adding columns to the stream are kept even
+// without rows, so that the columns are added to the stream.
+fn enforce_arrow_quota(
+    stream_name: &str,
+    events: Vec<(RecordBatch, bool)>,
+) -> Result<Vec<(RecordBatch, bool)>, PostError> {
+    let Some(quota) = STREAM_INFO
+        .quota(stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.to_string()))?
+    else {
+        return Ok(events);
+    };
+    if !quota.exceeded(quota::usage(stream_name)) {
+        return Ok(events);
+    }
+
+    match quota.overflow {
+        Overflow::Reject => Err(PostError::QuotaExceeded(stream_name.to_string())),
+        Overflow::Sample => {
+            let mut sampled = Vec::with_capacity(events.len());
+            for (rb, is_first_event) in events {
+                let rb = quota
+                    .sample_rows(&rb)
+                    .map_err(|err| PostError::Invalid(err.into()))?;
+                if rb.num_rows() > 0 || is_first_event {
+                    sampled.push((rb, is_first_event));
+                }
+            }
+            Ok(sampled)
+        }
+    }
+}
+
+// Record batches with the columns of the stream added, batches adding columns
+// to the stream are checked against the ones before them
+fn arrow_events(
+    stream_name: &str,
+    labels: &Labels,
+    batches: Vec<RecordBatch>,
+) -> Result<Vec<(RecordBatch, bool)>, PostError> {
+    let hash_map = STREAM_INFO.read().unwrap();
+    let metadata = hash_map
+        .get(stream_name)
+        .ok_or(PostError::StreamNotFound(stream_name.to_string()))?;
+    let json_rules = [
+        (metadata.ip_mask.is_some(), "masks IP addresses of"),
+        (metadata.sampling.is_some(), "samples"),
+        (metadata.dedup.is_some(), "collapses repeats of"),
+        (metadata.attribute_filter.is_some(), "filters attributes of"),
+    ];
+    if let Some((_, rule)) = json_rules.iter().find(|(set, _)| *set) {
+        return Err(PostError::Invalid(anyhow::anyhow!(
+            "stream {stream_name} {rule} JSON events, Arrow batches are stored as they are"
+        )));
+    }
+    let timestamp_key = metadata
+        .timestamp_key
+        .as_deref()
+        .unwrap_or(DEFAULT_TIMESTAMP_KEY);
+
+    let mut schema = metadata.schema.clone();
+    let mut events = Vec::with_capacity(batches.len());
+    for rb in batches.into_iter().filter(|rb| rb.num_rows() > 0) {
+        if metadata.schema_lock.is_some() {
+            let new = rb
+                .schema()
+                .fields()
+                .iter()
+                .map(|field| field.name())
+                .filter(|name| !metadata.schema.contains_key(*name))
+                .join(", ");
+            if !new.is_empty() {
+                return Err(PostError::SchemaLocked(new));
+            }
+        }
+        let (rb, is_first) = format::arrow::Event {
+            rb,
+            tags: labels.tags.clone(),
+            metadata: labels.metadata.clone(),
+        }
+        .into_recordbatch(schema.clone(), timestamp_key)?;
+        if is_first {
+            schema.extend(
+                rb.schema()
+                    .fields()
+                    .iter()
+                    .map(|field| (field.name().clone(), field.clone())),
+            );
+        }
+        events.push((rb, is_first));
+    }
+    Ok(events)
+}
+
+// Handler for POST /api/v1/loki/api/v1/push
+// ingests streams pushed by Loki clients such as Promtail, snappy compressed
+// protobuf or JSON when the content type is application/json. Stream name is
+// extracted from header and the stream is created if it does not exist
+pub async fn ingest_loki(
+    req: HttpRequest,
+    EventBody(body): EventBody,
+) -> Result<HttpResponse, PostError> {
+    let stream_name = stream_name_from_header(&req).unwrap_or_default();
+    observe_ingest(&stream_name, LOG_SOURCE_LOKI, async {
+        let Some((_, stream_name)) = req
+            .headers()
+            .iter()
+            .find(|&(key, _)| key == STREAM_NAME_HEADER_KEY)
+        else {
+            return Err(PostError::Header(ParseHeaderError::MissingStreamName));
+        };
+        let stream_name = stream_name.to_str().unwrap().to_owned();
+        create_stream_if_not_exists(&stream_name).await?;
+
+        let is_json = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("application/json"));
+        let mut records = if is_json {
+            loki::flatten_loki_json(&body)
+        } else {
+            loki::flatten_loki_protobuf(&body)
+        }
+        .map_err(PostError::Invalid)?;
+        keep_raw_records(&stream_name, &mut records, &body)?;
+        if !records.is_empty() {
+            let body: Bytes = serde_json::to_vec(&records)?.into();
+            push_logs(stream_name, req, body).await?;
+        }
+        Ok(HttpResponse::NoContent().finish())
+    })
+    .await
+}
+
+// Handler for POST /api/v1/write
+// ingests samples sent by Prometheus remote write, snappy compressed protobuf,
+// one event per sample. Stream name is extracted from header and the stream is
+// created if it does not exist
+pub async fn remote_write(
+    req: HttpRequest,
+    EventBody(body): EventBody,
+) -> Result<HttpResponse, PostError> {
+    let stream_name = stream_name_from_header(&req).unwrap_or_default();
+    observe_ingest(&stream_name, LOG_SOURCE_PROMETHEUS, async {
+        let Some((_, stream_name)) = req
+            .headers()
+            .iter()
+            .find(|&(key, _)| key == STREAM_NAME_HEADER_KEY)
+        else {
+            return Err(PostError::Header(ParseHeaderError::MissingStreamName));
+        };
+        let stream_name = stream_name.to_str().unwrap().to_owned();
+        create_stream_if_not_exists(&stream_name).await?;
+
+        let mut records = prometheus::flatten_remote_write(&body).map_err(PostError::Invalid)?;
+        keep_raw_records(&stream_name, &mut records, &body)?;
+        if !records.is_empty() {
+            let body: Bytes = serde_json::to_vec(&records)?.into();
+            push_logs(stream_name, req, body).await?;
+        }
+        Ok(HttpResponse::NoContent().finish())
+    })
+    .await
+}
+
+// Handler for POST /api/v1/ingest/multipart
+// ingests every part of a multipart/form-data request independently.
+// A part is ingested like a request to /ingest with the part's X-P-Stream,
+// X-P-Log-Source and Content-Type headers, the stream falls back to the
+// X-P-Stream header of the request. Parts for streams with ingest keys are
+// authorized by the X-P-Ingest-Key header of the request. Responds with the
+// result of every part. All parts are read before any is ingested, so a
+// request over the size limit is rejected without storing any of its parts.
+pub async fn ingest_multipart(
+    req: HttpRequest,
+    mut payload: Multipart,
+) -> Result<HttpResponse, PostError> {
+    let key = extract_session_key_from_req(&req)
+        .map_err(|err| PostError::Invalid(anyhow::anyhow!(err.to_string())))?;
+    let default_stream = stream_name_from_header(&req);
+    let ingest_key = req
+        .headers()
+        .get(INGEST_KEY_HEADER_KEY)
+        .and_then(|value| value.to_str().ok());
+    let parts = read_parts(
+        &mut payload,
+        default_stream.as_deref(),
+        CONFIG.parseable.max_request_size,
+    )
+    .await?;
+
+    let mut results = Vec::new();
+    for part in parts {
+        let stream_name = part.stream_name;
+        let result = observe_ingest(
+            stream_name.as_deref().unwrap_or_default(),
+            part.source,
+            async {
+                let Some(stream_name) = stream_name.clone() else {
+                    return Err(PostError::Header(ParseHeaderError::MissingStreamName));
+                };
+                let authorized = match ingest_key::authorize(&stream_name, ingest_key) {
+                    Some(authorized) => authorized,
+                    None => matches!(
+                        Users.authorize(key.clone(), Action::Ingest, Some(&stream_name), None),
+                        rbac::Response::Authorized
+                    ),
+                };
+                if !authorized {
+                    return Err(PostError::Unauthorized(stream_name));
+                }
+                create_stream_if_not_exists(&stream_name).await?;
+                flatten_and_push(
+                    req.clone(),
+                    part.body,
+                    stream_name,
+                    part.log_source.as_deref(),
+                    part.is_protobuf,
+                )
+                .await?;
+                Ok(HttpResponse::Ok().finish())
+            },
+        )
+        .await;
+
+        results.push(PartResult::new(part.name, stream_name, result));
+    }
+
+    Ok(HttpResponse::build(multipart_status(&results)).json(results))
+}
+
+// Read every part of a multipart request, failing once the parts together
+// are larger than the limit
+async fn read_parts(
+    payload: &mut Multipart,
+    default_stream: Option<&str>,
+    limit: usize,
+) -> Result<Vec<Part>, PostError> {
+    let mut parts = Vec::new();
+    let mut size = 0;
+
+    while let Some(mut field) = payload
+        .try_next()
+        .await
+        .map_err(|err| PostError::Invalid(anyhow::anyhow!(err.to_string())))?
+    {
+        let mut body = BytesMut::new();
+        while let Some(chunk) = field
+            .try_next()
+            .await
+            .map_err(|err| PostError::Invalid(anyhow::anyhow!(err.to_string())))?
+        {
+            size += chunk.len();
+            if size > limit {
+                OVERSIZED_REQUESTS
+                    .with_label_values(&[stream_label(default_stream.unwrap_or_default())])
+                    .inc();
+                return Err(PostError::PayloadTooLarge(limit));
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        let headers = field.headers();
+        parts.push(Part {
+            name: field.name().to_owned(),
+            stream_name: part_stream_name(headers, default_stream),
+            log_source: headers
+                .get(LOG_SOURCE_KEY)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned),
+            source: log_source_label(headers),
+            is_protobuf: is_protobuf(headers),
+            body: body.freeze(),
+        });
+    }
+
+    Ok(parts)
+}
+
+// A part of a multipart ingest request, read in full
+struct Part {
+    name: String,
+    stream_name: Option<String>,
+    log_source: Option<String>,
+    source: &'static str,
+    is_protobuf: bool,
+    body: Bytes,
+}
+
+// Stream of a part, from its own X-P-Stream header or else the request's
+fn part_stream_name(headers: &HeaderMap, default_stream: Option<&str>) -> Option<String> {
+    headers
+        .get(STREAM_NAME_HEADER_KEY)
+        .and_then(|value| value.to_str().ok())
+        .or(default_stream)
+        .map(str::to_owned)
+}
+
+// 200 when every part was ingested, 207 when any part failed
+fn multipart_status(results: &[PartResult]) -> StatusCode {
+    if results.iter().all(|result| result.error.is_none()) {
+        StatusCode::OK
     } else {
-        Err(PostError::Header(ParseHeaderError::MissingStreamName))
+        StatusCode::MULTI_STATUS
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PartResult {
+    part: String,
+    stream: Option<String>,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl PartResult {
+    fn new(part: String, stream: Option<String>, result: Result<HttpResponse, PostError>) -> Self {
+        let (status, error) = match result {
+            Ok(response) => (response.status(), None),
+            Err(err) => (err.status_code(), Some(err.to_string())),
+        };
+        Self {
+            part,
+            stream,
+            status: status.as_u16(),
+            error,
+        }
+    }
+}
+
+fn stream_name_from_header(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(STREAM_NAME_HEADER_KEY)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
+// Body of an ingest request, decompressed as per its Content-Encoding.
+// The body is rejected with 413 as soon as the Content-Length or the
+// decompressed bytes read so far exceed the maximum request size, so an
+// oversized request is never buffered in full.
+pub struct EventBody(pub Bytes);
+
+impl FromRequest for EventBody {
+    type Error = PostError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut dev::Payload) -> Self::Future {
+        let limit = CONFIG.parseable.max_request_size;
+        let stream_name = req
+            .match_info()
+            .get("logstream")
+            .map(str::to_owned)
+            .or_else(|| stream_name_from_header(req))
+            .unwrap_or_default();
+        let body = read_body(req, payload, limit);
+
+        Box::pin(async move {
+            let body = body.await;
+            if let Err(PostError::PayloadTooLarge(_)) = body {
+                OVERSIZED_REQUESTS
+                    .with_label_values(&[stream_label(&stream_name)])
+                    .inc();
+            }
+            body.map(EventBody)
+        })
     }
 }
 
+fn read_body(
+    req: &HttpRequest,
+    payload: &mut dev::Payload,
+    limit: usize,
+) -> impl Future<Output = Result<Bytes, PostError>> {
+    let content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    let mut stream = Decompress::from_headers(payload.take(), req.headers());
+
+    async move {
+        // a compressed body never shrinks when decompressed
+        if content_length.is_some_and(|length| length > limit) {
+            return Err(PostError::PayloadTooLarge(limit));
+        }
+
+        let mut body = BytesMut::new();
+        while let Some(chunk) = stream
+            .try_next()
+            .await
+            .map_err(|err| PostError::Invalid(anyhow::anyhow!(err.to_string())))?
+        {
+            if body.len() + chunk.len() > limit {
+                return Err(PostError::PayloadTooLarge(limit));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body.freeze())
+    }
+}
+
+// source label for ingest metrics, unknown sources are ingested as json
+// and are reported as such to keep the label cardinality bounded
+fn log_source_label(headers: &HeaderMap) -> &'static str {
+    let log_source = headers
+        .get(LOG_SOURCE_KEY)
+        .and_then(|value| value.to_str().ok());
+    match log_source {
+        Some(LOG_SOURCE_KINESIS) => LOG_SOURCE_KINESIS,
+        Some(LOG_SOURCE_OTEL) => LOG_SOURCE_OTEL,
+        Some(LOG_SOURCE_OTEL_LINES) => LOG_SOURCE_OTEL_LINES,
+        Some(LOG_SOURCE_TEXT) => LOG_SOURCE_TEXT,
+        Some(LOG_SOURCE_W3C) => LOG_SOURCE_W3C,
+        Some(LOG_SOURCE_CSV) => LOG_SOURCE_CSV,
+        Some(LOG_SOURCE_VECTOR) => LOG_SOURCE_VECTOR,
+        Some(LOG_SOURCE_LOKI) => LOG_SOURCE_LOKI,
+        Some(LOG_SOURCE_PROMETHEUS) => LOG_SOURCE_PROMETHEUS,
+        Some(LOG_SOURCE_ARROW) => LOG_SOURCE_ARROW,
+        _ => LOG_SOURCE_JSON,
+    }
+}
+
+// stream label for ingest metrics, requests for streams which don't exist are
+// reported under one label, which is not a valid stream name, so that stream
+// names sent in headers can't grow the label cardinality
+const UNKNOWN_STREAM_LABEL: &str = "_unknown";
+
+fn stream_label(stream_name: &str) -> &str {
+    if STREAM_INFO.stream_exists(stream_name) {
+        stream_name
+    } else {
+        UNKNOWN_STREAM_LABEL
+    }
+}
+
+// Record latency and response status of an ingest request.
+// The time covers the whole handler, parsing, flattening and buffering the events.
+// The stream is checked once the handler ran, as it may have created it
+async fn observe_ingest(
+    stream_name: &str,
+    source: &str,
+    handler: impl Future<Output = Result<HttpResponse, PostError>>,
+) -> Result<HttpResponse, PostError> {
+    let start = Instant::now();
+    let result = handler.await;
+    let status = match &result {
+        Ok(response) => response.status(),
+        Err(err) => err.status_code(),
+    };
+    let labels = [stream_label(stream_name), source, status.as_str()];
+    INGEST_REQUEST_DURATION_SECONDS
+        .with_label_values(&labels)
+        .observe(start.elapsed().as_secs_f64());
+    INGEST_REQUESTS_TOTAL.with_label_values(&labels).inc();
+    result
+}
+
+// Response to a request asking for a trace of the ingest stages, the trace is
+// returned whether the request failed or not
+#[derive(serde::Serialize)]
+struct TracedResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    receipts: Option<Vec<Receipt>>,
+    trace: IngestTrace,
+}
+
+// Ingest the events of a request. If asked for with the x-p-receipts header
+// the response lists the p_timestamp and object store directories every
+// accepted record was staged with, in the order of the records. Records held
+// back for deduplication or sampling are staged later and are not listed.
+// With the x-p-trace header the response is a trace of the ingest stages,
+// along with the receipts if asked for as well.
+async fn push_and_respond(
+    req: HttpRequest,
+    body: Bytes,
+    stream_name: String,
+) -> Result<HttpResponse, PostError> {
+    let wants_receipts = header_enabled(req.headers(), RECEIPTS_HEADER_KEY);
+    let wants_trace = header_enabled(req.headers(), TRACE_HEADER_KEY);
+    if !wants_trace {
+        if !wants_receipts {
+            flatten_and_push_logs(req, body, stream_name).await?;
+            return Ok(HttpResponse::Ok().finish());
+        }
+        let (result, receipts) =
+            receipts::collect(flatten_and_push_logs(req, body, stream_name)).await;
+        result?;
+        return Ok(HttpResponse::Ok().json(receipts));
+    }
+
+    let bytes_received = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+    let initial = IngestTrace::new(bytes_received, body.len() as u64);
+    let push = flatten_and_push_logs(req, body, stream_name);
+    let ((result, receipts), trace) = if wants_receipts {
+        trace::collect(initial, async {
+            let (result, receipts) = receipts::collect(push).await;
+            (result, Some(receipts))
+        })
+        .await
+    } else {
+        trace::collect(initial, async { (push.await, None) }).await
+    };
+
+    let status = match &result {
+        Ok(()) => StatusCode::OK,
+        Err(err) => err.status_code(),
+    };
+    Ok(HttpResponse::build(status).json(TracedResponse {
+        error: result.err().map(|err| err.to_string()),
+        receipts,
+        trace,
+    }))
+}
+
+fn header_enabled(headers: &HeaderMap, key: &str) -> bool {
+    headers
+        .get(key)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("true"))
+}
+
 async fn flatten_and_push_logs(
     req: HttpRequest,
     body: Bytes,
     stream_name: String,
+) -> Result<(), PostError> {
+    let log_source = req
+        .headers()
+        .get(LOG_SOURCE_KEY)
+        .map(|log_source| log_source.to_str().unwrap().to_owned());
+    let is_protobuf = is_protobuf(req.headers());
+    flatten_and_push(req, body, stream_name, log_source.as_deref(), is_protobuf).await
+}
+
+fn is_protobuf(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with(otel::CONTENT_TYPE_PROTOBUF))
+}
+
+async fn flatten_and_push(
+    req: HttpRequest,
+    body: Bytes,
+    stream_name: String,
+    log_source: Option<&str>,
+    is_protobuf: bool,
 ) -> Result<(), PostError> {
     //flatten logs
-    if let Some((_, log_source)) = req.headers().iter().find(|&(key, _)| key == LOG_SOURCE_KEY) {
+    if let Some(log_source) = log_source {
         let mut json: Vec<BTreeMap<String, Value>> = Vec::new();
-        let log_source: String = log_source.to_str().unwrap().to_owned();
-        match log_source.as_str() {
+        match log_source {
             LOG_SOURCE_KINESIS => json = kinesis::flatten_kinesis_logs(&body),
-            LOG_SOURCE_OTEL => {}
+            LOG_SOURCE_OTEL => {
+                let unknown_time = CONFIG.parseable.otel_unknown_time;
+                let (records, unknown) = if is_protobuf {
+                    otel::flatten_otel_logs_protobuf(&body, unknown_time)
+                        .map_err(|err| PostError::Invalid(err.into()))?
+                } else {
+                    otel::flatten_otel_logs(&body, unknown_time)?
+                };
+                json = prepare_otel_records(&stream_name, records, unknown)?;
+            }
+            LOG_SOURCE_OTEL_LINES => {
+                let body =
+                    std::str::from_utf8(&body).map_err(|err| PostError::Invalid(err.into()))?;
+                let (records, unknown) =
+                    otel::flatten_otel_lines(body, CONFIG.parseable.otel_unknown_time)
+                        .map_err(PostError::Invalid)?;
+                json = prepare_otel_records(&stream_name, records, unknown)?;
+            }
+            LOG_SOURCE_TEXT => {
+                let pattern = STREAM_INFO
+                    .log_pattern(&stream_name)
+                    .map_err(|_| PostError::StreamNotFound(stream_name.clone()))?
+                    .map(|pattern| text::cached_pattern(&pattern))
+                    .transpose()
+                    .map_err(|err| PostError::Invalid(anyhow::anyhow!(err)))?;
+                let body =
+                    std::str::from_utf8(&body).map_err(|err| PostError::Invalid(err.into()))?;
+                let (mut records, unmatched) = text::flatten_text_logs(body, pattern.as_ref());
+                keep_raw_records(&stream_name, &mut records, body.as_bytes())?;
+                if unmatched > 0 {
+                    UNMATCHED_LOG_LINES
+                        .with_label_values(&[&stream_name])
+                        .inc_by(unmatched as u64);
+                    trace::record(|trace| trace.drop_records(trace::UNMATCHED_LINE, unmatched));
+                }
+                if !records.is_empty() {
+                    let body: Bytes = serde_json::to_vec(&records).unwrap().into();
+                    push_logs(stream_name.to_string(), req.clone(), body).await?;
+                }
+            }
+            LOG_SOURCE_W3C => {
+                let fields = req
+                    .headers()
+                    .get(W3C_FIELDS_KEY)
+                    .and_then(|value| value.to_str().ok());
+                let body =
+                    std::str::from_utf8(&body).map_err(|err| PostError::Invalid(err.into()))?;
+                let (mut records, unmatched) = w3c::flatten_w3c_logs(body, fields);
+                keep_raw_records(&stream_name, &mut records, body.as_bytes())?;
+                map_record_severity(&stream_name, &mut records, Some(w3c::STATUS_KEY))?;
+                if unmatched > 0 {
+                    UNMATCHED_LOG_LINES
+                        .with_label_values(&[&stream_name])
+                        .inc_by(unmatched as u64);
+                    trace::record(|trace| trace.drop_records(trace::UNMATCHED_LINE, unmatched));
+                }
+                if !records.is_empty() {
+                    let body: Bytes = serde_json::to_vec(&records).unwrap().into();
+                    push_logs(stream_name.to_string(), req.clone(), body).await?;
+                }
+            }
+            LOG_SOURCE_CSV => {
+                let timestamp_column = req
+                    .headers()
+                    .get(TIMESTAMP_COLUMN_KEY)
+                    .and_then(|value| value.to_str().ok());
+                let body =
+                    std::str::from_utf8(&body).map_err(|err| PostError::Invalid(err.into()))?;
+                let (mut records, skipped) = csv::flatten_csv(body, timestamp_column)
+                    .map_err(|err| PostError::Invalid(anyhow::anyhow!(err)))?;
+                keep_raw_records(&stream_name, &mut records, body.as_bytes())?;
+                if skipped > 0 {
+                    MALFORMED_CSV_ROWS
+                        .with_label_values(&[&stream_name])
+                        .inc_by(skipped as u64);
+                    trace::record(|trace| trace.drop_records(trace::MALFORMED_CSV_ROW, skipped));
+                }
+                if !records.is_empty() {
+                    let body: Bytes = serde_json::to_vec(&records).unwrap().into();
+                    push_logs(stream_name.to_string(), req.clone(), body).await?;
+                }
+            }
             _ => {
                 log::warn!("Unknown log source: {}", log_source);
+                let body = keep_raw_payloads(&stream_name, body.clone())?;
+                let body = apply_severity_mapping(&stream_name, body)?;
                 push_logs(stream_name.to_string(), req.clone(), body).await?;
             }
         }
+        keep_raw_records(&stream_name, &mut json, &body)?;
         for record in json.iter_mut() {
             let body: Bytes = serde_json::to_vec(record).unwrap().into();
             push_logs(stream_name.to_string(), req.clone(), body).await?;
         }
     } else {
+        let body = keep_raw_payloads(&stream_name, body)?;
+        let body = apply_severity_mapping(&stream_name, body)?;
         push_logs(stream_name.to_string(), req, body).await?;
     }
     Ok(())
 }
 
+// Settings of the stream applied to flattened OTLP log records, of which
+// `unknown` had an unknown time
+fn prepare_otel_records(
+    stream_name: &str,
+    mut records: Vec<BTreeMap<String, Value>>,
+    unknown: usize,
+) -> Result<Vec<BTreeMap<String, Value>>, PostError> {
+    count_unknown_timestamps(stream_name, unknown);
+    correct_clock_skew(stream_name, &mut records)?;
+    handle_unknown_severity(stream_name, &mut records)?;
+    if let Some(config) = STREAM_INFO
+        .body_config(stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.to_string()))?
+    {
+        records.iter_mut().for_each(|record| config.apply(record));
+    }
+    map_record_severity(stream_name, &mut records, None)?;
+    Ok(records)
+}
+
+// Count OTLP records with a time of 0 or before the epoch, replaced on ingestion
+fn count_unknown_timestamps(stream_name: &str, unknown: usize) {
+    if unknown > 0 {
+        UNKNOWN_OTEL_TIMESTAMPS
+            .with_label_values(&[stream_name])
+            .inc_by(unknown as u64);
+    }
+}
+
+// Times of OTLP records are corrected for the clock skew of their sources,
+// corrections beyond the maximum of the stream are counted and logged instead
+fn correct_clock_skew(
+    stream_name: &str,
+    records: &mut [BTreeMap<String, Value>],
+) -> Result<(), PostError> {
+    let Some(skew) = STREAM_INFO
+        .clock_skew(stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.to_string()))?
+    else {
+        return Ok(());
+    };
+
+    let received = Utc::now().timestamp_nanos_opt().unwrap_or_default() as i128;
+    let (mut applied, mut skipped) = (0, 0);
+    // largest of the corrections not applied
+    let mut largest = 0;
+    for record in records.iter_mut() {
+        match skew.apply(record, received) {
+            Correction::None => (),
+            Correction::Applied(_) => applied += 1,
+            Correction::Skipped(correction) => {
+                skipped += 1;
+                largest = largest.max(correction.abs());
+            }
+        }
+    }
+    if applied > 0 {
+        CLOCK_SKEW_CORRECTIONS
+            .with_label_values(&[stream_name, "applied"])
+            .inc_by(applied);
+        log::debug!(
+            "corrected the time of {applied} records of stream {stream_name} for clock skew"
+        );
+    }
+    if skipped > 0 {
+        CLOCK_SKEW_CORRECTIONS
+            .with_label_values(&[stream_name, "skipped"])
+            .inc_by(skipped);
+        log::warn!(
+            "kept the time of {skipped} records of stream {stream_name}, correcting them for clock skew takes up to {} ms which is more than the max correction of {} ms",
+            largest / 1_000_000,
+            skew.max_correction_ms
+        );
+    }
+    Ok(())
+}
+
+// Severity numbers out of range are stored as configured for the stream
+fn handle_unknown_severity(
+    stream_name: &str,
+    records: &mut [BTreeMap<String, Value>],
+) -> Result<(), PostError> {
+    let unknown_severity = STREAM_INFO
+        .unknown_severity(stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.to_string()))?;
+    let unknown = records
+        .iter_mut()
+        .map(|record| unknown_severity.apply(record))
+        .filter(|known| !known)
+        .count();
+    if unknown > 0 {
+        UNKNOWN_SEVERITY_NUMBERS
+            .with_label_values(&[stream_name])
+            .inc_by(unknown as u64);
+    }
+    Ok(())
+}
+
+// Severity of OTLP records sent without one is taken from the level field of
+// the severity mapping, if the stream has one. Records always carrying an HTTP
+// status code in `status_field` take their severity from it otherwise.
+fn map_record_severity(
+    stream_name: &str,
+    records: &mut [BTreeMap<String, Value>],
+    status_field: Option<&str>,
+) -> Result<(), PostError> {
+    let mapping = STREAM_INFO
+        .severity_mapping(stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.to_string()))?;
+    let mapping = match (mapping, status_field) {
+        (mapping, Some(field)) => SeverityMapping::with_status(mapping, field),
+        (Some(mapping), None) => mapping,
+        (None, None) => return Ok(()),
+    };
+    let unknown = records
+        .iter_mut()
+        .map(|record| mapping.apply_record(record))
+        .filter(|known| !known)
+        .count();
+    if unknown > 0 {
+        UNKNOWN_SEVERITY_LEVELS
+            .with_label_values(&[stream_name])
+            .inc_by(unknown as u64);
+    }
+    Ok(())
+}
+
+// Events of a JSON body keep the text they were sent as in the `_raw` column
+// if the stream stores raw payloads. Applied before the body is changed in any way.
+fn keep_raw_payloads(stream_name: &str, body: Bytes) -> Result<Bytes, PostError> {
+    let Some(raw) = raw_payload(stream_name)? else {
+        return Ok(body);
+    };
+
+    let original = Original::json(&body)?;
+    let mut json: Value = serde_json::from_slice(&body)?;
+    count_oversized_raw_payloads(stream_name, raw.apply(&mut json, &original));
+    Ok(serde_json::to_vec(&json)?.into())
+}
+
+// Records parsed from a body in any other format keep the body in the `_raw`
+// column if the stream stores raw payloads
+fn keep_raw_records(
+    stream_name: &str,
+    records: &mut [BTreeMap<String, Value>],
+    body: &[u8],
+) -> Result<(), PostError> {
+    if let Some(raw) = raw_payload(stream_name)? {
+        let oversized = raw.apply_records(records, &Original::body(body));
+        count_oversized_raw_payloads(stream_name, oversized);
+    }
+    Ok(())
+}
+
+fn raw_payload(stream_name: &str) -> Result<Option<RawPayload>, PostError> {
+    STREAM_INFO
+        .raw_payload(stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.to_string()))
+}
+
+fn count_oversized_raw_payloads(stream_name: &str, oversized: usize) {
+    if oversized > 0 {
+        OVERSIZED_RAW_PAYLOADS
+            .with_label_values(&[stream_name])
+            .inc_by(oversized as u64);
+    }
+}
+
+// Add severity columns to plain JSON events if the stream has a severity mapping
+fn apply_severity_mapping(stream_name: &str, body: Bytes) -> Result<Bytes, PostError> {
+    let Some(mapping) = STREAM_INFO
+        .severity_mapping(stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.to_string()))?
+    else {
+        return Ok(body);
+    };
+
+    let mut json: Value = serde_json::from_slice(&body)?;
+    let unknown = match &mut json {
+        Value::Object(event) => usize::from(!mapping.apply(event)),
+        Value::Array(events) => events
+            .iter_mut()
+            .filter_map(Value::as_object_mut)
+            .map(|event| mapping.apply(event))
+            .filter(|known| !known)
+            .count(),
+        _ => 0,
+    };
+    if unknown > 0 {
+        UNKNOWN_SEVERITY_LEVELS
+            .with_label_values(&[stream_name])
+            .inc_by(unknown as u64);
+    }
+    Ok(serde_json::to_vec(&json)?.into())
+}
+
+// Rename keys of the events to identifiers if the stream sanitizes them,
+// persisting the columns of keys seen for the first time
+async fn sanitize_keys(stream_name: &str, body: Bytes) -> Result<Bytes, PostError> {
+    let sanitized = STREAM_INFO
+        .key_sanitization(stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.to_string()))?;
+    if sanitized.is_none() {
+        return Ok(body);
+    }
+
+    let mut json = flatten_json_body(serde_json::from_slice(&body)?)?;
+    let changed = STREAM_INFO
+        .sanitize_keys(stream_name, &mut json)
+        .map_err(|_| PostError::StreamNotFound(stream_name.to_string()))?;
+    if changed.is_some() {
+        let storage = CONFIG.storage().get_object_store();
+        let mut stream_metadata = storage
+            .get_stream_metadata(stream_name)
+            .await
+            .map_err(StreamError::from)?;
+        // latest mapping, other requests may have added keys meanwhile
+        stream_metadata.key_mapping = STREAM_INFO
+            .key_mapping(stream_name)
+            .map_err(|_| PostError::StreamNotFound(stream_name.to_string()))?;
+        storage
+            .put_stream_manifest(stream_name, &stream_metadata)
+            .await
+            .map_err(StreamError::from)?;
+    }
+    Ok(serde_json::to_vec(&json)?.into())
+}
+
+// Mask addresses in the IP columns of the stream before anything is stored
+fn mask_ips(stream_name: &str, body: Bytes) -> Result<Bytes, PostError> {
+    let Some(mask) = STREAM_INFO
+        .ip_mask(stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.to_string()))?
+    else {
+        return Ok(body);
+    };
+
+    let mut json = flatten_json_body(serde_json::from_slice(&body)?)?;
+    let invalid = mask.apply(&mut json);
+    if invalid > 0 {
+        INVALID_IP_VALUES
+            .with_label_values(&[stream_name])
+            .inc_by(invalid as u64);
+    }
+    Ok(serde_json::to_vec(&json)?.into())
+}
+
+// Drop the attributes of events which are not kept by the allow and deny
+// lists of the stream
+fn filter_attributes(stream_name: &str, body: Bytes) -> Result<Bytes, PostError> {
+    let Some(filter) = STREAM_INFO
+        .attribute_filter(stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.to_string()))?
+    else {
+        return Ok(body);
+    };
+    let timestamp_key = STREAM_INFO
+        .timestamp_key(stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.to_string()))?;
+
+    let mut json = flatten_json_body(serde_json::from_slice(&body)?)?;
+    let filtered = filter.apply(&mut json, &timestamp_key);
+    if filtered > 0 {
+        FILTERED_ATTRIBUTES
+            .with_label_values(&[stream_name])
+            .inc_by(filtered as u64);
+    }
+    Ok(serde_json::to_vec(&json)?.into())
+}
+
+// Once the daily quota of the stream is used up either reject the events
+// or keep a sample of them. Returns None if no event of the body is kept.
+fn enforce_quota(stream_name: &str, body: Bytes) -> Result<Option<Bytes>, PostError> {
+    let Some(quota) = STREAM_INFO
+        .quota(stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.to_string()))?
+    else {
+        return Ok(Some(body));
+    };
+    if !quota.exceeded(quota::usage(stream_name)) {
+        return Ok(Some(body));
+    }
+
+    match quota.overflow {
+        Overflow::Reject => Err(PostError::QuotaExceeded(stream_name.to_string())),
+        Overflow::Sample => {
+            let json: Value = serde_json::from_slice(&body)?;
+            let received = trace::count(&json);
+            let Some(json) = quota.sample(json) else {
+                trace::record(|trace| trace.drop_records(trace::QUOTA_EXCEEDED, received));
+                return Ok(None);
+            };
+            let kept = trace::count(&json);
+            trace::record(|trace| {
+                trace.drop_records(trace::QUOTA_EXCEEDED, received.saturating_sub(kept))
+            });
+            Ok(Some(serde_json::to_vec(&json)?.into()))
+        }
+    }
+}
+
 // Handler for POST /api/v1/logstream/{logstream}
 // only ingests events into the specified logstream
 // fails if the logstream does not exist
-pub async fn post_event(req: HttpRequest, body: Bytes) -> Result<HttpResponse, PostError> {
+pub async fn post_event(
+    req: HttpRequest,
+    EventBody(body): EventBody,
+) -> Result<HttpResponse, PostError> {
     let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let source = log_source_label(req.headers());
 
-    flatten_and_push_logs(req, body, stream_name).await?;
-    Ok(HttpResponse::Ok().finish())
+    observe_ingest(&stream_name, source, async {
+        push_and_respond(req, body, stream_name.clone()).await
+    })
+    .await
 }
 
-async fn push_logs(stream_name: String, req: HttpRequest, body: Bytes) -> Result<(), PostError> {
-    let (size, rb, is_first_event) = {
+pub async fn push_logs(
+    stream_name: String,
+    req: HttpRequest,
+    body: Bytes,
+) -> Result<(), PostError> {
+    if trace::is_tracing() {
+        let json: Value = serde_json::from_slice(&body)?;
+        trace::record(|trace| trace.records_parsed += trace::count(&json) as u64);
+    }
+    let routing = STREAM_INFO
+        .routing(&stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.clone()))?;
+    let Some(routing) = routing else {
+        return push_with_shadow(stream_name, req, body).await;
+    };
+
+    let json: Value = serde_json::from_slice(&body)?;
+    let routed = route_events(&stream_name, &routing, json).await?;
+    // nothing is ingested unless the caller may ingest into every stream
+    for (target, _) in &routed {
+        if !target.derived && target.stream != stream_name && !may_ingest(&req, &target.stream) {
+            return Err(PostError::Unauthorized(target.stream.clone()));
+        }
+    }
+    for (target, events) in routed {
+        create_stream_if_not_exists(&target.stream).await?;
+        let body: Bytes = serde_json::to_vec(&events)?.into();
+        push_with_shadow(target.stream, req.clone(), body).await?;
+    }
+    Ok(())
+}
+
+// Stream events are routed to and whether it was created by the routing of the
+// stream the events were sent to
+struct Target {
+    stream: String,
+    derived: bool,
+}
+
+// Split events by their route and resolve the stream of every route, creating
+// derived streams which do not exist yet. Events not routed to a derived stream
+// go to the default stream of the routing, which may not exist yet either.
+async fn route_events(
+    stream_name: &str,
+    routing: &Routing,
+    json: Value,
+) -> Result<Vec<(Target, Vec<Value>)>, PostError> {
+    let mut routed = Vec::new();
+    for (route, events) in routing.split(json) {
+        let target = match route {
+            Some(route) => route_target(stream_name, routing, route).await?,
+            None => None,
+        };
+        let target = target.unwrap_or_else(|| Target {
+            stream: routing
+                .default_stream
+                .clone()
+                .unwrap_or_else(|| stream_name.to_string()),
+            derived: false,
+        });
+        routed.push((target, events));
+    }
+    Ok(routed)
+}
+
+// Stream of a route, created if it does not exist and the routing may create
+// more streams. None if the events go where events without a route go.
+async fn route_target(
+    stream_name: &str,
+    routing: &Routing,
+    route: Route,
+) -> Result<Option<Target>, PostError> {
+    if STREAM_INFO.stream_exists(&route.stream) {
+        let derived = match STREAM_INFO.routed_from(&route.stream).ok().flatten() {
+            Some(routed_from) if routed_from.stream == stream_name => {
+                if routed_from.value != route.value {
+                    return Err(PostError::RoutingConflict {
+                        value: route.value,
+                        stream: route.stream,
+                        other: routed_from.value,
+                    });
+                }
+                true
+            }
+            _ => false,
+        };
+        return Ok(Some(Target {
+            stream: route.stream,
+            derived,
+        }));
+    }
+
+    if STREAM_INFO.routed_streams(stream_name) >= routing.max_streams {
+        log::warn!(
+            "routing of stream {stream_name} created {} streams already, not creating stream {}",
+            routing.max_streams,
+            route.stream
+        );
+        return Ok(None);
+    }
+    create_stream_with_template(&route.stream, routing.template.as_deref()).await?;
+    let routed_from = RoutedFrom {
+        stream: stream_name.to_string(),
+        value: route.value,
+    };
+    let storage = CONFIG.storage().get_object_store();
+    let mut metadata = storage
+        .get_stream_metadata(&route.stream)
+        .await
+        .map_err(StreamError::from)?;
+    metadata.routed_from = Some(routed_from.clone());
+    storage
+        .put_stream_manifest(&route.stream, &metadata)
+        .await
+        .map_err(StreamError::from)?;
+    STREAM_INFO
+        .set_routed_from(&route.stream, Some(routed_from))
+        .map_err(|_| PostError::StreamNotFound(route.stream.clone()))?;
+    Ok(Some(Target {
+        stream: route.stream,
+        derived: true,
+    }))
+}
+
+// Whether the caller of an ingest request may ingest into a stream, by an
+// ingest key of the stream or by the roles of the user
+fn may_ingest(req: &HttpRequest, stream_name: &str) -> bool {
+    let key = req
+        .headers()
+        .get(INGEST_KEY_HEADER_KEY)
+        .and_then(|value| value.to_str().ok());
+    match ingest_key::authorize(stream_name, key) {
+        Some(authorized) => authorized,
+        None => extract_session_key_from_req(req).is_ok_and(|creds| {
+            matches!(
+                Users.authorize(creds, Action::Ingest, Some(stream_name), None),
+                rbac::Response::Authorized
+            )
+        }),
+    }
+}
+
+async fn push_with_shadow(
+    stream_name: String,
+    req: HttpRequest,
+    body: Bytes,
+) -> Result<(), PostError> {
+    let shadow = STREAM_INFO
+        .shadow(&stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.clone()))?;
+    let Some(shadow) = shadow else {
+        return push_to_stream(stream_name, req, body).await;
+    };
+
+    push_to_stream(stream_name.clone(), req.clone(), body.clone()).await?;
+    let shadowed = receipts::ignore(trace::ignore(push_to_shadow(&shadow, req, &body))).await;
+    if let Err(err) = shadowed {
+        log::warn!(
+            "failed to ingest events of stream {} into shadow stream {}: {}",
+            stream_name,
+            shadow.stream,
+            err
+        );
+        SHADOW_INGEST_ERRORS
+            .with_label_values(&[&stream_name])
+            .inc();
+    }
+    Ok(())
+}
+
+async fn push_to_shadow(shadow: &Shadow, req: HttpRequest, body: &Bytes) -> Result<(), PostError> {
+    let mut json: Value = serde_json::from_slice(body)?;
+    shadow.apply(&mut json);
+    push_to_stream(
+        shadow.stream.clone(),
+        req,
+        serde_json::to_vec(&json)?.into(),
+    )
+    .await
+}
+
+async fn push_to_stream(
+    stream_name: String,
+    req: HttpRequest,
+    body: Bytes,
+) -> Result<(), PostError> {
+    let labels = Labels {
+        tags: collect_labelled_headers(&req, PREFIX_TAGS, SEPARATOR)?,
+        metadata: collect_labelled_headers(&req, PREFIX_META, SEPARATOR)?,
+    };
+    let dedup = STREAM_INFO
+        .dedup(&stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.clone()))?;
+    let Some(dedup) = dedup else {
+        return push_sampled(stream_name, labels, body).await;
+    };
+
+    // time of events differs between repeats
+    let mut ignored = STREAM_INFO
+        .time_fields(&stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.clone()))?;
+    ignored.push(
+        STREAM_INFO
+            .timestamp_key(&stream_name)
+            .map_err(|_| PostError::StreamNotFound(stream_name.clone()))?,
+    );
+
+    let body: Value = serde_json::from_slice(&body)?;
+    let received = trace::count(&body);
+    let mut kept = 0;
+    let collapsed = REPEATS.collapse(&stream_name, &dedup, &labels, body, &ignored, Utc::now());
+    for (labels, events) in collapsed {
+        kept += events.len();
+        let body: Bytes = serde_json::to_vec(&events)?.into();
+        push_sampled(stream_name.clone(), labels, body).await?;
+    }
+    trace::record(|trace| {
+        trace.drop_records(trace::COLLAPSED_REPEAT, received.saturating_sub(kept))
+    });
+    Ok(())
+}
+
+// Ingest the copy of an event standing for its repeats
+pub async fn push_repeats(
+    stream_name: &str,
+    labels: Labels,
+    event: Value,
+) -> Result<(), PostError> {
+    let body: Bytes = serde_json::to_vec(&event)?.into();
+    push_sampled(stream_name.to_string(), labels, body).await
+}
+
+async fn push_sampled(stream_name: String, labels: Labels, body: Bytes) -> Result<(), PostError> {
+    let sampling = STREAM_INFO
+        .sampling(&stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.clone()))?;
+    match sampling {
+        // events are ingested once the window of the sample ends
+        Some(sampling) => {
+            let body: Value = serde_json::from_slice(&body)?;
+            let held = trace::count(&body);
+            trace::record(|trace| trace.drop_records(trace::HELD_FOR_SAMPLING, held));
+            RESERVOIRS.offer(&stream_name, &sampling, &labels, body, Utc::now());
+            Ok(())
+        }
+        None => push_labelled_logs(stream_name, labels, body).await,
+    }
+}
+
+// Ingest events read back from a stream into the streams derived from it by
+// routing or a shadow stream, like `push_logs` does for new events but
+// without ingesting into the stream itself again. Events ingested since the
+// routing or shadow stream was set already went to the derived streams live.
+// Returns the number of events ingested into derived streams.
+pub async fn push_to_derived(
+    stream_name: &str,
+    labels: Labels,
+    mut events: Vec<Value>,
+) -> Result<u64, PostError> {
+    let routing = STREAM_INFO
+        .routing(stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.to_string()))?;
+    let Some(routing) = routing else {
+        return push_to_shadow_of(stream_name, labels, events).await;
+    };
+
+    events.retain(|event| rebuild::ingested_before(event, routing.since));
+    if events.is_empty() {
+        return Ok(0);
+    }
+    let mut count = 0;
+    for (target, events) in route_events(stream_name, &routing, Value::Array(events)).await? {
+        // events staying in the stream itself
+        if target.stream == stream_name {
+            continue;
+        }
+        create_stream_if_not_exists(&target.stream).await?;
+        let target = target.stream;
+        count += events.len() as u64;
+        let body: Bytes = serde_json::to_vec(&events)?.into();
+        push_replayed_logs(target.clone(), labels.clone(), body).await?;
+        count += push_to_shadow_of(&target, labels.clone(), events).await?;
+    }
+    Ok(count)
+}
+
+async fn push_to_shadow_of(
+    stream_name: &str,
+    labels: Labels,
+    mut events: Vec<Value>,
+) -> Result<u64, PostError> {
+    let shadow = STREAM_INFO
+        .shadow(stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.to_string()))?;
+    let Some(shadow) = shadow else {
+        return Ok(0);
+    };
+    events.retain(|event| rebuild::ingested_before(event, shadow.since));
+    if events.is_empty() {
+        return Ok(0);
+    }
+    let count = events.len() as u64;
+    let mut json = Value::Array(events);
+    shadow.apply(&mut json);
+    push_replayed_logs(shadow.stream, labels, serde_json::to_vec(&json)?.into()).await?;
+    Ok(count)
+}
+
+// Ingest events made by the server itself, such as the rollups of downsampled
+// data, as if sent without tags or metadata
+pub async fn push_events(stream_name: &str, events: Vec<Value>) -> Result<(), PostError> {
+    let labels = Labels {
+        tags: String::new(),
+        metadata: String::new(),
+    };
+    let body: Bytes = serde_json::to_vec(&events)?.into();
+    push_labelled_logs(stream_name.to_string(), labels, body).await
+}
+
+// Ingest the events kept from a sampling window, grouped by the labels they were sent with
+pub async fn push_sample(stream_name: &str, sample: Sample) -> Result<(), PostError> {
+    let groups = sample.events.into_iter().into_group_map();
+    for (labels, events) in groups {
+        let body: Bytes = serde_json::to_vec(&events)?.into();
+        push_labelled_logs(stream_name.to_string(), labels, body).await?;
+    }
+    Ok(())
+}
+
+async fn push_labelled_logs(
+    stream_name: String,
+    labels: Labels,
+    body: Bytes,
+) -> Result<(), PostError> {
+    push_labelled(stream_name, labels, body, false).await
+}
+
+// Ingest events read back from a stream by a rebuild, they keep the time they
+// were first ingested at as their timestamp
+async fn push_replayed_logs(
+    stream_name: String,
+    labels: Labels,
+    body: Bytes,
+) -> Result<(), PostError> {
+    push_labelled(stream_name, labels, body, true).await
+}
+
+async fn push_labelled(
+    stream_name: String,
+    labels: Labels,
+    body: Bytes,
+    replayed: bool,
+) -> Result<(), PostError> {
+    let Some(body) = enforce_quota(&stream_name, body)? else {
+        return Ok(());
+    };
+    let body = sanitize_keys(&stream_name, body).await?;
+    let body = mask_ips(&stream_name, body)?;
+    let body = filter_attributes(&stream_name, body)?;
+    let (size, rb, is_first_event, dropped) = {
         let hash_map = STREAM_INFO.read().unwrap();
-        let schema = hash_map
+        let metadata = hash_map
             .get(&stream_name)
-            .ok_or(PostError::StreamNotFound(stream_name.clone()))?
-            .schema
-            .clone();
-        into_event_batch(req, body, schema)?
+            .ok_or(PostError::StreamNotFound(stream_name.clone()))?;
+        let batch = into_event_batch(labels, body, IngestSettings::of(metadata))?;
+        if trace::is_tracing() {
+            let added = batch
+                .1
+                .schema()
+                .fields()
+                .iter()
+                .filter(|field| !metadata.schema.contains_key(field.name()))
+                .map(|field| field.name().clone())
+                .collect::<Vec<_>>();
+            trace::record(|trace| {
+                trace.attributes_dropped += batch.3 as u64;
+                if !added.is_empty() {
+                    trace
+                        .columns_added
+                        .entry(stream_name.clone())
+                        .or_default()
+                        .extend(added);
+                }
+            });
+        }
+        batch
     };
+    if dropped > 0 {
+        DROPPED_ATTRIBUTES
+            .with_label_values(&[&stream_name])
+            .inc_by(dropped as u64);
+    }
+    let rb = if replayed { with_source_time(rb)? } else { rb };
 
     event::Event {
         rb,
@@ -110,6 +1652,7 @@ async fn push_logs(stream_name: String, req: HttpRequest, body: Bytes) -> Result
         origin_format: "json",
         origin_size: size as u64,
         is_first_event,
+        replayed,
     }
     .process()
     .await?;
@@ -117,22 +1660,95 @@ async fn push_logs(stream_name: String, req: HttpRequest, body: Bytes) -> Result
     Ok(())
 }
 
+// Set the timestamp of replayed rows to their source_timestamp, rows without
+// one, e.g. dropped by a shadow stream, get the time of staging
+fn with_source_time(rb: RecordBatch) -> Result<RecordBatch, PostError> {
+    let now = Utc::now().timestamp_millis();
+    let times: TimestampMillisecondArray = match rb.column_by_name(SOURCE_TIMESTAMP_KEY) {
+        Some(column) => cast(column, &DataType::Timestamp(TimeUnit::Millisecond, None))
+            .map_err(|err| PostError::Invalid(err.into()))?
+            .as_primitive::<TimestampMillisecondType>()
+            .iter()
+            .map(|time| Some(time.unwrap_or(now)))
+            .collect(),
+        None => TimestampMillisecondArray::from_value(now, rb.num_rows()),
+    };
+    Ok(utils::arrow::replace_columns(
+        rb.schema(),
+        &rb,
+        &[0],
+        &[Arc::new(times)],
+    ))
+}
+
+// Settings of a stream its events are turned into record batches with
+struct IngestSettings<'a> {
+    schema: HashMap<String, Arc<Field>>,
+    timestamp_key: &'a str,
+    time_fields: &'a [String],
+    max_attributes: usize,
+    schema_lock: Option<&'a SchemaLock>,
+    attribute_map: Option<&'a AttributeMap>,
+    number_mode: NumberMode,
+    type_conflict: TypeConflict,
+}
+
+impl<'a> IngestSettings<'a> {
+    fn of(metadata: &'a LogStreamMetadata) -> Self {
+        Self {
+            schema: metadata.schema.clone(),
+            timestamp_key: metadata
+                .timestamp_key
+                .as_deref()
+                .unwrap_or(DEFAULT_TIMESTAMP_KEY),
+            time_fields: &metadata.time_fields,
+            max_attributes: CONFIG.parseable.max_record_attributes,
+            schema_lock: metadata.schema_lock.as_ref(),
+            attribute_map: metadata.attribute_map.as_ref(),
+            number_mode: metadata.number_mode,
+            type_conflict: metadata.type_conflict,
+        }
+    }
+}
+
 fn into_event_batch(
-    req: HttpRequest,
+    labels: Labels,
     body: Bytes,
-    schema: HashMap<String, Arc<Field>>,
-) -> Result<(usize, arrow_array::RecordBatch, bool), PostError> {
-    let tags = collect_labelled_headers(&req, PREFIX_TAGS, SEPARATOR)?;
-    let metadata = collect_labelled_headers(&req, PREFIX_META, SEPARATOR)?;
+    settings: IngestSettings,
+) -> Result<(usize, arrow_array::RecordBatch, bool, usize), PostError> {
+    let IngestSettings {
+        schema,
+        timestamp_key,
+        time_fields,
+        max_attributes,
+        schema_lock,
+        attribute_map,
+        number_mode,
+        type_conflict,
+    } = settings;
     let size = body.len();
-    let body: Value = serde_json::from_slice(&body)?;
+    let mut body = flatten_json_body(serde_json::from_slice(&body)?)?;
+    if let Some(attribute_map) = attribute_map {
+        attribute_map.apply(&mut body, timestamp_key, time_fields);
+    }
+    number_mode.apply(&mut body, &schema);
+    type_conflict
+        .apply(&mut body, &schema)
+        .map_err(|keys| PostError::TypeConflict(keys.join(", ")))?;
+    let dropped = limit_attributes(&mut body, &|key| schema.contains_key(key), max_attributes);
+    if let Some(lock) = schema_lock {
+        lock.apply(&mut body, &|key| schema.contains_key(key))
+            .map_err(|columns| PostError::SchemaLocked(columns.join(", ")))?;
+    }
     let event = format::json::Event {
         data: body,
-        tags,
-        metadata,
+        tags: labels.tags,
+        metadata: labels.metadata,
+        time_fields: time_fields.to_vec(),
+        attribute_column: attribute_map.map(|map| map.column().to_string()),
     };
-    let (rb, is_first) = event.into_recordbatch(schema)?;
-    Ok((size, rb, is_first))
+    let (rb, is_first) = event.into_recordbatch(schema, timestamp_key)?;
+    Ok((size, rb, is_first, dropped))
 }
 
 // Check if the stream exists and create a new stream if doesn't exist
@@ -158,6 +1774,24 @@ pub enum PostError {
     Invalid(#[from] anyhow::Error),
     #[error("{0}")]
     CreateStream(#[from] CreateStreamError),
+    #[error("{0}")]
+    Stream(#[from] StreamError),
+    #[error("Schema of the stream is locked, events can not add the columns {0}")]
+    SchemaLocked(String),
+    #[error("Attributes {0} have values of different types in this request")]
+    TypeConflict(String),
+    #[error("Not allowed to ingest into stream {0}")]
+    Unauthorized(String),
+    #[error("Value {value} is routed to stream {stream}, which was created for value {other}")]
+    RoutingConflict {
+        value: String,
+        stream: String,
+        other: String,
+    },
+    #[error("Request body exceeds the maximum request size of {0} bytes, split the events into smaller requests")]
+    PayloadTooLarge(usize),
+    #[error("Daily ingestion quota of stream {0} is exceeded")]
+    QuotaExceeded(String),
 }
 
 impl actix_web::ResponseError for PostError {
@@ -172,6 +1806,13 @@ impl actix_web::ResponseError for PostError {
             }
             PostError::CreateStream(_) => StatusCode::INTERNAL_SERVER_ERROR,
             PostError::StreamNotFound(_) => StatusCode::NOT_FOUND,
+            PostError::Unauthorized(_) => StatusCode::FORBIDDEN,
+            PostError::RoutingConflict { .. } => StatusCode::BAD_REQUEST,
+            PostError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            PostError::QuotaExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+            PostError::SchemaLocked(_) => StatusCode::BAD_REQUEST,
+            PostError::TypeConflict(_) => StatusCode::BAD_REQUEST,
+            PostError::Stream(err) => err.status_code(),
         }
     }
 
@@ -185,22 +1826,50 @@ impl actix_web::ResponseError for PostError {
 #[cfg(test)]
 mod tests {
 
+    use std::io::Write;
     use std::{collections::HashMap, sync::Arc};
 
+    use actix_web::http::header;
     use actix_web::test::TestRequest;
     use arrow_array::{
-        types::Int64Type, ArrayRef, Float64Array, Int64Array, ListArray, StringArray,
+        types::Int64Type, ArrayRef, Float64Array, Int64Array, ListArray, MapArray, StringArray,
+        TimestampMillisecondArray,
     };
-    use arrow_schema::{DataType, Field};
+    use arrow_schema::{DataType, Field, TimeUnit};
     use bytes::Bytes;
+    use flate2::write::GzEncoder;
     use serde_json::json;
 
     use crate::{
         event,
-        handlers::{PREFIX_META, PREFIX_TAGS},
+        event::attributes::{map_data_type, AttributeMap},
+        event::numbers::NumberMode,
+        event::schema_lock::{OnNewColumn, SchemaLock},
+        event::type_conflict::TypeConflict,
+        handlers::{PREFIX_META, PREFIX_TAGS, SEPARATOR},
+        sampling::Labels,
+        utils::header_parsing::collect_labelled_headers,
     };
 
-    use super::into_event_batch;
+    use super::{
+        into_event_batch, log_source_label, multipart_status, observe_ingest, part_stream_name,
+        read_body, read_parts, IngestSettings, PartResult, PostError, UNKNOWN_STREAM_LABEL,
+    };
+
+    impl Default for IngestSettings<'_> {
+        fn default() -> Self {
+            Self {
+                schema: HashMap::default(),
+                timestamp_key: event::DEFAULT_TIMESTAMP_KEY,
+                time_fields: &[],
+                max_attributes: usize::MAX,
+                schema_lock: None,
+                attribute_map: None,
+                number_mode: NumberMode::default(),
+                type_conflict: TypeConflict::default(),
+            }
+        }
+    }
 
     trait TestExt {
         fn as_int64_arr(&self) -> &Int64Array;
@@ -226,6 +1895,13 @@ mod tests {
         iter.map(|x| (x.name().clone(), Arc::new(x))).collect()
     }
 
+    fn labels(req: &actix_web::HttpRequest) -> Labels {
+        Labels {
+            tags: collect_labelled_headers(req, PREFIX_TAGS, SEPARATOR).unwrap(),
+            metadata: collect_labelled_headers(req, PREFIX_META, SEPARATOR).unwrap(),
+        }
+    }
+
     #[test]
     fn basic_object_into_rb() {
         let json = json!({
@@ -239,10 +1915,10 @@ mod tests {
             .append_header((PREFIX_META.to_string() + "C", "meta1"))
             .to_http_request();
 
-        let (size, rb, _) = into_event_batch(
-            req,
+        let (size, rb, _, _) = into_event_batch(
+            labels(&req),
             Bytes::from(serde_json::to_vec(&json).unwrap()),
-            HashMap::default(),
+            IngestSettings::default(),
         )
         .unwrap();
 
@@ -285,10 +1961,10 @@ mod tests {
 
         let req = TestRequest::default().to_http_request();
 
-        let (_, rb, _) = into_event_batch(
-            req,
+        let (_, rb, _, _) = into_event_batch(
+            labels(&req),
             Bytes::from(serde_json::to_vec(&json).unwrap()),
-            HashMap::default(),
+            IngestSettings::default(),
         )
         .unwrap();
 
@@ -322,8 +1998,15 @@ mod tests {
 
         let req = TestRequest::default().to_http_request();
 
-        let (_, rb, _) =
-            into_event_batch(req, Bytes::from(serde_json::to_vec(&json).unwrap()), schema).unwrap();
+        let (_, rb, _, _) = into_event_batch(
+            labels(&req),
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            IngestSettings {
+                schema,
+                ..Default::default()
+            },
+        )
+        .unwrap();
 
         assert_eq!(rb.num_rows(), 1);
         assert_eq!(rb.num_columns(), 5);
@@ -355,10 +2038,39 @@ mod tests {
 
         let req = TestRequest::default().to_http_request();
 
-        assert!(
-            into_event_batch(req, Bytes::from(serde_json::to_vec(&json).unwrap()), schema,)
-                .is_err()
+        assert!(into_event_batch(
+            labels(&req),
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            IngestSettings {
+                schema,
+                ..Default::default()
+            },
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn locked_schema_rejects_new_columns() {
+        let json = json!({"a": 1, "d": "new"});
+        let schema = fields_to_map([Field::new("a", DataType::Int64, true)].into_iter());
+        let lock = SchemaLock {
+            on_new_column: OnNewColumn::Reject,
+            locked_by: "admin".to_string(),
+            locked_at: chrono::Utc::now(),
+        };
+
+        let req = TestRequest::default().to_http_request();
+
+        let result = into_event_batch(
+            labels(&req),
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            IngestSettings {
+                schema,
+                schema_lock: Some(&lock),
+                ..Default::default()
+            },
         );
+        assert!(matches!(result, Err(PostError::SchemaLocked(columns)) if columns == "d"));
     }
 
     #[test]
@@ -376,8 +2088,15 @@ mod tests {
 
         let req = TestRequest::default().to_http_request();
 
-        let (_, rb, _) =
-            into_event_batch(req, Bytes::from(serde_json::to_vec(&json).unwrap()), schema).unwrap();
+        let (_, rb, _, _) = into_event_batch(
+            labels(&req),
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            IngestSettings {
+                schema,
+                ..Default::default()
+            },
+        )
+        .unwrap();
 
         assert_eq!(rb.num_rows(), 1);
         assert_eq!(rb.num_columns(), 3);
@@ -390,13 +2109,45 @@ mod tests {
         let req = TestRequest::default().to_http_request();
 
         assert!(into_event_batch(
-            req,
+            labels(&req),
             Bytes::from(serde_json::to_vec(&json).unwrap()),
-            HashMap::default(),
+            IngestSettings::default(),
         )
         .is_err())
     }
 
+    #[test]
+    fn custom_timestamp_key() {
+        let json = json!({"a": 1});
+        let req = TestRequest::default().to_http_request();
+
+        let (_, rb, _, _) = into_event_batch(
+            labels(&req),
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            IngestSettings {
+                timestamp_key: "@timestamp",
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(rb.schema().field(0).name(), "@timestamp");
+        assert!(rb.column_by_name(event::DEFAULT_TIMESTAMP_KEY).is_none());
+
+        let json = json!({"@timestamp": "2024-01-01T00:00:00Z"});
+        let req = TestRequest::default().to_http_request();
+
+        assert!(into_event_batch(
+            labels(&req),
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            IngestSettings {
+                timestamp_key: "@timestamp",
+                ..Default::default()
+            },
+        )
+        .is_err());
+    }
+
     #[test]
     fn array_into_recordbatch_inffered_schema() {
         let json = json!([
@@ -417,10 +2168,10 @@ mod tests {
 
         let req = TestRequest::default().to_http_request();
 
-        let (_, rb, _) = into_event_batch(
-            req,
+        let (_, rb, _, _) = into_event_batch(
+            labels(&req),
             Bytes::from(serde_json::to_vec(&json).unwrap()),
-            HashMap::default(),
+            IngestSettings::default(),
         )
         .unwrap();
 
@@ -470,10 +2221,10 @@ mod tests {
 
         let req = TestRequest::default().to_http_request();
 
-        let (_, rb, _) = into_event_batch(
-            req,
+        let (_, rb, _, _) = into_event_batch(
+            labels(&req),
             Bytes::from(serde_json::to_vec(&json).unwrap()),
-            HashMap::default(),
+            IngestSettings::default(),
         )
         .unwrap();
 
@@ -523,8 +2274,15 @@ mod tests {
         );
         let req = TestRequest::default().to_http_request();
 
-        let (_, rb, _) =
-            into_event_batch(req, Bytes::from(serde_json::to_vec(&json).unwrap()), schema).unwrap();
+        let (_, rb, _, _) = into_event_batch(
+            labels(&req),
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            IngestSettings {
+                schema,
+                ..Default::default()
+            },
+        )
+        .unwrap();
 
         assert_eq!(rb.num_rows(), 3);
         assert_eq!(rb.num_columns(), 6);
@@ -564,10 +2322,10 @@ mod tests {
 
         let req = TestRequest::default().to_http_request();
 
-        let (_, rb, _) = into_event_batch(
-            req,
+        let (_, rb, _, _) = into_event_batch(
+            labels(&req),
             Bytes::from(serde_json::to_vec(&json).unwrap()),
-            HashMap::default(),
+            IngestSettings::default(),
         )
         .unwrap();
 
@@ -614,10 +2372,15 @@ mod tests {
             .into_iter(),
         );
 
-        assert!(
-            into_event_batch(req, Bytes::from(serde_json::to_vec(&json).unwrap()), schema,)
-                .is_err()
-        );
+        assert!(into_event_batch(
+            labels(&req),
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            IngestSettings {
+                schema,
+                ..Default::default()
+            },
+        )
+        .is_err());
     }
 
     #[test]
@@ -645,10 +2408,10 @@ mod tests {
 
         let req = TestRequest::default().to_http_request();
 
-        let (_, rb, _) = into_event_batch(
-            req,
+        let (_, rb, _, _) = into_event_batch(
+            labels(&req),
             Bytes::from(serde_json::to_vec(&json).unwrap()),
-            HashMap::default(),
+            IngestSettings::default(),
         )
         .unwrap();
 
@@ -689,4 +2452,330 @@ mod tests {
             &ListArray::from_iter_primitive::<Int64Type, _, _>(c_b)
         );
     }
+
+    #[actix_web::test]
+    async fn oversized_body_is_rejected() {
+        let (req, mut payload) = TestRequest::default()
+            .set_payload(vec![b'a'; 20])
+            .to_http_parts();
+        assert!(matches!(
+            read_body(&req, &mut payload, 10).await,
+            Err(PostError::PayloadTooLarge(10))
+        ));
+
+        let (req, mut payload) = TestRequest::default()
+            .set_payload(vec![b'a'; 10])
+            .to_http_parts();
+        assert_eq!(read_body(&req, &mut payload, 10).await.unwrap().len(), 10);
+    }
+
+    #[actix_web::test]
+    async fn limit_applies_to_decompressed_body() {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&[b'a'; 100]).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert!(compressed.len() < 50);
+
+        let (req, mut payload) = TestRequest::default()
+            .insert_header((header::CONTENT_ENCODING, "gzip"))
+            .set_payload(compressed.clone())
+            .to_http_parts();
+        assert!(matches!(
+            read_body(&req, &mut payload, 50).await,
+            Err(PostError::PayloadTooLarge(50))
+        ));
+
+        let (req, mut payload) = TestRequest::default()
+            .insert_header((header::CONTENT_ENCODING, "gzip"))
+            .set_payload(compressed)
+            .to_http_parts();
+        assert_eq!(
+            read_body(&req, &mut payload, 100).await.unwrap(),
+            Bytes::from(vec![b'a'; 100])
+        );
+    }
+
+    #[test]
+    fn time_field_is_promoted() {
+        let json = json!([
+            {"a": 1, "seen": "2024-01-11T09:08:33.123Z"},
+            {"a": 2, "seen": 1704964113},
+            {"a": 3, "seen": "1704964113123"},
+        ]);
+        let req = TestRequest::default().to_http_request();
+
+        let (_, rb, is_first, _) = into_event_batch(
+            labels(&req),
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            IngestSettings {
+                time_fields: &["seen".to_string()],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert!(is_first);
+        let seen = rb.column_by_name("seen").unwrap();
+        assert_eq!(
+            seen.data_type(),
+            &DataType::Timestamp(TimeUnit::Millisecond, None)
+        );
+        assert_eq!(
+            seen.as_any()
+                .downcast_ref::<TimestampMillisecondArray>()
+                .unwrap(),
+            &TimestampMillisecondArray::from(vec![1704964113123, 1704964113000, 1704964113123])
+        );
+    }
+
+    #[test]
+    fn time_field_with_unparseable_value_is_not_promoted() {
+        let json = json!([
+            {"a": 1, "seen": "2024-01-11T09:08:33Z"},
+            {"a": 2, "seen": "yesterday"},
+        ]);
+        let req = TestRequest::default().to_http_request();
+
+        let (_, rb, _, _) = into_event_batch(
+            labels(&req),
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            IngestSettings {
+                time_fields: &["seen".to_string()],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            rb.column_by_name("seen").unwrap().data_type(),
+            &DataType::Utf8
+        );
+    }
+
+    #[test]
+    fn attributes_stored_in_map_column() {
+        let json = json!([
+            {"level": "info", "user": {"id": 7}},
+            {"level": "warn", "path": "/login"},
+        ]);
+        let req = TestRequest::default().to_http_request();
+        let attribute_map = AttributeMap {
+            column: None,
+            columns: vec!["level".to_string()],
+        };
+
+        let (_, rb, _, _) = into_event_batch(
+            labels(&req),
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            IngestSettings {
+                attribute_map: Some(&attribute_map),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(rb.num_columns(), 5);
+        assert!(rb.column_by_name("user_id").is_none());
+        let attributes = rb.column_by_name("attributes").unwrap();
+        assert_eq!(attributes.data_type(), &map_data_type());
+        let attributes = attributes.as_any().downcast_ref::<MapArray>().unwrap();
+        assert_eq!(
+            attributes.keys().as_ref(),
+            &StringArray::from(vec!["user_id", "path"]) as &dyn arrow_array::Array
+        );
+    }
+
+    #[test]
+    fn mixed_types_widened_to_string() {
+        let json = json!([{"status": 200}, {"status": "OK"}, {"status": true}]);
+        let req = TestRequest::default().to_http_request();
+
+        let (_, rb, _, _) = into_event_batch(
+            labels(&req),
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            IngestSettings::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            rb.column_by_name("status").unwrap().as_utf8_arr(),
+            &StringArray::from(vec!["200", "OK", "true"])
+        );
+    }
+
+    #[test]
+    fn big_integers_stored_exactly() {
+        let json = json!([
+            {"id": 18446744073709551615u64, "seq": 9007199254740993i64},
+            {"id": 42, "seq": 1},
+        ]);
+        let req = TestRequest::default().to_http_request();
+
+        let (_, rb, _, _) = into_event_batch(
+            labels(&req),
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            IngestSettings {
+                number_mode: NumberMode::Exact,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            rb.column_by_name("id").unwrap().as_utf8_arr(),
+            &StringArray::from(vec!["18446744073709551615", "42"])
+        );
+        assert_eq!(
+            rb.column_by_name("seq").unwrap().as_int64_arr(),
+            &Int64Array::from(vec![9007199254740993, 1])
+        );
+    }
+
+    #[actix_web::test]
+    async fn unknown_streams_share_a_metric_label() {
+        use crate::metrics::INGEST_REQUESTS_TOTAL;
+        use actix_web::HttpResponse;
+        use prometheus::core::Collector;
+
+        let result = observe_ingest("nosuchstreamlabel", "json", async {
+            Ok(HttpResponse::Ok().finish())
+        })
+        .await;
+
+        assert!(result.is_ok());
+        assert!(
+            INGEST_REQUESTS_TOTAL
+                .with_label_values(&[UNKNOWN_STREAM_LABEL, "json", "200"])
+                .get()
+                >= 1
+        );
+        let labelled = INGEST_REQUESTS_TOTAL.collect()[0]
+            .get_metric()
+            .iter()
+            .flat_map(|metric| metric.get_label())
+            .any(|label| label.get_value() == "nosuchstreamlabel");
+        assert!(!labelled);
+    }
+
+    #[test]
+    fn part_stream_falls_back_to_request_stream() {
+        use crate::handlers::STREAM_NAME_HEADER_KEY;
+        use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(part_stream_name(&headers, None), None);
+        assert_eq!(
+            part_stream_name(&headers, Some("app")).as_deref(),
+            Some("app")
+        );
+
+        headers.insert(
+            HeaderName::from_static(STREAM_NAME_HEADER_KEY),
+            HeaderValue::from_static("audit"),
+        );
+        assert_eq!(
+            part_stream_name(&headers, Some("app")).as_deref(),
+            Some("audit")
+        );
+    }
+
+    #[test]
+    fn part_results_report_status_and_error() {
+        use actix_web::http::StatusCode;
+        use actix_web::HttpResponse;
+
+        let ok = PartResult::new(
+            "logs".to_string(),
+            Some("app".to_string()),
+            Ok(HttpResponse::Ok().finish()),
+        );
+        let failed = PartResult::new(
+            "metrics".to_string(),
+            Some("audit".to_string()),
+            Err(PostError::Unauthorized("audit".to_string())),
+        );
+
+        assert_eq!(
+            serde_json::to_value(&ok).unwrap(),
+            json!({"part": "logs", "stream": "app", "status": 200})
+        );
+        let failed_json = serde_json::to_value(&failed).unwrap();
+        assert_eq!(failed_json["status"], 403);
+        assert!(failed_json["error"].is_string());
+
+        assert_eq!(multipart_status(&[]), StatusCode::OK);
+        assert_eq!(multipart_status(&[ok]), StatusCode::OK);
+        assert_eq!(multipart_status(&[failed]), StatusCode::MULTI_STATUS);
+    }
+
+    #[test]
+    fn every_log_source_has_a_metric_label() {
+        use crate::handlers::LOG_SOURCE_KEY;
+
+        for source in [
+            "kinesis",
+            "otel",
+            "otel-lines",
+            "text",
+            "w3c",
+            "csv",
+            "vector",
+            "loki",
+            "prometheus",
+            "arrow",
+        ] {
+            let req = TestRequest::default()
+                .insert_header((LOG_SOURCE_KEY, source))
+                .to_http_request();
+            assert_eq!(log_source_label(req.headers()), source);
+        }
+        let req = TestRequest::default()
+            .insert_header((LOG_SOURCE_KEY, "syslog"))
+            .to_http_request();
+        assert_eq!(log_source_label(req.headers()), "json");
+    }
+
+    #[actix_web::test]
+    async fn multipart_parts_read_within_limit() {
+        use actix_multipart::Multipart;
+        use actix_web::http::header::HeaderMap;
+
+        let body = "--b\r\n\
+            Content-Disposition: form-data; name=\"logs\"\r\n\
+            x-p-stream: audit\r\n\
+            x-p-log-source: text\r\n\r\n\
+            line one\r\n\
+            --b\r\n\
+            Content-Disposition: form-data; name=\"events\"\r\n\r\n\
+            [{\"a\": 1}]\r\n\
+            --b--\r\n";
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("multipart/form-data; boundary=b"),
+        );
+        let multipart = || {
+            Multipart::new(
+                &headers,
+                futures::stream::iter([Ok(Bytes::from_static(body.as_bytes()))]),
+            )
+        };
+
+        let parts = read_parts(&mut multipart(), Some("app"), 100)
+            .await
+            .unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name, "logs");
+        assert_eq!(parts[0].stream_name.as_deref(), Some("audit"));
+        assert_eq!(parts[0].source, "text");
+        assert_eq!(parts[0].body, "line one");
+        assert_eq!(parts[1].stream_name.as_deref(), Some("app"));
+        assert_eq!(parts[1].body, r#"[{"a": 1}]"#);
+
+        // the parts fit on their own but not together
+        assert!(matches!(
+            read_parts(&mut multipart(), Some("app"), 12).await,
+            Err(PostError::PayloadTooLarge(12))
+        ));
+    }
 }
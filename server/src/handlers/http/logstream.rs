@@ -16,22 +16,54 @@
  *
  */
 
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 
 use actix_web::http::StatusCode;
 use actix_web::{web, HttpRequest, Responder};
-use chrono::Utc;
+use arrow_schema::{DataType, Schema};
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use itertools::Itertools;
 use serde_json::Value;
 
 use crate::alerts::Alerts;
+use crate::dedup::Dedup;
+use crate::event::attribute_filter::AttributeFilter;
+use crate::event::attributes::{self, AttributeMap};
+use crate::event::body::BodyConfig;
+use crate::event::ip_mask::IpMask;
+use crate::event::keys::KeySanitization;
+use crate::event::numbers::NumberMode;
+use crate::event::raw::RawPayload;
+use crate::event::routing::Routing;
+use crate::event::schema_lock::{OnNewColumn, SchemaLock};
+use crate::event::severity::{SeverityMapping, UnknownSeverity};
+use crate::event::shadow::Shadow;
+use crate::event::skew::ClockSkew;
+use crate::event::type_conflict::TypeConflict;
+use crate::handlers::TEMPLATE_HEADER_KEY;
+use crate::metadata::error::stream_info::LoadError;
 use crate::metadata::STREAM_INFO;
 use crate::option::CONFIG;
+use crate::query::casts::{self, CastColumn};
+use crate::query::view::DefaultView;
+use crate::quota::{self, IngestQuota, QuotaStatus};
+use crate::rbac::ingest_key::IngestKey;
+use crate::rbac::role::Action;
+use crate::rbac::{self, Users};
+use crate::rebuild::{self, RebuildJob, RebuildState, REBUILDS};
+use crate::sampling::Sampling;
+use crate::storage::bundle::StreamBundle;
+use crate::storage::compression::{StagingCompression, StreamCompression};
+use crate::storage::partition::{self, Partitioning};
 use crate::storage::retention::{self, Retention};
-use crate::storage::{LogStream, StorageDir};
-use crate::{event, stats};
-use crate::{metadata, validator};
+use crate::storage::{LogStream, ObjectStoreFormat, StorageDir, StreamTemplate};
+use crate::utils::actix::extract_session_key_from_req;
+use crate::{catalog, event, stats};
+use crate::{metadata, utils, validator};
 
 use self::error::{CreateStreamError, StreamError};
+use super::text;
 
 pub async fn delete(req: HttpRequest) -> Result<impl Responder, StreamError> {
     let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
@@ -40,15 +72,22 @@ pub async fn delete(req: HttpRequest) -> Result<impl Responder, StreamError> {
         return Err(StreamError::StreamNotFound(stream_name));
     }
 
+    delete_stream(&stream_name).await?;
+
+    Ok((format!("log stream {stream_name} deleted"), StatusCode::OK))
+}
+
+async fn delete_stream(stream_name: &str) -> Result<(), StreamError> {
     let objectstore = CONFIG.storage().get_object_store();
-    objectstore.delete_stream(&stream_name).await?;
-    metadata::STREAM_INFO.delete_stream(&stream_name);
-    event::STREAM_WRITERS.delete_stream(&stream_name);
-    stats::delete_stats(&stream_name, "json").unwrap_or_else(|e| {
+    objectstore.delete_stream(stream_name).await?;
+    metadata::STREAM_INFO.delete_stream(stream_name);
+    event::STREAM_WRITERS.delete_stream(stream_name);
+    partition::reset(stream_name);
+    stats::delete_stats(stream_name, "json").unwrap_or_else(|e| {
         log::warn!("failed to delete stats for stream {}: {:?}", stream_name, e)
     });
 
-    let stream_dir = StorageDir::new(&stream_name);
+    let stream_dir = StorageDir::new(stream_name);
     if fs::remove_dir_all(&stream_dir.data_path).is_err() {
         log::warn!(
             "failed to delete local data for stream {}. Clean {} manually",
@@ -56,8 +95,100 @@ pub async fn delete(req: HttpRequest) -> Result<impl Responder, StreamError> {
             stream_dir.data_path.to_string_lossy()
         )
     }
+    Ok(())
+}
 
-    Ok((format!("log stream {stream_name} deleted"), StatusCode::OK))
+#[derive(Debug, serde::Deserialize)]
+pub struct BulkDeleteRequest {
+    #[serde(default)]
+    streams: Vec<String>,
+    pattern: Option<String>,
+    // must be the number of streams selected by this request
+    confirm: Option<usize>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct BulkDeleteResult {
+    deleted: Vec<String>,
+    failed: Vec<BulkDeleteFailure>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct BulkDeleteFailure {
+    stream: String,
+    error: String,
+}
+
+// Handler for DELETE /api/v1/logstream
+// Deletes every stream listed or matching the pattern. Nothing is deleted unless
+// all selected streams exist, the user is allowed to delete each of them and
+// `confirm` equals the number of selected streams.
+pub async fn delete_bulk(
+    req: HttpRequest,
+    body: web::Json<BulkDeleteRequest>,
+) -> Result<impl Responder, StreamError> {
+    let body = body.into_inner();
+    let mut streams: Vec<String> = Vec::new();
+    for stream_name in body.streams {
+        if !STREAM_INFO.stream_exists(&stream_name) {
+            return Err(StreamError::StreamNotFound(stream_name));
+        }
+        if !streams.contains(&stream_name) {
+            streams.push(stream_name);
+        }
+    }
+    if let Some(pattern) = &body.pattern {
+        for stream_name in STREAM_INFO.list_streams() {
+            if utils::glob_match(pattern, &stream_name) && !streams.contains(&stream_name) {
+                streams.push(stream_name);
+            }
+        }
+    }
+    if streams.is_empty() {
+        return Err(StreamError::Custom {
+            msg: "no log stream selected for deletion".to_string(),
+            status: StatusCode::BAD_REQUEST,
+        });
+    }
+
+    let key = extract_session_key_from_req(&req).map_err(|err| StreamError::Custom {
+        msg: err.to_string(),
+        status: StatusCode::UNAUTHORIZED,
+    })?;
+    for stream_name in &streams {
+        if !matches!(
+            Users.authorize(key.clone(), Action::DeleteStream, Some(stream_name), None),
+            rbac::Response::Authorized
+        ) {
+            return Err(StreamError::Custom {
+                msg: format!("not allowed to delete log stream {stream_name}"),
+                status: StatusCode::FORBIDDEN,
+            });
+        }
+    }
+
+    if body.confirm != Some(streams.len()) {
+        return Err(StreamError::BulkDeleteNotConfirmed(
+            streams.len(),
+            streams.join(", "),
+        ));
+    }
+
+    let mut result = BulkDeleteResult::default();
+    for stream_name in streams {
+        match delete_stream(&stream_name).await {
+            Ok(()) => result.deleted.push(stream_name),
+            Err(err) => {
+                log::error!("failed to delete stream {}: {}", stream_name, err);
+                result.failed.push(BulkDeleteFailure {
+                    stream: stream_name,
+                    error: err.to_string(),
+                })
+            }
+        }
+    }
+
+    Ok((web::Json(result), StatusCode::OK))
 }
 
 pub async fn list(_: HttpRequest) -> impl Responder {
@@ -76,6 +207,68 @@ pub async fn schema(req: HttpRequest) -> Result<impl Responder, StreamError> {
     Ok((web::Json(schema), StatusCode::OK))
 }
 
+#[derive(Debug, serde::Deserialize)]
+pub struct SchemaExportParams {
+    // comma separated stream names
+    streams: Option<String>,
+    pattern: Option<String>,
+}
+
+// Handler for GET /api/v1/schemas
+// Schemas of every stream the user may get the schema of, or of the ones listed
+// in `streams` and matching `pattern` when given, along with a fingerprint of
+// each schema to detect changes without comparing schemas.
+pub async fn export_schemas(
+    req: HttpRequest,
+    params: web::Query<SchemaExportParams>,
+) -> Result<impl Responder, StreamError> {
+    let params = params.into_inner();
+    let key = extract_session_key_from_req(&req).map_err(|err| StreamError::Custom {
+        msg: err.to_string(),
+        status: StatusCode::UNAUTHORIZED,
+    })?;
+
+    let mut streams = match &params.streams {
+        Some(streams) => {
+            let streams = utils::arrow::parse_columns(streams);
+            if let Some(stream_name) = streams
+                .iter()
+                .find(|stream_name| !STREAM_INFO.stream_exists(stream_name))
+            {
+                return Err(StreamError::StreamNotFound(stream_name.clone()));
+            }
+            streams
+        }
+        None => STREAM_INFO.list_streams(),
+    };
+    if let Some(pattern) = &params.pattern {
+        streams.retain(|stream_name| utils::glob_match(pattern, stream_name));
+    }
+
+    let mut schemas = BTreeMap::new();
+    for stream_name in streams {
+        if !matches!(
+            Users.authorize(key.clone(), Action::GetSchema, Some(&stream_name), None),
+            rbac::Response::Authorized
+        ) {
+            continue;
+        }
+        // the stream may have been deleted meanwhile
+        let Ok(schema) = STREAM_INFO.schema(&stream_name) else {
+            continue;
+        };
+        schemas.insert(
+            stream_name,
+            serde_json::json!({
+                "fingerprint": utils::arrow::schema_fingerprint(&schema),
+                "schema": schema,
+            }),
+        );
+    }
+
+    Ok((web::Json(schemas), StatusCode::OK))
+}
+
 pub async fn get_alert(req: HttpRequest) -> Result<impl Responder, StreamError> {
     let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
 
@@ -120,13 +313,343 @@ pub async fn put_stream(req: HttpRequest) -> Result<impl Responder, StreamError>
             ),
             status: StatusCode::BAD_REQUEST,
         });
-    } else {
-        create_stream(stream_name).await?;
     }
 
+    // settings of the stream can be taken from a template
+    let template = req
+        .headers()
+        .get(TEMPLATE_HEADER_KEY)
+        .and_then(|value| value.to_str().ok());
+    create_stream_with_template(&stream_name, template).await?;
+
     Ok(("log stream created", StatusCode::OK))
 }
 
+async fn get_template(name: &str) -> Result<StreamTemplate, StreamError> {
+    let metadata = CONFIG
+        .storage()
+        .get_object_store()
+        .get_metadata()
+        .await?
+        .expect("metadata is initialized");
+    metadata
+        .stream_templates
+        .get(name)
+        .cloned()
+        .ok_or_else(|| StreamError::TemplateNotFound(name.to_string()))
+}
+
+// Create a stream, with the settings of a template if given
+pub async fn create_stream_with_template(
+    stream_name: &str,
+    template: Option<&str>,
+) -> Result<(), StreamError> {
+    let template = match template {
+        Some(name) => Some((name, get_template(name).await?)),
+        None => None,
+    };
+    create_stream(stream_name.to_string()).await?;
+    if let Some((name, template)) = template {
+        apply_template(stream_name, name, &template).await?;
+    }
+    Ok(())
+}
+
+pub fn validate_template(template: &StreamTemplate) -> Result<(), StreamError> {
+    if let Some(pattern) = &template.log_pattern {
+        text::compile_pattern(pattern).map_err(StreamError::InvalidLogPattern)?;
+    }
+    if let Some(timestamp_key) = &template.timestamp_key {
+        validator::timestamp_key(timestamp_key)
+            .map_err(|err| StreamError::InvalidTimestampKey(err.to_string()))?;
+    }
+    if let Some(mapping) = &template.severity_mapping {
+        mapping
+            .validate()
+            .map_err(StreamError::InvalidSeverityMapping)?;
+    }
+    if let Some(config) = &template.body_config {
+        let timestamp_key = template
+            .timestamp_key
+            .as_deref()
+            .unwrap_or(event::DEFAULT_TIMESTAMP_KEY);
+        config
+            .validate(timestamp_key)
+            .map_err(StreamError::InvalidBodyConfig)?;
+    }
+    if let Some(quota) = &template.quota {
+        quota.validate().map_err(StreamError::InvalidQuota)?;
+    }
+    Ok(())
+}
+
+// Apply the settings of a template to a stream and record the template in its metadata.
+// The timestamp column is only set on streams which have not received events yet
+// and the cache only on servers with a local cache, other settings replace those of the stream.
+pub async fn apply_template(
+    stream_name: &str,
+    template_name: &str,
+    template: &StreamTemplate,
+) -> Result<(), StreamError> {
+    let storage = CONFIG.storage().get_object_store();
+    let timestamp_key = template.timestamp_key.clone().filter(|_| {
+        STREAM_INFO
+            .schema(stream_name)
+            .is_ok_and(|s| s.fields().is_empty())
+    });
+    let cache_enabled = template.cache_enabled && CONFIG.parseable.local_cache_path.is_some();
+
+    let mut stream_metadata = storage.get_stream_metadata(stream_name).await?;
+    stream_metadata.template = Some(template_name.to_string());
+    stream_metadata.cache_enabled = cache_enabled;
+    stream_metadata.log_pattern = template.log_pattern.clone();
+    stream_metadata.severity_mapping = template.severity_mapping.clone();
+    stream_metadata.body_config = template.body_config.clone();
+    stream_metadata.quota = template.quota.clone();
+    if timestamp_key.is_some() {
+        stream_metadata.timestamp_key = timestamp_key.clone();
+    }
+    storage
+        .put_stream_manifest(stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_stream_cache(stream_name, cache_enabled)?;
+    STREAM_INFO.set_log_pattern(stream_name, template.log_pattern.clone())?;
+    STREAM_INFO.set_severity_mapping(stream_name, template.severity_mapping.clone())?;
+    STREAM_INFO.set_body_config(stream_name, template.body_config.clone())?;
+    STREAM_INFO.set_quota(stream_name, template.quota.clone())?;
+    if let Some(timestamp_key) = timestamp_key {
+        STREAM_INFO.set_timestamp_key(stream_name, timestamp_key)?;
+    }
+
+    if let Some(retention) = &template.retention {
+        storage.put_retention(stream_name, retention).await?;
+        retention::init_scheduler(stream_name, retention.clone());
+    }
+    Ok(())
+}
+
+// Export the configuration of a stream as a bundle which creates the stream
+// again on another server, data and stats of the stream are not part of it
+pub async fn get_bundle(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    let schema = storage.get_schema(&stream_name).await?;
+    let retention = storage.get_retention(&stream_name).await?;
+    let alerts = storage.get_alerts(&stream_name).await?;
+
+    let mut bundle = StreamBundle::new(&stream_name, &stream_metadata);
+    bundle.schema = Some(schema).filter(|schema| !schema.fields().is_empty());
+    bundle.retention = Some(retention).filter(|retention| *retention != Retention::default());
+    bundle.alerts = Some(alerts).filter(|alerts| !alerts.alerts.is_empty());
+    Ok((web::Json(bundle), StatusCode::OK))
+}
+
+// Create a stream from a bundle exported by get_bundle. The settings are
+// validated as when they are set one by one before anything is written, the
+// cache is only enabled on servers with a local cache.
+pub async fn put_bundle(
+    req: HttpRequest,
+    body: web::Json<StreamBundle>,
+) -> Result<impl Responder, StreamError> {
+    let bundle = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::Custom {
+            msg: format!(
+                "log stream {stream_name} already exists, please create a new log stream with unique name"
+            ),
+            status: StatusCode::BAD_REQUEST,
+        });
+    }
+
+    bundle.validate().map_err(StreamError::InvalidBundle)?;
+    let settings = bundle
+        .apply(&Default::default())
+        .map_err(StreamError::InvalidBundle)?;
+    let schema = bundle.schema.clone().unwrap_or_else(Schema::empty);
+    validate_settings(&stream_name, &settings, &schema)?;
+    if let Some(alerts) = &bundle.alerts {
+        validator::alert(alerts)?;
+    }
+    if let Some(template) = &settings.template {
+        get_template(template).await?;
+    }
+
+    create_stream(stream_name.clone()).await?;
+    // a stream is created from the whole bundle or not at all
+    if let Err(err) = write_bundle(&stream_name, &bundle).await {
+        if let Err(err) = delete_stream(&stream_name).await {
+            log::warn!("failed to remove log stream {stream_name} created from a bundle: {err}");
+        }
+        return Err(err);
+    }
+    if let Some(retention) = &bundle.retention {
+        retention::init_scheduler(&stream_name, retention.clone());
+    }
+
+    Ok((
+        format!(
+            "log stream {stream_name} created from bundle of {}",
+            bundle.stream
+        ),
+        StatusCode::OK,
+    ))
+}
+
+async fn write_bundle(stream_name: &str, bundle: &StreamBundle) -> Result<(), StreamError> {
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = bundle
+        .apply(&storage.get_stream_metadata(stream_name).await?)
+        .map_err(StreamError::InvalidBundle)?;
+    stream_metadata.cache_enabled &= CONFIG.parseable.local_cache_path.is_some();
+    // routing and shadow ingestion of the new stream start now
+    let now = Some(Utc::now());
+    if let Some(routing) = &mut stream_metadata.routing {
+        routing.since = now;
+    }
+    if let Some(shadow) = &mut stream_metadata.shadow {
+        shadow.since = now;
+    }
+    storage
+        .put_stream_manifest(stream_name, &stream_metadata)
+        .await?;
+    if let Some(schema) = &bundle.schema {
+        storage.put_schema(stream_name, schema).await?;
+    }
+    if let Some(alerts) = &bundle.alerts {
+        storage.put_alerts(stream_name, alerts).await?;
+    }
+    if let Some(retention) = &bundle.retention {
+        storage.put_retention(stream_name, retention).await?;
+    }
+    STREAM_INFO
+        .load_stream(&*storage, stream_name)
+        .await
+        .map_err(|LoadError::ObjectStorage(err)| StreamError::Storage(err))
+}
+
+// Validate the settings of a stream metadata which did not come through the
+// handlers setting them
+fn validate_settings(
+    stream_name: &str,
+    settings: &ObjectStoreFormat,
+    schema: &Schema,
+) -> Result<(), StreamError> {
+    validator::labels(&settings.labels)
+        .map_err(|err| StreamError::InvalidLabels(err.to_string()))?;
+    if let Some(pattern) = &settings.log_pattern {
+        text::compile_pattern(pattern).map_err(StreamError::InvalidLogPattern)?;
+    }
+    if let Some(timestamp_key) = &settings.timestamp_key {
+        validator::timestamp_key(timestamp_key)
+            .map_err(|err| StreamError::InvalidTimestampKey(err.to_string()))?;
+    }
+    for field in &settings.time_fields {
+        validator::timestamp_key(field)
+            .map_err(|err| StreamError::InvalidTimeFields(err.to_string()))?;
+    }
+    if let Some(mapping) = &settings.severity_mapping {
+        mapping
+            .validate()
+            .map_err(StreamError::InvalidSeverityMapping)?;
+    }
+    if let Some(config) = &settings.body_config {
+        let timestamp_key = settings
+            .timestamp_key
+            .as_deref()
+            .unwrap_or(event::DEFAULT_TIMESTAMP_KEY);
+        config
+            .validate(timestamp_key)
+            .map_err(StreamError::InvalidBodyConfig)?;
+    }
+    if let Some(shadow) = &settings.shadow {
+        let timestamp_key = STREAM_INFO
+            .timestamp_key(&shadow.stream)
+            .unwrap_or_else(|_| event::DEFAULT_TIMESTAMP_KEY.to_string());
+        shadow
+            .validate(stream_name, &timestamp_key)
+            .map_err(StreamError::InvalidShadow)?;
+    }
+    if let Some(routing) = &settings.routing {
+        routing.validate().map_err(StreamError::InvalidRouting)?;
+    }
+    if let Some(quota) = &settings.quota {
+        quota.validate().map_err(StreamError::InvalidQuota)?;
+    }
+    if let Some(sampling) = &settings.sampling {
+        sampling.validate().map_err(StreamError::InvalidSampling)?;
+    }
+    if let Some(dedup) = &settings.dedup {
+        dedup.validate().map_err(StreamError::InvalidDedup)?;
+    }
+    if let Some(compression) = &settings.compression {
+        compression
+            .validate()
+            .map_err(StreamError::InvalidCompression)?;
+    }
+    if let Some(attribute_map) = &settings.attribute_map {
+        attribute_map
+            .validate()
+            .map_err(StreamError::InvalidAttributeMap)?;
+    }
+    if let Some(attribute_filter) = &settings.attribute_filter {
+        attribute_filter
+            .validate()
+            .map_err(StreamError::InvalidAttributeFilter)?;
+    }
+    if let Some(ip_mask) = &settings.ip_mask {
+        ip_mask.validate().map_err(StreamError::InvalidIpMask)?;
+    }
+    if let Some(partitioning) = &settings.partitioning {
+        partitioning
+            .validate()
+            .map_err(StreamError::InvalidPartitioning)?;
+    }
+    if let Some(raw_payload) = &settings.raw_payload {
+        raw_payload
+            .validate()
+            .map_err(StreamError::InvalidRawPayload)?;
+        if settings.ip_mask.is_some() {
+            return Err(StreamError::InvalidRawPayload(
+                "raw payloads would keep the addresses masked by the stream in full".to_string(),
+            ));
+        }
+    }
+    if let Some(default_view) = &settings.default_view {
+        default_view
+            .validate()
+            .map_err(StreamError::InvalidDefaultView)?;
+    }
+    if let Some(clock_skew) = &settings.clock_skew {
+        clock_skew
+            .validate()
+            .map_err(StreamError::InvalidClockSkew)?;
+    }
+    if settings
+        .max_file_size
+        .is_some_and(|size| size < MIN_MAX_FILE_SIZE)
+    {
+        return Err(StreamError::InvalidMaxFileSize(format!(
+            "must be at least {MIN_MAX_FILE_SIZE} bytes"
+        )));
+    }
+    casts::validate(&settings.casts, schema).map_err(StreamError::InvalidCasts)?;
+    if settings.encrypted && CONFIG.parseable.encryption_keyfile.is_none() {
+        return Err(StreamError::EncryptionNotConfigured(
+            stream_name.to_string(),
+        ));
+    }
+    Ok(())
+}
+
 pub async fn put_alert(
     req: HttpRequest,
     body: web::Json<serde_json::Value>,
@@ -258,33 +781,1669 @@ pub async fn put_enable_cache(
     ))
 }
 
-pub async fn get_stats(req: HttpRequest) -> Result<impl Responder, StreamError> {
+pub async fn get_encryption(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let encrypted = STREAM_INFO.encrypted(&stream_name)?;
+    Ok((web::Json(encrypted), StatusCode::OK))
+}
+
+// only files uploaded after the change are affected, existing files are left as they are
+pub async fn put_encryption(
+    req: HttpRequest,
+    body: web::Json<bool>,
+) -> Result<impl Responder, StreamError> {
+    let encrypted = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let storage = CONFIG.storage().get_object_store();
+
+    if encrypted && CONFIG.parseable.encryption_keyfile.is_none() {
+        return Err(StreamError::EncryptionNotConfigured(stream_name));
+    }
+
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.encrypted = encrypted;
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_encrypted(&stream_name, encrypted)?;
+    Ok((
+        format!("Encryption set to {encrypted} for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_labels(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let labels = STREAM_INFO.labels(&stream_name)?;
+    Ok((web::Json(labels), StatusCode::OK))
+}
+
+pub async fn put_labels(
+    req: HttpRequest,
+    body: web::Json<BTreeMap<String, String>>,
+) -> Result<impl Responder, StreamError> {
+    let labels = body.into_inner();
     let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
 
     if !metadata::STREAM_INFO.stream_exists(&stream_name) {
         return Err(StreamError::StreamNotFound(stream_name));
     }
 
-    let stats = stats::get_current_stats(&stream_name, "json")
-        .ok_or(StreamError::StreamNotFound(stream_name.clone()))?;
+    validator::labels(&labels).map_err(|err| StreamError::InvalidLabels(err.to_string()))?;
 
-    let time = Utc::now();
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.labels = labels.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
 
-    let stats = serde_json::json!({
-        "stream": stream_name,
-        "time": time,
-        "ingestion": {
-            "count": stats.events,
-            "size": format!("{} {}", stats.ingestion, "Bytes"),
-            "format": "json"
-        },
-        "storage": {
-            "size": format!("{} {}", stats.storage, "Bytes"),
-            "format": "parquet"
-        }
-    });
+    STREAM_INFO.set_labels(&stream_name, labels)?;
+    Ok((
+        format!("set labels for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
 
-    Ok((web::Json(stats), StatusCode::OK))
+pub async fn get_log_pattern(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let log_pattern = STREAM_INFO.log_pattern(&stream_name)?;
+    Ok((web::Json(log_pattern), StatusCode::OK))
+}
+
+pub async fn put_log_pattern(
+    req: HttpRequest,
+    body: web::Json<Option<String>>,
+) -> Result<impl Responder, StreamError> {
+    let log_pattern = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    if let Some(pattern) = &log_pattern {
+        text::compile_pattern(pattern).map_err(StreamError::InvalidLogPattern)?;
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.log_pattern = log_pattern.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_log_pattern(&stream_name, log_pattern)?;
+    Ok((
+        format!("set log pattern for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_severity_mapping(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let severity_mapping = STREAM_INFO.severity_mapping(&stream_name)?;
+    Ok((web::Json(severity_mapping), StatusCode::OK))
+}
+
+pub async fn put_severity_mapping(
+    req: HttpRequest,
+    body: web::Json<Option<SeverityMapping>>,
+) -> Result<impl Responder, StreamError> {
+    let severity_mapping = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    if let Some(mapping) = &severity_mapping {
+        mapping
+            .validate()
+            .map_err(StreamError::InvalidSeverityMapping)?;
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.severity_mapping = severity_mapping.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_severity_mapping(&stream_name, severity_mapping)?;
+    Ok((
+        format!("set severity mapping for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_unknown_severity(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let unknown_severity = STREAM_INFO.unknown_severity(&stream_name)?;
+    Ok((web::Json(unknown_severity), StatusCode::OK))
+}
+
+pub async fn put_unknown_severity(
+    req: HttpRequest,
+    body: web::Json<UnknownSeverity>,
+) -> Result<impl Responder, StreamError> {
+    let unknown_severity = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.unknown_severity = unknown_severity;
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_unknown_severity(&stream_name, unknown_severity)?;
+    Ok((
+        format!("set handling of unknown severity numbers for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_body_config(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let body_config = STREAM_INFO.body_config(&stream_name)?;
+    Ok((web::Json(body_config), StatusCode::OK))
+}
+
+pub async fn put_body_config(
+    req: HttpRequest,
+    body: web::Json<Option<BodyConfig>>,
+) -> Result<impl Responder, StreamError> {
+    let body_config = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    if let Some(config) = &body_config {
+        let timestamp_key = STREAM_INFO.timestamp_key(&stream_name)?;
+        config
+            .validate(&timestamp_key)
+            .map_err(StreamError::InvalidBodyConfig)?;
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.body_config = body_config.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_body_config(&stream_name, body_config)?;
+    Ok((
+        format!("set body config for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_shadow(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let shadow = STREAM_INFO.shadow(&stream_name)?;
+    Ok((web::Json(shadow), StatusCode::OK))
+}
+
+// Events of the stream are ingested into the shadow stream as well, with the
+// field changes of the config applied. The shadow stream is created if it does not exist
+pub async fn put_shadow(
+    req: HttpRequest,
+    body: web::Json<Shadow>,
+) -> Result<impl Responder, StreamError> {
+    let mut shadow = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    // a shadow stream created here has the default timestamp key
+    let timestamp_key = STREAM_INFO
+        .timestamp_key(&shadow.stream)
+        .unwrap_or_else(|_| event::DEFAULT_TIMESTAMP_KEY.to_string());
+    shadow
+        .validate(&stream_name, &timestamp_key)
+        .map_err(StreamError::InvalidShadow)?;
+    // live events already went to the same shadow stream since the previous config
+    shadow.since = STREAM_INFO
+        .shadow(&stream_name)?
+        .filter(|current| current.stream == shadow.stream)
+        .and_then(|current| current.since)
+        .or_else(|| Some(Utc::now()));
+    if !STREAM_INFO.stream_exists(&shadow.stream) {
+        create_stream(shadow.stream.clone()).await?;
+    }
+
+    set_shadow(&stream_name, Some(shadow.clone())).await?;
+    Ok((
+        format!(
+            "events of log stream {stream_name} are ingested into shadow stream {} as well",
+            shadow.stream
+        ),
+        StatusCode::OK,
+    ))
+}
+
+// Stops shadow ingestion, the shadow stream and its data are kept
+pub async fn delete_shadow(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    let Some(shadow) = STREAM_INFO.shadow(&stream_name)? else {
+        return Err(StreamError::NoShadowSet(stream_name));
+    };
+
+    set_shadow(&stream_name, None).await?;
+    Ok((
+        format!(
+            "stopped shadow ingestion of log stream {stream_name} into {}",
+            shadow.stream
+        ),
+        StatusCode::OK,
+    ))
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RebuildRequest {
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+}
+
+// Status of the latest rebuild of the streams derived from this stream
+pub async fn get_rebuild(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let job = latest_rebuild(&stream_name).await?;
+    Ok((web::Json(job), StatusCode::OK))
+}
+
+// Starts replaying the events of a time range into the streams derived from
+// this stream by routing or a shadow stream
+pub async fn put_rebuild(
+    req: HttpRequest,
+    body: web::Json<RebuildRequest>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let RebuildRequest {
+        start_time,
+        end_time,
+    } = body.into_inner();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+    if STREAM_INFO.routing(&stream_name)?.is_none() && STREAM_INFO.shadow(&stream_name)?.is_none() {
+        return Err(StreamError::InvalidRebuild(format!(
+            "log stream {stream_name} has no routing or shadow stream to rebuild"
+        )));
+    }
+    if start_time >= end_time {
+        return Err(StreamError::InvalidRebuild(
+            "start time must be before end time".to_string(),
+        ));
+    }
+
+    start_rebuild(&stream_name, RebuildJob::new(start_time, end_time)).await
+}
+
+// Resumes a failed, cancelled or interrupted rebuild from where it stopped
+pub async fn resume_rebuild(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let job = latest_rebuild(&stream_name).await?;
+    if job.state == RebuildState::Completed {
+        return Err(StreamError::InvalidRebuild(format!(
+            "rebuild {} is completed already",
+            job.id
+        )));
+    }
+    start_rebuild(&stream_name, job).await
+}
+
+// Stops the running rebuild, events replayed until then are kept
+pub async fn delete_rebuild(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let Some(job) = REBUILDS.cancel(&stream_name) else {
+        return Err(StreamError::RebuildNotFound(stream_name));
+    };
+    rebuild::save(&stream_name, &job).await;
+    Ok((web::Json(job), StatusCode::OK))
+}
+
+async fn latest_rebuild(stream_name: &str) -> Result<RebuildJob, StreamError> {
+    if !metadata::STREAM_INFO.stream_exists(stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name.to_string()));
+    }
+    if let Some(job) = REBUILDS.get(stream_name) {
+        return Ok(job);
+    }
+    // jobs of earlier runs of the server are only in object storage
+    let storage = CONFIG.storage().get_object_store();
+    storage
+        .get_rebuild_job(stream_name)
+        .await?
+        .ok_or_else(|| StreamError::RebuildNotFound(stream_name.to_string()))
+}
+
+async fn start_rebuild(
+    stream_name: &str,
+    job: RebuildJob,
+) -> Result<(web::Json<RebuildJob>, StatusCode), StreamError> {
+    let job = REBUILDS
+        .begin(stream_name, job)
+        .map_err(StreamError::RebuildRunning)?;
+    rebuild::save(stream_name, &job).await;
+    actix_web::rt::spawn(rebuild::run(stream_name.to_string(), job.clone()));
+    Ok((web::Json(job), StatusCode::ACCEPTED))
+}
+
+pub async fn get_routing(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let routing = STREAM_INFO.routing(&stream_name)?;
+    Ok((web::Json(routing), StatusCode::OK))
+}
+
+// Events ingested into the stream are dispatched to derived streams by the
+// value of the routing field, setting it to null stops routing
+pub async fn put_routing(
+    req: HttpRequest,
+    body: web::Json<Option<Routing>>,
+) -> Result<impl Responder, StreamError> {
+    let mut routing = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    if let Some(routing) = &mut routing {
+        routing.validate().map_err(StreamError::InvalidRouting)?;
+        if let Some(template) = &routing.template {
+            get_template(template).await?;
+        }
+        // live events were already routed the same way since the previous config
+        routing.since = STREAM_INFO
+            .routing(&stream_name)?
+            .filter(|current| current.field == routing.field && current.stream == routing.stream)
+            .and_then(|current| current.since)
+            .or_else(|| Some(Utc::now()));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.routing = routing.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_routing(&stream_name, routing)?;
+    Ok((
+        format!("set routing for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_schema_lock(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let lock = STREAM_INFO.schema_lock(&stream_name)?;
+    Ok((web::Json(lock), StatusCode::OK))
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockSchemaRequest {
+    #[serde(default)]
+    on_new_column: OnNewColumn,
+}
+
+// Freeze the current schema of the stream, events adding columns are then
+// rejected or have their new fields moved to the overflow column
+pub async fn lock_schema(
+    req: HttpRequest,
+    body: Option<web::Json<LockSchemaRequest>>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let body = body.map(web::Json::into_inner).unwrap_or_default();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+    if STREAM_INFO.schema(&stream_name)?.fields().is_empty() {
+        return Err(StreamError::UninitializedLogstream);
+    }
+
+    let key = extract_session_key_from_req(&req).map_err(|err| StreamError::Custom {
+        msg: err.to_string(),
+        status: StatusCode::UNAUTHORIZED,
+    })?;
+    let lock = SchemaLock {
+        on_new_column: body.on_new_column,
+        locked_by: Users.get_username(&key).unwrap_or_default(),
+        locked_at: Utc::now(),
+    };
+
+    set_schema_lock(&stream_name, Some(lock)).await?;
+    Ok((
+        format!("locked schema of log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn unlock_schema(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if STREAM_INFO.schema_lock(&stream_name)?.is_none() {
+        return Err(StreamError::SchemaNotLocked(stream_name));
+    }
+
+    set_schema_lock(&stream_name, None).await?;
+    Ok((
+        format!("unlocked schema of log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+async fn set_schema_lock(stream_name: &str, lock: Option<SchemaLock>) -> Result<(), StreamError> {
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(stream_name).await?;
+    stream_metadata.schema_lock = lock.clone();
+    storage
+        .put_stream_manifest(stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_schema_lock(stream_name, lock)?;
+    Ok(())
+}
+
+async fn set_shadow(stream_name: &str, shadow: Option<Shadow>) -> Result<(), StreamError> {
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(stream_name).await?;
+    stream_metadata.shadow = shadow.clone();
+    storage
+        .put_stream_manifest(stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_shadow(stream_name, shadow)?;
+    Ok(())
+}
+
+pub async fn get_quota(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let status = STREAM_INFO
+        .quota(&stream_name)?
+        .map(|quota| quota.status(quota::usage(&stream_name)));
+    Ok((web::Json(status), StatusCode::OK))
+}
+
+pub async fn put_quota(
+    req: HttpRequest,
+    body: web::Json<Option<IngestQuota>>,
+) -> Result<impl Responder, StreamError> {
+    let quota = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    if let Some(quota) = &quota {
+        quota.validate().map_err(StreamError::InvalidQuota)?;
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.quota = quota.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_quota(&stream_name, quota)?;
+    Ok((
+        format!("set ingestion quota for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_sampling(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let sampling = STREAM_INFO.sampling(&stream_name)?;
+    Ok((web::Json(sampling), StatusCode::OK))
+}
+
+// With sampling set only a weighted sample of the events of every window is
+// ingested, setting it to null ingests every event again
+pub async fn put_sampling(
+    req: HttpRequest,
+    body: web::Json<Option<Sampling>>,
+) -> Result<impl Responder, StreamError> {
+    let sampling = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    if let Some(sampling) = &sampling {
+        sampling.validate().map_err(StreamError::InvalidSampling)?;
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.sampling = sampling.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_sampling(&stream_name, sampling)?;
+    Ok((
+        format!("set sampling for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_dedup(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let dedup = STREAM_INFO.dedup(&stream_name)?;
+    Ok((web::Json(dedup), StatusCode::OK))
+}
+
+// With dedup set events repeating the previous event of the stream are stored
+// as a count, setting it to null ingests every event again
+pub async fn put_dedup(
+    req: HttpRequest,
+    body: web::Json<Option<Dedup>>,
+) -> Result<impl Responder, StreamError> {
+    let dedup = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    if let Some(dedup) = &dedup {
+        dedup.validate().map_err(StreamError::InvalidDedup)?;
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.dedup = dedup.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_dedup(&stream_name, dedup)?;
+    Ok((
+        format!("set dedup for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+// smallest size parquet files can be limited to, smaller files would only add
+// overhead to every query
+const MIN_MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+pub async fn get_max_file_size(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let max_file_size = STREAM_INFO.max_file_size(&stream_name)?;
+    Ok((web::Json(max_file_size), StatusCode::OK))
+}
+
+// Parquet files written from now on are rotated once they reach the size,
+// setting it to null writes a single file per minute again
+pub async fn put_max_file_size(
+    req: HttpRequest,
+    body: web::Json<Option<u64>>,
+) -> Result<impl Responder, StreamError> {
+    let max_file_size = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    if max_file_size.is_some_and(|size| size < MIN_MAX_FILE_SIZE) {
+        return Err(StreamError::InvalidMaxFileSize(format!(
+            "must be at least {MIN_MAX_FILE_SIZE} bytes"
+        )));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.max_file_size = max_file_size;
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_max_file_size(&stream_name, max_file_size)?;
+    Ok((
+        format!("set max file size for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_attribute_map(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let attribute_map = STREAM_INFO.attribute_map(&stream_name)?;
+    Ok((web::Json(attribute_map), StatusCode::OK))
+}
+
+// With an attribute map set, attributes of new events are stored in a single
+// map column instead of a column each, setting it to null flattens them again
+pub async fn put_attribute_map(
+    req: HttpRequest,
+    body: web::Json<Option<AttributeMap>>,
+) -> Result<impl Responder, StreamError> {
+    let attribute_map = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    if let Some(attribute_map) = &attribute_map {
+        attribute_map
+            .validate()
+            .map_err(StreamError::InvalidAttributeMap)?;
+        // events ingested before would have a column of this name already
+        let column = attribute_map.column();
+        let schema = STREAM_INFO.schema(&stream_name)?;
+        if let Ok(field) = schema.field_with_name(column) {
+            if field.data_type() != &attributes::map_data_type() {
+                return Err(StreamError::InvalidAttributeMap(format!(
+                    "column {column} exists with type {}",
+                    field.data_type()
+                )));
+            }
+        }
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.attribute_map = attribute_map.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_attribute_map(&stream_name, attribute_map)?;
+    Ok((
+        format!("set attribute map for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_attribute_filter(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let attribute_filter = STREAM_INFO.attribute_filter(&stream_name)?;
+    Ok((web::Json(attribute_filter), StatusCode::OK))
+}
+
+// Events ingested from now on only keep the attributes allowed by the filter,
+// columns of attributes dropped before stay in the schema
+pub async fn put_attribute_filter(
+    req: HttpRequest,
+    body: web::Json<Option<AttributeFilter>>,
+) -> Result<impl Responder, StreamError> {
+    let attribute_filter = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    if let Some(attribute_filter) = &attribute_filter {
+        attribute_filter
+            .validate()
+            .map_err(StreamError::InvalidAttributeFilter)?;
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.attribute_filter = attribute_filter.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_attribute_filter(&stream_name, attribute_filter)?;
+    Ok((
+        format!("set attribute filter for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_ip_mask(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let ip_mask = STREAM_INFO.ip_mask(&stream_name)?;
+    Ok((web::Json(ip_mask), StatusCode::OK))
+}
+
+// Addresses of events ingested from now on are masked, setting it to null
+// stores them in full again
+pub async fn put_ip_mask(
+    req: HttpRequest,
+    body: web::Json<Option<IpMask>>,
+) -> Result<impl Responder, StreamError> {
+    let ip_mask = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    if let Some(ip_mask) = &ip_mask {
+        ip_mask.validate().map_err(StreamError::InvalidIpMask)?;
+        if STREAM_INFO.raw_payload(&stream_name)?.is_some() {
+            return Err(StreamError::InvalidIpMask(
+                "raw payloads of the stream would keep addresses in full, stop storing them first"
+                    .to_string(),
+            ));
+        }
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.ip_mask = ip_mask.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_ip_mask(&stream_name, ip_mask)?;
+    Ok((
+        format!("set ip mask for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_default_view(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let default_view = STREAM_INFO.default_view(&stream_name)?;
+    Ok((web::Json(default_view), StatusCode::OK))
+}
+
+// The view is only kept for the console to run when the stream is opened,
+// queries of the stream are not affected by it
+pub async fn put_default_view(
+    req: HttpRequest,
+    body: web::Json<Option<DefaultView>>,
+) -> Result<impl Responder, StreamError> {
+    let default_view = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    if let Some(default_view) = &default_view {
+        default_view
+            .validate()
+            .map_err(StreamError::InvalidDefaultView)?;
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.default_view = default_view.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_default_view(&stream_name, default_view)?;
+    Ok((
+        format!("set default view for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_raw_payload(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let raw_payload = STREAM_INFO.raw_payload(&stream_name)?;
+    Ok((web::Json(raw_payload), StatusCode::OK))
+}
+
+// Events ingested from now on keep their raw payload, setting it to null stops
+// storing them while the `_raw` column stays in the schema
+pub async fn put_raw_payload(
+    req: HttpRequest,
+    body: web::Json<Option<RawPayload>>,
+) -> Result<impl Responder, StreamError> {
+    let raw_payload = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    if let Some(raw_payload) = &raw_payload {
+        raw_payload
+            .validate()
+            .map_err(StreamError::InvalidRawPayload)?;
+        if STREAM_INFO.ip_mask(&stream_name)?.is_some() {
+            return Err(StreamError::InvalidRawPayload(
+                "raw payloads would keep the addresses masked by the stream in full".to_string(),
+            ));
+        }
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.raw_payload = raw_payload.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_raw_payload(&stream_name, raw_payload)?;
+    Ok((
+        format!("set raw payload storage for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_clock_skew(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let clock_skew = STREAM_INFO.clock_skew(&stream_name)?;
+    Ok((web::Json(clock_skew), StatusCode::OK))
+}
+
+// Times of OTLP log records ingested from now on are corrected, setting it to
+// null stores them as they are sent again
+pub async fn put_clock_skew(
+    req: HttpRequest,
+    body: web::Json<Option<ClockSkew>>,
+) -> Result<impl Responder, StreamError> {
+    let clock_skew = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    if let Some(clock_skew) = &clock_skew {
+        clock_skew
+            .validate()
+            .map_err(StreamError::InvalidClockSkew)?;
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.clock_skew = clock_skew.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_clock_skew(&stream_name, clock_skew)?;
+    Ok((
+        format!("set clock skew correction for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_key_sanitization(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let key_sanitization = STREAM_INFO.key_sanitization(&stream_name)?;
+    Ok((web::Json(key_sanitization), StatusCode::OK))
+}
+
+// Keys of events ingested from now on are sanitized, setting it to null stores
+// new keys as they are sent. Keys already mapped keep their column either way.
+pub async fn put_key_sanitization(
+    req: HttpRequest,
+    body: web::Json<Option<KeySanitization>>,
+) -> Result<impl Responder, StreamError> {
+    let key_sanitization = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.key_sanitization = key_sanitization.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_key_sanitization(&stream_name, key_sanitization)?;
+    Ok((
+        format!("set key sanitization for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+// Original keys of the stream stored under another column
+pub async fn get_key_mapping(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let key_mapping = STREAM_INFO.key_mapping(&stream_name)?;
+    Ok((web::Json(key_mapping), StatusCode::OK))
+}
+
+pub async fn get_number_mode(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let number_mode = STREAM_INFO.number_mode(&stream_name)?;
+    Ok((web::Json(number_mode), StatusCode::OK))
+}
+
+// Applies to events ingested from now on, existing columns keep their type
+pub async fn put_number_mode(
+    req: HttpRequest,
+    body: web::Json<NumberMode>,
+) -> Result<impl Responder, StreamError> {
+    let number_mode = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.number_mode = number_mode;
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_number_mode(&stream_name, number_mode)?;
+    Ok((
+        format!("set number mode for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_type_conflict(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let type_conflict = STREAM_INFO.type_conflict(&stream_name)?;
+    Ok((web::Json(type_conflict), StatusCode::OK))
+}
+
+// Applies to events ingested from now on, existing columns keep their type
+pub async fn put_type_conflict(
+    req: HttpRequest,
+    body: web::Json<TypeConflict>,
+) -> Result<impl Responder, StreamError> {
+    let type_conflict = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.type_conflict = type_conflict;
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_type_conflict(&stream_name, type_conflict)?;
+    Ok((
+        format!("set type conflict handling for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_compression(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let compression = STREAM_INFO.compression(&stream_name)?;
+    Ok((web::Json(compression), StatusCode::OK))
+}
+
+pub async fn put_compression(
+    req: HttpRequest,
+    body: web::Json<Option<StreamCompression>>,
+) -> Result<impl Responder, StreamError> {
+    let compression = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    if let Some(compression) = &compression {
+        compression
+            .validate()
+            .map_err(StreamError::InvalidCompression)?;
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.compression = compression;
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_compression(&stream_name, compression)?;
+    Ok((
+        format!("set parquet compression for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_staging_compression(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let staging_compression = STREAM_INFO.staging_compression(&stream_name)?;
+    Ok((web::Json(staging_compression), StatusCode::OK))
+}
+
+// Staging files opened from now on are compressed with the codec, setting it
+// to null stages uncompressed again
+pub async fn put_staging_compression(
+    req: HttpRequest,
+    body: web::Json<Option<StagingCompression>>,
+) -> Result<impl Responder, StreamError> {
+    let staging_compression = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.staging_compression = staging_compression;
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_staging_compression(&stream_name, staging_compression)?;
+    Ok((
+        format!("set staging compression for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_partitioning(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let partitioning = STREAM_INFO.partitioning(&stream_name)?;
+    Ok((web::Json(partitioning), StatusCode::OK))
+}
+
+// partitioning applies to data staged from now on, existing files keep their layout
+pub async fn put_partitioning(
+    req: HttpRequest,
+    body: web::Json<Option<Partitioning>>,
+) -> Result<impl Responder, StreamError> {
+    let partitioning = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    if let Some(partitioning) = &partitioning {
+        partitioning
+            .validate()
+            .map_err(StreamError::InvalidPartitioning)?;
+        let timestamp_key = STREAM_INFO.timestamp_key(&stream_name)?;
+        if partitioning.columns.contains(&timestamp_key) {
+            return Err(StreamError::InvalidPartitioning(format!(
+                "timestamp column {timestamp_key} can not be used as a partition column"
+            )));
+        }
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.partitioning = partitioning.clone();
+    stream_metadata.partition_values.clear();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_partitioning(&stream_name, partitioning)?;
+    partition::reset(&stream_name);
+    Ok((
+        format!("set partitioning for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+// Handler for POST /api/v1/logstream/{logstream}/ingestkey
+// the key is only part of this response, the stream keeps its hash
+pub async fn create_ingest_key(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let (ingest_key, key) = IngestKey::generate();
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.ingest_keys.push(ingest_key.clone());
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_ingest_keys(&stream_name, stream_metadata.ingest_keys)?;
+    Ok((
+        web::Json(serde_json::json!({
+            "id": ingest_key.id,
+            "key": key,
+            "createdAt": ingest_key.created_at,
+        })),
+        StatusCode::OK,
+    ))
+}
+
+// Handler for GET /api/v1/logstream/{logstream}/ingestkey
+pub async fn list_ingest_keys(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let ingest_keys: Vec<_> = STREAM_INFO
+        .ingest_keys(&stream_name)?
+        .into_iter()
+        .map(|ingest_key| {
+            serde_json::json!({
+                "id": ingest_key.id,
+                "createdAt": ingest_key.created_at,
+            })
+        })
+        .collect();
+    Ok((web::Json(ingest_keys), StatusCode::OK))
+}
+
+// Handler for DELETE /api/v1/logstream/{logstream}/ingestkey/{id}
+// once the last key is deleted the stream accepts user credentials for ingest again
+pub async fn delete_ingest_key(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let id: String = req.match_info().get("id").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    let count = stream_metadata.ingest_keys.len();
+    stream_metadata
+        .ingest_keys
+        .retain(|ingest_key| ingest_key.id != id);
+    if stream_metadata.ingest_keys.len() == count {
+        return Err(StreamError::IngestKeyNotFound(id));
+    }
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_ingest_keys(&stream_name, stream_metadata.ingest_keys)?;
+    Ok((
+        format!("deleted ingest key {id} of log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_timestamp_key(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let timestamp_key = STREAM_INFO.timestamp_key(&stream_name)?;
+    Ok((web::Json(timestamp_key), StatusCode::OK))
+}
+
+pub async fn put_timestamp_key(
+    req: HttpRequest,
+    body: web::Json<String>,
+) -> Result<impl Responder, StreamError> {
+    let timestamp_key = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    validator::timestamp_key(&timestamp_key)
+        .map_err(|err| StreamError::InvalidTimestampKey(err.to_string()))?;
+
+    let schema = STREAM_INFO.schema(&stream_name)?;
+    if schema.field_with_name(&timestamp_key).is_ok() {
+        return Err(StreamError::InvalidTimestampKey(format!(
+            "column {timestamp_key} already exists in the schema of log stream {stream_name}"
+        )));
+    }
+    // existing data files are sorted and partitioned on the current timestamp column
+    if !schema.fields().is_empty() {
+        return Err(StreamError::InvalidTimestampKey(
+            "timestamp column can only be set before the first event is sent to this log stream"
+                .to_string(),
+        ));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.timestamp_key = Some(timestamp_key.clone());
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_timestamp_key(&stream_name, timestamp_key.clone())?;
+    Ok((
+        format!("set timestamp column to {timestamp_key} for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_time_fields(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let time_fields = STREAM_INFO.time_fields(&stream_name)?;
+    Ok((web::Json(time_fields), StatusCode::OK))
+}
+
+// Fields listed here are ingested as timestamp columns the first time they are
+// seen if all their values in that event are RFC3339 or epoch timestamps
+pub async fn put_time_fields(
+    req: HttpRequest,
+    body: web::Json<Vec<String>>,
+) -> Result<impl Responder, StreamError> {
+    let time_fields = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let timestamp_key = STREAM_INFO.timestamp_key(&stream_name)?;
+    let schema = STREAM_INFO.schema(&stream_name)?;
+    for field in &time_fields {
+        if field == &timestamp_key {
+            return Err(StreamError::InvalidTimeFields(format!(
+                "{field} is the timestamp column of log stream {stream_name}"
+            )));
+        }
+        validator::timestamp_key(field)
+            .map_err(|err| StreamError::InvalidTimeFields(err.to_string()))?;
+        // the type of an existing column can not change
+        if let Ok(existing) = schema.field_with_name(field) {
+            if !matches!(existing.data_type(), DataType::Timestamp(_, _)) {
+                return Err(StreamError::InvalidTimeFields(format!(
+                    "column {field} already exists in log stream {stream_name} as {}",
+                    existing.data_type()
+                )));
+            }
+        }
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.time_fields = time_fields.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_time_fields(&stream_name, time_fields)?;
+    Ok((
+        format!("set time fields for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_column_order(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let column_order = STREAM_INFO.column_order(&stream_name)?;
+    Ok((web::Json(column_order), StatusCode::OK))
+}
+
+// Columns listed here lead the results of queries on this stream, the order
+// only affects query responses and not how the data is stored
+pub async fn put_column_order(
+    req: HttpRequest,
+    body: web::Json<Vec<String>>,
+) -> Result<impl Responder, StreamError> {
+    let column_order = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    if column_order.iter().any(String::is_empty) {
+        return Err(StreamError::InvalidColumnOrder(
+            "column name can not be empty".to_string(),
+        ));
+    }
+    if let Some(column) = column_order.iter().duplicates().next() {
+        return Err(StreamError::InvalidColumnOrder(format!(
+            "column {column} is listed more than once"
+        )));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.column_order = column_order.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_column_order(&stream_name, column_order)?;
+    Ok((
+        format!("set column order for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_casts(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let casts = STREAM_INFO.casts(&stream_name)?;
+    Ok((web::Json(casts), StatusCode::OK))
+}
+
+// Cast columns are computed from the stored columns when querying the stream,
+// stored data is left as is. An empty list removes them
+pub async fn put_casts(
+    req: HttpRequest,
+    body: web::Json<Vec<CastColumn>>,
+) -> Result<impl Responder, StreamError> {
+    let casts = body.into_inner();
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let schema = STREAM_INFO.schema(&stream_name)?;
+    casts::validate(&casts, &schema).map_err(StreamError::InvalidCasts)?;
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.casts = casts.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_casts(&stream_name, casts)?;
+    Ok((
+        format!("set cast columns for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_stats(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let stats = stats::get_current_stats(&stream_name, "json")
+        .ok_or(StreamError::StreamNotFound(stream_name.clone()))?;
+
+    let time = Utc::now();
+
+    let stats = serde_json::json!({
+        "stream": stream_name,
+        "time": time,
+        "ingestion": {
+            "count": stats.events,
+            "size": format!("{} {}", stats.ingestion, "Bytes"),
+            "format": "json"
+        },
+        "storage": {
+            "size": format!("{} {}", stats.storage, "Bytes"),
+            "files": stats.files,
+            "format": "parquet"
+        },
+        "deleted": {
+            "count": stats.deleted_events,
+            "size": format!("{} {}", stats.deleted_ingestion, "Bytes"),
+            "format": "json"
+        },
+        "schemaVersions": stats.schema_versions
+    });
+
+    Ok((web::Json(stats), StatusCode::OK))
+}
+
+// Handler for GET /api/v1/stats/labels/{label}
+// stats of the streams visible to the caller summed per value of a label,
+// streams without the label are reported as unlabeled
+pub async fn get_stats_by_label(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let label: String = req.match_info().get("label").unwrap().parse().unwrap();
+    let key = extract_session_key_from_req(&req).map_err(|err| StreamError::Custom {
+        msg: err.to_string(),
+        status: StatusCode::UNAUTHORIZED,
+    })?;
+
+    let mut streams = Vec::new();
+    for stream_name in STREAM_INFO.list_streams() {
+        if !matches!(
+            Users.authorize(key.clone(), Action::GetStats, Some(&stream_name), None),
+            rbac::Response::Authorized
+        ) {
+            continue;
+        }
+        let (Ok(labels), Some(stats)) = (
+            STREAM_INFO.labels(&stream_name),
+            stats::get_current_stats(&stream_name, "json"),
+        ) else {
+            continue;
+        };
+        streams.push((labels, stats));
+    }
+
+    let mut groups = stats::rollup(
+        streams.iter().map(|(labels, stats)| (labels, *stats)),
+        &label,
+    );
+    let unlabeled = groups.remove(&None);
+    let groups: BTreeMap<String, stats::Stats> = groups
+        .into_iter()
+        .filter_map(|(value, stats)| Some((value?, stats)))
+        .collect();
+
+    let stats = serde_json::json!({
+        "label": label,
+        "time": Utc::now(),
+        "groups": groups,
+        "unlabeled": unlabeled,
+    });
+
+    Ok((web::Json(stats), StatusCode::OK))
+}
+
+// Handler for GET /api/v1/logstream/{logstream}/stats/partitions
+// bytes and files in the object store per date and partition column value
+pub async fn get_partition_stats(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let dates = catalog::usage_by_date(storage, &stream_name).await?;
+    let stats = serde_json::json!({
+        "stream": stream_name,
+        "dates": dates,
+    });
+
+    Ok((web::Json(stats), StatusCode::OK))
+}
+
+/// Summary of a column in the schema of a stream
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldSummary {
+    pub name: String,
+    pub data_type: String,
+    pub nullable: bool,
+}
+
+/// Metadata, configuration and stats of a stream in a single response
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamInfo {
+    pub stream: String,
+    pub created_at: String,
+    pub stats: stats::Stats,
+    pub schema: Vec<FieldSummary>,
+    pub retention: Retention,
+    pub timestamp_key: String,
+    pub cache_enabled: bool,
+    pub log_pattern: Option<String>,
+    pub severity_mapping: Option<SeverityMapping>,
+    pub body_config: Option<BodyConfig>,
+    pub shadow: Option<Shadow>,
+    pub schema_lock: Option<SchemaLock>,
+    pub quota: Option<QuotaStatus>,
+    pub compression: Option<StreamCompression>,
+    pub staging_compression: Option<StagingCompression>,
+    pub template: Option<String>,
+    pub default_view: Option<DefaultView>,
+}
+
+// Handler for GET /api/v1/logstream/{logstream}/info
+pub async fn get_stream_info(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    let retention = storage.get_retention(&stream_name).await?;
+    let stats = stats::get_current_stats(&stream_name, "json")
+        .ok_or(StreamError::StreamNotFound(stream_name.clone()))?;
+    let schema = STREAM_INFO
+        .schema(&stream_name)?
+        .fields()
+        .iter()
+        .map(|field| FieldSummary {
+            name: field.name().clone(),
+            data_type: field.data_type().to_string(),
+            nullable: field.is_nullable(),
+        })
+        .collect();
+
+    let info = StreamInfo {
+        created_at: stream_metadata.created_at,
+        template: stream_metadata.template,
+        stats,
+        schema,
+        retention,
+        timestamp_key: STREAM_INFO.timestamp_key(&stream_name)?,
+        cache_enabled: STREAM_INFO.cache_enabled(&stream_name)?,
+        log_pattern: STREAM_INFO.log_pattern(&stream_name)?,
+        severity_mapping: STREAM_INFO.severity_mapping(&stream_name)?,
+        body_config: STREAM_INFO.body_config(&stream_name)?,
+        shadow: STREAM_INFO.shadow(&stream_name)?,
+        schema_lock: STREAM_INFO.schema_lock(&stream_name)?,
+        quota: STREAM_INFO
+            .quota(&stream_name)?
+            .map(|quota| quota.status(quota::usage(&stream_name))),
+        compression: STREAM_INFO.compression(&stream_name)?,
+        staging_compression: STREAM_INFO.staging_compression(&stream_name)?,
+        default_view: STREAM_INFO.default_view(&stream_name)?,
+        stream: stream_name,
+    };
+
+    Ok((web::Json(info), StatusCode::OK))
+}
+
+// Handler for GET /api/v1/logstream/{logstream}/retention/history
+// lists what every run of the retention task deleted, most recent first
+pub async fn get_retention_history(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let mut history = CONFIG
+        .storage()
+        .get_object_store()
+        .get_retention_history(&stream_name)
+        .await?;
+    history.reverse();
+
+    Ok((web::Json(history), StatusCode::OK))
+}
+
+// Handler for DELETE /api/v1/logstream/{logstream}/partition/{date}
+// removes all data of the stream for the given date (YYYY-MM-DD).
+// The date has to be in the past and within the retention period of the stream,
+// pass force=true to delete the current date or a date outside retention.
+pub async fn delete_partition(
+    req: HttpRequest,
+    params: web::Query<HashMap<String, bool>>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let date: String = req.match_info().get("date").unwrap().parse().unwrap();
+    let force = params.get("force").copied().unwrap_or(false);
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let date = NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+        .map_err(|_| StreamError::InvalidPartitionDate(date))?;
+
+    let storage = CONFIG.storage().get_object_store();
+    if !force {
+        let today = Utc::now().date_naive();
+        let retain_from = storage
+            .get_retention(&stream_name)
+            .await?
+            .delete_after_days()
+            .map(|days| today - Days::new(days as u64));
+        if date >= today || retain_from.is_some_and(|retain_from| date < retain_from) {
+            return Err(StreamError::PartitionOutsideRetention(date.to_string()));
+        }
+    }
+
+    let Some((removed, _)) =
+        catalog::remove_date_partition(storage.clone(), &stream_name, date).await?
+    else {
+        return Err(StreamError::PartitionNotFound(date.to_string()));
+    };
+
+    stats::record_deleted(&stream_name, "json", &removed);
+    if let Some(stats) = stats::get_current_stats(&stream_name, "json") {
+        storage.put_stats(&stream_name, &stats).await?;
+    }
+
+    let removed = serde_json::json!({
+        "stream": stream_name,
+        "date": date,
+        "count": removed.events,
+        "ingestionSize": removed.ingestion,
+        "storageSize": removed.storage,
+    });
+
+    Ok((web::Json(removed), StatusCode::OK))
+}
+
+// Handler for GET /api/v1/logstream/{logstream}/schema/diff?from=YYYY-MM-DD&to=YYYY-MM-DD
+// returns fields added, removed or changed in type between the two dates
+pub async fn get_schema_diff(
+    req: HttpRequest,
+    params: web::Query<HashMap<String, String>>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !metadata::STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let parse_date = |key: &str| {
+        let value = params.get(key).cloned().unwrap_or_default();
+        NaiveDate::parse_from_str(&value, "%Y-%m-%d")
+            .map_err(|_| StreamError::InvalidPartitionDate(value))
+    };
+    let from = parse_date("from")?;
+    let to = parse_date("to")?;
+    if from > to {
+        return Err(StreamError::InvalidDateRange(
+            from.to_string(),
+            to.to_string(),
+        ));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let diff = catalog::schema_diff(storage, &stream_name, from, to).await?;
+
+    Ok((web::Json(diff), StatusCode::OK))
 }
 
 fn remove_id_from_alerts(value: &mut Value) {
@@ -344,6 +2503,8 @@ pub mod error {
             "Caching not enabled at Parseable server config. Can't enable cache for stream {0}"
         )]
         CacheNotEnabled(String),
+        #[error("Encryption keyfile not set at Parseable server config. Can't encrypt stream {0}")]
+        EncryptionNotConfigured(String),
         #[error("Log stream is not initialized, send an event to this logstream and try again")]
         UninitializedLogstream,
         #[error("Storage Error {0}")]
@@ -365,6 +2526,78 @@ pub mod error {
         InvalidAlertMessage(String, String),
         #[error("failed to set retention configuration due to err: {0}")]
         InvalidRetentionConfig(serde_json::Error),
+        #[error("invalid log pattern: {0}")]
+        InvalidLogPattern(String),
+        #[error("invalid timestamp column: {0}")]
+        InvalidTimestampKey(String),
+        #[error("invalid column order: {0}")]
+        InvalidColumnOrder(String),
+        #[error("invalid cast columns: {0}")]
+        InvalidCasts(String),
+        #[error("invalid time fields: {0}")]
+        InvalidTimeFields(String),
+        #[error("invalid labels: {0}")]
+        InvalidLabels(String),
+        #[error("invalid severity mapping: {0}")]
+        InvalidSeverityMapping(String),
+        #[error("invalid body config: {0}")]
+        InvalidBodyConfig(String),
+        #[error("invalid shadow stream: {0}")]
+        InvalidShadow(String),
+        #[error("log stream {0} has no shadow stream")]
+        NoShadowSet(String),
+        #[error("invalid rebuild: {0}")]
+        InvalidRebuild(String),
+        #[error("rebuild {0} is running already")]
+        RebuildRunning(String),
+        #[error("log stream {0} has no rebuild")]
+        RebuildNotFound(String),
+        #[error("invalid routing: {0}")]
+        InvalidRouting(String),
+        #[error("schema of log stream {0} is not locked")]
+        SchemaNotLocked(String),
+        #[error("invalid quota: {0}")]
+        InvalidQuota(String),
+        #[error("invalid sampling: {0}")]
+        InvalidSampling(String),
+        #[error("invalid dedup: {0}")]
+        InvalidDedup(String),
+        #[error("invalid max file size: {0}")]
+        InvalidMaxFileSize(String),
+        #[error("invalid attribute map: {0}")]
+        InvalidAttributeMap(String),
+        #[error("invalid ip mask: {0}")]
+        InvalidIpMask(String),
+        #[error("invalid attribute filter: {0}")]
+        InvalidAttributeFilter(String),
+        #[error("invalid stream bundle: {0}")]
+        InvalidBundle(String),
+        #[error("invalid clock skew correction: {0}")]
+        InvalidClockSkew(String),
+        #[error("invalid default view: {0}")]
+        InvalidDefaultView(String),
+        #[error("invalid raw payload storage: {0}")]
+        InvalidRawPayload(String),
+        #[error("ingest key {0} does not exist")]
+        IngestKeyNotFound(String),
+        #[error("invalid compression: {0}")]
+        InvalidCompression(String),
+        #[error("invalid partitioning: {0}")]
+        InvalidPartitioning(String),
+        #[error("stream template {0} does not exist")]
+        TemplateNotFound(String),
+        #[error("invalid partition date {0}, expected YYYY-MM-DD")]
+        InvalidPartitionDate(String),
+        #[error("partition {0} is outside the retention period of this stream, use force=true to delete it anyway")]
+        PartitionOutsideRetention(String),
+        #[error("no data found for partition {0}")]
+        PartitionNotFound(String),
+        #[error("invalid date range, {0} is after {1}")]
+        InvalidDateRange(String, String),
+        #[error("invalid tail aggregation: {0}")]
+        InvalidTailAggregate(String),
+        #[error("deleting {0} log streams ({1}) requires confirm to be set to {0}")]
+        BulkDeleteNotConfirmed(usize, String),
         #[error("{msg}")]
         Custom { msg: String, status: StatusCode },
     }
@@ -379,6 +2612,7 @@ pub mod error {
                     StatusCode::INTERNAL_SERVER_ERROR
                 }
                 StreamError::CacheNotEnabled(_) => StatusCode::BAD_REQUEST,
+                StreamError::EncryptionNotConfigured(_) => StatusCode::BAD_REQUEST,
                 StreamError::StreamNotFound(_) => StatusCode::NOT_FOUND,
                 StreamError::Custom { status, .. } => *status,
                 StreamError::UninitializedLogstream => StatusCode::METHOD_NOT_ALLOWED,
@@ -389,6 +2623,42 @@ pub mod error {
                 StreamError::InvalidAlert(_) => StatusCode::BAD_REQUEST,
                 StreamError::InvalidAlertMessage(_, _) => StatusCode::BAD_REQUEST,
                 StreamError::InvalidRetentionConfig(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidLogPattern(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidTimestampKey(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidColumnOrder(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidCasts(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidTimeFields(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidLabels(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidSeverityMapping(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidBodyConfig(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidShadow(_) => StatusCode::BAD_REQUEST,
+                StreamError::NoShadowSet(_) => StatusCode::NOT_FOUND,
+                StreamError::InvalidRebuild(_) => StatusCode::BAD_REQUEST,
+                StreamError::RebuildRunning(_) => StatusCode::CONFLICT,
+                StreamError::RebuildNotFound(_) => StatusCode::NOT_FOUND,
+                StreamError::InvalidRouting(_) => StatusCode::BAD_REQUEST,
+                StreamError::SchemaNotLocked(_) => StatusCode::NOT_FOUND,
+                StreamError::InvalidQuota(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidSampling(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidDedup(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidMaxFileSize(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidAttributeMap(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidIpMask(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidAttributeFilter(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidBundle(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidClockSkew(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidDefaultView(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidRawPayload(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidCompression(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidPartitioning(_) => StatusCode::BAD_REQUEST,
+                StreamError::IngestKeyNotFound(_) => StatusCode::NOT_FOUND,
+                StreamError::TemplateNotFound(_) => StatusCode::NOT_FOUND,
+                StreamError::InvalidPartitionDate(_) => StatusCode::BAD_REQUEST,
+                StreamError::PartitionOutsideRetention(_) => StatusCode::BAD_REQUEST,
+                StreamError::PartitionNotFound(_) => StatusCode::NOT_FOUND,
+                StreamError::InvalidDateRange(_, _) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidTailAggregate(_) => StatusCode::BAD_REQUEST,
+                StreamError::BulkDeleteNotConfirmed(_, _) => StatusCode::BAD_REQUEST,
             }
         }
 
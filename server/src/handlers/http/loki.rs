@@ -0,0 +1,267 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use bytes::Bytes;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use prost::Message;
+use serde_json::Value;
+
+// Loki push API as defined in
+// https://github.com/grafana/loki/blob/main/pkg/push/push.proto
+// Protobuf payloads are snappy (block format) compressed as sent by Promtail.
+
+#[derive(Clone, PartialEq, Message)]
+pub struct PushRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub streams: Vec<StreamAdapter>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct StreamAdapter {
+    #[prost(string, tag = "1")]
+    pub labels: String,
+    #[prost(message, repeated, tag = "2")]
+    pub entries: Vec<EntryAdapter>,
+    #[prost(uint64, tag = "3")]
+    pub hash: u64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct EntryAdapter {
+    #[prost(message, optional, tag = "1")]
+    pub timestamp: Option<Timestamp>,
+    #[prost(string, tag = "2")]
+    pub line: String,
+    #[prost(message, repeated, tag = "3")]
+    pub structured_metadata: Vec<LabelPairAdapter>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct LabelPairAdapter {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Timestamp {
+    #[prost(int64, tag = "1")]
+    pub seconds: i64,
+    #[prost(int32, tag = "2")]
+    pub nanos: i32,
+}
+
+// JSON body of the push API
+// {"streams": [{"stream": {"job": "app"}, "values": [["<unix ns>", "<line>", {<metadata>}]]}]}
+#[derive(Debug, serde::Deserialize)]
+struct JsonPushRequest {
+    streams: Vec<JsonStream>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct JsonStream {
+    #[serde(default)]
+    stream: BTreeMap<String, String>,
+    values: Vec<JsonEntry>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct JsonEntry(String, String, #[serde(default)] BTreeMap<String, String>);
+
+const BODY_KEY: &str = "body";
+const TIMESTAMP_KEY: &str = "timestamp";
+
+// Flatten a protobuf push request into JSON records.
+// Every entry becomes one record with the stream labels and its structured
+// metadata as columns, the log line as `body` and its time as `timestamp`.
+pub fn flatten_loki_protobuf(body: &Bytes) -> Result<Vec<BTreeMap<String, Value>>, anyhow::Error> {
+    let body = snap::raw::Decoder::new().decompress_vec(body)?;
+    let request = PushRequest::decode(body.as_slice())?;
+
+    let mut records = Vec::new();
+    for stream in request.streams {
+        let labels = parse_labels(&stream.labels)?;
+        for entry in stream.entries {
+            let timestamp = entry.timestamp.and_then(|ts| {
+                NaiveDateTime::from_timestamp_opt(ts.seconds, ts.nanos.max(0) as u32)
+            });
+            let metadata = entry
+                .structured_metadata
+                .into_iter()
+                .map(|pair| (pair.name, pair.value));
+            records.push(record(&labels, metadata, entry.line, timestamp));
+        }
+    }
+    Ok(records)
+}
+
+pub fn flatten_loki_json(body: &Bytes) -> Result<Vec<BTreeMap<String, Value>>, anyhow::Error> {
+    let request: JsonPushRequest = serde_json::from_slice(body)?;
+
+    let mut records = Vec::new();
+    for stream in request.streams {
+        for JsonEntry(timestamp, line, metadata) in stream.values {
+            let nanos: i64 = timestamp
+                .parse()
+                .map_err(|_| anyhow::anyhow!("invalid timestamp {timestamp}"))?;
+            let timestamp = NaiveDateTime::from_timestamp_opt(
+                nanos.div_euclid(1_000_000_000),
+                nanos.rem_euclid(1_000_000_000) as u32,
+            );
+            records.push(record(&stream.stream, metadata, line, timestamp));
+        }
+    }
+    Ok(records)
+}
+
+fn record(
+    labels: &BTreeMap<String, String>,
+    metadata: impl IntoIterator<Item = (String, String)>,
+    line: String,
+    timestamp: Option<NaiveDateTime>,
+) -> BTreeMap<String, Value> {
+    let mut record: BTreeMap<String, Value> = labels
+        .iter()
+        .map(|(key, value)| (key.clone(), Value::String(value.clone())))
+        .collect();
+    record.extend(
+        metadata
+            .into_iter()
+            .map(|(key, value)| (key, Value::String(value))),
+    );
+    record.insert(BODY_KEY.to_string(), Value::String(line));
+    if let Some(timestamp) = timestamp {
+        record.insert(
+            TIMESTAMP_KEY.to_string(),
+            Value::String(DateTime::<Utc>::from_naive_utc_and_offset(timestamp, Utc).to_rfc3339()),
+        );
+    }
+    record
+}
+
+// Parse labels in the Prometheus format of the protobuf payload, `{job="app", env="prod"}`
+fn parse_labels(labels: &str) -> Result<BTreeMap<String, String>, anyhow::Error> {
+    let invalid = || anyhow::anyhow!("invalid labels {labels}");
+    let inner = labels
+        .trim()
+        .strip_prefix('{')
+        .and_then(|labels| labels.strip_suffix('}'))
+        .ok_or_else(invalid)?;
+
+    let mut parsed = BTreeMap::new();
+    let mut chars = inner.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+        let name: String = std::iter::from_fn(|| chars.next_if(|c| *c != '=')).collect();
+        if chars.next() != Some('=') || chars.next() != Some('"') {
+            return Err(invalid());
+        }
+        let mut value = String::new();
+        loop {
+            match chars.next().ok_or_else(invalid)? {
+                '"' => break,
+                '\\' => match chars.next().ok_or_else(invalid)? {
+                    'n' => value.push('\n'),
+                    c => value.push(c),
+                },
+                c => value.push(c),
+            }
+        }
+        parsed.insert(name.trim().to_string(), value);
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use prost::Message;
+    use serde_json::json;
+
+    use super::{
+        flatten_loki_json, flatten_loki_protobuf, parse_labels, EntryAdapter, LabelPairAdapter,
+        PushRequest, StreamAdapter, Timestamp,
+    };
+
+    #[test]
+    fn decode_protobuf_push() {
+        let request = PushRequest {
+            streams: vec![StreamAdapter {
+                labels: r#"{job="varlogs", path="/var/log/\"app\".log"}"#.to_string(),
+                entries: vec![EntryAdapter {
+                    timestamp: Some(Timestamp {
+                        seconds: 1704964113,
+                        nanos: 500_000_000,
+                    }),
+                    line: "user logged in".to_string(),
+                    structured_metadata: vec![LabelPairAdapter {
+                        name: "trace_id".to_string(),
+                        value: "abc".to_string(),
+                    }],
+                }],
+                hash: 0,
+            }],
+        };
+        let body = snap::raw::Encoder::new()
+            .compress_vec(&request.encode_to_vec())
+            .unwrap();
+
+        let records = flatten_loki_protobuf(&Bytes::from(body)).unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["job"], "varlogs");
+        assert_eq!(records[0]["path"], "/var/log/\"app\".log");
+        assert_eq!(records[0]["trace_id"], "abc");
+        assert_eq!(records[0]["body"], "user logged in");
+        assert_eq!(records[0]["timestamp"], "2024-01-11T09:08:33.500+00:00");
+    }
+
+    #[test]
+    fn decode_json_push() {
+        let body = json!({
+            "streams": [{
+                "stream": {"job": "app"},
+                "values": [
+                    ["1704964113000000000", "first"],
+                    ["1704964114000000000", "second", {"user": "a"}]
+                ]
+            }]
+        });
+
+        let records = flatten_loki_json(&Bytes::from(body.to_string())).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["timestamp"], "2024-01-11T09:08:33+00:00");
+        assert_eq!(records[1]["body"], "second");
+        assert_eq!(records[1]["user"], "a");
+        assert_eq!(records[1]["job"], "app");
+    }
+
+    #[test]
+    fn invalid_labels_is_err() {
+        assert!(parse_labels(r#"job="app""#).is_err());
+        assert!(parse_labels(r#"{job="app}"#).is_err());
+        assert!(parse_labels("{}").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,131 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use actix_web::{http::header, web, HttpRequest, HttpResponse, Responder};
+use bytes::Bytes;
+use datafusion::error::DataFusionError;
+use http::StatusCode;
+use serde_json::Value;
+
+use crate::metadata::STREAM_INFO;
+use crate::query::lookup::LOOKUP_TABLES;
+use crate::validator::{self, error::StreamNameValidationError};
+
+use super::csv;
+
+// Handler for PUT /api/v1/lookup/{name}
+// Creates a lookup table, or replaces the rows of an existing one to refresh it.
+// The body is CSV with a header row when the content type is text/csv,
+// otherwise a JSON array of objects.
+pub async fn put(
+    req: HttpRequest,
+    name: web::Path<String>,
+    body: Bytes,
+) -> Result<impl Responder, LookupError> {
+    let name = name.into_inner();
+    validator::stream_name(&name)?;
+    if STREAM_INFO.stream_exists(&name) {
+        return Err(LookupError::StreamExists(name));
+    }
+
+    let is_csv = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/csv"));
+    let records = if is_csv {
+        csv_records(&body)?
+    } else {
+        json_records(&body)?
+    };
+
+    let info = LOOKUP_TABLES.put(&name, &records)?;
+    Ok(web::Json(info))
+}
+
+// Handler for GET /api/v1/lookup
+pub async fn list() -> impl Responder {
+    web::Json(LOOKUP_TABLES.list())
+}
+
+// Handler for DELETE /api/v1/lookup/{name}
+pub async fn delete(name: web::Path<String>) -> Result<impl Responder, LookupError> {
+    let name = name.into_inner();
+    if !LOOKUP_TABLES.remove(&name) {
+        return Err(LookupError::NotFound(name));
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+// rows of reference data are not skipped like malformed rows of logs, a
+// partially loaded lookup table would silently drop matches of a join
+fn csv_records(body: &Bytes) -> Result<Vec<Value>, LookupError> {
+    let body = std::str::from_utf8(body).map_err(|err| LookupError::Invalid(err.to_string()))?;
+    let (records, skipped) = csv::flatten_csv(body, None).map_err(LookupError::Invalid)?;
+    if skipped > 0 {
+        return Err(LookupError::Invalid(format!("{skipped} malformed rows")));
+    }
+    Ok(records
+        .into_iter()
+        .map(|record| Value::Object(record.into_iter().collect()))
+        .collect())
+}
+
+fn json_records(body: &Bytes) -> Result<Vec<Value>, LookupError> {
+    let records =
+        match serde_json::from_slice(body).map_err(|err| LookupError::Invalid(err.to_string()))? {
+            Value::Array(records) => records,
+            record => vec![record],
+        };
+    if records.iter().any(|record| !record.is_object()) {
+        return Err(LookupError::Invalid(
+            "expected a JSON object or an array of objects".to_string(),
+        ));
+    }
+    Ok(records)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LookupError {
+    #[error("Invalid lookup table name: {0}")]
+    InvalidName(#[from] StreamNameValidationError),
+    #[error("A stream named {0} already exists")]
+    StreamExists(String),
+    #[error("Invalid lookup table: {0}")]
+    Invalid(String),
+    #[error("Invalid lookup table: {0}")]
+    Decode(#[from] DataFusionError),
+    #[error("Lookup table {0} does not exist")]
+    NotFound(String),
+}
+
+impl actix_web::ResponseError for LookupError {
+    fn status_code(&self) -> http::StatusCode {
+        match self {
+            Self::InvalidName(_) | Self::Invalid(_) | Self::Decode(_) => StatusCode::BAD_REQUEST,
+            Self::StreamExists(_) => StatusCode::CONFLICT,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+        }
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
+        actix_web::HttpResponse::build(self.status_code())
+            .insert_header(header::ContentType::plaintext())
+            .body(self.to_string())
+    }
+}
@@ -17,24 +17,31 @@
 *
 */
 
+use std::cell::Cell;
 use std::future::{ready, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
 
 use actix_web::{
+    body::{BodySize, BoxBody, EitherBody, MessageBody},
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
     error::{ErrorBadRequest, ErrorForbidden, ErrorUnauthorized},
     http::header::{self, HeaderName},
-    Error, Route,
+    Error, HttpResponse, Route,
 };
+use bytes::Bytes;
 use futures_util::future::LocalBoxFuture;
 
 use crate::handlers::{
-    AUTHORIZATION_KEY, KINESIS_COMMON_ATTRIBUTES_KEY, LOG_SOURCE_KEY, LOG_SOURCE_KINESIS,
-    STREAM_NAME_HEADER_KEY,
+    AUTHORIZATION_KEY, INGEST_KEY_HEADER_KEY, KINESIS_COMMON_ATTRIBUTES_KEY, LOG_SOURCE_KEY,
+    LOG_SOURCE_KINESIS, STREAM_NAME_HEADER_KEY,
 };
+use crate::metrics::QUERY_RESPONSE_BYTES_SAVED;
 use crate::{
     option::CONFIG,
     rbac::Users,
-    rbac::{self, role::Action},
+    rbac::{self, ingest_key, role::Action},
     utils::actix::extract_session_key,
 };
 
@@ -58,6 +65,7 @@ pub trait RouteExt {
     fn authorize(self, action: Action) -> Self;
     fn authorize_for_stream(self, action: Action) -> Self;
     fn authorize_for_user(self, action: Action) -> Self;
+    fn authorize_for_ingest(self) -> Self;
 }
 
 impl RouteExt for Route {
@@ -81,6 +89,13 @@ impl RouteExt for Route {
             method: auth_user_context,
         })
     }
+
+    fn authorize_for_ingest(self) -> Self {
+        self.wrap(Auth {
+            action: Action::Ingest,
+            method: auth_ingest_context,
+        })
+    }
 }
 
 // Authentication Layer with no context
@@ -200,6 +215,35 @@ pub fn auth_user_context(
     creds.map(|key| Users.authorize(key, action, None, user))
 }
 
+// Streams with ingest keys only accept ingest requests carrying one of their keys,
+// other streams are authorized like any other stream action.
+pub fn auth_ingest_context(
+    req: &mut ServiceRequest,
+    action: Action,
+) -> Result<rbac::Response, Error> {
+    let headers = req.headers();
+    let stream = req
+        .match_info()
+        .get("logstream")
+        .or_else(|| {
+            headers
+                .get(STREAM_NAME_HEADER_KEY)
+                .and_then(|value| value.to_str().ok())
+        })
+        .map(str::to_owned);
+    let key = headers
+        .get(INGEST_KEY_HEADER_KEY)
+        .and_then(|value| value.to_str().ok());
+
+    match stream.and_then(|stream| ingest_key::authorize(&stream, key)) {
+        Some(true) => Ok(rbac::Response::Authorized),
+        Some(false) => Err(ErrorUnauthorized(
+            "A valid ingest key for this stream is required",
+        )),
+        None => auth_stream_context(req, action),
+    }
+}
+
 // The credentials set in the env vars (P_USERNAME & P_PASSWORD) are treated
 // as root credentials. Any other user is not allowed to modify or delete
 // the root user. Deny request if username is same as username
@@ -252,3 +296,174 @@ where
         })
     }
 }
+
+// Size of a response body before it is compressed, set by handlers whose
+// savings from compression are counted
+#[derive(Clone)]
+struct UncompressedSize(Rc<Cell<usize>>);
+
+// Count the bytes of the response body as they are produced, before the
+// compress middleware encodes them
+pub fn count_uncompressed(mut response: HttpResponse) -> HttpResponse {
+    let size = UncompressedSize(Rc::default());
+    response.extensions_mut().insert(size.clone());
+    response.map_body(|_, body| CountingBody::new(body, move |bytes| size.0.set(bytes)).boxed())
+}
+
+// Records the bytes saved by compressing responses which counted their size
+// before compression. Must wrap the compress middleware so the body it sees
+// is the encoded one.
+pub struct CompressionSavings;
+
+impl<S, B> Transform<S, ServiceRequest> for CompressionSavings
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CompressionSavingsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CompressionSavingsMiddleware { service }))
+    }
+}
+
+pub struct CompressionSavingsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for CompressionSavingsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?;
+            let uncompressed = res
+                .response()
+                .extensions()
+                .get::<UncompressedSize>()
+                .cloned();
+            let encoding = res
+                .headers()
+                .get(header::CONTENT_ENCODING)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned);
+            let (Some(uncompressed), Some(encoding)) = (uncompressed, encoding) else {
+                return Ok(res.map_into_left_body());
+            };
+            Ok(res.map_body(|_, body| {
+                EitherBody::right(
+                    CountingBody::new(body, move |compressed| {
+                        QUERY_RESPONSE_BYTES_SAVED
+                            .with_label_values(&[&encoding])
+                            .inc_by(uncompressed.0.get().saturating_sub(compressed) as u64)
+                    })
+                    .boxed(),
+                )
+            }))
+        })
+    }
+}
+
+// Body passing through the chunks of another and counting their bytes, the
+// count is handed to `on_end` once the body is complete
+struct CountingBody<F: FnOnce(usize)> {
+    body: BoxBody,
+    bytes: usize,
+    on_end: Option<F>,
+}
+
+impl<F: FnOnce(usize)> CountingBody<F> {
+    fn new(body: impl MessageBody + 'static, on_end: F) -> Self {
+        Self {
+            body: body.boxed(),
+            bytes: 0,
+            on_end: Some(on_end),
+        }
+    }
+}
+
+impl<F: FnOnce(usize) + Unpin> MessageBody for CountingBody<F> {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        let next = Pin::new(&mut this.body).poll_next(cx);
+        match &next {
+            Poll::Ready(Some(Ok(chunk))) => this.bytes += chunk.len(),
+            Poll::Ready(None) => {
+                if let Some(on_end) = this.on_end.take() {
+                    on_end(this.bytes)
+                }
+            }
+            _ => {}
+        }
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header;
+    use actix_web::middleware::Compress;
+    use actix_web::{test, web, App, HttpResponse};
+
+    use crate::metrics::QUERY_RESPONSE_BYTES_SAVED;
+
+    use super::{count_uncompressed, CompressionSavings};
+
+    #[actix_web::test]
+    async fn bytes_saved_by_compression_counted() {
+        let app = test::init_service(
+            App::new()
+                .route(
+                    "/",
+                    web::get().to(|| async {
+                        count_uncompressed(HttpResponse::Ok().body("row\n".repeat(1000)))
+                    }),
+                )
+                .wrap(Compress::default())
+                .wrap(CompressionSavings),
+        )
+        .await;
+        let saved = || {
+            QUERY_RESPONSE_BYTES_SAVED
+                .with_label_values(&["gzip"])
+                .get()
+        };
+        let before = saved();
+
+        let req = test::TestRequest::get()
+            .uri("/")
+            .insert_header((header::ACCEPT_ENCODING, "gzip"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+        let compressed = test::read_body(res).await.len();
+
+        assert!(compressed < 4000);
+        assert_eq!(saved() - before, (4000 - compressed) as u64);
+    }
+}
@@ -0,0 +1,617 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use bytes::{Buf, Bytes, BytesMut};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{Map, Number, Value};
+use std::collections::BTreeMap;
+
+use crate::event::body::BODY_KEY;
+use crate::event::severity::{SEVERITY_NUMBER_KEY, SEVERITY_TEXT_KEY};
+use crate::option::OtelUnknownTime;
+use crate::utils::correlation_id::{self, SPAN_ID_KEY, TRACE_ID_KEY};
+
+mod proto;
+
+// content type used by OTLP/HTTP exporters sending protobuf encoded data
+pub const CONTENT_TYPE_PROTOBUF: &str = "application/x-protobuf";
+
+// a varint length takes up to 10 bytes
+const MAX_DELIMITER_LEN: usize = 10;
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct LogsData {
+    #[serde(default)]
+    resource_logs: Vec<ResourceLogs>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct ResourceLogs {
+    #[serde(default)]
+    resource: Resource,
+    #[serde(default)]
+    scope_logs: Vec<ScopeLogs>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct Resource {
+    #[serde(default)]
+    attributes: Vec<KeyValue>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct ScopeLogs {
+    #[serde(default)]
+    scope: Scope,
+    #[serde(default)]
+    log_records: Vec<LogRecord>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct Scope {
+    name: Option<String>,
+    version: Option<String>,
+    #[serde(default)]
+    attributes: Vec<KeyValue>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct LogRecord {
+    time_unix_nano: Option<Value>,
+    observed_time_unix_nano: Option<Value>,
+    severity_number: Option<i32>,
+    severity_text: Option<String>,
+    body: Option<AnyValue>,
+    #[serde(default)]
+    attributes: Vec<KeyValue>,
+    trace_id: Option<String>,
+    span_id: Option<String>,
+    flags: Option<u32>,
+}
+
+// A line of OTLP JSON lines, a single log record with its own resource and scope
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct LogLine {
+    #[serde(default)]
+    resource: Resource,
+    #[serde(default)]
+    scope: Scope,
+    log: LogRecord,
+}
+
+#[derive(Deserialize, Debug)]
+struct KeyValue {
+    key: String,
+    value: Option<AnyValue>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct AnyValue {
+    string_value: Option<String>,
+    bool_value: Option<bool>,
+    // int64 values are encoded as strings in OTLP/JSON
+    int_value: Option<Value>,
+    double_value: Option<f64>,
+    array_value: Option<ArrayValue>,
+    kvlist_value: Option<KeyValueList>,
+    bytes_value: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct ArrayValue {
+    #[serde(default)]
+    values: Vec<AnyValue>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct KeyValueList {
+    #[serde(default)]
+    values: Vec<KeyValue>,
+}
+
+impl AnyValue {
+    fn into_json(self) -> Value {
+        if let Some(value) = self.string_value {
+            Value::String(value)
+        } else if let Some(value) = self.bool_value {
+            Value::Bool(value)
+        } else if let Some(value) = self.int_value {
+            match value {
+                Value::String(s) => s
+                    .parse::<i64>()
+                    .map(Value::from)
+                    .unwrap_or(Value::String(s)),
+                value => value,
+            }
+        } else if let Some(value) = self.double_value {
+            Number::from_f64(value).map_or(Value::Null, Value::Number)
+        } else if let Some(value) = self.array_value {
+            Value::Array(value.values.into_iter().map(AnyValue::into_json).collect())
+        } else if let Some(value) = self.kvlist_value {
+            let mut map = Map::new();
+            for kv in value.values {
+                map.insert(kv.key, kv.value.map_or(Value::Null, AnyValue::into_json));
+            }
+            Value::Object(map)
+        } else if let Some(value) = self.bytes_value {
+            Value::String(value)
+        } else {
+            Value::Null
+        }
+    }
+}
+
+fn attributes_to_json(attributes: Vec<KeyValue>) -> Vec<(String, Value)> {
+    attributes
+        .into_iter()
+        .map(|kv| (kv.key, kv.value.map_or(Value::Null, AnyValue::into_json)))
+        .collect()
+}
+
+// Flatten OTLP/JSON logs into one JSON record per log record.
+// Attributes are defined at resource, scope and log record level and are all
+// stored as top level columns named after the attribute key. When the same key
+// appears at more than one level the most specific one wins, so the order of
+// resolution is
+// resource attributes < scope attributes < log record attributes < log record fields
+// log record fields (body, severity, trace and span ids etc.) always take
+// precedence over an attribute of the same name.
+// A `time_unix_nano` of 0 means unknown in OTLP and negative times are invalid,
+// storing them as is would place the record in 1970. Such times are replaced
+// as configured by `unknown_time`.
+// Returns the records along with the number of records with an unknown time.
+pub fn flatten_otel_logs(
+    body: &Bytes,
+    unknown_time: OtelUnknownTime,
+) -> Result<(Vec<BTreeMap<String, Value>>, usize), serde_json::Error> {
+    let logs: LogsData = serde_json::from_slice(body)?;
+    Ok(flatten_logs_data(logs, unknown_time))
+}
+
+// Flatten protobuf encoded OTLP logs, the same way as OTLP/JSON logs
+pub fn flatten_otel_logs_protobuf(
+    body: &Bytes,
+    unknown_time: OtelUnknownTime,
+) -> Result<(Vec<BTreeMap<String, Value>>, usize), prost::DecodeError> {
+    let logs = <proto::LogsData as prost::Message>::decode(body.clone())?;
+    Ok(flatten_logs_data(logs.into(), unknown_time))
+}
+
+// Message of a stream of length delimited protobuf messages
+#[derive(Debug, PartialEq)]
+pub enum Frame {
+    Message(Bytes),
+    // length of a message larger than the maximum, its bytes are skipped
+    Oversized(usize),
+}
+
+// Splits a stream of length delimited protobuf messages, such as OTLP
+// `LogsData` or `ExportLogsServiceRequest`, into messages as the bytes of the
+// stream arrive. At most one message is held at a time.
+pub struct FrameDecoder {
+    buf: BytesMut,
+    max_len: usize,
+    // bytes of an oversized message still to be skipped
+    skip: usize,
+}
+
+impl FrameDecoder {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            buf: BytesMut::new(),
+            max_len,
+            skip: 0,
+        }
+    }
+
+    pub fn extend(&mut self, chunk: &[u8]) {
+        let skipped = self.skip.min(chunk.len());
+        self.skip -= skipped;
+        self.buf.extend_from_slice(&chunk[skipped..]);
+    }
+
+    /// The next complete message of the stream. An error means the length of a
+    /// message is corrupt, after which the stream can not be split any further.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>, prost::DecodeError> {
+        if self.skip > 0 {
+            return Ok(None);
+        }
+        // the length is incomplete as long as all its bytes are continued
+        if self.buf.len() < MAX_DELIMITER_LEN && self.buf.iter().all(|byte| byte & 0x80 != 0) {
+            return Ok(None);
+        }
+        let mut rest = &self.buf[..];
+        let len = prost::decode_length_delimiter(&mut rest)?;
+        let delimiter_len = self.buf.len() - rest.len();
+
+        if len > self.max_len {
+            self.buf.advance(delimiter_len);
+            let skipped = len.min(self.buf.len());
+            self.buf.advance(skipped);
+            self.skip = len - skipped;
+            return Ok(Some(Frame::Oversized(len)));
+        }
+        if rest.len() < len {
+            return Ok(None);
+        }
+        self.buf.advance(delimiter_len);
+        Ok(Some(Frame::Message(self.buf.split_to(len).freeze())))
+    }
+
+    // the stream ended within a message
+    pub fn is_truncated(&self) -> bool {
+        self.skip > 0 || !self.buf.is_empty()
+    }
+}
+
+// Flatten JSON lines of single log records, one record per line.
+// Every line is an object with the OTLP/JSON log record under `log` and
+// optionally the `resource` and `scope` of that record, whose attributes are
+// resolved the same way as in OTLP/JSON logs. Blank lines are skipped.
+pub fn flatten_otel_lines(
+    body: &str,
+    unknown_time: OtelUnknownTime,
+) -> anyhow::Result<(Vec<BTreeMap<String, Value>>, usize)> {
+    let mut records = Vec::new();
+    let mut unknown = 0;
+    for (index, line) in body.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line: LogLine = serde_json::from_str(line)
+            .map_err(|err| anyhow::anyhow!("line {} is not a log line, {err}", index + 1))?;
+        let mut scope = line.scope;
+        let mut record: BTreeMap<String, Value> = attributes_to_json(line.resource.attributes)
+            .into_iter()
+            .collect();
+        record.extend(attributes_to_json(std::mem::take(&mut scope.attributes)));
+        if !flatten_log_record(&mut record, &scope, line.log, unknown_time) {
+            unknown += 1;
+        }
+        records.push(record);
+    }
+    Ok((records, unknown))
+}
+
+fn flatten_logs_data(
+    logs: LogsData,
+    unknown_time: OtelUnknownTime,
+) -> (Vec<BTreeMap<String, Value>>, usize) {
+    let mut vec_otel_json = Vec::new();
+    let mut unknown = 0;
+
+    for resource_logs in logs.resource_logs {
+        let resource_attributes = attributes_to_json(resource_logs.resource.attributes);
+
+        for scope_logs in resource_logs.scope_logs {
+            let mut scope = scope_logs.scope;
+            let scope_attributes = attributes_to_json(std::mem::take(&mut scope.attributes));
+
+            for log_record in scope_logs.log_records {
+                let mut record: BTreeMap<String, Value> =
+                    resource_attributes.iter().cloned().collect();
+                record.extend(scope_attributes.iter().cloned());
+                if !flatten_log_record(&mut record, &scope, log_record, unknown_time) {
+                    unknown += 1;
+                }
+                vec_otel_json.push(record);
+            }
+        }
+    }
+
+    (vec_otel_json, unknown)
+}
+
+// Add the attributes and fields of a log record to the record already holding
+// the attributes of its resource and scope.
+// Returns false if the time of the log record was unknown and replaced.
+fn flatten_log_record(
+    record: &mut BTreeMap<String, Value>,
+    scope: &Scope,
+    log_record: LogRecord,
+    unknown_time: OtelUnknownTime,
+) -> bool {
+    let mut known = true;
+    record.extend(attributes_to_json(log_record.attributes));
+
+    if let Some(name) = &scope.name {
+        record.insert("scope_name".to_string(), Value::String(name.clone()));
+    }
+    if let Some(version) = &scope.version {
+        record.insert("scope_version".to_string(), Value::String(version.clone()));
+    }
+    let observed_time = log_record
+        .observed_time_unix_nano
+        .filter(|time| !is_unknown_time(time));
+    if let Some(mut time) = log_record.time_unix_nano {
+        if is_unknown_time(&time) {
+            known = false;
+            time = match unknown_time {
+                OtelUnknownTime::Observed => observed_time.clone(),
+                OtelUnknownTime::Server => None,
+            }
+            .unwrap_or_else(|| {
+                let now = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+                Value::String(now.to_string())
+            });
+        }
+        record.insert("time_unix_nano".to_string(), time);
+    }
+    if let Some(time) = observed_time {
+        record.insert("observed_time_unix_nano".to_string(), time);
+    }
+    if let Some(severity_number) = log_record.severity_number {
+        record.insert(
+            SEVERITY_NUMBER_KEY.to_string(),
+            Value::from(severity_number),
+        );
+    }
+    if let Some(severity_text) = log_record.severity_text {
+        record.insert(SEVERITY_TEXT_KEY.to_string(), Value::String(severity_text));
+    }
+    if let Some(body) = log_record.body {
+        record.insert(BODY_KEY.to_string(), body.into_json());
+    }
+    if let Some(trace_id) = log_record.trace_id {
+        record.insert(
+            TRACE_ID_KEY.to_string(),
+            Value::String(correlation_id::normalize(&trace_id)),
+        );
+    }
+    if let Some(span_id) = log_record.span_id {
+        record.insert(
+            SPAN_ID_KEY.to_string(),
+            Value::String(correlation_id::normalize(&span_id)),
+        );
+    }
+    if let Some(flags) = log_record.flags {
+        record.insert("flags".to_string(), Value::from(flags));
+    }
+    known
+}
+
+// unix nano times are strings in OTLP/JSON, but numbers are accepted as well
+fn is_unknown_time(time: &Value) -> bool {
+    match time {
+        Value::String(time) => time.parse::<i128>().is_ok_and(|time| time <= 0),
+        Value::Number(time) => time.as_f64().is_some_and(|time| time <= 0.),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use serde_json::{json, Value};
+
+    use super::{
+        flatten_otel_lines, flatten_otel_logs, flatten_otel_logs_protobuf, Frame, FrameDecoder,
+    };
+    use crate::option::OtelUnknownTime;
+
+    fn string_attribute(key: &str, value: &str) -> Value {
+        json!({"key": key, "value": {"stringValue": value}})
+    }
+
+    #[test]
+    fn log_attribute_overrides_resource_and_scope() {
+        let body = json!({
+            "resourceLogs": [{
+                "resource": {
+                    "attributes": [
+                        string_attribute("service.name", "checkout"),
+                        string_attribute("env", "prod"),
+                        string_attribute("region", "us-east-1"),
+                    ]
+                },
+                "scopeLogs": [{
+                    "scope": {
+                        "name": "app",
+                        "attributes": [string_attribute("env", "staging")]
+                    },
+                    "logRecords": [{
+                        "timeUnixNano": "1704964113659000000",
+                        "severityNumber": 9,
+                        "severityText": "INFO",
+                        "body": {"stringValue": "order placed"},
+                        "attributes": [
+                            string_attribute("region", "eu-west-1"),
+                            {"key": "retries", "value": {"intValue": "3"}},
+                        ]
+                    }]
+                }]
+            }]
+        });
+        let body = Bytes::from(serde_json::to_vec(&body).unwrap());
+
+        let (records, unknown) = flatten_otel_logs(&body, OtelUnknownTime::Observed).unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(unknown, 0);
+        let record = &records[0];
+        assert_eq!(record["service.name"], "checkout");
+        assert_eq!(record["env"], "staging");
+        assert_eq!(record["region"], "eu-west-1");
+        assert_eq!(record["retries"], Value::from(3));
+        assert_eq!(record["scope_name"], "app");
+        assert_eq!(record["severity_number"], Value::from(9));
+        assert_eq!(record["severity_text"], "INFO");
+        assert_eq!(record["body"], "order placed");
+    }
+
+    #[test]
+    fn protobuf_logs() {
+        // captured protobuf body of a single log record
+        let body = hex::decode(
+            "0a750a1c0a1a0a0c736572766963652e6e616d65120a0a08636865636b6f757412550a050a036170\
+             70124c09c0f46efdd33fa91710091a04494e464f2a0e0a0c6f7264657220706c61636564320d0a07\
+             72657472696573120218034a105b8efff798038103d269b633813fc60c5208eee19b7ec3c1b174",
+        )
+        .unwrap();
+
+        let (records, _) =
+            flatten_otel_logs_protobuf(&Bytes::from(body), OtelUnknownTime::Observed).unwrap();
+
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record["service.name"], "checkout");
+        assert_eq!(record["scope_name"], "app");
+        assert_eq!(record["time_unix_nano"], "1704964113659000000");
+        assert_eq!(record["severity_number"], Value::from(9));
+        assert_eq!(record["severity_text"], "INFO");
+        assert_eq!(record["body"], "order placed");
+        assert_eq!(record["retries"], Value::from(3));
+        assert_eq!(record["trace_id"], "5b8efff798038103d269b633813fc60c");
+        assert_eq!(record["span_id"], "eee19b7ec3c1b174");
+        assert!(record.get("flags").is_none());
+        assert!(record.get("scope_version").is_none());
+
+        assert!(flatten_otel_logs_protobuf(
+            &Bytes::from_static(&[0x0a, 0x73]),
+            OtelUnknownTime::Observed
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn lines_with_own_resource() {
+        let lines = [
+            json!({
+                "resource": {"attributes": [
+                    string_attribute("service.name", "checkout"),
+                    string_attribute("region", "us-east-1"),
+                ]},
+                "log": {
+                    "severityText": "INFO",
+                    "body": {"stringValue": "order placed"},
+                    "attributes": [string_attribute("region", "eu-west-1")]
+                }
+            }),
+            json!({"log": {"body": {"stringValue": "no resource"}}}),
+        ];
+        let body = format!("{}\n\n{}\n", lines[0], lines[1]);
+
+        let (records, _) = flatten_otel_lines(&body, OtelUnknownTime::Observed).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["service.name"], "checkout");
+        assert_eq!(records[0]["region"], "eu-west-1");
+        assert_eq!(records[0]["severity_text"], "INFO");
+        assert_eq!(records[0]["body"], "order placed");
+        assert_eq!(records[1].len(), 1);
+        assert_eq!(records[1]["body"], "no resource");
+
+        let err = flatten_otel_lines(
+            "{\"log\": {}}\n{\"resource\": {}}",
+            OtelUnknownTime::Observed,
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("line 2"));
+    }
+
+    #[test]
+    fn zero_time_is_unknown() {
+        let body = json!({
+            "resourceLogs": [{
+                "scopeLogs": [{
+                    "logRecords": [
+                        {
+                            "timeUnixNano": "0",
+                            "observedTimeUnixNano": "1704964113659000000",
+                            "body": {"stringValue": "observed"}
+                        },
+                        {
+                            "timeUnixNano": "-1",
+                            "observedTimeUnixNano": "0",
+                            "body": {"stringValue": "server"}
+                        }
+                    ]
+                }]
+            }]
+        });
+        let body = Bytes::from(serde_json::to_vec(&body).unwrap());
+
+        let (records, unknown) = flatten_otel_logs(&body, OtelUnknownTime::Observed).unwrap();
+
+        assert_eq!(unknown, 2);
+        assert_eq!(records[0]["time_unix_nano"], "1704964113659000000");
+        let server_time: i64 = records[1]["time_unix_nano"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(server_time > 1704964113659000000);
+        assert!(records[1].get("observed_time_unix_nano").is_none());
+
+        let (records, _) = flatten_otel_logs(&body, OtelUnknownTime::Server).unwrap();
+        assert_ne!(records[0]["time_unix_nano"], "1704964113659000000");
+    }
+
+    #[test]
+    fn frames_are_split_as_they_arrive() {
+        let mut stream = Vec::new();
+        for message in [&b"first"[..], &[0u8; 300][..], b"", b"last"] {
+            prost::encode_length_delimiter(message.len(), &mut stream).unwrap();
+            stream.extend_from_slice(message);
+        }
+
+        let mut frames = FrameDecoder::new(100);
+        let mut decoded = Vec::new();
+        // one byte at a time, lengths and messages are split across chunks
+        for byte in &stream {
+            frames.extend(std::slice::from_ref(byte));
+            while let Some(frame) = frames.next_frame().unwrap() {
+                decoded.push(frame);
+            }
+        }
+
+        assert_eq!(
+            decoded,
+            vec![
+                Frame::Message(Bytes::from_static(b"first")),
+                Frame::Oversized(300),
+                Frame::Message(Bytes::new()),
+                Frame::Message(Bytes::from_static(b"last")),
+            ]
+        );
+        assert!(!frames.is_truncated());
+
+        frames.extend(&[5, b'a']);
+        assert_eq!(frames.next_frame().unwrap(), None);
+        assert!(frames.is_truncated());
+
+        let mut corrupt = FrameDecoder::new(100);
+        corrupt.extend(&[0xff; 11]);
+        assert!(corrupt.next_frame().is_err());
+    }
+
+    #[test]
+    fn invalid_body_is_err() {
+        let body = Bytes::from_static(b"{\"resourceLogs\": 1}");
+        assert!(flatten_otel_logs(&body, OtelUnknownTime::Observed).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+// Protobuf encoding of OTLP logs as defined in
+// https://github.com/open-telemetry/opentelemetry-proto/blob/main/opentelemetry/proto/logs/v1/logs.proto
+// Decoded messages are converted into the same model used for OTLP/JSON,
+// following the OTLP/JSON mapping: ids are hex encoded, bytes are base64 encoded,
+// 64 bit integers are strings and fields with default values are absent.
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use prost::Message;
+use serde_json::Value;
+
+#[derive(Clone, PartialEq, Message)]
+pub struct LogsData {
+    #[prost(message, repeated, tag = "1")]
+    pub resource_logs: Vec<ResourceLogs>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ResourceLogs {
+    #[prost(message, optional, tag = "1")]
+    pub resource: Option<Resource>,
+    #[prost(message, repeated, tag = "2")]
+    pub scope_logs: Vec<ScopeLogs>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Resource {
+    #[prost(message, repeated, tag = "1")]
+    pub attributes: Vec<KeyValue>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ScopeLogs {
+    #[prost(message, optional, tag = "1")]
+    pub scope: Option<InstrumentationScope>,
+    #[prost(message, repeated, tag = "2")]
+    pub log_records: Vec<LogRecord>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct InstrumentationScope {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub version: String,
+    #[prost(message, repeated, tag = "3")]
+    pub attributes: Vec<KeyValue>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct LogRecord {
+    #[prost(fixed64, tag = "1")]
+    pub time_unix_nano: u64,
+    #[prost(fixed64, tag = "11")]
+    pub observed_time_unix_nano: u64,
+    #[prost(int32, tag = "2")]
+    pub severity_number: i32,
+    #[prost(string, tag = "3")]
+    pub severity_text: String,
+    #[prost(message, optional, tag = "5")]
+    pub body: Option<AnyValue>,
+    #[prost(message, repeated, tag = "6")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(fixed32, tag = "8")]
+    pub flags: u32,
+    #[prost(bytes = "vec", tag = "9")]
+    pub trace_id: Vec<u8>,
+    #[prost(bytes = "vec", tag = "10")]
+    pub span_id: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct KeyValue {
+    #[prost(string, tag = "1")]
+    pub key: String,
+    #[prost(message, optional, tag = "2")]
+    pub value: Option<AnyValue>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct AnyValue {
+    #[prost(oneof = "Kind", tags = "1, 2, 3, 4, 5, 6, 7")]
+    pub value: Option<Kind>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum Kind {
+    #[prost(string, tag = "1")]
+    String(String),
+    #[prost(bool, tag = "2")]
+    Bool(bool),
+    #[prost(int64, tag = "3")]
+    Int(i64),
+    #[prost(double, tag = "4")]
+    Double(f64),
+    #[prost(message, tag = "5")]
+    Array(ArrayValue),
+    #[prost(message, tag = "6")]
+    Kvlist(KeyValueList),
+    #[prost(bytes, tag = "7")]
+    Bytes(Vec<u8>),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ArrayValue {
+    #[prost(message, repeated, tag = "1")]
+    pub values: Vec<AnyValue>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct KeyValueList {
+    #[prost(message, repeated, tag = "1")]
+    pub values: Vec<KeyValue>,
+}
+
+fn non_empty(value: String) -> Option<String> {
+    (!value.is_empty()).then_some(value)
+}
+
+fn non_zero(value: u64) -> Option<Value> {
+    (value != 0).then(|| Value::String(value.to_string()))
+}
+
+fn hex_id(id: Vec<u8>) -> Option<String> {
+    (!id.is_empty()).then(|| hex::encode(id))
+}
+
+fn attributes(attributes: Vec<KeyValue>) -> Vec<super::KeyValue> {
+    attributes.into_iter().map(Into::into).collect()
+}
+
+impl From<LogsData> for super::LogsData {
+    fn from(logs: LogsData) -> Self {
+        Self {
+            resource_logs: logs.resource_logs.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<ResourceLogs> for super::ResourceLogs {
+    fn from(logs: ResourceLogs) -> Self {
+        Self {
+            resource: super::Resource {
+                attributes: attributes(logs.resource.unwrap_or_default().attributes),
+            },
+            scope_logs: logs.scope_logs.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<ScopeLogs> for super::ScopeLogs {
+    fn from(logs: ScopeLogs) -> Self {
+        let scope = logs.scope.unwrap_or_default();
+        Self {
+            scope: super::Scope {
+                name: non_empty(scope.name),
+                version: non_empty(scope.version),
+                attributes: attributes(scope.attributes),
+            },
+            log_records: logs.log_records.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<LogRecord> for super::LogRecord {
+    fn from(record: LogRecord) -> Self {
+        Self {
+            // 0 means unknown, it is kept to be replaced like in OTLP/JSON
+            time_unix_nano: Some(Value::String(record.time_unix_nano.to_string())),
+            observed_time_unix_nano: non_zero(record.observed_time_unix_nano),
+            severity_number: (record.severity_number != 0).then_some(record.severity_number),
+            severity_text: non_empty(record.severity_text),
+            body: record.body.map(Into::into),
+            attributes: attributes(record.attributes),
+            trace_id: hex_id(record.trace_id),
+            span_id: hex_id(record.span_id),
+            flags: (record.flags != 0).then_some(record.flags),
+        }
+    }
+}
+
+impl From<KeyValue> for super::KeyValue {
+    fn from(kv: KeyValue) -> Self {
+        Self {
+            key: kv.key,
+            value: kv.value.map(Into::into),
+        }
+    }
+}
+
+impl From<AnyValue> for super::AnyValue {
+    fn from(value: AnyValue) -> Self {
+        let mut any = super::AnyValue::default();
+        match value.value {
+            Some(Kind::String(value)) => any.string_value = Some(value),
+            Some(Kind::Bool(value)) => any.bool_value = Some(value),
+            Some(Kind::Int(value)) => any.int_value = Some(Value::from(value)),
+            Some(Kind::Double(value)) => any.double_value = Some(value),
+            Some(Kind::Array(array)) => {
+                any.array_value = Some(super::ArrayValue {
+                    values: array.values.into_iter().map(Into::into).collect(),
+                })
+            }
+            Some(Kind::Kvlist(list)) => {
+                any.kvlist_value = Some(super::KeyValueList {
+                    values: attributes(list.values),
+                })
+            }
+            Some(Kind::Bytes(value)) => any.bytes_value = Some(STANDARD.encode(value)),
+            None => {}
+        }
+        any
+    }
+}
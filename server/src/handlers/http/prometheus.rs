@@ -0,0 +1,200 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use bytes::Bytes;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use prost::Message;
+use serde_json::{Number, Value};
+
+// Prometheus remote write request as defined in
+// https://github.com/prometheus/prometheus/blob/main/prompb/remote.proto
+// Payloads are snappy (block format) compressed. Exemplars, native histograms
+// and metric metadata are not decoded.
+
+#[derive(Clone, PartialEq, Message)]
+pub struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    pub labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Label {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Sample {
+    #[prost(double, tag = "1")]
+    pub value: f64,
+    // milliseconds since epoch
+    #[prost(int64, tag = "2")]
+    pub timestamp: i64,
+}
+
+const METRIC_NAME_LABEL: &str = "__name__";
+const METRIC_NAME_KEY: &str = "metric_name";
+const VALUE_KEY: &str = "value";
+const TIMESTAMP_KEY: &str = "timestamp";
+
+// Flatten a remote write request into JSON records.
+// Every sample becomes one record with the series labels as columns, the
+// metric name as `metric_name`, the sample as `value` and its time as `timestamp`.
+// Non finite values such as the staleness marker are stored as null. Series
+// with a label of the same name as one of these columns are rejected.
+pub fn flatten_remote_write(body: &Bytes) -> Result<Vec<BTreeMap<String, Value>>, anyhow::Error> {
+    let body = snap::raw::Decoder::new().decompress_vec(body)?;
+    let request = WriteRequest::decode(body.as_slice())?;
+
+    let mut records = Vec::new();
+    for series in request.timeseries {
+        let mut labels = BTreeMap::new();
+        for label in series.labels {
+            let key = if label.name == METRIC_NAME_LABEL {
+                METRIC_NAME_KEY.to_string()
+            } else {
+                label.name
+            };
+            // labels are unique, so only `metric_name` can be there already
+            if [VALUE_KEY, TIMESTAMP_KEY].contains(&key.as_str()) || labels.contains_key(&key) {
+                anyhow::bail!("label {key} collides with the column of the same name");
+            }
+            labels.insert(key, Value::String(label.value));
+        }
+
+        for sample in series.samples {
+            let mut record = labels.clone();
+            record.insert(
+                VALUE_KEY.to_string(),
+                Number::from_f64(sample.value).map_or(Value::Null, Value::Number),
+            );
+            let timestamp = NaiveDateTime::from_timestamp_millis(sample.timestamp)
+                .ok_or_else(|| anyhow::anyhow!("invalid timestamp {}", sample.timestamp))?;
+            record.insert(
+                TIMESTAMP_KEY.to_string(),
+                Value::String(
+                    DateTime::<Utc>::from_naive_utc_and_offset(timestamp, Utc).to_rfc3339(),
+                ),
+            );
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use prost::Message;
+    use serde_json::{json, Value};
+
+    use super::{flatten_remote_write, Label, Sample, TimeSeries, WriteRequest};
+
+    fn label(name: &str, value: &str) -> Label {
+        Label {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn compress(request: &WriteRequest) -> Bytes {
+        snap::raw::Encoder::new()
+            .compress_vec(&request.encode_to_vec())
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn decode_remote_write() {
+        let request = WriteRequest {
+            timeseries: vec![TimeSeries {
+                labels: vec![
+                    label("__name__", "http_requests_total"),
+                    label("job", "api"),
+                    label("code", "200"),
+                ],
+                samples: vec![
+                    Sample {
+                        value: 10.0,
+                        timestamp: 1704964113500,
+                    },
+                    Sample {
+                        value: f64::NAN,
+                        timestamp: 1704964114000,
+                    },
+                ],
+            }],
+        };
+
+        let records = flatten_remote_write(&compress(&request)).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["metric_name"], "http_requests_total");
+        assert_eq!(records[0]["job"], "api");
+        assert_eq!(records[0]["code"], "200");
+        assert_eq!(records[0]["value"], json!(10.0));
+        assert_eq!(records[0]["timestamp"], "2024-01-11T09:08:33.500+00:00");
+        assert!(!records[0].contains_key("__name__"));
+        assert_eq!(records[1]["value"], Value::Null);
+    }
+
+    #[test]
+    fn labels_colliding_with_columns_are_err() {
+        let request = |labels| WriteRequest {
+            timeseries: vec![TimeSeries {
+                labels,
+                samples: vec![Sample {
+                    value: 1.0,
+                    timestamp: 1704964113500,
+                }],
+            }],
+        };
+
+        for labels in [
+            vec![label("__name__", "up"), label("value", "x")],
+            vec![label("__name__", "up"), label("timestamp", "x")],
+            vec![label("__name__", "up"), label("metric_name", "x")],
+        ] {
+            assert!(flatten_remote_write(&compress(&request(labels))).is_err());
+        }
+    }
+
+    #[test]
+    fn uncompressed_body_is_err() {
+        let request = WriteRequest {
+            timeseries: vec![TimeSeries {
+                labels: vec![label("__name__", "up")],
+                samples: vec![],
+            }],
+        };
+        let body = Bytes::from(request.encode_to_vec());
+        assert!(flatten_remote_write(&body).is_err());
+    }
+}
@@ -16,25 +16,55 @@
  *
  */
 
-use actix_web::http::header::ContentType;
+use actix_web::http::header::{self, ContentType};
 use actix_web::web::{self, Json};
-use actix_web::{FromRequest, HttpRequest, Responder};
-use chrono::{DateTime, Utc};
+use actix_web::{FromRequest, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, SecondsFormat, Utc};
+use datafusion::arrow::json::writer::record_batches_to_json_rows;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::Column;
 use datafusion::error::DataFusionError;
 use datafusion::execution::context::SessionState;
+use datafusion::logical_expr::{Expr, LogicalPlan, LogicalPlanBuilder};
 use futures_util::Future;
 use http::StatusCode;
-use std::collections::HashMap;
+use itertools::Itertools;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
 use std::pin::Pin;
 use std::time::Instant;
 
+use crate::catalog;
+use crate::event::severity::{SeverityBand, SEVERITY_NUMBER_KEY};
+use crate::event::DEFAULT_TIMESTAMP_KEY;
+use crate::handlers::{
+    QUERY_END_TIME_HEADER_KEY, QUERY_ID_HEADER_KEY, QUERY_START_TIME_HEADER_KEY, SELECT_HEADER_KEY,
+};
+use crate::metadata::STREAM_INFO;
 use crate::metrics::QUERY_EXECUTE_TIME;
+use crate::option::CONFIG;
+use crate::query::buckets::TimeBuckets;
 use crate::query::error::ExecuteError;
-use crate::query::QUERY_SESSION;
-use crate::rbac::role::{Action, Permission};
+use crate::query::histogram::{self, Histogram};
+use crate::query::latest::{self, Latest};
+use crate::query::params::{self, QueryParam};
+use crate::query::profiler::{QueryProfile, QUERY_PROFILER};
+use crate::query::range_schema;
+use crate::query::running::RUNNING_QUERIES;
+use crate::query::{Deadline, QueryStream, QUERY_SESSION};
+use crate::rbac::map::SessionKey;
+use crate::rbac::role::{stream_access, Action, Permission};
 use crate::rbac::Users;
-use crate::response::QueryResponse;
+use crate::response::{timeout_reason, EncodeError, QueryResponse, NDJSON_CONTENT_TYPE};
+use crate::storage::staging;
 use crate::utils::actix::extract_session_key_from_req;
+use crate::utils::arrow::{self, parse_columns};
+use crate::utils::correlation_id;
+
+use super::middleware;
+
+const DEFAULT_TOP_QUERIES: usize = 10;
+const DEFAULT_FACET_LIMIT: usize = 10;
 
 /// Query Request through http endpoint.
 #[derive(Debug, serde::Deserialize)]
@@ -45,63 +75,267 @@ pub struct Query {
     end_time: String,
     #[serde(default)]
     send_null: bool,
+    #[serde(default)]
+    empty_result: EmptyResult,
+    // values bound to the `$1`, `$2`, .. placeholders of the query
+    #[serde(default)]
+    params: Vec<QueryParam>,
+    // columns leading the result, overrides the column order of the stream
+    #[serde(default)]
+    column_order: Option<Vec<String>>,
+    // columns to group the rows of the result by into a nested tree, the rows
+    // are returned flat by default
+    #[serde(default)]
+    group_by: Vec<String>,
+    // interval of the time buckets to group an aggregate query by, like `5m`
+    #[serde(default)]
+    bucket: Option<String>,
+    // size in bytes of the result above which the query fails, lower than the
+    // limit of the server
+    #[serde(default)]
+    max_result_size: Option<usize>,
+    // leave out columns of `SELECT *` which no file in the time range has, the
+    // result has every column the stream ever had by default
+    #[serde(default)]
+    range_schema: bool,
     #[serde(skip)]
     fields: bool,
     #[serde(skip)]
+    analyze: bool,
+    #[serde(skip)]
+    expand_nested: bool,
+    #[serde(skip)]
     filter_tags: Option<Vec<String>>,
+    #[serde(skip)]
+    select: Option<Vec<String>>,
+    #[serde(skip)]
+    partial_on_timeout: bool,
+    #[serde(skip)]
+    with_stats: bool,
+}
+
+/// Response sent when a query returns no rows, set with `emptyResult` in the request body
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EmptyResult {
+    /// 200 with no records along with the fields of the result,
+    /// `{"fields": [...], "records": []}`, so that clients can still render columns
+    #[default]
+    Schema,
+    /// 204 without a body
+    NoContent,
 }
 
 pub async fn query(req: HttpRequest, query_request: Query) -> Result<impl Responder, QueryError> {
     let creds = extract_session_key_from_req(&req).expect("expects basic auth");
     let permissions = Users.get_permissions(&creds);
     let session_state = QUERY_SESSION.state();
+    let deadline = CONFIG
+        .parseable
+        .query_timeout
+        .map(|timeout| Deadline::new(timeout, query_request.partial_on_timeout));
+    let max_result_size = max_result_size(&query_request)?;
     let mut query = into_query(&query_request, &session_state).await?;
+    // the absolute time range the query ran on, so that results of queries
+    // with relative times such as `10m` to now can be reproduced
+    let mut headers = vec![
+        (
+            QUERY_START_TIME_HEADER_KEY,
+            query.start.to_rfc3339_opts(SecondsFormat::Millis, true),
+        ),
+        (
+            QUERY_END_TIME_HEADER_KEY,
+            query.end.to_rfc3339_opts(SecondsFormat::Millis, true),
+        ),
+    ];
 
     // check authorization of this query if it references physical table;
     let table_name = query.table_name();
-    if let Some(ref table) = table_name {
-        let mut authorized = false;
-        let mut tags = Vec::new();
-
-        // in permission check if user can run query on the stream.
-        // also while iterating add any filter tags for this stream
-        for permission in permissions {
-            match permission {
-                Permission::Stream(Action::All, _) => {
-                    authorized = true;
-                    break;
-                }
-                Permission::StreamWithTag(Action::Query, ref stream, tag)
-                    if stream == table || stream == "*" =>
-                {
-                    authorized = true;
-                    if let Some(tag) = tag {
-                        tags.push(tag)
-                    }
-                }
-                _ => (),
+    let raw_ids = authorize_query(permissions, &mut query)?;
+    mask_ids(&mut query, raw_ids, &creds);
+
+    let time = Instant::now();
+    let executed_at = Utc::now();
+
+    if query_request.analyze {
+        let (analysis, bytes_scanned) = query.analyze().await?;
+        QUERY_PROFILER.record(QueryProfile::new(
+            &query_request.query,
+            table_name.clone(),
+            executed_at,
+            time.elapsed().as_millis(),
+            bytes_scanned,
+            analysis.rows,
+        ));
+        let mut response = HttpResponse::Ok().json(analysis);
+        insert_headers(&mut response, &headers);
+        return Ok(response);
+    }
+
+    // the id under which the query can be cancelled while it runs, clients
+    // pick their own id to be able to cancel before the response arrives
+    let query_id = req
+        .headers()
+        .get(QUERY_ID_HEADER_KEY)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+        .unwrap_or_else(|| ulid::Ulid::new().to_string());
+    let running = RUNNING_QUERIES
+        .register(query_owner(&creds), query_id.clone())
+        .ok_or(QueryError::DuplicateQueryId(query_id.clone()))?;
+    let ndjson = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains(NDJSON_CONTENT_TYPE));
+    let column_order = match &query_request.column_order {
+        Some(column_order) => column_order.clone(),
+        None => table_name
+            .as_deref()
+            .and_then(|table| STREAM_INFO.column_order(table).ok())
+            .unwrap_or_default(),
+    };
+    let buckets = time_buckets(&query_request, &query)?;
+
+    // NDJSON rows are sent as they are produced, unless the whole result
+    // is needed to fill, trim or group it first
+    let streamed = ndjson
+        && buckets.is_none()
+        && query_request.group_by.is_empty()
+        && !query_request.range_schema
+        && !query_request.with_stats;
+    if streamed {
+        let mut stream = query.execute_stream(deadline, Some(running)).await?;
+        if !column_order.is_empty() {
+            let names = stream.fields.iter().map(String::as_str).collect_vec();
+            let indices = arrow::column_order(&names, &column_order);
+            stream.project(indices);
+        }
+        // wait for the first rows, so that an empty result is answered as usual
+        let mut records = Vec::new();
+        while let Some(batch) = stream.next().await? {
+            if batch.num_rows() > 0 {
+                records.push(batch);
+                break;
             }
         }
-
-        if !authorized {
-            return Err(QueryError::Unauthorized);
+        headers.push((QUERY_ID_HEADER_KEY, query_id));
+        let statement = query_request.query.clone();
+        let on_end = move |stream: &QueryStream| {
+            QUERY_PROFILER.record(QueryProfile::new(
+                &statement,
+                table_name.clone(),
+                executed_at,
+                time.elapsed().as_millis(),
+                stream.bytes_scanned(),
+                stream.rows(),
+            ));
+            if let Some(table) = table_name {
+                QUERY_EXECUTE_TIME
+                    .with_label_values(&[&table])
+                    .observe(time.elapsed().as_secs_f64());
+            }
+        };
+        let partial = pending_uploads(&query);
+        let empty = records.is_empty();
+        if empty
+            && partial.is_none()
+            && stream.timed_out().is_none()
+            && query_request.empty_result == EmptyResult::NoContent
+        {
+            on_end(&stream);
+            let mut response = HttpResponse::NoContent().finish();
+            insert_headers(&mut response, &headers);
+            return Ok(response);
         }
+        let response = QueryResponse {
+            records,
+            fields: stream.fields.clone(),
+            fill_null: query_request.send_null,
+            with_fields: query_request.fields || empty,
+            expand_nested: query_request.expand_nested,
+            group_by: Vec::new(),
+            partial,
+            stats: None,
+            max_size: Some(max_result_size),
+        };
+        let mut response = response.stream_ndjson_http(stream, on_end);
+        insert_headers(&mut response, &headers);
+        return Ok(middleware::count_uncompressed(response));
+    }
 
-        if !tags.is_empty() {
-            query.filter_tag = Some(tags)
+    let (mut records, fields, bytes_scanned, timed_out) = query
+        .execute_until(deadline, Some(&running), Some(max_result_size))
+        .await?;
+    // a query which ran out of time would not have its stats ready either
+    let stats = if query_request.with_stats && !timed_out {
+        query.stats(deadline, Some(&running)).await?
+    } else {
+        None
+    };
+    drop(running);
+    headers.push((QUERY_ID_HEADER_KEY, query_id));
+    QUERY_PROFILER.record(QueryProfile::new(
+        &query_request.query,
+        table_name.clone(),
+        executed_at,
+        time.elapsed().as_millis(),
+        bytes_scanned,
+        records.iter().map(|rb| rb.num_rows()).sum(),
+    ));
+    if let Some(buckets) = buckets {
+        records = buckets
+            .fill_gaps(records, &query.raw_logical_plan)
+            .map_err(DataFusionError::from)?;
+    }
+    let (records, fields) = match &table_name {
+        Some(table) if query_request.range_schema => {
+            limit_to_range(
+                &query_request,
+                &session_state,
+                &query,
+                table,
+                records,
+                fields,
+            )
+            .await?
         }
+        _ => (records, fields),
+    };
+    let (records, fields) = order_columns(records, fields, &column_order)?;
+    check_group_by(&query_request.group_by, &fields)?;
+    let partial = deadline
+        .filter(|_| timed_out)
+        .map(|deadline| timeout_reason(deadline.timeout));
+    let partial = match (partial, pending_uploads(&query)) {
+        (partial, None) => partial,
+        (partial, Some(pending)) => Some(partial.into_iter().chain([pending]).join("; ")),
+    };
+    let empty = records.iter().all(|rb| rb.num_rows() == 0);
+    if empty && partial.is_none() && query_request.empty_result == EmptyResult::NoContent {
+        let mut response = HttpResponse::NoContent().finish();
+        insert_headers(&mut response, &headers);
+        return Ok(response);
     }
-
-    let time = Instant::now();
-
-    let (records, fields) = query.execute().await?;
     let response = QueryResponse {
         records,
         fields,
         fill_null: query_request.send_null,
-        with_fields: query_request.fields,
-    }
-    .to_http();
+        with_fields: query_request.fields || empty,
+        expand_nested: query_request.expand_nested,
+        group_by: query_request.group_by.clone(),
+        partial,
+        stats,
+        max_size: Some(max_result_size),
+    };
+    // compressed by the compress middleware as the client accepts, the bytes
+    // saved are counted by the compression savings middleware
+    let mut response = if ndjson {
+        response.to_ndjson_http()?
+    } else {
+        response.to_http()?
+    };
+    insert_headers(&mut response, &headers);
 
     if let Some(table) = table_name {
         let time = time.elapsed().as_secs_f64();
@@ -110,7 +344,365 @@ pub async fn query(req: HttpRequest, query_request: Query) -> Result<impl Respon
             .observe(time);
     }
 
-    Ok(response)
+    Ok(middleware::count_uncompressed(response))
+}
+
+// Records staged while uploads fail are only queryable once uploaded
+fn pending_uploads(query: &crate::query::Query) -> Option<String> {
+    let pending = query
+        .table_names()
+        .into_iter()
+        .filter(|table| staging::pending_upload(table))
+        .collect_vec();
+    (!pending.is_empty()).then(|| {
+        format!(
+            "uploads to the object store are failing, records of {} staged since are missing",
+            pending.join(", ")
+        )
+    })
+}
+
+fn max_result_size(query: &Query) -> Result<usize, QueryError> {
+    let limit = CONFIG.parseable.query_max_result_size;
+    match query.max_result_size {
+        None => Ok(limit),
+        Some(size) if size == 0 || size > limit => Err(QueryError::InvalidResultSize(limit)),
+        Some(size) => Ok(size),
+    }
+}
+
+fn insert_headers(response: &mut HttpResponse, headers: &[(&'static str, String)]) {
+    for (key, value) in headers {
+        // values are either timestamps or the query id, which came in a header itself
+        let value = header::HeaderValue::from_str(value).expect("valid header value");
+        response
+            .headers_mut()
+            .insert(header::HeaderName::from_static(key), value);
+    }
+}
+
+// Check the permissions of the user for the streams referenced by the query and
+// restrict the query to the tags and rows of every stream visible to the user.
+// Returns whether the user is allowed to see raw trace and span ids.
+fn authorize_query(
+    permissions: Vec<Permission>,
+    query: &mut crate::query::Query,
+) -> Result<bool, QueryError> {
+    let tables = query.table_names();
+    let mut raw_ids = !tables.is_empty();
+    let mut tags = Vec::new();
+    for table in tables {
+        let access = stream_access(&permissions, &table).ok_or(QueryError::Unauthorized)?;
+        raw_ids &= access.raw_ids;
+        tags.extend(access.tags);
+        if let Some(row_filters) = access.row_filters {
+            if let Some(column) = query.conflicting_row_filter(&table, &row_filters) {
+                return Err(QueryError::RowFilterConflict(column));
+            }
+            query.row_filters.insert(table, row_filters);
+        }
+    }
+
+    if !tags.is_empty() {
+        query.filter_tag = Some(tags)
+    }
+
+    Ok(raw_ids)
+}
+
+// Handler for GET /api/v1/query/profile
+// lists the slowest and most expensive of the recently executed queries
+pub async fn get_top_queries(
+    params: web::Query<HashMap<String, usize>>,
+) -> Result<impl Responder, QueryError> {
+    let n = params.get("n").cloned().unwrap_or(DEFAULT_TOP_QUERIES);
+    Ok(web::Json(QUERY_PROFILER.top(n)))
+}
+
+// Handler for POST /api/v1/query/{id}/_cancel
+// cancels the running query with this id, which is given in the
+// X-P-Query-Id header of the query request or response
+// only queries of the same user can be cancelled, except by admins
+pub async fn cancel_query(req: HttpRequest) -> Result<impl Responder, QueryError> {
+    let id = req.match_info().get("id").unwrap_or_default();
+    let creds = extract_session_key_from_req(&req).expect("expects basic auth");
+    let is_admin = Users
+        .get_permissions(&creds)
+        .contains(&Permission::Stream(Action::All, "*".to_string()));
+    let owner = (!is_admin).then(|| query_owner(&creds));
+    let cancelled = RUNNING_QUERIES.cancel(owner.as_deref(), id);
+    let status = if cancelled {
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    };
+    Ok(HttpResponse::build(status).json(serde_json::json!({
+        "id": id,
+        "cancelled": cancelled,
+    })))
+}
+
+// hash trace and span ids for users not allowed to see raw ids, with the key
+// of the roles restricting them so that users of a tenant see the same ids
+fn mask_ids(query: &mut crate::query::Query, raw_ids: bool, creds: &SessionKey) {
+    if let (false, Some(key)) = (raw_ids, &CONFIG.parseable.correlation_id_key) {
+        let roles = Users.id_restricting_roles(creds, &query.table_names());
+        query.id_key = Some(correlation_id::tenant_key(key, &roles));
+    }
+}
+
+// user a query runs for, the session itself if it has no user
+fn query_owner(creds: &SessionKey) -> String {
+    match Users.get_username(creds) {
+        Some(username) => username,
+        None => match creds {
+            SessionKey::BasicAuth { username, .. } => username.clone(),
+            SessionKey::SessionId(id) => id.to_string(),
+        },
+    }
+}
+
+/// Facet request through http endpoint, counts rows by the values of a column
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FacetRequest {
+    stream: String,
+    #[serde(default)]
+    column: Option<String>,
+    start_time: String,
+    end_time: String,
+    // count severity numbers by the six coarse severity levels instead,
+    // the column defaults to severity_number
+    #[serde(default)]
+    severity_bands: bool,
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct FacetCount {
+    value: Value,
+    count: u64,
+}
+
+// Handler for POST /api/v1/query/facets
+// counts rows in the time range by the most frequent values of the column,
+// with severityBands the count of every severity level is returned
+pub async fn query_facets(
+    req: HttpRequest,
+    facet: Json<FacetRequest>,
+) -> Result<impl Responder, QueryError> {
+    let facet = facet.into_inner();
+    let column = match (facet.column, facet.severity_bands) {
+        (Some(column), _) => column,
+        (None, true) => SEVERITY_NUMBER_KEY.to_string(),
+        (None, false) => return Err(QueryError::InvalidFacet("column is required".to_string())),
+    };
+    let schema = STREAM_INFO
+        .schema(&facet.stream)
+        .map_err(|_| QueryError::InvalidFacet(format!("stream {} does not exist", facet.stream)))?;
+    if schema.field_with_name(&column).is_err() {
+        return Err(QueryError::InvalidFacet(format!(
+            "column {column} does not exist in stream {}",
+            facet.stream
+        )));
+    }
+
+    let quoted = format!("\"{}\"", column.replace('"', "\"\""));
+    let sql = if facet.severity_bands {
+        format!(
+            "SELECT CAST({quoted} AS BIGINT) AS \"value\", count(*) AS \"count\" FROM \"{}\" GROUP BY CAST({quoted} AS BIGINT)",
+            facet.stream
+        )
+    } else {
+        format!(
+            "SELECT {quoted} AS \"value\", count(*) AS \"count\" FROM \"{}\" GROUP BY {quoted} ORDER BY \"count\" DESC LIMIT {}",
+            facet.stream,
+            facet.limit.unwrap_or(DEFAULT_FACET_LIMIT)
+        )
+    };
+    let rows = query_rows(&req, sql, facet.start_time, facet.end_time).await?;
+    let counts = rows.into_iter().map(|mut row| FacetCount {
+        value: row.remove("value").unwrap_or(Value::Null),
+        count: row.get("count").and_then(Value::as_u64).unwrap_or_default(),
+    });
+
+    let values = if facet.severity_bands {
+        let mut bands: BTreeMap<SeverityBand, u64> =
+            SeverityBand::ALL.iter().map(|band| (*band, 0)).collect();
+        for count in counts {
+            if let Some(band) = count.value.as_i64().and_then(SeverityBand::of) {
+                *bands.entry(band).or_default() += count.count;
+            }
+        }
+        bands
+            .into_iter()
+            .map(|(band, count)| FacetCount {
+                value: serde_json::json!(band),
+                count,
+            })
+            .collect()
+    } else {
+        counts.collect_vec()
+    };
+
+    Ok(web::Json(serde_json::json!({
+        "column": column,
+        "values": values,
+    })))
+}
+
+/// Histogram request through http endpoint, counts rows by buckets of the
+/// values of a numeric column
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistogramRequest {
+    stream: String,
+    column: String,
+    // upper bounds of the buckets in increasing order, a last bucket holds
+    // the values from the last bound up
+    bounds: Vec<f64>,
+    start_time: String,
+    end_time: String,
+}
+
+// Handler for POST /api/v1/query/histogram
+// counts rows in the time range by the bucket of their value of the column
+pub async fn query_histogram(
+    req: HttpRequest,
+    request: Json<HistogramRequest>,
+) -> Result<impl Responder, QueryError> {
+    let request = request.into_inner();
+    let histogram = Histogram::new(request.bounds).map_err(QueryError::InvalidHistogram)?;
+    let schema = STREAM_INFO.schema(&request.stream).map_err(|_| {
+        QueryError::InvalidHistogram(format!("stream {} does not exist", request.stream))
+    })?;
+    match schema.field_with_name(&request.column) {
+        Ok(field) if field.data_type().is_numeric() => (),
+        Ok(_) => {
+            return Err(QueryError::InvalidHistogram(format!(
+                "column {} is not numeric",
+                request.column
+            )))
+        }
+        Err(_) => {
+            return Err(QueryError::InvalidHistogram(format!(
+                "column {} does not exist in stream {}",
+                request.column, request.stream
+            )))
+        }
+    }
+
+    let sql = histogram.sql(&request.stream, &request.column);
+    let rows = query_rows(&req, sql, request.start_time, request.end_time).await?;
+    let counts = rows.iter().filter_map(|row| {
+        let bucket = row.get(histogram::BUCKET_KEY)?.as_u64()?;
+        let count = row.get(histogram::COUNT_KEY)?.as_u64()?;
+        Some((bucket as usize, count))
+    });
+
+    Ok(web::Json(serde_json::json!({
+        "column": request.column,
+        "buckets": histogram.buckets(counts),
+    })))
+}
+
+/// Latest rows request through http endpoint, returns the most recent row of
+/// every group of rows with the same values of the group columns
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LatestRequest {
+    stream: String,
+    group_by: Vec<String>,
+    // columns returned along with the group columns, all if left out
+    #[serde(default)]
+    columns: Option<Vec<String>>,
+    start_time: String,
+    end_time: String,
+}
+
+// Handler for POST /api/v1/query/latest
+// returns the row with the latest time in the time range of every group
+pub async fn query_latest(
+    req: HttpRequest,
+    request: Json<LatestRequest>,
+) -> Result<impl Responder, QueryError> {
+    let request = request.into_inner();
+    let latest = Latest::new(request.group_by.clone(), request.columns)
+        .map_err(QueryError::InvalidLatest)?;
+    let schema = STREAM_INFO.schema(&request.stream).map_err(|_| {
+        QueryError::InvalidLatest(format!("stream {} does not exist", request.stream))
+    })?;
+    if let Some(column) = latest
+        .referenced_columns()
+        .find(|column| schema.field_with_name(column).is_err())
+    {
+        return Err(QueryError::InvalidLatest(format!(
+            "column {column} does not exist in stream {}",
+            request.stream
+        )));
+    }
+
+    let timestamp_key = STREAM_INFO
+        .timestamp_key(&request.stream)
+        .unwrap_or_else(|_| DEFAULT_TIMESTAMP_KEY.to_string());
+    let sql = latest.sql(&request.stream, &timestamp_key);
+    let mut rows = query_rows(&req, sql, request.start_time, request.end_time).await?;
+    for row in &mut rows {
+        row.remove(latest::RANK_KEY);
+    }
+
+    Ok(web::Json(serde_json::json!({
+        "groupBy": request.group_by,
+        "records": rows,
+    })))
+}
+
+// Run a query built by a helper endpoint for the user of the request,
+// returning its rows as JSON objects
+async fn query_rows(
+    req: &HttpRequest,
+    sql: String,
+    start_time: String,
+    end_time: String,
+) -> Result<Vec<serde_json::Map<String, Value>>, QueryError> {
+    let query_request = Query {
+        query: sql,
+        start_time,
+        end_time,
+        send_null: false,
+        empty_result: EmptyResult::default(),
+        params: Vec::new(),
+        column_order: None,
+        group_by: Vec::new(),
+        bucket: None,
+        max_result_size: None,
+        range_schema: false,
+        fields: false,
+        analyze: false,
+        expand_nested: false,
+        filter_tags: None,
+        select: None,
+        partial_on_timeout: false,
+        with_stats: false,
+    };
+
+    let creds = extract_session_key_from_req(req).expect("expects basic auth");
+    let session_state = QUERY_SESSION.state();
+    let mut query = into_query(&query_request, &session_state).await?;
+    let raw_ids = authorize_query(Users.get_permissions(&creds), &mut query)?;
+    mask_ids(&mut query, raw_ids, &creds);
+    let records = match CONFIG.parseable.query_timeout {
+        Some(timeout) => {
+            query
+                .execute_until(Some(Deadline::new(timeout, false)), None, None)
+                .await?
+                .0
+        }
+        None => query.execute().await?.0,
+    };
+    let records: Vec<&RecordBatch> = records.iter().collect();
+    Ok(record_batches_to_json_rows(&records).map_err(DataFusionError::from)?)
 }
 
 impl FromRequest for Query {
@@ -123,11 +715,27 @@ impl FromRequest for Query {
             .into_inner()
             .map(|x| x.0)
             .unwrap_or_default();
+        // shorthand for selecting columns of the result, `X-P-Select: host, status`
+        let select = req
+            .headers()
+            .get(SELECT_HEADER_KEY)
+            .and_then(|value| value.to_str().ok())
+            .map(parse_columns)
+            .filter(|columns| !columns.is_empty());
 
         let fut = async move {
             let mut query = query.await?.into_inner();
             // format output json to include field names
             query.fields = params.get("fields").cloned().unwrap_or(false);
+            // run as EXPLAIN ANALYZE and respond with the metrics of the plan
+            query.analyze = params.get("analyze").cloned().unwrap_or(false);
+            // return JSON encoded string cells as nested objects and arrays
+            query.expand_nested = params.get("expandNested").cloned().unwrap_or(false);
+            query.select = select;
+            // respond with the rows produced so far when the query times out
+            query.partial_on_timeout = params.get("partialOnTimeout").cloned().unwrap_or(false);
+            // count and time range of all matching rows along with the records
+            query.with_stats = params.get("withStats").cloned().unwrap_or(false);
 
             if !query.send_null {
                 query.send_null = params.get("sendNull").cloned().unwrap_or(false);
@@ -175,14 +783,148 @@ async fn into_query(
         return Err(QueryError::StartTimeAfterEndTime);
     }
 
+    let dialect = session_state.config().options().sql_parser.dialect.clone();
+    let mut statement = session_state.sql_to_statement(&query.query, &dialect)?;
+    if let Some(interval) = &query.bucket {
+        TimeBuckets::new(interval, start, end)
+            .and_then(|buckets| {
+                buckets.rewrite(&mut statement, |table| {
+                    STREAM_INFO
+                        .timestamp_key(table)
+                        .unwrap_or_else(|_| DEFAULT_TIMESTAMP_KEY.to_string())
+                })
+            })
+            .map_err(QueryError::InvalidBucket)?;
+    }
+    let mut raw_logical_plan = params::bind(
+        session_state.statement_to_plan(statement).await?,
+        &query.params,
+    )?;
+    if let Some(columns) = &query.select {
+        raw_logical_plan = select(raw_logical_plan, columns)?;
+    }
+
     Ok(crate::query::Query {
-        raw_logical_plan: session_state.create_logical_plan(&query.query).await?,
+        raw_logical_plan,
         start,
         end,
         filter_tag: query.filter_tags.clone(),
+        row_filters: HashMap::new(),
+        id_key: None,
     })
 }
 
+// Time buckets of an aggregate query asking for them, over the time range the
+// query ran on
+fn time_buckets(
+    query_request: &Query,
+    query: &crate::query::Query,
+) -> Result<Option<TimeBuckets>, QueryError> {
+    query_request
+        .bucket
+        .as_deref()
+        .map(|interval| TimeBuckets::new(interval, query.start, query.end))
+        .transpose()
+        .map_err(QueryError::InvalidBucket)
+}
+
+// Leave out the columns of the stream which no file in the time range of a
+// wildcard query has
+async fn limit_to_range(
+    query_request: &Query,
+    session_state: &SessionState,
+    query: &crate::query::Query,
+    table: &str,
+    records: Vec<RecordBatch>,
+    fields: Vec<String>,
+) -> Result<(Vec<RecordBatch>, Vec<String>), QueryError> {
+    let dialect = session_state.config().options().sql_parser.dialect.clone();
+    let statement = session_state.sql_to_statement(&query_request.query, &dialect)?;
+    if !range_schema::selects_all(&statement) {
+        return Ok((records, fields));
+    }
+    let Ok(schema) = STREAM_INFO.schema(table) else {
+        return Ok((records, fields));
+    };
+    let storage = CONFIG.storage().get_object_store();
+    let columns = catalog::range_columns(storage, table, query.start, query.end)
+        .await
+        .map_err(ExecuteError::from)?;
+    let absent = schema
+        .fields()
+        .iter()
+        .map(|field| field.name())
+        .filter(|name| !columns.contains(*name))
+        .cloned()
+        .collect();
+    Ok(range_schema::drop_absent(records, fields, &absent)?)
+}
+
+// Reorder the result so that the listed columns come first.
+// This only changes how the result is serialized
+fn order_columns(
+    records: Vec<RecordBatch>,
+    fields: Vec<String>,
+    order: &[String],
+) -> Result<(Vec<RecordBatch>, Vec<String>), QueryError> {
+    if order.is_empty() {
+        return Ok((records, fields));
+    }
+    let names = fields.iter().map(String::as_str).collect_vec();
+    let indices = arrow::column_order(&names, order);
+    let fields = indices.iter().map(|&index| fields[index].clone()).collect();
+    // batches have the columns of the fields of the result
+    let records = records
+        .iter()
+        .map(|rb| rb.project(&indices))
+        .collect::<Result<_, _>>()
+        .map_err(DataFusionError::from)?;
+    Ok((records, fields))
+}
+
+// The rows of a grouped result keep at least one column, grouping by all of
+// them would leave empty rows
+fn check_group_by(group_by: &[String], fields: &[String]) -> Result<(), QueryError> {
+    if let Some(column) = group_by.iter().find(|column| !fields.contains(column)) {
+        return Err(QueryError::InvalidGroupBy(format!(
+            "column {column} is not in the result of the query"
+        )));
+    }
+    if group_by.iter().duplicates().next().is_some() {
+        return Err(QueryError::InvalidGroupBy(
+            "columns can be grouped by once".to_string(),
+        ));
+    }
+    if !group_by.is_empty() && group_by.len() >= fields.len() {
+        return Err(QueryError::InvalidGroupBy(
+            "at least one column should be left for the rows".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// project the result of the query on the listed columns
+fn select(plan: LogicalPlan, columns: &[String]) -> Result<LogicalPlan, QueryError> {
+    let missing = columns
+        .iter()
+        .filter(|column| {
+            !plan
+                .schema()
+                .fields()
+                .iter()
+                .any(|field| field.name() == *column)
+        })
+        .join(", ");
+    if !missing.is_empty() {
+        return Err(QueryError::UnknownColumns(missing));
+    }
+
+    let exprs = columns
+        .iter()
+        .map(|column| Expr::Column(Column::from_name(column)));
+    Ok(LogicalPlanBuilder::from(plan).project(exprs)?.build()?)
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum QueryError {
     #[error("Query cannot be empty")]
@@ -203,16 +945,55 @@ pub enum QueryError {
     StartTimeAfterEndTime,
     #[error("Unauthorized")]
     Unauthorized,
+    #[error("Selected columns {0} are not in the result of the query")]
+    UnknownColumns(String),
+    #[error("Invalid time bucket: {0}")]
+    InvalidBucket(String),
+    #[error("Invalid facet: {0}")]
+    InvalidFacet(String),
+    #[error("Invalid histogram: {0}")]
+    InvalidHistogram(String),
+    #[error("Invalid latest rows request: {0}")]
+    InvalidLatest(String),
+    #[error("Invalid grouping: {0}")]
+    InvalidGroupBy(String),
+    #[error("Query filters column {0} on values which are not visible to this user")]
+    RowFilterConflict(String),
+    #[error("Max result size should be between 1 and {0} bytes")]
+    InvalidResultSize(usize),
+    #[error("Query result is larger than the limit of {0} bytes, select fewer rows or columns")]
+    ResultTooLarge(usize),
+    #[error("A query with id {0} is already running")]
+    DuplicateQueryId(String),
     #[error("Datafusion Error: {0}")]
     Datafusion(#[from] DataFusionError),
     #[error("Execution Error: {0}")]
     Execute(#[from] ExecuteError),
+    #[error("Failed to encode query response: {0}")]
+    Encode(#[from] std::io::Error),
+}
+
+impl From<EncodeError> for QueryError {
+    fn from(err: EncodeError) -> Self {
+        match err {
+            EncodeError::TooLarge(limit) => QueryError::ResultTooLarge(limit),
+            EncodeError::Io(err) => QueryError::Encode(err),
+        }
+    }
 }
 
 impl actix_web::ResponseError for QueryError {
     fn status_code(&self) -> http::StatusCode {
         match self {
-            QueryError::Execute(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            QueryError::Execute(ExecuteError::Timeout(_)) => StatusCode::REQUEST_TIMEOUT,
+            // non standard status for requests cancelled by the client, as used by nginx
+            QueryError::Execute(ExecuteError::Cancelled) => {
+                StatusCode::from_u16(499).expect("499 is a valid status code")
+            }
+            QueryError::DuplicateQueryId(_) => StatusCode::CONFLICT,
+            QueryError::Execute(ExecuteError::ResultTooLarge(_)) => StatusCode::BAD_REQUEST,
+            QueryError::Execute(_) | QueryError::Encode(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            QueryError::RowFilterConflict(_) => StatusCode::FORBIDDEN,
             _ => StatusCode::BAD_REQUEST,
         }
     }
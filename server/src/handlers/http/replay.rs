@@ -0,0 +1,364 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+// Resumable replay of large NDJSON files.
+// A client opens a session for a stream, uploads the file in numbered chunks and
+// commits the session once all chunks are uploaded. Each chunk is ingested as soon
+// as it is received and acknowledged only after it has been written to staging,
+// so an interrupted upload can be resumed from the status of the session.
+// Chunks which were already acknowledged are not ingested again when replayed.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use actix_web::{http::header::ContentType, web, HttpRequest, HttpResponse, Responder};
+use bytes::Bytes;
+use http::StatusCode;
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use ulid::Ulid;
+
+use crate::event;
+use crate::metadata::STREAM_INFO;
+
+use super::ingest::{push_logs, EventBody, PostError};
+
+// sessions without any activity for this long are dropped
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub static REPLAY_SESSIONS: Lazy<ReplaySessions> = Lazy::new(ReplaySessions::default);
+
+#[derive(Debug)]
+pub struct ReplaySession {
+    stream: String,
+    committed: BTreeSet<u64>,
+    in_flight: BTreeSet<u64>,
+    events: u64,
+    last_activity: Instant,
+}
+
+impl ReplaySession {
+    fn new(stream: String) -> Self {
+        Self {
+            stream,
+            committed: BTreeSet::new(),
+            in_flight: BTreeSet::new(),
+            events: 0,
+            last_activity: Instant::now(),
+        }
+    }
+
+    // highest chunk number up to which every chunk starting from 0 is committed
+    fn last_acked_chunk(&self) -> Option<u64> {
+        let mut last = None;
+        for (expected, chunk) in self.committed.iter().enumerate() {
+            if expected as u64 != *chunk {
+                break;
+            }
+            last = Some(*chunk);
+        }
+        last
+    }
+
+    fn status(&self, session: Ulid) -> SessionStatus {
+        SessionStatus {
+            session: session.to_string(),
+            stream: self.stream.clone(),
+            committed_chunks: self.committed.iter().copied().collect(),
+            last_acked_chunk: self.last_acked_chunk(),
+            events: self.events,
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionStatus {
+    session: String,
+    stream: String,
+    committed_chunks: Vec<u64>,
+    last_acked_chunk: Option<u64>,
+    events: u64,
+}
+
+#[derive(Debug, PartialEq)]
+enum ChunkState {
+    // chunk was reserved and should be ingested by the caller
+    Reserved,
+    // chunk was already committed earlier in the session
+    Duplicate,
+}
+
+#[derive(Debug, Default)]
+pub struct ReplaySessions(RwLock<HashMap<Ulid, ReplaySession>>);
+
+impl ReplaySessions {
+    fn create(&self, stream: String) -> Ulid {
+        let mut sessions = self.0.write().unwrap();
+        sessions.retain(|_, session| session.last_activity.elapsed() < SESSION_IDLE_TIMEOUT);
+        let id = Ulid::new();
+        sessions.insert(id, ReplaySession::new(stream));
+        id
+    }
+
+    fn status(&self, id: Ulid, stream: &str) -> Result<SessionStatus, ReplayError> {
+        let sessions = self.0.read().unwrap();
+        let session = get_session(&sessions, id, stream)?;
+        Ok(session.status(id))
+    }
+
+    fn begin_chunk(&self, id: Ulid, stream: &str, chunk: u64) -> Result<ChunkState, ReplayError> {
+        let mut sessions = self.0.write().unwrap();
+        let session = get_session_mut(&mut sessions, id, stream)?;
+        session.last_activity = Instant::now();
+        if session.committed.contains(&chunk) {
+            return Ok(ChunkState::Duplicate);
+        }
+        if !session.in_flight.insert(chunk) {
+            return Err(ReplayError::ChunkInProgress(chunk));
+        }
+        Ok(ChunkState::Reserved)
+    }
+
+    fn end_chunk(&self, id: Ulid, chunk: u64, events: Option<u64>) {
+        let mut sessions = self.0.write().unwrap();
+        let Some(session) = sessions.get_mut(&id) else {
+            return;
+        };
+        session.in_flight.remove(&chunk);
+        if let Some(events) = events {
+            session.committed.insert(chunk);
+            session.events += events;
+        }
+    }
+
+    fn remove(&self, id: Ulid, stream: &str) -> Result<ReplaySession, ReplayError> {
+        let mut sessions = self.0.write().unwrap();
+        let session = get_session(&sessions, id, stream)?;
+        if let Some(chunk) = session.in_flight.first() {
+            return Err(ReplayError::ChunkInProgress(*chunk));
+        }
+        Ok(sessions.remove(&id).expect("session exists"))
+    }
+}
+
+fn get_session<'a>(
+    sessions: &'a HashMap<Ulid, ReplaySession>,
+    id: Ulid,
+    stream: &str,
+) -> Result<&'a ReplaySession, ReplayError> {
+    sessions
+        .get(&id)
+        .filter(|session| session.stream == stream)
+        .ok_or_else(|| ReplayError::SessionNotFound(id.to_string()))
+}
+
+fn get_session_mut<'a>(
+    sessions: &'a mut HashMap<Ulid, ReplaySession>,
+    id: Ulid,
+    stream: &str,
+) -> Result<&'a mut ReplaySession, ReplayError> {
+    sessions
+        .get_mut(&id)
+        .filter(|session| session.stream == stream)
+        .ok_or_else(|| ReplayError::SessionNotFound(id.to_string()))
+}
+
+fn session_id(req: &HttpRequest) -> Result<Ulid, ReplayError> {
+    let session = req.match_info().get("session").unwrap();
+    Ulid::from_string(session).map_err(|_| ReplayError::SessionNotFound(session.to_string()))
+}
+
+// Parse a chunk of newline delimited JSON into a JSON array of events
+fn parse_ndjson(body: &[u8]) -> Result<Vec<Value>, ReplayError> {
+    let body = std::str::from_utf8(body).map_err(|_| ReplayError::InvalidChunk(0))?;
+    body.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|_| ReplayError::InvalidChunk(index + 1))
+        })
+        .collect()
+}
+
+// Handler for POST /api/v1/logstream/{logstream}/replay
+// opens a new replay session for the given stream
+pub async fn create_session(req: HttpRequest) -> Result<impl Responder, ReplayError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(PostError::StreamNotFound(stream_name).into());
+    }
+
+    let id = REPLAY_SESSIONS.create(stream_name.clone());
+    let status = REPLAY_SESSIONS.status(id, &stream_name)?;
+    Ok((web::Json(status), StatusCode::CREATED))
+}
+
+// Handler for GET /api/v1/logstream/{logstream}/replay/{session}
+// returns the committed chunks of a session, used to resume an interrupted upload
+pub async fn get_session_status(req: HttpRequest) -> Result<impl Responder, ReplayError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let id = session_id(&req)?;
+    let status = REPLAY_SESSIONS.status(id, &stream_name)?;
+    Ok((web::Json(status), StatusCode::OK))
+}
+
+// Handler for PUT /api/v1/logstream/{logstream}/replay/{session}/{chunk}
+// ingests one NDJSON chunk, replaying an already committed chunk is a no-op
+pub async fn put_chunk(
+    req: HttpRequest,
+    EventBody(body): EventBody,
+) -> Result<impl Responder, ReplayError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let id = session_id(&req)?;
+    let chunk: u64 = req
+        .match_info()
+        .get("chunk")
+        .unwrap()
+        .parse()
+        .map_err(|_| ReplayError::InvalidChunkNumber)?;
+
+    if REPLAY_SESSIONS.begin_chunk(id, &stream_name, chunk)? == ChunkState::Duplicate {
+        let status = REPLAY_SESSIONS.status(id, &stream_name)?;
+        return Ok((web::Json(status), StatusCode::OK));
+    }
+
+    let result = ingest_chunk(&stream_name, req, &body).await;
+    REPLAY_SESSIONS.end_chunk(id, chunk, result.as_ref().ok().copied());
+    result?;
+
+    let status = REPLAY_SESSIONS.status(id, &stream_name)?;
+    Ok((web::Json(status), StatusCode::OK))
+}
+
+async fn ingest_chunk(
+    stream_name: &str,
+    req: HttpRequest,
+    body: &[u8],
+) -> Result<u64, ReplayError> {
+    let events = parse_ndjson(body)?;
+    let count = events.len() as u64;
+    if count > 0 {
+        let body: Bytes = serde_json::to_vec(&events).map_err(PostError::from)?.into();
+        push_logs(stream_name.to_string(), req, body).await?;
+    }
+    Ok(count)
+}
+
+// Handler for POST /api/v1/logstream/{logstream}/replay/{session}/commit
+// closes the session and flushes the staged data of the stream
+pub async fn commit_session(req: HttpRequest) -> Result<impl Responder, ReplayError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let id = session_id(&req)?;
+    let session = REPLAY_SESSIONS.remove(id, &stream_name)?;
+    event::STREAM_WRITERS.unset_stream(&stream_name);
+    Ok((web::Json(session.status(id)), StatusCode::OK))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    #[error("Replay session {0} not found")]
+    SessionNotFound(String),
+    #[error("Chunk number must be a non negative integer")]
+    InvalidChunkNumber,
+    #[error("Chunk {0} is still being ingested")]
+    ChunkInProgress(u64),
+    #[error("Chunk is not valid NDJSON, error at line {0}")]
+    InvalidChunk(usize),
+    #[error("{0}")]
+    Post(#[from] PostError),
+}
+
+impl actix_web::ResponseError for ReplayError {
+    fn status_code(&self) -> http::StatusCode {
+        match self {
+            ReplayError::SessionNotFound(_) => StatusCode::NOT_FOUND,
+            ReplayError::InvalidChunkNumber => StatusCode::BAD_REQUEST,
+            ReplayError::ChunkInProgress(_) => StatusCode::CONFLICT,
+            ReplayError::InvalidChunk(_) => StatusCode::BAD_REQUEST,
+            ReplayError::Post(err) => err.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        HttpResponse::build(self.status_code())
+            .insert_header(ContentType::plaintext())
+            .body(self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_ndjson, ChunkState, ReplayError, ReplaySessions};
+
+    #[test]
+    fn replayed_chunk_is_duplicate() {
+        let sessions = ReplaySessions::default();
+        let id = sessions.create("app".to_string());
+
+        assert_eq!(
+            sessions.begin_chunk(id, "app", 0).unwrap(),
+            ChunkState::Reserved
+        );
+        assert!(matches!(
+            sessions.begin_chunk(id, "app", 0),
+            Err(ReplayError::ChunkInProgress(0))
+        ));
+        sessions.end_chunk(id, 0, Some(10));
+        assert_eq!(
+            sessions.begin_chunk(id, "app", 0).unwrap(),
+            ChunkState::Duplicate
+        );
+
+        // failed chunk can be retried
+        sessions.begin_chunk(id, "app", 1).unwrap();
+        sessions.end_chunk(id, 1, None);
+        assert_eq!(
+            sessions.begin_chunk(id, "app", 1).unwrap(),
+            ChunkState::Reserved
+        );
+
+        assert!(sessions.begin_chunk(id, "other", 2).is_err());
+    }
+
+    #[test]
+    fn last_acked_chunk_is_contiguous() {
+        let sessions = ReplaySessions::default();
+        let id = sessions.create("app".to_string());
+        for chunk in [0, 1, 3] {
+            sessions.begin_chunk(id, "app", chunk).unwrap();
+            sessions.end_chunk(id, chunk, Some(1));
+        }
+
+        let status = sessions.status(id, "app").unwrap();
+        assert_eq!(status.last_acked_chunk, Some(1));
+        assert_eq!(status.committed_chunks, vec![0, 1, 3]);
+        assert_eq!(status.events, 3);
+    }
+
+    #[test]
+    fn ndjson_chunk() {
+        let events = parse_ndjson(b"{\"a\": 1}\n\n{\"a\": 2}\n").unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(
+            parse_ndjson(b"{\"a\": 1}\n{\"a\""),
+            Err(ReplayError::InvalidChunk(2))
+        ));
+    }
+}
@@ -0,0 +1,152 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use http::StatusCode;
+use rand::distributions::{Alphanumeric, DistString};
+use tokio::sync::mpsc;
+
+use crate::livetail::aggregate::{WindowAggregator, WindowCounts};
+use crate::livetail::{Message, LIVETAIL};
+use crate::metadata::STREAM_INFO;
+use crate::rbac::role::RowFilter;
+use crate::rbac::Users;
+use crate::utils::actix::extract_session_key_from_req;
+use crate::utils::arrow::filter_rows;
+
+use super::logstream::error::StreamError;
+
+const DEFAULT_WINDOW: &str = "1m";
+const DEFAULT_LATENESS: &str = "5s";
+// how often idle windows are checked for closing
+const TICK: Duration = Duration::from_secs(1);
+// closed windows waiting to be sent to the client
+const WINDOW_BUFFER: usize = 64;
+
+// Handler for GET /api/v1/logstream/{logstream}/tail/aggregate?window=1m&lateness=5s&groupBy=level
+// Counts the events of the live tail in tumbling windows and sends every closed
+// window as a server sent event. Closed windows are buffered for a slow client,
+// while the buffer is full the tail is not read and the events it misses are
+// counted as skipped.
+pub async fn aggregate(
+    req: HttpRequest,
+    params: web::Query<HashMap<String, String>>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let parse_duration = |key: &str, default: &str| {
+        let value = params.get(key).map(String::as_str).unwrap_or(default);
+        humantime::parse_duration(value)
+            .ok()
+            .and_then(|duration| chrono::Duration::from_std(duration).ok())
+            .ok_or_else(|| StreamError::InvalidTailAggregate(format!("invalid {key} {value}")))
+    };
+    let window = parse_duration("window", DEFAULT_WINDOW)?;
+    let lateness = parse_duration("lateness", DEFAULT_LATENESS)?;
+    if window < chrono::Duration::seconds(1) {
+        return Err(StreamError::InvalidTailAggregate(
+            "window should be at least 1s".to_string(),
+        ));
+    }
+
+    let group_by = params.get("groupBy").cloned();
+    if let Some(column) = &group_by {
+        let schema = STREAM_INFO.schema(&stream_name)?;
+        if schema.field_with_name(column).is_err() {
+            return Err(StreamError::InvalidTailAggregate(format!(
+                "column {column} does not exist in stream {stream_name}"
+            )));
+        }
+    }
+
+    // readers restricted to some rows of the stream only count those rows
+    let creds = extract_session_key_from_req(&req).expect("expects basic auth");
+    let row_filters = Users
+        .stream_access(&creds, &stream_name)
+        .ok_or_else(|| StreamError::Custom {
+            msg: format!("not allowed to query stream {stream_name}"),
+            status: StatusCode::FORBIDDEN,
+        })?
+        .row_filters;
+
+    let timestamp_key = STREAM_INFO.timestamp_key(&stream_name)?;
+    let aggregator = WindowAggregator::new(window, lateness, timestamp_key, group_by);
+    let (tx, rx) = mpsc::channel(WINDOW_BUFFER);
+    actix_web::rt::spawn(run(stream_name, row_filters, aggregator, tx));
+
+    let events = futures::stream::unfold(rx, |mut rx| async move {
+        let window = rx.recv().await?;
+        let event = format!(
+            "event: window\ndata: {}\n\n",
+            serde_json::to_string(&window).ok()?
+        );
+        Some((Ok::<_, actix_web::Error>(Bytes::from(event)), rx))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(events))
+}
+
+// reads the live tail until the client goes away
+async fn run(
+    stream_name: String,
+    row_filters: Option<Vec<RowFilter>>,
+    mut aggregator: WindowAggregator,
+    tx: mpsc::Sender<WindowCounts>,
+) {
+    let mut pipe = LIVETAIL.new_pipe(
+        Alphanumeric.sample_string(&mut rand::thread_rng(), 32),
+        stream_name,
+    );
+    let mut tick = actix_web::rt::time::interval(TICK);
+
+    loop {
+        tokio::select! {
+            message = pipe.next() => match message {
+                Some(Message::Record(rb)) => match &row_filters {
+                    Some(row_filters) => match filter_rows(&rb, row_filters) {
+                        Ok(rb) => aggregator.add(&rb),
+                        Err(err) => log::warn!("failed to filter rows of the live tail: {err}"),
+                    },
+                    None => aggregator.add(&rb),
+                },
+                Some(Message::Skipped(rows)) => aggregator.skip(rows),
+                None => break,
+            },
+            _ = tick.tick() => {
+                for window in aggregator.advance(chrono::Utc::now().timestamp_millis()) {
+                    if tx.send(window).await.is_err() {
+                        return;
+                    }
+                }
+            },
+            _ = tx.closed() => break,
+        }
+    }
+}
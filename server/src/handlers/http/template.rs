@@ -0,0 +1,136 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::HashMap;
+
+use actix_web::{http::header::ContentType, web, HttpResponse, Responder};
+use http::StatusCode;
+
+use crate::{
+    metadata::STREAM_INFO,
+    option::CONFIG,
+    storage::{self, ObjectStorageError, StorageMetadata, StreamTemplate},
+};
+
+use super::logstream::{self, error::StreamError};
+
+// Handler for PUT /api/v1/template/{name}
+// Creates a new stream template or updates an existing one.
+// With ?propagate=true the template is applied again to every stream created from it.
+pub async fn put(
+    name: web::Path<String>,
+    params: web::Query<HashMap<String, bool>>,
+    body: web::Json<StreamTemplate>,
+) -> Result<impl Responder, TemplateError> {
+    let name = name.into_inner();
+    let template = body.into_inner();
+    logstream::validate_template(&template)?;
+
+    let mut metadata = get_metadata().await?;
+    metadata
+        .stream_templates
+        .insert(name.clone(), template.clone());
+    put_metadata(&metadata).await?;
+
+    let mut updated = Vec::new();
+    if params.get("propagate").copied().unwrap_or(false) {
+        let storage = CONFIG.storage().get_object_store();
+        for stream_name in STREAM_INFO.list_streams() {
+            let stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+            if stream_metadata.template.as_deref() == Some(name.as_str()) {
+                logstream::apply_template(&stream_name, &name, &template).await?;
+                updated.push(stream_name);
+            }
+        }
+    }
+
+    Ok((web::Json(updated), StatusCode::OK))
+}
+
+// Handler for GET /api/v1/template/{name}
+pub async fn get(name: web::Path<String>) -> Result<impl Responder, TemplateError> {
+    let name = name.into_inner();
+    let metadata = get_metadata().await?;
+    let template = metadata
+        .stream_templates
+        .get(&name)
+        .cloned()
+        .ok_or(TemplateError::NotFound(name))?;
+    Ok(web::Json(template))
+}
+
+// Handler for GET /api/v1/template
+pub async fn list() -> Result<impl Responder, TemplateError> {
+    let metadata = get_metadata().await?;
+    let templates: Vec<String> = metadata.stream_templates.keys().cloned().collect();
+    Ok(web::Json(templates))
+}
+
+// Handler for DELETE /api/v1/template/{name}
+// Streams created from the template keep their settings
+pub async fn delete(name: web::Path<String>) -> Result<impl Responder, TemplateError> {
+    let name = name.into_inner();
+    let mut metadata = get_metadata().await?;
+    if metadata.stream_templates.remove(&name).is_none() {
+        return Err(TemplateError::NotFound(name));
+    }
+    put_metadata(&metadata).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn get_metadata() -> Result<StorageMetadata, ObjectStorageError> {
+    let metadata = CONFIG
+        .storage()
+        .get_object_store()
+        .get_metadata()
+        .await?
+        .expect("metadata is initialized");
+    Ok(metadata)
+}
+
+async fn put_metadata(metadata: &StorageMetadata) -> Result<(), ObjectStorageError> {
+    storage::put_remote_metadata(metadata).await?;
+    storage::put_staging_metadata(metadata)?;
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TemplateError {
+    #[error("Failed to connect to storage: {0}")]
+    ObjectStorageError(#[from] ObjectStorageError),
+    #[error("Stream template {0} does not exist")]
+    NotFound(String),
+    #[error("{0}")]
+    Stream(#[from] StreamError),
+}
+
+impl actix_web::ResponseError for TemplateError {
+    fn status_code(&self) -> http::StatusCode {
+        match self {
+            Self::ObjectStorageError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Stream(err) => err.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
+        actix_web::HttpResponse::build(self.status_code())
+            .insert_header(ContentType::plaintext())
+            .body(self.to_string())
+    }
+}
@@ -0,0 +1,219 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::event::severity::{SeverityNumber, SEVERITY_NUMBER_KEY, SEVERITY_TEXT_KEY};
+
+// column holding the original line when it does not match the stream's pattern
+pub const RAW_LINE_KEY: &str = "line";
+// capture group which is mapped to severity columns instead of being stored as is
+const LEVEL_GROUP: &str = "level";
+
+// grok style aliases that can be used inside a pattern as %{NAME} or %{NAME:field}
+const GROK_PATTERNS: &[(&str, &str)] = &[
+    (
+        "TIMESTAMP_ISO8601",
+        r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}(?::\d{2}(?:[.,]\d+)?)?(?:Z|[+-]\d{2}:?\d{2})?",
+    ),
+    (
+        "LOGLEVEL",
+        r"(?i:trace|debug|info(?:rmation)?|notice|warn(?:ing)?|err(?:or)?|crit(?:ical)?|fatal|severe|emerg(?:ency)?|alert)",
+    ),
+    ("IP", r"(?:\d{1,3}\.){3}\d{1,3}"),
+    ("NUMBER", r"[+-]?\d+(?:\.\d+)?"),
+    ("INT", r"[+-]?\d+"),
+    ("WORD", r"\w+"),
+    ("NOTSPACE", r"\S+"),
+    ("SPACE", r"\s*"),
+    ("DATA", r".*?"),
+    ("GREEDYDATA", r".*"),
+];
+
+// patterns already compiled for ingest, keyed by the pattern as set on the stream
+static COMPILED_PATTERNS: Lazy<RwLock<HashMap<String, Regex>>> = Lazy::new(RwLock::default);
+
+// Compile a stream's log pattern. The pattern is a regular expression where
+// every named capture group becomes a column. Grok style aliases are expanded
+// before compiling, so both of these are equivalent
+// %{TIMESTAMP_ISO8601:timestamp} %{LOGLEVEL:level} %{GREEDYDATA:message}
+// (?P<timestamp>\d{4}-...) (?P<level>...) (?P<message>.*)
+pub fn compile_pattern(pattern: &str) -> Result<Regex, String> {
+    let expanded = expand_grok(pattern)?;
+    let regex = Regex::new(&expanded).map_err(|err| err.to_string())?;
+    if regex.capture_names().flatten().next().is_none() {
+        return Err("pattern must contain at least one named capture group".to_string());
+    }
+    Ok(regex)
+}
+
+// Compiled regex of a stream's log pattern, the pattern is only expanded and
+// compiled the first time it is seen
+pub fn cached_pattern(pattern: &str) -> Result<Regex, String> {
+    if let Some(regex) = COMPILED_PATTERNS
+        .read()
+        .expect("pattern cache lock is not poisoned")
+        .get(pattern)
+    {
+        return Ok(regex.clone());
+    }
+    let regex = compile_pattern(pattern)?;
+    COMPILED_PATTERNS
+        .write()
+        .expect("pattern cache lock is not poisoned")
+        .insert(pattern.to_string(), regex.clone());
+    Ok(regex)
+}
+
+fn expand_grok(pattern: &str) -> Result<String, String> {
+    let mut expanded = String::with_capacity(pattern.len());
+    let mut rest = pattern;
+    while let Some(start) = rest.find("%{") {
+        expanded.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            return Err("unterminated %{ in pattern".to_string());
+        };
+        let alias = &rest[start + 2..start + end];
+        let (name, field) = match alias.split_once(':') {
+            Some((name, field)) => (name, Some(field)),
+            None => (alias, None),
+        };
+        let Some((_, regex)) = GROK_PATTERNS.iter().find(|(key, _)| *key == name) else {
+            return Err(format!("unknown pattern %{{{name}}}"));
+        };
+        match field {
+            Some(field) => expanded.push_str(&format!("(?P<{field}>{regex})")),
+            None => expanded.push_str(&format!("(?:{regex})")),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+// Parse plaintext log lines into JSON records using the stream's pattern.
+// Lines which do not match are kept as is in the `line` column.
+// Returns the records along with the number of lines which did not match.
+pub fn flatten_text_logs(
+    body: &str,
+    pattern: Option<&Regex>,
+) -> (Vec<BTreeMap<String, Value>>, usize) {
+    let mut records = Vec::new();
+    let mut unmatched = 0;
+
+    for line in body.lines().filter(|line| !line.trim().is_empty()) {
+        let Some(captures) = pattern.and_then(|pattern| pattern.captures(line)) else {
+            if pattern.is_some() {
+                unmatched += 1;
+            }
+            records.push(BTreeMap::from([(
+                RAW_LINE_KEY.to_string(),
+                Value::String(line.to_string()),
+            )]));
+            continue;
+        };
+
+        let mut record = BTreeMap::new();
+        for name in pattern
+            .into_iter()
+            .flat_map(|x| x.capture_names())
+            .flatten()
+        {
+            let Some(value) = captures.name(name) else {
+                continue;
+            };
+            let value = value.as_str().to_string();
+            if name == LEVEL_GROUP {
+                let severity = SeverityNumber::from_level(&value);
+                record.insert(
+                    SEVERITY_NUMBER_KEY.to_string(),
+                    Value::from(severity as i32),
+                );
+                record.insert(SEVERITY_TEXT_KEY.to_string(), Value::String(value));
+            } else {
+                record.insert(name.to_string(), Value::String(value));
+            }
+        }
+        records.push(record);
+    }
+
+    (records, unmatched)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::{
+        cached_pattern, compile_pattern, flatten_text_logs, COMPILED_PATTERNS, RAW_LINE_KEY,
+    };
+
+    #[test]
+    fn grok_pattern_parses_lines() {
+        let pattern = compile_pattern(
+            "%{TIMESTAMP_ISO8601:timestamp} %{LOGLEVEL:level} %{GREEDYDATA:message}",
+        )
+        .unwrap();
+        let body = "2024-01-11T09:08:34Z WARN disk almost full\nnot a log line\n\n";
+
+        let (records, unmatched) = flatten_text_logs(body, Some(&pattern));
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(unmatched, 1);
+        assert_eq!(records[0]["timestamp"], "2024-01-11T09:08:34Z");
+        assert_eq!(records[0]["message"], "disk almost full");
+        assert_eq!(records[0]["severity_text"], "WARN");
+        assert_eq!(records[0]["severity_number"], Value::from(13));
+        assert_eq!(records[1][RAW_LINE_KEY], "not a log line");
+    }
+
+    #[test]
+    fn regex_pattern_without_named_group_is_err() {
+        assert!(compile_pattern(r"\d+ .*").is_err());
+        assert!(compile_pattern("%{UNKNOWN:field}").is_err());
+        assert!(compile_pattern(r"(?P<code>\d+) .*").is_ok());
+    }
+
+    #[test]
+    fn no_pattern_keeps_raw_lines() {
+        let (records, unmatched) = flatten_text_logs("a\nb", None);
+        assert_eq!(records.len(), 2);
+        assert_eq!(unmatched, 0);
+    }
+
+    #[test]
+    fn patterns_compiled_once() {
+        let pattern = "%{IP:client} %{WORD:method} cached";
+
+        let first = cached_pattern(pattern).unwrap();
+        let second = cached_pattern(pattern).unwrap();
+
+        assert_eq!(first.as_str(), second.as_str());
+        assert!(COMPILED_PATTERNS.read().unwrap().contains_key(pattern));
+        assert!(cached_pattern("%{UNKNOWN:field}").is_err());
+        assert!(!COMPILED_PATTERNS
+            .read()
+            .unwrap()
+            .contains_key("%{UNKNOWN:field}"));
+    }
+}
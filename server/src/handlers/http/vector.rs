@@ -0,0 +1,231 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use bytes::{Buf, Bytes};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use prost::Message;
+use serde_json::Number;
+
+// Subset of Vector's event model as defined in
+// https://github.com/vectordotdev/vector/blob/master/lib/vector-core/proto/event.proto
+// Only log events are decoded, metrics and traces are skipped over as raw bytes.
+
+#[derive(Clone, PartialEq, Message)]
+pub struct EventWrapper {
+    #[prost(oneof = "Event", tags = "1, 2, 3")]
+    pub event: Option<Event>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum Event {
+    #[prost(message, tag = "1")]
+    Log(Log),
+    #[prost(bytes, tag = "2")]
+    Metric(Vec<u8>),
+    #[prost(bytes, tag = "3")]
+    Trace(Vec<u8>),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Log {
+    // deprecated by vector in favour of value but still sent by older agents
+    #[prost(btree_map = "string, message", tag = "1")]
+    pub fields: BTreeMap<String, Value>,
+    #[prost(message, optional, tag = "2")]
+    pub value: Option<Value>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Value {
+    #[prost(oneof = "Kind", tags = "1, 2, 4, 5, 6, 7, 8, 9")]
+    pub kind: Option<Kind>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum Kind {
+    #[prost(bytes, tag = "1")]
+    RawBytes(Vec<u8>),
+    #[prost(message, tag = "2")]
+    Timestamp(Timestamp),
+    #[prost(int64, tag = "4")]
+    Integer(i64),
+    #[prost(double, tag = "5")]
+    Float(f64),
+    #[prost(bool, tag = "6")]
+    Boolean(bool),
+    #[prost(message, tag = "7")]
+    Map(ValueMap),
+    #[prost(message, tag = "8")]
+    Array(ValueArray),
+    #[prost(int32, tag = "9")]
+    Null(i32),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Timestamp {
+    #[prost(int64, tag = "1")]
+    pub seconds: i64,
+    #[prost(int32, tag = "2")]
+    pub nanos: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ValueMap {
+    #[prost(btree_map = "string, message", tag = "1")]
+    pub fields: BTreeMap<String, Value>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ValueArray {
+    #[prost(message, repeated, tag = "1")]
+    pub items: Vec<Value>,
+}
+
+impl Value {
+    fn into_json(self) -> serde_json::Value {
+        match self.kind {
+            Some(Kind::RawBytes(bytes)) => {
+                serde_json::Value::String(String::from_utf8_lossy(&bytes).into_owned())
+            }
+            Some(Kind::Timestamp(ts)) => {
+                NaiveDateTime::from_timestamp_opt(ts.seconds, ts.nanos.max(0) as u32)
+                    .map(|time| DateTime::<Utc>::from_naive_utc_and_offset(time, Utc).to_rfc3339())
+                    .map_or(serde_json::Value::Null, serde_json::Value::String)
+            }
+            Some(Kind::Integer(value)) => serde_json::Value::from(value),
+            Some(Kind::Float(value)) => {
+                Number::from_f64(value).map_or(serde_json::Value::Null, serde_json::Value::Number)
+            }
+            Some(Kind::Boolean(value)) => serde_json::Value::Bool(value),
+            Some(Kind::Map(map)) => serde_json::Value::Object(
+                map.fields
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into_json()))
+                    .collect(),
+            ),
+            Some(Kind::Array(array)) => {
+                serde_json::Value::Array(array.items.into_iter().map(Value::into_json).collect())
+            }
+            Some(Kind::Null(_)) | None => serde_json::Value::Null,
+        }
+    }
+}
+
+// Flatten Vector events into JSON records.
+// The body is a sequence of length delimited protobuf encoded `EventWrapper`s as
+// sent by Vector. Each log event becomes one record with its fields (`message`,
+// `timestamp`, `host` and any other field set in the pipeline) as top level keys.
+pub fn flatten_vector_events(
+    body: &Bytes,
+) -> Result<Vec<BTreeMap<String, serde_json::Value>>, prost::DecodeError> {
+    let mut buf = body.clone();
+    let mut records = Vec::new();
+
+    while buf.has_remaining() {
+        let event = EventWrapper::decode_length_delimited(&mut buf)?;
+        let Some(Event::Log(log)) = event.event else {
+            continue;
+        };
+
+        let fields = match log.value {
+            Some(value) => match value.into_json() {
+                serde_json::Value::Object(map) => map.into_iter().collect(),
+                value => BTreeMap::from([("message".to_string(), value)]),
+            },
+            None => log
+                .fields
+                .into_iter()
+                .map(|(key, value)| (key, value.into_json()))
+                .collect(),
+        };
+        records.push(fields);
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use bytes::{Bytes, BytesMut};
+    use prost::Message;
+    use serde_json::json;
+
+    use super::{
+        flatten_vector_events, Event, EventWrapper, Kind, Log, Timestamp, Value, ValueMap,
+    };
+
+    fn value(kind: Kind) -> Value {
+        Value { kind: Some(kind) }
+    }
+
+    #[test]
+    fn decode_log_events() {
+        let log = Log {
+            fields: BTreeMap::new(),
+            value: Some(value(Kind::Map(ValueMap {
+                fields: BTreeMap::from([
+                    (
+                        "message".to_string(),
+                        value(Kind::RawBytes(b"user logged in".to_vec())),
+                    ),
+                    (
+                        "timestamp".to_string(),
+                        value(Kind::Timestamp(Timestamp {
+                            seconds: 1704964113,
+                            nanos: 0,
+                        })),
+                    ),
+                    ("status".to_string(), value(Kind::Integer(200))),
+                ]),
+            }))),
+        };
+        let legacy = Log {
+            fields: BTreeMap::from([("message".to_string(), value(Kind::Boolean(true)))]),
+            value: None,
+        };
+
+        let mut buf = BytesMut::new();
+        for event in [
+            Event::Log(log),
+            Event::Metric(vec![1, 2, 3]),
+            Event::Log(legacy),
+        ] {
+            EventWrapper { event: Some(event) }
+                .encode_length_delimited(&mut buf)
+                .unwrap();
+        }
+
+        let records = flatten_vector_events(&buf.freeze()).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["message"], "user logged in");
+        assert_eq!(records[0]["timestamp"], "2024-01-11T09:08:33+00:00");
+        assert_eq!(records[0]["status"], json!(200));
+        assert_eq!(records[1]["message"], json!(true));
+    }
+
+    #[test]
+    fn truncated_body_is_err() {
+        let body = Bytes::from_static(&[0x0a, 0x05, 0x01]);
+        assert!(flatten_vector_events(&body).is_err());
+    }
+}
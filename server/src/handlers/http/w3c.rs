@@ -0,0 +1,228 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use super::text::RAW_LINE_KEY;
+
+// W3C Extended Log Format as described in https://www.w3.org/TR/WD-logfile.html
+// The `#Fields:` directive lists the field of every space delimited value of a line.
+const FIELDS_DIRECTIVE: &str = "#Fields:";
+pub const STATUS_KEY: &str = "status";
+const TIMESTAMP_KEY: &str = "timestamp";
+
+enum FieldType {
+    String,
+    Int,
+    Float,
+}
+
+// well known field identifiers, stored under a readable column name with their type
+const KNOWN_FIELDS: &[(&str, &str, FieldType)] = &[
+    ("sc-status", STATUS_KEY, FieldType::Int),
+    ("sc-substatus", "substatus", FieldType::Int),
+    ("sc-bytes", "bytes", FieldType::Int),
+    ("cs-bytes", "request_bytes", FieldType::Int),
+    ("cs-method", "method", FieldType::String),
+    ("cs-uri", "uri", FieldType::String),
+    ("cs-uri-stem", "uri", FieldType::String),
+    ("cs-uri-query", "query", FieldType::String),
+    ("cs-version", "protocol", FieldType::String),
+    ("cs-host", "host", FieldType::String),
+    ("cs(user-agent)", "user_agent", FieldType::String),
+    ("cs(referer)", "referer", FieldType::String),
+    ("c-ip", "client_ip", FieldType::String),
+    ("s-ip", "server_ip", FieldType::String),
+    ("s-port", "server_port", FieldType::Int),
+    ("cs-username", "username", FieldType::String),
+    ("time-taken", "response_time", FieldType::Float),
+];
+
+// Parse access log lines into JSON records.
+// Fields are taken from `#Fields:` directives in the body and, for lines before
+// the first directive, from `default_fields` (the fields of an X-P-W3C-Fields header).
+// Unknown fields are stored as strings under their identifier with punctuation
+// replaced by `_`, `-` stands for a missing value. The `date` and `time` fields
+// are combined into an RFC 3339 `timestamp` and `sc-status` is stored as
+// `status`, from which severity columns are derived. Lines with a different number of values than fields are kept as is.
+// Returns the records along with the number of lines which could not be parsed.
+pub fn flatten_w3c_logs(
+    body: &str,
+    default_fields: Option<&str>,
+) -> (Vec<BTreeMap<String, Value>>, usize) {
+    let mut fields: Option<Vec<String>> = default_fields.map(parse_fields);
+    let mut records = Vec::new();
+    let mut unmatched = 0;
+
+    for line in body.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(directive) = line.strip_prefix(FIELDS_DIRECTIVE) {
+            fields = Some(parse_fields(directive));
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let values = split_line(line);
+        let Some(fields) = fields
+            .as_ref()
+            .filter(|fields| fields.len() == values.len())
+        else {
+            unmatched += 1;
+            records.push(BTreeMap::from([(
+                RAW_LINE_KEY.to_string(),
+                Value::String(line.to_string()),
+            )]));
+            continue;
+        };
+
+        let mut record = BTreeMap::new();
+        let mut date = None;
+        let mut time = None;
+        for (field, value) in fields.iter().zip(values) {
+            if value == "-" {
+                continue;
+            }
+            match field.as_str() {
+                "date" => date = Some(value),
+                "time" => time = Some(value),
+                field => {
+                    let (key, value) = typed_value(field, value);
+                    record.insert(key, value);
+                }
+            }
+        }
+        if let (Some(date), Some(time)) = (date, time) {
+            record.insert(
+                TIMESTAMP_KEY.to_string(),
+                Value::String(format!("{date}T{time}Z")),
+            );
+        }
+        records.push(record);
+    }
+
+    (records, unmatched)
+}
+
+fn parse_fields(directive: &str) -> Vec<String> {
+    directive
+        .split_whitespace()
+        .map(str::to_ascii_lowercase)
+        .collect()
+}
+
+// split on spaces, keeping "quoted" and [bracketed] values together as in
+// `"GET / HTTP/1.1"` or `[10/Oct/2000:13:55:36 -0700]`
+fn split_line(line: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c == ' ' || c == '\t' {
+            chars.next();
+            continue;
+        }
+        let close = match c {
+            '"' => Some('"'),
+            '[' => Some(']'),
+            _ => None,
+        };
+        let mut value = String::new();
+        if let Some(close) = close {
+            chars.next();
+            for c in chars.by_ref() {
+                if c == close {
+                    break;
+                }
+                value.push(c);
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c == ' ' || c == '\t' {
+                    break;
+                }
+                value.push(c);
+                chars.next();
+            }
+        }
+        values.push(value);
+    }
+    values
+}
+
+fn typed_value(field: &str, value: String) -> (String, Value) {
+    let Some((_, key, field_type)) = KNOWN_FIELDS.iter().find(|(name, ..)| *name == field) else {
+        let key = field
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>()
+            .trim_matches('_')
+            .to_string();
+        return (key, Value::String(value));
+    };
+    let typed = match field_type {
+        FieldType::Int => value.parse::<i64>().ok().map(Value::from),
+        FieldType::Float => value.parse::<f64>().ok().map(Value::from),
+        FieldType::String => None,
+    };
+    (key.to_string(), typed.unwrap_or(Value::String(value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::flatten_w3c_logs;
+    use crate::handlers::http::text::RAW_LINE_KEY;
+
+    #[test]
+    fn fields_directive_parses_typed_columns() {
+        let body = "#Version: 1.0\n\
+            #Fields: date time c-ip cs-method cs-uri-stem sc-status sc-bytes time-taken cs(User-Agent)\n\
+            2024-01-11 09:08:34 10.0.0.1 GET /index.html 200 5120 0.012 \"curl/8.0 (linux)\"\n\
+            2024-01-11 09:08:35 10.0.0.2 POST /login 503 - 1.5 -\n\
+            truncated line\n";
+
+        let (records, unmatched) = flatten_w3c_logs(body, None);
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(unmatched, 1);
+        assert_eq!(records[0]["timestamp"], "2024-01-11T09:08:34Z");
+        assert_eq!(records[0]["method"], "GET");
+        assert_eq!(records[0]["uri"], "/index.html");
+        assert_eq!(records[0]["status"], Value::from(200));
+        assert_eq!(records[0]["bytes"], Value::from(5120));
+        assert_eq!(records[0]["response_time"], Value::from(0.012));
+        assert_eq!(records[0]["user_agent"], "curl/8.0 (linux)");
+        assert!(!records[1].contains_key("bytes"));
+        assert_eq!(records[2][RAW_LINE_KEY], "truncated line");
+    }
+
+    #[test]
+    fn default_fields_for_lines_without_directive() {
+        let body = "10.0.0.1 [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 404";
+        let (records, unmatched) =
+            flatten_w3c_logs(body, Some("c-ip x-time-local x-request sc-status"));
+
+        assert_eq!(unmatched, 0);
+        assert_eq!(records[0]["x_time_local"], "10/Oct/2000:13:55:36 -0700");
+        assert_eq!(records[0]["x_request"], "GET / HTTP/1.1");
+        assert_eq!(records[0]["status"], Value::from(404));
+    }
+}
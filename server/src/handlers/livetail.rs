@@ -17,6 +17,7 @@
  */
 
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 use arrow_array::RecordBatch;
 use arrow_flight::encode::FlightDataEncoderBuilder;
@@ -95,7 +96,7 @@ impl FlightService for FlightServiceImpl {
             .map_err(|err| Status::internal(err.to_string()))?;
         let stream = extract_stream(&ticket)?;
         log::info!("livetail requested for stream {}", stream);
-        match Users.authorize(key, rbac::role::Action::Query, Some(stream), None) {
+        match Users.authorize(key.clone(), rbac::role::Action::Query, Some(stream), None) {
             rbac::Response::Authorized => (),
             rbac::Response::UnAuthorized => {
                 return Err(Status::permission_denied(
@@ -106,10 +107,35 @@ impl FlightService for FlightServiceImpl {
                 return Err(Status::unauthenticated("reload required"))
             }
         }
+        // readers restricted to some rows of the stream only see those rows
+        let row_filters = Users
+            .stream_access(&key, stream)
+            .ok_or_else(|| {
+                Status::permission_denied("user is not authenticated to access this resource")
+            })?
+            .row_filters;
 
         let schema = STREAM_INFO
             .schema(stream)
             .map_err(|err| Status::failed_precondition(err.to_string()))?;
+        // records are adapted to the schema, so projecting the schema
+        // on the selected columns projects the records as well
+        let schema = match extract_select(&ticket)? {
+            Some(columns) => {
+                let indices = utils::arrow::projection(&schema, &columns).map_err(|missing| {
+                    Status::invalid_argument(format!(
+                        "selected columns {} are not in stream {stream}",
+                        missing.join(", ")
+                    ))
+                })?;
+                Arc::new(
+                    schema
+                        .project(&indices)
+                        .map_err(|err| Status::internal(err.to_string()))?,
+                )
+            }
+            None => schema,
+        };
 
         let rx = LIVETAIL.new_pipe(
             Alphanumeric.sample_string(&mut rand::thread_rng(), 32),
@@ -118,7 +144,13 @@ impl FlightService for FlightServiceImpl {
 
         let adapter_schema = schema.clone();
         let rx = rx.map(move |x| match x {
-            Message::Record(t) => Ok(utils::arrow::adapt_batch(&adapter_schema, &t)),
+            Message::Record(t) => {
+                let t = match &row_filters {
+                    Some(row_filters) => utils::arrow::filter_rows(&t, row_filters)?,
+                    None => t,
+                };
+                Ok(utils::arrow::adapt_batch(&adapter_schema, &t))
+            }
             Message::Skipped(_) => {
                 log::warn!("livetail channel capacity is full.");
                 Ok(RecordBatch::new_empty(adapter_schema.clone()))
@@ -232,6 +264,17 @@ fn extract_stream(body: &serde_json::Value) -> Result<&str, Status> {
         .ok_or(Status::invalid_argument("stream key value is invalid"))
 }
 
+// optional `select` key of the ticket listing the columns to stream
+fn extract_select(body: &serde_json::Value) -> Result<Option<Vec<String>>, Status> {
+    let Some(select) = body.get("select") else {
+        return Ok(None);
+    };
+    let columns: Vec<String> = serde_json::from_value(select.clone()).map_err(|_| {
+        Status::invalid_argument("select key value should be a list of column names")
+    })?;
+    Ok((!columns.is_empty()).then_some(columns))
+}
+
 fn extract_session_key(headers: &MetadataMap) -> Result<SessionKey, Status> {
     // Extract username and password from the request using basic auth extractor.
     let basic = extract_basic_auth(headers).map(|creds| SessionKey::BasicAuth {
@@ -16,6 +16,8 @@
  *
  */
 
+pub mod aggregate;
+
 use std::{
     collections::HashMap,
     sync::{Arc, RwLock, Weak},
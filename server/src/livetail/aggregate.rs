@@ -0,0 +1,225 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use arrow_array::{cast::AsArray, types::TimestampMillisecondType, Array, RecordBatch};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use datafusion::arrow::util::display::array_value_to_string;
+
+const NULL_GROUP: &str = "null";
+
+// Counts of a closed window, grouped by the value of the group by column.
+// `late` is the number of rows which arrived after the window they belong
+// to was closed and `skipped` the number of rows the aggregation could not
+// keep up with, both since the previous window was emitted.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowCounts {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub counts: BTreeMap<String, u64>,
+    pub late: u64,
+    pub skipped: u64,
+}
+
+// Tumbling window count over the events of the live tail.
+// Rows are assigned to windows by the timestamp column of the stream. A window
+// is closed once the watermark, the latest timestamp seen or the current time,
+// is past its end by `lateness`. Rows for a window which is already closed are
+// only counted as late.
+pub struct WindowAggregator {
+    window: i64,
+    lateness: i64,
+    timestamp_key: String,
+    group_by: Option<String>,
+    windows: BTreeMap<i64, BTreeMap<String, u64>>,
+    watermark: i64,
+    closed_until: i64,
+    late: u64,
+    skipped: u64,
+}
+
+impl WindowAggregator {
+    pub fn new(
+        window: Duration,
+        lateness: Duration,
+        timestamp_key: String,
+        group_by: Option<String>,
+    ) -> Self {
+        Self {
+            window: window.num_milliseconds().max(1),
+            lateness: lateness.num_milliseconds().max(0),
+            timestamp_key,
+            group_by,
+            windows: BTreeMap::new(),
+            watermark: i64::MIN,
+            closed_until: i64::MIN,
+            late: 0,
+            skipped: 0,
+        }
+    }
+
+    pub fn add(&mut self, rb: &RecordBatch) {
+        let Some(timestamps) = rb
+            .column_by_name(&self.timestamp_key)
+            .and_then(|column| column.as_primitive_opt::<TimestampMillisecondType>())
+        else {
+            return;
+        };
+        let groups = self
+            .group_by
+            .as_ref()
+            .and_then(|group_by| rb.column_by_name(group_by));
+
+        for row in 0..rb.num_rows() {
+            if timestamps.is_null(row) {
+                continue;
+            }
+            let timestamp = timestamps.value(row);
+            let start = timestamp - timestamp.rem_euclid(self.window);
+            if start < self.closed_until {
+                self.late += 1;
+                continue;
+            }
+            let group = match groups {
+                Some(groups) if !groups.is_null(row) => {
+                    array_value_to_string(groups, row).unwrap_or_else(|_| NULL_GROUP.to_string())
+                }
+                _ => NULL_GROUP.to_string(),
+            };
+            *self
+                .windows
+                .entry(start)
+                .or_default()
+                .entry(group)
+                .or_default() += 1;
+            self.watermark = self.watermark.max(timestamp);
+        }
+    }
+
+    pub fn skip(&mut self, rows: usize) {
+        self.skipped += rows as u64;
+    }
+
+    // move the watermark to the current time and return the windows closed by it
+    pub fn advance(&mut self, now: i64) -> Vec<WindowCounts> {
+        self.watermark = self.watermark.max(now);
+        let mut closed = Vec::new();
+        while let Some(entry) = self.windows.first_entry() {
+            let start = *entry.key();
+            let end = start + self.window;
+            if end + self.lateness > self.watermark {
+                break;
+            }
+            let counts = entry.remove();
+            self.closed_until = end;
+            closed.push(WindowCounts {
+                start: to_datetime(start),
+                end: to_datetime(end),
+                counts,
+                late: std::mem::take(&mut self.late),
+                skipped: std::mem::take(&mut self.skipped),
+            });
+        }
+        // windows without any rows close as time passes too
+        let idle_until = self.watermark - self.lateness;
+        if idle_until > self.closed_until {
+            self.closed_until = idle_until - idle_until.rem_euclid(self.window);
+        }
+        closed
+    }
+}
+
+fn to_datetime(millis: i64) -> DateTime<Utc> {
+    NaiveDateTime::from_timestamp_millis(millis)
+        .unwrap_or_default()
+        .and_utc()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{RecordBatch, StringArray, TimestampMillisecondArray};
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use chrono::Duration;
+
+    use super::WindowAggregator;
+
+    fn batch(rows: &[(i64, &str)]) -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+            Field::new("level", DataType::Utf8, true),
+        ]));
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(TimestampMillisecondArray::from_iter_values(
+                    rows.iter().map(|(time, _)| *time),
+                )),
+                Arc::new(StringArray::from_iter_values(
+                    rows.iter().map(|(_, level)| *level),
+                )),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn windows_close_after_lateness() {
+        let mut aggregator = WindowAggregator::new(
+            Duration::seconds(10),
+            Duration::seconds(5),
+            "p_timestamp".to_string(),
+            Some("level".to_string()),
+        );
+
+        aggregator.add(&batch(&[
+            (1_000, "INFO"),
+            (2_000, "ERROR"),
+            (9_000, "INFO"),
+        ]));
+        aggregator.add(&batch(&[(12_000, "INFO")]));
+        // within lateness of the first window
+        assert!(aggregator.advance(14_000).is_empty());
+        aggregator.add(&batch(&[(8_000, "WARN")]));
+
+        let closed = aggregator.advance(15_000);
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].start.timestamp(), 0);
+        assert_eq!(closed[0].end.timestamp(), 10);
+        assert_eq!(closed[0].counts["INFO"], 2);
+        assert_eq!(closed[0].counts["ERROR"], 1);
+        assert_eq!(closed[0].counts["WARN"], 1);
+
+        // too late for the closed window
+        aggregator.add(&batch(&[(3_000, "INFO")]));
+        aggregator.skip(4);
+        let closed = aggregator.advance(25_000);
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].start.timestamp(), 10);
+        assert_eq!(closed[0].counts["INFO"], 1);
+        assert_eq!(closed[0].late, 1);
+        assert_eq!(closed[0].skipped, 4);
+    }
+}
@@ -205,6 +205,20 @@ impl LocalCacheManager {
         Ok(())
     }
 
+    // drop a file from the cache of a stream, used when the file in storage changed
+    pub async fn remove(&self, stream: &str, key: &str) -> Result<(), CacheError> {
+        let lock = self.semaphore.lock().await;
+        let mut cache = self.get_cache(stream).await?;
+        if let Some(path) = cache.files.remove(&key.to_string()) {
+            let file_size = fs::metadata(&path).await.map_or(0, |meta| meta.len());
+            cache.current_size = cache.current_size.saturating_sub(file_size);
+            let _ = fs::remove_file(path).await;
+            self.put_cache(stream, &cache).await?;
+        }
+        drop(lock);
+        Ok(())
+    }
+
     pub async fn partition_on_cached<T>(
         &self,
         stream: &str,
@@ -30,6 +30,7 @@ mod alerts;
 mod analytics;
 mod banner;
 mod catalog;
+mod dedup;
 mod event;
 mod handlers;
 mod livetail;
@@ -40,8 +41,11 @@ mod migration;
 mod oidc;
 mod option;
 mod query;
+mod quota;
 mod rbac;
+mod rebuild;
 mod response;
+mod sampling;
 mod stats;
 mod storage;
 mod utils;
@@ -58,6 +62,8 @@ async fn main() -> anyhow::Result<()> {
     migration::run_metadata_migration(&CONFIG).await?;
     let metadata = storage::resolve_parseable_metadata().await?;
     CONFIG.validate_staging()?;
+    // fail at startup rather than on the first upload of an encrypted stream
+    once_cell::sync::Lazy::force(&storage::encryption::KEYRING);
     banner::print(&CONFIG, &metadata).await;
     rbac::map::init(&metadata);
     metadata.set_global();
@@ -90,6 +96,9 @@ async fn main() -> anyhow::Result<()> {
         analytics::init_analytics_scheduler();
     }
 
+    sampling::init_sample_scheduler();
+    dedup::init_repeat_scheduler();
+    alerts::init_gap_scheduler();
     tokio::spawn(handlers::livetail::server());
 
     let app = handlers::http::run_http(prometheus, CONFIG.parseable.openid.clone());
@@ -98,6 +107,7 @@ async fn main() -> anyhow::Result<()> {
         tokio::select! {
             e = &mut app => {
                 // actix server finished .. stop other threads and stop the server
+                dedup::flush_repeats().await;
                 remote_sync_inbox.send(()).unwrap_or(());
                 localsync_inbox.send(()).unwrap_or(());
                 localsync_handler.join().unwrap_or(());
@@ -133,7 +143,9 @@ fn object_store_sync() -> (JoinHandle<()>, oneshot::Receiver<()>, oneshot::Sende
                     // Extra time interval is added so that this schedular does not race with local sync.
                     .plus(5u32.seconds())
                     .run(|| async {
-                        if let Err(e) = CONFIG.storage().get_object_store().sync().await {
+                        let synced = CONFIG.storage().get_object_store().sync().await;
+                        storage::staging::set_uploads_failing(synced.is_err());
+                        if let Err(e) = synced {
                             log::warn!("failed to sync local data with object store. {:?}", e);
                         }
                     });
@@ -178,6 +190,11 @@ fn run_local_sync() -> (JoinHandle<()>, oneshot::Receiver<()>, oneshot::Sender<(
                 scheduler
                     .every((storage::LOCAL_SYNC_INTERVAL as u32).seconds())
                     .run(move || crate::event::STREAM_WRITERS.unset_all());
+                scheduler
+                    .every((storage::STAGING_AGE_INTERVAL as u32).seconds())
+                    .run(move || {
+                        crate::event::STREAM_WRITERS.refresh_staging_age(chrono::Utc::now())
+                    });
 
                 loop {
                     thread::sleep(Duration::from_millis(50));
@@ -18,13 +18,39 @@
 
 use arrow_array::RecordBatch;
 use arrow_schema::{Field, Fields, Schema};
+use chrono::{DateTime, Utc};
 use itertools::Itertools;
 use once_cell::sync::Lazy;
-use std::collections::HashMap;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, RwLock};
 
 use crate::alerts::Alerts;
-use crate::metrics::{EVENTS_INGESTED, EVENTS_INGESTED_SIZE};
+use crate::dedup::Dedup;
+use crate::event::attribute_filter::AttributeFilter;
+use crate::event::attributes::AttributeMap;
+use crate::event::body::BodyConfig;
+use crate::event::ip_mask::IpMask;
+use crate::event::keys::{KeyMapping, KeySanitization};
+use crate::event::numbers::NumberMode;
+use crate::event::raw::RawPayload;
+use crate::event::routing::{RoutedFrom, Routing};
+use crate::event::schema_lock::SchemaLock;
+use crate::event::severity::{SeverityMapping, UnknownSeverity};
+use crate::event::shadow::Shadow;
+use crate::event::skew::ClockSkew;
+use crate::event::type_conflict::TypeConflict;
+use crate::event::DEFAULT_TIMESTAMP_KEY;
+use crate::metrics::{
+    EVENTS_INGESTED, EVENTS_INGESTED_DATE, EVENTS_INGESTED_SIZE, EVENTS_INGESTED_SIZE_DATE,
+};
+use crate::query::casts::CastColumn;
+use crate::query::view::DefaultView;
+use crate::quota::{self, IngestQuota};
+use crate::rbac::ingest_key::IngestKey;
+use crate::sampling::Sampling;
+use crate::storage::compression::{StagingCompression, StreamCompression};
+use crate::storage::partition::{self, Partitioning};
 use crate::storage::{ObjectStorage, StorageDir};
 use crate::utils::arrow::MergedRecordReader;
 
@@ -43,6 +69,39 @@ pub struct LogStreamMetadata {
     pub schema: HashMap<String, Arc<Field>>,
     pub alerts: Alerts,
     pub cache_enabled: bool,
+    pub encrypted: bool,
+    pub labels: BTreeMap<String, String>,
+    pub log_pattern: Option<String>,
+    pub timestamp_key: Option<String>,
+    pub time_fields: Vec<String>,
+    pub column_order: Vec<String>,
+    pub casts: Vec<CastColumn>,
+    pub severity_mapping: Option<SeverityMapping>,
+    pub unknown_severity: UnknownSeverity,
+    pub body_config: Option<BodyConfig>,
+    pub shadow: Option<Shadow>,
+    pub routing: Option<Routing>,
+    pub routed_from: Option<RoutedFrom>,
+    pub schema_lock: Option<SchemaLock>,
+    pub quota: Option<IngestQuota>,
+    pub sampling: Option<Sampling>,
+    pub dedup: Option<Dedup>,
+    pub compression: Option<StreamCompression>,
+    pub staging_compression: Option<StagingCompression>,
+    // size in bytes of a parquet file above which a new file is started
+    pub max_file_size: Option<u64>,
+    pub attribute_map: Option<AttributeMap>,
+    pub attribute_filter: Option<AttributeFilter>,
+    pub ip_mask: Option<IpMask>,
+    pub raw_payload: Option<RawPayload>,
+    pub key_sanitization: Option<KeySanitization>,
+    pub key_mapping: KeyMapping,
+    pub clock_skew: Option<ClockSkew>,
+    pub default_view: Option<DefaultView>,
+    pub number_mode: NumberMode,
+    pub type_conflict: TypeConflict,
+    pub ingest_keys: Vec<IngestKey>,
+    pub partitioning: Option<Partitioning>,
 }
 
 // It is very unlikely that panic will occur when dealing with metadata.
@@ -72,6 +131,15 @@ impl StreamInfo {
         Ok(())
     }
 
+    pub fn check_ingest_gaps(&self, now: DateTime<Utc>) {
+        let map = self.read().expect(LOCK_EXPECT);
+        for (stream_name, meta) in map.iter() {
+            for alert in &meta.alerts.alerts {
+                alert.check_gap(stream_name, now)
+            }
+        }
+    }
+
     pub fn stream_exists(&self, stream_name: &str) -> bool {
         let map = self.read().expect(LOCK_EXPECT);
         map.contains_key(stream_name)
@@ -97,6 +165,678 @@ impl StreamInfo {
         Ok(())
     }
 
+    pub fn encrypted(&self, stream_name: &str) -> Result<bool, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.encrypted)
+    }
+
+    pub fn set_encrypted(&self, stream_name: &str, encrypted: bool) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.encrypted = encrypted;
+        Ok(())
+    }
+
+    pub fn labels(&self, stream_name: &str) -> Result<BTreeMap<String, String>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.labels.clone())
+    }
+
+    pub fn set_labels(
+        &self,
+        stream_name: &str,
+        labels: BTreeMap<String, String>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.labels = labels;
+        Ok(())
+    }
+
+    pub fn log_pattern(&self, stream_name: &str) -> Result<Option<String>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.log_pattern.clone())
+    }
+
+    pub fn set_log_pattern(
+        &self,
+        stream_name: &str,
+        pattern: Option<String>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.log_pattern = pattern;
+        Ok(())
+    }
+
+    /// name of the column holding the event timestamp for this stream
+    pub fn timestamp_key(&self, stream_name: &str) -> Result<String, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata
+                    .timestamp_key
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_TIMESTAMP_KEY.to_string())
+            })
+    }
+
+    pub fn set_timestamp_key(
+        &self,
+        stream_name: &str,
+        timestamp_key: String,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.timestamp_key = Some(timestamp_key);
+        Ok(())
+    }
+
+    pub fn time_fields(&self, stream_name: &str) -> Result<Vec<String>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.time_fields.clone())
+    }
+
+    pub fn set_time_fields(
+        &self,
+        stream_name: &str,
+        time_fields: Vec<String>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.time_fields = time_fields;
+        Ok(())
+    }
+
+    pub fn column_order(&self, stream_name: &str) -> Result<Vec<String>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.column_order.clone())
+    }
+
+    pub fn casts(&self, stream_name: &str) -> Result<Vec<CastColumn>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.casts.clone())
+    }
+
+    pub fn set_casts(
+        &self,
+        stream_name: &str,
+        casts: Vec<CastColumn>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.casts = casts;
+        Ok(())
+    }
+
+    pub fn set_column_order(
+        &self,
+        stream_name: &str,
+        column_order: Vec<String>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.column_order = column_order;
+        Ok(())
+    }
+
+    pub fn severity_mapping(
+        &self,
+        stream_name: &str,
+    ) -> Result<Option<SeverityMapping>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.severity_mapping.clone())
+    }
+
+    pub fn set_severity_mapping(
+        &self,
+        stream_name: &str,
+        mapping: Option<SeverityMapping>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.severity_mapping = mapping;
+        Ok(())
+    }
+
+    pub fn unknown_severity(&self, stream_name: &str) -> Result<UnknownSeverity, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.unknown_severity)
+    }
+
+    pub fn set_unknown_severity(
+        &self,
+        stream_name: &str,
+        unknown_severity: UnknownSeverity,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.unknown_severity = unknown_severity;
+        Ok(())
+    }
+
+    pub fn body_config(&self, stream_name: &str) -> Result<Option<BodyConfig>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.body_config.clone())
+    }
+
+    pub fn set_body_config(
+        &self,
+        stream_name: &str,
+        config: Option<BodyConfig>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.body_config = config;
+        Ok(())
+    }
+
+    pub fn shadow(&self, stream_name: &str) -> Result<Option<Shadow>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.shadow.clone())
+    }
+
+    pub fn set_shadow(
+        &self,
+        stream_name: &str,
+        shadow: Option<Shadow>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.shadow = shadow;
+        Ok(())
+    }
+
+    pub fn routing(&self, stream_name: &str) -> Result<Option<Routing>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.routing.clone())
+    }
+
+    pub fn set_routing(
+        &self,
+        stream_name: &str,
+        routing: Option<Routing>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.routing = routing;
+        Ok(())
+    }
+
+    pub fn routed_from(&self, stream_name: &str) -> Result<Option<RoutedFrom>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.routed_from.clone())
+    }
+
+    pub fn set_routed_from(
+        &self,
+        stream_name: &str,
+        routed_from: Option<RoutedFrom>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.routed_from = routed_from;
+        Ok(())
+    }
+
+    // number of streams created by the routing of a stream
+    pub fn routed_streams(&self, stream_name: &str) -> usize {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.values()
+            .filter(|metadata| {
+                metadata
+                    .routed_from
+                    .as_ref()
+                    .is_some_and(|routed_from| routed_from.stream == stream_name)
+            })
+            .count()
+    }
+
+    pub fn schema_lock(&self, stream_name: &str) -> Result<Option<SchemaLock>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.schema_lock.clone())
+    }
+
+    pub fn set_schema_lock(
+        &self,
+        stream_name: &str,
+        lock: Option<SchemaLock>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.schema_lock = lock;
+        Ok(())
+    }
+
+    pub fn quota(&self, stream_name: &str) -> Result<Option<IngestQuota>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.quota.clone())
+    }
+
+    pub fn set_quota(
+        &self,
+        stream_name: &str,
+        quota: Option<IngestQuota>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.quota = quota;
+        Ok(())
+    }
+
+    pub fn sampling(&self, stream_name: &str) -> Result<Option<Sampling>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.sampling.clone())
+    }
+
+    pub fn set_sampling(
+        &self,
+        stream_name: &str,
+        sampling: Option<Sampling>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.sampling = sampling;
+        Ok(())
+    }
+
+    pub fn dedup(&self, stream_name: &str) -> Result<Option<Dedup>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.dedup.clone())
+    }
+
+    pub fn set_dedup(&self, stream_name: &str, dedup: Option<Dedup>) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.dedup = dedup;
+        Ok(())
+    }
+
+    pub fn max_file_size(&self, stream_name: &str) -> Result<Option<u64>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.max_file_size)
+    }
+
+    pub fn set_max_file_size(
+        &self,
+        stream_name: &str,
+        max_file_size: Option<u64>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.max_file_size = max_file_size;
+        Ok(())
+    }
+
+    pub fn attribute_map(&self, stream_name: &str) -> Result<Option<AttributeMap>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.attribute_map.clone())
+    }
+
+    pub fn set_attribute_map(
+        &self,
+        stream_name: &str,
+        attribute_map: Option<AttributeMap>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.attribute_map = attribute_map;
+        Ok(())
+    }
+
+    pub fn attribute_filter(
+        &self,
+        stream_name: &str,
+    ) -> Result<Option<AttributeFilter>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.attribute_filter.clone())
+    }
+
+    pub fn set_attribute_filter(
+        &self,
+        stream_name: &str,
+        attribute_filter: Option<AttributeFilter>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.attribute_filter = attribute_filter;
+        Ok(())
+    }
+
+    pub fn ip_mask(&self, stream_name: &str) -> Result<Option<IpMask>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.ip_mask.clone())
+    }
+
+    pub fn set_ip_mask(
+        &self,
+        stream_name: &str,
+        ip_mask: Option<IpMask>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.ip_mask = ip_mask;
+        Ok(())
+    }
+
+    pub fn raw_payload(&self, stream_name: &str) -> Result<Option<RawPayload>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.raw_payload.clone())
+    }
+
+    pub fn set_raw_payload(
+        &self,
+        stream_name: &str,
+        raw_payload: Option<RawPayload>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.raw_payload = raw_payload;
+        Ok(())
+    }
+
+    pub fn key_sanitization(
+        &self,
+        stream_name: &str,
+    ) -> Result<Option<KeySanitization>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.key_sanitization.clone())
+    }
+
+    pub fn set_key_sanitization(
+        &self,
+        stream_name: &str,
+        key_sanitization: Option<KeySanitization>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.key_sanitization = key_sanitization;
+        Ok(())
+    }
+
+    pub fn default_view(&self, stream_name: &str) -> Result<Option<DefaultView>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.default_view.clone())
+    }
+
+    pub fn set_default_view(
+        &self,
+        stream_name: &str,
+        default_view: Option<DefaultView>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.default_view = default_view;
+        Ok(())
+    }
+
+    pub fn clock_skew(&self, stream_name: &str) -> Result<Option<ClockSkew>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.clock_skew.clone())
+    }
+
+    pub fn set_clock_skew(
+        &self,
+        stream_name: &str,
+        clock_skew: Option<ClockSkew>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.clock_skew = clock_skew;
+        Ok(())
+    }
+
+    pub fn key_mapping(&self, stream_name: &str) -> Result<KeyMapping, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.key_mapping.clone())
+    }
+
+    // Sanitize the keys of flattened events as per the stream configuration.
+    // The mapping is updated under the lock so that concurrent requests agree
+    // on the column of a new key, returns it if it changed.
+    pub fn sanitize_keys(
+        &self,
+        stream_name: &str,
+        json: &mut Value,
+    ) -> Result<Option<KeyMapping>, MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        let Some(config) = &stream.key_sanitization else {
+            return Ok(None);
+        };
+        let changed = config.apply(json, &mut stream.key_mapping, &stream.schema);
+        Ok(changed.then(|| stream.key_mapping.clone()))
+    }
+
+    pub fn number_mode(&self, stream_name: &str) -> Result<NumberMode, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.number_mode)
+    }
+
+    pub fn type_conflict(&self, stream_name: &str) -> Result<TypeConflict, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.type_conflict)
+    }
+
+    pub fn set_type_conflict(
+        &self,
+        stream_name: &str,
+        type_conflict: TypeConflict,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.type_conflict = type_conflict;
+        Ok(())
+    }
+
+    pub fn set_number_mode(
+        &self,
+        stream_name: &str,
+        number_mode: NumberMode,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.number_mode = number_mode;
+        Ok(())
+    }
+
+    pub fn compression(
+        &self,
+        stream_name: &str,
+    ) -> Result<Option<StreamCompression>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.compression)
+    }
+
+    pub fn set_compression(
+        &self,
+        stream_name: &str,
+        compression: Option<StreamCompression>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.compression = compression;
+        Ok(())
+    }
+
+    pub fn staging_compression(
+        &self,
+        stream_name: &str,
+    ) -> Result<Option<StagingCompression>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.staging_compression)
+    }
+
+    pub fn set_staging_compression(
+        &self,
+        stream_name: &str,
+        staging_compression: Option<StagingCompression>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.staging_compression = staging_compression;
+        Ok(())
+    }
+
+    pub fn partitioning(&self, stream_name: &str) -> Result<Option<Partitioning>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.partitioning.clone())
+    }
+
+    pub fn set_partitioning(
+        &self,
+        stream_name: &str,
+        partitioning: Option<Partitioning>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.partitioning = partitioning;
+        Ok(())
+    }
+
+    pub fn ingest_keys(&self, stream_name: &str) -> Result<Vec<IngestKey>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.ingest_keys.clone())
+    }
+
+    pub fn set_ingest_keys(
+        &self,
+        stream_name: &str,
+        ingest_keys: Vec<IngestKey>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.ingest_keys = ingest_keys;
+        Ok(())
+    }
+
     pub fn schema(&self, stream_name: &str) -> Result<Arc<Schema>, MetadataError> {
         let map = self.read().expect(LOCK_EXPECT);
         let schema = map
@@ -146,32 +886,77 @@ impl StreamInfo {
         // return error in case of an error from object storage itself.
 
         for stream in storage.list_streams().await? {
-            let alerts = storage.get_alerts(&stream.name).await?;
-            let schema = storage.get_schema(&stream.name).await?;
-            let meta = storage.get_stream_metadata(&stream.name).await?;
-
-            let schema = update_schema_from_staging(&stream.name, schema);
-            let schema = HashMap::from_iter(
-                schema
-                    .fields
-                    .iter()
-                    .map(|v| (v.name().to_owned(), v.clone())),
-            );
-
-            let metadata = LogStreamMetadata {
-                schema,
-                alerts,
-                cache_enabled: meta.cache_enabled,
-            };
-
-            let mut map = self.write().expect(LOCK_EXPECT);
-
-            map.insert(stream.name, metadata);
+            self.load_stream(storage, &stream.name).await?;
         }
 
         Ok(())
     }
 
+    // Load the metadata of a single stream from the object store, replacing what
+    // is held for it
+    pub async fn load_stream(
+        &self,
+        storage: &(impl ObjectStorage + ?Sized),
+        stream_name: &str,
+    ) -> Result<(), LoadError> {
+        let alerts = storage.get_alerts(stream_name).await?;
+        let schema = storage.get_schema(stream_name).await?;
+        let meta = storage.get_stream_metadata(stream_name).await?;
+        partition::load(stream_name, &meta.partition_values);
+
+        let schema = update_schema_from_staging(stream_name, schema);
+        let schema = HashMap::from_iter(
+            schema
+                .fields
+                .iter()
+                .map(|v| (v.name().to_owned(), v.clone())),
+        );
+
+        let metadata = LogStreamMetadata {
+            schema,
+            alerts,
+            cache_enabled: meta.cache_enabled,
+            encrypted: meta.encrypted,
+            labels: meta.labels,
+            log_pattern: meta.log_pattern,
+            timestamp_key: meta.timestamp_key,
+            time_fields: meta.time_fields,
+            column_order: meta.column_order,
+            casts: meta.casts,
+            severity_mapping: meta.severity_mapping,
+            unknown_severity: meta.unknown_severity,
+            body_config: meta.body_config,
+            shadow: meta.shadow,
+            routing: meta.routing,
+            routed_from: meta.routed_from,
+            schema_lock: meta.schema_lock,
+            quota: meta.quota,
+            sampling: meta.sampling,
+            dedup: meta.dedup,
+            compression: meta.compression,
+            staging_compression: meta.staging_compression,
+            max_file_size: meta.max_file_size,
+            attribute_map: meta.attribute_map,
+            attribute_filter: meta.attribute_filter,
+            ip_mask: meta.ip_mask,
+            raw_payload: meta.raw_payload,
+            key_sanitization: meta.key_sanitization,
+            key_mapping: meta.key_mapping,
+            clock_skew: meta.clock_skew,
+            default_view: meta.default_view,
+            number_mode: meta.number_mode,
+            type_conflict: meta.type_conflict,
+            ingest_keys: meta.ingest_keys,
+            partitioning: meta.partitioning,
+        };
+
+        let mut map = self.write().expect(LOCK_EXPECT);
+
+        map.insert(stream_name.to_string(), metadata);
+
+        Ok(())
+    }
+
     pub fn list_streams(&self) -> Vec<String> {
         self.read()
             .expect(LOCK_EXPECT)
@@ -193,6 +978,13 @@ impl StreamInfo {
         EVENTS_INGESTED_SIZE
             .with_label_values(&[stream_name, origin])
             .add(size as i64);
+        let date = quota::today();
+        EVENTS_INGESTED_DATE
+            .with_label_values(&[stream_name, origin, &date])
+            .inc_by(num_rows);
+        EVENTS_INGESTED_SIZE_DATE
+            .with_label_values(&[stream_name, origin, &date])
+            .inc_by(size);
         Ok(())
     }
 }
@@ -0,0 +1,250 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::{btree_map::Entry, BTreeMap, HashSet};
+use std::sync::Arc;
+
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::{Metric, MetricFamily, MetricType};
+
+// Labels left out of the scraped metrics to bound their cardinality, series
+// differing only in dropped labels are exposed as their sum, or as their
+// maximum for gauges such as ages which don't add up. Metrics keep all their
+// labels internally, only what is scraped is filtered. Gauges which neither
+// add up nor have a meaningful maximum, such as rates, are not wrapped and
+// keep all their labels.
+#[derive(Debug, Clone, Default)]
+pub struct LabelFilter {
+    dropped: Arc<HashSet<String>>,
+}
+
+impl LabelFilter {
+    pub fn new(dropped: &[String]) -> Self {
+        Self {
+            dropped: Arc::new(dropped.iter().cloned().collect()),
+        }
+    }
+
+    pub fn wrap(&self, collector: impl Collector + 'static) -> Box<dyn Collector> {
+        self.wrap_with(collector, Aggregate::Sum)
+    }
+
+    // series of gauges are combined into their maximum instead
+    pub fn wrap_max(&self, collector: impl Collector + 'static) -> Box<dyn Collector> {
+        self.wrap_with(collector, Aggregate::Max)
+    }
+
+    fn wrap_with(
+        &self,
+        collector: impl Collector + 'static,
+        aggregate: Aggregate,
+    ) -> Box<dyn Collector> {
+        if self.dropped.is_empty() {
+            return Box::new(collector);
+        }
+        Box::new(Filtered {
+            collector: Box::new(collector),
+            filter: self.clone(),
+            aggregate,
+        })
+    }
+
+    fn apply(&self, mut family: MetricFamily, aggregate: Aggregate) -> MetricFamily {
+        // quantiles of summaries can not be added up
+        if family.get_field_type() == MetricType::SUMMARY
+            || !family.get_metric().iter().any(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|label| self.dropped.contains(label.get_name()))
+            })
+        {
+            return family;
+        }
+
+        let mut series: BTreeMap<Vec<(String, String)>, Metric> = BTreeMap::new();
+        for mut metric in family.take_metric() {
+            let labels: Vec<_> = metric
+                .take_label()
+                .into_iter()
+                .filter(|label| !self.dropped.contains(label.get_name()))
+                .collect();
+            let key = labels
+                .iter()
+                .map(|label| (label.get_name().to_string(), label.get_value().to_string()))
+                .collect();
+            metric.set_label(labels.into());
+            match series.entry(key) {
+                Entry::Vacant(entry) => {
+                    entry.insert(metric);
+                }
+                Entry::Occupied(mut entry) => add(entry.get_mut(), &metric, aggregate),
+            }
+        }
+        family.set_metric(series.into_values().collect::<Vec<_>>().into());
+        family
+    }
+}
+
+// How series differing only in dropped labels are combined
+#[derive(Debug, Clone, Copy)]
+enum Aggregate {
+    Sum,
+    Max,
+}
+
+fn add(sum: &mut Metric, metric: &Metric, aggregate: Aggregate) {
+    if metric.has_counter() {
+        let value = sum.get_counter().get_value() + metric.get_counter().get_value();
+        sum.mut_counter().set_value(value);
+    }
+    if metric.has_gauge() {
+        let (left, right) = (sum.get_gauge().get_value(), metric.get_gauge().get_value());
+        let value = match aggregate {
+            Aggregate::Sum => left + right,
+            Aggregate::Max => left.max(right),
+        };
+        sum.mut_gauge().set_value(value);
+    }
+    if metric.has_untyped() {
+        let value = sum.get_untyped().get_value() + metric.get_untyped().get_value();
+        sum.mut_untyped().set_value(value);
+    }
+    if metric.has_histogram() {
+        let histogram = metric.get_histogram();
+        let sum = sum.mut_histogram();
+        sum.set_sample_count(sum.get_sample_count() + histogram.get_sample_count());
+        sum.set_sample_sum(sum.get_sample_sum() + histogram.get_sample_sum());
+        // series of a histogram share its buckets
+        for (bucket, other) in sum.mut_bucket().iter_mut().zip(histogram.get_bucket()) {
+            bucket
+                .set_cumulative_count(bucket.get_cumulative_count() + other.get_cumulative_count());
+        }
+    }
+}
+
+struct Filtered {
+    collector: Box<dyn Collector>,
+    filter: LabelFilter,
+    aggregate: Aggregate,
+}
+
+impl Collector for Filtered {
+    fn desc(&self) -> Vec<&Desc> {
+        self.collector.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.collector
+            .collect()
+            .into_iter()
+            .map(|family| self.filter.apply(family, self.aggregate))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::{
+        GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    };
+
+    use super::LabelFilter;
+
+    #[test]
+    fn dropped_labels_are_summed_up() {
+        let counter = IntCounterVec::new(
+            Opts::new("events_ingested_date", "Events"),
+            &["stream", "format", "date"],
+        )
+        .unwrap();
+        counter
+            .with_label_values(&["app", "json", "2024-01-01"])
+            .inc_by(2);
+        counter
+            .with_label_values(&["app", "json", "2024-01-02"])
+            .inc_by(3);
+        counter
+            .with_label_values(&["db", "json", "2024-01-02"])
+            .inc_by(1);
+        let histogram = HistogramVec::new(
+            HistogramOpts::new("flush_seconds", "Flush").buckets(vec![1.0, 10.0]),
+            &["date"],
+        )
+        .unwrap();
+        histogram.with_label_values(&["2024-01-01"]).observe(0.5);
+        histogram.with_label_values(&["2024-01-02"]).observe(5.0);
+
+        let filter = LabelFilter::new(&["date".to_string()]);
+        let registry = Registry::new();
+        registry.register(filter.wrap(counter.clone())).unwrap();
+        registry.register(filter.wrap(histogram)).unwrap();
+        let families = registry.gather();
+
+        let series: Vec<(String, f64)> = families[0]
+            .get_metric()
+            .iter()
+            .map(|metric| {
+                let labels: Vec<_> = metric.get_label().iter().map(|l| l.get_value()).collect();
+                (labels.join(","), metric.get_counter().get_value())
+            })
+            .collect();
+        assert_eq!(
+            series,
+            vec![("json,app".to_string(), 5.0), ("json,db".to_string(), 1.0)]
+        );
+        // the counter itself keeps counting per date
+        assert_eq!(
+            counter
+                .with_label_values(&["app", "json", "2024-01-01"])
+                .get(),
+            2
+        );
+
+        let histogram = families[1].get_metric()[0].get_histogram();
+        assert_eq!(families[1].get_metric().len(), 1);
+        assert_eq!(histogram.get_sample_count(), 2);
+        let buckets: Vec<u64> = histogram
+            .get_bucket()
+            .iter()
+            .map(|bucket| bucket.get_cumulative_count())
+            .collect();
+        assert_eq!(buckets, vec![1, 2]);
+    }
+
+    #[test]
+    fn gauges_combined_by_their_maximum() {
+        let age = IntGaugeVec::new(Opts::new("oldest_age", "Age"), &["stream"]).unwrap();
+        age.with_label_values(&["app"]).set(30);
+        age.with_label_values(&["db"]).set(90);
+        let rate = GaugeVec::new(Opts::new("retention_rate", "Rate"), &["stream"]).unwrap();
+        rate.with_label_values(&["app"]).set(0.5);
+        rate.with_label_values(&["db"]).set(0.25);
+
+        let filter = LabelFilter::new(&["stream".to_string()]);
+        let registry = Registry::new();
+        registry.register(filter.wrap_max(age)).unwrap();
+        // rates keep their labels
+        registry.register(Box::new(rate)).unwrap();
+        let families = registry.gather();
+
+        assert_eq!(families[0].get_metric().len(), 1);
+        assert_eq!(families[0].get_metric()[0].get_gauge().get_value(), 90.0);
+        assert_eq!(families[1].get_metric().len(), 2);
+    }
+}
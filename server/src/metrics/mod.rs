@@ -16,13 +16,18 @@
  *
  */
 
+pub mod filter;
 pub mod storage;
 
 use actix_web_prometheus::{PrometheusMetrics, PrometheusMetricsBuilder};
 use once_cell::sync::Lazy;
-use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry};
+use prometheus::{
+    GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+};
 
-use crate::{handlers::http::metrics_path, metadata::STREAM_INFO, option::CONFIG};
+use crate::{catalog, handlers::http::metrics_path, metadata::STREAM_INFO, option::CONFIG};
+
+use self::filter::LabelFilter;
 
 pub const METRICS_NAMESPACE: &str = env!("CARGO_PKG_NAME");
 
@@ -43,6 +48,46 @@ pub static EVENTS_INGESTED_SIZE: Lazy<IntGaugeVec> = Lazy::new(|| {
     .expect("metric can be created")
 });
 
+pub static EVENTS_INGESTED_DATE: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "events_ingested_date",
+            "Events ingested for a stream on a date",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream", "format", "date"],
+    )
+    .expect("metric can be created")
+});
+
+pub static EVENTS_INGESTED_SIZE_DATE: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "events_ingested_size_date",
+            "Events ingested size bytes for a stream on a date",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream", "format", "date"],
+    )
+    .expect("metric can be created")
+});
+
+pub static EVENTS_DELETED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new("events_deleted", "Events deleted").namespace(METRICS_NAMESPACE),
+        &["stream", "format"],
+    )
+    .expect("metric can be created")
+});
+
+pub static EVENTS_DELETED_SIZE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(
+        Opts::new("events_deleted_size", "Events deleted size bytes").namespace(METRICS_NAMESPACE),
+        &["stream", "format"],
+    )
+    .expect("metric can be created")
+});
+
 pub static STORAGE_SIZE: Lazy<IntGaugeVec> = Lazy::new(|| {
     IntGaugeVec::new(
         Opts::new("storage_size", "Storage size bytes").namespace(METRICS_NAMESPACE),
@@ -51,6 +96,19 @@ pub static STORAGE_SIZE: Lazy<IntGaugeVec> = Lazy::new(|| {
     .expect("metric can be created")
 });
 
+// A count growing without bound makes listing the stream slow on object stores
+pub static PARQUET_FILE_COUNT: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "parquet_file_count",
+            "Parquet files of a stream in object storage",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
 pub static STAGING_FILES: Lazy<IntGaugeVec> = Lazy::new(|| {
     IntGaugeVec::new(
         Opts::new("staging_files", "Active Staging files").namespace(METRICS_NAMESPACE),
@@ -67,6 +125,18 @@ pub static QUERY_EXECUTE_TIME: Lazy<HistogramVec> = Lazy::new(|| {
     .expect("metric can be created")
 });
 
+pub static QUERIES_IN_FLIGHT: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "queries_in_flight",
+            "Queries currently executing on a stream",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
 pub static QUERY_CACHE_HIT: Lazy<IntCounterVec> = Lazy::new(|| {
     IntCounterVec::new(
         Opts::new("QUERY_CACHE_HIT", "Full Cache hit").namespace(METRICS_NAMESPACE),
@@ -83,33 +153,419 @@ pub static ALERTS_STATES: Lazy<IntCounterVec> = Lazy::new(|| {
     .expect("metric can be created")
 });
 
-fn custom_metrics(registry: &Registry) {
+pub static UNMATCHED_LOG_LINES: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "unmatched_log_lines",
+            "Plaintext log lines not matching the stream pattern",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static CLOCK_SKEW_CORRECTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "clock_skew_corrections",
+            "OTLP log records whose time was corrected for clock skew, or not as the correction was too large",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream", "outcome"],
+    )
+    .expect("metric can be created")
+});
+
+pub static UNKNOWN_OTEL_TIMESTAMPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "unknown_otel_timestamps",
+            "OTLP log records with a time of 0 or before the epoch, replaced on ingestion",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static SUPPRESSED_REPEATS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "suppressed_repeats",
+            "Events repeating the previous event of the stream, stored as a count",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static MALFORMED_CSV_ROWS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "malformed_csv_rows",
+            "CSV rows skipped for broken quoting or not matching the header",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static MALFORMED_OTLP_FRAMES: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "malformed_otlp_frames",
+            "OTLP messages of ingest streams skipped for failing to decode or being too large",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static DROPPED_ATTRIBUTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "dropped_attributes",
+            "Attributes dropped from records with more than the maximum attributes per record",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static FILTERED_ATTRIBUTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "filtered_attributes",
+            "Attributes dropped from events by the allow and deny lists of the stream",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static INVALID_IP_VALUES: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "invalid_ip_values",
+            "Values of IP mask columns which are not IP addresses, stored unmasked",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static OVERSIZED_RAW_PAYLOADS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "oversized_raw_payloads",
+            "Events stored without their raw payload for being larger than the max size of the stream",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static UNKNOWN_SEVERITY_LEVELS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "unknown_severity_levels",
+            "Events with a log level not known to the stream severity mapping",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static UNKNOWN_SEVERITY_NUMBERS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "unknown_severity_numbers",
+            "OTLP log records with a severity number out of range, stored as configured for the stream",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static SHADOW_INGEST_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "shadow_ingest_errors",
+            "Requests that could not be ingested into the shadow stream of a stream",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static SAMPLING_RETENTION_RATE: Lazy<GaugeVec> = Lazy::new(|| {
+    GaugeVec::new(
+        Opts::new(
+            "sampling_retention_rate",
+            "Fraction of the events kept in the last sampling window of a stream in sampling mode",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static OLDEST_STAGING_RECORD_AGE_SECONDS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "oldest_staging_record_age_seconds",
+            "Age of the oldest record not yet flushed from staging",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static STAGING_SPILLED_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "staging_spilled_bytes",
+            "Bytes of staged records kept only on disk because staging memory was over the limit",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static OVERSIZED_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "oversized_requests",
+            "Ingest requests rejected because the body exceeded the maximum request size",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static QUERY_RESPONSE_BYTES_SAVED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "query_response_bytes_saved",
+            "Bytes saved by compressing query responses",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["encoding"],
+    )
+    .expect("metric can be created")
+});
+
+pub static INGEST_REQUEST_DURATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    HistogramVec::new(
+        HistogramOpts::new(
+            "ingest_request_duration_seconds",
+            "Time taken to parse, flatten and buffer an ingest request",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream", "source", "status"],
+    )
+    .expect("metric can be created")
+});
+
+pub static INGEST_REQUESTS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "ingest_requests_total",
+            "Ingest requests by response status",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream", "source", "status"],
+    )
+    .expect("metric can be created")
+});
+
+pub static SCHEMA_VERSIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "schema_versions",
+            "Distinct schemas a stream has had, see /logstream/{stream}/schema/diff for the changes",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+// Rising flush durations usually precede backpressure on ingestion, staging
+// files pile up while the previous minute is still being written
+pub static PARQUET_FLUSH_DURATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    HistogramVec::new(
+        HistogramOpts::new(
+            "parquet_flush_duration_seconds",
+            "Time taken to write the staging files of a minute to parquet",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static PARQUET_FLUSH_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "parquet_flush_failures",
+            "Writes of staging files to parquet that failed",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static PARQUET_FLUSH_BYTES: Lazy<HistogramVec> = Lazy::new(|| {
+    HistogramVec::new(
+        HistogramOpts::new(
+            "parquet_flush_bytes",
+            "Bytes of parquet written by a flush of staging files",
+        )
+        .namespace(METRICS_NAMESPACE)
+        // 64 KiB to 1 GiB
+        .buckets(prometheus::exponential_buckets(65536.0, 4.0, 8).expect("valid buckets")),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+fn custom_metrics(registry: &Registry, filter: &LabelFilter) {
+    registry
+        .register(filter.wrap(EVENTS_INGESTED.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(EVENTS_INGESTED_SIZE.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(EVENTS_INGESTED_DATE.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(EVENTS_INGESTED_SIZE_DATE.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(EVENTS_DELETED.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(EVENTS_DELETED_SIZE.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(STORAGE_SIZE.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(PARQUET_FILE_COUNT.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(STAGING_FILES.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(QUERY_EXECUTE_TIME.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(QUERIES_IN_FLIGHT.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(QUERY_CACHE_HIT.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(ALERTS_STATES.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(UNMATCHED_LOG_LINES.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(UNKNOWN_OTEL_TIMESTAMPS.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(CLOCK_SKEW_CORRECTIONS.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(SUPPRESSED_REPEATS.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(MALFORMED_CSV_ROWS.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(MALFORMED_OTLP_FRAMES.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(DROPPED_ATTRIBUTES.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(FILTERED_ATTRIBUTES.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(UNKNOWN_SEVERITY_LEVELS.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(UNKNOWN_SEVERITY_NUMBERS.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(SHADOW_INGEST_ERRORS.clone()))
+        .expect("metric can be registered");
+    // a rate of one stream says nothing about the others, so its stream label is kept
+    registry
+        .register(Box::new(SAMPLING_RETENTION_RATE.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap_max(OLDEST_STAGING_RECORD_AGE_SECONDS.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(STAGING_SPILLED_BYTES.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(OVERSIZED_REQUESTS.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(QUERY_RESPONSE_BYTES_SAVED.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(filter.wrap(INGEST_REQUEST_DURATION_SECONDS.clone()))
+        .expect("metric can be registered");
     registry
-        .register(Box::new(EVENTS_INGESTED.clone()))
+        .register(filter.wrap(INGEST_REQUESTS_TOTAL.clone()))
         .expect("metric can be registered");
     registry
-        .register(Box::new(EVENTS_INGESTED_SIZE.clone()))
+        .register(filter.wrap(SCHEMA_VERSIONS.clone()))
         .expect("metric can be registered");
     registry
-        .register(Box::new(STORAGE_SIZE.clone()))
+        .register(filter.wrap(INVALID_IP_VALUES.clone()))
         .expect("metric can be registered");
     registry
-        .register(Box::new(STAGING_FILES.clone()))
+        .register(filter.wrap(OVERSIZED_RAW_PAYLOADS.clone()))
         .expect("metric can be registered");
     registry
-        .register(Box::new(QUERY_EXECUTE_TIME.clone()))
+        .register(filter.wrap(PARQUET_FLUSH_DURATION_SECONDS.clone()))
         .expect("metric can be registered");
     registry
-        .register(Box::new(QUERY_CACHE_HIT.clone()))
+        .register(filter.wrap(PARQUET_FLUSH_FAILURES.clone()))
         .expect("metric can be registered");
     registry
-        .register(Box::new(ALERTS_STATES.clone()))
+        .register(filter.wrap(PARQUET_FLUSH_BYTES.clone()))
         .expect("metric can be registered");
 }
 
 pub fn build_metrics_handler() -> PrometheusMetrics {
     let registry = prometheus::Registry::new();
-    custom_metrics(&registry);
+    custom_metrics(
+        &registry,
+        &LabelFilter::new(&CONFIG.parseable.metrics_drop_labels),
+    );
 
     let prometheus = PrometheusMetricsBuilder::new(METRICS_NAMESPACE)
         .registry(registry)
@@ -135,12 +591,18 @@ fn prom_process_metrics(_metrics: &PrometheusMetrics) {}
 
 pub async fn load_from_stats_from_storage() {
     for stream_name in STREAM_INFO.list_streams() {
-        let stats = CONFIG
-            .storage()
-            .get_object_store()
+        let storage = CONFIG.storage().get_object_store();
+        let mut stats = storage
             .get_stats(&stream_name)
             .await
             .expect("stats are loaded properly");
+        // stats written before files were counted, count them once from the manifests
+        if stats.files == 0 && stats.storage > 0 {
+            match catalog::file_count(storage, &stream_name).await {
+                Ok(files) => stats.files = files,
+                Err(err) => log::warn!("failed to count files of stream {stream_name}: {err}"),
+            }
+        }
 
         EVENTS_INGESTED
             .with_label_values(&[&stream_name, "json"])
@@ -150,6 +612,22 @@ pub async fn load_from_stats_from_storage() {
             .set(stats.ingestion as i64);
         STORAGE_SIZE
             .with_label_values(&["data", &stream_name, "parquet"])
-            .set(stats.storage as i64)
+            .set(stats.storage as i64);
+        PARQUET_FILE_COUNT
+            .with_label_values(&[&stream_name])
+            .set(stats.files as i64);
+        if stats.deleted_events > 0 {
+            EVENTS_DELETED
+                .with_label_values(&[&stream_name, "json"])
+                .inc_by(stats.deleted_events);
+            EVENTS_DELETED_SIZE
+                .with_label_values(&[&stream_name, "json"])
+                .set(stats.deleted_ingestion as i64);
+        }
+        if stats.schema_versions > 0 {
+            SCHEMA_VERSIONS
+                .with_label_values(&[&stream_name])
+                .inc_by(stats.schema_versions);
+        }
     }
 }
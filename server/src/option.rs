@@ -24,6 +24,7 @@ use parquet::basic::{BrotliLevel, GzipLevel, ZstdLevel};
 use std::env;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use url::Url;
 
 use crate::oidc::{self, OpenidConfig};
@@ -229,6 +230,37 @@ pub struct Server {
 
     /// Parquet compression algorithm
     pub parquet_compression: Compression,
+
+    /// Accept events sent by Vector over its native protocol
+    pub vector_ingest: bool,
+
+    /// Key to derive the per tenant keys trace and span ids are hashed with for users not allowed to see raw ids
+    pub correlation_id_key: Option<String>,
+
+    /// Maximum number of attributes of a single record, the rest are dropped
+    pub max_record_attributes: usize,
+
+    /// Memory used by staged records above which new records are kept on disk only while uploads fail
+    pub staging_memory_limit: Option<usize>,
+
+    /// Keyfile with the keys used to encrypt parquet files of encrypted streams
+    pub encryption_keyfile: Option<PathBuf>,
+
+    /// Maximum size in bytes of the decompressed body of a single ingest request
+    pub max_request_size: usize,
+
+    /// Time after which a running query is cancelled
+    pub query_timeout: Option<Duration>,
+
+    /// Maximum size in bytes of the serialized result of a single query
+    pub query_max_result_size: usize,
+
+    /// Time stored for OTLP log records with an unknown time
+    pub otel_unknown_time: OtelUnknownTime,
+
+    /// Labels left out of the exposed metrics, series are summed up over them
+    /// or, for ages, combined into their maximum
+    pub metrics_drop_labels: Vec<String>,
 }
 
 impl FromArgMatches for Server {
@@ -240,6 +272,7 @@ impl FromArgMatches for Server {
 
     fn update_from_arg_matches(&mut self, m: &clap::ArgMatches) -> Result<(), clap::Error> {
         self.local_cache_path = m.get_one::<PathBuf>(Self::CACHE).cloned();
+        self.encryption_keyfile = m.get_one::<PathBuf>(Self::ENCRYPTION_KEYFILE).cloned();
         self.tls_cert_path = m.get_one::<PathBuf>(Self::TLS_CERT).cloned();
         self.tls_key_path = m.get_one::<PathBuf>(Self::TLS_KEY).cloned();
         self.domain_address = m.get_one::<Url>(Self::DOMAIN_URI).cloned();
@@ -297,6 +330,46 @@ impl FromArgMatches for Server {
             .get_one::<usize>(Self::ROW_GROUP_SIZE)
             .cloned()
             .expect("default for row_group size");
+        self.vector_ingest = m
+            .get_one::<bool>(Self::VECTOR_INGEST)
+            .cloned()
+            .expect("default for vector ingest");
+        self.correlation_id_key = m.get_one::<String>(Self::CORRELATION_ID_KEY).cloned();
+        self.max_record_attributes = m
+            .get_one::<usize>(Self::MAX_RECORD_ATTRIBUTES)
+            .cloned()
+            .expect("default for max record attributes");
+        self.staging_memory_limit = m
+            .get_one::<usize>(Self::STAGING_MEMORY_LIMIT)
+            .cloned()
+            .map(|mib| mib * 1024usize.pow(2));
+        self.max_request_size = m
+            .get_one::<usize>(Self::MAX_REQUEST_SIZE)
+            .cloned()
+            .expect("default for max request size")
+            * 1024usize.pow(2);
+        self.query_timeout = m
+            .get_one::<u64>(Self::QUERY_TIMEOUT)
+            .cloned()
+            .map(Duration::from_secs);
+        self.query_max_result_size = m
+            .get_one::<u64>(Self::QUERY_MAX_RESULT_SIZE)
+            .map(|mib| *mib as usize)
+            .expect("default for query max result size")
+            * 1024usize.pow(2);
+        self.otel_unknown_time = match m
+            .get_one::<String>(Self::OTEL_UNKNOWN_TIME)
+            .expect("default for otel unknown time")
+            .as_str()
+        {
+            "observed" => OtelUnknownTime::Observed,
+            "server" => OtelUnknownTime::Server,
+            _ => unreachable!(),
+        };
+        self.metrics_drop_labels = m
+            .get_many::<String>(Self::METRICS_DROP_LABELS)
+            .map(|labels| labels.cloned().collect())
+            .unwrap_or_default();
         self.parquet_compression = match m
             .get_one::<String>(Self::PARQUET_COMPRESSION_ALGO)
             .expect("default for compression algo")
@@ -360,6 +433,16 @@ impl Server {
     pub const QUERY_MEM_POOL_SIZE: &'static str = "query-mempool-size";
     pub const ROW_GROUP_SIZE: &'static str = "row-group-size";
     pub const PARQUET_COMPRESSION_ALGO: &'static str = "compression-algo";
+    pub const VECTOR_INGEST: &'static str = "vector-ingest";
+    pub const CORRELATION_ID_KEY: &'static str = "correlation-id-key";
+    pub const MAX_RECORD_ATTRIBUTES: &'static str = "max-record-attributes";
+    pub const STAGING_MEMORY_LIMIT: &'static str = "staging-memory-limit";
+    pub const ENCRYPTION_KEYFILE: &'static str = "encryption-keyfile";
+    pub const MAX_REQUEST_SIZE: &'static str = "max-request-size";
+    pub const QUERY_TIMEOUT: &'static str = "query-timeout";
+    pub const QUERY_MAX_RESULT_SIZE: &'static str = "query-max-result-size";
+    pub const OTEL_UNKNOWN_TIME: &'static str = "otel-unknown-time";
+    pub const METRICS_DROP_LABELS: &'static str = "metrics-drop-labels";
     pub const DEFAULT_USERNAME: &'static str = "admin";
     pub const DEFAULT_PASSWORD: &'static str = "admin";
 
@@ -573,6 +656,100 @@ impl Server {
                         "lz4",
                         "zstd"])
                     .help("Parquet compression algorithm"),
+            )
+            .arg(
+                Arg::new(Self::VECTOR_INGEST)
+                    .long(Self::VECTOR_INGEST)
+                    .env("P_VECTOR_INGEST")
+                    .value_name("BOOL")
+                    .required(false)
+                    .default_value("false")
+                    .value_parser(value_parser!(bool))
+                    .help("Enable/Disable ingestion over Vector's native protocol"),
+            )
+            .arg(
+                Arg::new(Self::CORRELATION_ID_KEY)
+                    .long(Self::CORRELATION_ID_KEY)
+                    .env("P_CORRELATION_ID_KEY")
+                    .value_name("STRING")
+                    .required(false)
+                    .help("Key to derive the per tenant keys trace and span ids are hashed with for users without access to raw ids, a tenant being the roles restricting the user"),
+            )
+            .arg(
+                Arg::new(Self::MAX_RECORD_ATTRIBUTES)
+                    .long(Self::MAX_RECORD_ATTRIBUTES)
+                    .env("P_MAX_RECORD_ATTRIBUTES")
+                    .value_name("NUMBER")
+                    .required(false)
+                    .default_value("1000")
+                    .value_parser(value_parser!(usize))
+                    .help("Maximum number of attributes of a single record after flattening, attributes beyond it are dropped"),
+            )
+            .arg(
+                Arg::new(Self::STAGING_MEMORY_LIMIT)
+                    .long(Self::STAGING_MEMORY_LIMIT)
+                    .env("P_STAGING_MEMORY_LIMIT")
+                    .value_name("MiB")
+                    .required(false)
+                    .value_parser(value_parser!(usize))
+                    .help("Memory used by staged records above which new records are only kept on the staging disk while uploads to the object store fail"),
+            )
+            .arg(
+                Arg::new(Self::ENCRYPTION_KEYFILE)
+                    .long(Self::ENCRYPTION_KEYFILE)
+                    .env("P_ENCRYPTION_KEYFILE")
+                    .value_name("FILE")
+                    .required(false)
+                    .value_parser(validation::file_path)
+                    .help("JSON file with the active key id and the hex encoded AES-256 keys used to encrypt parquet files of encrypted streams"),
+            )
+            .arg(
+                Arg::new(Self::MAX_REQUEST_SIZE)
+                    .long(Self::MAX_REQUEST_SIZE)
+                    .env("P_MAX_REQUEST_SIZE")
+                    .value_name("MiB")
+                    .required(false)
+                    .default_value("10")
+                    .value_parser(value_parser!(usize))
+                    .help("Maximum size of a single ingest request, for compressed requests the limit applies to the decompressed body"),
+            )
+            .arg(
+                Arg::new(Self::QUERY_TIMEOUT)
+                    .long(Self::QUERY_TIMEOUT)
+                    .env("P_QUERY_TIMEOUT")
+                    .value_name("SECONDS")
+                    .required(false)
+                    .value_parser(value_parser!(u64).range(1..))
+                    .help("Time after which a running query is cancelled, by default queries run until they finish"),
+            )
+            .arg(
+                Arg::new(Self::QUERY_MAX_RESULT_SIZE)
+                    .long(Self::QUERY_MAX_RESULT_SIZE)
+                    .env("P_QUERY_MAX_RESULT_SIZE")
+                    .value_name("MiB")
+                    .required(false)
+                    .default_value("256")
+                    .value_parser(value_parser!(u64).range(1..))
+                    .help("Maximum size of the result of a single query before compression, queries with larger results fail and requests can only lower the limit"),
+            )
+            .arg(
+                Arg::new(Self::OTEL_UNKNOWN_TIME)
+                    .long(Self::OTEL_UNKNOWN_TIME)
+                    .env("P_OTEL_UNKNOWN_TIME")
+                    .value_name("[OBSERVED, SERVER]")
+                    .required(false)
+                    .default_value("observed")
+                    .value_parser(["observed", "server"])
+                    .help("Time stored for OTLP log records with a time of 0 (unknown) or before the epoch, their observed time falling back to the server time or always the server time"),
+            )
+            .arg(
+                Arg::new(Self::METRICS_DROP_LABELS)
+                    .long(Self::METRICS_DROP_LABELS)
+                    .env("P_METRICS_DROP_LABELS")
+                    .value_name("LABEL,..")
+                    .required(false)
+                    .value_delimiter(',')
+                    .help("Labels left out of the metrics exposed for scraping, like date or severity, series differing only in them are exposed as their sum, or their maximum for ages. Rates keep all their labels"),
             ).group(
                 ArgGroup::new("oidc")
                     .args([Self::OPENID_CLIENT_ID, Self::OPENID_CLIENT_SECRET, Self::OPENID_ISSUER])
@@ -595,6 +772,15 @@ pub enum Compression {
     ZSTD,
 }
 
+// Time stored for OTLP log records whose time is unknown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OtelUnknownTime {
+    // observed time of the record, the server time if that is unknown too
+    #[default]
+    Observed,
+    Server,
+}
+
 impl From<Compression> for parquet::basic::Compression {
     fn from(value: Compression) -> Self {
         match value {
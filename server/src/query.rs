@@ -16,33 +16,78 @@
  *
  */
 
+pub mod analyze;
+pub mod buckets;
+#[cfg(feature = "query-builder")]
+#[allow(dead_code)]
+pub mod builder;
+pub mod casts;
 mod filter_optimizer;
+pub mod histogram;
+pub mod latest;
 mod listing_table_builder;
+pub mod lookup;
+pub mod map_get;
+pub mod params;
+pub mod profiler;
+pub mod range_schema;
+pub mod running;
+pub mod stats;
 mod stream_schema_provider;
+pub mod unnest;
+pub mod view;
 
 use chrono::{DateTime, Utc};
 use chrono::{NaiveDateTime, TimeZone};
+use datafusion::arrow::array::{Array, BooleanArray, StringArray};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::Schema;
+use datafusion::arrow::json::writer::record_batches_to_json_rows;
 use datafusion::arrow::record_batch::RecordBatch;
 
 use datafusion::common::tree_node::{Transformed, TreeNode, TreeNodeVisitor, VisitRecursion};
+use datafusion::common::{DFSchema, DFSchemaRef, ScalarValue, TableReference};
 use datafusion::error::DataFusionError;
 use datafusion::execution::context::SessionState;
 use datafusion::execution::disk_manager::DiskManagerConfig;
 use datafusion::execution::runtime_env::RuntimeEnv;
-use datafusion::logical_expr::{Explain, Filter, LogicalPlan, PlanType, ToStringifiedPlan};
+use datafusion::logical_expr::expr::{InList, ScalarUDF};
+use datafusion::logical_expr::{
+    BinaryExpr, Explain, Filter, LogicalPlan, Operator, PlanType, Projection, SubqueryAlias,
+    ToStringifiedPlan,
+};
+use datafusion::optimizer::simplify_expressions::{ExprSimplifier, SimplifyContext};
+use datafusion::optimizer::utils::split_conjunction;
+use datafusion::physical_expr::create_physical_expr;
+use datafusion::physical_expr::execution_props::ExecutionProps;
+use datafusion::physical_plan::{
+    collect, execute_stream, ExecutionPlan, SendableRecordBatchStream,
+};
 use datafusion::prelude::*;
+use futures_util::StreamExt;
 use itertools::Itertools;
 use once_cell::sync::Lazy;
+use prometheus::IntGauge;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use sysinfo::{System, SystemExt};
+use tokio::time::Instant;
 
 use crate::event;
+use crate::metadata::STREAM_INFO;
+use crate::metrics::QUERIES_IN_FLIGHT;
 use crate::option::CONFIG;
+use crate::rbac::role::RowFilter;
 use crate::storage::{ObjectStorageProvider, StorageDir};
+use crate::utils::correlation_id::{self, SPAN_ID_KEY, TRACE_ID_KEY};
 
+use self::analyze::QueryAnalysis;
 use self::error::ExecuteError;
+use self::lookup::LOOKUP_TABLES;
+use self::running::RunningQuery;
+use self::stats::QueryStats;
 
 use self::stream_schema_provider::GlobalSchemaProvider;
 pub use self::stream_schema_provider::PartialTimeFilter;
@@ -56,6 +101,29 @@ pub struct Query {
     pub start: DateTime<Utc>,
     pub end: DateTime<Utc>,
     pub filter_tag: Option<Vec<String>>,
+    // rows visible to the user per stream of the query
+    pub row_filters: HashMap<String, Vec<RowFilter>>,
+    // key trace and span ids are hashed with, None if the user may see raw ids
+    pub id_key: Option<String>,
+}
+
+/// Time by which the execution of a query should finish
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    pub timeout: Duration,
+    pub at: Instant,
+    // return the results produced until the deadline instead of failing
+    pub partial: bool,
+}
+
+impl Deadline {
+    pub fn new(timeout: Duration, partial: bool) -> Self {
+        Self {
+            timeout,
+            at: Instant::now() + timeout,
+            partial,
+        }
+    }
 }
 
 impl Query {
@@ -85,7 +153,8 @@ impl Query {
             .with_prefer_existing_sort(true)
             .with_round_robin_repartition(true);
 
-        let state = SessionState::new_with_config_rt(config, runtime);
+        let state = SessionState::new_with_config_rt(config, runtime)
+            .add_analyzer_rule(Arc::new(unnest::UnnestRule));
         let schema_provider = Arc::new(GlobalSchemaProvider {
             storage: storage.get_object_store(),
         });
@@ -99,13 +168,118 @@ impl Query {
             )
             .unwrap();
 
-        SessionContext::new_with_state(state)
+        let ctx = SessionContext::new_with_state(state);
+        ctx.register_udf(unnest::unnest_udf());
+        ctx.register_udf(map_get::map_get_udf());
+        ctx
     }
 
-    pub async fn execute(&self) -> Result<(Vec<RecordBatch>, Vec<String>), ExecuteError> {
+    /// execute the query and return the results, the output field names
+    /// and the number of bytes scanned from parquet files while executing
+    pub async fn execute(&self) -> Result<(Vec<RecordBatch>, Vec<String>, usize), ExecuteError> {
+        let (results, fields, plan, _) = self
+            .execute_plan(self.final_logical_plan(), None, None, None)
+            .await?;
+        Ok((results, fields, bytes_scanned(plan.as_ref())))
+    }
+
+    /// execute the query like `execute`, cancelling it once the deadline passes
+    /// or the running query is cancelled. With a partial deadline the batches
+    /// produced until then are returned along with true, otherwise the query
+    /// fails with a timeout. With a max size the query also fails as soon as
+    /// the rows produced serialize to more bytes than that
+    pub async fn execute_until(
+        &self,
+        deadline: Option<Deadline>,
+        running: Option<&RunningQuery>,
+        max_size: Option<usize>,
+    ) -> Result<(Vec<RecordBatch>, Vec<String>, usize, bool), ExecuteError> {
+        let (results, fields, plan, timed_out) = self
+            .execute_plan(self.final_logical_plan(), deadline, running, max_size)
+            .await?;
+        Ok((results, fields, bytes_scanned(plan.as_ref()), timed_out))
+    }
+
+    /// execute the query like `execute_until`, but return the batches as they
+    /// are produced instead of once the query finished. The query is counted
+    /// as running until the stream is dropped
+    pub async fn execute_stream(
+        &self,
+        deadline: Option<Deadline>,
+        running: Option<RunningQuery>,
+    ) -> Result<QueryStream, ExecuteError> {
+        let in_flight = self.table_name().as_deref().map(InFlight::new);
         let df = QUERY_SESSION
             .execute_logical_plan(self.final_logical_plan())
             .await?;
+        let fields = df
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name())
+            .cloned()
+            .collect_vec();
+        let task_ctx = Arc::new(df.task_ctx());
+        let plan = df.create_physical_plan().await?;
+        let stream = execute_stream(plan.clone(), task_ctx)?;
+        Ok(QueryStream {
+            fields,
+            stream,
+            plan,
+            deadline,
+            running,
+            projection: None,
+            rows: 0,
+            timed_out: false,
+            _in_flight: in_flight,
+        })
+    }
+
+    /// count the rows matching the query, regardless of its limit, and the
+    /// time range they span. Runs under the same deadline as the query but
+    /// fails instead of returning partial stats once it passes
+    pub async fn stats(
+        &self,
+        deadline: Option<Deadline>,
+        running: Option<&RunningQuery>,
+    ) -> Result<Option<QueryStats>, ExecuteError> {
+        let timestamp_key = self
+            .table_name()
+            .and_then(|table| STREAM_INFO.timestamp_key(&table).ok())
+            .unwrap_or_else(|| event::DEFAULT_TIMESTAMP_KEY.to_string());
+        let Some(plan) = stats::stats_plan(self.final_logical_plan(), &timestamp_key)? else {
+            return Ok(None);
+        };
+        let deadline = deadline.map(|deadline| Deadline {
+            partial: false,
+            ..deadline
+        });
+        let (results, _, _, _) = self.execute_plan(plan, deadline, running, None).await?;
+        Ok(Some(QueryStats::from_batches(&results)?))
+    }
+
+    /// execute the query like EXPLAIN ANALYZE, returning metrics of every operator
+    /// of the executed plan instead of the results
+    pub async fn analyze(&self) -> Result<(QueryAnalysis, usize), ExecuteError> {
+        let (results, _, plan, _) = self
+            .execute_plan(self.final_logical_plan(), None, None, None)
+            .await?;
+        let rows = results.iter().map(|rb| rb.num_rows()).sum();
+        Ok((
+            QueryAnalysis::new(plan.as_ref(), rows),
+            bytes_scanned(plan.as_ref()),
+        ))
+    }
+
+    async fn execute_plan(
+        &self,
+        plan: LogicalPlan,
+        deadline: Option<Deadline>,
+        running: Option<&RunningQuery>,
+        max_size: Option<usize>,
+    ) -> Result<(Vec<RecordBatch>, Vec<String>, Arc<dyn ExecutionPlan>, bool), ExecuteError> {
+        let _in_flight = self.table_name().as_deref().map(InFlight::new);
+        let df = QUERY_SESSION.execute_logical_plan(plan).await?;
 
         let fields = df
             .schema()
@@ -115,13 +289,38 @@ impl Query {
             .cloned()
             .collect_vec();
 
-        let results = df.collect().await?;
-        Ok((results, fields))
+        let task_ctx = Arc::new(df.task_ctx());
+        let plan = df.create_physical_plan().await?;
+        if deadline.is_none() && running.is_none() && max_size.is_none() {
+            let results = collect(plan.clone(), task_ctx).await?;
+            return Ok((results, fields, plan, false));
+        }
+
+        // dropping the stream cancels the rest of the execution
+        let mut stream = execute_stream(plan.clone(), task_ctx)?;
+        let mut results = Vec::new();
+        let mut size = max_size.map(ResultSize::new);
+        loop {
+            match next_batch(&mut stream, deadline, running).await? {
+                NextBatch::Batch(batch) => {
+                    if let Some(size) = &mut size {
+                        size.add(&batch)?;
+                    }
+                    results.push(batch)
+                }
+                NextBatch::Done => return Ok((results, fields, plan, false)),
+                NextBatch::TimedOut => return Ok((results, fields, plan, true)),
+            }
+        }
     }
 
     /// return logical plan with all time filters applied through
     fn final_logical_plan(&self) -> LogicalPlan {
-        let filters = self.filter_tag.clone().and_then(tag_filter);
+        let filters = ScanFilters {
+            tags: self.filter_tag.as_deref(),
+            row_filters: &self.row_filters,
+            id_key: self.id_key.as_deref(),
+        };
         // see https://github.com/apache/arrow-datafusion/pull/8400
         // this can be eliminated in later version of datafusion but with slight caveat
         // transform cannot modify stringified plans by itself
@@ -132,7 +331,7 @@ impl Query {
                     plan.plan.as_ref().clone(),
                     self.start.naive_utc(),
                     self.end.naive_utc(),
-                    filters,
+                    &filters,
                 );
                 LogicalPlan::Explain(Explain {
                     verbose: plan.verbose,
@@ -144,14 +343,302 @@ impl Query {
                     logical_optimization_succeeded: plan.logical_optimization_succeeded,
                 })
             }
-            x => transform(x, self.start.naive_utc(), self.end.naive_utc(), filters),
+            x => transform(x, self.start.naive_utc(), self.end.naive_utc(), &filters),
         }
     }
 
+    /// Name of the column of a row filter of `table` which the query compares to
+    /// a value not allowed by any of the row filters, or restricts by a predicate
+    /// which none of the allowed values satisfies. Such a query would silently
+    /// return no rows so it is rejected instead.
+    pub fn conflicting_row_filter(&self, table: &str, row_filters: &[RowFilter]) -> Option<String> {
+        let filtered = |column: &Column| {
+            column
+                .relation
+                .as_ref()
+                .map_or(true, |relation| relation.table() == table)
+                && row_filters
+                    .iter()
+                    .any(|filter| filter.column == column.name)
+        };
+        let allowed = |column: &Column, value: &ScalarValue| {
+            let value = match value {
+                ScalarValue::Utf8(Some(value)) | ScalarValue::LargeUtf8(Some(value)) => {
+                    value.clone()
+                }
+                value => value.to_string(),
+            };
+            !filtered(column)
+                || row_filters
+                    .iter()
+                    .any(|filter| filter.column == column.name && filter.value == value)
+        };
+
+        let mut conflict = None;
+        let _ = self.raw_logical_plan.apply(&mut |plan| {
+            if let LogicalPlan::Filter(filter) = plan {
+                for predicate in split_conjunction(&filter.predicate) {
+                    let columns = predicate.to_columns().unwrap_or_default();
+                    let Ok(column) = columns.into_iter().exactly_one() else {
+                        continue;
+                    };
+                    if !filtered(&column) {
+                        continue;
+                    }
+                    let values = row_filters
+                        .iter()
+                        .filter(|filter| filter.column == column.name)
+                        .map(|filter| filter.value.as_str())
+                        .collect_vec();
+                    if unsatisfiable(predicate, &column, filter.input.schema(), &values) {
+                        conflict = Some(column.name);
+                        return Ok(VisitRecursion::Stop);
+                    }
+                }
+            }
+            for expr in plan.expressions() {
+                expr.apply(&mut |expr| {
+                    let compared = match expr {
+                        Expr::BinaryExpr(BinaryExpr {
+                            left,
+                            op: Operator::Eq,
+                            right,
+                        }) => match (left.as_ref(), right.as_ref()) {
+                            (Expr::Column(column), Expr::Literal(value))
+                            | (Expr::Literal(value), Expr::Column(column)) => {
+                                vec![(column, value)]
+                            }
+                            _ => vec![],
+                        },
+                        Expr::InList(InList {
+                            expr,
+                            list,
+                            negated: false,
+                        }) => match expr.as_ref() {
+                            Expr::Column(column) => list
+                                .iter()
+                                .filter_map(|item| match item {
+                                    Expr::Literal(value) => Some((column, value)),
+                                    _ => None,
+                                })
+                                .collect(),
+                            _ => vec![],
+                        },
+                        _ => vec![],
+                    };
+                    if let Some((column, _)) = compared
+                        .into_iter()
+                        .find(|(column, value)| !allowed(column, value))
+                    {
+                        conflict = Some(column.name.clone());
+                        return Ok(VisitRecursion::Stop);
+                    }
+                    Ok(VisitRecursion::Continue)
+                })?;
+            }
+            Ok(if conflict.is_some() {
+                VisitRecursion::Stop
+            } else {
+                VisitRecursion::Continue
+            })
+        });
+        conflict
+    }
+
     pub fn table_name(&self) -> Option<String> {
+        self.table_names().into_iter().next()
+    }
+
+    // streams scanned by the query, lookup tables aside
+    pub fn table_names(&self) -> Vec<String> {
         let mut visitor = TableScanVisitor::default();
         let _ = self.raw_logical_plan.visit(&mut visitor);
-        visitor.into_inner().pop()
+        visitor.into_inner().into_iter().unique().collect()
+    }
+}
+
+/// Batches of a query returned as they are produced, see `Query::execute_stream`
+pub struct QueryStream {
+    pub fields: Vec<String>,
+    stream: SendableRecordBatchStream,
+    plan: Arc<dyn ExecutionPlan>,
+    deadline: Option<Deadline>,
+    running: Option<RunningQuery>,
+    // indices of the columns every batch is projected on
+    projection: Option<Vec<usize>>,
+    rows: usize,
+    timed_out: bool,
+    _in_flight: Option<InFlight>,
+}
+
+impl QueryStream {
+    /// next batch of the query, None once it finished. With a partial deadline
+    /// the query also finishes once the deadline passes, see `timed_out`
+    pub async fn next(&mut self) -> Result<Option<RecordBatch>, ExecuteError> {
+        if self.timed_out {
+            return Ok(None);
+        }
+        let batch = match next_batch(&mut self.stream, self.deadline, self.running.as_ref()).await?
+        {
+            NextBatch::Batch(batch) => batch,
+            NextBatch::Done => return Ok(None),
+            NextBatch::TimedOut => {
+                self.timed_out = true;
+                return Ok(None);
+            }
+        };
+        self.rows += batch.num_rows();
+        match &self.projection {
+            Some(indices) => Ok(Some(batch.project(indices).map_err(DataFusionError::from)?)),
+            None => Ok(Some(batch)),
+        }
+    }
+
+    /// project every batch on the columns at the indices, in their order
+    pub fn project(&mut self, indices: Vec<usize>) {
+        self.fields = indices
+            .iter()
+            .map(|&index| self.fields[index].clone())
+            .collect();
+        self.projection = Some(indices);
+    }
+
+    /// the timeout of the deadline if the query stopped at it
+    pub fn timed_out(&self) -> Option<Duration> {
+        self.deadline
+            .filter(|_| self.timed_out)
+            .map(|deadline| deadline.timeout)
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn bytes_scanned(&self) -> usize {
+        bytes_scanned(self.plan.as_ref())
+    }
+}
+
+#[cfg(test)]
+impl QueryStream {
+    // stream of the batches as if produced by a query
+    pub fn from_batches(batches: Vec<RecordBatch>) -> Self {
+        use datafusion::execution::TaskContext;
+        use datafusion::physical_plan::memory::MemoryExec;
+
+        let schema = batches[0].schema();
+        let fields = schema.fields().iter().map(|f| f.name().clone()).collect();
+        let plan: Arc<dyn ExecutionPlan> =
+            Arc::new(MemoryExec::try_new(&[batches], schema, None).unwrap());
+        let stream = execute_stream(plan.clone(), Arc::new(TaskContext::default())).unwrap();
+        QueryStream {
+            fields,
+            stream,
+            plan,
+            deadline: None,
+            running: None,
+            projection: None,
+            rows: 0,
+            timed_out: false,
+            _in_flight: None,
+        }
+    }
+}
+
+// Size of the rows of a result serialized to JSON, counted as the batches are
+// produced so that a query is stopped once its result can't be sent anyway.
+// Fails with the limit once the rows are larger
+struct ResultSize {
+    bytes: usize,
+    limit: usize,
+}
+
+impl ResultSize {
+    fn new(limit: usize) -> Self {
+        Self { bytes: 0, limit }
+    }
+
+    fn add(&mut self, batch: &RecordBatch) -> Result<(), ExecuteError> {
+        let rows = record_batches_to_json_rows(&[batch]).map_err(DataFusionError::from)?;
+        for row in rows {
+            serde_json::to_writer(&mut *self, &row)
+                .map_err(|_| ExecuteError::ResultTooLarge(self.limit))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::io::Write for ResultSize {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.bytes += buf.len();
+        if self.bytes > self.limit {
+            return Err(std::io::ErrorKind::Other.into());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+enum NextBatch {
+    Batch(RecordBatch),
+    Done,
+    // the partial deadline passed
+    TimedOut,
+}
+
+// Wait for the next batch of the stream until the deadline passes or the
+// running query is cancelled
+async fn next_batch(
+    stream: &mut SendableRecordBatchStream,
+    deadline: Option<Deadline>,
+    running: Option<&RunningQuery>,
+) -> Result<NextBatch, ExecuteError> {
+    let timeout = async {
+        match deadline {
+            Some(deadline) => {
+                tokio::time::sleep_until(deadline.at).await;
+                deadline
+            }
+            None => std::future::pending().await,
+        }
+    };
+    let cancelled = async {
+        match running {
+            Some(running) => running.cancelled().await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        batch = stream.next() => match batch {
+            Some(batch) => Ok(NextBatch::Batch(batch?)),
+            None => Ok(NextBatch::Done),
+        },
+        deadline = timeout => match deadline.partial {
+            true => Ok(NextBatch::TimedOut),
+            false => Err(ExecuteError::Timeout(deadline.timeout)),
+        },
+        _ = cancelled => Err(ExecuteError::Cancelled),
+    }
+}
+
+// Counts a query as in flight on its stream until dropped, so that the gauge
+// also goes down when the query fails or the request is cancelled
+struct InFlight(IntGauge);
+
+impl InFlight {
+    fn new(stream: &str) -> Self {
+        let gauge = QUERIES_IN_FLIGHT.with_label_values(&[stream]);
+        gauge.inc();
+        Self(gauge)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.dec();
     }
 }
 
@@ -171,71 +658,179 @@ impl TreeNodeVisitor for TableScanVisitor {
 
     fn pre_visit(&mut self, node: &Self::N) -> Result<VisitRecursion, DataFusionError> {
         match node {
+            LogicalPlan::TableScan(table) if is_lookup_table(table.table_name.table()) => {
+                Ok(VisitRecursion::Continue)
+            }
             LogicalPlan::TableScan(table) => {
                 self.tables.push(table.table_name.table().to_string());
-                Ok(VisitRecursion::Stop)
+                Ok(VisitRecursion::Continue)
             }
             _ => Ok(VisitRecursion::Continue),
         }
     }
 }
 
-fn tag_filter(filters: Vec<String>) -> Option<Expr> {
-    filters
-        .iter()
-        .map(|literal| {
-            Expr::Column(Column::from_name(event::DEFAULT_TAGS_KEY))
-                .like(lit(format!("%{}%", literal)))
-        })
-        .reduce(or)
+// lookup tables have no time column, tags or rows restricted by roles, so the
+// filters of streams are not applied to them
+fn is_lookup_table(name: &str) -> bool {
+    !STREAM_INFO.stream_exists(name) && LOOKUP_TABLES.contains(name)
+}
+
+// Whether none of the values allowed by the row filters satisfies a predicate
+// on their column alone. Predicates which can't be evaluated on the values of
+// the column are not considered conflicting.
+fn unsatisfiable(predicate: &Expr, column: &Column, schema: &DFSchemaRef, values: &[&str]) -> bool {
+    let Ok(field) = schema.field_from_column(column) else {
+        return false;
+    };
+    let Ok(df_schema) = DFSchema::new_with_metadata(vec![field.clone()], HashMap::new()) else {
+        return false;
+    };
+    let df_schema = Arc::new(df_schema);
+    let arrow_schema = Arc::new(Schema::new(vec![field.field().as_ref().clone()]));
+    let Ok(values) = cast(&StringArray::from(values.to_vec()), field.data_type()) else {
+        return false;
+    };
+    let Ok(batch) = RecordBatch::try_new(arrow_schema.clone(), vec![values]) else {
+        return false;
+    };
+
+    let props = ExecutionProps::new();
+    let simplifier =
+        ExprSimplifier::new(SimplifyContext::new(&props).with_schema(df_schema.clone()));
+    let result = simplifier
+        .coerce(predicate.clone(), df_schema.clone())
+        .and_then(|predicate| create_physical_expr(&predicate, &df_schema, &arrow_schema, &props))
+        .and_then(|predicate| predicate.evaluate(&batch));
+    match result.map(|result| result.into_array(batch.num_rows())) {
+        Ok(result) => result
+            .as_any()
+            .downcast_ref::<BooleanArray>()
+            .is_some_and(|result| result.true_count() == 0),
+        Err(_) => false,
+    }
+}
+
+// Filters added to every scan of a stream, restricting it to the tags and rows
+// visible to the user, and the key trace and span ids are masked with
+struct ScanFilters<'a> {
+    tags: Option<&'a [String]>,
+    row_filters: &'a HashMap<String, Vec<RowFilter>>,
+    id_key: Option<&'a str>,
+}
+
+impl ScanFilters<'_> {
+    // columns are qualified by the scanned table, a join of streams which
+    // share the column would be ambiguous otherwise
+    fn for_table(&self, table: &TableReference) -> Vec<Expr> {
+        let column = |name: &str| Expr::Column(Column::new(Some(table.to_owned_reference()), name));
+        let tags = self.tags.and_then(|tags| {
+            tags.iter()
+                .map(|tag| column(event::DEFAULT_TAGS_KEY).like(lit(format!("%{}%", tag))))
+                .reduce(or)
+        });
+        let rows = self.row_filters.get(table.table()).and_then(|filters| {
+            filters
+                .iter()
+                .map(|filter| column(&filter.column).eq(lit(filter.value.clone())))
+                .reduce(or)
+        });
+        tags.into_iter().chain(rows).collect()
+    }
+
+    // Replace the trace and span ids of the scan by their hash, so that every
+    // part of the query above it, filters and joins included, only ever sees
+    // the hashed ids. The projection is aliased by the table for the columns
+    // to keep their qualifier.
+    fn mask_ids(&self, scan: LogicalPlan, table: &TableReference) -> LogicalPlan {
+        let Some(key) = self.id_key else {
+            return scan;
+        };
+        let is_id = |name: &str| name == TRACE_ID_KEY || name == SPAN_ID_KEY;
+        let schema = scan.schema().clone();
+        if !schema.fields().iter().any(|field| is_id(field.name())) {
+            return scan;
+        }
+        let mask = Arc::new(correlation_id::mask_udf(key.to_string()));
+        let exprs = schema
+            .fields()
+            .iter()
+            .map(|field| {
+                let column = Expr::Column(field.qualified_column());
+                if is_id(field.name()) {
+                    Expr::ScalarUDF(ScalarUDF::new(mask.clone(), vec![column])).alias(field.name())
+                } else {
+                    column
+                }
+            })
+            .collect();
+        Projection::try_new(exprs, Arc::new(scan))
+            .and_then(|projection| {
+                SubqueryAlias::try_new(
+                    LogicalPlan::Projection(projection),
+                    table.to_owned_reference(),
+                )
+            })
+            .map(LogicalPlan::SubqueryAlias)
+            .expect("projection of the columns of the scan")
+    }
 }
 
 fn transform(
     plan: LogicalPlan,
     start_time: NaiveDateTime,
     end_time: NaiveDateTime,
-    filters: Option<Expr>,
+    filters: &ScanFilters,
 ) -> LogicalPlan {
     plan.transform(&|plan| match plan {
+        LogicalPlan::TableScan(table) if is_lookup_table(table.table_name.table()) => {
+            Ok(Transformed::No(LogicalPlan::TableScan(table)))
+        }
         LogicalPlan::TableScan(table) => {
             let mut new_filters = vec![];
-            if !table_contains_any_time_filters(&table) {
+            let timestamp_key = STREAM_INFO
+                .timestamp_key(table.table_name.table())
+                .unwrap_or_else(|_| event::DEFAULT_TIMESTAMP_KEY.to_string());
+            if !table_contains_any_time_filters(&table, &timestamp_key) {
                 let start_time_filter = PartialTimeFilter::Low(std::ops::Bound::Included(
                     start_time,
                 ))
                 .binary_expr(Expr::Column(Column::new(
                     Some(table.table_name.to_owned_reference()),
-                    event::DEFAULT_TIMESTAMP_KEY,
+                    &timestamp_key,
                 )));
                 let end_time_filter = PartialTimeFilter::High(std::ops::Bound::Excluded(end_time))
                     .binary_expr(Expr::Column(Column::new(
                         Some(table.table_name.to_owned_reference()),
-                        event::DEFAULT_TIMESTAMP_KEY,
+                        &timestamp_key,
                     )));
                 new_filters.push(start_time_filter);
                 new_filters.push(end_time_filter);
             }
 
-            if let Some(tag_filters) = filters.clone() {
-                new_filters.push(tag_filters)
-            }
+            new_filters.extend(filters.for_table(&table.table_name));
 
             let new_filter = new_filters.into_iter().reduce(and);
 
-            if let Some(new_filter) = new_filter {
+            let table_name = table.table_name.clone();
+            let plan = if let Some(new_filter) = new_filter {
                 let filter =
                     Filter::try_new(new_filter, Arc::new(LogicalPlan::TableScan(table))).unwrap();
-                Ok(Transformed::Yes(LogicalPlan::Filter(filter)))
+                LogicalPlan::Filter(filter)
             } else {
-                Ok(Transformed::No(LogicalPlan::TableScan(table)))
-            }
+                LogicalPlan::TableScan(table)
+            };
+            Ok(Transformed::Yes(filters.mask_ids(plan, &table_name)))
         }
         x => Ok(Transformed::No(x)),
     })
     .expect("transform only transforms the tablescan")
 }
 
-fn table_contains_any_time_filters(table: &datafusion::logical_expr::TableScan) -> bool {
+fn table_contains_any_time_filters(
+    table: &datafusion::logical_expr::TableScan,
+    timestamp_key: &str,
+) -> bool {
     table
         .filters
         .iter()
@@ -246,7 +841,7 @@ fn table_contains_any_time_filters(table: &datafusion::logical_expr::TableScan)
                 None
             }
         })
-        .any(|expr| matches!(&*expr.left, Expr::Column(Column { name, .. }) if (name == event::DEFAULT_TIMESTAMP_KEY)))
+        .any(|expr| matches!(&*expr.left, Expr::Column(Column { name, .. }) if (name == timestamp_key)))
 }
 
 #[allow(dead_code)]
@@ -290,6 +885,21 @@ fn time_from_path(path: &Path) -> DateTime<Utc> {
         .unwrap()
 }
 
+// sum of bytes read by all scans in an executed physical plan
+fn bytes_scanned(plan: &dyn ExecutionPlan) -> usize {
+    let scanned = plan
+        .metrics()
+        .and_then(|metrics| metrics.sum_by_name("bytes_scanned"))
+        .map_or(0, |value| value.as_usize());
+
+    scanned
+        + plan
+            .children()
+            .iter()
+            .map(|child| bytes_scanned(child.as_ref()))
+            .sum::<usize>()
+}
+
 pub mod error {
     use crate::storage::ObjectStorageError;
     use datafusion::error::DataFusionError;
@@ -300,18 +910,232 @@ pub mod error {
         ObjectStorage(#[from] ObjectStorageError),
         #[error("Query Execution failed due to error in datafusion: {0}")]
         Datafusion(#[from] DataFusionError),
+        #[error("Query did not finish within {0:?}")]
+        Timeout(std::time::Duration),
+        #[error("Query was cancelled")]
+        Cancelled,
+        #[error(
+            "Query result is larger than the limit of {0} bytes, select fewer rows or columns"
+        )]
+        ResultTooLarge(usize),
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::time_from_path;
+    use super::error::ExecuteError;
+    use super::{time_from_path, InFlight, Query, ResultSize};
+    use crate::metrics::QUERIES_IN_FLIGHT;
+    use crate::rbac::role::RowFilter;
+    use arrow_schema::{DataType, Field, Schema};
+    use chrono::Utc;
+    use datafusion::logical_expr::{col, lit, table_scan, JoinType};
+    use std::collections::HashMap;
     use std::path::PathBuf;
 
+    fn query(filter: datafusion::prelude::Expr) -> Query {
+        let schema = Schema::new(vec![
+            Field::new("tenant_id", DataType::Utf8, true),
+            Field::new("message", DataType::Utf8, true),
+        ]);
+        Query {
+            raw_logical_plan: table_scan(Some("app"), &schema, None)
+                .unwrap()
+                .filter(filter)
+                .unwrap()
+                .build()
+                .unwrap(),
+            start: Utc::now(),
+            end: Utc::now(),
+            filter_tag: None,
+            row_filters: HashMap::new(),
+            id_key: None,
+        }
+    }
+
+    #[test]
+    fn conflicting_row_filter() {
+        let row_filters = [RowFilter {
+            column: "tenant_id".to_string(),
+            value: "acme".to_string(),
+        }];
+
+        let allowed = query(
+            col("tenant_id")
+                .eq(lit("acme"))
+                .and(col("message").eq(lit("a"))),
+        );
+        assert_eq!(allowed.conflicting_row_filter("app", &row_filters), None);
+
+        let other = query(lit("globex").eq(col("tenant_id")));
+        assert_eq!(
+            other.conflicting_row_filter("app", &row_filters).as_deref(),
+            Some("tenant_id")
+        );
+
+        let in_list = query(col("tenant_id").in_list(vec![lit("acme"), lit("globex")], false));
+        assert_eq!(
+            in_list
+                .conflicting_row_filter("app", &row_filters)
+                .as_deref(),
+            Some("tenant_id")
+        );
+
+        // filters of another stream don't apply
+        assert_eq!(other.conflicting_row_filter("web", &row_filters), None);
+    }
+
+    #[test]
+    fn row_filter_conflicts_with_any_predicate() {
+        let row_filters = [RowFilter {
+            column: "tenant_id".to_string(),
+            value: "acme".to_string(),
+        }];
+        let conflict = |filter| query(filter).conflicting_row_filter("app", &row_filters);
+
+        assert_eq!(
+            conflict(col("tenant_id").not_eq(lit("acme"))).as_deref(),
+            Some("tenant_id")
+        );
+        assert_eq!(
+            conflict(col("tenant_id").in_list(vec![lit("acme")], true)).as_deref(),
+            Some("tenant_id")
+        );
+        assert_eq!(
+            conflict(col("tenant_id").like(lit("glo%"))).as_deref(),
+            Some("tenant_id")
+        );
+        assert_eq!(
+            conflict(col("tenant_id").gt(lit("b"))).as_deref(),
+            Some("tenant_id")
+        );
+
+        assert_eq!(conflict(col("tenant_id").like(lit("ac%"))), None);
+        assert_eq!(conflict(col("tenant_id").not_eq(lit("globex"))), None);
+        // a disjunction with other columns can still match allowed rows
+        assert_eq!(
+            conflict(
+                col("tenant_id")
+                    .gt(lit("b"))
+                    .or(col("message").eq(lit("a")))
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn scan_filters_qualified_by_table() {
+        let schema = Schema::new(vec![Field::new("tenant_id", DataType::Utf8, true)]);
+        let plan = table_scan(Some("app"), &schema, None)
+            .unwrap()
+            .join(
+                table_scan(Some("web"), &schema, None)
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+                JoinType::Inner,
+                (vec!["app.tenant_id"], vec!["web.tenant_id"]),
+                None,
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+        let query = Query {
+            raw_logical_plan: plan,
+            start: Utc::now(),
+            end: Utc::now(),
+            filter_tag: None,
+            row_filters: HashMap::from([(
+                "app".to_string(),
+                vec![RowFilter {
+                    column: "tenant_id".to_string(),
+                    value: "acme".to_string(),
+                }],
+            )]),
+            id_key: None,
+        };
+
+        let plan = format!("{:?}", query.final_logical_plan());
+        assert!(plan.contains("app.tenant_id = Utf8(\"acme\")"), "{plan}");
+        assert!(!plan.contains("web.tenant_id = Utf8"), "{plan}");
+    }
+
+    #[test]
+    fn ids_masked_at_the_scan() {
+        let schema = Schema::new(vec![
+            Field::new("trace_id", DataType::Utf8, true),
+            Field::new("message", DataType::Utf8, true),
+        ]);
+        let query = Query {
+            raw_logical_plan: table_scan(Some("app"), &schema, None)
+                .unwrap()
+                .filter(col("app.trace_id").eq(lit("eee19b7ec3c1b174")))
+                .unwrap()
+                .project(vec![col("app.message")])
+                .unwrap()
+                .build()
+                .unwrap(),
+            start: Utc::now(),
+            end: Utc::now(),
+            filter_tag: None,
+            row_filters: HashMap::new(),
+            id_key: Some("key".to_string()),
+        };
+
+        let plan = query.final_logical_plan();
+        let plan = format!("{:?}", plan);
+        // the filter applies to the hashed ids, the key never shows
+        let filter = plan.find("Filter: app.trace_id").unwrap();
+        let mask = plan.find("mask_id(app.trace_id) AS trace_id").unwrap();
+        assert!(filter < mask, "{plan}");
+        assert!(plan.contains("SubqueryAlias: app"), "{plan}");
+        assert!(!plan.contains("key"), "{plan}");
+    }
+
     #[test]
     fn test_time_from_parquet_path() {
         let path = PathBuf::from("date=2022-01-01.hour=00.minute=00.hostname.data.parquet");
         let time = time_from_path(path.as_path());
         assert_eq!(time.timestamp(), 1640995200);
     }
+
+    #[test]
+    fn in_flight_gauge_goes_down_on_drop() {
+        let gauge = QUERIES_IN_FLIGHT.with_label_values(&["in_flight_test"]);
+        let first = InFlight::new("in_flight_test");
+        let second = InFlight::new("in_flight_test");
+        assert_eq!(gauge.get(), 2);
+
+        drop(first);
+        drop(second);
+        assert_eq!(gauge.get(), 0);
+    }
+
+    #[test]
+    fn result_size_counted_per_batch() {
+        use datafusion::arrow::array::StringArray;
+        use datafusion::arrow::record_batch::RecordBatch;
+        use std::sync::Arc;
+
+        let schema = Arc::new(Schema::new(vec![Field::new("body", DataType::Utf8, true)]));
+        let batch = |body: &str| {
+            RecordBatch::try_new(
+                schema.clone(),
+                vec![Arc::new(StringArray::from(vec![body]))],
+            )
+            .unwrap()
+        };
+        // {"body":"ok"} is 13 bytes
+        let mut size = ResultSize::new(30);
+        size.add(&batch("ok")).unwrap();
+        size.add(&batch("ok")).unwrap();
+        assert!(matches!(
+            size.add(&batch("ok")),
+            Err(ExecuteError::ResultTooLarge(30))
+        ));
+        assert!(matches!(
+            ResultSize::new(30).add(&batch(&"x".repeat(1024))),
+            Err(ExecuteError::ResultTooLarge(30))
+        ));
+    }
 }
@@ -0,0 +1,139 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use datafusion::physical_plan::{display::DisplayableExecutionPlan, ExecutionPlan};
+
+// Result of running a query with EXPLAIN ANALYZE. Next to the text plan
+// annotated with metrics, as printed by datafusion, every operator of the
+// executed plan is listed with its metrics summed over all partitions, so
+// that clients can find the operator dominating a slow query.
+// Times are in nanoseconds.
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryAnalysis {
+    pub plan: String,
+    pub operators: OperatorMetrics,
+    pub rows: usize,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperatorMetrics {
+    pub operator: String,
+    pub detail: String,
+    pub metrics: BTreeMap<String, usize>,
+    pub children: Vec<OperatorMetrics>,
+}
+
+impl QueryAnalysis {
+    // plan has to be executed already for it to carry any metrics
+    pub fn new(plan: &dyn ExecutionPlan, rows: usize) -> Self {
+        Self {
+            plan: DisplayableExecutionPlan::with_metrics(plan)
+                .indent(true)
+                .to_string(),
+            operators: OperatorMetrics::new(plan),
+            rows,
+        }
+    }
+}
+
+impl OperatorMetrics {
+    fn new(plan: &dyn ExecutionPlan) -> Self {
+        let line = DisplayableExecutionPlan::new(plan).one_line().to_string();
+        let (operator, detail) = match line.trim().split_once(':') {
+            Some((operator, detail)) => (operator.to_string(), detail.trim().to_string()),
+            None => (line.trim().to_string(), String::new()),
+        };
+        let metrics = plan
+            .metrics()
+            .map(|metrics| {
+                metrics
+                    .aggregate_by_name()
+                    .timestamps_removed()
+                    .iter()
+                    .map(|metric| {
+                        let value = metric.value();
+                        (value.name().to_string(), value.as_usize())
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            operator,
+            detail,
+            metrics,
+            children: plan
+                .children()
+                .iter()
+                .map(|child| OperatorMetrics::new(child.as_ref()))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use datafusion::arrow::array::Int64Array;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::datasource::MemTable;
+    use datafusion::physical_plan::collect;
+    use datafusion::prelude::SessionContext;
+
+    use super::{OperatorMetrics, QueryAnalysis};
+
+    #[actix_web::test]
+    async fn operators_carry_metrics() {
+        let ctx = SessionContext::new();
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int64Array::from(vec![1, 2, 3, 4]))],
+        )
+        .unwrap();
+        let table = MemTable::try_new(schema, vec![vec![batch]]).unwrap();
+        ctx.register_table("app", Arc::new(table)).unwrap();
+
+        let df = ctx.sql("SELECT id FROM app WHERE id > 2").await.unwrap();
+        let task_ctx = Arc::new(df.task_ctx());
+        let plan = df.create_physical_plan().await.unwrap();
+        let results = collect(plan.clone(), task_ctx).await.unwrap();
+        let rows = results.iter().map(|rb| rb.num_rows()).sum();
+
+        let analysis = QueryAnalysis::new(plan.as_ref(), rows);
+        assert_eq!(analysis.rows, 2);
+        assert!(analysis.plan.contains("output_rows"));
+
+        fn find<'a>(operator: &'a OperatorMetrics, name: &str) -> Option<&'a OperatorMetrics> {
+            if operator.operator == name {
+                return Some(operator);
+            }
+            operator.children.iter().find_map(|child| find(child, name))
+        }
+        let filter = find(&analysis.operators, "FilterExec").unwrap();
+        assert_eq!(filter.detail, "id@0 > 2");
+        assert_eq!(filter.metrics["output_rows"], 2);
+        assert!(filter.metrics.contains_key("elapsed_compute"));
+    }
+}
@@ -0,0 +1,387 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+// Time bucketed aggregation for time series charts. With a bucket interval
+// such as `5m` the aggregate query
+//
+//   SELECT count(*) AS hits FROM app WHERE status = 500
+//
+// runs as
+//
+//   SELECT date_bin(INTERVAL '300000 milliseconds', "p_timestamp", TIMESTAMP '<start>') AS bucket,
+//     count(*) AS hits FROM app WHERE status = 500
+//   GROUP BY date_bin(..) ORDER BY bucket
+//
+// and buckets of the time range without rows are filled in, with zero for
+// counts and null for every other column. Buckets start at the start time of
+// the query.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::{DateTime, Duration, Utc};
+use datafusion::arrow::array::{
+    new_null_array, Array, ArrayRef, Int64Array, TimestampMillisecondArray,
+};
+use datafusion::arrow::compute::{cast, concat_batches, sort_to_indices, take};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::logical_expr::expr::{AggregateFunction, Alias};
+use datafusion::logical_expr::{aggregate_function, Expr, LogicalPlan};
+use datafusion::sql::parser::Statement;
+use datafusion::sql::sqlparser::ast::{
+    self, GroupByExpr, Ident, OrderByExpr, SelectItem, SetExpr, TableFactor,
+};
+use datafusion::sql::sqlparser::dialect::GenericDialect;
+use datafusion::sql::sqlparser::parser::Parser;
+
+pub const BUCKET_KEY: &str = "bucket";
+// more buckets than a chart can show are most likely a mistaken interval
+const MAX_BUCKETS: i64 = 10_000;
+
+#[derive(Debug, Clone)]
+pub struct TimeBuckets {
+    pub interval: Duration,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl TimeBuckets {
+    pub fn new(interval: &str, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Self, String> {
+        let interval = humantime::parse_duration(interval)
+            .map_err(|err| format!("invalid bucket interval {interval}, {err}"))?;
+        let interval = Duration::from_std(interval)
+            .ok()
+            .filter(|interval| interval.num_seconds() >= 1)
+            .ok_or_else(|| "bucket interval must be at least a second".to_string())?;
+        let buckets = Self {
+            interval,
+            start,
+            end,
+        };
+        if buckets.count() > MAX_BUCKETS {
+            return Err(format!(
+                "time range has more than {MAX_BUCKETS} buckets, use a larger interval"
+            ));
+        }
+        Ok(buckets)
+    }
+
+    fn count(&self) -> i64 {
+        let range = (self.end - self.start).num_milliseconds();
+        let interval = self.interval.num_milliseconds();
+        (range + interval - 1) / interval
+    }
+
+    /// Group the SELECT by the bucket of the timestamp column, adding the
+    /// bucket as the first column. Queries without an ORDER BY are ordered
+    /// by bucket.
+    pub fn rewrite(
+        &self,
+        statement: &mut Statement,
+        timestamp_key: impl Fn(&str) -> String,
+    ) -> Result<(), String> {
+        let not_select = || "time buckets need a SELECT query".to_string();
+        let Statement::Statement(statement) = statement else {
+            return Err(not_select());
+        };
+        let ast::Statement::Query(query) = statement.as_mut() else {
+            return Err(not_select());
+        };
+        let SetExpr::Select(select) = query.body.as_mut() else {
+            return Err(not_select());
+        };
+        let table = match select.from.first().map(|from| &from.relation) {
+            Some(TableFactor::Table { name, .. }) => name
+                .0
+                .last()
+                .map(|ident| ident.value.clone())
+                .unwrap_or_default(),
+            _ => return Err("time buckets need a query on a stream".to_string()),
+        };
+
+        let sql = format!(
+            "date_bin(INTERVAL '{} milliseconds', \"{}\", TIMESTAMP '{}')",
+            self.interval.num_milliseconds(),
+            timestamp_key(&table).replace('"', "\"\""),
+            self.start.naive_utc().format("%Y-%m-%dT%H:%M:%S%.3f"),
+        );
+        let bucket = Parser::new(&GenericDialect {})
+            .try_with_sql(&sql)
+            .and_then(|mut parser| parser.parse_expr())
+            .map_err(|err| err.to_string())?;
+
+        select.projection.insert(
+            0,
+            SelectItem::ExprWithAlias {
+                expr: bucket.clone(),
+                alias: Ident::new(BUCKET_KEY),
+            },
+        );
+        if let GroupByExpr::Expressions(group_by) = &mut select.group_by {
+            group_by.insert(0, bucket);
+        }
+        if query.order_by.is_empty() {
+            query.order_by.push(OrderByExpr {
+                expr: ast::Expr::Identifier(Ident::new(BUCKET_KEY)),
+                asc: Some(true),
+                nulls_first: None,
+            });
+        }
+        Ok(())
+    }
+
+    /// Add a row for every bucket of the time range missing from the result.
+    /// Results ordered by bucket stay so, otherwise the rows are appended.
+    pub fn fill_gaps(
+        &self,
+        records: Vec<RecordBatch>,
+        plan: &LogicalPlan,
+    ) -> Result<Vec<RecordBatch>, ArrowError> {
+        let schema: SchemaRef = match records.first() {
+            Some(batch) => batch.schema(),
+            None => Arc::new(plan.schema().as_ref().into()),
+        };
+        let Ok(bucket_index) = schema.index_of(BUCKET_KEY) else {
+            return Ok(records);
+        };
+        let zero = count_columns(plan);
+        // filled rows are null in the columns which are not counts
+        let schema = Arc::new(Schema::new(
+            schema
+                .fields()
+                .iter()
+                .map(|field| Field::new(field.name(), field.data_type().clone(), true))
+                .collect::<Vec<_>>(),
+        ));
+        let batch = concat_batches(&schema, &records)?;
+
+        let buckets = cast(
+            batch.column(bucket_index),
+            &DataType::Timestamp(TimeUnit::Millisecond, None),
+        )?;
+        let buckets = buckets
+            .as_any()
+            .downcast_ref::<TimestampMillisecondArray>()
+            .expect("cast to millisecond timestamps");
+        let present: HashSet<i64> = buckets.iter().flatten().collect();
+        let start = self.start.timestamp_millis();
+        let interval = self.interval.num_milliseconds();
+        let missing: Vec<i64> = (0..self.count())
+            .map(|index| start + index * interval)
+            .filter(|bucket| !present.contains(bucket))
+            .collect();
+        if missing.is_empty() {
+            return Ok(vec![batch]);
+        }
+
+        let columns = schema
+            .fields()
+            .iter()
+            .enumerate()
+            .map(|(index, field)| -> Result<ArrayRef, ArrowError> {
+                if index == bucket_index {
+                    let buckets: ArrayRef =
+                        Arc::new(TimestampMillisecondArray::from(missing.clone()));
+                    cast(&buckets, field.data_type())
+                } else if zero.contains(field.name()) {
+                    let zeros: ArrayRef = Arc::new(Int64Array::from(vec![0; missing.len()]));
+                    cast(&zeros, field.data_type())
+                } else {
+                    Ok(new_null_array(field.data_type(), missing.len()))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let filled = RecordBatch::try_new(schema.clone(), columns)?;
+        let batch = concat_batches(&schema, &[batch, filled])?;
+
+        if !ordered_by_bucket(plan) {
+            return Ok(vec![batch]);
+        }
+        let indices = sort_to_indices(batch.column(bucket_index), None, None)?;
+        let columns = batch
+            .columns()
+            .iter()
+            .map(|column| take(column, &indices, None))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(vec![RecordBatch::try_new(schema, columns)?])
+    }
+}
+
+// Output columns of the plan computed by a COUNT aggregate
+fn count_columns(plan: &LogicalPlan) -> HashSet<String> {
+    match plan {
+        LogicalPlan::Projection(projection) => {
+            let counts = count_columns(&projection.input);
+            projection
+                .expr
+                .iter()
+                .filter_map(|expr| match expr {
+                    Expr::Alias(Alias { expr, name }) => match expr.as_ref() {
+                        Expr::Column(column) if counts.contains(&column.name) => Some(name.clone()),
+                        _ => None,
+                    },
+                    Expr::Column(column) if counts.contains(&column.name) => {
+                        Some(column.name.clone())
+                    }
+                    _ => None,
+                })
+                .collect()
+        }
+        LogicalPlan::Sort(sort) => count_columns(&sort.input),
+        LogicalPlan::Limit(limit) => count_columns(&limit.input),
+        LogicalPlan::Aggregate(aggregate) => aggregate
+            .aggr_expr
+            .iter()
+            .filter(|expr| {
+                matches!(
+                    expr,
+                    Expr::AggregateFunction(AggregateFunction {
+                        fun: aggregate_function::AggregateFunction::Count,
+                        ..
+                    })
+                )
+            })
+            .filter_map(|expr| expr.display_name().ok())
+            .collect(),
+        _ => HashSet::new(),
+    }
+}
+
+fn ordered_by_bucket(plan: &LogicalPlan) -> bool {
+    let mut plan = plan;
+    loop {
+        plan = match plan {
+            LogicalPlan::Sort(sort) => return sort.expr.first().is_some_and(|expr| match expr {
+                Expr::Sort(sort) => {
+                    matches!(sort.expr.as_ref(), Expr::Column(column) if column.name == BUCKET_KEY)
+                }
+                _ => false,
+            }),
+            LogicalPlan::Projection(projection) => &projection.input,
+            LogicalPlan::Limit(limit) => &limit.input,
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{TimeZone, Utc};
+    use datafusion::arrow::array::{Int64Array, StringArray, TimestampMillisecondArray};
+    use datafusion::arrow::compute::cast;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::datasource::MemTable;
+    use datafusion::prelude::SessionContext;
+
+    use super::TimeBuckets;
+
+    #[actix_web::test]
+    async fn empty_buckets_are_filled() {
+        let ctx = SessionContext::new();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+            Field::new("host", DataType::Utf8, true),
+        ]));
+        // rows at 00:00:30, 00:01:10 and 00:03:00
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(TimestampMillisecondArray::from(vec![
+                    1704067230000,
+                    1704067270000,
+                    1704067380000,
+                ])),
+                Arc::new(StringArray::from(vec!["a", "b", "a"])),
+            ],
+        )
+        .unwrap();
+        let table = MemTable::try_new(schema, vec![vec![batch]]).unwrap();
+        ctx.register_table("app", Arc::new(table)).unwrap();
+
+        let buckets = TimeBuckets::new(
+            "1m",
+            Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+            Utc.with_ymd_and_hms(2024, 1, 1, 0, 4, 0).unwrap(),
+        )
+        .unwrap();
+        let state = ctx.state();
+        let mut statement = state
+            .sql_to_statement(
+                "SELECT count(*) AS hits, max(host) AS host FROM app",
+                "generic",
+            )
+            .unwrap();
+        buckets
+            .rewrite(&mut statement, |_| "p_timestamp".to_string())
+            .unwrap();
+        let plan = state.statement_to_plan(statement).await.unwrap();
+        let records = ctx
+            .execute_logical_plan(plan.clone())
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let records = buckets.fill_gaps(records, &plan).unwrap();
+
+        assert_eq!(records.len(), 1);
+        let batch = &records[0];
+        let bucket = cast(
+            batch.column_by_name("bucket").unwrap(),
+            &DataType::Timestamp(TimeUnit::Millisecond, None),
+        )
+        .unwrap();
+        let bucket = bucket
+            .as_any()
+            .downcast_ref::<TimestampMillisecondArray>()
+            .map(|array| array.values().to_vec());
+        let start = 1704067200000;
+        assert_eq!(
+            bucket,
+            Some(vec![start, start + 60000, start + 120000, start + 180000])
+        );
+        assert_eq!(
+            batch
+                .column_by_name("hits")
+                .unwrap()
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap(),
+            &Int64Array::from(vec![1, 1, 0, 1])
+        );
+        assert_eq!(batch.column_by_name("host").unwrap().null_count(), 1);
+    }
+
+    #[test]
+    fn bucket_interval_is_checked() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap();
+        assert!(TimeBuckets::new("1h", start, end).is_ok());
+        assert!(TimeBuckets::new("1s", start, end).is_err());
+        assert!(TimeBuckets::new("100ms", start, end).is_err());
+        assert!(TimeBuckets::new("often", start, end).is_err());
+    }
+}
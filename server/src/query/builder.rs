@@ -0,0 +1,455 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+// Builder of queries checked against the schema of a stream, for code
+// constructing queries instead of writing SQL. Columns are checked to exist,
+// filter values to fit the type of their column, aggregates of numbers to be
+// over numeric columns and selected columns of aggregate queries to be
+// grouped by. The built request is the body of the query endpoint.
+//
+//   let request = QueryBuilder::new("app", schema)
+//       .select("host")
+//       .count("errors")
+//       .filter("status", Op::GtEq, 500)
+//       .group_by("host")
+//       .time_range(start, end)
+//       .limit(10)
+//       .build()?;
+//
+//   SELECT "host", count(*) AS "errors" FROM "app" WHERE "status" >= 500
+//   GROUP BY "host" LIMIT 10
+
+use std::fmt::Write;
+use std::sync::Arc;
+
+use arrow_schema::{DataType, Schema};
+use chrono::{DateTime, SecondsFormat, Utc};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+}
+
+impl From<i64> for Literal {
+    fn from(value: i64) -> Self {
+        Literal::Int(value)
+    }
+}
+
+impl From<i32> for Literal {
+    fn from(value: i32) -> Self {
+        Literal::Int(value.into())
+    }
+}
+
+impl From<f64> for Literal {
+    fn from(value: f64) -> Self {
+        Literal::Float(value)
+    }
+}
+
+impl From<bool> for Literal {
+    fn from(value: bool) -> Self {
+        Literal::Bool(value)
+    }
+}
+
+impl From<&str> for Literal {
+    fn from(value: &str) -> Self {
+        Literal::Str(value.to_string())
+    }
+}
+
+impl From<String> for Literal {
+    fn from(value: String) -> Self {
+        Literal::Str(value)
+    }
+}
+
+impl Literal {
+    fn fits(&self, data_type: &DataType) -> bool {
+        match self {
+            Literal::Int(_) => data_type.is_numeric(),
+            Literal::Float(_) => data_type.is_floating(),
+            Literal::Bool(_) => data_type == &DataType::Boolean,
+            // timestamps are compared to RFC3339 strings
+            Literal::Str(_) => matches!(
+                data_type,
+                DataType::Utf8 | DataType::LargeUtf8 | DataType::Timestamp(_, _)
+            ),
+        }
+    }
+
+    fn to_sql(&self) -> String {
+        match self {
+            Literal::Int(value) => value.to_string(),
+            Literal::Float(value) => format!("{value:?}"),
+            Literal::Bool(value) => value.to_string(),
+            Literal::Str(value) => format!("'{}'", value.replace('\'', "''")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    /// substring match of string columns
+    Contains,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone)]
+enum Projection {
+    Column(String),
+    Aggregate {
+        fun: Aggregate,
+        column: Option<String>,
+        alias: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Filter {
+    column: String,
+    op: Op,
+    value: Literal,
+}
+
+/// Body of a request to the query endpoint
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryRequest {
+    pub query: String,
+    pub start_time: String,
+    pub end_time: String,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum BuilderError {
+    #[error("column {0} is not in the schema of the stream")]
+    UnknownColumn(String),
+    #[error("value {value} does not fit column {column} of type {data_type}")]
+    TypeMismatch {
+        column: String,
+        value: String,
+        data_type: String,
+    },
+    #[error("{0} is only applicable to numeric columns")]
+    NotNumeric(String),
+    #[error("contains is only applicable to string columns, {0} is not one")]
+    NotString(String),
+    #[error("column {0} is selected along with aggregates but not grouped by")]
+    NotGrouped(String),
+    #[error("time range is required and must start before it ends")]
+    InvalidTimeRange,
+    #[error("limit must be greater than zero")]
+    InvalidLimit,
+}
+
+#[derive(Debug, Clone)]
+pub struct QueryBuilder {
+    stream: String,
+    schema: Arc<Schema>,
+    projection: Vec<Projection>,
+    filters: Vec<Filter>,
+    group_by: Vec<String>,
+    time_range: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    limit: Option<usize>,
+}
+
+impl QueryBuilder {
+    pub fn new(stream: impl Into<String>, schema: Arc<Schema>) -> Self {
+        Self {
+            stream: stream.into(),
+            schema,
+            projection: Vec::new(),
+            filters: Vec::new(),
+            group_by: Vec::new(),
+            time_range: None,
+            limit: None,
+        }
+    }
+
+    pub fn select(mut self, column: impl Into<String>) -> Self {
+        self.projection.push(Projection::Column(column.into()));
+        self
+    }
+
+    /// count of the rows, returned as `alias`
+    pub fn count(mut self, alias: impl Into<String>) -> Self {
+        self.projection.push(Projection::Aggregate {
+            fun: Aggregate::Count,
+            column: None,
+            alias: alias.into(),
+        });
+        self
+    }
+
+    pub fn aggregate(
+        mut self,
+        fun: Aggregate,
+        column: impl Into<String>,
+        alias: impl Into<String>,
+    ) -> Self {
+        self.projection.push(Projection::Aggregate {
+            fun,
+            column: Some(column.into()),
+            alias: alias.into(),
+        });
+        self
+    }
+
+    pub fn filter(mut self, column: impl Into<String>, op: Op, value: impl Into<Literal>) -> Self {
+        self.filters.push(Filter {
+            column: column.into(),
+            op,
+            value: value.into(),
+        });
+        self
+    }
+
+    pub fn group_by(mut self, column: impl Into<String>) -> Self {
+        self.group_by.push(column.into());
+        self
+    }
+
+    pub fn time_range(mut self, start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        self.time_range = Some((start, end));
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn build(self) -> Result<QueryRequest, BuilderError> {
+        let (start, end) = self
+            .time_range
+            .filter(|(start, end)| start < end)
+            .ok_or(BuilderError::InvalidTimeRange)?;
+        if self.limit == Some(0) {
+            return Err(BuilderError::InvalidLimit);
+        }
+
+        let aggregated = !self.group_by.is_empty()
+            || self
+                .projection
+                .iter()
+                .any(|projection| matches!(projection, Projection::Aggregate { .. }));
+        let mut columns = Vec::with_capacity(self.projection.len());
+        for projection in &self.projection {
+            match projection {
+                Projection::Column(column) => {
+                    self.data_type(column)?;
+                    if aggregated && !self.group_by.contains(column) {
+                        return Err(BuilderError::NotGrouped(column.clone()));
+                    }
+                    columns.push(quote(column));
+                }
+                Projection::Aggregate { fun, column, alias } => {
+                    let argument = match column {
+                        Some(column) => {
+                            let data_type = self.data_type(column)?;
+                            if matches!(fun, Aggregate::Sum | Aggregate::Avg)
+                                && !data_type.is_numeric()
+                            {
+                                return Err(BuilderError::NotNumeric(format!(
+                                    "{fun:?} of {column}"
+                                )));
+                            }
+                            quote(column)
+                        }
+                        None => "*".to_string(),
+                    };
+                    let fun = format!("{fun:?}").to_lowercase();
+                    columns.push(format!("{fun}({argument}) AS {}", quote(alias)));
+                }
+            }
+        }
+        for column in &self.group_by {
+            self.data_type(column)?;
+        }
+
+        let mut sql = match columns.is_empty() {
+            true => "SELECT *".to_string(),
+            false => format!("SELECT {}", columns.join(", ")),
+        };
+        write!(sql, " FROM {}", quote(&self.stream)).expect("write to string");
+        let mut conditions = Vec::with_capacity(self.filters.len());
+        for filter in &self.filters {
+            conditions.push(self.condition(filter)?);
+        }
+        if !conditions.is_empty() {
+            write!(sql, " WHERE {}", conditions.join(" AND ")).expect("write to string");
+        }
+        if !self.group_by.is_empty() {
+            let group_by = self.group_by.iter().map(|column| quote(column));
+            write!(sql, " GROUP BY {}", group_by.collect::<Vec<_>>().join(", "))
+                .expect("write to string");
+        }
+        if let Some(limit) = self.limit {
+            write!(sql, " LIMIT {limit}").expect("write to string");
+        }
+
+        Ok(QueryRequest {
+            query: sql,
+            start_time: start.to_rfc3339_opts(SecondsFormat::Millis, true),
+            end_time: end.to_rfc3339_opts(SecondsFormat::Millis, true),
+        })
+    }
+
+    fn data_type(&self, column: &str) -> Result<&DataType, BuilderError> {
+        self.schema
+            .field_with_name(column)
+            .map(|field| field.data_type())
+            .map_err(|_| BuilderError::UnknownColumn(column.to_string()))
+    }
+
+    fn condition(&self, filter: &Filter) -> Result<String, BuilderError> {
+        let data_type = self.data_type(&filter.column)?;
+        let column = quote(&filter.column);
+        if filter.op == Op::Contains {
+            let Literal::Str(value) = &filter.value else {
+                return Err(BuilderError::NotString(filter.column.clone()));
+            };
+            if !matches!(data_type, DataType::Utf8 | DataType::LargeUtf8) {
+                return Err(BuilderError::NotString(filter.column.clone()));
+            }
+            // the value is matched literally, not as a pattern
+            let pattern = value
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            return Ok(format!(
+                "{column} LIKE {}",
+                Literal::Str(format!("%{pattern}%")).to_sql()
+            ));
+        }
+        if !filter.value.fits(data_type) {
+            return Err(BuilderError::TypeMismatch {
+                column: filter.column.clone(),
+                value: filter.value.to_sql(),
+                data_type: data_type.to_string(),
+            });
+        }
+        let op = match filter.op {
+            Op::Eq => "=",
+            Op::NotEq => "!=",
+            Op::Lt => "<",
+            Op::LtEq => "<=",
+            Op::Gt => ">",
+            Op::GtEq => ">=",
+            Op::Contains => unreachable!("handled above"),
+        };
+        Ok(format!("{column} {op} {}", filter.value.to_sql()))
+    }
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_schema::{DataType, Field, Schema};
+    use chrono::{TimeZone, Utc};
+
+    use super::{Aggregate, BuilderError, Op, QueryBuilder};
+
+    fn builder() -> QueryBuilder {
+        let schema = Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new("status", DataType::Int64, true),
+            Field::new("latency", DataType::Float64, true),
+        ]);
+        QueryBuilder::new("app", Arc::new(schema)).time_range(
+            Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+            Utc.with_ymd_and_hms(2024, 1, 1, 1, 0, 0).unwrap(),
+        )
+    }
+
+    #[test]
+    fn build_aggregate_query() {
+        let request = builder()
+            .select("host")
+            .count("errors")
+            .aggregate(Aggregate::Avg, "latency", "latency")
+            .filter("status", Op::GtEq, 500)
+            .filter("host", Op::Contains, "web_1")
+            .group_by("host")
+            .limit(10)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            request.query,
+            "SELECT \"host\", count(*) AS \"errors\", avg(\"latency\") AS \"latency\" FROM \"app\" \
+             WHERE \"status\" >= 500 AND \"host\" LIKE '%web\\_1%' GROUP BY \"host\" LIMIT 10"
+        );
+        assert_eq!(request.start_time, "2024-01-01T00:00:00.000Z");
+        assert_eq!(request.end_time, "2024-01-01T01:00:00.000Z");
+    }
+
+    #[test]
+    fn invalid_queries_are_rejected() {
+        assert_eq!(
+            builder().select("path").build().unwrap_err(),
+            BuilderError::UnknownColumn("path".to_string())
+        );
+        assert!(matches!(
+            builder().filter("status", Op::Eq, "500").build(),
+            Err(BuilderError::TypeMismatch { .. })
+        ));
+        assert!(matches!(
+            builder().aggregate(Aggregate::Sum, "host", "total").build(),
+            Err(BuilderError::NotNumeric(_))
+        ));
+        assert_eq!(
+            builder().select("host").count("n").build().unwrap_err(),
+            BuilderError::NotGrouped("host".to_string())
+        );
+        assert_eq!(
+            builder().limit(0).build().unwrap_err(),
+            BuilderError::InvalidLimit
+        );
+        let schema = Arc::new(Schema::empty());
+        assert_eq!(
+            QueryBuilder::new("app", schema).build().unwrap_err(),
+            BuilderError::InvalidTimeRange
+        );
+    }
+}
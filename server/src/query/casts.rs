@@ -0,0 +1,234 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+// Columns of a stream computed at query time by casting a stored column to
+// another type, for columns whose type was inferred wrongly from the first
+// events, such as numbers sent as strings. With the cast column
+//
+//   {"name": "status_code", "column": "status", "type": "int64"}
+//
+// queries can aggregate `status_code` instead of repeating the cast
+//
+//   SELECT status_code, count(*) FROM app GROUP BY status_code
+//
+// Any column can also be cast in a query itself, `CAST(status AS BIGINT)`
+// fails the query on values which do not convert, `TRY_CAST(status AS BIGINT)`
+// returns null for them. Cast columns use TRY_CAST.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use arrow_schema::{DataType, Schema, TimeUnit};
+use datafusion::common::OwnedTableReference;
+use datafusion::datasource::{provider_as_source, TableProvider};
+use datafusion::error::DataFusionError;
+use datafusion::logical_expr::{LogicalPlan, LogicalPlanBuilder, TryCast};
+use datafusion::prelude::Expr;
+
+use crate::event::{DEFAULT_METADATA_KEY, DEFAULT_TAGS_KEY};
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CastColumn {
+    pub name: String,
+    pub column: String,
+    #[serde(rename = "type")]
+    pub cast_type: CastType,
+}
+
+/// Types stored columns can be cast to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CastType {
+    /// from numeric strings, booleans and floats, which are truncated
+    Int64,
+    /// from numeric strings, booleans and integers
+    Float64,
+    /// from the strings `true` and `false` and numbers, zero being false
+    Boolean,
+    /// from any type
+    String,
+    /// from RFC3339 strings and integers, which are epoch milliseconds
+    Timestamp,
+}
+
+impl CastType {
+    pub fn data_type(&self) -> DataType {
+        match self {
+            CastType::Int64 => DataType::Int64,
+            CastType::Float64 => DataType::Float64,
+            CastType::Boolean => DataType::Boolean,
+            CastType::String => DataType::Utf8,
+            CastType::Timestamp => DataType::Timestamp(TimeUnit::Millisecond, None),
+        }
+    }
+}
+
+pub fn validate(casts: &[CastColumn], schema: &Schema) -> Result<(), String> {
+    let mut names = HashSet::new();
+    for cast in casts {
+        if cast.name.is_empty() || cast.column.is_empty() {
+            return Err("name and column of a cast column can not be empty".to_string());
+        }
+        if !names.insert(&cast.name) {
+            return Err(format!(
+                "cast column {} is defined more than once",
+                cast.name
+            ));
+        }
+        if [DEFAULT_TAGS_KEY, DEFAULT_METADATA_KEY].contains(&cast.name.as_str())
+            || schema.field_with_name(&cast.name).is_ok()
+        {
+            return Err(format!(
+                "cast column {} has the name of a column of the stream",
+                cast.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Plan of the stream with the cast columns following its stored columns.
+// Cast columns whose column is not stored yet, or whose name is taken by a
+// column stored since, are left out. Returns None if no cast column applies.
+pub fn view_plan(
+    stream: &str,
+    provider: Arc<dyn TableProvider>,
+    casts: &[CastColumn],
+) -> Result<Option<LogicalPlan>, DataFusionError> {
+    let schema = provider.schema();
+    let casts = casts
+        .iter()
+        .filter(|cast| {
+            schema.field_with_name(&cast.column).is_ok()
+                && schema.field_with_name(&cast.name).is_err()
+        })
+        .collect::<Vec<_>>();
+    if casts.is_empty() {
+        return Ok(None);
+    }
+
+    let scan = LogicalPlanBuilder::scan(
+        OwnedTableReference::bare(stream.to_owned()),
+        provider_as_source(provider),
+        None,
+    )?;
+    let input = scan.schema().clone();
+    let mut columns: Vec<Expr> = input
+        .fields()
+        .iter()
+        .map(|field| Expr::Column(field.qualified_column()))
+        .collect();
+    for cast in casts {
+        let field = input.field_with_unqualified_name(&cast.column)?;
+        columns.push(
+            Expr::TryCast(TryCast::new(
+                Box::new(Expr::Column(field.qualified_column())),
+                cast.cast_type.data_type(),
+            ))
+            .alias(&cast.name),
+        );
+    }
+
+    scan.project(columns)?.build().map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::datasource::{MemTable, ViewTable};
+    use datafusion::prelude::SessionContext;
+
+    use super::{validate, view_plan, CastColumn, CastType};
+
+    fn cast(name: &str, column: &str, cast_type: CastType) -> CastColumn {
+        CastColumn {
+            name: name.to_string(),
+            column: column.to_string(),
+            cast_type,
+        }
+    }
+
+    #[actix_web::test]
+    async fn aggregate_cast_column() {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "status",
+            DataType::Utf8,
+            true,
+        )]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(StringArray::from(vec!["200", "500", "n/a"]))],
+        )
+        .unwrap();
+        let table = Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap());
+        let casts = [
+            cast("status_code", "status", CastType::Int64),
+            cast("missing", "code", CastType::Int64),
+        ];
+        let plan = view_plan("app", table, &casts).unwrap().unwrap();
+        assert_eq!(plan.schema().fields().len(), 2);
+
+        let ctx = SessionContext::new();
+        ctx.register_table("app", Arc::new(ViewTable::try_new(plan, None).unwrap()))
+            .unwrap();
+        let batches = ctx
+            .sql("SELECT sum(status_code) AS total, count(status_code) AS n FROM app")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            batches[0]
+                .column(0)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap(),
+            &Int64Array::from(vec![700])
+        );
+        assert_eq!(
+            batches[0]
+                .column(1)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap(),
+            &Int64Array::from(vec![2])
+        );
+    }
+
+    #[test]
+    fn cast_column_names_are_unique() {
+        let schema = Schema::new(vec![Field::new("status", DataType::Utf8, true)]);
+        assert!(validate(&[cast("status_code", "status", CastType::Int64)], &schema).is_ok());
+        assert!(validate(&[cast("status", "status", CastType::Int64)], &schema).is_err());
+        assert!(validate(
+            &[
+                cast("code", "status", CastType::Int64),
+                cast("code", "status", CastType::Float64)
+            ],
+            &schema
+        )
+        .is_err());
+    }
+}
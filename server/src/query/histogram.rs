@@ -0,0 +1,139 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+// Histogram of a numeric column over the given bucket bounds, for latency
+// distribution charts. Bounds [10, 100] count the rows of
+//
+//   SELECT CASE WHEN "duration" < 10 THEN 0 WHEN "duration" < 100 THEN 1 ELSE 2 END AS "bucket",
+//     count(*) AS "count" FROM app WHERE "duration" IS NOT NULL GROUP BY 1
+//
+// into the buckets (-inf, 10), [10, 100) and [100, +inf). Buckets without rows
+// are returned with a count of zero.
+
+use std::fmt::Write;
+
+// a CASE arm per bound, more than a chart shows would only slow the query down
+const MAX_BOUNDS: usize = 200;
+
+pub const BUCKET_KEY: &str = "bucket";
+pub const COUNT_KEY: &str = "count";
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct HistogramBucket {
+    // bounds of the bucket, the lower one inclusive, none for the open ends
+    pub lower: Option<f64>,
+    pub upper: Option<f64>,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    bounds: Vec<f64>,
+}
+
+impl Histogram {
+    pub fn new(bounds: Vec<f64>) -> Result<Self, String> {
+        if bounds.is_empty() || bounds.len() > MAX_BOUNDS {
+            return Err(format!(
+                "between 1 and {MAX_BOUNDS} bucket bounds are allowed"
+            ));
+        }
+        if bounds.iter().any(|bound| !bound.is_finite()) {
+            return Err("bucket bounds must be finite numbers".to_string());
+        }
+        if bounds.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err("bucket bounds must be in increasing order".to_string());
+        }
+        Ok(Self { bounds })
+    }
+
+    pub fn sql(&self, stream_name: &str, column: &str) -> String {
+        let column = format!("\"{}\"", column.replace('"', "\"\""));
+        let mut arms = String::new();
+        for (index, bound) in self.bounds.iter().enumerate() {
+            let _ = write!(arms, "WHEN {column} < {bound:?} THEN {index} ");
+        }
+        format!(
+            "SELECT CASE {arms}ELSE {} END AS \"{BUCKET_KEY}\", count(*) AS \"{COUNT_KEY}\" FROM \"{}\" WHERE {column} IS NOT NULL GROUP BY 1",
+            self.bounds.len(),
+            stream_name.replace('"', "\"\"")
+        )
+    }
+
+    /// Every bucket along with its count out of the counts per bucket index
+    pub fn buckets(&self, counts: impl IntoIterator<Item = (usize, u64)>) -> Vec<HistogramBucket> {
+        let mut buckets: Vec<HistogramBucket> = (0..=self.bounds.len())
+            .map(|index| HistogramBucket {
+                lower: index.checked_sub(1).map(|index| self.bounds[index]),
+                upper: self.bounds.get(index).copied(),
+                count: 0,
+            })
+            .collect();
+        for (index, count) in counts {
+            if let Some(bucket) = buckets.get_mut(index) {
+                bucket.count += count;
+            }
+        }
+        buckets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Histogram, HistogramBucket};
+
+    #[test]
+    fn bounds_are_validated() {
+        assert!(Histogram::new(vec![]).is_err());
+        assert!(Histogram::new(vec![10.0, 5.0]).is_err());
+        assert!(Histogram::new(vec![10.0, 10.0]).is_err());
+        assert!(Histogram::new(vec![f64::NAN]).is_err());
+        assert!(Histogram::new(vec![0.5, 10.0]).is_ok());
+    }
+
+    #[test]
+    fn bucket_by_bounds() {
+        let histogram = Histogram::new(vec![10.0, 100.0]).unwrap();
+        assert_eq!(
+            histogram.sql("app", "duration"),
+            "SELECT CASE WHEN \"duration\" < 10.0 THEN 0 WHEN \"duration\" < 100.0 THEN 1 ELSE 2 END AS \"bucket\", count(*) AS \"count\" FROM \"app\" WHERE \"duration\" IS NOT NULL GROUP BY 1"
+        );
+
+        let buckets = histogram.buckets([(2, 3), (0, 5)]);
+        assert_eq!(
+            buckets,
+            vec![
+                HistogramBucket {
+                    lower: None,
+                    upper: Some(10.0),
+                    count: 5
+                },
+                HistogramBucket {
+                    lower: Some(10.0),
+                    upper: Some(100.0),
+                    count: 0
+                },
+                HistogramBucket {
+                    lower: Some(100.0),
+                    upper: None,
+                    count: 3
+                },
+            ]
+        );
+    }
+}
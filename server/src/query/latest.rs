@@ -0,0 +1,185 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+// Most recent row of every group of rows with the same values of the group
+// columns, such as the last event of every service for status dashboards.
+// DataFusion has no `DISTINCT ON`, the rows are ranked by time within their
+// group instead. Grouping by service selects
+//
+//   SELECT * FROM (SELECT *, row_number() OVER (PARTITION BY "service"
+//     ORDER BY "p_timestamp" DESC) AS "p_latest_rank" FROM "app")
+//     WHERE "p_latest_rank" = 1 ORDER BY "service"
+//
+// which can also be sent to /query as it is, with a WHERE clause in the inner
+// query to only look at some of the rows. Rows without a time are ranked last.
+
+use itertools::Itertools;
+
+pub const RANK_KEY: &str = "p_latest_rank";
+
+// a partition per group, more columns than this is rather a plain query
+const MAX_GROUP_COLUMNS: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct Latest {
+    group_by: Vec<String>,
+    // columns of the rows returned along with the group columns, all if none
+    columns: Option<Vec<String>>,
+}
+
+impl Latest {
+    pub fn new(group_by: Vec<String>, columns: Option<Vec<String>>) -> Result<Self, String> {
+        if group_by.is_empty() || group_by.len() > MAX_GROUP_COLUMNS {
+            return Err(format!(
+                "between 1 and {MAX_GROUP_COLUMNS} group columns are allowed"
+            ));
+        }
+        if columns.as_ref().is_some_and(Vec::is_empty) {
+            return Err("columns can not be empty, leave them out for all".to_string());
+        }
+        Ok(Self { group_by, columns })
+    }
+
+    /// Columns of the stream the query refers to
+    pub fn referenced_columns(&self) -> impl Iterator<Item = &String> {
+        self.group_by.iter().chain(self.columns.iter().flatten())
+    }
+
+    pub fn sql(&self, stream_name: &str, timestamp_key: &str) -> String {
+        let partition = self.group_by.iter().map(|column| quote(column)).join(", ");
+        let projection = match &self.columns {
+            Some(columns) => self
+                .group_by
+                .iter()
+                .chain(
+                    columns
+                        .iter()
+                        .filter(|column| !self.group_by.contains(column)),
+                )
+                .map(|column| quote(column))
+                .join(", "),
+            None => "*".to_string(),
+        };
+        format!(
+            "SELECT {projection} FROM (SELECT *, row_number() OVER (PARTITION BY {partition} ORDER BY {} DESC NULLS LAST) AS \"{RANK_KEY}\" FROM {}) WHERE \"{RANK_KEY}\" = 1 ORDER BY {partition}",
+            quote(timestamp_key),
+            quote(stream_name)
+        )
+    }
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use datafusion::arrow::array::{Int64Array, StringArray, TimestampMillisecondArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+    use datafusion::arrow::json::writer::record_batches_to_json_rows;
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::datasource::MemTable;
+    use datafusion::prelude::SessionContext;
+    use serde_json::{json, Value};
+
+    use super::{Latest, RANK_KEY};
+
+    async fn latest(latest: Latest) -> Vec<Value> {
+        let ctx = SessionContext::new();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+            Field::new("service", DataType::Utf8, true),
+            Field::new("host", DataType::Utf8, true),
+            Field::new("status", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(TimestampMillisecondArray::from(vec![
+                    Some(1000),
+                    Some(3000),
+                    Some(2000),
+                    None,
+                    Some(1500),
+                ])),
+                Arc::new(StringArray::from(vec!["web", "web", "api", "api", "web"])),
+                Arc::new(StringArray::from(vec!["a", "b", "a", "a", "a"])),
+                Arc::new(Int64Array::from(vec![200, 503, 200, 500, 404])),
+            ],
+        )
+        .unwrap();
+        let table = MemTable::try_new(schema, vec![vec![batch]]).unwrap();
+        ctx.register_table("app", Arc::new(table)).unwrap();
+
+        let batches = ctx
+            .sql(&latest.sql("app", "p_timestamp"))
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let batches: Vec<&RecordBatch> = batches.iter().collect();
+        record_batches_to_json_rows(&batches)
+            .unwrap()
+            .into_iter()
+            .map(|mut row| {
+                row.remove(RANK_KEY);
+                row.remove("p_timestamp");
+                Value::Object(row)
+            })
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn latest_row_per_group() {
+        let by_service = Latest::new(vec!["service".to_string()], None).unwrap();
+        assert_eq!(
+            latest(by_service).await,
+            vec![
+                json!({"service": "api", "host": "a", "status": 200}),
+                json!({"service": "web", "host": "b", "status": 503}),
+            ]
+        );
+
+        let by_host = Latest::new(
+            vec!["service".to_string(), "host".to_string()],
+            Some(vec!["status".to_string()]),
+        )
+        .unwrap();
+        assert_eq!(
+            latest(by_host).await,
+            vec![
+                json!({"service": "api", "host": "a", "status": 200}),
+                json!({"service": "web", "host": "a", "status": 404}),
+                json!({"service": "web", "host": "b", "status": 503}),
+            ]
+        );
+    }
+
+    #[test]
+    fn group_columns_are_required() {
+        assert!(Latest::new(Vec::new(), None).is_err());
+        assert!(Latest::new(vec!["service".to_string()], Some(Vec::new())).is_err());
+    }
+}
@@ -20,19 +20,19 @@ use std::{collections::HashMap, ops::Bound, pin::Pin, sync::Arc};
 
 use arrow_schema::Schema;
 use datafusion::{
+    common::Column,
     datasource::{
         file_format::parquet::ParquetFormat,
         listing::{ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl},
     },
     error::DataFusionError,
-    logical_expr::col,
+    logical_expr::Expr,
 };
 use futures_util::{future, stream::FuturesUnordered, Future, TryStreamExt};
 use itertools::Itertools;
 use object_store::{ObjectMeta, ObjectStore};
 
 use crate::{
-    event::DEFAULT_TIMESTAMP_KEY,
     storage::{ObjectStorage, OBJECT_STORE_DATA_GRANULARITY},
     utils::TimePeriod,
 };
@@ -182,6 +182,7 @@ impl ListingTableBuilder {
     pub fn build(
         self,
         schema: Arc<Schema>,
+        timestamp_key: &str,
         map: impl Fn(Vec<String>) -> Vec<ListingTableUrl>,
     ) -> Result<Option<Arc<ListingTable>>, DataFusionError> {
         if self.listing.is_empty() {
@@ -189,7 +190,9 @@ impl ListingTableBuilder {
         }
 
         let file_format = ParquetFormat::default().with_enable_pruning(Some(true));
-        let file_sort_order = vec![vec![col(DEFAULT_TIMESTAMP_KEY).sort(true, false)]];
+        let file_sort_order = vec![vec![
+            Expr::Column(Column::new_unqualified(timestamp_key)).sort(true, false)
+        ]];
         let listing_options = ListingOptions::new(Arc::new(file_format))
             .with_file_extension(".parquet")
             .with_file_sort_order(file_sort_order)
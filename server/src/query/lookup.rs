@@ -0,0 +1,213 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+// Lookup tables are small static tables, such as a mapping of services to
+// teams, which queries can JOIN with streams to enrich events without
+// reingesting them
+//
+//   SELECT app.*, teams.team FROM app JOIN teams ON app.service = teams.service
+//
+// They are uploaded through the lookup API and live in memory only, uploading
+// a table again replaces its rows. A stream of the same name takes precedence
+// over a lookup table in queries.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use arrow_json::reader::{infer_json_schema_from_iterator, ReaderBuilder};
+use chrono::{DateTime, Utc};
+use datafusion::arrow::error::ArrowError;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::datasource::MemTable;
+use datafusion::error::DataFusionError;
+use once_cell::sync::Lazy;
+use serde_json::Value;
+
+pub static LOOKUP_TABLES: Lazy<LookupTables> = Lazy::new(LookupTables::default);
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LookupTableInfo {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub rows: usize,
+    pub updated_at: DateTime<Utc>,
+}
+
+struct LookupTable {
+    table: Arc<MemTable>,
+    info: LookupTableInfo,
+}
+
+#[derive(Default)]
+pub struct LookupTables(RwLock<HashMap<String, LookupTable>>);
+
+impl LookupTables {
+    // create the lookup table or replace the rows of an existing one
+    pub fn put(&self, name: &str, records: &[Value]) -> Result<LookupTableInfo, DataFusionError> {
+        let batch = record_batch(records)?;
+        let info = LookupTableInfo {
+            name: name.to_owned(),
+            columns: batch
+                .schema()
+                .fields()
+                .iter()
+                .map(|field| field.name().clone())
+                .collect(),
+            rows: batch.num_rows(),
+            updated_at: Utc::now(),
+        };
+        let table = Arc::new(MemTable::try_new(batch.schema(), vec![vec![batch]])?);
+
+        self.0.write().unwrap().insert(
+            name.to_owned(),
+            LookupTable {
+                table,
+                info: info.clone(),
+            },
+        );
+        Ok(info)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<MemTable>> {
+        self.0
+            .read()
+            .unwrap()
+            .get(name)
+            .map(|lookup| lookup.table.clone())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.read().unwrap().contains_key(name)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.0.read().unwrap().keys().cloned().collect()
+    }
+
+    pub fn list(&self) -> Vec<LookupTableInfo> {
+        let mut tables: Vec<LookupTableInfo> = self
+            .0
+            .read()
+            .unwrap()
+            .values()
+            .map(|lookup| lookup.info.clone())
+            .collect();
+        tables.sort_by(|a, b| a.name.cmp(&b.name));
+        tables
+    }
+
+    pub fn remove(&self, name: &str) -> bool {
+        self.0.write().unwrap().remove(name).is_some()
+    }
+}
+
+// Decode JSON objects into a single record batch with an inferred schema
+fn record_batch(records: &[Value]) -> Result<RecordBatch, ArrowError> {
+    if records.is_empty() {
+        return Err(ArrowError::JsonError(
+            "lookup table has no rows".to_string(),
+        ));
+    }
+    let schema = infer_json_schema_from_iterator(records.iter().map(Ok))?;
+    let mut decoder = ReaderBuilder::new(Arc::new(schema))
+        .with_batch_size(records.len())
+        .build_decoder()?;
+    decoder.serialize(records)?;
+    Ok(decoder.flush()?.expect("lookup table has rows"))
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::arrow::array::StringArray;
+    use datafusion::prelude::SessionContext;
+    use serde_json::json;
+
+    use super::LookupTables;
+
+    #[test]
+    fn put_replaces_rows() {
+        let tables = LookupTables::default();
+        tables
+            .put("teams", &[json!({"service": "api", "team": "core"})])
+            .unwrap();
+        let info = tables
+            .put(
+                "teams",
+                &[
+                    json!({"service": "api", "team": "platform"}),
+                    json!({"service": "web", "team": "frontend", "oncall": "a"}),
+                ],
+            )
+            .unwrap();
+
+        assert_eq!(info.rows, 2);
+        assert_eq!(info.columns, vec!["service", "team", "oncall"]);
+        assert_eq!(tables.list().len(), 1);
+        assert!(tables.put("empty", &[]).is_err());
+        assert!(tables.remove("teams"));
+        assert!(!tables.contains("teams"));
+    }
+
+    #[actix_web::test]
+    async fn join_with_lookup_table() {
+        let tables = LookupTables::default();
+        tables
+            .put(
+                "teams",
+                &[
+                    json!({"service": "api", "team": "core"}),
+                    json!({"service": "web", "team": "frontend"}),
+                ],
+            )
+            .unwrap();
+        tables
+            .put(
+                "app",
+                &[
+                    json!({"service": "web", "message": "a"}),
+                    json!({"service": "db", "message": "b"}),
+                ],
+            )
+            .unwrap();
+
+        let ctx = SessionContext::new();
+        ctx.register_table("teams", tables.get("teams").unwrap())
+            .unwrap();
+        ctx.register_table("app", tables.get("app").unwrap())
+            .unwrap();
+        let batches = ctx
+            .sql(
+                "SELECT app.message, teams.team FROM app JOIN teams ON app.service = teams.service",
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+
+        assert_eq!(batches.iter().map(|rb| rb.num_rows()).sum::<usize>(), 1);
+        let batch = batches.iter().find(|rb| rb.num_rows() > 0).unwrap();
+        let team = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(team.value(0), "frontend");
+    }
+}
@@ -0,0 +1,169 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+// `map_get` returns the value of a key in a map column, such as the attribute
+// map of streams storing their attributes in a single column.
+//
+//   SELECT map_get(attributes, 'user_id') AS user_id FROM app
+//   WHERE map_get(attributes, 'path') = '/login'
+//
+// Values are returned as strings, null when the key is not in the map.
+
+use std::sync::Arc;
+
+use datafusion::arrow::array::{Array, MapArray, StringArray};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::DataType;
+use datafusion::common::{exec_err, plan_err, DataFusionError};
+use datafusion::logical_expr::{
+    ColumnarValue, ReturnTypeFunction, ScalarFunctionImplementation, ScalarUDF, Signature,
+    Volatility,
+};
+
+pub const MAP_GET: &str = "map_get";
+
+pub fn map_get_udf() -> ScalarUDF {
+    let return_type: ReturnTypeFunction = Arc::new(|args: &[DataType]| match &args[0] {
+        DataType::Map(_, _) => Ok(Arc::new(DataType::Utf8)),
+        data_type => plan_err!("map_get expects a map argument, got {data_type}"),
+    });
+    let fun: ScalarFunctionImplementation = Arc::new(map_get);
+
+    ScalarUDF::new(
+        MAP_GET,
+        &Signature::any(2, Volatility::Immutable),
+        &return_type,
+        &fun,
+    )
+}
+
+fn map_get(args: &[ColumnarValue]) -> Result<ColumnarValue, DataFusionError> {
+    let len = args
+        .iter()
+        .find_map(|arg| match arg {
+            ColumnarValue::Array(array) => Some(array.len()),
+            ColumnarValue::Scalar(_) => None,
+        })
+        .unwrap_or(1);
+    let map = args[0].clone().into_array(len);
+    let Some(map) = map.as_any().downcast_ref::<MapArray>() else {
+        return exec_err!("map_get expects a map argument");
+    };
+    let keys = cast(&args[1].clone().into_array(len), &DataType::Utf8)?;
+    let keys = keys
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .expect("cast to strings");
+    let entry_keys = cast(map.keys(), &DataType::Utf8)?;
+    let entry_keys = entry_keys
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .expect("cast to strings");
+    let entry_values = cast(map.values(), &DataType::Utf8)?;
+    let entry_values = entry_values
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .expect("cast to strings");
+
+    let offsets = map.value_offsets();
+    let values: StringArray = (0..len)
+        .map(|row| {
+            if map.is_null(row) || keys.is_null(row) {
+                return None;
+            }
+            let key = keys.value(row);
+            (offsets[row] as usize..offsets[row + 1] as usize)
+                .find(|&entry| entry_keys.value(entry) == key)
+                .filter(|&entry| !entry_values.is_null(entry))
+                .map(|entry| entry_values.value(entry))
+        })
+        .collect();
+
+    Ok(ColumnarValue::Array(Arc::new(values)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_json::ReaderBuilder;
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::datatypes::{Field, Schema};
+    use datafusion::datasource::MemTable;
+    use datafusion::prelude::SessionContext;
+    use serde_json::json;
+
+    use super::map_get_udf;
+    use crate::event::attributes::map_data_type;
+
+    #[actix_web::test]
+    async fn get_values_of_map_keys() {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "attributes",
+            map_data_type(),
+            true,
+        )]));
+        let mut decoder = ReaderBuilder::new(schema.clone()).build_decoder().unwrap();
+        decoder
+            .serialize(&[
+                json!({"attributes": {"user_id": "7", "path": "/login"}}),
+                json!({"attributes": {"path": "/"}}),
+                json!({}),
+            ])
+            .unwrap();
+        let batch = decoder.flush().unwrap().unwrap();
+
+        let ctx = SessionContext::new();
+        ctx.register_udf(map_get_udf());
+        let table = MemTable::try_new(schema, vec![vec![batch]]).unwrap();
+        ctx.register_table("app", Arc::new(table)).unwrap();
+
+        let batches = ctx
+            .sql("SELECT map_get(attributes, 'user_id') AS user_id FROM app")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            batches[0]
+                .column(0)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap(),
+            &StringArray::from(vec![Some("7"), None, None])
+        );
+
+        let batches = ctx
+            .sql("SELECT count(*) AS n FROM app WHERE map_get(attributes, 'path') = '/login'")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            batches[0]
+                .column(0)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap()
+                .value(0),
+            1
+        );
+    }
+}
@@ -0,0 +1,192 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+// Parameters of a query are bound into the plan in place of `$1`, `$2`, .. placeholders
+//
+//   SELECT * FROM app WHERE host = $1 AND status >= $2
+//
+// with `"params": [{"type": "string", "value": "web-1"}, {"type": "int", "value": 500}]`.
+// Values never go through the SQL parser, so they can not change the structure of the query.
+// A placeholder compared with a column takes the type of the column and the value is cast to it.
+// Placeholders are bound by datafusion, which leaves out those of IN and EXISTS subqueries.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use datafusion::arrow::compute::{cast_with_options, CastOptions};
+use datafusion::arrow::datatypes::DataType;
+use datafusion::common::{DataFusionError, Result, ScalarValue};
+use datafusion::logical_expr::{LogicalPlan, Prepare};
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum QueryParam {
+    String(String),
+    Int(i64),
+    Timestamp(DateTime<Utc>),
+}
+
+impl QueryParam {
+    fn to_scalar(&self) -> ScalarValue {
+        match self {
+            QueryParam::String(value) => ScalarValue::Utf8(Some(value.clone())),
+            QueryParam::Int(value) => ScalarValue::Int64(Some(*value)),
+            QueryParam::Timestamp(value) => {
+                ScalarValue::TimestampMillisecond(Some(value.timestamp_millis()), None)
+            }
+        }
+    }
+}
+
+/// Replace every placeholder of the plan with the value of its parameter.
+/// Datafusion only binds values of the exact type of their placeholder, so
+/// values are cast to the type of the placeholder first.
+pub fn bind(plan: LogicalPlan, params: &[QueryParam]) -> Result<LogicalPlan> {
+    let types = plan.get_parameter_types()?;
+    if types.is_empty() && params.is_empty() {
+        return Ok(plan);
+    }
+    let values = params
+        .iter()
+        .enumerate()
+        .map(|(index, param)| {
+            let id = format!("${}", index + 1);
+            match types.get(&id) {
+                Some(Some(data_type)) => cast_to(&id, param.to_scalar(), data_type),
+                _ => Ok(param.to_scalar()),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    LogicalPlan::Prepare(Prepare {
+        name: String::new(),
+        data_types: values.iter().map(ScalarValue::data_type).collect(),
+        input: Arc::new(plan),
+    })
+    .with_param_values(values)
+}
+
+// fail on values which do not fit the type rather than binding null
+fn cast_to(id: &str, value: ScalarValue, data_type: &DataType) -> Result<ScalarValue> {
+    if value.data_type() == *data_type {
+        return Ok(value);
+    }
+    let options = CastOptions {
+        safe: false,
+        ..Default::default()
+    };
+    cast_with_options(&value.to_array(), data_type, &options)
+        .map_err(DataFusionError::from)
+        .and_then(|array| ScalarValue::try_from_array(&array, 0))
+        .map_err(|_| {
+            DataFusionError::Plan(format!(
+                "query parameter {id} can not be used as {data_type}"
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::datasource::MemTable;
+    use datafusion::prelude::SessionContext;
+
+    use super::{bind, QueryParam};
+
+    fn context() -> SessionContext {
+        let ctx = SessionContext::new();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new("status", DataType::Float64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(vec!["a", "b", "a' OR '1'='1"])),
+                Arc::new(datafusion::arrow::array::Float64Array::from(vec![
+                    200.0, 500.0, 500.0,
+                ])),
+            ],
+        )
+        .unwrap();
+        let table = MemTable::try_new(schema, vec![vec![batch]]).unwrap();
+        ctx.register_table("app", Arc::new(table)).unwrap();
+        ctx
+    }
+
+    async fn count(sql: &str, params: &[QueryParam]) -> Result<i64, String> {
+        let ctx = context();
+        let plan = ctx
+            .state()
+            .create_logical_plan(sql)
+            .await
+            .map_err(|err| err.to_string())?;
+        let plan = bind(plan, params).map_err(|err| err.to_string())?;
+        let batches = ctx
+            .execute_logical_plan(plan)
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        Ok(batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap()
+            .value(0))
+    }
+
+    #[actix_web::test]
+    async fn bind_typed_params() {
+        let params: Vec<QueryParam> = serde_json::from_str(
+            r#"[{"type": "string", "value": "a"}, {"type": "int", "value": 300}]"#,
+        )
+        .unwrap();
+        let sql = "SELECT count(*) FROM app WHERE host = $1 OR status > $2";
+        assert_eq!(count(sql, &params).await.unwrap(), 3);
+
+        let sql = "SELECT count(*) FROM app WHERE host = $1";
+        let params = [QueryParam::String("a' OR '1'='1".to_string())];
+        assert_eq!(count(sql, &params).await.unwrap(), 1);
+    }
+
+    #[actix_web::test]
+    async fn bind_params_in_subquery() {
+        let sql =
+            "SELECT count(*) FROM app WHERE status > (SELECT min(status) FROM app WHERE host = $1)";
+        let params = [QueryParam::String("a".to_string())];
+        assert_eq!(count(sql, &params).await.unwrap(), 2);
+    }
+
+    #[actix_web::test]
+    async fn missing_param_is_err() {
+        let sql = "SELECT count(*) FROM app WHERE host = $1 AND status > $2";
+        let err = count(sql, &[QueryParam::String("a".to_string())])
+            .await
+            .unwrap_err();
+        assert!(err.contains("$2"));
+
+        let sql = "SELECT count(*) FROM app WHERE status > $1";
+        let params = [QueryParam::String("high".to_string())];
+        assert!(count(sql, &params).await.is_err());
+    }
+}
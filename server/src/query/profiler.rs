@@ -0,0 +1,166 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+// number of most recent queries kept for profiling
+const PROFILER_CAPACITY: usize = 256;
+
+pub static QUERY_PROFILER: Lazy<QueryProfiler> =
+    Lazy::new(|| QueryProfiler::new(PROFILER_CAPACITY));
+
+// string literals in a query can carry values such as emails or tokens used in filters
+static STRING_LITERAL: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"'(?:[^']|'')*'").expect("valid regex"));
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryProfile {
+    pub sql: String,
+    pub stream: Option<String>,
+    pub executed_at: DateTime<Utc>,
+    pub duration_ms: u128,
+    pub bytes_scanned: usize,
+    pub rows: usize,
+}
+
+impl QueryProfile {
+    pub fn new(
+        sql: &str,
+        stream: Option<String>,
+        executed_at: DateTime<Utc>,
+        duration_ms: u128,
+        bytes_scanned: usize,
+        rows: usize,
+    ) -> Self {
+        Self {
+            sql: redact(sql),
+            stream,
+            executed_at,
+            duration_ms,
+            bytes_scanned,
+            rows,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopQueries {
+    pub slowest: Vec<QueryProfile>,
+    pub most_expensive: Vec<QueryProfile>,
+}
+
+// Bounded ring buffer of recently executed queries.
+// Once full the oldest query is dropped for every new one.
+#[derive(Debug)]
+pub struct QueryProfiler {
+    capacity: usize,
+    queries: Mutex<VecDeque<QueryProfile>>,
+}
+
+impl QueryProfiler {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            queries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn record(&self, profile: QueryProfile) {
+        let mut queries = self.queries.lock().unwrap();
+        if queries.len() == self.capacity {
+            queries.pop_front();
+        }
+        queries.push_back(profile);
+    }
+
+    // top n queries among the recent ones by duration and by bytes scanned
+    pub fn top(&self, n: usize) -> TopQueries {
+        let queries = self.queries.lock().unwrap();
+
+        let mut slowest: Vec<QueryProfile> = queries.iter().cloned().collect();
+        slowest.sort_by(|a, b| b.duration_ms.cmp(&a.duration_ms));
+        slowest.truncate(n);
+
+        let mut most_expensive: Vec<QueryProfile> = queries.iter().cloned().collect();
+        most_expensive.sort_by(|a, b| b.bytes_scanned.cmp(&a.bytes_scanned));
+        most_expensive.truncate(n);
+
+        TopQueries {
+            slowest,
+            most_expensive,
+        }
+    }
+}
+
+fn redact(sql: &str) -> String {
+    STRING_LITERAL.replace_all(sql, "'***'").into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::{QueryProfile, QueryProfiler};
+
+    fn profile(duration_ms: u128, bytes_scanned: usize) -> QueryProfile {
+        QueryProfile::new(
+            "select * from app",
+            Some("app".to_string()),
+            Utc::now(),
+            duration_ms,
+            bytes_scanned,
+            0,
+        )
+    }
+
+    #[test]
+    fn ring_buffer_drops_oldest() {
+        let profiler = QueryProfiler::new(2);
+        profiler.record(profile(300, 1));
+        profiler.record(profile(100, 2));
+        profiler.record(profile(200, 3));
+
+        let top = profiler.top(10);
+        assert_eq!(top.slowest.len(), 2);
+        assert_eq!(top.slowest[0].duration_ms, 200);
+        assert_eq!(top.most_expensive[0].bytes_scanned, 3);
+    }
+
+    #[test]
+    fn string_literals_are_redacted() {
+        let profile = QueryProfile::new(
+            "select * from app where email = 'a@b.com' and name = 'o''neil'",
+            None,
+            Utc::now(),
+            0,
+            0,
+            0,
+        );
+        assert_eq!(
+            profile.sql,
+            "select * from app where email = '***' and name = '***'"
+        );
+    }
+}
@@ -0,0 +1,128 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+// Schema of a query limited to its time range. The schema of a stream is the
+// union of every field it ever had, so a `SELECT *` keeps returning columns of
+// fields which stopped being sent long ago, as nulls. With the schema limited
+// to the time range, such columns are left out of the result if no file in the
+// range has them. A column is only left out when all of its values are null,
+// data still in staging is not listed in any manifest and must not be hidden.
+
+use std::collections::HashSet;
+
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::DataFusionError;
+use datafusion::sql::parser::Statement;
+use datafusion::sql::sqlparser::ast::{self, SelectItem, SetExpr};
+
+// Whether the query selects every column with a wildcard. Columns selected by
+// name are always kept in the result.
+pub fn selects_all(statement: &Statement) -> bool {
+    let Statement::Statement(statement) = statement else {
+        return false;
+    };
+    let ast::Statement::Query(query) = statement.as_ref() else {
+        return false;
+    };
+    let SetExpr::Select(select) = query.body.as_ref() else {
+        return false;
+    };
+    select.projection.iter().any(|item| {
+        matches!(
+            item,
+            SelectItem::Wildcard(_) | SelectItem::QualifiedWildcard(..)
+        )
+    })
+}
+
+// Remove the columns of the result which are in `absent` and null in every row
+pub fn drop_absent(
+    records: Vec<RecordBatch>,
+    fields: Vec<String>,
+    absent: &HashSet<String>,
+) -> Result<(Vec<RecordBatch>, Vec<String>), DataFusionError> {
+    let keep = (0..fields.len())
+        .filter(|&index| {
+            !absent.contains(&fields[index])
+                || records.iter().any(|rb| {
+                    let column = rb.column(index);
+                    column.null_count() < column.len()
+                })
+        })
+        .collect::<Vec<_>>();
+    if keep.len() == fields.len() {
+        return Ok((records, fields));
+    }
+    let fields = keep.iter().map(|&index| fields[index].clone()).collect();
+    let records = records
+        .iter()
+        .map(|rb| rb.project(&keep))
+        .collect::<Result<_, _>>()?;
+    Ok((records, fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::sql::parser::DFParser;
+
+    use super::{drop_absent, selects_all};
+
+    #[test]
+    fn wildcard_queries() {
+        let statement = |sql| DFParser::parse_sql(sql).unwrap().pop_front().unwrap();
+        assert!(selects_all(&statement("SELECT * FROM app")));
+        assert!(selects_all(&statement("SELECT app.* FROM app")));
+        assert!(!selects_all(&statement("SELECT host, status FROM app")));
+        assert!(!selects_all(&statement("SELECT count(*) FROM app")));
+    }
+
+    #[test]
+    fn null_columns_absent_from_range_dropped() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new("legacy", DataType::Utf8, true),
+            Field::new("status", DataType::Int64, true),
+            Field::new("staged", DataType::Int64, true),
+        ]));
+        let rb = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(vec![Some("a"), Some("b")])),
+                Arc::new(StringArray::from(vec![None::<&str>, None])),
+                Arc::new(Int64Array::from(vec![None, None])),
+                Arc::new(Int64Array::from(vec![None, Some(1)])),
+            ],
+        )
+        .unwrap();
+        let fields = ["host", "legacy", "status", "staged"].map(String::from);
+        // status is in the files of the range, staged is only in staging
+        let absent = HashSet::from(["legacy".to_string(), "staged".to_string()]);
+
+        let (records, fields) = drop_absent(vec![rb], fields.to_vec(), &absent).unwrap();
+
+        assert_eq!(fields, ["host", "status", "staged"]);
+        assert_eq!(records[0].num_columns(), 3);
+        assert_eq!(records[0].schema().field(1).name(), "status");
+    }
+}
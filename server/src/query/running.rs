@@ -0,0 +1,134 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use once_cell::sync::Lazy;
+use tokio::sync::Notify;
+
+pub static RUNNING_QUERIES: Lazy<RunningQueries> = Lazy::new(RunningQueries::default);
+
+// Queries currently executing by the user who runs them and their id, so
+// that they can be cancelled from another request. Ids are picked by clients
+// and only unique per user, so one user can neither take the id of a query of
+// another user nor cancel it.
+#[derive(Debug, Default)]
+pub struct RunningQueries {
+    queries: Mutex<HashMap<(String, String), Arc<Notify>>>,
+}
+
+impl RunningQueries {
+    /// Track a query of this user under the given id until the returned guard
+    /// is dropped. Returns None if the user already runs a query with this id
+    pub fn register(&'static self, owner: String, id: String) -> Option<RunningQuery> {
+        let mut queries = self.queries.lock().unwrap();
+        let key = (owner, id);
+        if queries.contains_key(&key) {
+            return None;
+        }
+        let cancel = Arc::new(Notify::new());
+        queries.insert(key.clone(), cancel.clone());
+        Some(RunningQuery {
+            key,
+            cancel,
+            queries: self,
+        })
+    }
+
+    /// Cancel the running query with this id of the given user, or with this
+    /// id of any user if there is no owner to match, as for admins. Returns
+    /// false if there is none
+    pub fn cancel(&self, owner: Option<&str>, id: &str) -> bool {
+        let queries = self.queries.lock().unwrap();
+        let mut cancelled = false;
+        for ((query_owner, query_id), cancel) in queries.iter() {
+            if query_id == id && owner.map_or(true, |owner| owner == query_owner) {
+                // stores a permit if the query is not waiting on it at the moment
+                cancel.notify_one();
+                cancelled = true;
+            }
+        }
+        cancelled
+    }
+}
+
+pub struct RunningQuery {
+    key: (String, String),
+    cancel: Arc<Notify>,
+    queries: &'static RunningQueries,
+}
+
+impl RunningQuery {
+    /// Resolves once the query is cancelled
+    pub async fn cancelled(&self) {
+        self.cancel.notified().await
+    }
+}
+
+impl Drop for RunningQuery {
+    fn drop(&mut self) {
+        self.queries.queries.lock().unwrap().remove(&self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use once_cell::sync::Lazy;
+
+    use super::RunningQueries;
+
+    static QUERIES: Lazy<RunningQueries> = Lazy::new(RunningQueries::default);
+
+    #[actix_web::test]
+    async fn cancel_running_query() {
+        let query = QUERIES
+            .register("alice".to_string(), "q1".to_string())
+            .unwrap();
+        assert!(QUERIES
+            .register("alice".to_string(), "q1".to_string())
+            .is_none());
+
+        assert!(QUERIES.cancel(Some("alice"), "q1"));
+        query.cancelled().await;
+
+        drop(query);
+        assert!(!QUERIES.cancel(Some("alice"), "q1"));
+        assert!(QUERIES
+            .register("alice".to_string(), "q1".to_string())
+            .is_some());
+    }
+
+    #[actix_web::test]
+    async fn query_ids_scoped_by_owner() {
+        let alice = QUERIES
+            .register("alice".to_string(), "q2".to_string())
+            .unwrap();
+        // another user can use the same id, but not cancel the query of alice
+        let bob = QUERIES
+            .register("bob".to_string(), "q2".to_string())
+            .unwrap();
+        assert!(!QUERIES.cancel(Some("carol"), "q2"));
+        assert!(QUERIES.cancel(Some("bob"), "q2"));
+        bob.cancelled().await;
+
+        // without an owner to match, as for admins, any query with the id
+        assert!(QUERIES.cancel(None, "q2"));
+        alice.cancelled().await;
+    }
+}
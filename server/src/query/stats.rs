@@ -0,0 +1,190 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use datafusion::arrow::array::{Array, Int64Array, TimestampMillisecondArray};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{DataType, TimeUnit};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::DataFusionError;
+use datafusion::logical_expr::{count, max, min, LogicalPlan, LogicalPlanBuilder};
+use datafusion::prelude::{lit, Expr};
+
+const COUNT: &str = "count";
+const MIN_TIME: &str = "min_time";
+const MAX_TIME: &str = "max_time";
+
+// Number of rows matching a query and the time range they span, returned
+// along with the results. The count is taken before any limit of the query
+// so that clients can page through or summarize the matching data.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryStats {
+    pub count: u64,
+    pub min_time: Option<DateTime<Utc>>,
+    pub max_time: Option<DateTime<Utc>>,
+}
+
+impl QueryStats {
+    pub fn from_batches(batches: &[RecordBatch]) -> Result<Self, DataFusionError> {
+        let Some(batch) = batches.iter().find(|batch| batch.num_rows() > 0) else {
+            return Ok(Self::default());
+        };
+        let count = batch
+            .column_by_name(COUNT)
+            .and_then(|column| column.as_any().downcast_ref::<Int64Array>())
+            .map(|column| column.value(0).max(0) as u64)
+            .unwrap_or_default();
+
+        Ok(Self {
+            count,
+            min_time: time(batch, MIN_TIME)?,
+            max_time: time(batch, MAX_TIME)?,
+        })
+    }
+}
+
+// Aggregate counting the rows matching `plan` along with the smallest and
+// largest timestamp among them. The limit, sort and projection on top of the
+// filtered scan are dropped so that datafusion can answer the count from
+// parquet statistics when nothing is filtered out. Returns None for plans
+// which do not produce rows, such as EXPLAIN.
+pub fn stats_plan(
+    plan: LogicalPlan,
+    timestamp_key: &str,
+) -> Result<Option<LogicalPlan>, DataFusionError> {
+    let mut plan = plan;
+    let input = loop {
+        plan = match plan {
+            LogicalPlan::Limit(limit) => limit.input.as_ref().clone(),
+            LogicalPlan::Sort(sort) => sort.input.as_ref().clone(),
+            LogicalPlan::Projection(projection) => projection.input.as_ref().clone(),
+            LogicalPlan::Explain(_) | LogicalPlan::Analyze(_) => return Ok(None),
+            input => break input,
+        }
+    };
+
+    let mut aggregates = vec![count(lit(1u8)).alias(COUNT)];
+    if let Ok(field) = input.schema().field_with_unqualified_name(timestamp_key) {
+        if matches!(field.data_type(), DataType::Timestamp(_, _)) {
+            let column = Expr::Column(field.qualified_column());
+            aggregates.push(min(column.clone()).alias(MIN_TIME));
+            aggregates.push(max(column).alias(MAX_TIME));
+        }
+    }
+
+    LogicalPlanBuilder::from(input)
+        .aggregate(Vec::<Expr>::new(), aggregates)?
+        .build()
+        .map(Some)
+}
+
+fn time(batch: &RecordBatch, name: &str) -> Result<Option<DateTime<Utc>>, DataFusionError> {
+    let Some(column) = batch.column_by_name(name) else {
+        return Ok(None);
+    };
+    let column = cast(column, &DataType::Timestamp(TimeUnit::Millisecond, None))?;
+    let column = column
+        .as_any()
+        .downcast_ref::<TimestampMillisecondArray>()
+        .expect("cast to millisecond timestamps");
+    if column.is_null(0) {
+        return Ok(None);
+    }
+    Ok(NaiveDateTime::from_timestamp_millis(column.value(0))
+        .map(|time| DateTime::<Utc>::from_naive_utc_and_offset(time, Utc)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{TimeZone, Utc};
+    use datafusion::arrow::array::{StringArray, TimestampMillisecondArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::datasource::MemTable;
+    use datafusion::prelude::SessionContext;
+
+    use super::{stats_plan, QueryStats};
+
+    async fn stats(sql: &str) -> Option<QueryStats> {
+        let ctx = SessionContext::new();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+            Field::new("host", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(TimestampMillisecondArray::from(vec![
+                    1704067200000,
+                    1704067260000,
+                    1704067320000,
+                ])),
+                Arc::new(StringArray::from(vec!["a", "b", "a"])),
+            ],
+        )
+        .unwrap();
+        let table = MemTable::try_new(schema, vec![vec![batch]]).unwrap();
+        ctx.register_table("app", Arc::new(table)).unwrap();
+
+        let plan = ctx.state().create_logical_plan(sql).await.unwrap();
+        let plan = stats_plan(plan, "p_timestamp").unwrap()?;
+        let batches = ctx
+            .execute_logical_plan(plan)
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        Some(QueryStats::from_batches(&batches).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn stats_ignore_limit_and_projection() {
+        let stats = stats("SELECT host FROM app WHERE host = 'a' ORDER BY host LIMIT 1")
+            .await
+            .unwrap();
+
+        assert_eq!(stats.count, 2);
+        assert_eq!(
+            stats.min_time,
+            Some(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap())
+        );
+        assert_eq!(
+            stats.max_time,
+            Some(Utc.with_ymd_and_hms(2024, 1, 1, 0, 2, 0).unwrap())
+        );
+    }
+
+    #[actix_web::test]
+    async fn stats_of_no_matching_rows() {
+        let stats = stats("SELECT * FROM app WHERE host = 'c'").await.unwrap();
+        assert_eq!(stats, QueryStats::default());
+    }
+
+    #[actix_web::test]
+    async fn no_stats_for_explain() {
+        assert!(stats("EXPLAIN SELECT * FROM app").await.is_none());
+    }
+}
@@ -32,7 +32,7 @@ use datafusion::{
         file_format::{parquet::ParquetFormat, FileFormat},
         listing::PartitionedFile,
         physical_plan::FileScanConfig,
-        MemTable, TableProvider,
+        MemTable, TableProvider, ViewTable,
     },
     error::DataFusionError,
     execution::{context::SessionState, object_store::ObjectStoreUrl},
@@ -53,15 +53,17 @@ use crate::{
         self, column::TypedStatistics, manifest::Manifest, snapshot::ManifestItem, ManifestFile,
         Snapshot,
     },
-    event::{self, DEFAULT_TIMESTAMP_KEY},
+    event,
     localcache::LocalCacheManager,
     metadata::STREAM_INFO,
     metrics::QUERY_CACHE_HIT,
     option::CONFIG,
-    storage::ObjectStorage,
+    storage::{partition, ObjectStorage},
 };
 
+use super::casts;
 use super::listing_table_builder::ListingTableBuilder;
+use super::lookup::LOOKUP_TABLES;
 
 // schema provider for stream based on global data
 pub struct GlobalSchemaProvider {
@@ -75,23 +77,50 @@ impl SchemaProvider for GlobalSchemaProvider {
     }
 
     fn table_names(&self) -> Vec<String> {
-        STREAM_INFO.list_streams()
+        let mut names = STREAM_INFO.list_streams();
+        let lookups = LOOKUP_TABLES.names();
+        names.extend(
+            lookups
+                .into_iter()
+                .filter(|name| !STREAM_INFO.stream_exists(name)),
+        );
+        names
     }
 
     async fn table(&self, name: &str) -> Option<Arc<dyn TableProvider>> {
-        if self.table_exist(name) {
-            Some(Arc::new(StandardTableProvider {
+        if STREAM_INFO.stream_exists(name) {
+            let table: Arc<dyn TableProvider> = Arc::new(StandardTableProvider {
                 schema: STREAM_INFO.schema(name).unwrap(),
                 stream: name.to_owned(),
+                timestamp_key: STREAM_INFO.timestamp_key(name).unwrap(),
                 url: self.storage.store_url(),
-            }))
+            });
+            // cast columns are added through a view on the stream, which
+            // datafusion inlines into the plan of the query
+            let casts = STREAM_INFO.casts(name).unwrap_or_default();
+            match casts::view_plan(name, table.clone(), &casts) {
+                Ok(Some(plan)) => match ViewTable::try_new(plan, None) {
+                    Ok(view) => Some(Arc::new(view)),
+                    Err(err) => {
+                        log::warn!("could not add cast columns to stream {name}: {err}");
+                        Some(table)
+                    }
+                },
+                Ok(None) => Some(table),
+                Err(err) => {
+                    log::warn!("could not add cast columns to stream {name}: {err}");
+                    Some(table)
+                }
+            }
         } else {
-            None
+            LOOKUP_TABLES
+                .get(name)
+                .map(|table| table as Arc<dyn TableProvider>)
         }
     }
 
     fn table_exist(&self, name: &str) -> bool {
-        STREAM_INFO.stream_exists(name)
+        STREAM_INFO.stream_exists(name) || LOOKUP_TABLES.contains(name)
     }
 }
 
@@ -100,6 +129,8 @@ struct StandardTableProvider {
     schema: SchemaRef,
     // prefix under which to find snapshot
     stream: String,
+    // column holding the event timestamp of this stream
+    timestamp_key: String,
     // url to find right instance of object store
     url: Url,
 }
@@ -114,6 +145,7 @@ async fn create_parquet_physical_plan(
     filters: &[Expr],
     limit: Option<usize>,
     state: &SessionState,
+    timestamp_key: &str,
 ) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
     let filters = if let Some(expr) = conjunction(filters.to_vec()) {
         let table_df_schema = schema.as_ref().clone().to_dfschema()?;
@@ -125,7 +157,7 @@ async fn create_parquet_physical_plan(
     };
 
     let sort_expr = PhysicalSortExpr {
-        expr: physical_plan::expressions::col(DEFAULT_TIMESTAMP_KEY, &schema)?,
+        expr: physical_plan::expressions::col(timestamp_key, &schema)?,
         options: SortOptions {
             descending: true,
             nulls_first: true,
@@ -160,6 +192,7 @@ async fn collect_from_snapshot(
     time_filters: &[PartialTimeFilter],
     object_store: Arc<dyn ObjectStore>,
     filters: &[Expr],
+    partition_columns: &[String],
     limit: Option<usize>,
 ) -> Result<Vec<catalog::manifest::File>, DataFusionError> {
     let items = snapshot.manifests(time_filters);
@@ -178,7 +211,12 @@ async fn collect_from_snapshot(
         .rev()
         .collect();
     for filter in filters {
-        manifest_files.retain(|file| !file.can_be_pruned(filter))
+        manifest_files.retain(|file| !file.can_be_pruned(filter));
+        if let Some((column, values)) = partition_values(filter) {
+            manifest_files.retain(|file| {
+                !partition::can_prune(&file.file_path, partition_columns, &column, &values)
+            });
+        }
     }
     if let Some(limit) = limit {
         let limit = limit as u64;
@@ -288,14 +326,14 @@ impl TableProvider for StandardTableProvider {
         let mut memory_exec = None;
         let mut cache_exec = None;
 
-        let time_filters = extract_primary_filter(filters);
+        let time_filters = extract_primary_filter(filters, &self.timestamp_key);
         if time_filters.is_empty() {
             return Err(DataFusionError::Plan("potentially unbounded query on time range. Table scanning requires atleast one time bound".to_string()));
         }
 
-        if include_now(filters) {
+        if include_now(filters, &self.timestamp_key) {
             if let Some(records) =
-                event::STREAM_WRITERS.recordbatches_cloned(&self.stream, &self.schema)
+                event::STREAM_WRITERS.recordbatches_cloned(&self.stream, &self.schema)?
             {
                 let reversed_mem_table = reversed_mem_table(records, self.schema.clone())?;
                 memory_exec = Some(
@@ -323,6 +361,7 @@ impl TableProvider for StandardTableProvider {
         if is_overlapping_query(&snapshot.manifest_list, &time_filters) {
             return legacy_listing_table(
                 self.stream.clone(),
+                &self.timestamp_key,
                 memory_exec,
                 glob_storage,
                 object_store,
@@ -336,8 +375,21 @@ impl TableProvider for StandardTableProvider {
             .await;
         }
 
-        let mut manifest_files =
-            collect_from_snapshot(&snapshot, &time_filters, object_store, filters, limit).await?;
+        let partition_columns = STREAM_INFO
+            .partitioning(&self.stream)
+            .ok()
+            .flatten()
+            .map(|partitioning| partitioning.columns)
+            .unwrap_or_default();
+        let mut manifest_files = collect_from_snapshot(
+            &snapshot,
+            &time_filters,
+            object_store,
+            filters,
+            &partition_columns,
+            limit,
+        )
+        .await?;
 
         if manifest_files.is_empty() {
             return final_plan(vec![memory_exec], projection, self.schema.clone());
@@ -374,6 +426,7 @@ impl TableProvider for StandardTableProvider {
                 filters,
                 limit,
                 state,
+                &self.timestamp_key,
             )
             .await?;
 
@@ -399,6 +452,7 @@ impl TableProvider for StandardTableProvider {
             filters,
             limit,
             state,
+            &self.timestamp_key,
         )
         .await?;
 
@@ -413,7 +467,7 @@ impl TableProvider for StandardTableProvider {
         &self,
         filter: &Expr,
     ) -> Result<TableProviderFilterPushDown, DataFusionError> {
-        if expr_in_boundary(filter) {
+        if expr_in_boundary(filter, &self.timestamp_key) {
             // if filter can be handled by time partiton pruning, it is exact
             Ok(TableProviderFilterPushDown::Exact)
         } else {
@@ -427,6 +481,7 @@ impl TableProvider for StandardTableProvider {
 #[allow(clippy::too_many_arguments)]
 async fn legacy_listing_table(
     stream: String,
+    timestamp_key: &str,
     mem_exec: Option<Arc<dyn ExecutionPlan>>,
     glob_storage: Arc<dyn ObjectStorage + Send>,
     object_store: Arc<dyn ObjectStore>,
@@ -440,7 +495,9 @@ async fn legacy_listing_table(
     let remote_table = ListingTableBuilder::new(stream)
         .populate_via_listing(glob_storage.clone(), object_store, time_filters)
         .and_then(|builder| async {
-            let table = builder.build(schema.clone(), |x| glob_storage.query_prefixes(x))?;
+            let table = builder.build(schema.clone(), timestamp_key, |x| {
+                glob_storage.query_prefixes(x)
+            })?;
             let res = match table {
                 Some(table) => Some(table.scan(state, projection, filters, limit).await?),
                 _ => None,
@@ -492,11 +549,11 @@ pub enum PartialTimeFilter {
 }
 
 impl PartialTimeFilter {
-    fn try_from_expr(expr: &Expr) -> Option<Self> {
+    fn try_from_expr(expr: &Expr, timestamp_key: &str) -> Option<Self> {
         let Expr::BinaryExpr(binexpr) = expr else {
             return None;
         };
-        let (op, time) = extract_timestamp_bound(binexpr)?;
+        let (op, time) = extract_timestamp_bound(binexpr, timestamp_key)?;
         let value = match op {
             Operator::Gt => PartialTimeFilter::Low(Bound::Excluded(time)),
             Operator::GtEq => PartialTimeFilter::Low(Bound::Included(time)),
@@ -566,14 +623,14 @@ fn is_overlapping_query(
         .all(|filter| filter.is_greater_than(&first_entry_upper_bound.naive_utc()))
 }
 
-fn include_now(filters: &[Expr]) -> bool {
+fn include_now(filters: &[Expr], timestamp_key: &str) -> bool {
     let current_minute = Utc::now()
         .with_second(0)
         .and_then(|x| x.with_nanosecond(0))
         .expect("zeroed value is valid")
         .naive_utc();
 
-    let time_filters = extract_primary_filter(filters);
+    let time_filters = extract_primary_filter(filters, timestamp_key);
 
     let upper_bound_matches = time_filters.iter().any(|filter| match filter {
         PartialTimeFilter::High(Bound::Excluded(time))
@@ -594,11 +651,11 @@ fn include_now(filters: &[Expr]) -> bool {
     !has_upper_bound
 }
 
-fn expr_in_boundary(filter: &Expr) -> bool {
+fn expr_in_boundary(filter: &Expr, timestamp_key: &str) -> bool {
     let Expr::BinaryExpr(binexpr) = filter else {
         return false;
     };
-    let Some((op, time)) = extract_timestamp_bound(binexpr) else {
+    let Some((op, time)) = extract_timestamp_bound(binexpr, timestamp_key) else {
         return false;
     };
 
@@ -625,9 +682,11 @@ fn extract_from_lit(expr: &Expr) -> Option<NaiveDateTime> {
     }
 }
 
-fn extract_timestamp_bound(binexpr: &BinaryExpr) -> Option<(Operator, NaiveDateTime)> {
-    if matches!(&*binexpr.left, Expr::Column(Column { name, .. }) if name == DEFAULT_TIMESTAMP_KEY)
-    {
+fn extract_timestamp_bound(
+    binexpr: &BinaryExpr,
+    timestamp_key: &str,
+) -> Option<(Operator, NaiveDateTime)> {
+    if matches!(&*binexpr.left, Expr::Column(Column { name, .. }) if name == timestamp_key) {
         let time = extract_from_lit(&binexpr.right)?;
         Some((binexpr.op, time))
     } else {
@@ -658,11 +717,11 @@ async fn collect_manifest_files(
 }
 
 // extract start time and end time from filter preficate
-fn extract_primary_filter(filters: &[Expr]) -> Vec<PartialTimeFilter> {
+fn extract_primary_filter(filters: &[Expr], timestamp_key: &str) -> Vec<PartialTimeFilter> {
     let mut time_filters = Vec::new();
     filters.iter().for_each(|expr| {
         let _ = expr.apply(&mut |expr| {
-            let time = PartialTimeFilter::try_from_expr(expr);
+            let time = PartialTimeFilter::try_from_expr(expr, timestamp_key);
             if let Some(time) = time {
                 time_filters.push(time);
                 Ok(VisitRecursion::Stop)
@@ -702,6 +761,12 @@ trait ManifestExt: ManifestFile {
             Some((expr.op, value))
         }
 
+        if let (Some(index), Some(ranges)) = (self.severity(), severity_ranges(partial_filter)) {
+            if !ranges.iter().any(|(min, max)| index.overlaps(*min, *max)) {
+                return true;
+            }
+        }
+
         let Some(col) = self.find_matching_column(partial_filter) else {
             return false;
         };
@@ -724,6 +789,94 @@ trait ManifestExt: ManifestFile {
 
 impl<T: ManifestFile> ManifestExt for T {}
 
+// Column and values of an equality or IN list filter, as written in partition directories
+fn partition_values(expr: &Expr) -> Option<(String, Vec<String>)> {
+    fn value(expr: &Expr) -> Option<String> {
+        let Expr::Literal(value) = expr else {
+            return None;
+        };
+        match cast_or_none(value)? {
+            CastRes::Bool(value) => Some(value.to_string()),
+            CastRes::Int(value) => Some(value.to_string()),
+            CastRes::String(value) => Some(value.to_string()),
+            CastRes::Float(_) => None,
+        }
+    }
+
+    match expr {
+        Expr::BinaryExpr(BinaryExpr {
+            left,
+            op: Operator::Eq,
+            right,
+        }) => match left.as_ref() {
+            Expr::Column(col) => Some((col.name.clone(), vec![value(right)?])),
+            _ => None,
+        },
+        Expr::InList(in_list) if !in_list.negated => match in_list.expr.as_ref() {
+            Expr::Column(col) => Some((
+                col.name.clone(),
+                in_list.list.iter().map(value).collect::<Option<_>>()?,
+            )),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Ranges of severity numbers a filter on the severity_number column can match,
+// None if the filter is not on severity_number alone
+fn severity_ranges(expr: &Expr) -> Option<Vec<(i64, i64)>> {
+    fn is_severity_column(expr: &Expr) -> bool {
+        matches!(expr, Expr::Column(col) if col.name == event::severity::SEVERITY_NUMBER_KEY)
+    }
+
+    fn severity_value(expr: &Expr) -> Option<i64> {
+        let Expr::Literal(value) = expr else {
+            return None;
+        };
+        match cast_or_none(value)? {
+            CastRes::Int(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    match expr {
+        Expr::BinaryExpr(BinaryExpr {
+            left,
+            op: Operator::Or,
+            right,
+        }) => {
+            let mut ranges = severity_ranges(left)?;
+            ranges.extend(severity_ranges(right)?);
+            Some(ranges)
+        }
+        Expr::BinaryExpr(BinaryExpr { left, op, right }) if is_severity_column(left) => {
+            let value = severity_value(right)?;
+            let range = match op {
+                Operator::Eq => (value, value),
+                Operator::Lt => (i64::MIN, value.saturating_sub(1)),
+                Operator::LtEq => (i64::MIN, value),
+                Operator::Gt => (value.saturating_add(1), i64::MAX),
+                Operator::GtEq => (value, i64::MAX),
+                _ => return None,
+            };
+            Some(vec![range])
+        }
+        Expr::Between(between) if !between.negated && is_severity_column(&between.expr) => {
+            Some(vec![(
+                severity_value(&between.low)?,
+                severity_value(&between.high)?,
+            )])
+        }
+        Expr::InList(in_list) if !in_list.negated && is_severity_column(&in_list.expr) => in_list
+            .list
+            .iter()
+            .map(|value| severity_value(value).map(|value| (value, value)))
+            .collect(),
+        _ => None,
+    }
+}
+
 enum CastRes<'a> {
     Bool(bool),
     Int(i64),
@@ -785,10 +938,12 @@ mod tests {
     use std::ops::Add;
 
     use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+    use datafusion::prelude::{col, lit};
 
+    use crate::catalog::manifest::{File, SeverityIndex};
     use crate::catalog::snapshot::ManifestItem;
 
-    use super::{is_overlapping_query, PartialTimeFilter};
+    use super::{is_overlapping_query, ManifestExt, PartialTimeFilter};
 
     fn datetime_min(year: i32, month: u32, day: u32) -> DateTime<Utc> {
         NaiveDate::from_ymd_opt(year, month, day)
@@ -862,4 +1017,25 @@ mod tests {
 
         assert!(!res)
     }
+
+    #[test]
+    fn severity_index_prunes_files() {
+        // a file with only INFO rows
+        let file = File {
+            severity: Some(SeverityIndex { min: 9, max: 12 }),
+            ..File::default()
+        };
+        let severity = || col("severity_number");
+
+        assert!(file.can_be_pruned(&severity().gt_eq(lit(17i64))));
+        assert!(file.can_be_pruned(&severity().in_list(vec![lit(17i64), lit(21i64)], false)));
+        assert!(file.can_be_pruned(
+            &severity()
+                .between(lit(13i64), lit(16i64))
+                .or(severity().between(lit(21i64), lit(24i64)))
+        ));
+        assert!(!file.can_be_pruned(&severity().in_list(vec![lit(9i64), lit(17i64)], false)));
+        assert!(!file.can_be_pruned(&severity().in_list(vec![lit(17i64)], true)));
+        assert!(!file.can_be_pruned(&severity().lt(lit(10i64)).or(col("level").eq(lit("ERROR")))));
+    }
 }
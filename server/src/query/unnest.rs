@@ -0,0 +1,274 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+// `unnest` expands every element of a list column into its own row.
+//
+//   SELECT host, unnest(tags) AS tag FROM app
+//
+// returns one row per element of `tags`, with the other columns repeated.
+// Empty and null lists produce a single row with a null element.
+// unnest can only be used in the select list, to filter or aggregate on the
+// elements use a subquery
+//
+//   SELECT tag, count(*) FROM (SELECT unnest(tags) AS tag FROM app) GROUP BY tag
+//
+// The SQL planner has no notion of unnest, so it is registered as a placeholder
+// function which the `UnnestRule` analyzer rule replaces with an unnest node in the plan.
+
+use std::sync::Arc;
+
+use datafusion::arrow::datatypes::DataType;
+use datafusion::common::tree_node::{Transformed, TreeNode, VisitRecursion};
+use datafusion::common::{plan_err, Column, DataFusionError, Result};
+use datafusion::config::ConfigOptions;
+use datafusion::logical_expr::{
+    LogicalPlan, LogicalPlanBuilder, ReturnTypeFunction, ScalarFunctionImplementation, ScalarUDF,
+    Signature, Volatility,
+};
+use datafusion::optimizer::analyzer::AnalyzerRule;
+use datafusion::prelude::Expr;
+
+pub const UNNEST: &str = "unnest";
+
+pub fn unnest_udf() -> ScalarUDF {
+    let return_type: ReturnTypeFunction = Arc::new(|args: &[DataType]| match &args[0] {
+        DataType::List(field) | DataType::LargeList(field) | DataType::FixedSizeList(field, _) => {
+            Ok(Arc::new(field.data_type().clone()))
+        }
+        data_type => plan_err!("unnest expects a list argument, got {data_type}"),
+    });
+    let fun: ScalarFunctionImplementation = Arc::new(|_| {
+        Err(DataFusionError::Plan(
+            "unnest is only supported in the select list".to_string(),
+        ))
+    });
+
+    ScalarUDF::new(
+        UNNEST,
+        &Signature::any(1, Volatility::Immutable),
+        &return_type,
+        &fun,
+    )
+}
+
+// Rewrites a projection containing unnest(expr) into
+// Projection(unnest(expr) replaced by its column)
+//   Unnest(column)
+//     Projection(input columns, expr AS column)
+pub struct UnnestRule;
+
+impl AnalyzerRule for UnnestRule {
+    fn analyze(&self, plan: LogicalPlan, _: &ConfigOptions) -> Result<LogicalPlan> {
+        plan.transform_up(&rewrite_projection)
+    }
+
+    fn name(&self) -> &str {
+        "parseable_unnest"
+    }
+}
+
+fn rewrite_projection(plan: LogicalPlan) -> Result<Transformed<LogicalPlan>> {
+    let LogicalPlan::Projection(projection) = &plan else {
+        return Ok(Transformed::No(plan));
+    };
+
+    let mut args: Vec<Expr> = Vec::new();
+    for expr in &projection.expr {
+        expr.apply(&mut |expr| {
+            if let Some(arg) = unnest_arg(expr) {
+                if contains_unnest(arg)? {
+                    return plan_err!("nested unnest is not supported");
+                }
+                if !args.contains(arg) {
+                    args.push(arg.clone());
+                }
+                return Ok(VisitRecursion::Skip);
+            }
+            Ok(VisitRecursion::Continue)
+        })?;
+    }
+
+    if args.is_empty() {
+        return Ok(Transformed::No(plan));
+    }
+
+    let input = projection.input.as_ref();
+    let mut columns: Vec<Expr> = input
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| Expr::Column(field.qualified_column()))
+        .collect();
+    columns.extend(
+        args.iter()
+            .enumerate()
+            .map(|(index, arg)| arg.clone().alias(unnest_column(index))),
+    );
+
+    let mut builder = LogicalPlanBuilder::from(input.clone()).project(columns)?;
+    for index in 0..args.len() {
+        builder = builder.unnest_column(unnest_column(index))?;
+    }
+
+    let exprs = projection
+        .expr
+        .iter()
+        .map(|expr| {
+            let rewritten = expr.clone().transform_up(&|expr| {
+                let Some(index) =
+                    unnest_arg(&expr).and_then(|arg| args.iter().position(|x| x == arg))
+                else {
+                    return Ok(Transformed::No(expr));
+                };
+                Ok(Transformed::Yes(Expr::Column(Column::from_name(
+                    unnest_column(index),
+                ))))
+            })?;
+            match expr {
+                Expr::Alias(_) => Ok(rewritten),
+                expr if rewritten != *expr => Ok(rewritten.alias(expr.display_name()?)),
+                _ => Ok(rewritten),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Transformed::Yes(builder.project(exprs)?.build()?))
+}
+
+fn unnest_arg(expr: &Expr) -> Option<&Expr> {
+    match expr {
+        Expr::ScalarUDF(udf) if udf.fun.name == UNNEST => udf.args.first(),
+        _ => None,
+    }
+}
+
+fn contains_unnest(expr: &Expr) -> Result<bool> {
+    let mut found = false;
+    expr.apply(&mut |expr| {
+        found = unnest_arg(expr).is_some();
+        Ok(if found {
+            VisitRecursion::Stop
+        } else {
+            VisitRecursion::Continue
+        })
+    })?;
+    Ok(found)
+}
+
+fn unnest_column(index: usize) -> String {
+    format!("__unnest_{index}")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use datafusion::arrow::array::{Int64Array, ListArray, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Int64Type, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::datasource::MemTable;
+    use datafusion::execution::context::SessionState;
+    use datafusion::prelude::{SessionConfig, SessionContext};
+
+    use super::{unnest_udf, UnnestRule};
+
+    fn context() -> SessionContext {
+        let state = SessionState::new_with_config_rt(SessionConfig::default(), Default::default())
+            .add_analyzer_rule(Arc::new(UnnestRule));
+        let ctx = SessionContext::new_with_state(state);
+        ctx.register_udf(unnest_udf());
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new(
+                "codes",
+                DataType::List(Arc::new(Field::new("item", DataType::Int64, true))),
+                true,
+            ),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(vec!["a", "b", "c"])),
+                Arc::new(ListArray::from_iter_primitive::<Int64Type, _, _>(vec![
+                    Some(vec![Some(1), Some(2)]),
+                    Some(vec![Some(3)]),
+                    None,
+                ])),
+            ],
+        )
+        .unwrap();
+        let table = MemTable::try_new(schema, vec![vec![batch]]).unwrap();
+        ctx.register_table("app", Arc::new(table)).unwrap();
+        ctx
+    }
+
+    async fn query(sql: &str) -> Vec<RecordBatch> {
+        context().sql(sql).await.unwrap().collect().await.unwrap()
+    }
+
+    #[actix_web::test]
+    async fn unnest_list_column_into_rows() {
+        let batches =
+            query("SELECT host, unnest(codes) AS code FROM app WHERE host != 'c' ORDER BY code")
+                .await;
+        let batch = &batches[0];
+
+        assert_eq!(batch.schema().field(1).name(), "code");
+        assert_eq!(
+            batch
+                .column(0)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap(),
+            &StringArray::from(vec!["a", "a", "b"])
+        );
+        assert_eq!(
+            batch
+                .column(1)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap(),
+            &Int64Array::from(vec![1, 2, 3])
+        );
+    }
+
+    #[actix_web::test]
+    async fn unnest_in_subquery() {
+        let batches =
+            query("SELECT sum(code) AS total FROM (SELECT unnest(codes) AS code FROM app)").await;
+
+        assert_eq!(
+            batches[0]
+                .column(0)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap(),
+            &Int64Array::from(vec![6])
+        );
+    }
+
+    #[actix_web::test]
+    async fn unnest_outside_select_list_is_err() {
+        let df = context()
+            .sql("SELECT host FROM app WHERE unnest(codes) > 1")
+            .await
+            .unwrap();
+        assert!(df.collect().await.is_err());
+    }
+}
@@ -0,0 +1,102 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use datafusion::sql::sqlparser::ast::Statement;
+use datafusion::sql::sqlparser::dialect::GenericDialect;
+use datafusion::sql::sqlparser::parser::Parser;
+
+// a view is the query analysts start from, not a place for whole reports
+const MAX_QUERY_LEN: usize = 16 * 1024;
+
+fn default_start_time() -> String {
+    "1h".to_string()
+}
+
+// Query the console runs when a stream is opened, so that everyone looking at a
+// stream starts from the same view of it. The view is only stored with the
+// stream, it is run like any other query over the last `startTime` up to now.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DefaultView {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub query: String,
+    // how far back the query looks, like `15m`
+    #[serde(default = "default_start_time")]
+    pub start_time: String,
+    // interval of the time buckets of aggregate queries, like `5m`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column_order: Option<Vec<String>>,
+}
+
+impl DefaultView {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.query.trim().is_empty() {
+            return Err("query is empty".to_string());
+        }
+        if self.query.len() > MAX_QUERY_LEN {
+            return Err(format!("query is longer than {MAX_QUERY_LEN} bytes"));
+        }
+        let statements =
+            Parser::parse_sql(&GenericDialect {}, &self.query).map_err(|err| err.to_string())?;
+        match statements.as_slice() {
+            [Statement::Query(_)] => (),
+            _ => return Err("a view is a single SELECT query".to_string()),
+        }
+        humantime::parse_duration(&self.start_time)
+            .map_err(|err| format!("invalid start time {}: {err}", self.start_time))?;
+        if let Some(bucket) = &self.bucket {
+            humantime::parse_duration(bucket)
+                .map_err(|err| format!("invalid bucket interval {bucket}: {err}"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::DefaultView;
+
+    #[test]
+    fn views_are_validated() {
+        let view: DefaultView = serde_json::from_value(json!({
+            "name": "errors",
+            "query": "SELECT * FROM app WHERE level = 'error'"
+        }))
+        .unwrap();
+        assert_eq!(view.start_time, "1h");
+        assert!(view.validate().is_ok());
+
+        let invalid = [
+            json!({"query": " "}),
+            json!({"query": "SELECT * FROM"}),
+            json!({"query": "DROP TABLE app"}),
+            json!({"query": "SELECT 1; SELECT 2"}),
+            json!({"query": "SELECT * FROM app", "startTime": "yesterday"}),
+            json!({"query": "SELECT * FROM app", "bucket": "5x"}),
+        ];
+        for view in invalid {
+            let view: DefaultView = serde_json::from_value(view).unwrap();
+            assert!(view.validate().is_err(), "{view:?}");
+        }
+    }
+}
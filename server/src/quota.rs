@@ -0,0 +1,228 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use arrow_array::{BooleanArray, RecordBatch};
+use arrow_schema::ArrowError;
+use arrow_select::filter::filter_record_batch;
+use chrono::{DateTime, Days, NaiveTime, Utc};
+use rand::Rng;
+use serde_json::Value;
+
+use crate::metrics::{EVENTS_INGESTED_DATE, EVENTS_INGESTED_SIZE_DATE};
+
+// origin formats ingested events are counted under
+const ORIGINS: [&str; 2] = ["json", "arrow"];
+
+// What to do with events ingested after the quota of the day is used up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Overflow {
+    // reject with 429 Too Many Requests
+    #[default]
+    Reject,
+    // keep `sample_percent` percent of the events
+    Sample,
+}
+
+// Per stream daily ingestion quota. Usage is counted per UTC day, the same
+// boundary as the date partitions of a stream, from the date labelled
+// ingestion metrics. These are kept in memory, so usage restarts from zero
+// when the server restarts.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IngestQuota {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_events: Option<u64>,
+    #[serde(default)]
+    pub overflow: Overflow,
+    #[serde(default = "default_sample_percent")]
+    pub sample_percent: u8,
+}
+
+fn default_sample_percent() -> u8 {
+    10
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub bytes: u64,
+    pub events: u64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaStatus {
+    #[serde(flatten)]
+    pub quota: IngestQuota,
+    pub used_bytes: u64,
+    pub used_events: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining_events: Option<u64>,
+    pub resets_at: DateTime<Utc>,
+}
+
+impl IngestQuota {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_bytes.is_none() && self.max_events.is_none() {
+            return Err("quota needs at least one of maxBytes or maxEvents".to_string());
+        }
+        if !(1..=100).contains(&self.sample_percent) {
+            return Err("samplePercent must be between 1 and 100".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn exceeded(&self, usage: Usage) -> bool {
+        self.max_bytes.is_some_and(|max| usage.bytes >= max)
+            || self.max_events.is_some_and(|max| usage.events >= max)
+    }
+
+    pub fn status(&self, usage: Usage) -> QuotaStatus {
+        let tomorrow = Utc::now().date_naive() + Days::new(1);
+        QuotaStatus {
+            quota: self.clone(),
+            used_bytes: usage.bytes,
+            used_events: usage.events,
+            remaining_bytes: self.max_bytes.map(|max| max.saturating_sub(usage.bytes)),
+            remaining_events: self.max_events.map(|max| max.saturating_sub(usage.events)),
+            resets_at: tomorrow.and_time(NaiveTime::MIN).and_utc(),
+        }
+    }
+
+    /// Keep a sample of the events of an ingest body.
+    /// Returns None if no event is kept.
+    // Keep a sample of the rows of a batch, like `sample` does for JSON events
+    pub fn sample_rows(&self, rb: &RecordBatch) -> Result<RecordBatch, ArrowError> {
+        let probability = f64::from(self.sample_percent) / 100.;
+        let mut rng = rand::thread_rng();
+        let keep = (0..rb.num_rows())
+            .map(|_| Some(rng.gen_bool(probability)))
+            .collect::<BooleanArray>();
+        filter_record_batch(rb, &keep)
+    }
+
+    pub fn sample(&self, body: Value) -> Option<Value> {
+        let probability = f64::from(self.sample_percent) / 100.;
+        let mut rng = rand::thread_rng();
+        match body {
+            Value::Array(mut events) => {
+                events.retain(|_| rng.gen_bool(probability));
+                (!events.is_empty()).then_some(Value::Array(events))
+            }
+            event => rng.gen_bool(probability).then_some(event),
+        }
+    }
+}
+
+pub fn today() -> String {
+    Utc::now().date_naive().to_string()
+}
+
+// ingestion of the stream so far today
+pub fn usage(stream_name: &str) -> Usage {
+    let date = today();
+    let mut usage = Usage::default();
+    for origin in ORIGINS {
+        let labels = [stream_name, origin, &date];
+        usage.bytes += EVENTS_INGESTED_SIZE_DATE
+            .get_metric_with_label_values(&labels)
+            .map(|metric| metric.get())
+            .unwrap_or_default();
+        usage.events += EVENTS_INGESTED_DATE
+            .get_metric_with_label_values(&labels)
+            .map(|metric| metric.get())
+            .unwrap_or_default();
+    }
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use serde_json::json;
+
+    use super::{IngestQuota, Overflow, Usage};
+
+    fn quota(sample_percent: u8) -> IngestQuota {
+        IngestQuota {
+            max_bytes: Some(1000),
+            max_events: None,
+            overflow: Overflow::Sample,
+            sample_percent,
+        }
+    }
+
+    #[test]
+    fn quota_exceeded_on_any_limit() {
+        let quota = quota(10);
+        assert!(!quota.exceeded(Usage {
+            bytes: 999,
+            events: 1_000_000
+        }));
+        assert!(quota.exceeded(Usage {
+            bytes: 1000,
+            events: 0
+        }));
+
+        let status = quota.status(Usage {
+            bytes: 1200,
+            events: 3,
+        });
+        assert_eq!(status.remaining_bytes, Some(0));
+        assert_eq!(status.remaining_events, None);
+    }
+
+    #[test]
+    fn sampling_keeps_all_or_some() {
+        let events = json!([{"a": 1}, {"a": 2}, {"a": 3}]);
+        assert_eq!(quota(100).sample(events.clone()), Some(events));
+
+        let many = json!((0..1000).map(|a| json!({ "a": a })).collect::<Vec<_>>());
+        let kept = quota(10).sample(many).unwrap();
+        assert!(kept.as_array().unwrap().len() < 500);
+    }
+
+    #[test]
+    fn sampling_keeps_some_rows() {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let rb = RecordBatch::try_new(
+            schema,
+            vec![Arc::new(Int64Array::from_iter_values(0..1000))],
+        )
+        .unwrap();
+        assert_eq!(quota(100).sample_rows(&rb).unwrap().num_rows(), 1000);
+        assert!(quota(10).sample_rows(&rb).unwrap().num_rows() < 500);
+    }
+
+    #[test]
+    fn quota_without_limit_is_invalid() {
+        let mut quota = quota(10);
+        quota.max_bytes = None;
+        assert!(quota.validate().is_err());
+        quota.max_events = Some(10);
+        quota.sample_percent = 0;
+        assert!(quota.validate().is_err());
+    }
+}
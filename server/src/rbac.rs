@@ -16,6 +16,7 @@
  *
  */
 
+pub mod ingest_key;
 pub mod map;
 pub mod role;
 pub mod user;
@@ -30,7 +31,7 @@ use crate::rbac::role::Action;
 use crate::rbac::user::User;
 
 use self::map::SessionKey;
-use self::role::{Permission, RoleBuilder};
+use self::role::{Permission, RoleBuilder, StreamAccess};
 use self::user::UserType;
 
 pub enum Response {
@@ -100,6 +101,34 @@ impl Users {
         sessions().get(session).cloned().unwrap_or_default()
     }
 
+    // what the user of this session may see of the stream, None if it may not query it
+    pub fn stream_access(&self, session: &SessionKey, stream: &str) -> Option<StreamAccess> {
+        role::stream_access(&self.get_permissions(session), stream)
+    }
+
+    // Roles of the user of this session allowing to query any of the streams
+    // without seeing raw trace and span ids. These are the tenants the ids
+    // are hashed for.
+    pub fn id_restricting_roles(&self, session: &SessionKey, streams: &[String]) -> Vec<String> {
+        let Some(username) = self.get_username(session) else {
+            return Vec::new();
+        };
+        self.get_role(&username)
+            .into_iter()
+            .filter(|role| {
+                let permissions = roles_to_permission(vec![role.clone()]);
+                streams.iter().any(|stream| {
+                    role::stream_access(&permissions, stream).is_some_and(|access| !access.raw_ids)
+                })
+            })
+            .sorted()
+            .collect()
+    }
+
+    pub fn get_username(&self, session: &SessionKey) -> Option<String> {
+        sessions().get_username(session).cloned()
+    }
+
     pub fn session_exists(&self, session: &SessionKey) -> bool {
         sessions().get(session).is_some()
     }
@@ -0,0 +1,85 @@
+/*
+* Parseable Server (C) 2022 - 2024 Parseable, Inc.
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU Affero General Public License as
+* published by the Free Software Foundation, either version 3 of the
+* License, or (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU Affero General Public License for more details.
+*
+* You should have received a copy of the GNU Affero General Public License
+* along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*
+*
+*/
+use chrono::{DateTime, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use sha2::{Digest, Sha256};
+use ulid::Ulid;
+
+use crate::metadata::STREAM_INFO;
+
+// Ingest keys let a log source ingest into a single stream without a user account.
+// Once a stream has ingest keys, every ingest request for it has to carry one of
+// them in the X-P-Ingest-Key header, requests without a valid key are rejected.
+// Only a hash of the key is stored, the key itself is returned once when created.
+// Keys are random and long, so a plain SHA-256 is enough and cheap to check on
+// every request, unlike the salted argon2 hash used for user passwords.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IngestKey {
+    pub id: String,
+    pub hash: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl IngestKey {
+    // generate a new key, returns the stored form along with the key itself
+    pub fn generate() -> (Self, String) {
+        let key = Alphanumeric.sample_string(&mut rand::thread_rng(), 40);
+        let ingest_key = Self {
+            id: Ulid::new().to_string(),
+            hash: hash(&key),
+            created_at: Utc::now(),
+        };
+        (ingest_key, key)
+    }
+}
+
+fn hash(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+pub fn verify(keys: &[IngestKey], key: &str) -> bool {
+    let hash = hash(key.trim());
+    keys.iter().any(|ingest_key| ingest_key.hash == hash)
+}
+
+// None if the stream does not use ingest keys, otherwise whether the key is valid for it
+pub fn authorize(stream_name: &str, key: Option<&str>) -> Option<bool> {
+    let keys = STREAM_INFO.ingest_keys(stream_name).ok()?;
+    if keys.is_empty() {
+        return None;
+    }
+    Some(key.is_some_and(|key| verify(&keys, key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify, IngestKey};
+
+    #[test]
+    fn verify_generated_key() {
+        let (ingest_key, key) = IngestKey::generate();
+        let (other, other_key) = IngestKey::generate();
+
+        assert_ne!(ingest_key.hash, key);
+        assert!(verify(&[other.clone(), ingest_key.clone()], &key));
+        assert!(!verify(&[ingest_key], &other_key));
+        assert!(!verify(&[other], ""));
+    }
+}
@@ -194,6 +194,11 @@ impl Sessions {
         self.active_sessions.get(key).map(|(_, perms)| perms)
     }
 
+    // get user related to this session
+    pub fn get_username(&self, key: &SessionKey) -> Option<&String> {
+        self.active_sessions.get(key).map(|(username, _)| username)
+    }
+
     // returns None if user is not in the map
     // Otherwise returns Some(is_authenticated)
     pub fn check_auth(
@@ -209,7 +214,8 @@ impl Sessions {
                     // if any action is ALL then we we authorize
                     Permission::Unit(action) => action == required_action || action == Action::All,
                     Permission::Stream(action, ref stream)
-                    | Permission::StreamWithTag(action, ref stream, _) => {
+                    | Permission::StreamWithTag(action, ref stream, _)
+                    | Permission::StreamWithRowFilter(action, ref stream, _, _) => {
                         let ok_stream = if let Some(context_stream) = context_stream {
                             stream == context_stream || stream == "*"
                         } else {
@@ -22,15 +22,81 @@
 pub enum Action {
     Ingest,
     Query,
+    QueryRawIds,
     CreateStream,
     ListStream,
     GetSchema,
     GetStats,
+    GetStreamInfo,
     DeleteStream,
+    DeletePartition,
     GetRetention,
     PutRetention,
     GetCacheEnabled,
     PutCacheEnabled,
+    GetEncryption,
+    PutEncryption,
+    GetLabels,
+    PutLabels,
+    GetLogPattern,
+    PutLogPattern,
+    GetTimestampKey,
+    PutTimestampKey,
+    GetTimeFields,
+    PutTimeFields,
+    GetColumnOrder,
+    PutColumnOrder,
+    GetCasts,
+    PutCasts,
+    GetSeverityMapping,
+    PutSeverityMapping,
+    GetUnknownSeverity,
+    PutUnknownSeverity,
+    GetBodyConfig,
+    PutBodyConfig,
+    GetShadow,
+    PutShadow,
+    GetRouting,
+    PutRouting,
+    GetRebuild,
+    Rebuild,
+    LockSchema,
+    GetQuota,
+    PutQuota,
+    GetSampling,
+    PutSampling,
+    GetDedup,
+    PutDedup,
+    GetMaxFileSize,
+    PutMaxFileSize,
+    GetAttributeMap,
+    PutAttributeMap,
+    GetNumberMode,
+    PutNumberMode,
+    GetTypeConflict,
+    PutTypeConflict,
+    GetAttributeFilter,
+    PutAttributeFilter,
+    GetIpMask,
+    PutIpMask,
+    GetClockSkew,
+    PutClockSkew,
+    GetDefaultView,
+    PutDefaultView,
+    GetRawPayload,
+    PutRawPayload,
+    GetStreamBundle,
+    GetKeySanitization,
+    PutKeySanitization,
+    GetCompression,
+    PutCompression,
+    GetStagingCompression,
+    PutStagingCompression,
+    GetPartitioning,
+    PutPartitioning,
+    CreateIngestKey,
+    ListIngestKey,
+    DeleteIngestKey,
     PutAlert,
     GetAlert,
     PutUser,
@@ -41,9 +107,17 @@ pub enum Action {
     PutRole,
     GetRole,
     DeleteRole,
+    PutTemplate,
+    GetTemplate,
+    ListTemplate,
+    DeleteTemplate,
+    PutLookup,
+    ListLookup,
+    DeleteLookup,
     ListRole,
     GetAbout,
     QueryLLM,
+    GetQueryProfile,
     All,
 }
 
@@ -52,15 +126,79 @@ pub enum Permission {
     Unit(Action),
     Stream(Action, String),
     StreamWithTag(Action, String, Option<String>),
+    StreamWithRowFilter(Action, String, Option<String>, RowFilter),
     SelfUser,
 }
 
+// Restricts the rows of a stream visible to a role to those where `column` is `value`.
+// The filter is added to every query of the role right above the table scan.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct RowFilter {
+    pub column: String,
+    pub value: String,
+}
+
+// What the permissions of a user allow to see of a stream when querying it,
+// from a query, the live tail or any other way of reading its events
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StreamAccess {
+    pub tags: Vec<String>,
+    // rows visible to the user, all of them if none
+    pub row_filters: Option<Vec<RowFilter>>,
+    pub raw_ids: bool,
+}
+
+// Access to the events of a stream, None if the permissions don't allow querying it.
+// Rows are only restricted if every permission allowing the query restricts them.
+pub fn stream_access(permissions: &[Permission], stream: &str) -> Option<StreamAccess> {
+    let mut authorized = false;
+    let mut access = StreamAccess::default();
+    let mut row_filters = Vec::new();
+    let mut all_rows = false;
+    for permission in permissions {
+        match permission {
+            Permission::Stream(Action::All, _) => {
+                return Some(StreamAccess {
+                    tags: Vec::new(),
+                    row_filters: None,
+                    raw_ids: true,
+                })
+            }
+            Permission::Stream(Action::QueryRawIds, allowed)
+                if allowed == stream || allowed == "*" =>
+            {
+                access.raw_ids = true;
+            }
+            Permission::StreamWithTag(Action::Query, allowed, tag)
+                if allowed == stream || allowed == "*" =>
+            {
+                authorized = true;
+                all_rows = true;
+                access.tags.extend(tag.clone());
+            }
+            Permission::StreamWithRowFilter(Action::Query, allowed, tag, row_filter)
+                if allowed == stream || allowed == "*" =>
+            {
+                authorized = true;
+                row_filters.push(row_filter.clone());
+                access.tags.extend(tag.clone());
+            }
+            _ => (),
+        }
+    }
+    if !all_rows && !row_filters.is_empty() {
+        access.row_filters = Some(row_filters);
+    }
+    authorized.then_some(access)
+}
+
 // Currently Roles are tied to one stream
 #[derive(Debug, Default)]
 pub struct RoleBuilder {
     actions: Vec<Action>,
     stream: Option<String>,
     tag: Option<String>,
+    row_filter: Option<RowFilter>,
 }
 
 // R x P
@@ -75,15 +213,28 @@ impl RoleBuilder {
         self
     }
 
+    pub fn with_row_filter(mut self, row_filter: RowFilter) -> Self {
+        self.row_filter = Some(row_filter);
+        self
+    }
+
     pub fn build(self) -> Vec<Permission> {
         let mut perms = Vec::new();
         for action in self.actions {
             let perm = match action {
-                Action::Query => Permission::StreamWithTag(
-                    action,
-                    self.stream.clone().unwrap(),
-                    self.tag.clone(),
-                ),
+                Action::Query => match self.row_filter.clone() {
+                    Some(row_filter) => Permission::StreamWithRowFilter(
+                        action,
+                        self.stream.clone().unwrap(),
+                        self.tag.clone(),
+                        row_filter,
+                    ),
+                    None => Permission::StreamWithTag(
+                        action,
+                        self.stream.clone().unwrap(),
+                        self.tag.clone(),
+                    ),
+                },
                 Action::PutUser
                 | Action::ListUser
                 | Action::PutUserRoles
@@ -91,20 +242,94 @@ impl RoleBuilder {
                 | Action::DeleteUser
                 | Action::GetAbout
                 | Action::QueryLLM
+                | Action::GetQueryProfile
                 | Action::PutRole
                 | Action::GetRole
                 | Action::DeleteRole
+                | Action::PutTemplate
+                | Action::GetTemplate
+                | Action::ListTemplate
+                | Action::DeleteTemplate
+                | Action::PutLookup
+                | Action::ListLookup
+                | Action::DeleteLookup
                 | Action::ListRole
                 | Action::CreateStream
                 | Action::DeleteStream
                 | Action::ListStream => Permission::Unit(action),
                 Action::Ingest
+                | Action::QueryRawIds
+                | Action::DeletePartition
                 | Action::GetSchema
                 | Action::GetStats
+                | Action::GetStreamInfo
                 | Action::GetRetention
                 | Action::PutRetention
                 | Action::GetCacheEnabled
                 | Action::PutCacheEnabled
+                | Action::GetEncryption
+                | Action::PutEncryption
+                | Action::GetLabels
+                | Action::PutLabels
+                | Action::GetLogPattern
+                | Action::PutLogPattern
+                | Action::GetTimestampKey
+                | Action::PutTimestampKey
+                | Action::GetTimeFields
+                | Action::PutTimeFields
+                | Action::GetColumnOrder
+                | Action::PutColumnOrder
+                | Action::GetCasts
+                | Action::PutCasts
+                | Action::GetSeverityMapping
+                | Action::PutSeverityMapping
+                | Action::GetUnknownSeverity
+                | Action::PutUnknownSeverity
+                | Action::GetBodyConfig
+                | Action::PutBodyConfig
+                | Action::GetShadow
+                | Action::PutShadow
+                | Action::GetRouting
+                | Action::PutRouting
+                | Action::GetRebuild
+                | Action::Rebuild
+                | Action::LockSchema
+                | Action::GetQuota
+                | Action::PutQuota
+                | Action::GetSampling
+                | Action::PutSampling
+                | Action::GetDedup
+                | Action::PutDedup
+                | Action::GetMaxFileSize
+                | Action::PutMaxFileSize
+                | Action::GetAttributeMap
+                | Action::PutAttributeMap
+                | Action::GetNumberMode
+                | Action::PutNumberMode
+                | Action::GetTypeConflict
+                | Action::PutTypeConflict
+                | Action::GetAttributeFilter
+                | Action::PutAttributeFilter
+                | Action::GetIpMask
+                | Action::PutIpMask
+                | Action::GetClockSkew
+                | Action::PutClockSkew
+                | Action::GetDefaultView
+                | Action::PutDefaultView
+                | Action::GetRawPayload
+                | Action::PutRawPayload
+                | Action::GetStreamBundle
+                | Action::GetKeySanitization
+                | Action::PutKeySanitization
+                | Action::GetCompression
+                | Action::PutCompression
+                | Action::GetStagingCompression
+                | Action::PutStagingCompression
+                | Action::GetPartitioning
+                | Action::PutPartitioning
+                | Action::CreateIngestKey
+                | Action::ListIngestKey
+                | Action::DeleteIngestKey
                 | Action::PutAlert
                 | Action::GetAlert
                 | Action::All => Permission::Stream(action, self.stream.clone().unwrap()),
@@ -120,16 +345,25 @@ impl RoleBuilder {
 // we can put same model in the backend
 // user -> Vec<DefaultRoles>
 pub mod model {
-    use super::{Action, RoleBuilder};
+    use super::{Action, RoleBuilder, RowFilter};
 
     #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, Hash)]
     #[serde(tag = "privilege", content = "resource", rename_all = "lowercase")]
     pub enum DefaultPrivilege {
         Admin,
         Editor,
-        Writer { stream: String },
-        Ingester { stream: String },
-        Reader { stream: String, tag: Option<String> },
+        Writer {
+            stream: String,
+        },
+        Ingester {
+            stream: String,
+        },
+        Reader {
+            stream: String,
+            tag: Option<String>,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            rows: Option<RowFilter>,
+        },
     }
 
     impl From<&DefaultPrivilege> for RoleBuilder {
@@ -140,11 +374,14 @@ pub mod model {
                 DefaultPrivilege::Writer { stream } => {
                     writer_perm_builder().with_stream(stream.to_owned())
                 }
-                DefaultPrivilege::Reader { stream, tag } => {
+                DefaultPrivilege::Reader { stream, tag, rows } => {
                     let mut reader = reader_perm_builder().with_stream(stream.to_owned());
                     if let Some(tag) = tag {
                         reader = reader.with_tag(tag.to_owned())
                     }
+                    if let Some(rows) = rows {
+                        reader = reader.with_row_filter(rows.to_owned())
+                    }
                     reader
                 }
                 DefaultPrivilege::Ingester { stream } => {
@@ -159,6 +396,7 @@ pub mod model {
             actions: vec![Action::All],
             stream: Some("*".to_string()),
             tag: None,
+            row_filter: None,
         }
     }
 
@@ -167,14 +405,85 @@ pub mod model {
             actions: vec![
                 Action::Ingest,
                 Action::Query,
+                Action::QueryRawIds,
                 Action::CreateStream,
                 Action::ListStream,
+                Action::PutTemplate,
+                Action::GetTemplate,
+                Action::ListTemplate,
+                Action::DeleteTemplate,
+                Action::PutLookup,
+                Action::ListLookup,
+                Action::DeleteLookup,
                 Action::GetSchema,
                 Action::GetStats,
+                Action::GetStreamInfo,
                 Action::GetRetention,
                 Action::PutRetention,
                 Action::PutCacheEnabled,
                 Action::GetCacheEnabled,
+                Action::PutEncryption,
+                Action::GetEncryption,
+                Action::PutLabels,
+                Action::GetLabels,
+                Action::PutLogPattern,
+                Action::GetLogPattern,
+                Action::PutTimestampKey,
+                Action::GetTimestampKey,
+                Action::PutTimeFields,
+                Action::GetTimeFields,
+                Action::PutColumnOrder,
+                Action::GetColumnOrder,
+                Action::PutCasts,
+                Action::GetCasts,
+                Action::PutSeverityMapping,
+                Action::GetSeverityMapping,
+                Action::PutUnknownSeverity,
+                Action::GetUnknownSeverity,
+                Action::PutBodyConfig,
+                Action::GetBodyConfig,
+                Action::PutShadow,
+                Action::GetShadow,
+                Action::PutRouting,
+                Action::GetRouting,
+                Action::GetRebuild,
+                Action::Rebuild,
+                Action::PutQuota,
+                Action::GetQuota,
+                Action::PutSampling,
+                Action::GetSampling,
+                Action::PutDedup,
+                Action::GetDedup,
+                Action::PutMaxFileSize,
+                Action::GetMaxFileSize,
+                Action::PutAttributeMap,
+                Action::GetAttributeMap,
+                Action::PutNumberMode,
+                Action::GetNumberMode,
+                Action::PutTypeConflict,
+                Action::GetTypeConflict,
+                Action::PutAttributeFilter,
+                Action::GetAttributeFilter,
+                Action::PutIpMask,
+                Action::GetIpMask,
+                Action::PutClockSkew,
+                Action::GetClockSkew,
+                Action::PutDefaultView,
+                Action::GetDefaultView,
+                Action::PutRawPayload,
+                Action::GetRawPayload,
+                Action::GetStreamBundle,
+                Action::PutKeySanitization,
+                Action::GetKeySanitization,
+                Action::PutCompression,
+                Action::GetCompression,
+                Action::PutStagingCompression,
+                Action::GetStagingCompression,
+                Action::PutPartitioning,
+                Action::GetPartitioning,
+                Action::CreateIngestKey,
+                Action::ListIngestKey,
+                Action::DeleteIngestKey,
                 Action::PutAlert,
                 Action::GetAlert,
                 Action::GetAbout,
@@ -182,6 +491,7 @@ pub mod model {
             ],
             stream: Some("*".to_string()),
             tag: None,
+            row_filter: None,
         }
     }
 
@@ -193,7 +503,12 @@ pub mod model {
                 Action::ListStream,
                 Action::GetSchema,
                 Action::GetStats,
+                Action::GetStreamInfo,
+                Action::GetQuota,
                 Action::GetRetention,
+                Action::GetLabels,
+                Action::GetDefaultView,
+                Action::ListLookup,
                 Action::PutAlert,
                 Action::GetAlert,
                 Action::GetAbout,
@@ -201,6 +516,7 @@ pub mod model {
             ],
             stream: None,
             tag: None,
+            row_filter: None,
         }
     }
 
@@ -211,13 +527,19 @@ pub mod model {
                 Action::ListStream,
                 Action::GetSchema,
                 Action::GetStats,
+                Action::GetStreamInfo,
+                Action::GetQuota,
                 Action::GetRetention,
+                Action::GetLabels,
+                Action::GetDefaultView,
+                Action::ListLookup,
                 Action::GetAlert,
                 Action::GetAbout,
                 Action::QueryLLM,
             ],
             stream: None,
             tag: None,
+            row_filter: None,
         }
     }
 
@@ -226,6 +548,7 @@ pub mod model {
             actions: vec![Action::Ingest],
             stream: None,
             tag: None,
+            row_filter: None,
         }
     }
 }
@@ -0,0 +1,439 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+// Rebuild of the streams derived from a stream, by routing or a shadow stream,
+// from the data already stored in the stream. Routing and shadow streams only
+// apply to events ingested after they are set, a rebuild reads the events of
+// a time range back from the stream and ingests them into the derived streams
+// as if they were ingested again. The stream itself is not ingested into, so
+// its data and stats stay as they are.
+//
+// The time range is replayed in windows, the events of a window in groups of
+// the tags and metadata they were ingested with. After every group the job is
+// saved to object storage with the groups of the window already ingested, and
+// after every window with the time up to which events were replayed, a failed
+// or interrupted job resumes from there without ingesting a group twice.
+// Events ingested after routing or the shadow stream was set already went to
+// the derived streams as they were ingested and are not replayed.
+//
+// Events in derived streams keep the time they were first ingested at as their
+// timestamp, which is also kept in `source_timestamp`.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, NaiveDateTime, SecondsFormat, Utc};
+use datafusion::arrow::error::ArrowError;
+use datafusion::arrow::json::writer::record_batches_to_json_rows;
+use datafusion::error::DataFusionError;
+use once_cell::sync::Lazy;
+use serde_json::Value;
+
+use crate::event::{DEFAULT_METADATA_KEY, DEFAULT_TAGS_KEY, DEFAULT_TIMESTAMP_KEY};
+use crate::handlers::http::{push_to_derived, PostError};
+use crate::metadata::STREAM_INFO;
+use crate::option::CONFIG;
+use crate::query::error::ExecuteError;
+use crate::query::{Query, QUERY_SESSION};
+use crate::sampling::Labels;
+
+pub const SOURCE_TIMESTAMP_KEY: &str = "source_timestamp";
+
+// span of time replayed at once, and after which progress is saved
+const WINDOW_MINUTES: i64 = 60;
+
+pub static REBUILDS: Lazy<Rebuilds> = Lazy::new(Rebuilds::default);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RebuildState {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RebuildJob {
+    pub id: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    // events before this time are replayed
+    pub cursor: DateTime<Utc>,
+    pub events: u64,
+    // percentage of the time range replayed
+    pub progress: f64,
+    pub state: RebuildState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub updated_at: DateTime<Utc>,
+    // groups of the window at the cursor already ingested
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub delivered: Vec<String>,
+}
+
+impl RebuildJob {
+    pub fn new(start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        Self {
+            id: ulid::Ulid::new().to_string(),
+            start,
+            end,
+            cursor: start,
+            events: 0,
+            progress: 0.,
+            state: RebuildState::Running,
+            error: None,
+            updated_at: Utc::now(),
+            delivered: Vec::new(),
+        }
+    }
+
+    // next window of the time range to replay
+    fn next_window(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        if self.cursor >= self.end {
+            return None;
+        }
+        let window_end = (self.cursor + Duration::minutes(WINDOW_MINUTES)).min(self.end);
+        Some((self.cursor, window_end))
+    }
+
+    fn deliver(&mut self, group: String, events: u64) {
+        self.delivered.push(group);
+        self.events += events;
+        self.updated_at = Utc::now();
+    }
+
+    fn advance(&mut self, to: DateTime<Utc>) {
+        self.cursor = to;
+        self.delivered.clear();
+        let total = (self.end - self.start).num_milliseconds().max(1) as f64;
+        let done = (self.cursor - self.start).num_milliseconds() as f64;
+        self.progress = (done / total * 100.).clamp(0., 100.);
+        if self.cursor >= self.end {
+            self.state = RebuildState::Completed;
+        }
+        self.updated_at = Utc::now();
+    }
+
+    fn finish(&mut self, state: RebuildState, error: Option<String>) {
+        self.state = state;
+        self.error = error;
+        self.updated_at = Utc::now();
+    }
+}
+
+// The latest rebuild job of every stream, at most one runs per stream
+#[derive(Debug, Default)]
+pub struct Rebuilds(Mutex<HashMap<String, RebuildJob>>);
+
+impl Rebuilds {
+    pub fn get(&self, stream: &str) -> Option<RebuildJob> {
+        self.0.lock().unwrap().get(stream).cloned()
+    }
+
+    // Register a job to run, fails with the id of the running job of the stream if any
+    pub fn begin(&self, stream: &str, mut job: RebuildJob) -> Result<RebuildJob, String> {
+        let mut jobs = self.0.lock().unwrap();
+        if let Some(running) = jobs
+            .get(stream)
+            .filter(|job| job.state == RebuildState::Running)
+        {
+            return Err(running.id.clone());
+        }
+        job.finish(RebuildState::Running, None);
+        jobs.insert(stream.to_string(), job.clone());
+        Ok(job)
+    }
+
+    // Apply a change to the job with this id, returns None if another job
+    // of the stream was started since
+    fn update(
+        &self,
+        stream: &str,
+        id: &str,
+        f: impl FnOnce(&mut RebuildJob),
+    ) -> Option<RebuildJob> {
+        let mut jobs = self.0.lock().unwrap();
+        let job = jobs.get_mut(stream).filter(|job| job.id == id)?;
+        f(job);
+        Some(job.clone())
+    }
+
+    pub fn cancel(&self, stream: &str) -> Option<RebuildJob> {
+        let mut jobs = self.0.lock().unwrap();
+        let job = jobs
+            .get_mut(stream)
+            .filter(|job| job.state == RebuildState::Running)?;
+        job.finish(RebuildState::Cancelled, None);
+        Some(job.clone())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RebuildError {
+    #[error("{0}")]
+    Execute(#[from] ExecuteError),
+    #[error("{0}")]
+    Datafusion(#[from] DataFusionError),
+    #[error("{0}")]
+    Arrow(#[from] ArrowError),
+    #[error("{0}")]
+    Post(#[from] PostError),
+}
+
+// Replay the job window by window until it completes, fails or is cancelled
+pub async fn run(stream: String, job: RebuildJob) {
+    let id = job.id.clone();
+    let mut job = job;
+    while let Some((from, to)) = job.next_window() {
+        let result = replay_window(&stream, &job, from, to).await;
+        // a job cancelled while replaying a window still records the window,
+        // so that resuming it does not replay the window again
+        let updated = match result {
+            Ok(()) => REBUILDS.update(&stream, &id, |job| {
+                let state = job.state;
+                job.advance(to);
+                if state == RebuildState::Cancelled {
+                    job.state = state;
+                }
+            }),
+            Err(err) => {
+                log::warn!("rebuild of streams derived from {stream} failed: {err}");
+                REBUILDS.update(&stream, &id, |job| {
+                    if job.state == RebuildState::Running {
+                        job.finish(RebuildState::Failed, Some(err.to_string()))
+                    }
+                })
+            }
+        };
+        let Some(updated) = updated else {
+            return;
+        };
+        job = updated;
+        save(&stream, &job).await;
+        if job.state != RebuildState::Running {
+            return;
+        }
+    }
+}
+
+pub async fn save(stream: &str, job: &RebuildJob) {
+    let storage = CONFIG.storage().get_object_store();
+    if let Err(err) = storage.put_rebuild_job(stream, job).await {
+        log::warn!("failed to save rebuild job of stream {stream}: {err}");
+    }
+}
+
+async fn replay_window(
+    stream: &str,
+    job: &RebuildJob,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<(), RebuildError> {
+    let sql = format!("SELECT * FROM \"{stream}\"");
+    let raw_logical_plan = QUERY_SESSION.state().create_logical_plan(&sql).await?;
+    let query = Query {
+        raw_logical_plan,
+        start: from,
+        end: to,
+        filter_tag: None,
+        row_filters: HashMap::new(),
+        id_key: None,
+    };
+    let (records, _, _) = query.execute().await?;
+    let records = records.iter().collect::<Vec<_>>();
+    let timestamp_key = STREAM_INFO
+        .timestamp_key(stream)
+        .unwrap_or_else(|_| DEFAULT_TIMESTAMP_KEY.to_string());
+
+    for (labels, group) in into_events(record_batches_to_json_rows(&records)?, &timestamp_key) {
+        let key = group_key(&labels);
+        if job.delivered.contains(&key) {
+            continue;
+        }
+        let events = push_to_derived(stream, labels, group).await?;
+        let Some(updated) = REBUILDS.update(stream, &job.id, |job| job.deliver(key, events)) else {
+            return Ok(());
+        };
+        save(stream, &updated).await;
+    }
+    Ok(())
+}
+
+fn group_key(labels: &Labels) -> String {
+    let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+    hasher.update(labels.tags.as_bytes());
+    hasher.update(&[0]);
+    hasher.update(labels.metadata.as_bytes());
+    format!("{:x}", hasher.digest())
+}
+
+// Whether an event read back from a stream was ingested before `since`, the
+// time from which live events go to a derived stream
+pub fn ingested_before(event: &Value, since: Option<DateTime<Utc>>) -> bool {
+    let Some(since) = since else {
+        return true;
+    };
+    event
+        .get(SOURCE_TIMESTAMP_KEY)
+        .and_then(Value::as_str)
+        .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+        .map_or(true, |time| time < since)
+}
+
+// Turn rows read from a stream back into events, grouped by the tags and
+// metadata they were ingested with
+fn into_events(
+    rows: Vec<serde_json::Map<String, Value>>,
+    timestamp_key: &str,
+) -> HashMap<Labels, Vec<Value>> {
+    let mut groups: HashMap<Labels, Vec<Value>> = HashMap::new();
+    for mut row in rows {
+        let mut label = |key: &str| match row.remove(key) {
+            Some(Value::String(value)) => value,
+            _ => String::new(),
+        };
+        let labels = Labels {
+            tags: label(DEFAULT_TAGS_KEY),
+            metadata: label(DEFAULT_METADATA_KEY),
+        };
+        if let Some(Value::String(time)) = row.remove(timestamp_key) {
+            let time = NaiveDateTime::parse_from_str(&time, "%Y-%m-%dT%H:%M:%S%.f")
+                .map(|time| {
+                    DateTime::<Utc>::from_naive_utc_and_offset(time, Utc)
+                        .to_rfc3339_opts(SecondsFormat::Millis, true)
+                })
+                .unwrap_or(time);
+            row.insert(SOURCE_TIMESTAMP_KEY.to_string(), Value::String(time));
+        }
+        groups.entry(labels).or_default().push(Value::Object(row));
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use serde_json::json;
+
+    use super::{ingested_before, into_events, RebuildJob, RebuildState, Rebuilds};
+
+    #[test]
+    fn job_advances_window_by_window() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2024, 1, 1, 1, 30, 0).unwrap();
+        let rebuilds = Rebuilds::default();
+        let job = rebuilds.begin("app", RebuildJob::new(start, end)).unwrap();
+        assert!(rebuilds.begin("app", RebuildJob::new(start, end)).is_err());
+
+        let (from, to) = job.next_window().unwrap();
+        assert_eq!((from, to), (start, start + chrono::Duration::hours(1)));
+        let job = rebuilds
+            .update("app", &job.id, |job| job.deliver("a".to_string(), 10))
+            .unwrap();
+        assert_eq!(job.delivered, ["a"]);
+        let job = rebuilds
+            .update("app", &job.id, |job| job.advance(to))
+            .unwrap();
+        assert_eq!(job.progress.round(), 67.);
+        assert!(job.delivered.is_empty());
+
+        let (from, to) = job.next_window().unwrap();
+        assert_eq!((from, to), (start + chrono::Duration::hours(1), end));
+        let job = rebuilds
+            .update("app", &job.id, |job| {
+                job.deliver("a".to_string(), 5);
+                job.advance(to)
+            })
+            .unwrap();
+        assert_eq!(job.state, RebuildState::Completed);
+        assert_eq!(job.events, 15);
+        assert!(job.next_window().is_none());
+    }
+
+    #[test]
+    fn cancelled_job_can_be_resumed() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let rebuilds = Rebuilds::default();
+        let job = rebuilds
+            .begin(
+                "app",
+                RebuildJob::new(start, start + chrono::Duration::days(1)),
+            )
+            .unwrap();
+        assert!(rebuilds.cancel("app").is_some());
+        assert!(rebuilds.cancel("app").is_none());
+
+        // a stopped job can be resumed from where it stopped
+        let resumed = rebuilds.begin("app", job.clone()).unwrap();
+        assert_eq!(resumed.cursor, job.cursor);
+        assert_eq!(resumed.state, RebuildState::Running);
+
+        // a job replaced by a new one is not updated anymore
+        rebuilds.cancel("app").unwrap();
+        rebuilds
+            .begin(
+                "app",
+                RebuildJob::new(start, start + chrono::Duration::days(1)),
+            )
+            .unwrap();
+        assert!(rebuilds
+            .update("app", &job.id, |job| job.advance(start))
+            .is_none());
+    }
+
+    #[test]
+    fn rows_become_events_grouped_by_labels() {
+        let rows = vec![
+            json!({"p_timestamp": "2024-01-01T00:00:00.250", "p_tags": "env=prod", "p_metadata": "", "a": 1}),
+            json!({"p_timestamp": "2024-01-01T00:00:01", "p_tags": "env=dev", "p_metadata": "", "a": 2}),
+        ]
+        .into_iter()
+        .map(|row| row.as_object().unwrap().clone())
+        .collect();
+
+        let groups = into_events(rows, "p_timestamp");
+        assert_eq!(groups.len(), 2);
+        let prod = groups
+            .iter()
+            .find(|(labels, _)| labels.tags == "env=prod")
+            .unwrap()
+            .1;
+        assert_eq!(
+            prod,
+            &vec![json!({"a": 1, "source_timestamp": "2024-01-01T00:00:00.250Z"})]
+        );
+    }
+
+    #[test]
+    fn events_routed_live_not_replayed() {
+        let since = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 1).unwrap();
+        let event = |time: &str| json!({"a": 1, "source_timestamp": time});
+        assert!(ingested_before(
+            &event("2024-01-01T00:00:00.250Z"),
+            Some(since)
+        ));
+        assert!(!ingested_before(
+            &event("2024-01-01T00:00:01.000Z"),
+            Some(since)
+        ));
+        assert!(ingested_before(&event("2024-01-01T00:00:01.000Z"), None));
+        assert!(ingested_before(&json!({"a": 1}), Some(since)));
+    }
+}
@@ -16,24 +16,172 @@
  *
  */
 
-use actix_web::{web, Responder};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use actix_web::http::header::ContentType;
+use actix_web::HttpResponse;
+use bytes::Bytes;
 use datafusion::arrow::json::writer::record_batches_to_json_rows;
 use datafusion::arrow::record_batch::RecordBatch;
+use futures::StreamExt;
 use itertools::Itertools;
-use serde_json::{json, Value};
+use serde_json::{json, Map, Value};
+
+use crate::query::stats::QueryStats;
+use crate::query::QueryStream;
+
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
 
 pub struct QueryResponse {
     pub records: Vec<RecordBatch>,
     pub fields: Vec<String>,
     pub fill_null: bool,
     pub with_fields: bool,
+    // return string cells holding JSON objects or arrays (such as bodies stored
+    // with forceString) as nested JSON instead of as strings
+    pub expand_nested: bool,
+    // columns to group the rows by into a tree, one level per column, rows
+    // are returned flat if there are none
+    pub group_by: Vec<String>,
+    // reason the records are incomplete, such as the query timing out
+    pub partial: Option<String>,
+    // count and time range of all rows matching the query, when asked for
+    pub stats: Option<QueryStats>,
+    // size in bytes of the serialized result above which it is not sent,
+    // before compression
+    pub max_size: Option<usize>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EncodeError {
+    #[error("query result is larger than the limit of {0} bytes")]
+    TooLarge(usize),
+    #[error("{0}")]
+    Io(#[from] io::Error),
 }
 
 impl QueryResponse {
-    pub fn to_http(&self) -> impl Responder {
+    pub fn to_http(&self) -> Result<HttpResponse, EncodeError> {
         log::info!("{}", "Returning query results");
-        let records: Vec<&RecordBatch> = self.records.iter().collect();
-        let mut json_records = record_batches_to_json_rows(&records).unwrap();
+        let response = self.to_json();
+
+        let mut writer = CountingWriter::new(Vec::new(), self.max_size);
+        serde_json::to_writer(&mut writer, &response).map_err(|err| writer.error(err.into()))?;
+        Ok(HttpResponse::Ok()
+            .content_type(ContentType::json())
+            .body(writer.inner))
+    }
+
+    /// Respond with one JSON object per row and line (NDJSON).
+    /// Every record batch is sent as its own chunk, so clients can process rows
+    /// without waiting for the whole response. Grouped results are sent as one
+    /// chunk with a line per top level group. When fields are asked for,
+    /// the first line is `{"fields": [...]}`. Stats follow the rows as
+    /// a `{"stats": {...}}` line and partial results end with
+    /// a `{"partial": true, "reason": "..."}` line.
+    pub fn to_ndjson_http(&self) -> Result<HttpResponse, EncodeError> {
+        log::info!("{}", "Returning query results as ndjson");
+        let mut writer = CountingWriter::new(Vec::new(), self.max_size);
+        let chunks = self
+            .ndjson_chunks(&mut writer)
+            .map_err(|err| writer.error(err))?;
+
+        Ok(HttpResponse::Ok()
+            .content_type(NDJSON_CONTENT_TYPE)
+            .streaming(futures::stream::iter(
+                chunks.into_iter().map(Ok::<_, Infallible>),
+            )))
+    }
+
+    /// Respond with NDJSON like `to_ndjson_http`, sending the batches of the
+    /// running query as they are produced after the records of the response.
+    /// Rows sent can't be taken back, so a query failing or growing past the
+    /// size limit midway ends the response with an `{"error": "..."}` line.
+    /// `on_end` is called with the query once it ended. Grouping is not
+    /// supported and stats are not returned.
+    pub fn stream_ndjson_http<F>(self, query: QueryStream, on_end: F) -> HttpResponse
+    where
+        F: FnOnce(&QueryStream) + 'static,
+    {
+        log::info!("{}", "Streaming query results as ndjson");
+        let mut rows = NdjsonRows {
+            writer: CountingWriter::new(Vec::new(), self.max_size),
+            response: self,
+            query,
+            on_end: Some(on_end),
+        };
+        let (first, rows) = match rows.head() {
+            Ok(()) => (rows.take(), Some(rows)),
+            Err(err) => (rows.fail(err), None),
+        };
+        let rest = futures::stream::unfold(rows, |rows| async move {
+            let mut rows = rows?;
+            let chunk = match rows.query.next().await {
+                Ok(Some(batch)) => match rows.batch(&batch) {
+                    Ok(()) => return Some((rows.take(), Some(rows))),
+                    Err(err) => rows.fail(err),
+                },
+                Ok(None) => rows.finish(),
+                Err(err) => rows.fail(err),
+            };
+            Some((chunk, None))
+        });
+        HttpResponse::Ok()
+            .content_type(NDJSON_CONTENT_TYPE)
+            .streaming(
+                futures::stream::once(async { first })
+                    .chain(rest)
+                    .map(Ok::<_, Infallible>),
+            )
+    }
+
+    // the lines of every chunk are written into the writer and taken out of it
+    // once the chunk is complete, so that the writer counts the whole result
+    fn ndjson_chunks(&self, writer: &mut CountingWriter<Vec<u8>>) -> io::Result<Vec<Bytes>> {
+        let mut chunks = Vec::with_capacity(self.records.len() + 1);
+        if self.with_fields {
+            write_line(writer, &json!({ "fields": self.fields }))?;
+            chunks.push(Bytes::from(std::mem::take(&mut writer.inner)));
+        }
+        if self.group_by.is_empty() {
+            for batch in &self.records {
+                self.write_rows(writer, batch)?;
+                chunks.push(Bytes::from(std::mem::take(&mut writer.inner)));
+            }
+        } else {
+            for group in self.values() {
+                write_line(writer, &group)?;
+            }
+            chunks.push(Bytes::from(std::mem::take(&mut writer.inner)));
+        }
+        if let Some(stats) = &self.stats {
+            write_line(writer, &json!({ "stats": stats }))?;
+            chunks.push(Bytes::from(std::mem::take(&mut writer.inner)));
+        }
+        if let Some(reason) = &self.partial {
+            write_line(writer, &json!({ "partial": true, "reason": reason }))?;
+            chunks.push(Bytes::from(std::mem::take(&mut writer.inner)));
+        }
+        Ok(chunks)
+    }
+
+    fn write_rows(&self, writer: &mut impl Write, batch: &RecordBatch) -> io::Result<()> {
+        for row in self.json_rows(&[batch]) {
+            write_line(writer, &row)?;
+        }
+        Ok(())
+    }
+
+    fn json_rows(&self, records: &[&RecordBatch]) -> Vec<Map<String, Value>> {
+        let mut json_records = record_batches_to_json_rows(records).unwrap();
+        if self.expand_nested {
+            for map in &mut json_records {
+                map.values_mut().for_each(expand_nested);
+            }
+        }
         if self.fill_null {
             for map in &mut json_records {
                 for field in &self.fields {
@@ -43,16 +191,520 @@ impl QueryResponse {
                 }
             }
         }
-        let values = json_records.into_iter().map(Value::Object).collect_vec();
-        let response = if self.with_fields {
+        json_records
+    }
+
+    // rows of the result, or the groups of rows if grouped
+    fn values(&self) -> Vec<Value> {
+        let records: Vec<&RecordBatch> = self.records.iter().collect();
+        group_rows(self.json_rows(&records), &self.group_by)
+    }
+
+    fn to_json(&self) -> Value {
+        let values = self.values();
+        if !self.with_fields && self.partial.is_none() && self.stats.is_none() {
+            return Value::Array(values);
+        }
+
+        let mut response = json!({
+            "fields": self.fields,
+            "records": values
+        });
+        if let Some(stats) = &self.stats {
+            response["stats"] = json!(stats);
+        }
+        if let Some(reason) = &self.partial {
+            response["partial"] = Value::Bool(true);
+            response["reason"] = Value::String(reason.clone());
+        }
+        response
+    }
+}
+
+// Reason of a result being partial because the query stopped at its deadline
+pub fn timeout_reason(timeout: Duration) -> String {
+    format!("query did not finish within {timeout:?}, records are the ones produced until then")
+}
+
+// A streamed NDJSON response, the lines are written into the writer and taken
+// out of it as a chunk per batch so that the writer counts the whole result
+struct NdjsonRows<F> {
+    response: QueryResponse,
+    query: QueryStream,
+    writer: CountingWriter<Vec<u8>>,
+    on_end: Option<F>,
+}
+
+impl<F: FnOnce(&QueryStream)> NdjsonRows<F> {
+    // the fields line and the records of the response itself
+    fn head(&mut self) -> Result<(), EncodeError> {
+        if self.response.with_fields {
+            write_line(&mut self.writer, &json!({ "fields": self.response.fields }))
+                .map_err(|err| self.writer.error(err))?;
+        }
+        for batch in &self.response.records {
+            self.response
+                .write_rows(&mut self.writer, batch)
+                .map_err(|err| self.writer.error(err))?;
+        }
+        Ok(())
+    }
+
+    fn batch(&mut self, batch: &RecordBatch) -> Result<(), EncodeError> {
+        self.response
+            .write_rows(&mut self.writer, batch)
+            .map_err(|err| self.writer.error(err))
+    }
+
+    fn take(&mut self) -> Bytes {
+        Bytes::from(std::mem::take(&mut self.writer.inner))
+    }
+
+    // the last chunk once the query finished, flagged as partial if the query
+    // stopped at its deadline
+    fn finish(&mut self) -> Bytes {
+        let reason = self
+            .query
+            .timed_out()
+            .map(timeout_reason)
+            .into_iter()
+            .chain(self.response.partial.clone())
+            .join("; ");
+        if !reason.is_empty() {
+            let line = json!({ "partial": true, "reason": reason });
+            if let Err(err) = write_line(&mut self.writer, &line) {
+                let err = self.writer.error(err);
+                return self.fail(err);
+            }
+        }
+        self.end();
+        self.take()
+    }
+
+    // the last chunk once the query failed, the lines of the chunk which
+    // failed are left out. The error line is not counted so that it is sent
+    // even when the size limit is reached
+    fn fail(&mut self, err: impl std::fmt::Display) -> Bytes {
+        self.end();
+        self.writer.inner.clear();
+        write_line(&mut self.writer.inner, &json!({ "error": err.to_string() }))
+            .expect("lines can be written to a vec");
+        self.take()
+    }
+
+    fn end(&mut self) {
+        if let Some(on_end) = self.on_end.take() {
+            on_end(&self.query);
+        }
+    }
+}
+
+// Group rows by the values of the columns into a tree with a level per column,
+// such as `[{"service": "api", "groups": [{"host": "a", "records": [...]}]}]`.
+// Groups come in the order of their first row and rows keep the columns which
+// are not grouped by. Rows without a column are grouped under null.
+fn group_rows(rows: Vec<Map<String, Value>>, columns: &[String]) -> Vec<Value> {
+    let Some((column, rest)) = columns.split_first() else {
+        return rows.into_iter().map(Value::Object).collect_vec();
+    };
+    let mut groups: Vec<(Value, Vec<Map<String, Value>>)> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for mut row in rows {
+        let value = row.remove(column).unwrap_or(Value::Null);
+        let group = *index.entry(value.to_string()).or_insert_with(|| {
+            groups.push((value, Vec::new()));
+            groups.len() - 1
+        });
+        groups[group].1.push(row);
+    }
+
+    let children = if rest.is_empty() { "records" } else { "groups" };
+    groups
+        .into_iter()
+        .map(|(value, rows)| {
+            let mut group = Map::new();
+            group.insert(column.clone(), value);
+            group.insert(children.to_string(), Value::Array(group_rows(rows, rest)));
+            Value::Object(group)
+        })
+        .collect_vec()
+}
+
+fn write_line(writer: &mut impl Write, value: &impl serde::Serialize) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, value)?;
+    writer.write_all(b"\n")
+}
+
+fn expand_nested(value: &mut Value) {
+    let Value::String(text) = value else {
+        return;
+    };
+    if !(text.starts_with('{') || text.starts_with('[')) {
+        return;
+    }
+    if let Ok(nested @ (Value::Object(_) | Value::Array(_))) = serde_json::from_str(text) {
+        *value = nested;
+    }
+}
+
+// Counts the bytes written and fails writes past the limit, if there is one
+struct CountingWriter<W> {
+    inner: W,
+    count: usize,
+    limit: Option<usize>,
+    exceeded: bool,
+}
+
+impl<W> CountingWriter<W> {
+    fn new(inner: W, limit: Option<usize>) -> Self {
+        Self {
+            inner,
+            count: 0,
+            limit,
+            exceeded: false,
+        }
+    }
+
+    // tell a write failing for the limit apart from a failing writer
+    fn error(&self, err: io::Error) -> EncodeError {
+        match self.limit {
+            Some(limit) if self.exceeded => EncodeError::TooLarge(limit),
+            _ => EncodeError::Io(err),
+        }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self
+            .limit
+            .is_some_and(|limit| self.count + buf.len() > limit)
+        {
+            self.exceeded = true;
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "result size limit exceeded",
+            ));
+        }
+        let written = self.inner.write(buf)?;
+        self.count += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use chrono::{TimeZone, Utc};
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use serde_json::json;
+
+    use super::{EncodeError, QueryResponse};
+    use crate::query::stats::QueryStats;
+    use crate::query::QueryStream;
+
+    #[actix_web::test]
+    async fn ndjson_response_has_one_row_per_line() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new("code", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(vec![Some("a"), None])),
+                Arc::new(Int64Array::from(vec![200, 500])),
+            ],
+        )
+        .unwrap();
+        let response = QueryResponse {
+            records: vec![batch.clone(), batch],
+            fields: vec!["host".to_string(), "code".to_string()],
+            fill_null: true,
+            with_fields: true,
+            expand_nested: false,
+            group_by: Vec::new(),
+            partial: None,
+            stats: None,
+            max_size: None,
+        }
+        .to_ndjson_http()
+        .unwrap();
+
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let lines: Vec<serde_json::Value> = std::str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], json!({"fields": ["host", "code"]}));
+        assert_eq!(lines[1], json!({"host": "a", "code": 200}));
+        assert_eq!(lines[2], json!({"host": null, "code": 500}));
+    }
+
+    #[actix_web::test]
+    async fn streamed_ndjson_ends_with_error_past_the_limit() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("code", DataType::Int64, true),
+            Field::new("body", DataType::Utf8, true),
+        ]));
+        let batch = |code: i64, body: &str| {
+            RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(Int64Array::from(vec![code])),
+                    Arc::new(StringArray::from(vec![body])),
+                ],
+            )
+            .unwrap()
+        };
+        let mut stream =
+            QueryStream::from_batches(vec![batch(200, "ok"), batch(500, &"x".repeat(1024))]);
+        // body first, the order of the columns applies to the streamed batches
+        stream.project(vec![1, 0]);
+        let first = stream.next().await.unwrap().unwrap();
+        let ended = Arc::new(AtomicUsize::new(0));
+        let response = QueryResponse {
+            records: vec![first],
+            fields: stream.fields.clone(),
+            fill_null: false,
+            with_fields: true,
+            expand_nested: false,
+            group_by: Vec::new(),
+            partial: None,
+            stats: None,
+            max_size: Some(1000),
+        }
+        .stream_ndjson_http(stream, {
+            let ended = ended.clone();
+            move |stream| {
+                ended.store(stream.rows(), Ordering::Relaxed);
+            }
+        });
+
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let lines: Vec<serde_json::Value> = std::str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], json!({"fields": ["body", "code"]}));
+        assert_eq!(lines[1], json!({"body": "ok", "code": 200}));
+        assert_eq!(
+            lines[2],
+            json!({"error": "query result is larger than the limit of 1000 bytes"})
+        );
+        assert_eq!(ended.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn expand_json_encoded_cells() {
+        let schema = Arc::new(Schema::new(vec![Field::new("body", DataType::Utf8, true)]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![Arc::new(StringArray::from(vec![
+                r#"{"user": {"id": 1}}"#,
+                "[1, 2]",
+                "{not json",
+                "plain",
+            ]))],
+        )
+        .unwrap();
+        let response = QueryResponse {
+            records: vec![batch],
+            fields: vec!["body".to_string()],
+            fill_null: false,
+            with_fields: false,
+            expand_nested: true,
+            group_by: Vec::new(),
+            partial: None,
+            stats: None,
+            max_size: None,
+        };
+
+        assert_eq!(
+            response.to_json(),
+            json!([
+                {"body": {"user": {"id": 1}}},
+                {"body": [1, 2]},
+                {"body": "{not json"},
+                {"body": "plain"}
+            ])
+        );
+    }
+
+    #[test]
+    fn rows_grouped_into_tree() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("service", DataType::Utf8, true),
+            Field::new("host", DataType::Utf8, true),
+            Field::new("code", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(vec![
+                    Some("web"),
+                    Some("api"),
+                    Some("web"),
+                    None,
+                ])),
+                Arc::new(StringArray::from(vec!["a", "b", "b", "a"])),
+                Arc::new(Int64Array::from(vec![200, 500, 404, 200])),
+            ],
+        )
+        .unwrap();
+        let response = |group_by: &[&str]| QueryResponse {
+            records: vec![batch.clone()],
+            fields: vec![
+                "service".to_string(),
+                "host".to_string(),
+                "code".to_string(),
+            ],
+            fill_null: true,
+            with_fields: false,
+            expand_nested: false,
+            group_by: group_by.iter().map(|column| column.to_string()).collect(),
+            partial: None,
+            stats: None,
+            max_size: None,
+        };
+
+        assert_eq!(
+            response(&["service", "host"]).to_json(),
+            json!([
+                {"service": "web", "groups": [
+                    {"host": "a", "records": [{"code": 200}]},
+                    {"host": "b", "records": [{"code": 404}]}
+                ]},
+                {"service": "api", "groups": [
+                    {"host": "b", "records": [{"code": 500}]}
+                ]},
+                {"service": null, "groups": [
+                    {"host": "a", "records": [{"code": 200}]}
+                ]}
+            ])
+        );
+        assert_eq!(
+            response(&["host"]).to_json(),
+            json!([
+                {"host": "a", "records": [
+                    {"service": "web", "code": 200},
+                    {"service": null, "code": 200}
+                ]},
+                {"host": "b", "records": [
+                    {"service": "api", "code": 500},
+                    {"service": "web", "code": 404}
+                ]}
+            ])
+        );
+        assert_eq!(response(&[]).to_json().as_array().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn partial_response_is_flagged() {
+        let schema = Arc::new(Schema::new(vec![Field::new("code", DataType::Int64, true)]));
+        let batch =
+            RecordBatch::try_new(schema, vec![Arc::new(Int64Array::from(vec![200]))]).unwrap();
+        let response = QueryResponse {
+            records: vec![batch],
+            fields: vec!["code".to_string()],
+            fill_null: false,
+            with_fields: false,
+            expand_nested: false,
+            group_by: Vec::new(),
+            partial: Some("timed out".to_string()),
+            stats: None,
+            max_size: None,
+        };
+
+        assert_eq!(
+            response.to_json(),
             json!({
-                "fields": self.fields,
-                "records": values
+                "fields": ["code"],
+                "records": [{"code": 200}],
+                "partial": true,
+                "reason": "timed out"
             })
-        } else {
-            Value::Array(values)
+        );
+    }
+
+    #[test]
+    fn stats_are_returned_with_records() {
+        let schema = Arc::new(Schema::new(vec![Field::new("code", DataType::Int64, true)]));
+        let batch =
+            RecordBatch::try_new(schema, vec![Arc::new(Int64Array::from(vec![200]))]).unwrap();
+        let response = QueryResponse {
+            records: vec![batch],
+            fields: vec!["code".to_string()],
+            fill_null: false,
+            with_fields: false,
+            expand_nested: false,
+            group_by: Vec::new(),
+            partial: None,
+            stats: Some(QueryStats {
+                count: 42,
+                min_time: Some(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()),
+                max_time: None,
+            }),
+            max_size: None,
+        };
+
+        assert_eq!(
+            response.to_json(),
+            json!({
+                "fields": ["code"],
+                "records": [{"code": 200}],
+                "stats": {"count": 42, "minTime": "2024-01-01T00:00:00Z", "maxTime": null}
+            })
+        );
+    }
+
+    #[test]
+    fn results_over_the_size_limit_are_not_sent() {
+        let schema = Arc::new(Schema::new(vec![Field::new("body", DataType::Utf8, true)]));
+        let body = "x".repeat(1024);
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![Arc::new(StringArray::from(vec![body.as_str(), "small"]))],
+        )
+        .unwrap();
+        let response = |max_size| QueryResponse {
+            records: vec![batch.clone()],
+            fields: vec!["body".to_string()],
+            fill_null: false,
+            with_fields: false,
+            expand_nested: false,
+            group_by: Vec::new(),
+            partial: None,
+            stats: None,
+            max_size: Some(max_size),
         };
 
-        web::Json(response)
+        assert!(response(2048).to_http().is_ok());
+        assert!(matches!(
+            response(1000).to_http(),
+            Err(EncodeError::TooLarge(1000))
+        ));
+        assert!(matches!(
+            response(1000).to_ndjson_http(),
+            Err(EncodeError::TooLarge(1000))
+        ));
     }
 }
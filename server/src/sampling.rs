@@ -0,0 +1,339 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use once_cell::sync::Lazy;
+use rand::Rng;
+use serde_json::Value;
+
+use crate::event::severity::{SeverityBand, SEVERITY_NUMBER_KEY};
+use crate::metrics::SAMPLING_RETENTION_RATE;
+
+// how often windows which have ended are flushed
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+pub static RESERVOIRS: Lazy<Reservoirs> = Lazy::new(Reservoirs::default);
+
+// Per stream sampling mode. Instead of ingesting every event, at most
+// `sample_size` events of every `window_secs` long window are kept. Events are
+// picked by weighted reservoir sampling (Efraimidis and Spirakis), weighted by
+// their severity so that errors are over-represented in the sample.
+// Events are held in memory until their window ends and are then ingested
+// all at once, so their p_timestamp is the end of the window.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sampling {
+    pub sample_size: usize,
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+    // weights by severity level, overriding the default weights
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub weights: BTreeMap<SeverityBand, u32>,
+}
+
+fn default_window_secs() -> u64 {
+    60
+}
+
+impl Sampling {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.sample_size == 0 {
+            return Err("sampleSize must be at least 1".to_string());
+        }
+        if self.window_secs == 0 {
+            return Err("windowSecs must be at least 1".to_string());
+        }
+        if let Some((band, _)) = self.weights.iter().find(|(_, weight)| **weight == 0) {
+            return Err(format!("weight of {band:?} must be at least 1"));
+        }
+        Ok(())
+    }
+
+    /// Weight of an event by its severity number, events without one weigh 1
+    pub fn weight(&self, event: &Value) -> f64 {
+        let Some(band) = event
+            .get(SEVERITY_NUMBER_KEY)
+            .and_then(Value::as_i64)
+            .and_then(SeverityBand::of)
+        else {
+            return 1.;
+        };
+        let weight = self.weights.get(&band).copied().unwrap_or(match band {
+            SeverityBand::Trace | SeverityBand::Debug | SeverityBand::Info => 1,
+            SeverityBand::Warn => 2,
+            SeverityBand::Error => 5,
+            SeverityBand::Fatal => 10,
+        });
+        f64::from(weight)
+    }
+
+    // start of the window the given time falls into
+    fn window_start(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let window = self.window_secs as i64;
+        let secs = time.timestamp();
+        Utc.timestamp_opt(secs - secs.rem_euclid(window), 0)
+            .single()
+            .unwrap_or(time)
+    }
+}
+
+// Tags and metadata of the request an event was ingested with
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Labels {
+    pub tags: String,
+    pub metadata: String,
+}
+
+#[derive(Debug)]
+struct Keyed {
+    key: f64,
+    labels: Labels,
+    event: Value,
+}
+
+impl PartialEq for Keyed {
+    fn eq(&self, other: &Self) -> bool {
+        self.key.total_cmp(&other.key).is_eq()
+    }
+}
+
+impl Eq for Keyed {}
+
+impl PartialOrd for Keyed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Keyed {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.total_cmp(&other.key)
+    }
+}
+
+#[derive(Debug)]
+struct Reservoir {
+    ends_at: DateTime<Utc>,
+    seen: u64,
+    // min heap on the keys, so that the event with the lowest key is replaced first
+    sample: BinaryHeap<Reverse<Keyed>>,
+}
+
+// Events kept from a window which has ended
+#[derive(Debug)]
+pub struct Sample {
+    pub seen: u64,
+    pub events: Vec<(Labels, Value)>,
+}
+
+impl Sample {
+    // fraction of the events of the window which are kept
+    pub fn retention_rate(&self) -> f64 {
+        if self.seen == 0 {
+            return 1.;
+        }
+        self.events.len() as f64 / self.seen as f64
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Reservoirs {
+    reservoirs: Mutex<HashMap<String, Reservoir>>,
+}
+
+impl Reservoirs {
+    /// Offer the events of an ingest body to the reservoir of the current window of the stream
+    pub fn offer(
+        &self,
+        stream_name: &str,
+        sampling: &Sampling,
+        labels: &Labels,
+        body: Value,
+        now: DateTime<Utc>,
+    ) {
+        let events = match body {
+            Value::Array(events) => events,
+            event => vec![event],
+        };
+        let mut reservoirs = self.reservoirs.lock().unwrap();
+        let reservoir = reservoirs
+            .entry(stream_name.to_string())
+            .or_insert_with(|| Reservoir {
+                ends_at: sampling.window_start(now)
+                    + chrono::Duration::seconds(sampling.window_secs as i64),
+                seen: 0,
+                sample: BinaryHeap::with_capacity(sampling.sample_size),
+            });
+
+        let mut rng = rand::thread_rng();
+        for event in events {
+            reservoir.seen += 1;
+            // key = u^(1/w), compared through its logarithm to keep precision for large weights
+            let u: f64 = rng.gen_range(f64::MIN_POSITIVE..1.);
+            let key = u.ln() / sampling.weight(&event);
+            if reservoir.sample.len() >= sampling.sample_size {
+                match reservoir.sample.peek() {
+                    Some(Reverse(lowest)) if lowest.key < key => {
+                        reservoir.sample.pop();
+                    }
+                    _ => continue,
+                }
+            }
+            reservoir.sample.push(Reverse(Keyed {
+                key,
+                labels: labels.clone(),
+                event,
+            }));
+        }
+    }
+
+    /// Remove and return the samples of all windows which have ended by now
+    pub fn take_ended(&self, now: DateTime<Utc>) -> Vec<(String, Sample)> {
+        let mut reservoirs = self.reservoirs.lock().unwrap();
+        let ended: Vec<String> = reservoirs
+            .iter()
+            .filter(|(_, reservoir)| reservoir.ends_at <= now)
+            .map(|(stream_name, _)| stream_name.clone())
+            .collect();
+        ended
+            .into_iter()
+            .filter_map(|stream_name| {
+                let reservoir = reservoirs.remove(&stream_name)?;
+                let events = reservoir
+                    .sample
+                    .into_iter()
+                    .map(|Reverse(keyed)| (keyed.labels, keyed.event))
+                    .collect();
+                Some((
+                    stream_name,
+                    Sample {
+                        seen: reservoir.seen,
+                        events,
+                    },
+                ))
+            })
+            .collect()
+    }
+}
+
+// Ingest the samples of windows as they end
+pub fn init_sample_scheduler() {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(FLUSH_INTERVAL).await;
+            for (stream_name, sample) in RESERVOIRS.take_ended(Utc::now()) {
+                SAMPLING_RETENTION_RATE
+                    .with_label_values(&[&stream_name])
+                    .set(sample.retention_rate());
+                if let Err(err) = crate::handlers::http::push_sample(&stream_name, sample).await {
+                    log::warn!(
+                        "failed to ingest sampled events of stream {}: {}",
+                        stream_name,
+                        err
+                    );
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::{Duration, TimeZone, Utc};
+    use serde_json::{json, Value};
+
+    use super::{Labels, Reservoirs, Sampling};
+    use crate::event::severity::SeverityBand;
+
+    fn sampling(sample_size: usize) -> Sampling {
+        Sampling {
+            sample_size,
+            window_secs: 60,
+            weights: BTreeMap::new(),
+        }
+    }
+
+    fn labels() -> Labels {
+        Labels {
+            tags: String::new(),
+            metadata: String::new(),
+        }
+    }
+
+    fn events(n: usize, severity_number: i64) -> Value {
+        json!((0..n)
+            .map(|_| json!({ "severity_number": severity_number }))
+            .collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn sample_is_bounded_and_favours_errors() {
+        let reservoirs = Reservoirs::default();
+        let sampling = sampling(50);
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 10, 0, 30).unwrap();
+        reservoirs.offer("app", &sampling, &labels(), events(1000, 9), now);
+        reservoirs.offer("app", &sampling, &labels(), events(100, 17), now);
+
+        assert!(reservoirs.take_ended(now).is_empty());
+        let ended = reservoirs.take_ended(now + Duration::seconds(30));
+        assert_eq!(ended.len(), 1);
+
+        let (stream_name, sample) = &ended[0];
+        assert_eq!(stream_name, "app");
+        assert_eq!(sample.seen, 1100);
+        assert_eq!(sample.events.len(), 50);
+        assert!((sample.retention_rate() - 50. / 1100.).abs() < f64::EPSILON);
+        // errors are 9% of the events but weigh 5 times as much as the others
+        let errors = sample
+            .events
+            .iter()
+            .filter(|(_, event)| event["severity_number"] == 17)
+            .count();
+        assert!(errors > 10, "only {errors} errors sampled");
+    }
+
+    #[test]
+    fn small_windows_keep_everything() {
+        let reservoirs = Reservoirs::default();
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 10, 0, 0).unwrap();
+        reservoirs.offer("app", &sampling(10), &labels(), json!({"a": 1}), now);
+        let ended = reservoirs.take_ended(now + Duration::seconds(60));
+        assert_eq!(ended[0].1.events.len(), 1);
+        assert_eq!(ended[0].1.retention_rate(), 1.);
+    }
+
+    #[test]
+    fn weights_are_configurable() {
+        let mut sampling = sampling(10);
+        assert_eq!(sampling.weight(&json!({"severity_number": 21})), 10.);
+        assert_eq!(sampling.weight(&json!({"level": "error"})), 1.);
+        sampling.weights.insert(SeverityBand::Info, 3);
+        assert_eq!(sampling.weight(&json!({"severity_number": 9})), 3.);
+        assert!(sampling.validate().is_ok());
+
+        sampling.weights.insert(SeverityBand::Debug, 0);
+        assert!(sampling.validate().is_err());
+    }
+}
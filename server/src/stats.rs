@@ -16,7 +16,15 @@
  *
  */
 
-use crate::metrics::{EVENTS_INGESTED, EVENTS_INGESTED_SIZE, STORAGE_SIZE};
+use std::collections::{BTreeMap, HashMap};
+
+use prometheus::core::{Collector, MetricVec, MetricVecBuilder};
+
+use crate::metrics::{
+    EVENTS_DELETED, EVENTS_DELETED_SIZE, EVENTS_INGESTED, EVENTS_INGESTED_SIZE,
+    INGEST_REQUESTS_TOTAL, INGEST_REQUEST_DURATION_SECONDS, OVERSIZED_REQUESTS, PARQUET_FILE_COUNT,
+    SCHEMA_VERSIONS, STORAGE_SIZE,
+};
 
 /// Helper struct type created by copying stats values from metadata
 #[derive(Debug, Default, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
@@ -24,6 +32,45 @@ pub struct Stats {
     pub events: u64,
     pub ingestion: u64,
     pub storage: u64,
+    // events and ingestion bytes removed from the stream after ingestion
+    #[serde(default)]
+    pub deleted_events: u64,
+    #[serde(default)]
+    pub deleted_ingestion: u64,
+    // number of distinct schemas the stream has had, grows with every new field
+    #[serde(default)]
+    pub schema_versions: u64,
+    // parquet files of the stream in object storage
+    #[serde(default)]
+    pub files: u64,
+}
+
+impl Stats {
+    // add up the stats of several streams
+    pub fn merge(&mut self, other: &Stats) {
+        self.events += other.events;
+        self.ingestion += other.ingestion;
+        self.storage += other.storage;
+        self.deleted_events += other.deleted_events;
+        self.deleted_ingestion += other.deleted_ingestion;
+        self.schema_versions += other.schema_versions;
+        self.files += other.files;
+    }
+}
+
+/// Stats of streams summed per value of a label, streams without the label are under `None`
+pub fn rollup<'a>(
+    streams: impl IntoIterator<Item = (&'a BTreeMap<String, String>, Stats)>,
+    label: &str,
+) -> BTreeMap<Option<String>, Stats> {
+    let mut groups: BTreeMap<Option<String>, Stats> = BTreeMap::new();
+    for (labels, stats) in streams {
+        groups
+            .entry(labels.get(label).cloned())
+            .or_default()
+            .merge(&stats);
+    }
+    groups
 }
 
 pub fn get_current_stats(stream_name: &str, format: &'static str) -> Option<Stats> {
@@ -42,17 +89,59 @@ pub fn get_current_stats(stream_name: &str, format: &'static str) -> Option<Stat
         .get_metric_with_label_values(&storage_size_labels)
         .ok()?
         .get();
+    let events_deleted = EVENTS_DELETED
+        .get_metric_with_label_values(&event_labels)
+        .ok()?
+        .get();
+    let deleted_size = EVENTS_DELETED_SIZE
+        .get_metric_with_label_values(&event_labels)
+        .ok()?
+        .get();
+    let schema_versions = SCHEMA_VERSIONS
+        .get_metric_with_label_values(&[stream_name])
+        .ok()?
+        .get();
+    let files = PARQUET_FILE_COUNT
+        .get_metric_with_label_values(&[stream_name])
+        .ok()?
+        .get();
     // this should be valid for all cases given that gauge must never go negative
     let ingestion_size = ingestion_size as u64;
     let storage_size = storage_size as u64;
+    let deleted_size = deleted_size as u64;
+    let files = files as u64;
 
     Some(Stats {
         events: events_ingested,
         ingestion: ingestion_size,
         storage: storage_size,
+        deleted_events: events_deleted,
+        deleted_ingestion: deleted_size,
+        schema_versions,
+        files,
     })
 }
 
+// Move the stats of data removed from storage into the deleted stats of the stream.
+// Events ingested is a counter and only ever grows, the storage size shrinks by the removed size.
+pub fn record_deleted(stream_name: &str, format: &'static str, removed: &Stats) {
+    let event_labels = event_labels(stream_name, format);
+    let storage_size_labels = storage_size_labels(stream_name);
+
+    EVENTS_DELETED
+        .with_label_values(&event_labels)
+        .inc_by(removed.events);
+    EVENTS_DELETED_SIZE
+        .with_label_values(&event_labels)
+        .add(removed.ingestion as i64);
+    STORAGE_SIZE
+        .with_label_values(&storage_size_labels)
+        .sub(removed.storage as i64);
+    PARQUET_FILE_COUNT
+        .with_label_values(&[stream_name])
+        .sub(removed.files as i64);
+}
+
 pub fn delete_stats(stream_name: &str, format: &'static str) -> prometheus::Result<()> {
     let event_labels = event_labels(stream_name, format);
     let storage_size_labels = storage_size_labels(stream_name);
@@ -60,10 +149,35 @@ pub fn delete_stats(stream_name: &str, format: &'static str) -> prometheus::Resu
     EVENTS_INGESTED.remove_label_values(&event_labels)?;
     EVENTS_INGESTED_SIZE.remove_label_values(&event_labels)?;
     STORAGE_SIZE.remove_label_values(&storage_size_labels)?;
+    // deleted stats are only created once data is deleted
+    let _ = EVENTS_DELETED.remove_label_values(&event_labels);
+    let _ = EVENTS_DELETED_SIZE.remove_label_values(&event_labels);
+    let _ = SCHEMA_VERSIONS.remove_label_values(&[stream_name]);
+    let _ = PARQUET_FILE_COUNT.remove_label_values(&[stream_name]);
+    let _ = OVERSIZED_REQUESTS.remove_label_values(&[stream_name]);
+    remove_stream_series(&INGEST_REQUEST_DURATION_SECONDS, stream_name);
+    remove_stream_series(&INGEST_REQUESTS_TOTAL, stream_name);
 
     Ok(())
 }
 
+// Remove every series of the metric labelled with the stream, whatever the
+// values of its other labels are
+fn remove_stream_series<T: MetricVecBuilder>(metric: &MetricVec<T>, stream_name: &str) {
+    for family in metric.collect() {
+        for series in family.get_metric() {
+            let labels: HashMap<&str, &str> = series
+                .get_label()
+                .iter()
+                .map(|label| (label.get_name(), label.get_value()))
+                .collect();
+            if labels.get("stream") == Some(&stream_name) {
+                let _ = metric.remove(&labels);
+            }
+        }
+    }
+}
+
 fn event_labels<'a>(stream_name: &'a str, format: &'static str) -> [&'a str; 2] {
     [stream_name, format]
 }
@@ -71,3 +185,64 @@ fn event_labels<'a>(stream_name: &'a str, format: &'static str) -> [&'a str; 2]
 fn storage_size_labels(stream_name: &str) -> [&str; 3] {
     ["data", stream_name, "parquet"]
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{remove_stream_series, rollup, Stats};
+
+    fn stats(events: u64, storage: u64) -> Stats {
+        Stats {
+            events,
+            storage,
+            ..Stats::default()
+        }
+    }
+
+    #[test]
+    fn rollup_by_label() {
+        let payments = BTreeMap::from([("team".to_string(), "payments".to_string())]);
+        let search = BTreeMap::from([("team".to_string(), "search".to_string())]);
+        let unlabeled = BTreeMap::new();
+
+        let groups = rollup(
+            [
+                (&payments, stats(1, 10)),
+                (&search, stats(2, 20)),
+                (&payments, stats(3, 30)),
+                (&unlabeled, stats(4, 40)),
+            ],
+            "team",
+        );
+
+        assert_eq!(groups[&Some("payments".to_string())], stats(4, 40));
+        assert_eq!(groups[&Some("search".to_string())], stats(2, 20));
+        assert_eq!(groups[&None], stats(4, 40));
+    }
+
+    #[test]
+    fn request_series_of_stream_removed() {
+        use crate::metrics::INGEST_REQUESTS_TOTAL;
+
+        for labels in [
+            ["removedstream", "json", "200"],
+            ["removedstream", "otel-logs", "400"],
+            ["keptstream", "json", "200"],
+        ] {
+            INGEST_REQUESTS_TOTAL.with_label_values(&labels).inc();
+        }
+
+        remove_stream_series(&INGEST_REQUESTS_TOTAL, "removedstream");
+
+        let streams: Vec<String> = prometheus::core::Collector::collect(&*INGEST_REQUESTS_TOTAL)[0]
+            .get_metric()
+            .iter()
+            .flat_map(|metric| metric.get_label())
+            .filter(|label| label.get_name() == "stream")
+            .map(|label| label.get_value().to_string())
+            .collect();
+        assert!(!streams.contains(&"removedstream".to_string()));
+        assert!(streams.contains(&"keptstream".to_string()));
+    }
+}
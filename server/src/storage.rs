@@ -16,15 +16,45 @@
  *
  */
 
-use crate::{catalog::snapshot::Snapshot, stats::Stats};
+use crate::{
+    catalog::snapshot::Snapshot,
+    dedup::Dedup,
+    event::{
+        attribute_filter::AttributeFilter,
+        attributes::AttributeMap,
+        body::BodyConfig,
+        ip_mask::IpMask,
+        keys::{KeyMapping, KeySanitization},
+        numbers::NumberMode,
+        raw::RawPayload,
+        routing::{RoutedFrom, Routing},
+        schema_lock::SchemaLock,
+        severity::{SeverityMapping, UnknownSeverity},
+        shadow::Shadow,
+        skew::ClockSkew,
+        type_conflict::TypeConflict,
+    },
+    query::{casts::CastColumn, view::DefaultView},
+    quota::IngestQuota,
+    rbac::ingest_key::IngestKey,
+    sampling::Sampling,
+    stats::Stats,
+    storage::compression::{StagingCompression, StreamCompression},
+    storage::partition::{PartitionValues, Partitioning},
+};
 
 use chrono::Local;
 
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 
+pub mod bundle;
+pub mod compression;
+pub mod encryption;
 mod localfs;
 mod metrics_layer;
 mod object_storage;
+pub mod partition;
 pub mod retention;
 mod s3;
 pub mod staging;
@@ -35,6 +65,7 @@ pub use object_storage::{ObjectStorage, ObjectStorageProvider};
 pub use s3::S3Config;
 pub use store_metadata::{
     put_remote_metadata, put_staging_metadata, resolve_parseable_metadata, StorageMetadata,
+    StreamTemplate,
 };
 
 pub use self::staging::StorageDir;
@@ -42,6 +73,8 @@ pub use self::staging::StorageDir;
 /// local sync interval to move data.records to /tmp dir of that stream.
 /// 60 sec is a reasonable value.
 pub const LOCAL_SYNC_INTERVAL: u64 = 60;
+/// interval in seconds to refresh the age of the oldest staged record of every stream
+pub const STAGING_AGE_INTERVAL: u64 = 10;
 
 /// duration used to configure prefix in objectstore and local disk structure
 /// used for storage. Defaults to 1 min.
@@ -76,6 +109,83 @@ pub struct ObjectStoreFormat {
     pub snapshot: Snapshot,
     #[serde(default)]
     pub cache_enabled: bool,
+    #[serde(default)]
+    pub encrypted: bool,
+    // arbitrary labels such as team or project, used to roll up stats
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_pattern: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp_key: Option<String>,
+    // fields promoted to timestamp columns if their values parse as timestamps
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub time_fields: Vec<String>,
+    // columns leading query results, the rest follow in schema order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub column_order: Vec<String>,
+    // columns computed at query time by casting stored columns
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub casts: Vec<CastColumn>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity_mapping: Option<SeverityMapping>,
+    #[serde(default, skip_serializing_if = "UnknownSeverity::is_unspecified")]
+    pub unknown_severity: UnknownSeverity,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_config: Option<BodyConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shadow: Option<Shadow>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub routing: Option<Routing>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_lock: Option<SchemaLock>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<IngestQuota>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sampling: Option<Sampling>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dedup: Option<Dedup>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<StreamCompression>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub staging_compression: Option<StagingCompression>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attribute_map: Option<AttributeMap>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attribute_filter: Option<AttributeFilter>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_mask: Option<IpMask>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_payload: Option<RawPayload>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_sanitization: Option<KeySanitization>,
+    // original keys stored under another column, kept after sanitization is
+    // turned off so that their columns can still be looked up
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub key_mapping: KeyMapping,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_skew: Option<ClockSkew>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_view: Option<DefaultView>,
+    #[serde(default, skip_serializing_if = "NumberMode::is_infer")]
+    pub number_mode: NumberMode,
+    #[serde(default, skip_serializing_if = "TypeConflict::is_string")]
+    pub type_conflict: TypeConflict,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ingest_keys: Vec<IngestKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partitioning: Option<Partitioning>,
+    // values seen per partition column, bounded by the max values of the partitioning
+    #[serde(default, skip_serializing_if = "PartitionValues::is_empty")]
+    pub partition_values: PartitionValues,
+    // name of the template this stream was created from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    // stream and value this stream was created for by routing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub routed_from: Option<RoutedFrom>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -118,6 +228,40 @@ impl Default for ObjectStoreFormat {
             stats: Stats::default(),
             snapshot: Snapshot::default(),
             cache_enabled: false,
+            encrypted: false,
+            labels: BTreeMap::new(),
+            log_pattern: None,
+            timestamp_key: None,
+            time_fields: Vec::new(),
+            column_order: Vec::new(),
+            casts: Vec::new(),
+            severity_mapping: None,
+            unknown_severity: UnknownSeverity::default(),
+            body_config: None,
+            shadow: None,
+            routing: None,
+            schema_lock: None,
+            quota: None,
+            sampling: None,
+            dedup: None,
+            compression: None,
+            staging_compression: None,
+            max_file_size: None,
+            attribute_map: None,
+            attribute_filter: None,
+            ip_mask: None,
+            raw_payload: None,
+            key_sanitization: None,
+            key_mapping: KeyMapping::new(),
+            clock_skew: None,
+            default_view: None,
+            number_mode: NumberMode::default(),
+            type_conflict: TypeConflict::default(),
+            ingest_keys: Vec::new(),
+            partitioning: None,
+            partition_values: PartitionValues::new(),
+            template: None,
+            routed_from: None,
         }
     }
 }
@@ -0,0 +1,150 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use arrow_schema::Schema;
+use serde_json::{Map, Value};
+
+use crate::alerts::Alerts;
+
+use super::{retention::Retention, ObjectStoreFormat};
+
+// Version of the bundle format, bumped on changes which older servers can not
+// read. Bundles of older versions are upgraded when they are imported.
+pub const BUNDLE_VERSION: u32 = 1;
+
+// Keys of the stream metadata which belong to the stream in one cluster and are
+// not carried over, ingest keys are credentials and stay where they were issued
+const EXCLUDED_KEYS: [&str; 8] = [
+    "version",
+    "objectstore-format",
+    "created-at",
+    "owner",
+    "permissions",
+    "stats",
+    "snapshot",
+    "ingest_keys",
+];
+
+// Portable configuration of a stream, everything but its data, used to create
+// the stream again on another cluster. `config` holds the settings as they are
+// kept in the stream metadata so that new settings are exported without
+// changes to the bundle format.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamBundle {
+    pub version: u32,
+    // name of the exported stream, bundles can be imported under any name
+    #[serde(default)]
+    pub stream: String,
+    #[serde(default)]
+    pub config: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Schema>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<Retention>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alerts: Option<Alerts>,
+}
+
+impl StreamBundle {
+    pub fn new(stream: &str, manifest: &ObjectStoreFormat) -> Self {
+        let Value::Object(mut config) =
+            serde_json::to_value(manifest).expect("stream metadata is serializable")
+        else {
+            unreachable!("stream metadata is an object")
+        };
+        config.retain(|key, _| !EXCLUDED_KEYS.contains(&key.as_str()));
+        Self {
+            version: BUNDLE_VERSION,
+            stream: stream.to_string(),
+            config,
+            ..Default::default()
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self.version {
+            0 => Err("bundle version is missing".to_string()),
+            BUNDLE_VERSION => Ok(()),
+            version if version > BUNDLE_VERSION => Err(format!(
+                "bundle version {version} is newer than version {BUNDLE_VERSION} supported by this server"
+            )),
+            version => Err(format!("bundle version {version} is not supported")),
+        }
+    }
+
+    /// Stream metadata with the settings of the bundle applied to it
+    pub fn apply(&self, manifest: &ObjectStoreFormat) -> Result<ObjectStoreFormat, String> {
+        let mut value = serde_json::to_value(manifest).expect("stream metadata is serializable");
+        let target = value.as_object_mut().expect("stream metadata is an object");
+        for (key, setting) in &self.config {
+            if !EXCLUDED_KEYS.contains(&key.as_str()) {
+                target.insert(key.clone(), setting.clone());
+            }
+        }
+        serde_json::from_value(value).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{StreamBundle, BUNDLE_VERSION};
+    use crate::storage::ObjectStoreFormat;
+
+    #[test]
+    fn bundle_carries_settings_only() {
+        let mut manifest = ObjectStoreFormat {
+            log_pattern: Some("%{IP:client}".to_string()),
+            ..Default::default()
+        };
+        manifest.stats.events = 42;
+
+        let bundle = StreamBundle::new("app", &manifest);
+        assert_eq!(bundle.config["log_pattern"], "%{IP:client}");
+        assert!(bundle.config.get("stats").is_none());
+        assert!(bundle.config.get("created-at").is_none());
+
+        let target = ObjectStoreFormat::default();
+        let imported = bundle.apply(&target).unwrap();
+        assert_eq!(imported.log_pattern.as_deref(), Some("%{IP:client}"));
+        assert_eq!(imported.stats.events, 0);
+        assert_eq!(imported.created_at, target.created_at);
+    }
+
+    #[test]
+    fn bundle_versions() {
+        let bundle: StreamBundle =
+            serde_json::from_value(json!({"version": 1, "config": {"labels": {"team": "a"}}}))
+                .unwrap();
+        assert!(bundle.validate().is_ok());
+        let imported = bundle.apply(&ObjectStoreFormat::default()).unwrap();
+        assert_eq!(imported.labels.get("team").map(String::as_str), Some("a"));
+
+        let newer: StreamBundle =
+            serde_json::from_value(json!({"version": BUNDLE_VERSION + 1})).unwrap();
+        assert!(newer.validate().is_err());
+        let missing = StreamBundle::default();
+        assert!(missing.validate().is_err());
+
+        let invalid: StreamBundle =
+            serde_json::from_value(json!({"version": 1, "config": {"labels": 1}})).unwrap();
+        assert!(invalid.apply(&ObjectStoreFormat::default()).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use arrow_ipc::writer::IpcWriteOptions;
+use arrow_ipc::CompressionType;
+use arrow_schema::ArrowError;
+use parquet::basic::{Compression, ZstdLevel};
+
+// Per stream parquet compression codec, streams without one use the server wide
+// `P_PARQUET_COMPRESSION_ALGO`. The codec is recorded in the footer of every
+// parquet file, so changing it only affects files written from then on and
+// files written with a previous codec stay readable.
+//
+//   {"codec": "zstd", "level": 19}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "codec", rename_all = "lowercase")]
+pub enum StreamCompression {
+    None,
+    Snappy,
+    Lz4,
+    Zstd {
+        #[serde(default = "default_zstd_level")]
+        level: i32,
+    },
+}
+
+fn default_zstd_level() -> i32 {
+    ZstdLevel::default().compression_level()
+}
+
+impl StreamCompression {
+    pub fn validate(&self) -> Result<(), String> {
+        if let StreamCompression::Zstd { level } = self {
+            ZstdLevel::try_new(*level)
+                .map_err(|_| format!("zstd level {level} is not in range 1..=22"))?;
+        }
+        Ok(())
+    }
+}
+
+impl From<StreamCompression> for Compression {
+    fn from(value: StreamCompression) -> Self {
+        match value {
+            StreamCompression::None => Compression::UNCOMPRESSED,
+            StreamCompression::Snappy => Compression::SNAPPY,
+            StreamCompression::Lz4 => Compression::LZ4,
+            StreamCompression::Zstd { level } => {
+                Compression::ZSTD(ZstdLevel::try_new(level).unwrap_or_default())
+            }
+        }
+    }
+}
+
+// Per stream compression of the arrow files events are staged in before they
+// are written to parquet. Trades cpu on every write for less disk io, which
+// matters for streams ingesting faster than the disk writes. Streams without
+// it, such as latency sensitive ones, stage uncompressed. Compression is
+// recorded in every record batch of the file so readers of the staging files
+// decompress transparently, whatever the setting was when they were written.
+//
+//   {"codec": "zstd"}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "codec", rename_all = "lowercase")]
+pub enum StagingCompression {
+    Zstd,
+}
+
+impl StagingCompression {
+    pub fn write_options(
+        compression: Option<StagingCompression>,
+    ) -> Result<IpcWriteOptions, ArrowError> {
+        let codec = compression.map(|compression| match compression {
+            StagingCompression::Zstd => CompressionType::ZSTD,
+        });
+        IpcWriteOptions::default().try_with_compression(codec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    use arrow_array::{Int64Array, RecordBatch, StringArray};
+    use arrow_ipc::reader::StreamReader;
+    use arrow_ipc::writer::StreamWriter;
+    use arrow_schema::{DataType, Field, Schema};
+    use bytes::Bytes;
+    use parquet::{
+        arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter},
+        file::properties::WriterProperties,
+    };
+
+    use super::{StagingCompression, StreamCompression};
+    use crate::utils::arrow::reverse_reader::get_reverse_reader;
+
+    #[test]
+    fn parse_codec() {
+        let zstd: StreamCompression =
+            serde_json::from_str(r#"{"codec": "zstd", "level": 19}"#).unwrap();
+        assert_eq!(zstd, StreamCompression::Zstd { level: 19 });
+        assert!(zstd.validate().is_ok());
+
+        let zstd: StreamCompression = serde_json::from_str(r#"{"codec": "zstd"}"#).unwrap();
+        assert_eq!(zstd, StreamCompression::Zstd { level: 1 });
+
+        let zstd: StreamCompression =
+            serde_json::from_str(r#"{"codec": "zstd", "level": 23}"#).unwrap();
+        assert!(zstd.validate().is_err());
+
+        assert!(serde_json::from_str::<StreamCompression>(r#"{"codec": "gzip"}"#).is_err());
+    }
+
+    #[test]
+    fn files_readable_with_any_codec() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("message", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2, 3])),
+                Arc::new(StringArray::from(vec!["a", "b", "c"])),
+            ],
+        )
+        .unwrap();
+
+        for codec in [
+            StreamCompression::None,
+            StreamCompression::Snappy,
+            StreamCompression::Lz4,
+            StreamCompression::Zstd { level: 19 },
+        ] {
+            let props = WriterProperties::builder()
+                .set_compression(codec.into())
+                .build();
+            let mut buf = Vec::new();
+            let mut writer = ArrowWriter::try_new(&mut buf, schema.clone(), Some(props)).unwrap();
+            writer.write(&batch).unwrap();
+            writer.close().unwrap();
+
+            let reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(buf))
+                .unwrap()
+                .build()
+                .unwrap();
+            let batches: Vec<RecordBatch> = reader.map(Result::unwrap).collect();
+            assert_eq!(batches, vec![batch.clone()]);
+        }
+    }
+
+    #[test]
+    fn staging_files_read_back_compressed() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("message", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from_iter_values(0..1000)),
+                Arc::new(StringArray::from_iter_values(
+                    (0..1000).map(|_| "connection reset by peer"),
+                )),
+            ],
+        )
+        .unwrap();
+
+        let write = |compression| {
+            let options = StagingCompression::write_options(compression).unwrap();
+            let mut buf = Vec::new();
+            let mut writer =
+                StreamWriter::try_new_with_options(&mut buf, &schema, options).unwrap();
+            writer.write(&batch).unwrap();
+            writer.write(&batch).unwrap();
+            writer.finish().unwrap();
+            drop(writer);
+            buf
+        };
+        let plain = write(None);
+        let compressed = write(Some(StagingCompression::Zstd));
+        assert!(compressed.len() < plain.len() / 2);
+
+        let batches: Vec<RecordBatch> =
+            StreamReader::try_new(Cursor::new(compressed.clone()), None)
+                .unwrap()
+                .map(Result::unwrap)
+                .collect();
+        assert_eq!(batches, vec![batch.clone(), batch.clone()]);
+        let reversed: Vec<RecordBatch> = get_reverse_reader(Cursor::new(compressed))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(reversed.len(), 2);
+    }
+}
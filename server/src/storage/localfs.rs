@@ -24,9 +24,16 @@ use std::{
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use datafusion::{datasource::listing::ListingTableUrl, execution::runtime_env::RuntimeConfig};
+use datafusion::{
+    datasource::{
+        listing::ListingTableUrl,
+        object_store::{DefaultObjectStoreRegistry, ObjectStoreRegistry, ObjectStoreUrl},
+    },
+    execution::runtime_env::RuntimeConfig,
+};
 use fs_extra::file::CopyOptions;
 use futures::{stream::FuturesUnordered, TryStreamExt};
+use object_store::local::LocalFileSystem;
 use relative_path::RelativePath;
 use tokio::fs::{self, DirEntry};
 use tokio_stream::wrappers::ReadDirStream;
@@ -34,6 +41,7 @@ use tokio_stream::wrappers::ReadDirStream;
 use crate::metrics::storage::{localfs::REQUEST_RESPONSE_TIME, StorageMetrics};
 use crate::{option::validation, utils::validate_path_is_writeable};
 
+use super::encryption::{self, EncryptionLayer};
 use super::{object_storage, LogStream, ObjectStorage, ObjectStorageError, ObjectStorageProvider};
 
 #[derive(Debug, Clone, clap::Args)]
@@ -57,7 +65,15 @@ pub struct FSConfig {
 
 impl ObjectStorageProvider for FSConfig {
     fn get_datafusion_runtime(&self) -> RuntimeConfig {
-        RuntimeConfig::new()
+        let Some(keyring) = encryption::KEYRING.as_ref() else {
+            return RuntimeConfig::new();
+        };
+        let object_store_registry = DefaultObjectStoreRegistry::new();
+        object_store_registry.register_store(
+            ObjectStoreUrl::local_filesystem().as_ref(),
+            Arc::new(EncryptionLayer::new(LocalFileSystem::new(), keyring)),
+        );
+        RuntimeConfig::new().with_object_store_registry(Arc::new(object_store_registry))
     }
 
     fn get_object_store(&self) -> Arc<dyn ObjectStorage + Send> {
@@ -17,8 +17,10 @@
  */
 
 use super::{
-    retention::Retention, staging::convert_disk_files_to_parquet, LogStream, ObjectStorageError,
-    ObjectStoreFormat, Permisssion, StorageDir, StorageMetadata,
+    encryption, partition,
+    retention::{Retention, RetentionRecord},
+    staging::{self, convert_disk_files_to_parquet},
+    LogStream, ObjectStorageError, ObjectStoreFormat, Permisssion, StorageDir, StorageMetadata,
 };
 
 use crate::{
@@ -26,8 +28,9 @@ use crate::{
     catalog::{self, manifest::Manifest, snapshot::Snapshot},
     localcache::LocalCacheManager,
     metadata::STREAM_INFO,
-    metrics::{storage::StorageMetrics, STORAGE_SIZE},
+    metrics::{storage::StorageMetrics, PARQUET_FILE_COUNT, STORAGE_SIZE},
     option::CONFIG,
+    rebuild::RebuildJob,
     stats::{self, Stats},
 };
 
@@ -35,6 +38,7 @@ use actix_web_prometheus::PrometheusMetrics;
 use arrow_schema::Schema;
 use async_trait::async_trait;
 use bytes::Bytes;
+use chrono::NaiveDate;
 use datafusion::{datasource::listing::ListingTableUrl, execution::runtime_env::RuntimeConfig};
 use itertools::Itertools;
 use relative_path::RelativePath;
@@ -42,7 +46,7 @@ use relative_path::RelativePathBuf;
 use serde_json::Value;
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
     fs,
     path::Path,
     sync::Arc,
@@ -54,6 +58,9 @@ pub(super) const STREAM_METADATA_FILE_NAME: &str = ".stream.json";
 pub(super) const PARSEABLE_METADATA_FILE_NAME: &str = ".parseable.json";
 const SCHEMA_FILE_NAME: &str = ".schema";
 const ALERT_FILE_NAME: &str = ".alert.json";
+const RETENTION_HISTORY_FILE_NAME: &str = ".retention_history.json";
+const REBUILD_FILE_NAME: &str = ".rebuild.json";
+const ROLLED_UP_FILE_NAME: &str = ".rolled_up.json";
 const MANIFEST_FILE: &str = "manifest.json";
 
 pub trait ObjectStorageProvider: StorageMetrics + std::fmt::Debug {
@@ -190,6 +197,67 @@ pub trait ObjectStorage: Sync + 'static {
         }
     }
 
+    async fn get_retention_history(
+        &self,
+        stream_name: &str,
+    ) -> Result<Vec<RetentionRecord>, ObjectStorageError> {
+        match self.get_object(&retention_history_path(stream_name)).await {
+            Ok(history) => Ok(serde_json::from_slice(&history).unwrap_or_default()),
+            Err(ObjectStorageError::NoSuchKey(_)) => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn put_retention_history(
+        &self,
+        stream_name: &str,
+        history: &[RetentionRecord],
+    ) -> Result<(), ObjectStorageError> {
+        self.put_object(&retention_history_path(stream_name), to_bytes(history))
+            .await
+    }
+
+    // dates whose rollups were ingested by a downsample task, until their rows are deleted
+    async fn get_rolled_up_dates(
+        &self,
+        stream_name: &str,
+    ) -> Result<BTreeSet<NaiveDate>, ObjectStorageError> {
+        match self.get_object(&rolled_up_path(stream_name)).await {
+            Ok(dates) => Ok(serde_json::from_slice(&dates).unwrap_or_default()),
+            Err(ObjectStorageError::NoSuchKey(_)) => Ok(BTreeSet::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn put_rolled_up_dates(
+        &self,
+        stream_name: &str,
+        dates: &BTreeSet<NaiveDate>,
+    ) -> Result<(), ObjectStorageError> {
+        self.put_object(&rolled_up_path(stream_name), to_bytes(dates))
+            .await
+    }
+
+    async fn get_rebuild_job(
+        &self,
+        stream_name: &str,
+    ) -> Result<Option<RebuildJob>, ObjectStorageError> {
+        match self.get_object(&rebuild_path(stream_name)).await {
+            Ok(job) => Ok(serde_json::from_slice(&job).ok()),
+            Err(ObjectStorageError::NoSuchKey(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn put_rebuild_job(
+        &self,
+        stream_name: &str,
+        job: &RebuildJob,
+    ) -> Result<(), ObjectStorageError> {
+        self.put_object(&rebuild_path(stream_name), to_bytes(job))
+            .await
+    }
+
     async fn get_stream_metadata(
         &self,
         stream_name: &str,
@@ -325,6 +393,11 @@ pub trait ObjectStorage: Sync + 'static {
             let cache_enabled = STREAM_INFO
                 .cache_enabled(stream)
                 .map_err(|err| ObjectStorageError::UnhandledError(Box::new(err)))?;
+            let keyring = STREAM_INFO
+                .encrypted(stream)
+                .map_err(|err| ObjectStorageError::UnhandledError(Box::new(err)))?
+                .then_some(encryption::KEYRING.as_ref())
+                .flatten();
             let dir = StorageDir::new(stream);
             let schema = convert_disk_files_to_parquet(stream, &dir)
                 .map_err(|err| ObjectStorageError::UnhandledError(Box::new(err)))?;
@@ -348,15 +421,30 @@ pub trait ObjectStorage: Sync + 'static {
                     .expect("only parquet files are returned by iterator")
                     .to_str()
                     .expect("filename is valid string");
-                let file_suffix = str::replacen(filename, ".", "/", 3);
-                let stream_relative_path = format!("{stream}/{file_suffix}");
-                self.upload_file(&stream_relative_path, &file).await?;
+                let file_suffix = staging::object_store_suffix(filename);
+                let mut stream_relative_path = format!("{stream}/{file_suffix}");
+                // the manifest entry of an encrypted file describes the plain file
+                if let Some(keyring) = keyring {
+                    let encrypted_file = keyring
+                        .encrypt_file(&file)
+                        .map_err(|err| ObjectStorageError::UnhandledError(Box::new(err)))?;
+                    stream_relative_path = encryption::encrypted_name(&stream_relative_path);
+                    let uploaded = self
+                        .upload_file(&stream_relative_path, &encrypted_file)
+                        .await;
+                    let _ = fs::remove_file(encrypted_file);
+                    uploaded?;
+                } else {
+                    self.upload_file(&stream_relative_path, &file).await?;
+                }
+                PARQUET_FILE_COUNT.with_label_values(&[stream]).inc();
                 let absolute_path = self
                     .absolute_url(RelativePath::from_path(&stream_relative_path).unwrap())
                     .to_string();
                 let store = CONFIG.storage().get_object_store();
-                let manifest =
+                let mut manifest =
                     catalog::create_from_parquet_file(absolute_path.clone(), &file).unwrap();
+                manifest.key_id = keyring.map(|keyring| keyring.active_key().to_string());
                 catalog::update_snapshot(store, stream, manifest).await?;
                 if cache_enabled && cache_manager.is_some() {
                     cache_updates
@@ -369,6 +457,22 @@ pub trait ObjectStorage: Sync + 'static {
             }
         }
 
+        // values of partition columns seen since the last sync, so that a restart
+        // doesn't reset the number of values of a column kept apart
+        for (stream, values) in partition::take_changed() {
+            let persisted = match self.get_stream_metadata(&stream).await {
+                Ok(mut meta) => {
+                    meta.partition_values = values;
+                    self.put_stream_manifest(&stream, &meta).await
+                }
+                Err(err) => Err(err),
+            };
+            if let (Err(err), true) = (persisted, STREAM_INFO.stream_exists(&stream)) {
+                log::warn!("failed to persist partition values of stream {stream}: {err}");
+                partition::mark_changed(&stream);
+            }
+        }
+
         for (stream, compressed_size) in stream_stats {
             STORAGE_SIZE
                 .with_label_values(&["data", stream, "parquet"])
@@ -440,6 +544,21 @@ fn alert_json_path(stream_name: &str) -> RelativePathBuf {
     RelativePathBuf::from_iter([stream_name, ALERT_FILE_NAME])
 }
 
+#[inline(always)]
+fn retention_history_path(stream_name: &str) -> RelativePathBuf {
+    RelativePathBuf::from_iter([stream_name, RETENTION_HISTORY_FILE_NAME])
+}
+
+#[inline(always)]
+fn rebuild_path(stream_name: &str) -> RelativePathBuf {
+    RelativePathBuf::from_iter([stream_name, REBUILD_FILE_NAME])
+}
+
+#[inline(always)]
+fn rolled_up_path(stream_name: &str) -> RelativePathBuf {
+    RelativePathBuf::from_iter([stream_name, ROLLED_UP_FILE_NAME])
+}
+
 #[inline(always)]
 fn manifest_path(prefix: &str) -> RelativePathBuf {
     RelativePathBuf::from_iter([prefix, MANIFEST_FILE])
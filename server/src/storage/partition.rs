@@ -0,0 +1,395 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Mutex;
+
+use arrow_array::{BooleanArray, RecordBatch};
+use arrow_select::filter::filter_record_batch;
+use datafusion::arrow::util::display::array_value_to_string;
+use once_cell::sync::Lazy;
+
+const MAX_PARTITION_COLUMNS: usize = 3;
+const DEFAULT_MAX_VALUES: usize = 100;
+const TIME_DIRECTORIES: [&str; 3] = ["date", "hour", "minute"];
+// directory of the rows with a null or missing partition column
+const NULL_PARTITION: &str = "__null__";
+// directory of the rows whose value came after the partition was full,
+// it can hold any value so it is never pruned
+pub const OTHER_PARTITION: &str = "__other__";
+
+// values seen per stream and partition column, bounded by the max values of the stream
+type SeenValues = HashMap<(String, String), HashSet<String>>;
+static PARTITION_VALUES: Lazy<Mutex<SeenValues>> = Lazy::new(Mutex::default);
+// streams with values seen since their values were last persisted
+static CHANGED: Lazy<Mutex<HashSet<String>>> = Lazy::new(Mutex::default);
+
+/// Values seen per partition column of a stream, persisted in the stream
+/// metadata so that the max values of a column hold across restarts
+pub type PartitionValues = BTreeMap<String, BTreeSet<String>>;
+
+// Partitioning of a stream by columns of its events on top of the date.
+// Data of a stream with partitioning ["service.name"] is laid out as
+// {stream}/date=../hour=../minute=../service_name=../{file}.parquet
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Partitioning {
+    pub columns: Vec<String>,
+    // number of distinct values of a column kept apart,
+    // rows with any further value go to the __other__ partition
+    #[serde(default = "default_max_values")]
+    pub max_values: usize,
+}
+
+fn default_max_values() -> usize {
+    DEFAULT_MAX_VALUES
+}
+
+impl Partitioning {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.columns.is_empty() || self.columns.len() > MAX_PARTITION_COLUMNS {
+            return Err(format!(
+                "between 1 and {MAX_PARTITION_COLUMNS} partition columns are allowed"
+            ));
+        }
+        let mut seen: HashMap<String, &String> = HashMap::new();
+        for column in &self.columns {
+            if column.is_empty() {
+                return Err("partition column can not be empty".to_string());
+            }
+            if TIME_DIRECTORIES.contains(&dir_name(column).as_str()) {
+                return Err(format!("{column} can not be used as a partition column"));
+            }
+            // columns with the same directory name could not be told apart by path
+            match seen.insert(dir_name(column), column) {
+                Some(other) if other == column => {
+                    return Err(format!("partition column {column} is repeated"))
+                }
+                Some(other) => {
+                    return Err(format!(
+                        "partition columns {other} and {column} have the same directory name {}",
+                        dir_name(column)
+                    ))
+                }
+                None => (),
+            }
+        }
+        if self.max_values == 0 {
+            return Err("max values should be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    // File name segment of the partition of every row of a record batch by the
+    // values of the partition columns, `service_name=web.` for example
+    pub fn segments(&self, stream_name: &str, rb: &RecordBatch) -> Vec<String> {
+        let mut values = PARTITION_VALUES.lock().unwrap();
+
+        (0..rb.num_rows())
+            .map(|row| {
+                let mut segment = String::new();
+                for column in &self.columns {
+                    let value = match rb.column_by_name(column) {
+                        Some(array) if !array.is_null(row) => array_value_to_string(array, row)
+                            .map(|value| dir_name(&value))
+                            .unwrap_or_else(|_| NULL_PARTITION.to_string()),
+                        _ => NULL_PARTITION.to_string(),
+                    };
+                    let seen = values
+                        .entry((stream_name.to_string(), column.clone()))
+                        .or_default();
+                    let value = if seen.contains(&value) {
+                        value
+                    } else if seen.len() < self.max_values {
+                        seen.insert(value.clone());
+                        CHANGED.lock().unwrap().insert(stream_name.to_string());
+                        value
+                    } else {
+                        OTHER_PARTITION.to_string()
+                    };
+                    segment.push_str(&format!("{}={}.", dir_name(column), value));
+                }
+                segment
+            })
+            .collect()
+    }
+}
+
+// Split a record batch by the partition segments of its rows.
+// Returns every segment along with its rows.
+pub fn split_by(segments: &[String], rb: &RecordBatch) -> Vec<(String, RecordBatch)> {
+    let mut partitions: BTreeMap<&str, Vec<bool>> = BTreeMap::new();
+    for (row, segment) in segments.iter().enumerate() {
+        partitions
+            .entry(segment)
+            .or_insert_with(|| vec![false; rb.num_rows()])[row] = true;
+    }
+
+    if partitions.len() == 1 {
+        let (segment, _) = partitions.pop_first().unwrap();
+        return vec![(segment.to_string(), rb.clone())];
+    }
+    partitions
+        .into_iter()
+        .map(|(segment, rows)| {
+            let rb = filter_record_batch(rb, &BooleanArray::from(rows))
+                .expect("mask is as long as the batch");
+            (segment.to_string(), rb)
+        })
+        .collect()
+}
+
+// forget the values seen for a stream, when it is deleted or its partitioning changes
+pub fn reset(stream_name: &str) {
+    PARTITION_VALUES
+        .lock()
+        .unwrap()
+        .retain(|(stream, _), _| stream != stream_name);
+    CHANGED.lock().unwrap().remove(stream_name);
+}
+
+// values of a stream persisted before, when its metadata is loaded
+pub fn load(stream_name: &str, values: &PartitionValues) {
+    let mut seen = PARTITION_VALUES.lock().unwrap();
+    for (column, values) in values {
+        seen.entry((stream_name.to_string(), column.clone()))
+            .or_default()
+            .extend(values.iter().cloned());
+    }
+}
+
+// Streams with values seen since they were last persisted, along with all
+// of their values. Streams are taken back with `mark_changed` if persisting
+// their values fails.
+pub fn take_changed() -> Vec<(String, PartitionValues)> {
+    let changed = std::mem::take(&mut *CHANGED.lock().unwrap());
+    let seen = PARTITION_VALUES.lock().unwrap();
+    changed
+        .into_iter()
+        .map(|stream_name| {
+            let values = seen
+                .iter()
+                .filter(|((stream, _), _)| *stream == stream_name)
+                .map(|((_, column), values)| (column.clone(), values.iter().cloned().collect()))
+                .collect();
+            (stream_name, values)
+        })
+        .collect()
+}
+
+pub fn mark_changed(stream_name: &str) {
+    CHANGED.lock().unwrap().insert(stream_name.to_string());
+}
+
+// Name of a column or value as a directory.
+// Dots and slashes separate directories in staging file names and object paths,
+// so anything but alphanumerics, `-` and `_` is replaced with `_`.
+pub fn dir_name(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// Partition directories of a file as (column, value), they follow the date,
+// hour and minute directories
+pub fn partition_dirs(file_path: &str) -> impl Iterator<Item = (&str, &str)> {
+    file_path
+        .split('/')
+        .skip_while(|segment| !segment.starts_with("minute="))
+        .skip(1)
+        .filter_map(|segment| segment.split_once('='))
+}
+
+// Whether a file can be skipped by a filter asking for the given values of a column.
+// Only filters on one of the partition columns of the stream prune, another column
+// can have the same directory name. Files without a directory for the column, or in
+// the __other__ partition, are kept.
+pub fn can_prune(file_path: &str, columns: &[String], column: &str, values: &[String]) -> bool {
+    if !columns
+        .iter()
+        .any(|partition_column| partition_column == column)
+    {
+        return false;
+    }
+    let column = dir_name(column);
+    let Some((_, partition)) = partition_dirs(file_path).find(|(name, _)| *name == column) else {
+        return false;
+    };
+    partition != OTHER_PARTITION && !values.iter().any(|value| dir_name(value) == partition)
+}
+
+// Whether a file is in the partition of one of the values of a column,
+// files in the __other__ partition can hold any value and are never
+pub fn in_partition(file_path: &str, column: &str, values: &[String]) -> bool {
+    let column = dir_name(column);
+    partition_dirs(file_path).any(|(name, partition)| {
+        name == column && values.iter().any(|value| dir_name(value) == partition)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int64Array, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+
+    use std::collections::BTreeSet;
+
+    use super::{
+        can_prune, in_partition, load, split_by, take_changed, PartitionValues, Partitioning,
+    };
+
+    fn batch() -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("service.name", DataType::Utf8, true),
+            Field::new("status", DataType::Int64, true),
+        ]));
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(vec![
+                    Some("web"),
+                    Some("db"),
+                    Some("web"),
+                    None,
+                    Some("queue"),
+                ])),
+                Arc::new(Int64Array::from(vec![200, 500, 404, 200, 200])),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn split_by_partition_columns() {
+        let partitioning = Partitioning {
+            columns: vec!["service.name".to_string()],
+            max_values: 3,
+        };
+        let batch = batch();
+        let segments = partitioning.segments("partition_split_test", &batch);
+        let partitions = split_by(&segments, &batch);
+        let rows: Vec<(&str, usize)> = partitions
+            .iter()
+            .map(|(segment, rb)| (segment.as_str(), rb.num_rows()))
+            .collect();
+
+        // null takes one of the three values, queue goes to other
+        assert_eq!(
+            rows,
+            vec![
+                ("service_name=__null__.", 1),
+                ("service_name=__other__.", 1),
+                ("service_name=db.", 1),
+                ("service_name=web.", 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn seen_values_persisted() {
+        let stream = "partition_persist_test";
+        // values seen before a restart
+        load(
+            stream,
+            &PartitionValues::from([(
+                "service.name".to_string(),
+                BTreeSet::from(["web".to_string(), "db".to_string()]),
+            )]),
+        );
+        let partitioning = Partitioning {
+            columns: vec!["service.name".to_string()],
+            max_values: 3,
+        };
+        let segments = partitioning.segments(stream, &batch());
+        assert_eq!(segments[3], "service_name=__null__.");
+        assert_eq!(segments[4], "service_name=__other__.");
+
+        let (_, values) = take_changed()
+            .into_iter()
+            .find(|(changed, _)| changed == stream)
+            .unwrap();
+        assert_eq!(
+            values["service.name"],
+            BTreeSet::from(["__null__", "db", "web"].map(String::from))
+        );
+    }
+
+    #[test]
+    fn prune_by_partition_directory() {
+        let path =
+            "s3://bucket/app/date=2024-01-01/hour=10/minute=05/service_name=web/host.data.parquet";
+        let other = "s3://bucket/app/date=2024-01-01/hour=10/minute=05/service_name=__other__/host.data.parquet";
+
+        let columns = ["service.name".to_string()];
+        let db = ["db".to_string()];
+
+        assert!(can_prune(path, &columns, "service.name", &db));
+        assert!(!can_prune(
+            path,
+            &columns,
+            "service.name",
+            &["web".to_string()]
+        ));
+        assert!(!can_prune(other, &columns, "service.name", &db));
+        assert!(!can_prune(path, &columns, "host", &db));
+        assert!(!can_prune(
+            path,
+            &columns,
+            "date",
+            &["2024-01-02".to_string()]
+        ));
+        // another column with the same directory name is not partitioned
+        assert!(!can_prune(path, &columns, "service_name", &db));
+    }
+
+    #[test]
+    fn files_of_partition_values() {
+        let path =
+            "s3://bucket/app/date=2024-01-01/hour=10/minute=05/service_name=web/host.data.parquet";
+        let other = "s3://bucket/app/date=2024-01-01/hour=10/minute=05/service_name=__other__/host.data.parquet";
+        let web = ["web".to_string()];
+
+        assert!(in_partition(path, "service.name", &web));
+        assert!(!in_partition(path, "service.name", &["db".to_string()]));
+        assert!(!in_partition(other, "service.name", &web));
+        assert!(!in_partition(path, "host", &web));
+    }
+
+    #[test]
+    fn colliding_partition_columns() {
+        let partitioning = |columns: &[&str]| Partitioning {
+            columns: columns.iter().map(|column| column.to_string()).collect(),
+            max_values: 10,
+        };
+        assert!(partitioning(&["service.name", "host"]).validate().is_ok());
+        assert!(partitioning(&["host", "host"]).validate().is_err());
+        let err = partitioning(&["service.name", "service_name"])
+            .validate()
+            .unwrap_err();
+        assert!(err.contains("same directory name"), "{err}");
+    }
+}
@@ -22,10 +22,12 @@ use std::sync::Mutex;
 use std::thread;
 use std::time::Duration;
 
+use chrono::{DateTime, NaiveDate, Utc};
 use clokwerk::AsyncScheduler;
 use clokwerk::Job;
 use clokwerk::TimeUnits;
 use derive_more::Display;
+use itertools::Itertools;
 use once_cell::sync::Lazy;
 
 use crate::metadata::STREAM_INFO;
@@ -67,15 +69,17 @@ pub async fn load_retention_from_global() {
 pub fn init_scheduler(stream: &str, config: Retention) {
     log::info!("Setting up schedular for {stream}");
     let mut scheduler = AsyncScheduler::new();
-    for Task { action, days, .. } in config.tasks.into_iter() {
-        let func = match action {
-            Action::Delete => {
-                let stream = stream.to_string();
-                move || action::delete(stream.clone(), u32::from(days))
-            }
-        };
-
-        scheduler.every(1.day()).at("00:00").run(func);
+    for task in config.tasks.into_iter() {
+        let precise = task.precise;
+        let stream = stream.to_string();
+        let func = move || action::run(stream.clone(), task.clone());
+
+        // a precise window rolls forward through the day instead of once at midnight
+        if precise {
+            scheduler.every(1.hour()).run(func);
+        } else {
+            scheduler.every(1.day()).at("00:00").run(func);
+        }
     }
 
     let handler = thread::spawn(|| {
@@ -99,11 +103,143 @@ pub struct Retention {
     tasks: Vec<Task>,
 }
 
+impl Retention {
+    /// number of days after which data is deleted, if a delete or downsample
+    /// task for the whole stream is configured
+    pub fn delete_after_days(&self) -> Option<u32> {
+        self.tasks
+            .iter()
+            .filter(|task| task.partition.is_none())
+            .map(|task| u32::from(task.days))
+            .min()
+    }
+}
+
+// Partition a delete task is limited to, by values of one of the partition
+// columns of the stream. Files in the __other__ partition can hold any value,
+// they are only deleted by a task for the whole stream.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PartitionRetention {
+    pub column: String,
+    pub values: Vec<String>,
+}
+
+impl PartitionRetention {
+    fn validate(&self) -> Result<(), String> {
+        if self.column.is_empty() {
+            return Err("partition column cannot be empty".to_string());
+        }
+        if self.values.is_empty() {
+            return Err("partition values cannot be empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Rollups a downsample task replaces the rows of a stream with. Rows are
+// counted, and the `sums` columns summed, per `interval` and values of the
+// `keys` columns. Rollups are ingested into `stream` with the start of their
+// interval in the `bucket` column, a `count` column and a `sum_{column}`
+// column for every column of `sums`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Rollup {
+    pub stream: String,
+    pub interval: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sums: Vec<String>,
+}
+
+impl Rollup {
+    fn validate(&self) -> Result<(), String> {
+        if self.stream.trim().is_empty() {
+            return Err("rollup stream cannot be empty".to_string());
+        }
+        let interval = humantime::parse_duration(&self.interval)
+            .map_err(|err| format!("invalid rollup interval {}, {err}", self.interval))?;
+        if interval.as_secs() == 0 {
+            return Err("rollup interval should be at least 1s".to_string());
+        }
+        for key in &self.keys {
+            if key.is_empty() || key == ROLLUP_BUCKET_KEY || key == ROLLUP_COUNT_KEY {
+                return Err(format!("{key:?} can not be used as a rollup key"));
+            }
+        }
+        if self.sums.iter().any(String::is_empty) {
+            return Err("rollup sum column cannot be empty".to_string());
+        }
+        Ok(())
+    }
+
+    // Query computing the rollups of the rows of a stream
+    fn sql(&self, stream_name: &str, timestamp_key: &str) -> String {
+        let interval = humantime::parse_duration(&self.interval)
+            .map(|interval| interval.as_secs())
+            .unwrap_or(1);
+        let bucket = format!(
+            "date_bin(INTERVAL '{interval} seconds', {}, TIMESTAMP '1970-01-01T00:00:00')",
+            quote(timestamp_key)
+        );
+        let keys = self.keys.iter().map(|key| quote(key)).collect_vec();
+
+        let mut columns = vec![format!("{bucket} AS {}", quote(ROLLUP_BUCKET_KEY))];
+        columns.extend(keys.iter().cloned());
+        columns.push(format!("COUNT(*) AS {}", quote(ROLLUP_COUNT_KEY)));
+        for column in &self.sums {
+            columns.push(format!(
+                "SUM({}) AS {}",
+                quote(column),
+                quote(&format!("sum_{column}"))
+            ));
+        }
+        let mut group_by = vec![bucket];
+        group_by.extend(keys);
+
+        format!(
+            "SELECT {} FROM {} GROUP BY {}",
+            columns.join(", "),
+            quote(stream_name),
+            group_by.join(", ")
+        )
+    }
+}
+
+const ROLLUP_BUCKET_KEY: &str = "bucket";
+const ROLLUP_COUNT_KEY: &str = "count";
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+// What a single run of the delete task removed from a stream
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionRecord {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    pub files: u64,
+    pub events: u64,
+    pub ingestion_size: u64,
+    pub storage_size: u64,
+    pub deleted_at: DateTime<Utc>,
+    // stream the deleted rows were rolled up into by a downsample task
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rollup_stream: Option<String>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Task {
     description: String,
     action: Action,
     days: NonZeroU32,
+    // keep exactly `days` before now by trimming rows of the boundary date,
+    // instead of keeping whole dates
+    precise: bool,
+    // set for downsample tasks
+    rollup: Option<Rollup>,
+    // set for delete tasks of a partition only
+    partition: Option<PartitionRetention>,
 }
 
 #[derive(
@@ -112,6 +248,8 @@ pub struct Task {
 #[serde(rename_all = "lowercase")]
 enum Action {
     Delete,
+    // replace rows with rollups in another stream, then delete them
+    Downsample,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -119,6 +257,12 @@ struct TaskView {
     description: String,
     action: Action,
     duration: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    precise: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rollup: Option<Rollup>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    partition: Option<PartitionRetention>,
 }
 
 impl TryFrom<Vec<TaskView>> for Retention {
@@ -137,19 +281,50 @@ impl TryFrom<Vec<TaskView>> for Retention {
                 return Err("could not convert duration to an unsigned number".to_string());
             };
 
-            if set.contains(&task.action) {
+            // a stream can have a delete task per partition on top of the one for all its data
+            let key = (task.action, task.partition.clone());
+            if set.contains(&key) {
                 return Err(format!(
                     "Configuration contains two task both of action \"{}\"",
                     task.action
                 ));
             } else {
-                set.push(task.action)
+                set.push(key)
+            }
+
+            if let Some(partition) = &task.partition {
+                if task.action != Action::Delete {
+                    return Err("partition is only applicable to the delete action".to_string());
+                }
+                if task.precise {
+                    return Err("delete action of a partition can not be precise".to_string());
+                }
+                partition.validate()?;
+            }
+
+            match (&task.action, &task.rollup) {
+                (Action::Delete, None) => (),
+                (Action::Delete, Some(_)) => {
+                    return Err("rollup is only applicable to the downsample action".to_string())
+                }
+                (Action::Downsample, None) => {
+                    return Err("downsample action requires a rollup".to_string())
+                }
+                (Action::Downsample, Some(rollup)) => {
+                    if task.precise {
+                        return Err("downsample action can not be precise".to_string());
+                    }
+                    rollup.validate()?;
+                }
             }
 
             tasks.push(Task {
                 description: task.description,
                 action: task.action,
                 days,
+                precise: task.precise,
+                rollup: task.rollup,
+                partition: task.partition,
             })
         }
 
@@ -168,6 +343,9 @@ impl From<Retention> for Vec<TaskView> {
                     description: task.description,
                     action: task.action,
                     duration,
+                    precise: task.precise,
+                    rollup: task.rollup,
+                    partition: task.partition,
                 }
             })
             .collect()
@@ -175,56 +353,318 @@ impl From<Retention> for Vec<TaskView> {
 }
 
 mod action {
-    use chrono::{Days, NaiveDate, Utc};
-    use futures::{stream::FuturesUnordered, StreamExt};
+    use std::collections::HashMap;
+
+    use chrono::{DateTime, Days, NaiveDate, NaiveDateTime, NaiveTime, SecondsFormat, Utc};
+    use datafusion::arrow::json::writer::record_batches_to_json_rows;
     use itertools::Itertools;
     use relative_path::RelativePathBuf;
+    use serde_json::Value;
 
+    use super::{Action, PartitionRetention, RetentionRecord, Rollup, Task, ROLLUP_BUCKET_KEY};
+    use crate::handlers::http::{create_stream_if_not_exists, push_events};
+    use crate::metadata::STREAM_INFO;
     use crate::option::CONFIG;
+    use crate::query::{Query, QUERY_SESSION};
+    use crate::stats::{self, Stats};
+    use crate::storage::partition;
+    use crate::{catalog, storage::ObjectStorageError};
+
+    // keep the history of the last year of daily runs
+    const MAX_RETENTION_HISTORY: usize = 365;
+
+    pub(super) async fn run(stream_name: String, task: Task) {
+        let days = u32::from(task.days);
+        match (task.action, task.rollup) {
+            (Action::Delete, _) => match task.partition {
+                Some(partition) => delete_partition(stream_name, days, partition).await,
+                None => delete(stream_name, days, task.precise).await,
+            },
+            (Action::Downsample, Some(rollup)) => downsample(stream_name, days, rollup).await,
+            (Action::Downsample, None) => (),
+        }
+    }
 
-    pub(super) async fn delete(stream_name: String, days: u32) {
+    async fn delete(stream_name: String, days: u32, precise: bool) {
         log::info!("running retention task - delete");
+        let cutoff = precise.then(|| get_cutoff(Utc::now(), days as u64));
+        if let Err(err) = delete_dates(&stream_name, days, cutoff).await {
+            log::error!("Failed to run delete task {err:?}")
+        }
+    }
+
+    async fn delete_partition(stream_name: String, days: u32, partition: PartitionRetention) {
+        log::info!("running retention task - delete partition");
+        if let Err(err) = delete_partition_dates(&stream_name, days, &partition).await {
+            log::error!("Failed to run delete task of partition {err:?}")
+        }
+    }
+
+    async fn downsample(stream_name: String, days: u32, rollup: Rollup) {
+        log::info!("running retention task - downsample");
+        if let Err(err) = downsample_dates(&stream_name, days, &rollup).await {
+            log::error!("Failed to run downsample task {err}")
+        }
+    }
+
+    // Dates are downsampled one after the other, the rollups of a date are
+    // ingested into the rollup stream before its rows are deleted the same
+    // way the delete task does. Dates are marked as rolled up in object storage
+    // once their rollups are ingested, so that a run stopped before the rows
+    // were deleted does not ingest the rollups of the date again.
+    async fn downsample_dates(stream_name: &str, days: u32, rollup: &Rollup) -> anyhow::Result<()> {
+        if rollup.stream == stream_name {
+            anyhow::bail!("stream {stream_name} can not be rolled up into itself");
+        }
         let retain_until = get_retain_until(Utc::now().date_naive(), days as u64);
+        let storage = CONFIG.storage().get_object_store();
+        let dates = storage
+            .list_dates(stream_name)
+            .await?
+            .into_iter()
+            .map(|date| string_to_date(&date))
+            .filter(|date| *date < retain_until)
+            .sorted()
+            .collect_vec();
+        if dates.is_empty() {
+            return Ok(());
+        }
+        create_stream_if_not_exists(&rollup.stream).await?;
+        let mut rolled_up = storage.get_rolled_up_dates(stream_name).await?;
+        rolled_up.retain(|date| dates.contains(date));
+
+        let timestamp_key = STREAM_INFO.timestamp_key(stream_name)?;
+        let sql = rollup.sql(stream_name, &timestamp_key);
+        for date in dates {
+            if !rolled_up.contains(&date) {
+                let start = date.and_time(NaiveTime::MIN).and_utc();
+                let raw_logical_plan = QUERY_SESSION.state().create_logical_plan(&sql).await?;
+                let query = Query {
+                    raw_logical_plan,
+                    start,
+                    end: start + Days::new(1),
+                    filter_tag: None,
+                    row_filters: HashMap::new(),
+                    id_key: None,
+                };
+                let (records, _, _) = query.execute().await?;
+                let records = records.iter().collect_vec();
+                let rollups = into_rollups(record_batches_to_json_rows(&records)?);
+                if !rollups.is_empty() {
+                    push_events(&rollup.stream, rollups).await?;
+                }
+                rolled_up.insert(date);
+                storage.put_rolled_up_dates(stream_name, &rolled_up).await?;
+            }
 
-        let Ok(dates) = CONFIG
-            .storage()
-            .get_object_store()
-            .list_dates(&stream_name)
-            .await
-        else {
-            return;
+            let removed =
+                catalog::remove_date_partition(storage.clone(), stream_name, date).await?;
+            rolled_up.remove(&date);
+            storage.put_rolled_up_dates(stream_name, &rolled_up).await?;
+            let Some((removed, files)) = removed else {
+                continue;
+            };
+            account(
+                stream_name,
+                &removed,
+                RetentionRecord {
+                    start: date,
+                    end: date,
+                    files,
+                    events: removed.events,
+                    ingestion_size: removed.ingestion,
+                    storage_size: removed.storage,
+                    deleted_at: Utc::now(),
+                    rollup_stream: Some(rollup.stream.clone()),
+                },
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    // Rows of the rollup query as events, with the bucket as an RFC 3339 time
+    fn into_rollups(rows: Vec<serde_json::Map<String, Value>>) -> Vec<Value> {
+        rows.into_iter()
+            .map(|mut row| {
+                if let Some(Value::String(bucket)) = row.get_mut(ROLLUP_BUCKET_KEY) {
+                    if let Ok(time) = NaiveDateTime::parse_from_str(bucket, "%Y-%m-%dT%H:%M:%S%.f")
+                    {
+                        *bucket = time.and_utc().to_rfc3339_opts(SecondsFormat::Millis, true);
+                    }
+                }
+                Value::Object(row)
+            })
+            .collect()
+    }
+
+    // Record deleted data in the stats of the stream and its retention history
+    async fn account(
+        stream_name: &str,
+        removed: &Stats,
+        record: RetentionRecord,
+    ) -> Result<(), ObjectStorageError> {
+        let storage = CONFIG.storage().get_object_store();
+        stats::record_deleted(stream_name, "json", removed);
+        if let Some(stats) = stats::get_current_stats(stream_name, "json") {
+            storage.put_stats(stream_name, &stats).await?;
+        }
+
+        let mut history = storage.get_retention_history(stream_name).await?;
+        history.push(record);
+        if history.len() > MAX_RETENTION_HISTORY {
+            history.drain(..history.len() - MAX_RETENTION_HISTORY);
+        }
+        storage.put_retention_history(stream_name, &history).await
+    }
+
+    // Dates are removed one after the other through the catalog, so that the
+    // snapshot never refers to deleted files and the deleted data is accounted
+    // for in the stats of the stream and in its retention history.
+    // With a cutoff, dates before the one of the cutoff are removed and rows of
+    // that date older than the cutoff are trimmed.
+    async fn delete_dates(
+        stream_name: &str,
+        days: u32,
+        cutoff: Option<DateTime<Utc>>,
+    ) -> Result<(), ObjectStorageError> {
+        let retain_until = match cutoff {
+            Some(cutoff) => cutoff.date_naive(),
+            None => get_retain_until(Utc::now().date_naive(), days as u64),
         };
+        let storage = CONFIG.storage().get_object_store();
 
-        let dates_to_delete = dates
+        let dates_to_delete = storage
+            .list_dates(stream_name)
+            .await?
             .into_iter()
-            .filter(|date| string_to_date(date) < retain_until)
+            .map(|date| string_to_date(&date))
+            .filter(|date| *date < retain_until)
+            .sorted()
             .collect_vec();
 
-        let delete_tasks = FuturesUnordered::new();
-        for date in dates_to_delete {
-            let path = RelativePathBuf::from_iter([&stream_name, &date]);
-            delete_tasks.push(async move {
-                CONFIG
-                    .storage()
-                    .get_object_store()
-                    .delete_prefix(&path)
-                    .await
-            });
+        let mut removed = Stats::default();
+        let mut files = 0;
+        let mut trimmed = None;
+        if let Some(cutoff) = cutoff {
+            if let Some((stats, count)) =
+                catalog::trim_date_partition(storage.clone(), stream_name, cutoff).await?
+            {
+                removed = stats;
+                files = count;
+                trimmed = Some(cutoff.date_naive());
+            }
         }
 
-        let res: Vec<_> = delete_tasks.collect().await;
-
-        for res in res {
-            if let Err(err) = res {
-                log::error!("Failed to run delete task {err:?}")
+        let (Some(start), Some(end)) = (
+            dates_to_delete.first().or(trimmed.as_ref()),
+            trimmed.as_ref().or(dates_to_delete.last()),
+        ) else {
+            return Ok(());
+        };
+        for date in &dates_to_delete {
+            match catalog::remove_date_partition(storage.clone(), stream_name, *date).await? {
+                Some((stats, count)) => {
+                    removed.events += stats.events;
+                    removed.ingestion += stats.ingestion;
+                    removed.storage += stats.storage;
+                    removed.files += stats.files;
+                    files += count;
+                }
+                // data not listed in the snapshot is deleted without accounting
+                None => {
+                    let path = RelativePathBuf::from_iter([
+                        stream_name,
+                        &format!("date={}", date.format("%Y-%m-%d")),
+                    ]);
+                    storage.delete_prefix(&path).await?;
+                }
             }
         }
+
+        account(
+            stream_name,
+            &removed,
+            RetentionRecord {
+                start: *start,
+                end: *end,
+                files,
+                events: removed.events,
+                ingestion_size: removed.ingestion,
+                storage_size: removed.storage,
+                deleted_at: Utc::now(),
+                rollup_stream: None,
+            },
+        )
+        .await
+    }
+
+    // Files of the partition are removed from every date past retention through
+    // the catalog like the ones of whole dates, files of other partitions stay
+    async fn delete_partition_dates(
+        stream_name: &str,
+        days: u32,
+        partition: &PartitionRetention,
+    ) -> Result<(), ObjectStorageError> {
+        let retain_until = get_retain_until(Utc::now().date_naive(), days as u64);
+        let storage = CONFIG.storage().get_object_store();
+        let dates = storage
+            .list_dates(stream_name)
+            .await?
+            .into_iter()
+            .map(|date| string_to_date(&date))
+            .filter(|date| *date < retain_until)
+            .sorted()
+            .collect_vec();
+
+        let mut removed = Stats::default();
+        let mut files = 0;
+        let mut range: Option<(NaiveDate, NaiveDate)> = None;
+        for date in dates {
+            let in_partition =
+                |path: &str| partition::in_partition(path, &partition.column, &partition.values);
+            let Some((stats, count)) =
+                catalog::remove_date_files(storage.clone(), stream_name, date, in_partition)
+                    .await?
+            else {
+                continue;
+            };
+            removed.events += stats.events;
+            removed.ingestion += stats.ingestion;
+            removed.storage += stats.storage;
+            removed.files += stats.files;
+            files += count;
+            range = Some(range.map_or((date, date), |(start, _)| (start, date)));
+        }
+
+        let Some((start, end)) = range else {
+            return Ok(());
+        };
+        account(
+            stream_name,
+            &removed,
+            RetentionRecord {
+                start,
+                end,
+                files,
+                events: removed.events,
+                ingestion_size: removed.ingestion,
+                storage_size: removed.storage,
+                deleted_at: Utc::now(),
+                rollup_stream: None,
+            },
+        )
+        .await
     }
 
     fn get_retain_until(current_date: NaiveDate, days: u64) -> NaiveDate {
         current_date - Days::new(days)
     }
 
+    fn get_cutoff(now: DateTime<Utc>, days: u64) -> DateTime<Utc> {
+        now - Days::new(days)
+    }
+
     fn string_to_date(date: &str) -> NaiveDate {
         let year = date[5..9].parse().unwrap();
         let month = date[10..12].parse().unwrap();
@@ -235,10 +675,13 @@ mod action {
 
     #[cfg(test)]
     mod tests {
-        use chrono::{Datelike, NaiveDate};
+        use chrono::{Datelike, NaiveDate, TimeZone, Utc};
+
+        use serde_json::json;
 
         use super::get_retain_until;
         use super::string_to_date;
+        use super::{super::Retention, get_cutoff, into_rollups};
 
         #[test]
         fn test_time_from_string() {
@@ -252,5 +695,85 @@ mod action {
             let date = get_retain_until(current_date, 1);
             assert_eq!(date.day(), 1)
         }
+
+        #[test]
+        fn test_precise_cutoff() {
+            let now = Utc.with_ymd_and_hms(2000, 1, 2, 15, 30, 0).unwrap();
+            assert_eq!(
+                get_cutoff(now, 1),
+                Utc.with_ymd_and_hms(2000, 1, 1, 15, 30, 0).unwrap()
+            );
+        }
+
+        #[test]
+        fn test_precise_flag_roundtrip() {
+            let config =
+                r#"[{"description":"rolling","action":"delete","duration":"7d","precise":true}]"#;
+            let retention: Retention = serde_json::from_str(config).unwrap();
+            assert!(retention.tasks[0].precise);
+            assert_eq!(serde_json::to_string(&retention).unwrap(), config);
+
+            let config = r#"[{"description":"daily","action":"delete","duration":"7d"}]"#;
+            let retention: Retention = serde_json::from_str(config).unwrap();
+            assert!(!retention.tasks[0].precise);
+            assert_eq!(serde_json::to_string(&retention).unwrap(), config);
+        }
+
+        #[test]
+        fn downsample_task_config() {
+            let config = r#"[{"description":"rollup","action":"downsample","duration":"30d","rollup":{"stream":"app_hourly","interval":"1h","keys":["host"],"sums":["bytes"]}},{"description":"daily","action":"delete","duration":"90d"}]"#;
+            let retention: Retention = serde_json::from_str(config).unwrap();
+            assert_eq!(serde_json::to_string(&retention).unwrap(), config);
+            assert_eq!(retention.delete_after_days(), Some(30));
+
+            let rollup = retention.tasks[0].rollup.as_ref().unwrap();
+            assert_eq!(
+                rollup.sql("app", "p_timestamp"),
+                "SELECT date_bin(INTERVAL '3600 seconds', \"p_timestamp\", TIMESTAMP '1970-01-01T00:00:00') AS \"bucket\", \
+                 \"host\", COUNT(*) AS \"count\", SUM(\"bytes\") AS \"sum_bytes\" FROM \"app\" \
+                 GROUP BY date_bin(INTERVAL '3600 seconds', \"p_timestamp\", TIMESTAMP '1970-01-01T00:00:00'), \"host\""
+            );
+
+            for config in [
+                r#"[{"description":"d","action":"downsample","duration":"30d"}]"#,
+                r#"[{"description":"d","action":"delete","duration":"30d","rollup":{"stream":"s","interval":"1h"}}]"#,
+                r#"[{"description":"d","action":"downsample","duration":"30d","rollup":{"stream":"s","interval":"1ms"}}]"#,
+                r#"[{"description":"d","action":"downsample","duration":"30d","rollup":{"stream":"s","interval":"1h","keys":["count"]}}]"#,
+            ] {
+                assert!(serde_json::from_str::<Retention>(config).is_err());
+            }
+        }
+
+        #[test]
+        fn partition_delete_task_config() {
+            let config = r#"[{"description":"all","action":"delete","duration":"90d"},{"description":"debug","action":"delete","duration":"7d","partition":{"column":"service.name","values":["debug-svc"]}}]"#;
+            let retention: Retention = serde_json::from_str(config).unwrap();
+            assert_eq!(serde_json::to_string(&retention).unwrap(), config);
+            // data of other partitions is kept until the task of the whole stream
+            assert_eq!(retention.delete_after_days(), Some(90));
+
+            for config in [
+                r#"[{"description":"d","action":"delete","duration":"7d","partition":{"column":"host","values":[]}}]"#,
+                r#"[{"description":"d","action":"delete","duration":"7d","precise":true,"partition":{"column":"host","values":["a"]}}]"#,
+                r#"[{"description":"d","action":"downsample","duration":"7d","rollup":{"stream":"s","interval":"1h"},"partition":{"column":"host","values":["a"]}}]"#,
+                r#"[{"description":"d","action":"delete","duration":"7d","partition":{"column":"host","values":["a"]}},{"description":"e","action":"delete","duration":"9d","partition":{"column":"host","values":["a"]}}]"#,
+            ] {
+                assert!(serde_json::from_str::<Retention>(config).is_err());
+            }
+        }
+
+        #[test]
+        fn rollup_buckets_are_rfc3339() {
+            let rows = vec![
+                json!({"bucket": "2024-01-01T10:00:00", "host": "a", "count": 3})
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            ];
+            assert_eq!(
+                into_rollups(rows),
+                vec![json!({"bucket": "2024-01-01T10:00:00.000Z", "host": "a", "count": 3})]
+            );
+        }
     }
 }
@@ -41,6 +41,7 @@ use std::time::{Duration, Instant};
 use crate::metrics::storage::{s3::REQUEST_RESPONSE_TIME, StorageMetrics};
 use crate::storage::{LogStream, ObjectStorage, ObjectStorageError};
 
+use super::encryption::{self, EncryptionLayer};
 use super::metrics_layer::MetricLayer;
 use super::{object_storage, ObjectStorageProvider};
 
@@ -183,7 +184,11 @@ impl ObjectStorageProvider for S3Config {
 
         let object_store_registry: DefaultObjectStoreRegistry = DefaultObjectStoreRegistry::new();
         let url = ObjectStoreUrl::parse(format!("s3://{}", &self.bucket_name)).unwrap();
-        object_store_registry.register_store(url.as_ref(), Arc::new(s3));
+        match encryption::KEYRING.as_ref() {
+            Some(keyring) => object_store_registry
+                .register_store(url.as_ref(), Arc::new(EncryptionLayer::new(s3, keyring))),
+            None => object_store_registry.register_store(url.as_ref(), Arc::new(s3)),
+        };
 
         RuntimeConfig::new().with_object_store_registry(Arc::new(object_store_registry))
     }
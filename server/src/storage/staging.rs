@@ -22,14 +22,19 @@ use std::{
     fs,
     path::{Path, PathBuf},
     process,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
 };
 
+use arrow_array::RecordBatch;
 use arrow_schema::{ArrowError, Schema};
 use chrono::{NaiveDateTime, Timelike, Utc};
 use parquet::{
     arrow::ArrowWriter,
-    basic::Encoding,
+    basic::{Compression, Encoding},
     errors::ParquetError,
     file::properties::{WriterProperties, WriterPropertiesBuilder},
     format::SortingColumn,
@@ -38,6 +43,7 @@ use parquet::{
 
 use crate::{
     event::DEFAULT_TIMESTAMP_KEY,
+    metadata::STREAM_INFO,
     metrics,
     option::CONFIG,
     storage::OBJECT_STORE_DATA_GRANULARITY,
@@ -59,31 +65,36 @@ impl StorageDir {
         Self { data_path }
     }
 
-    pub fn file_time_suffix(time: NaiveDateTime, extention: &str) -> String {
+    fn file_time_prefix(time: NaiveDateTime) -> String {
         let uri = utils::date_to_prefix(time.date())
             + &utils::hour_to_prefix(time.hour())
             + &utils::minute_to_prefix(time.minute(), OBJECT_STORE_DATA_GRANULARITY).unwrap();
-        let local_uri = str::replace(&uri, "/", ".");
+        str::replace(&uri, "/", ".")
+    }
+
+    // partition is empty or the segments of partition directories, each ending with a dot
+    pub fn file_time_suffix(time: NaiveDateTime, partition: &str, extention: &str) -> String {
+        let local_uri = Self::file_time_prefix(time);
         let hostname = utils::hostname_unchecked();
-        format!("{local_uri}{hostname}.{extention}")
+        format!("{local_uri}{partition}{hostname}.{extention}")
     }
 
-    fn filename_by_time(stream_hash: &str, time: NaiveDateTime) -> String {
+    fn filename_by_time(stream_hash: &str, partition: &str, time: NaiveDateTime) -> String {
         format!(
             "{}.{}",
             stream_hash,
-            Self::file_time_suffix(time, ARROW_FILE_EXTENSION)
+            Self::file_time_suffix(time, partition, ARROW_FILE_EXTENSION)
         )
     }
 
-    fn filename_by_current_time(stream_hash: &str) -> String {
+    fn filename_by_current_time(stream_hash: &str, partition: &str) -> String {
         let datetime = Utc::now();
-        Self::filename_by_time(stream_hash, datetime.naive_utc())
+        Self::filename_by_time(stream_hash, partition, datetime.naive_utc())
     }
 
-    pub fn path_by_current_time(&self, stream_hash: &str) -> PathBuf {
+    pub fn path_by_current_time(&self, stream_hash: &str, partition: &str) -> PathBuf {
         self.data_path
-            .join(Self::filename_by_current_time(stream_hash))
+            .join(Self::filename_by_current_time(stream_hash, partition))
     }
 
     pub fn arrow_files(&self) -> Vec<PathBuf> {
@@ -120,17 +131,15 @@ impl StorageDir {
         &self,
         exclude: NaiveDateTime,
     ) -> HashMap<PathBuf, Vec<PathBuf>> {
-        let hot_filename = StorageDir::file_time_suffix(exclude, ARROW_FILE_EXTENSION);
+        let hot_prefix = StorageDir::file_time_prefix(exclude);
+        let hot_suffix = format!("{}.{}", utils::hostname_unchecked(), ARROW_FILE_EXTENSION);
         // hashmap <time, vec[paths]> but exclude where hot filename matches
         let mut grouped_arrow_file: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
         let mut arrow_files = self.arrow_files();
         arrow_files.retain(|path| {
-            !path
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .ends_with(&hot_filename)
+            let filename = path.file_name().unwrap().to_str().unwrap();
+            let (_, filename) = filename.split_once('.').unwrap();
+            !(filename.starts_with(&hot_prefix) && filename.ends_with(&hot_suffix))
         });
         for arrow_file_path in arrow_files {
             let key = Self::arrow_path_to_parquet(&arrow_file_path);
@@ -164,14 +173,60 @@ impl StorageDir {
     }
 }
 
+// set while syncing staged files with the object store fails, staged records
+// then pile up on disk until the store recovers
+static UPLOADS_FAILING: AtomicBool = AtomicBool::new(false);
+
+pub fn uploads_failing() -> bool {
+    UPLOADS_FAILING.load(Ordering::Relaxed)
+}
+
+pub fn set_uploads_failing(failing: bool) {
+    if UPLOADS_FAILING.swap(failing, Ordering::Relaxed) && !failing {
+        log::info!("object store sync recovered, uploading staged records");
+    }
+}
+
+/// Whether queries of the stream miss staged records, which are only
+/// queryable once uploaded and can't be while uploads are failing
+pub fn pending_upload(stream_name: &str) -> bool {
+    uploads_failing() && !StorageDir::new(stream_name).parquet_files().is_empty()
+}
+
 #[allow(unused)]
 pub fn to_parquet_path(stream_name: &str, time: NaiveDateTime) -> PathBuf {
     let data_path = CONFIG.parseable.local_stream_data_path(stream_name);
-    let dir = StorageDir::file_time_suffix(time, PARQUET_FILE_EXTENSION);
+    let dir = StorageDir::file_time_suffix(time, "", PARQUET_FILE_EXTENSION);
 
     data_path.join(dir)
 }
 
+// Path of a staged parquet file relative to its stream in the object store.
+// The leading `name=value` segments of the file name (date, hour, minute and
+// partition columns) are its directories.
+pub fn object_store_suffix(filename: &str) -> String {
+    let dirs = filename
+        .split('.')
+        .take_while(|segment| segment.contains('='))
+        .count();
+    str::replacen(filename, ".", "/", dirs)
+}
+
+// Directories in the object store, relative to the stream, of the data of a
+// staging arrow file. Arrow files are named after the schema of their records
+// followed by the name of the parquet file they become.
+pub fn object_store_dir(arrow_path: &Path) -> String {
+    let filename = arrow_path
+        .file_name()
+        .and_then(|filename| filename.to_str())
+        .unwrap_or_default();
+    let (_, filename) = filename.split_once('.').unwrap_or_default();
+    object_store_suffix(filename)
+        .rsplit_once('/')
+        .map(|(dir, _)| dir.to_string())
+        .unwrap_or_default()
+}
+
 pub fn convert_disk_files_to_parquet(
     stream: &str,
     dir: &StorageDir,
@@ -200,19 +255,34 @@ pub fn convert_disk_files_to_parquet(
 
         let record_reader = MergedReverseRecordReader::try_new(&files).unwrap();
 
-        let parquet_file = fs::File::create(&parquet_path).map_err(|_| MoveDataError::Create)?;
-
-        let props = parquet_writer_props().build();
+        let props = stream_parquet_props(stream);
+        let max_file_size = STREAM_INFO.max_file_size(stream).ok().flatten();
         let merged_schema = record_reader.merged_schema();
         schemas.push(merged_schema.clone());
         let schema = Arc::new(merged_schema);
-        let mut writer = ArrowWriter::try_new(parquet_file, schema.clone(), Some(props))?;
-
-        for ref record in record_reader.merged_iter(schema) {
-            writer.write(record)?;
-        }
-
-        writer.close()?;
+        let started = Instant::now();
+        let written = write_parquet(
+            &parquet_path,
+            record_reader.merged_iter(schema.clone()),
+            schema,
+            props,
+            max_file_size,
+        );
+        let written = match written {
+            Ok(written) => written,
+            Err(err) => {
+                metrics::PARQUET_FLUSH_FAILURES
+                    .with_label_values(&[stream])
+                    .inc();
+                return Err(err);
+            }
+        };
+        metrics::PARQUET_FLUSH_DURATION_SECONDS
+            .with_label_values(&[stream])
+            .observe(started.elapsed().as_secs_f64());
+        metrics::PARQUET_FLUSH_BYTES
+            .with_label_values(&[stream])
+            .observe(parquet_size(&parquet_path, written) as f64);
 
         for file in files {
             if fs::remove_file(file).is_err() {
@@ -229,12 +299,98 @@ pub fn convert_disk_files_to_parquet(
     }
 }
 
-fn parquet_writer_props() -> WriterPropertiesBuilder {
+// Write the batches to parquet files starting at `path`. With a maximum file size
+// a new file is started once the current one reaches it, files after the first
+// are numbered `<name>.1.data.parquet`, `<name>.2.data.parquet` and so on.
+// Files are only rotated between batches so a file can exceed the size by up to a batch.
+// Returns the number of files written.
+fn write_parquet(
+    path: &Path,
+    batches: impl Iterator<Item = RecordBatch>,
+    schema: Arc<Schema>,
+    props: WriterProperties,
+    max_file_size: Option<u64>,
+) -> Result<usize, MoveDataError> {
+    let new_writer = |path: &Path| -> Result<ArrowWriter<fs::File>, MoveDataError> {
+        let file = fs::File::create(path).map_err(|_| MoveDataError::Create)?;
+        Ok(ArrowWriter::try_new(
+            file,
+            schema.clone(),
+            Some(props.clone()),
+        )?)
+    };
+
+    let mut files = 1;
+    let mut writer = Some(new_writer(path)?);
+    for batch in batches {
+        let current = match &mut writer {
+            Some(writer) => writer,
+            None => {
+                files += 1;
+                writer.insert(new_writer(&rotated_path(path, files - 1))?)
+            }
+        };
+        current.write(&batch)?;
+        if max_file_size.is_some_and(|max| written_size(current) >= max) {
+            writer.take().expect("writer is open").close()?;
+        }
+    }
+    if let Some(writer) = writer {
+        writer.close()?;
+    }
+    Ok(files)
+}
+
+// size of the row groups written so far along with the buffered rows
+fn written_size(writer: &ArrowWriter<fs::File>) -> u64 {
+    let flushed: i64 = writer
+        .flushed_row_groups()
+        .iter()
+        .map(|row_group| row_group.compressed_size())
+        .sum();
+    flushed as u64 + writer.in_progress_size() as u64
+}
+
+// size on disk of the `files` parquet files written starting at `path`
+fn parquet_size(path: &Path, files: usize) -> u64 {
+    (0..files)
+        .map(|index| match index {
+            0 => path.to_path_buf(),
+            index => rotated_path(path, index),
+        })
+        .filter_map(|path| path.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let filename = path.file_name().unwrap().to_str().unwrap();
+    let name = filename
+        .strip_suffix(PARQUET_FILE_EXTENSION)
+        .expect("staged parquet files end with the parquet extension");
+    path.with_file_name(format!("{name}{index}.{PARQUET_FILE_EXTENSION}"))
+}
+
+/// Properties of the parquet files written for a stream
+pub fn stream_parquet_props(stream: &str) -> WriterProperties {
+    let timestamp_key = STREAM_INFO
+        .timestamp_key(stream)
+        .unwrap_or_else(|_| DEFAULT_TIMESTAMP_KEY.to_string());
+    let compression = STREAM_INFO
+        .compression(stream)
+        .ok()
+        .flatten()
+        .map(Compression::from)
+        .unwrap_or_else(|| CONFIG.parseable.parquet_compression.into());
+    parquet_writer_props(&timestamp_key, compression).build()
+}
+
+fn parquet_writer_props(timestamp_key: &str, compression: Compression) -> WriterPropertiesBuilder {
     WriterProperties::builder()
         .set_max_row_group_size(CONFIG.parseable.row_group_size)
-        .set_compression(CONFIG.parseable.parquet_compression.into())
+        .set_compression(compression)
         .set_column_encoding(
-            ColumnPath::new(vec![DEFAULT_TIMESTAMP_KEY.to_string()]),
+            ColumnPath::new(vec![timestamp_key.to_string()]),
             Encoding::DELTA_BINARY_PACKED,
         )
         .set_sorting_columns(Some(vec![SortingColumn {
@@ -255,3 +411,87 @@ pub enum MoveDataError {
     #[error("Could not generate parquet file")]
     Create,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use arrow_array::{Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use parquet::{
+        arrow::arrow_reader::ParquetRecordBatchReaderBuilder, basic::Compression,
+        file::properties::WriterProperties,
+    };
+
+    use super::{object_store_dir, object_store_suffix, parquet_size, rotated_path, write_parquet};
+
+    #[test]
+    fn files_are_rotated_at_max_size() {
+        let dir = std::env::temp_dir().join(format!("rotate-{}", ulid::Ulid::new()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("date=2024-01-01.hour=10.minute=05.host.data.parquet");
+
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let batch_schema = schema.clone();
+        let batches = (0..10).map(move |i| {
+            RecordBatch::try_new(
+                batch_schema.clone(),
+                vec![Arc::new(Int64Array::from_iter_values(
+                    i * 10_000..(i + 1) * 10_000,
+                ))],
+            )
+            .unwrap()
+        });
+        // small row groups so that the written size grows with every batch
+        let props = WriterProperties::builder()
+            .set_compression(Compression::UNCOMPRESSED)
+            .set_max_row_group_size(10_000)
+            .build();
+
+        let files = write_parquet(&path, batches, schema, props, Some(100_000)).unwrap();
+
+        assert!(files > 1);
+        let mut rows = 0;
+        let mut size = 0;
+        for index in 0..files {
+            let path = if index == 0 {
+                path.clone()
+            } else {
+                rotated_path(&path, index)
+            };
+            let file = std::fs::File::open(&path).unwrap();
+            size += file.metadata().unwrap().len();
+            let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+            rows += reader.metadata().file_metadata().num_rows();
+        }
+        assert_eq!(rows, 100_000);
+        assert_eq!(parquet_size(&path, files), size);
+        assert_eq!(
+            rotated_path(&path, 2).file_name().unwrap(),
+            "date=2024-01-01.hour=10.minute=05.host.2.data.parquet"
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn partition_segments_become_directories() {
+        assert_eq!(
+            object_store_suffix("date=2024-01-01.hour=10.minute=05.host.data.parquet"),
+            "date=2024-01-01/hour=10/minute=05/host.data.parquet"
+        );
+        assert_eq!(
+            object_store_suffix(
+                "date=2024-01-01.hour=10.minute=05.service_name=web.host.data.parquet"
+            ),
+            "date=2024-01-01/hour=10/minute=05/service_name=web/host.data.parquet"
+        );
+        assert_eq!(
+            object_store_dir(Path::new(
+                "/staging/app/abc.date=2024-01-01.hour=10.minute=05.service_name=web.host.data.arrows"
+            )),
+            "date=2024-01-01/hour=10/minute=05/service_name=web"
+        );
+    }
+}
@@ -26,13 +26,16 @@ use once_cell::sync::OnceCell;
 use std::io;
 
 use crate::{
+    event::{body::BodyConfig, severity::SeverityMapping},
     option::CONFIG,
+    quota::IngestQuota,
     rbac::{role::model::DefaultPrivilege, user::User},
     storage::ObjectStorageError,
     utils::uid,
 };
 
 use super::object_storage::PARSEABLE_METADATA_FILE_NAME;
+use super::retention::Retention;
 
 // Expose some static variables for internal usage
 pub static STORAGE_METADATA: OnceCell<StaticStorageMetadata> = OnceCell::new();
@@ -59,6 +62,28 @@ pub struct StorageMetadata {
     pub roles: HashMap<String, Vec<DefaultPrivilege>>,
     #[serde(default)]
     pub default_role: Option<String>,
+    #[serde(default)]
+    pub stream_templates: HashMap<String, StreamTemplate>,
+}
+
+// Named bundle of stream settings applied to streams created from it
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamTemplate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<Retention>,
+    #[serde(default)]
+    pub cache_enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_pattern: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity_mapping: Option<SeverityMapping>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_config: Option<BodyConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<IngestQuota>,
 }
 
 impl StorageMetadata {
@@ -73,6 +98,7 @@ impl StorageMetadata {
             streams: Vec::new(),
             roles: HashMap::default(),
             default_role: None,
+            stream_templates: HashMap::default(),
         }
     }
 
@@ -18,6 +18,7 @@
 
 pub mod actix;
 pub mod arrow;
+pub mod correlation_id;
 pub mod header_parsing;
 pub mod json;
 pub mod uid;
@@ -87,6 +88,37 @@ pub fn minute_to_prefix(minute: u32, data_granularity: u32) -> Option<String> {
     ))
 }
 
+// match name against a pattern where `*` matches any run of characters and `?` a single one
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` and of the name when it was seen, to backtrack to
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
 pub struct TimePeriod {
     start: DateTime<Utc>,
     end: DateTime<Utc>,
@@ -240,7 +272,19 @@ mod tests {
     use chrono::DateTime;
     use rstest::*;
 
-    use super::TimePeriod;
+    use super::{glob_match, TimePeriod};
+
+    #[test]
+    fn glob_match_patterns() {
+        assert!(glob_match("proj-*", "proj-web"));
+        assert!(glob_match("proj-*", "proj-"));
+        assert!(glob_match("*-logs", "app-logs"));
+        assert!(glob_match("a?c*d", "abcxxd"));
+        assert!(glob_match("app", "app"));
+        assert!(!glob_match("proj-*", "project"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(!glob_match("app", "apps"));
+    }
 
     fn time_period_from_str(start: &str, end: &str) -> TimePeriod {
         TimePeriod::new(
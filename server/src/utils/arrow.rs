@@ -19,10 +19,14 @@
 
 use std::sync::Arc;
 
-use arrow_array::{Array, RecordBatch};
-use arrow_schema::Schema;
+use arrow_array::{Array, BooleanArray, RecordBatch, StringArray};
+use arrow_schema::{ArrowError, DataType, Schema};
+use datafusion::arrow::compute::kernels::cmp::eq;
+use datafusion::arrow::compute::{cast, filter_record_batch, or_kleene};
 use itertools::Itertools;
 
+use crate::rbac::role::RowFilter;
+
 pub mod batch_adapter;
 pub mod merged_reader;
 pub mod reverse_reader;
@@ -30,6 +34,68 @@ pub mod reverse_reader;
 pub use batch_adapter::adapt_batch;
 pub use merged_reader::MergedRecordReader;
 
+// Columns of a comma separated list such as `host, status` of the X-P-Select header
+pub fn parse_columns(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|column| !column.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+// Indices of the columns in the schema in the order they are listed.
+// Fails with the columns missing from the schema
+pub fn projection(schema: &Schema, columns: &[String]) -> Result<Vec<usize>, Vec<String>> {
+    let (indices, missing): (Vec<_>, Vec<_>) = columns
+        .iter()
+        .map(|column| schema.index_of(column).map_err(|_| column.clone()))
+        .partition_result();
+    if missing.is_empty() {
+        Ok(indices)
+    } else {
+        Err(missing)
+    }
+}
+
+// Indices of the columns with the listed ones first, in the order they are
+// listed, followed by the rest in their original order.
+// Listed columns which are not among the columns are ignored
+pub fn column_order(columns: &[&str], order: &[String]) -> Vec<usize> {
+    let leading = order
+        .iter()
+        .filter_map(|name| columns.iter().position(|column| column == name))
+        .unique()
+        .collect_vec();
+    let rest = (0..columns.len()).filter(|index| !leading.contains(index));
+    leading.iter().copied().chain(rest).collect()
+}
+
+// Fingerprint of the names, types and nullability of the fields of the schema,
+// independent of their order, which changes whenever the schema does
+pub fn schema_fingerprint(schema: &Schema) -> String {
+    let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+    for field in schema.fields().iter().sorted_by_key(|field| field.name()) {
+        hasher.update(&serde_json::to_vec(field).expect("field can be serialized"));
+    }
+    format!("{:x}", hasher.digest())
+}
+
+// Rows of the batch visible through any of the row filters of a role, those
+// where the value of the column of a filter, as a string, is its value
+pub fn filter_rows(rb: &RecordBatch, filters: &[RowFilter]) -> Result<RecordBatch, ArrowError> {
+    let mut visible = BooleanArray::from(vec![false; rb.num_rows()]);
+    for filter in filters {
+        let Some(column) = rb.column_by_name(&filter.column) else {
+            continue;
+        };
+        let values = cast(column, &DataType::Utf8)?;
+        let matches = eq(&values, &StringArray::new_scalar(&filter.value))?;
+        visible = or_kleene(&visible, &matches)?;
+    }
+    filter_record_batch(rb, &visible)
+}
+
 pub fn replace_columns(
     schema: Arc<Schema>,
     batch: &RecordBatch,
@@ -50,7 +116,47 @@ mod tests {
     use arrow_array::{Array, Int32Array, RecordBatch};
     use arrow_schema::{DataType, Field, Schema};
 
-    use super::replace_columns;
+    use super::{
+        column_order, filter_rows, parse_columns, projection, replace_columns, schema_fingerprint,
+    };
+    use crate::rbac::role::RowFilter;
+
+    #[test]
+    fn rows_filtered_by_any_row_filter() {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "tenant",
+            DataType::Int32,
+            true,
+        )]));
+        let rb = RecordBatch::try_new(
+            schema,
+            vec![Arc::new(Int32Array::from(vec![
+                Some(1),
+                None,
+                Some(2),
+                Some(3),
+            ]))],
+        )
+        .unwrap();
+        let filter = |value: &str| RowFilter {
+            column: "tenant".to_string(),
+            value: value.to_string(),
+        };
+
+        let visible = filter_rows(&rb, &[filter("1"), filter("3")]).unwrap();
+        let tenants = visible
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int32Array>()
+            .unwrap();
+        assert_eq!(tenants.values(), &[1, 3]);
+
+        let missing = RowFilter {
+            column: "other".to_string(),
+            value: "1".to_string(),
+        };
+        assert_eq!(filter_rows(&rb, &[missing]).unwrap().num_rows(), 0);
+    }
 
     #[test]
     fn check_replace() {
@@ -80,6 +186,50 @@ mod tests {
         assert_eq!(new_rb.num_columns(), 3);
         assert_eq!(new_rb.num_rows(), 3)
     }
+
+    #[test]
+    fn fingerprint_ignores_field_order() {
+        let a = Field::new("a", DataType::Int64, true);
+        let b = Field::new("b", DataType::Utf8, true);
+        let fingerprint = schema_fingerprint(&Schema::new(vec![a.clone(), b.clone()]));
+
+        assert_eq!(
+            fingerprint,
+            schema_fingerprint(&Schema::new(vec![b.clone(), a]))
+        );
+        assert_ne!(
+            fingerprint,
+            schema_fingerprint(&Schema::new(vec![
+                Field::new("a", DataType::Float64, true),
+                b
+            ]))
+        );
+    }
+
+    #[test]
+    fn listed_columns_lead() {
+        let columns = ["a", "p_timestamp", "b", "severity_text", "c"];
+        let order = ["severity_text", "missing", "p_timestamp"].map(String::from);
+        assert_eq!(column_order(&columns, &order), vec![3, 1, 0, 2, 4]);
+        assert_eq!(column_order(&columns, &[]), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn project_listed_columns() {
+        let schema = Schema::new(vec![
+            Field::new("a", DataType::Int32, false),
+            Field::new("b", DataType::Int32, false),
+            Field::new("c", DataType::Int32, false),
+        ]);
+
+        let columns = parse_columns(" c, a ,,");
+        assert_eq!(columns, vec!["c", "a"]);
+        assert_eq!(projection(&schema, &columns), Ok(vec![2, 0]));
+        assert_eq!(
+            projection(&schema, &parse_columns("a,x,y")),
+            Err(vec!["x".to_string(), "y".to_string()])
+        );
+    }
 }
 
 pub fn get_field<'a>(
@@ -0,0 +1,152 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::sync::Arc;
+
+use arrow_array::{Array, StringArray};
+use arrow_schema::DataType;
+use datafusion::arrow::compute::cast;
+use datafusion::common::DataFusionError;
+use datafusion::logical_expr::{
+    ColumnarValue, ReturnTypeFunction, ScalarFunctionImplementation, ScalarUDF, Signature,
+    Volatility,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+pub const TRACE_ID_KEY: &str = "trace_id";
+pub const SPAN_ID_KEY: &str = "span_id";
+pub const MASK_ID: &str = "mask_id";
+
+/// Normalize a trace or span id to lowercase hex without surrounding whitespace,
+/// so the same id sent by different clients is stored and compared the same way.
+pub fn normalize(id: &str) -> String {
+    id.trim().to_ascii_lowercase()
+}
+
+/// Deterministically hash an id with the given key.
+/// The same id always maps to the same value for a key, so correlating
+/// events still works, while the original id cannot be derived from it.
+/// The hash has the length of the normalized id to keep the id format.
+pub fn hash(key: &[u8], id: &str) -> String {
+    let id = normalize(id);
+    let mut hashed = hash_with(key, &id);
+    hashed.truncate(id.len().max(1));
+    hashed
+}
+
+/// Key of a tenant derived from the configured key. A tenant is the set of
+/// roles restricting a user, users sharing them see the same hashed ids,
+/// which can not be matched with the ones seen in another tenant.
+pub fn tenant_key(key: &str, roles: &[String]) -> String {
+    hash_with(key.as_bytes(), &roles.join("\n"))
+}
+
+fn hash_with(key: &[u8], value: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts keys of any size");
+    mac.update(value.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Function hashing the ids of its argument with the given key. The key is
+/// part of the function and not an argument, so it doesn't show in plans.
+/// Not registered with the session, queries get it through the scans of
+/// id columns only.
+pub fn mask_udf(key: String) -> ScalarUDF {
+    let return_type: ReturnTypeFunction = Arc::new(|_| Ok(Arc::new(DataType::Utf8)));
+    let fun: ScalarFunctionImplementation = Arc::new(move |args| mask(args, key.as_bytes()));
+    ScalarUDF::new(
+        MASK_ID,
+        &Signature::any(1, Volatility::Immutable),
+        &return_type,
+        &fun,
+    )
+}
+
+fn mask(args: &[ColumnarValue], key: &[u8]) -> Result<ColumnarValue, DataFusionError> {
+    let len = match &args[0] {
+        ColumnarValue::Array(array) => array.len(),
+        ColumnarValue::Scalar(_) => 1,
+    };
+    let ids = cast(&args[0].clone().into_array(len), &DataType::Utf8)?;
+    let ids = ids
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .expect("cast to strings");
+    let hashed: StringArray = ids.iter().map(|id| id.map(|id| hash(key, id))).collect();
+    Ok(ColumnarValue::Array(Arc::new(hashed)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Array, StringArray};
+    use datafusion::logical_expr::ColumnarValue;
+
+    use super::{hash, mask, tenant_key};
+
+    #[test]
+    fn hash_is_deterministic_per_key() {
+        let id = "5b8efff798038103d269b633813fc60c";
+        assert_eq!(hash(b"a", id), hash(b"a", &id.to_uppercase()));
+        assert_ne!(hash(b"a", id), hash(b"b", id));
+        assert_ne!(hash(b"a", id), id);
+        assert_eq!(hash(b"a", id).len(), id.len());
+    }
+
+    #[test]
+    fn keys_derived_per_tenant() {
+        let tenant = |roles: &[&str]| {
+            roles
+                .iter()
+                .map(|role| role.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            tenant_key("key", &tenant(&["acme"])),
+            tenant_key("key", &tenant(&["acme"]))
+        );
+        assert_ne!(
+            tenant_key("key", &tenant(&["acme"])),
+            tenant_key("key", &tenant(&["globex"]))
+        );
+        assert_ne!(
+            tenant_key("key", &tenant(&["acme", "globex"])),
+            tenant_key("key", &tenant(&["acme"]))
+        );
+        assert_ne!(
+            tenant_key("key", &tenant(&["acme"])),
+            tenant_key("other", &tenant(&["acme"]))
+        );
+    }
+
+    #[test]
+    fn ids_masked_with_key() {
+        let ids = ColumnarValue::Array(Arc::new(StringArray::from(vec![
+            Some("eee19b7ec3c1b174"),
+            None,
+        ])));
+        let ColumnarValue::Array(masked) = mask(&[ids], b"key").unwrap() else {
+            panic!("masked ids are an array")
+        };
+        let masked = masked.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(masked.value(0), hash(b"key", "eee19b7ec3c1b174"));
+        assert!(masked.is_null(1));
+    }
+}
@@ -25,6 +25,29 @@ pub fn flatten_json_body(body: serde_json::Value) -> Result<Value, anyhow::Error
     flatten::flatten(body, "_")
 }
 
+// Drop the attributes of flattened records beyond `max` attributes per record.
+// Attributes which are known columns of the stream are kept over new ones,
+// within each group attributes are kept in key order.
+// Returns the number of dropped attributes.
+pub fn limit_attributes(value: &mut Value, is_known: &dyn Fn(&str) -> bool, max: usize) -> usize {
+    match value {
+        Value::Array(records) => records
+            .iter_mut()
+            .map(|record| limit_attributes(record, is_known, max))
+            .sum(),
+        Value::Object(record) if record.len() > max => {
+            let (known, new): (Vec<String>, Vec<String>) =
+                record.keys().cloned().partition(|key| is_known(key));
+            let dropped = record.len() - max;
+            for key in known.into_iter().chain(new).skip(max) {
+                record.remove(&key);
+            }
+            dropped
+        }
+        _ => 0,
+    }
+}
+
 pub fn convert_to_string(value: &Value) -> Value {
     match value {
         Value::Null => Value::String("null".to_owned()),
@@ -44,3 +67,23 @@ pub fn convert_to_string(value: &Value) -> Value {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::limit_attributes;
+
+    #[test]
+    fn known_attributes_are_kept() {
+        let mut value = json!([
+            {"a": 1, "b": 2, "c": 3, "z": 4},
+            {"a": 1, "b": 2},
+        ]);
+
+        let dropped = limit_attributes(&mut value, &|key| key == "z", 2);
+
+        assert_eq!(dropped, 2);
+        assert_eq!(value, json!([{"a": 1, "z": 4}, {"a": 1, "b": 2}]));
+    }
+}
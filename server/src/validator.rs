@@ -16,11 +16,22 @@
  *
  */
 
+use std::collections::BTreeMap;
+
 use crate::alerts::rule::base::{NumericRule, StringRule};
 use crate::alerts::rule::{ColumnRule, ConsecutiveNumericRule, ConsecutiveStringRule};
-use crate::alerts::{Alerts, Rule};
+use crate::alerts::{Alerts, Rule, GAP_CHECK_INTERVAL};
+
+use crate::event::{DEFAULT_METADATA_KEY, DEFAULT_TAGS_KEY};
 
-use self::error::{AlertValidationError, StreamNameValidationError, UsernameValidationError};
+use self::error::{
+    AlertValidationError, LabelValidationError, StreamNameValidationError,
+    TimestampKeyValidationError, UsernameValidationError,
+};
+
+const MAX_LABELS: usize = 32;
+const MAX_LABEL_NAME_LEN: usize = 63;
+const MAX_LABEL_VALUE_LEN: usize = 255;
 
 // Add more sql keywords here in lower case
 const DENIED_NAMES: &[&str] = &[
@@ -39,6 +50,12 @@ pub fn alert(alerts: &Alerts) -> Result<(), AlertValidationError> {
             return Err(AlertValidationError::NoTarget);
         }
 
+        if let Rule::IngestGap(ref rule) = alert.rule {
+            if rule.interval < GAP_CHECK_INTERVAL {
+                return Err(AlertValidationError::InvalidGapInterval);
+            }
+        }
+
         if let Rule::Column(ref column_rule) = alert.rule {
             match column_rule {
                 ColumnRule::ConsecutiveNumeric(ConsecutiveNumericRule {
@@ -129,6 +146,50 @@ pub fn user_name(username: &str) -> Result<(), UsernameValidationError> {
     Ok(())
 }
 
+// validate the name of a stream's timestamp column
+// name should not be empty or one of the other reserved columns
+// name may contain alphanumeric characters, _ or @
+pub fn timestamp_key(name: &str) -> Result<(), TimestampKeyValidationError> {
+    if name.is_empty() {
+        return Err(TimestampKeyValidationError::EmptyName);
+    }
+    if name == DEFAULT_TAGS_KEY || name == DEFAULT_METADATA_KEY {
+        return Err(TimestampKeyValidationError::Reserved(name.to_owned()));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '@'))
+    {
+        return Err(TimestampKeyValidationError::SpecialChar(name.to_owned()));
+    }
+
+    Ok(())
+}
+
+// validate the labels of a stream
+// names are 1 to 63 characters among alphanumeric, _, - and .
+// values are non empty and at most 255 characters
+pub fn labels(labels: &BTreeMap<String, String>) -> Result<(), LabelValidationError> {
+    if labels.len() > MAX_LABELS {
+        return Err(LabelValidationError::TooMany(MAX_LABELS));
+    }
+    for (name, value) in labels {
+        if name.is_empty()
+            || name.len() > MAX_LABEL_NAME_LEN
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        {
+            return Err(LabelValidationError::InvalidName(name.to_owned()));
+        }
+        if value.is_empty() || value.len() > MAX_LABEL_VALUE_LEN {
+            return Err(LabelValidationError::InvalidValue(name.to_owned()));
+        }
+    }
+
+    Ok(())
+}
+
 pub mod error {
 
     #[derive(Debug, thiserror::Error)]
@@ -143,6 +204,8 @@ pub mod error {
         InvalidRuleRepeat,
         #[error("Alert must have at least one target")]
         NoTarget,
+        #[error("Alert's rule.interval must be at least {} seconds", crate::alerts::GAP_CHECK_INTERVAL.as_secs())]
+        InvalidGapInterval,
     }
 
     #[derive(Debug, thiserror::Error)]
@@ -163,6 +226,26 @@ pub mod error {
         SQLKeyword(String),
     }
 
+    #[derive(Debug, thiserror::Error)]
+    pub enum TimestampKeyValidationError {
+        #[error("Timestamp column name cannot be empty")]
+        EmptyName,
+        #[error("{0} is a reserved column")]
+        Reserved(String),
+        #[error("Timestamp column name {0} can only contain alphanumeric characters, _ or @")]
+        SpecialChar(String),
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum LabelValidationError {
+        #[error("A stream can have at most {0} labels")]
+        TooMany(usize),
+        #[error("Label name {0} should be 1 to 63 alphanumeric, _, - or . characters")]
+        InvalidName(String),
+        #[error("Value of label {0} should be 1 to 255 characters long")]
+        InvalidValue(String),
+    }
+
     #[derive(Debug, thiserror::Error)]
     pub enum UsernameValidationError {
         #[error("Username should be between 3 and 64 chars long")]